@@ -0,0 +1,29 @@
+use std::process::Command;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The gRPC server is optional, so only require protoc when it's enabled
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/newsletter.proto")?;
+
+    // Commit reported by the health endpoints, from GIT_SHA when the build
+    // pipeline sets it or the checkout otherwise
+    let git_sha = std::env::var("GIT_SHA").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    });
+    println!(
+        "cargo:rustc-env=GIT_SHA={}",
+        git_sha.unwrap_or_else(|| "unknown".to_string())
+    );
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+
+    Ok(())
+}
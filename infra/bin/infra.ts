@@ -4,7 +4,9 @@ import * as cdk from 'aws-cdk-lib';
 import { NewsletterBackendStack } from '../lib/newsletter-backend-stack';
 
 const app = new cdk.App();
-new NewsletterBackendStack(app, 'NewsletterBackendStack', {
+// One stack per STAGE, so deploying dev doesn't replace prod
+const stage = process.env.STAGE;
+new NewsletterBackendStack(app, stage ? `NewsletterBackendStack-${stage}` : 'NewsletterBackendStack', {
   /* If you don't specify 'env', this stack will be environment-agnostic.
    * For more information, see https://docs.aws.amazon.com/cdk/latest/guide/environments.html */
   env: {
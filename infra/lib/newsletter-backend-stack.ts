@@ -3,37 +3,306 @@ import { Construct } from 'constructs';
 import * as dynamodb from 'aws-cdk-lib/aws-dynamodb';
 import * as lambda from 'aws-cdk-lib/aws-lambda';
 import * as apigateway from 'aws-cdk-lib/aws-apigateway';
+import * as events from 'aws-cdk-lib/aws-events';
+import * as targets from 'aws-cdk-lib/aws-events-targets';
+import * as iam from 'aws-cdk-lib/aws-iam';
 import { RustFunction } from 'cargo-lambda-cdk';
 
 export class NewsletterBackendStack extends cdk.Stack {
   constructor(scope: Construct, id: string, props?: cdk.StackProps) {
     super(scope, id, props);
 
-    // DynamoDB Table - using free tier capacity
-    const subscribersTable = new dynamodb.Table(this, 'SubscribersTable', {
-      tableName: 'newsletter_subscribers',
+    // STAGE deploys a separate copy of the backend into the same account, e.g.
+    // STAGE=dev gives newsletter_subscribers_dev and newsletter-subscribe-dev.
+    // The binaries read the same variable to find their tables.
+    const stage = process.env.STAGE || '';
+    const tableSuffix = stage ? `_${stage}` : '';
+    const nameSuffix = stage ? `-${stage}` : '';
+
+    // DynamoDB Table - using free tier capacity. The stack deployed in a replica
+    // region (SUBSCRIBERS_TABLE_REPLICA=true) uses the replica the home region's
+    // stack created, indexes included, instead of creating its own.
+    const replicaRegions = (process.env.SUBSCRIBERS_REPLICA_REGIONS || '')
+      .split(',')
+      .map((region) => region.trim())
+      .filter((region) => region.length > 0);
+    let subscribersTable: dynamodb.ITable;
+    if (process.env.SUBSCRIBERS_TABLE_REPLICA === 'true') {
+      subscribersTable = dynamodb.Table.fromTableAttributes(this, 'SubscribersTable', {
+        tableName: `newsletter_subscribers${tableSuffix}`,
+        grantIndexPermissions: true,
+      });
+    } else {
+      const homeTable = new dynamodb.Table(this, 'SubscribersTable', {
+        tableName: `newsletter_subscribers${tableSuffix}`,
+        partitionKey: { name: 'id', type: dynamodb.AttributeType.STRING },
+        // Set only on unconfirmed subscribers, so stale signups are purged
+        timeToLiveAttribute: 'token_ttl',
+        billingMode: dynamodb.BillingMode.PAY_PER_REQUEST, // On-demand capacity, starts in free tier
+        removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+        // Global Table replicas for active-active deployments, e.g.
+        // SUBSCRIBERS_REPLICA_REGIONS=eu-west-1
+        replicationRegions: replicaRegions.length > 0 ? replicaRegions : undefined,
+      });
+
+      // Add email GSI for looking up subscribers by email
+      // Lookups by address, keyed on a hash of it so the index holds no raw emails
+      homeTable.addGlobalSecondaryIndex({
+        indexName: 'email-hash-index',
+        partitionKey: { name: 'email_hash', type: dynamodb.AttributeType.STRING },
+        projectionType: dynamodb.ProjectionType.ALL,
+      });
+      // The index it replaced, kept with KEEP_EMAIL_INDEX=true for the deploy
+      // before `newsletterctl subscribers reindex` fills the new one
+      if (process.env.KEEP_EMAIL_INDEX === 'true') {
+        homeTable.addGlobalSecondaryIndex({
+          indexName: 'email-index',
+          partitionKey: { name: 'email', type: dynamodb.AttributeType.STRING },
+          projectionType: dynamodb.ProjectionType.ALL,
+        });
+      }
+      homeTable.addGlobalSecondaryIndex({
+        indexName: 'referral-code-index',
+        partitionKey: { name: 'referral_code', type: dynamodb.AttributeType.STRING },
+        projectionType: dynamodb.ProjectionType.ALL,
+      });
+      homeTable.addGlobalSecondaryIndex({
+        indexName: 'stripe-customer-index',
+        partitionKey: { name: 'stripe_customer_id', type: dynamodb.AttributeType.STRING },
+        projectionType: dynamodb.ProjectionType.ALL,
+      });
+
+      // Admin search by domain and by email prefix, keyed on attributes derived from the email
+      homeTable.addGlobalSecondaryIndex({
+        indexName: 'email-domain-index',
+        partitionKey: { name: 'email_domain', type: dynamodb.AttributeType.STRING },
+        sortKey: { name: 'email_search', type: dynamodb.AttributeType.STRING },
+        projectionType: dynamodb.ProjectionType.ALL,
+      });
+      homeTable.addGlobalSecondaryIndex({
+        indexName: 'email-prefix-index',
+        partitionKey: { name: 'email_prefix', type: dynamodb.AttributeType.STRING },
+        sortKey: { name: 'email_search', type: dynamodb.AttributeType.STRING },
+        projectionType: dynamodb.ProjectionType.ALL,
+      });
+
+      // Changes in updated_at order, for CRMs syncing deltas through the admin API
+      homeTable.addGlobalSecondaryIndex({
+        indexName: 'updated-at-index',
+        partitionKey: { name: 'sync_partition', type: dynamodb.AttributeType.STRING },
+        sortKey: { name: 'sync_key', type: dynamodb.AttributeType.STRING },
+        projectionType: dynamodb.ProjectionType.ALL,
+      });
+
+      // Sparse index of signups whose validation message couldn't be queued,
+      // read by the requeue Lambda
+      homeTable.addGlobalSecondaryIndex({
+        indexName: 'enqueue-pending-index',
+        partitionKey: { name: 'enqueue_pending', type: dynamodb.AttributeType.STRING },
+        projectionType: dynamodb.ProjectionType.ALL,
+      });
+      subscribersTable = homeTable;
+    }
+
+    // Subscriber counters (total, active, validated, mailable and per tag),
+    // updated in the same transactions as the subscribers they count.
+    // Replicated alongside the subscribers table, as transactions only span
+    // tables in one region.
+    let countersTable: dynamodb.ITable;
+    if (process.env.SUBSCRIBERS_TABLE_REPLICA === 'true') {
+      countersTable = dynamodb.Table.fromTableName(
+        this,
+        'CountersTable',
+        `newsletter_subscriber_counters${tableSuffix}`,
+      );
+    } else {
+      countersTable = new dynamodb.Table(this, 'CountersTable', {
+        tableName: `newsletter_subscriber_counters${tableSuffix}`,
+        partitionKey: { name: 'counter', type: dynamodb.AttributeType.STRING },
+        billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+        removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+        replicationRegions: replicaRegions.length > 0 ? replicaRegions : undefined,
+      });
+    }
+
+    // Campaigns table, managed through the newsletterctl CLI and the admin API
+    const campaignsTable = new dynamodb.Table(this, 'CampaignsTable', {
+      tableName: `newsletter_campaigns${tableSuffix}`,
       partitionKey: { name: 'id', type: dynamodb.AttributeType.STRING },
-      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST, // On-demand capacity, starts in free tier
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Engagement events (opens and clicks), keyed by subscriber
+    const eventsTable = new dynamodb.Table(this, 'EventsTable', {
+      tableName: `newsletter_events${tableSuffix}`,
+      partitionKey: { name: 'subscriber_id', type: dynamodb.AttributeType.STRING },
+      sortKey: { name: 'event_key', type: dynamodb.AttributeType.STRING },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Append-only subscriber lifecycle history (subscribed, confirmed, unsubscribed, ...)
+    const historyTable = new dynamodb.Table(this, 'HistoryTable', {
+      tableName: `newsletter_subscriber_history${tableSuffix}`,
+      partitionKey: { name: 'subscriber_id', type: dynamodb.AttributeType.STRING },
+      sortKey: { name: 'event_key', type: dynamodb.AttributeType.STRING },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Idempotency keys for POST /subscribe, expired by DynamoDB TTL
+    const idempotencyTable = new dynamodb.Table(this, 'IdempotencyTable', {
+      tableName: `newsletter_idempotency_keys${tableSuffix}`,
+      partitionKey: { name: 'key', type: dynamodb.AttributeType.STRING },
+      timeToLiveAttribute: 'expires_at',
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // SQS message ids the validate and send_email workers have processed, so
+    // redelivered messages are skipped. Expired by DynamoDB TTL.
+    const processedMessagesTable = new dynamodb.Table(this, 'ProcessedMessagesTable', {
+      tableName: `newsletter_processed_messages${tableSuffix}`,
+      partitionKey: { name: 'message_id', type: dynamodb.AttributeType.STRING },
+      timeToLiveAttribute: 'expires_at',
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Per API key usage counters for quotas, expired by DynamoDB TTL
+    const quotasTable = new dynamodb.Table(this, 'QuotasTable', {
+      tableName: `newsletter_api_quotas${tableSuffix}`,
+      partitionKey: { name: 'quota_key', type: dynamodb.AttributeType.STRING },
+      timeToLiveAttribute: 'expires_at',
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Daily counters per email domain, expired by DynamoDB TTL
+    const domainStatsTable = new dynamodb.Table(this, 'DomainStatsTable', {
+      tableName: `newsletter_domain_stats${tableSuffix}`,
+      partitionKey: { name: 'domain', type: dynamodb.AttributeType.STRING },
+      sortKey: { name: 'day', type: dynamodb.AttributeType.STRING },
+      timeToLiveAttribute: 'expires_at',
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
       removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
     });
 
-    // Add email GSI for looking up subscribers by email
-    subscribersTable.addGlobalSecondaryIndex({
-      indexName: 'email-index',
-      partitionKey: { name: 'email', type: dynamodb.AttributeType.STRING },
-      projectionType: dynamodb.ProjectionType.ALL,
+    // Operator-managed allow and deny rules for signup domains
+    const domainRulesTable = new dynamodb.Table(this, 'DomainRulesTable', {
+      tableName: `newsletter_domain_rules${tableSuffix}`,
+      partitionKey: { name: 'domain', type: dynamodb.AttributeType.STRING },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Invite codes for invite-only launches, with usage counters
+    const inviteCodesTable = new dynamodb.Table(this, 'InviteCodesTable', {
+      tableName: `newsletter_invite_codes${tableSuffix}`,
+      partitionKey: { name: 'code', type: dynamodb.AttributeType.STRING },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Recently sent campaigns waiting to go out in weekly digests, expired by DynamoDB TTL
+    const digestItemsTable = new dynamodb.Table(this, 'DigestItemsTable', {
+      tableName: `newsletter_digest_items${tableSuffix}`,
+      partitionKey: { name: 'campaign_id', type: dynamodb.AttributeType.STRING },
+      timeToLiveAttribute: 'expires_at',
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Per-timezone buckets of campaigns scheduled for a local time
+    const campaignScheduleTable = new dynamodb.Table(this, 'CampaignScheduleTable', {
+      tableName: `newsletter_campaign_schedule${tableSuffix}`,
+      partitionKey: { name: 'campaign_id', type: dynamodb.AttributeType.STRING },
+      sortKey: { name: 'timezone', type: dynamodb.AttributeType.STRING },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Recipients of scheduled campaigns as resolved at schedule time, removed by TTL after the send
+    const campaignRecipientsTable = new dynamodb.Table(this, 'CampaignRecipientsTable', {
+      tableName: `newsletter_campaign_recipients${tableSuffix}`,
+      partitionKey: { name: 'campaign_id', type: dynamodb.AttributeType.STRING },
+      sortKey: { name: 'subscriber_id', type: dynamodb.AttributeType.STRING },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      timeToLiveAttribute: 'expires_at',
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // From addresses campaigns can be sent from, verified through SES
+    const senderIdentitiesTable = new dynamodb.Table(this, 'SenderIdentitiesTable', {
+      tableName: `newsletter_sender_identities${tableSuffix}`,
+      partitionKey: { name: 'id', type: dynamodb.AttributeType.STRING },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Campaign templates, and their immutable versions keyed by version number
+    const templatesTable = new dynamodb.Table(this, 'TemplatesTable', {
+      tableName: `newsletter_templates${tableSuffix}`,
+      partitionKey: { name: 'id', type: dynamodb.AttributeType.STRING },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+    const templateVersionsTable = new dynamodb.Table(this, 'TemplateVersionsTable', {
+      tableName: `newsletter_template_versions${tableSuffix}`,
+      partitionKey: { name: 'template_id', type: dynamodb.AttributeType.STRING },
+      sortKey: { name: 'version', type: dynamodb.AttributeType.NUMBER },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // Signed log of admin API mutations, one partition per day, expired by DynamoDB TTL.
+    // Retained on stack deletion so the trail outlives the deployment.
+    const auditTable = new dynamodb.Table(this, 'AuditTable', {
+      tableName: `newsletter_admin_audit${tableSuffix}`,
+      partitionKey: { name: 'day', type: dynamodb.AttributeType.STRING },
+      sortKey: { name: 'entry_key', type: dynamodb.AttributeType.STRING },
+      timeToLiveAttribute: 'expires_at',
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.RETAIN,
+    });
+
+    // Long-running jobs started through the admin API, with their progress
+    const jobsTable = new dynamodb.Table(this, 'JobsTable', {
+      tableName: `newsletter_jobs${tableSuffix}`,
+      partitionKey: { name: 'id', type: dynamodb.AttributeType.STRING },
+      billingMode: dynamodb.BillingMode.PAY_PER_REQUEST,
+      removalPolicy: cdk.RemovalPolicy.DESTROY, // For dev/test environments only
+    });
+
+    // VALIDATION_QUEUE_FIFO=true deploys FIFO queues with content-based
+    // deduplication, so retried signups can't send a second validation email
+    const fifo = process.env.VALIDATION_QUEUE_FIFO === 'true';
+    const queueSuffix = fifo ? '.fifo' : '';
+
+    // Validation messages that kept failing, redriven with `newsletterctl queue redrive`
+    const validationDeadLetterQueue = new cdk.aws_sqs.Queue(this, 'ValidationDeadLetterQueue', {
+      queueName: `newsletter-validation-dlq${nameSuffix}${queueSuffix}`,
+      fifo: fifo || undefined,
+      retentionPeriod: cdk.Duration.days(14),
     });
 
     // SQS Queue for validating email addresses
     const emailValidationQueue = new cdk.aws_sqs.Queue(this, 'EmailValidationQueue', {
-      queueName: 'newsletter-validation-queue',
+      queueName: `newsletter-validation-queue${nameSuffix}${queueSuffix}`,
+      fifo: fifo || undefined,
+      contentBasedDeduplication: fifo || undefined,
       visibilityTimeout: cdk.Duration.seconds(30),
       retentionPeriod: cdk.Duration.days(1),
+      deadLetterQueue: {
+        queue: validationDeadLetterQueue,
+        maxReceiveCount: 5,
+      },
     });
 
     const subscribeLambda = new RustFunction(this, 'SubscribeLambda', {
       manifestPath: '../Cargo.toml',
-      functionName: 'newsletter-subscribe',
+      functionName: `newsletter-subscribe${nameSuffix}`,
       architecture: lambda.Architecture.ARM_64,
       memorySize: 128,
 
@@ -47,7 +316,7 @@ export class NewsletterBackendStack extends cdk.Stack {
     // Unsubscribe Lambda Function
     const unsubscribeLambda = new RustFunction(this, 'UnsubscribeLambda', {
       manifestPath: '../Cargo.toml',
-      functionName: 'newsletter-unsubscribe',
+      functionName: `newsletter-unsubscribe${nameSuffix}`,
       architecture: lambda.Architecture.ARM_64,
       memorySize: 128,
 
@@ -57,18 +326,33 @@ export class NewsletterBackendStack extends cdk.Stack {
     // Unsubscribe Lambda Function
     const validateLambda = new RustFunction(this, 'ValidateLambda', {
       manifestPath: '../Cargo.toml',
-      functionName: 'newsletter-validate',
+      functionName: `newsletter-validate${nameSuffix}`,
       architecture: lambda.Architecture.ARM_64,
       memorySize: 128,
 
+      environment: {
+        SENDER_EMAIL: process.env.SENDER_EMAIL || 'newsletter@example.com',
+        VALIDATE_CONCURRENCY: process.env.VALIDATE_CONCURRENCY || '10',
+      },
+
       binaryName: 'validate',
     });
     subscribersTable.grantReadWriteData(validateLambda);
+    validateLambda.addEventSource(new cdk.aws_lambda_event_sources.SqsEventSource(emailValidationQueue, {
+      // FIFO event sources take at most 10 records and no batching window
+      batchSize: fifo ? 10 : 50,
+      maxBatchingWindow: fifo ? undefined : cdk.Duration.seconds(5),
+      reportBatchItemFailures: true,
+    }));
+    validateLambda.addToRolePolicy(new iam.PolicyStatement({
+      actions: ['ses:SendEmail', 'ses:SendRawEmail'],
+      resources: ['*'],
+    }));
 
     // Confirm Lambda Function
     const confirmLambda = new RustFunction(this, 'ConfirmLambda', {
       manifestPath: '../Cargo.toml',
-      functionName: 'newsletter-confirm',
+      functionName: `newsletter-confirm${nameSuffix}`,
       architecture: lambda.Architecture.ARM_64,
       memorySize: 128,
 
@@ -76,13 +360,736 @@ export class NewsletterBackendStack extends cdk.Stack {
     });
     subscribersTable.grantReadWriteData(confirmLambda);
 
+    // Confirmation reminder Lambda Function, runs hourly
+    const remindLambda = new RustFunction(this, 'RemindLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-remind${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+      timeout: cdk.Duration.minutes(5),
+
+      environment: {
+        SENDER_EMAIL: process.env.SENDER_EMAIL || 'newsletter@example.com',
+        REMINDER_AFTER_HOURS: '24',
+      },
+
+      binaryName: 'remind',
+    });
+    subscribersTable.grantReadWriteData(remindLambda);
+    remindLambda.addToRolePolicy(new iam.PolicyStatement({
+      actions: ['ses:SendEmail', 'ses:SendRawEmail'],
+      resources: ['*'],
+    }));
+
+    new events.Rule(this, 'RemindSchedule', {
+      schedule: events.Schedule.rate(cdk.Duration.hours(1)),
+      targets: [new targets.LambdaFunction(remindLambda)],
+    });
+
+    // Open/click tracking Lambda Function
+    const trackLambda = new RustFunction(this, 'TrackLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-track${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+
+      binaryName: 'track',
+    });
+    eventsTable.grantWriteData(trackLambda);
+    subscribersTable.grantReadWriteData(trackLambda);
+
+    // SES configuration set publishing delivery, open, click, reject and bounce
+    // events to SNS, so SES's own tracking can be used with or instead of the pixel
+    const configurationSet = new cdk.aws_ses.ConfigurationSet(this, 'NewsletterConfigurationSet', {
+      configurationSetName: `newsletter${nameSuffix}`,
+    });
+    const sesEventsTopic = new cdk.aws_sns.Topic(this, 'SesEventsTopic');
+    configurationSet.addEventDestination('SnsDestination', {
+      destination: cdk.aws_ses.EventDestination.snsTopic(sesEventsTopic),
+      events: [
+        cdk.aws_ses.EmailSendingEvent.DELIVERY,
+        cdk.aws_ses.EmailSendingEvent.OPEN,
+        cdk.aws_ses.EmailSendingEvent.CLICK,
+        cdk.aws_ses.EmailSendingEvent.REJECT,
+        cdk.aws_ses.EmailSendingEvent.BOUNCE,
+      ],
+    });
+
+    const sesEventsLambda = new RustFunction(this, 'SesEventsLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-ses-events${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+
+      environment: {
+        PIXEL_TRACKING: process.env.PIXEL_TRACKING || 'true',
+      },
+
+      binaryName: 'ses_events',
+    });
+    sesEventsTopic.addSubscription(new cdk.aws_sns_subscriptions.LambdaSubscription(sesEventsLambda));
+    eventsTable.grantWriteData(sesEventsLambda);
+    subscribersTable.grantReadWriteData(sesEventsLambda);
+    historyTable.grantWriteData(sesEventsLambda);
+
+    // Admin API Lambda Function, authenticated with the x-api-key header, by
+    // the JWT authorizer below with ADMIN_AUTH=jwt, or with a Cognito or OIDC
+    // access token verified in the Lambda when JWT_ISSUER is set
+    const adminLambda = new RustFunction(this, 'AdminLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-admin${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+      timeout: cdk.Duration.minutes(15),
+
+      environment: {
+        ADMIN_API_KEY: process.env.ADMIN_API_KEY || '',
+        API_KEYS: process.env.API_KEYS || '[]',
+        SENDER_EMAIL: process.env.SENDER_EMAIL || 'newsletter@example.com',
+        REFERRAL_MILESTONES: process.env.REFERRAL_MILESTONES || '1,3,5,10',
+        // Kept well under the API Gateway timeout, since sends wait on the check
+        LINK_CHECK_TIMEOUT_SECONDS: process.env.LINK_CHECK_TIMEOUT_SECONDS || '10',
+        LINK_CHECK_CONCURRENCY: process.env.LINK_CHECK_CONCURRENCY || '10',
+        JWT_ISSUER: process.env.JWT_ISSUER || '',
+        JWT_AUDIENCE: process.env.JWT_AUDIENCE || '',
+        JWT_JWKS_URL: process.env.JWT_JWKS_URL || '',
+        JWT_REQUIRED_SCOPES: process.env.JWT_REQUIRED_SCOPES || '',
+        JWT_GROUPS_CLAIM: process.env.JWT_GROUPS_CLAIM || '',
+        // e.g. admin=newsletter-admins,editor=marketing,viewer=analysts
+        JWT_ROLE_GROUPS: process.env.JWT_ROLE_GROUPS || '',
+      },
+
+      binaryName: 'admin',
+    });
+    subscribersTable.grantReadWriteData(adminLambda);
+    campaignsTable.grantReadWriteData(adminLambda);
+    quotasTable.grantReadWriteData(adminLambda);
+    historyTable.grantReadData(adminLambda);
+    eventsTable.grantReadData(adminLambda);
+    adminLambda.addToRolePolicy(new iam.PolicyStatement({
+      actions: ['ses:SendEmail', 'ses:SendRawEmail'],
+      resources: ['*'],
+    }));
+
+    // JWT authorizer for the admin routes, enabled with ADMIN_AUTH=jwt. Admin
+    // requests then need an `Authorization: Bearer <token>` header from
+    // JWT_ISSUER, and the gateway rejects everything else before the admin
+    // Lambda runs.
+    let adminAuthorizer: apigateway.TokenAuthorizer | undefined;
+    if (process.env.ADMIN_AUTH === 'jwt') {
+      const authorizerLambda = new RustFunction(this, 'AuthorizerLambda', {
+        manifestPath: '../Cargo.toml',
+        functionName: `newsletter-authorizer${nameSuffix}`,
+        architecture: lambda.Architecture.ARM_64,
+        memorySize: 128,
+        timeout: cdk.Duration.seconds(10),
+
+        environment: {
+          JWT_ISSUER: process.env.JWT_ISSUER || '',
+          JWT_AUDIENCE: process.env.JWT_AUDIENCE || '',
+          JWT_JWKS_URL: process.env.JWT_JWKS_URL || '',
+          JWT_REQUIRED_SCOPES: process.env.JWT_REQUIRED_SCOPES || '',
+          JWT_GROUPS_CLAIM: process.env.JWT_GROUPS_CLAIM || '',
+          JWKS_CACHE_SECONDS: process.env.JWKS_CACHE_SECONDS || '3600',
+        },
+
+        binaryName: 'authorizer',
+      });
+      adminAuthorizer = new apigateway.TokenAuthorizer(this, 'AdminAuthorizer', {
+        handler: authorizerLambda,
+        identitySource: apigateway.IdentitySource.header('Authorization'),
+        resultsCacheTtl: cdk.Duration.minutes(5),
+      });
+    }
+    // Inherited by every admin resource below
+    const adminResourceOptions: apigateway.ResourceOptions = adminAuthorizer
+      ? {
+          defaultMethodOptions: {
+            authorizer: adminAuthorizer,
+            authorizationType: apigateway.AuthorizationType.CUSTOM,
+          },
+        }
+      : {};
+
+    // Referral Lambda Function, showing a subscriber their code and progress
+    const referralLambda = new RustFunction(this, 'ReferralLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-referral${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+
+      environment: {
+        REFERRAL_MILESTONES: process.env.REFERRAL_MILESTONES || '1,3,5,10',
+      },
+
+      binaryName: 'referral',
+    });
+    subscribersTable.grantReadWriteData(referralLambda);
+
+    // Stripe webhook Lambda Function, keeping subscriber tiers in sync with payments
+    const stripeWebhookLambda = new RustFunction(this, 'StripeWebhookLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-stripe-webhook${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+
+      environment: {
+        STRIPE_WEBHOOK_SECRET: process.env.STRIPE_WEBHOOK_SECRET || '',
+      },
+
+      binaryName: 'stripe_webhook',
+    });
+    subscribersTable.grantReadWriteData(stripeWebhookLambda);
+
+    // Webhook receiver for bounces, complaints and engagement from ESPs other than SES
+    const espWebhookLambda = new RustFunction(this, 'EspWebhookLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-esp-webhook${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+
+      environment: {
+        SENDGRID_WEBHOOK_PUBLIC_KEY: process.env.SENDGRID_WEBHOOK_PUBLIC_KEY || '',
+        POSTMARK_WEBHOOK_CREDENTIALS: process.env.POSTMARK_WEBHOOK_CREDENTIALS || '',
+        PIXEL_TRACKING: process.env.PIXEL_TRACKING || 'true',
+      },
+
+      binaryName: 'esp_webhook',
+    });
+    subscribersTable.grantReadWriteData(espWebhookLambda);
+    eventsTable.grantWriteData(espWebhookLambda);
+    historyTable.grantWriteData(espWebhookLambda);
+    domainStatsTable.grantWriteData(espWebhookLambda);
+
+    // OpenAPI document Lambda Function
+    const openapiLambda = new RustFunction(this, 'OpenapiLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-openapi${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+
+      binaryName: 'openapi',
+    });
+
+    // Health check Lambda Function, serving /health and /version
+    const healthLambda = new RustFunction(this, 'HealthLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-health${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+
+      binaryName: 'health',
+    });
+    for (const table of [
+      subscribersTable,
+      campaignsTable,
+      eventsTable,
+      historyTable,
+      idempotencyTable,
+      quotasTable,
+      domainStatsTable,
+      domainRulesTable,
+      inviteCodesTable,
+      digestItemsTable,
+      campaignScheduleTable,
+      campaignRecipientsTable,
+      senderIdentitiesTable,
+      templatesTable,
+      templateVersionsTable,
+      auditTable,
+      countersTable,
+      processedMessagesTable,
+      jobsTable,
+    ]) {
+      table.grant(healthLambda, 'dynamodb:DescribeTable');
+    }
+
+    // Embeddable signup form Lambda Function
+    const embedLambda = new RustFunction(this, 'EmbedLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-embed${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+
+      binaryName: 'embed',
+    });
+
+    // Hosted web version Lambda Function, serving campaigns from their
+    // {{ web_version_url }} links
+    const webVersionLambda = new RustFunction(this, 'WebVersionLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-web-version${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+
+      binaryName: 'web_version',
+    });
+    subscribersTable.grantReadData(webVersionLambda);
+    campaignsTable.grantReadData(webVersionLambda);
+
+    // Re-engagement / sunset Lambda Function, runs daily
+    const sunsetLambda = new RustFunction(this, 'SunsetLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-sunset${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+      timeout: cdk.Duration.minutes(15),
+
+      environment: {
+        SENDER_EMAIL: process.env.SENDER_EMAIL || 'newsletter@example.com',
+        SUNSET_INACTIVE_DAYS: '180',
+        SUNSET_GRACE_DAYS: '30',
+      },
+
+      binaryName: 'sunset',
+    });
+    subscribersTable.grantReadWriteData(sunsetLambda);
+    eventsTable.grantReadData(sunsetLambda);
+    historyTable.grantWriteData(sunsetLambda);
+    sunsetLambda.addToRolePolicy(new iam.PolicyStatement({
+      actions: ['ses:SendEmail', 'ses:SendRawEmail'],
+      resources: ['*'],
+    }));
+
+    new events.Rule(this, 'SunsetSchedule', {
+      schedule: events.Schedule.rate(cdk.Duration.days(1)),
+      targets: [new targets.LambdaFunction(sunsetLambda)],
+    });
+
+    // Digest Lambda Function, sends weekly subscribers the campaigns of the past week
+    const digestLambda = new RustFunction(this, 'DigestLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-digest${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 256,
+      timeout: cdk.Duration.minutes(15),
+
+      environment: {
+        SENDER_EMAIL: process.env.SENDER_EMAIL || 'newsletter@example.com',
+        DIGEST_MAX_AGE_DAYS: process.env.DIGEST_MAX_AGE_DAYS || '14',
+      },
+
+      binaryName: 'digest',
+    });
+    subscribersTable.grantReadWriteData(digestLambda);
+    campaignsTable.grantReadData(digestLambda);
+    digestItemsTable.grantReadData(digestLambda);
+    digestLambda.addToRolePolicy(new iam.PolicyStatement({
+      actions: ['ses:SendEmail', 'ses:SendRawEmail'],
+      resources: ['*'],
+    }));
+
+    new events.Rule(this, 'DigestSchedule', {
+      schedule: events.Schedule.cron({ weekDay: 'MON', hour: '9', minute: '0' }),
+      targets: [new targets.LambdaFunction(digestLambda)],
+    });
+
+    // Pulse Lambda Function, emails the owner yesterday's subscriber numbers
+    const pulseLambda = new RustFunction(this, 'PulseLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-pulse${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 256,
+      timeout: cdk.Duration.minutes(5),
+
+      environment: {
+        SENDER_EMAIL: process.env.SENDER_EMAIL || 'newsletter@example.com',
+        PULSE_EMAIL: process.env.PULSE_EMAIL || '',
+      },
+
+      binaryName: 'pulse',
+    });
+    subscribersTable.grantReadData(pulseLambda);
+    domainStatsTable.grantReadData(pulseLambda);
+    pulseLambda.addToRolePolicy(new iam.PolicyStatement({
+      actions: ['ses:SendEmail'],
+      resources: ['*'],
+    }));
+
+    new events.Rule(this, 'PulseSchedule', {
+      schedule: events.Schedule.cron({ hour: '7', minute: '0' }),
+      targets: [new targets.LambdaFunction(pulseLambda)],
+    });
+
+    // Scheduled send Lambda Function, sends each timezone bucket of a scheduled
+    // campaign once its local send time comes
+    const scheduledSendLambda = new RustFunction(this, 'ScheduledSendLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-scheduled-send${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 256,
+      timeout: cdk.Duration.minutes(15),
+
+      environment: {
+        SENDER_EMAIL: process.env.SENDER_EMAIL || 'newsletter@example.com',
+        DEFAULT_TIMEZONE: process.env.DEFAULT_TIMEZONE || 'UTC',
+      },
+
+      binaryName: 'scheduled_send',
+    });
+    subscribersTable.grantReadData(scheduledSendLambda);
+    campaignsTable.grantReadWriteData(scheduledSendLambda);
+    digestItemsTable.grantWriteData(scheduledSendLambda);
+    campaignScheduleTable.grantReadWriteData(scheduledSendLambda);
+    campaignRecipientsTable.grantReadData(scheduledSendLambda);
+    scheduledSendLambda.addToRolePolicy(new iam.PolicyStatement({
+      actions: ['ses:SendEmail', 'ses:SendRawEmail'],
+      resources: ['*'],
+    }));
+
+    new events.Rule(this, 'ScheduledSendSchedule', {
+      schedule: events.Schedule.rate(cdk.Duration.minutes(15)),
+      targets: [new targets.LambdaFunction(scheduledSendLambda)],
+    });
+
+    // Campaign send workflow Lambda Function, each state of the campaign send
+    // state machine: resolve the recipients, send a batch, add up the counts
+    const campaignWorkflowLambda = new RustFunction(this, 'CampaignWorkflowLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-campaign-workflow${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 256,
+      timeout: cdk.Duration.minutes(5),
+
+      environment: {
+        SENDER_EMAIL: process.env.SENDER_EMAIL || 'newsletter@example.com',
+      },
+
+      binaryName: 'campaign_workflow',
+    });
+    subscribersTable.grantReadData(campaignWorkflowLambda);
+    campaignsTable.grantReadWriteData(campaignWorkflowLambda);
+    digestItemsTable.grantWriteData(campaignWorkflowLambda);
+    campaignRecipientsTable.grantReadWriteData(campaignWorkflowLambda);
+    campaignWorkflowLambda.addToRolePolicy(new iam.PolicyStatement({
+      actions: ['ses:SendEmail', 'ses:SendRawEmail'],
+      resources: ['*'],
+    }));
+
+    // Campaign send state machine: resolve, send the batches from a Map state
+    // with at most CAMPAIGN_WORKFLOW_CONCURRENCY at once, then finish. Failed
+    // states are retried with backoff and every step shows in the console.
+    const workflowStep = (id: string, payload: Record<string, unknown>) => {
+      const task = new cdk.aws_stepfunctions_tasks.LambdaInvoke(this, id, {
+        lambdaFunction: campaignWorkflowLambda,
+        payload: cdk.aws_stepfunctions.TaskInput.fromObject(payload),
+        payloadResponseOnly: true,
+      });
+      task.addRetry({
+        errors: ['States.ALL'],
+        interval: cdk.Duration.seconds(10),
+        maxAttempts: 3,
+        backoffRate: 2,
+      });
+      return task;
+    };
+    const sendBatches = new cdk.aws_stepfunctions.Map(this, 'SendBatches', {
+      itemsPath: '$.batches',
+      itemSelector: {
+        step: 'send_batch',
+        'batch.$': '$$.Map.Item.Value',
+      },
+      maxConcurrency: Number(process.env.CAMPAIGN_WORKFLOW_CONCURRENCY || '5'),
+      resultPath: '$.results',
+    });
+    sendBatches.itemProcessor(workflowStep('SendBatch', { 'step.$': '$.step', 'batch.$': '$.batch' }));
+    const campaignStateMachine = new cdk.aws_stepfunctions.StateMachine(this, 'CampaignSendStateMachine', {
+      stateMachineName: `newsletter-campaign-send${nameSuffix}`,
+      definitionBody: cdk.aws_stepfunctions.DefinitionBody.fromChainable(
+        workflowStep('ResolveRecipients', { step: 'resolve', 'campaign_id.$': '$.campaign_id' })
+          .next(sendBatches)
+          .next(workflowStep('FinishSend', {
+            step: 'finish',
+            'campaign_id.$': '$.campaign_id',
+            'results.$': '$.results',
+          })),
+      ),
+      timeout: cdk.Duration.hours(24),
+    });
+    // CAMPAIGN_WORKFLOW=step_functions sends campaigns through it instead of
+    // in the admin Lambda, for big lists
+    if (process.env.CAMPAIGN_WORKFLOW === 'step_functions') {
+      adminLambda.addEnvironment('CAMPAIGN_STATE_MACHINE_ARN', campaignStateMachine.stateMachineArn);
+      campaignStateMachine.grantStartExecution(adminLambda);
+    }
+
+    // Email verification hygiene Lambda Function, runs weekly when a provider is configured
+    const verifyLambda = new RustFunction(this, 'VerifyLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-verify${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+      timeout: cdk.Duration.minutes(15),
+
+      environment: {
+        VERIFICATION_MAX_AGE_DAYS: '90',
+        VERIFICATION_BATCH_LIMIT: '500',
+      },
+
+      binaryName: 'verify',
+    });
+    subscribersTable.grantReadWriteData(verifyLambda);
+    historyTable.grantWriteData(verifyLambda);
+
+    new events.Rule(this, 'VerifySchedule', {
+      schedule: events.Schedule.rate(cdk.Duration.days(7)),
+      targets: [new targets.LambdaFunction(verifyLambda)],
+    });
+
+    // Retention Lambda Function, hard-deletes soft-deleted subscribers once a day
+    const retentionLambda = new RustFunction(this, 'RetentionLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-retention${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+      timeout: cdk.Duration.minutes(15),
+
+      environment: {
+        DELETED_RETENTION_DAYS: process.env.DELETED_RETENTION_DAYS || '30',
+      },
+
+      binaryName: 'retention',
+    });
+    subscribersTable.grantReadWriteData(retentionLambda);
+    eventsTable.grantReadWriteData(retentionLambda);
+    historyTable.grantReadWriteData(retentionLambda);
+
+    new events.Rule(this, 'RetentionSchedule', {
+      schedule: events.Schedule.rate(cdk.Duration.days(1)),
+      targets: [new targets.LambdaFunction(retentionLambda)],
+    });
+
+    // Requeue Lambda Function, queues the validation messages subscribe
+    // couldn't, e.g. while SQS was unavailable
+    const requeueLambda = new RustFunction(this, 'RequeueLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-requeue${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+      timeout: cdk.Duration.minutes(5),
+
+      environment: {
+        VALIDATION_QUEUE_URL: emailValidationQueue.queueUrl,
+      },
+
+      binaryName: 'requeue',
+    });
+    subscribersTable.grantReadWriteData(requeueLambda);
+    emailValidationQueue.grantSendMessages(requeueLambda);
+
+    new events.Rule(this, 'RequeueSchedule', {
+      schedule: events.Schedule.rate(cdk.Duration.minutes(5)),
+      targets: [new targets.LambdaFunction(requeueLambda)],
+    });
+
+    // Reconcile Lambda Function, recounts subscribers nightly and corrects
+    // drift in the maintained counters
+    const reconcileLambda = new RustFunction(this, 'ReconcileLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-reconcile${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 256,
+      timeout: cdk.Duration.minutes(15),
+      binaryName: 'reconcile',
+    });
+    subscribersTable.grantReadData(reconcileLambda);
+
+    new events.Rule(this, 'ReconcileSchedule', {
+      schedule: events.Schedule.cron({ minute: '30', hour: '3' }),
+      targets: [new targets.LambdaFunction(reconcileLambda)],
+    });
+
+    // Job queue: each message is one page of subscribers of a job started
+    // through the admin API, and the worker queues the next page as it goes
+    const jobDeadLetterQueue = new cdk.aws_sqs.Queue(this, 'JobDeadLetterQueue', {
+      queueName: `newsletter-jobs-dlq${nameSuffix}`,
+      retentionPeriod: cdk.Duration.days(14),
+    });
+
+    const jobQueue = new cdk.aws_sqs.Queue(this, 'JobQueue', {
+      queueName: `newsletter-jobs${nameSuffix}`,
+      visibilityTimeout: cdk.Duration.minutes(6),
+      retentionPeriod: cdk.Duration.days(4),
+      deadLetterQueue: {
+        queue: jobDeadLetterQueue,
+        maxReceiveCount: 5,
+      },
+    });
+
+    const jobWorkerLambda = new RustFunction(this, 'JobWorkerLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-job-worker${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 256,
+      timeout: cdk.Duration.minutes(5),
+
+      environment: {
+        JOB_QUEUE_URL: jobQueue.queueUrl,
+      },
+
+      binaryName: 'job_worker',
+    });
+    jobWorkerLambda.addEventSource(new cdk.aws_lambda_event_sources.SqsEventSource(jobQueue, {
+      batchSize: 1,
+      reportBatchItemFailures: true,
+    }));
+    jobQueue.grantSendMessages(jobWorkerLambda);
+    jobQueue.grantSendMessages(adminLambda);
+    adminLambda.addEnvironment('JOB_QUEUE_URL', jobQueue.queueUrl);
+    subscribersTable.grantReadWriteData(jobWorkerLambda);
+    historyTable.grantWriteData(jobWorkerLambda);
+    jobsTable.grantReadWriteData(jobWorkerLambda);
+    jobsTable.grantReadWriteData(adminLambda);
+
+    // Email send queue: confirmation, reminder, re-engagement and campaign emails are
+    // enqueued as jobs and sent by the worker, which retries transient SES errors
+    // with backoff and dead-letters jobs with their last error once out of attempts
+    const emailSendDeadLetterQueue = new cdk.aws_sqs.Queue(this, 'EmailSendDeadLetterQueue', {
+      queueName: `newsletter-email-send-dlq${nameSuffix}`,
+      retentionPeriod: cdk.Duration.days(14),
+    });
+
+    const emailSendQueue = new cdk.aws_sqs.Queue(this, 'EmailSendQueue', {
+      queueName: `newsletter-email-send${nameSuffix}`,
+      visibilityTimeout: cdk.Duration.seconds(180),
+      retentionPeriod: cdk.Duration.days(4),
+      // Only reached when the worker itself keeps failing, e.g. on unparseable jobs
+      deadLetterQueue: {
+        queue: emailSendDeadLetterQueue,
+        maxReceiveCount: 5,
+      },
+    });
+
+    const sendEmailLambda = new RustFunction(this, 'SendEmailLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-send-email${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+      timeout: cdk.Duration.seconds(30),
+
+      environment: {
+        SENDER_EMAIL: process.env.SENDER_EMAIL || 'newsletter@example.com',
+        EMAIL_QUEUE_URL: emailSendQueue.queueUrl,
+        EMAIL_DLQ_URL: emailSendDeadLetterQueue.queueUrl,
+        EMAIL_MAX_ATTEMPTS: process.env.EMAIL_MAX_ATTEMPTS || '5',
+      },
+
+      binaryName: 'send_email',
+    });
+    sendEmailLambda.addEventSource(new cdk.aws_lambda_event_sources.SqsEventSource(emailSendQueue, {
+      batchSize: 10,
+      reportBatchItemFailures: true,
+    }));
+    sendEmailLambda.addToRolePolicy(new iam.PolicyStatement({
+      actions: ['ses:SendEmail', 'ses:SendRawEmail'],
+      resources: ['*'],
+    }));
+    emailSendQueue.grantSendMessages(sendEmailLambda);
+    // Emails of cancelled campaigns are dropped and counted on the campaign
+    campaignsTable.grantReadWriteData(sendEmailLambda);
+    emailSendDeadLetterQueue.grantSendMessages(sendEmailLambda);
+    processedMessagesTable.grantReadWriteData(sendEmailLambda);
+
+    // Files attached to welcome emails, e.g. a PDF guide promised on the signup
+    // form. Queued jobs carry only the keys, the worker downloads the files.
+    const attachmentsBucket = new cdk.aws_s3.Bucket(this, 'AttachmentsBucket', {
+      encryption: cdk.aws_s3.BucketEncryption.S3_MANAGED,
+      blockPublicAccess: cdk.aws_s3.BlockPublicAccess.BLOCK_ALL,
+      removalPolicy: cdk.RemovalPolicy.RETAIN,
+    });
+    for (const fn of [sendEmailLambda, confirmLambda, subscribeLambda, adminLambda]) {
+      fn.addEnvironment('ATTACHMENTS_BUCKET', attachmentsBucket.bucketName);
+      fn.addEnvironment('ATTACHMENT_MAX_BYTES', process.env.ATTACHMENT_MAX_BYTES || '5242880');
+      attachmentsBucket.grantRead(fn);
+    }
+    // Subscribe welcomes new subscribers itself when DOUBLE_OPT_IN=false
+    for (const fn of [confirmLambda, subscribeLambda]) {
+      fn.addEnvironment('SENDER_EMAIL', process.env.SENDER_EMAIL || 'newsletter@example.com');
+      fn.addEnvironment('WELCOME_ATTACHMENTS', process.env.WELCOME_ATTACHMENTS || '');
+    }
+    confirmLambda.addEnvironment('WELCOME_EMAIL', process.env.WELCOME_EMAIL || 'false');
+    // Admin approval of a quarantined signup sends what subscribe held back
+    adminLambda.addEnvironment('WELCOME_ATTACHMENTS', process.env.WELCOME_ATTACHMENTS || '');
+    for (const fn of [subscribeLambda, adminLambda]) {
+      fn.addEnvironment('DOUBLE_OPT_IN', process.env.DOUBLE_OPT_IN || 'true');
+      // Signups and imports validate addresses the same way
+      fn.addEnvironment('ALLOW_UTF8_LOCAL_PART', process.env.ALLOW_UTF8_LOCAL_PART || 'false');
+    }
+    adminLambda.addEnvironment('VALIDATION_QUEUE_URL', emailValidationQueue.queueUrl);
+
+    // Suspicious signups are quarantined until approved in the admin API
+    subscribeLambda.addEnvironment('SIGNUP_BURST_LIMIT', process.env.SIGNUP_BURST_LIMIT || '5');
+    subscribeLambda.addEnvironment(
+      'QUARANTINE_DICTIONARY_LOCAL_PARTS',
+      process.env.QUARANTINE_DICTIONARY_LOCAL_PARTS || 'true',
+    );
+    // Duplicate signups of an unconfirmed address resend the link, this many times a day
+    subscribeLambda.addEnvironment(
+      'CONFIRMATION_RESENDS_PER_DAY',
+      process.env.CONFIRMATION_RESENDS_PER_DAY || '3',
+    );
+
+    // Producers enqueue instead of calling SES while EMAIL_QUEUE_URL is set
+    for (const fn of [validateLambda, remindLambda, sunsetLambda, adminLambda, digestLambda, scheduledSendLambda, campaignWorkflowLambda, confirmLambda, subscribeLambda]) {
+      fn.addEnvironment('EMAIL_QUEUE_URL', emailSendQueue.queueUrl);
+      emailSendQueue.grantSendMessages(fn);
+    }
+
+    // Email verification provider (zerobounce or kickbox), off unless configured
+    for (const fn of [subscribeLambda, verifyLambda]) {
+      fn.addEnvironment('EMAIL_VERIFICATION_PROVIDER', process.env.EMAIL_VERIFICATION_PROVIDER || '');
+      fn.addEnvironment('EMAIL_VERIFICATION_API_KEY', process.env.EMAIL_VERIFICATION_API_KEY || '');
+    }
+    subscribeLambda.addEnvironment(
+      'EMAIL_VERIFICATION_ON_SUBSCRIBE',
+      process.env.EMAIL_VERIFICATION_ON_SUBSCRIBE || 'false',
+    );
+    // INVITE_ONLY=true requires an invite code to subscribe
+    subscribeLambda.addEnvironment('INVITE_ONLY', process.env.INVITE_ONLY || 'false');
+
     // Grant Lambda functions permissions to access DynamoDB
     subscribersTable.grantReadWriteData(subscribeLambda);
+    idempotencyTable.grantReadWriteData(subscribeLambda);
+    processedMessagesTable.grantReadWriteData(validateLambda);
     subscribersTable.grantReadWriteData(unsubscribeLambda);
+    for (const fn of [subscribeLambda, confirmLambda, unsubscribeLambda, adminLambda]) {
+      historyTable.grantWriteData(fn);
+    }
+    for (const fn of [subscribeLambda, confirmLambda, unsubscribeLambda, trackLambda, sesEventsLambda]) {
+      domainStatsTable.grantWriteData(fn);
+    }
+    domainStatsTable.grantReadWriteData(adminLambda);
+    // Per-IP signup counters
+    quotasTable.grantReadWriteData(subscribeLambda);
+    auditTable.grantReadWriteData(adminLambda);
+    domainRulesTable.grantReadData(subscribeLambda);
+    domainRulesTable.grantReadWriteData(adminLambda);
+    inviteCodesTable.grantReadWriteData(subscribeLambda);
+    inviteCodesTable.grantReadWriteData(adminLambda);
+    digestItemsTable.grantWriteData(adminLambda);
+    // Cancelling a scheduled campaign closes its pending buckets
+    campaignScheduleTable.grantReadWriteData(adminLambda);
+    campaignRecipientsTable.grantReadWriteData(adminLambda);
+    adminLambda.addEnvironment('DEFAULT_TIMEZONE', process.env.DEFAULT_TIMEZONE || 'UTC');
+    adminLambda.addEnvironment('AUDIT_RETENTION_DAYS', process.env.AUDIT_RETENTION_DAYS || '365');
+    // Sender identities are checked with SES before campaigns go out from them
+    for (const fn of [adminLambda, scheduledSendLambda, campaignWorkflowLambda]) {
+      senderIdentitiesTable.grantReadWriteData(fn);
+      fn.addToRolePolicy(new iam.PolicyStatement({
+        actions: ['ses:GetEmailIdentity'],
+        resources: ['*'],
+      }));
+    }
+    templatesTable.grantReadWriteData(adminLambda);
+    templateVersionsTable.grantReadWriteData(adminLambda);
+    adminLambda.addToRolePolicy(new iam.PolicyStatement({
+      actions: ['ses:CreateEmailIdentity'],
+      resources: ['*'],
+    }));
 
     // API Gateway
     const api = new apigateway.RestApi(this, 'NewsletterAPI', {
-      restApiName: 'Newsletter Service',
+      restApiName: stage ? `Newsletter Service (${stage})` : 'Newsletter Service',
       description: 'API for newsletter subscription management',
       deployOptions: {
         stageName: 'v1',
@@ -92,6 +1099,7 @@ export class NewsletterBackendStack extends cdk.Stack {
       defaultCorsPreflightOptions: {
         allowOrigins: apigateway.Cors.ALL_ORIGINS,
         allowMethods: apigateway.Cors.ALL_METHODS,
+        allowHeaders: [...apigateway.Cors.DEFAULT_HEADERS, 'Idempotency-Key'],
       },
     });
 
@@ -104,13 +1112,481 @@ export class NewsletterBackendStack extends cdk.Stack {
     const unsubscribeIntegration = new apigateway.LambdaIntegration(unsubscribeLambda);
     const unsubscribeResource = api.root.addResource('unsubscribe');
     unsubscribeResource.addMethod('POST', unsubscribeIntegration);
+    const oneClickResource = unsubscribeResource.addResource('one-click');
+    oneClickResource.addMethod('POST', unsubscribeIntegration);
+    // The {{ unsubscribe_url }} link in email bodies opens a confirmation page
+    oneClickResource.addMethod('GET', unsubscribeIntegration);
 
     // Confirm endpoint
     const confirmIntegration = new apigateway.LambdaIntegration(confirmLambda);
     const confirmResource = api.root.addResource('confirm');
     confirmResource.addMethod('GET', confirmIntegration);
+    confirmResource.addMethod('POST', confirmIntegration);
+
+    // Tracking endpoint
+    const trackIntegration = new apigateway.LambdaIntegration(trackLambda);
+    const trackResource = api.root.addResource('track');
+    trackResource.addMethod('GET', trackIntegration);
+
+    // Admin endpoints
+    const adminIntegration = new apigateway.LambdaIntegration(adminLambda);
+    const adminResource = api.root.addResource('admin', adminResourceOptions);
+    const adminSubscribersResource = adminResource.addResource('subscribers');
+    adminSubscribersResource.addMethod('GET', adminIntegration);
+    adminSubscribersResource.addMethod('POST', adminIntegration);
+    adminSubscribersResource.addResource('search').addMethod('GET', adminIntegration);
+    adminSubscribersResource.addResource('lookup').addMethod('GET', adminIntegration);
+    adminSubscribersResource.addResource('changes').addMethod('GET', adminIntegration);
+    const adminSubscriberResource = adminSubscribersResource.addResource('{id}');
+    adminSubscriberResource.addMethod('GET', adminIntegration);
+    adminSubscriberResource.addResource('history').addMethod('GET', adminIntegration);
+    adminSubscriberResource.addResource('frequency').addMethod('PUT', adminIntegration);
+    adminSubscriberResource.addResource('approve').addMethod('POST', adminIntegration);
+    adminSubscriberResource.addResource('confirm').addMethod('POST', adminIntegration);
+    const statsResource = adminResource.addResource('stats');
+    statsResource.addResource('sources').addMethod('GET', adminIntegration);
+    statsResource.addResource('referrals').addMethod('GET', adminIntegration);
+    statsResource.addResource('domains').addMethod('GET', adminIntegration);
+    statsResource.addResource('subscribers').addMethod('GET', adminIntegration);
+    const domainRulesResource = adminResource.addResource('domain-rules');
+    domainRulesResource.addMethod('GET', adminIntegration);
+    const domainRuleResource = domainRulesResource.addResource('{domain}');
+    domainRuleResource.addMethod('PUT', adminIntegration);
+    domainRuleResource.addMethod('DELETE', adminIntegration);
+    const invitesResource = adminResource.addResource('invites');
+    invitesResource.addMethod('GET', adminIntegration);
+    invitesResource.addMethod('POST', adminIntegration);
+    invitesResource.addResource('{code}').addMethod('DELETE', adminIntegration);
+    const sendersResource = adminResource.addResource('senders');
+    sendersResource.addMethod('GET', adminIntegration);
+    sendersResource.addMethod('POST', adminIntegration);
+    const senderResource = sendersResource.addResource('{id}');
+    senderResource.addMethod('GET', adminIntegration);
+    senderResource.addMethod('DELETE', adminIntegration);
+    const templatesResource = adminResource.addResource('templates');
+    templatesResource.addMethod('GET', adminIntegration);
+    templatesResource.addMethod('POST', adminIntegration);
+    const templateResource = templatesResource.addResource('{id}');
+    templateResource.addMethod('GET', adminIntegration);
+    templateResource.addMethod('PUT', adminIntegration);
+    templateResource.addResource('publish').addMethod('POST', adminIntegration);
+    adminResource.addResource('preview').addMethod('POST', adminIntegration);
+    adminResource.addResource('import').addResource('preview').addMethod('POST', adminIntegration);
+    adminResource.addResource('audit').addMethod('GET', adminIntegration);
+    const jobsResource = adminResource.addResource('jobs');
+    jobsResource.addMethod('GET', adminIntegration);
+    jobsResource.addMethod('POST', adminIntegration);
+    const jobResource = jobsResource.addResource('{id}');
+    jobResource.addMethod('GET', adminIntegration);
+    jobResource.addResource('cancel').addMethod('POST', adminIntegration);
+    adminResource.addResource('opt-outs').addResource('replay').addMethod('POST', adminIntegration);
+    const campaignResource = adminResource.addResource('campaigns').addResource('{id}');
+    campaignResource.addResource('send').addMethod('POST', adminIntegration);
+    campaignResource.addResource('check-links').addMethod('POST', adminIntegration);
+    campaignResource.addResource('cancel').addMethod('POST', adminIntegration);
+    campaignResource.addResource('exclusions').addMethod('PUT', adminIntegration);
+
+    // The same public and admin endpoints under /v1, answering with the v1
+    // response envelope. The handlers strip the prefix and route as before.
+    const v1Resource = api.root.addResource('v1');
+    v1Resource.addResource('subscribe').addMethod('POST', subscribeIntegration);
+    const v1UnsubscribeResource = v1Resource.addResource('unsubscribe');
+    v1UnsubscribeResource.addMethod('POST', unsubscribeIntegration);
+    const v1OneClickResource = v1UnsubscribeResource.addResource('one-click');
+    v1OneClickResource.addMethod('POST', unsubscribeIntegration);
+    v1OneClickResource.addMethod('GET', unsubscribeIntegration);
+    const v1ConfirmResource = v1Resource.addResource('confirm');
+    v1ConfirmResource.addMethod('GET', confirmIntegration);
+    v1ConfirmResource.addMethod('POST', confirmIntegration);
+    v1Resource
+      .addResource('admin', adminResourceOptions)
+      .addProxy({ defaultIntegration: adminIntegration });
+
+    // Stripe webhook endpoint
+    const stripeWebhookIntegration = new apigateway.LambdaIntegration(stripeWebhookLambda);
+    api.root.addResource('stripe').addResource('webhook').addMethod('POST', stripeWebhookIntegration);
+
+    // ESP event webhooks, e.g. /webhooks/sendgrid and /webhooks/postmark
+    const espWebhookIntegration = new apigateway.LambdaIntegration(espWebhookLambda);
+    api.root.addResource('webhooks').addResource('{provider}').addMethod('POST', espWebhookIntegration);
+
+    // OpenAPI document
+    const openapiIntegration = new apigateway.LambdaIntegration(openapiLambda);
+    api.root.addResource('openapi.json').addMethod('GET', openapiIntegration);
+
+    // Health check and build info
+    const healthIntegration = new apigateway.LambdaIntegration(healthLambda);
+    api.root.addResource('health').addMethod('GET', healthIntegration);
+    api.root.addResource('version').addMethod('GET', healthIntegration);
+
+    // Embeddable signup form script
+    const embedIntegration = new apigateway.LambdaIntegration(embedLambda);
+    api.root.addResource('embed.js').addMethod('GET', embedIntegration);
+    api.root.addResource('web').addMethod('GET', new apigateway.LambdaIntegration(webVersionLambda));
+
+    // Referral endpoint
+    const referralIntegration = new apigateway.LambdaIntegration(referralLambda);
+    api.root.addResource('referral').addMethod('GET', referralIntegration);
+    v1Resource.addResource('referral').addMethod('GET', referralIntegration);
+
+    // Validation token policy, shared so emails and the confirm endpoint agree on it
+    for (const fn of [validateLambda, remindLambda, confirmLambda]) {
+      fn.addEnvironment('VALIDATION_TOKEN_TTL_HOURS', process.env.VALIDATION_TOKEN_TTL_HOURS || '24');
+      fn.addEnvironment('VALIDATION_TOKEN_FORMAT', process.env.VALIDATION_TOKEN_FORMAT || 'uuid');
+      fn.addEnvironment('VALIDATION_TOKEN_BYTES', process.env.VALIDATION_TOKEN_BYTES || '16');
+      fn.addEnvironment('VALIDATION_MAX_REMINDERS', process.env.VALIDATION_MAX_REMINDERS || '1');
+      fn.addEnvironment('VALIDATION_PENDING_RETENTION_HOURS', process.env.VALIDATION_PENDING_RETENTION_HOURS || '72');
+    }
+
+    // Outgoing email goes through the configuration set; PIXEL_TRACKING=false drops
+    // the open pixel and click redirects in favour of SES tracking
+    for (const fn of [validateLambda, remindLambda, sunsetLambda, adminLambda, digestLambda, scheduledSendLambda, campaignWorkflowLambda, sendEmailLambda]) {
+      fn.addEnvironment('SES_CONFIGURATION_SET', configurationSet.configurationSetName);
+      fn.addEnvironment('PIXEL_TRACKING', process.env.PIXEL_TRACKING || 'true');
+    }
+
+    // DRY_RUN=true logs emails instead of sending them, e.g. on a staging stage loaded
+    // with production data. DRY_RUN_BUCKET names an existing bucket keeping each message.
+    const dryRunBucketName = process.env.DRY_RUN_BUCKET || '';
+    const dryRunBucket = dryRunBucketName
+      ? cdk.aws_s3.Bucket.fromBucketName(this, 'DryRunBucket', dryRunBucketName)
+      : undefined;
+    for (const fn of [validateLambda, remindLambda, sunsetLambda, adminLambda, digestLambda, scheduledSendLambda, campaignWorkflowLambda, sendEmailLambda, confirmLambda, subscribeLambda]) {
+      fn.addEnvironment('DRY_RUN', process.env.DRY_RUN || 'false');
+      fn.addEnvironment('DRY_RUN_BUCKET', dryRunBucketName);
+      // Outside production, recipients not on the allowlist are redirected to the catch-all
+      fn.addEnvironment('RECIPIENT_ALLOWLIST', process.env.RECIPIENT_ALLOWLIST || '');
+      fn.addEnvironment('CATCH_ALL_EMAIL', process.env.CATCH_ALL_EMAIL || '');
+      dryRunBucket?.grantPut(fn);
+    }
+
+    // Tracking and unsubscribe links in emails point back at this API, signed with a shared secret
+    const signingSecret = process.env.SIGNING_SECRET || '';
+    for (const fn of [validateLambda, remindLambda, sunsetLambda, adminLambda, digestLambda, scheduledSendLambda, campaignWorkflowLambda, sendEmailLambda, webVersionLambda]) {
+      fn.addEnvironment('API_URL', api.url);
+      fn.addEnvironment('SIGNING_SECRET', signingSecret);
+    }
+    unsubscribeLambda.addEnvironment('SIGNING_SECRET', signingSecret);
+    openapiLambda.addEnvironment('API_URL', api.url);
+    embedLambda.addEnvironment('API_URL', api.url);
+
+    // Team chat notifications for confirmations and unsubscribes, off unless a webhook URL is set
+    const notifyEnv: Record<string, string> = {
+      NOTIFY_SLACK_WEBHOOK_URL: process.env.NOTIFY_SLACK_WEBHOOK_URL || '',
+      NOTIFY_DISCORD_WEBHOOK_URL: process.env.NOTIFY_DISCORD_WEBHOOK_URL || '',
+      NOTIFY_WEBHOOK_URL: process.env.NOTIFY_WEBHOOK_URL || '',
+      NOTIFY_WEBHOOK_SECRET: process.env.NOTIFY_WEBHOOK_SECRET || '',
+    };
+    for (const fn of [subscribeLambda, confirmLambda, unsubscribeLambda, adminLambda]) {
+      for (const [name, value] of Object.entries(notifyEnv)) {
+        fn.addEnvironment(name, value);
+      }
+    }
+
+    // Opt-out webhook for CRMs and outreach tools, from every Lambda that
+    // unsubscribes or suppresses; admin also replays missed events
+    const optOutEnv: Record<string, string> = {
+      OPT_OUT_WEBHOOK_URL: process.env.OPT_OUT_WEBHOOK_URL || '',
+      OPT_OUT_WEBHOOK_SECRET: process.env.OPT_OUT_WEBHOOK_SECRET || '',
+    };
+    for (const fn of [
+      unsubscribeLambda,
+      sesEventsLambda,
+      espWebhookLambda,
+      sunsetLambda,
+      verifyLambda,
+      adminLambda,
+    ]) {
+      for (const [name, value] of Object.entries(optOutEnv)) {
+        fn.addEnvironment(name, value);
+      }
+    }
+
+    // Canary Lambda Function, runs a synthetic subscribe → confirm → unsubscribe
+    // against the deployed API every 15 minutes
+    const canaryLambda = new RustFunction(this, 'CanaryLambda', {
+      manifestPath: '../Cargo.toml',
+      functionName: `newsletter-canary${nameSuffix}`,
+      architecture: lambda.Architecture.ARM_64,
+      memorySize: 128,
+      timeout: cdk.Duration.minutes(2),
+
+      environment: {
+        API_URL: api.url,
+        CANARY_EMAIL: process.env.CANARY_EMAIL || 'success+canary@simulator.amazonses.com',
+      },
+
+      binaryName: 'canary',
+    });
+    subscribersTable.grantReadWriteData(canaryLambda);
+    eventsTable.grantReadWriteData(canaryLambda);
+    historyTable.grantReadWriteData(canaryLambda);
+
+    new events.Rule(this, 'CanarySchedule', {
+      schedule: events.Schedule.rate(cdk.Duration.minutes(15)),
+      targets: [new targets.LambdaFunction(canaryLambda)],
+    });
+
+    // Event archive for long-term analytics in Athena, enabled with
+    // EVENT_ARCHIVE=true. Engagement and lifecycle events go through Firehose
+    // to S3 as Parquet, partitioned by day, next to their DynamoDB writes.
+    if (process.env.EVENT_ARCHIVE === 'true') {
+      const archiveBucket = new cdk.aws_s3.Bucket(this, 'EventArchiveBucket', {
+        encryption: cdk.aws_s3.BucketEncryption.S3_MANAGED,
+        blockPublicAccess: cdk.aws_s3.BlockPublicAccess.BLOCK_ALL,
+        removalPolicy: cdk.RemovalPolicy.RETAIN,
+        lifecycleRules: process.env.EVENT_ARCHIVE_RETENTION_DAYS
+          ? [{ expiration: cdk.Duration.days(Number(process.env.EVENT_ARCHIVE_RETENTION_DAYS)) }]
+          : [],
+      });
+
+      const archiveDatabase = new cdk.aws_glue.CfnDatabase(this, 'EventArchiveDatabase', {
+        catalogId: this.account,
+        databaseInput: { name: `newsletter_archive${nameSuffix.replace(/-/g, '_')}` },
+      });
+      const archiveTable = new cdk.aws_glue.CfnTable(this, 'EventArchiveTable', {
+        catalogId: this.account,
+        databaseName: archiveDatabase.ref,
+        tableInput: {
+          name: 'events',
+          tableType: 'EXTERNAL_TABLE',
+          partitionKeys: [{ name: 'dt', type: 'string' }],
+          parameters: {
+            classification: 'parquet',
+            // Partition projection, so Athena finds new days without crawlers
+            'projection.enabled': 'true',
+            'projection.dt.type': 'date',
+            'projection.dt.format': 'yyyy-MM-dd',
+            'projection.dt.range': '2024-01-01,NOW',
+            'projection.dt.interval': '1',
+            'projection.dt.interval.unit': 'DAYS',
+            'storage.location.template': `s3://${archiveBucket.bucketName}/events/dt=\${dt}/`,
+          },
+          storageDescriptor: {
+            location: `s3://${archiveBucket.bucketName}/events/`,
+            inputFormat: 'org.apache.hadoop.hive.ql.io.parquet.MapredParquetInputFormat',
+            outputFormat: 'org.apache.hadoop.hive.ql.io.parquet.MapredParquetOutputFormat',
+            serdeInfo: {
+              serializationLibrary: 'org.apache.hadoop.hive.ql.io.parquet.serde.ParquetHiveSerDe',
+            },
+            // Same fields as `archive::ArchivedEvent`
+            columns: [
+              { name: 'kind', type: 'string' },
+              { name: 'event_type', type: 'string' },
+              { name: 'subscriber_id', type: 'string' },
+              { name: 'campaign_id', type: 'string' },
+              { name: 'url', type: 'string' },
+              { name: 'message_id', type: 'string' },
+              { name: 'source', type: 'string' },
+              { name: 'detail', type: 'string' },
+              { name: 'occurred_at', type: 'timestamp' },
+            ],
+          },
+        },
+      });
+
+      const firehoseRole = new iam.Role(this, 'EventArchiveFirehoseRole', {
+        assumedBy: new iam.ServicePrincipal('firehose.amazonaws.com'),
+      });
+      archiveBucket.grantReadWrite(firehoseRole);
+      firehoseRole.addToPolicy(new iam.PolicyStatement({
+        actions: ['glue:GetTable', 'glue:GetTableVersion', 'glue:GetTableVersions'],
+        resources: ['*'],
+      }));
+
+      const archiveStream = new cdk.aws_kinesisfirehose.CfnDeliveryStream(this, 'EventArchiveStream', {
+        deliveryStreamName: `newsletter-event-archive${nameSuffix}`,
+        deliveryStreamType: 'DirectPut',
+        extendedS3DestinationConfiguration: {
+          bucketArn: archiveBucket.bucketArn,
+          roleArn: firehoseRole.roleArn,
+          prefix: 'events/dt=!{timestamp:yyyy-MM-dd}/',
+          errorOutputPrefix: 'errors/!{firehose:error-output-type}/dt=!{timestamp:yyyy-MM-dd}/',
+          // Parquet conversion needs buffers of at least 64 MB
+          bufferingHints: { sizeInMBs: 128, intervalInSeconds: 900 },
+          dataFormatConversionConfiguration: {
+            enabled: true,
+            inputFormatConfiguration: { deserializer: { openXJsonSerDe: {} } },
+            outputFormatConfiguration: { serializer: { parquetSerDe: {} } },
+            schemaConfiguration: {
+              catalogId: this.account,
+              databaseName: archiveDatabase.ref,
+              tableName: archiveTable.ref,
+              region: this.region,
+              roleArn: firehoseRole.roleArn,
+            },
+          },
+        },
+      });
+      archiveStream.node.addDependency(firehoseRole);
+
+      for (const fn of [
+        subscribeLambda,
+        confirmLambda,
+        unsubscribeLambda,
+        trackLambda,
+        sesEventsLambda,
+        espWebhookLambda,
+        sunsetLambda,
+        verifyLambda,
+        adminLambda,
+        canaryLambda,
+      ]) {
+        fn.addEnvironment('EVENT_ARCHIVE_STREAM', archiveStream.ref);
+        fn.addToRolePolicy(new iam.PolicyStatement({
+          actions: ['firehose:PutRecordBatch'],
+          resources: [archiveStream.attrArn],
+        }));
+      }
+
+      new cdk.CfnOutput(this, 'EventArchiveBucketName', {
+        value: archiveBucket.bucketName,
+        description: 'Bucket the event archive is written to',
+      });
+    }
+
+    // Every Lambda reading or writing subscribers
+    const subscriberLambdas = [
+      subscribeLambda,
+      unsubscribeLambda,
+      validateLambda,
+      confirmLambda,
+      remindLambda,
+      trackLambda,
+      sesEventsLambda,
+      adminLambda,
+      referralLambda,
+      stripeWebhookLambda,
+      espWebhookLambda,
+      sunsetLambda,
+      digestLambda,
+      pulseLambda,
+      scheduledSendLambda,
+      campaignWorkflowLambda,
+      verifyLambda,
+      retentionLambda,
+      canaryLambda,
+      reconcileLambda,
+      requeueLambda,
+      jobWorkerLambda,
+      webVersionLambda,
+    ];
+
+    for (const fn of subscriberLambdas) {
+      countersTable.grantReadWriteData(fn);
+    }
+
+    // Envelope encryption of subscriber emails and custom fields, enabled with
+    // PII_KMS_KEY_ID. Every Lambda reading or writing subscribers needs it, and
+    // Decrypt on any key so records from before a key change still read.
+    if (process.env.PII_KMS_KEY_ID) {
+      const piiEnv: Record<string, string> = {
+        PII_KMS_KEY_ID: process.env.PII_KMS_KEY_ID,
+        PII_INDEX_SECRET: process.env.PII_INDEX_SECRET || '',
+      };
+      for (const fn of subscriberLambdas) {
+        for (const [name, value] of Object.entries(piiEnv)) {
+          fn.addEnvironment(name, value);
+        }
+        fn.addToRolePolicy(new iam.PolicyStatement({
+          actions: ['kms:GenerateDataKey', 'kms:Decrypt'],
+          resources: ['*'],
+        }));
+      }
+    }
+
+    // Regions subscriber reads and writes go to when the table is a Global
+    // Table, e.g. every region writing through the home region. Other regions'
+    // copies aren't covered by the grants on this region's table.
+    const regionEnv: Record<string, string> = {
+      DYNAMODB_WRITE_REGION: process.env.DYNAMODB_WRITE_REGION || '',
+      DYNAMODB_READ_REGION: process.env.DYNAMODB_READ_REGION || '',
+      DYNAMODB_READ_PREFERENCE: process.env.DYNAMODB_READ_PREFERENCE || 'local',
+    };
+    const remoteTables = [regionEnv.DYNAMODB_WRITE_REGION, regionEnv.DYNAMODB_READ_REGION]
+      .filter((region) => region.length > 0)
+      .flatMap((region) => {
+        const table = this.formatArn({
+          service: 'dynamodb',
+          region,
+          resource: 'table',
+          resourceName: `newsletter_subscribers${tableSuffix}`,
+        });
+        const counters = this.formatArn({
+          service: 'dynamodb',
+          region,
+          resource: 'table',
+          resourceName: `newsletter_subscriber_counters${tableSuffix}`,
+        });
+        return [table, `${table}/index/*`, counters];
+      });
+    for (const fn of subscriberLambdas) {
+      for (const [name, value] of Object.entries(regionEnv)) {
+        fn.addEnvironment(name, value);
+      }
+      if (remoteTables.length > 0) {
+        fn.addToRolePolicy(new iam.PolicyStatement({
+          actions: [
+            'dynamodb:GetItem',
+            'dynamodb:Query',
+            'dynamodb:Scan',
+            'dynamodb:PutItem',
+            'dynamodb:UpdateItem',
+            'dynamodb:DeleteItem',
+            'dynamodb:BatchWriteItem',
+          ],
+          resources: remoteTables,
+        }));
+      }
+    }
+
+    // Alarm after two failed (or missing) canary runs in a row
+    new cdk.aws_cloudwatch.Alarm(this, 'CanaryAlarm', {
+      metric: new cdk.aws_cloudwatch.Metric({
+        namespace: 'Newsletter',
+        metricName: 'CanarySuccess',
+        statistic: 'Minimum',
+        period: cdk.Duration.minutes(15),
+      }),
+      threshold: 1,
+      comparisonOperator: cdk.aws_cloudwatch.ComparisonOperator.LESS_THAN_THRESHOLD,
+      evaluationPeriods: 2,
+      treatMissingData: cdk.aws_cloudwatch.TreatMissingData.BREACHING,
+    });
+    referralLambda.addEnvironment('SIGNING_SECRET', signingSecret);
+
+    // Every binary suffixes its table names with the stage
+    if (stage) {
+      for (const fn of [
+        subscribeLambda, unsubscribeLambda, validateLambda, confirmLambda, remindLambda,
+        trackLambda, sesEventsLambda, adminLambda, referralLambda, stripeWebhookLambda, espWebhookLambda,
+        openapiLambda, healthLambda, embedLambda, sunsetLambda, digestLambda, pulseLambda,
+        scheduledSendLambda, verifyLambda, retentionLambda, sendEmailLambda, canaryLambda,
+        reconcileLambda, requeueLambda, jobWorkerLambda, campaignWorkflowLambda, webVersionLambda,
+      ]) {
+        fn.addEnvironment('STAGE', stage);
+      }
+    }
 
     emailValidationQueue.grantSendMessages(subscribeLambda);
+    emailValidationQueue.grantSendMessages(adminLambda);
+
+    // Bucket for the `backup`/`restore` utilities, versioned so overwritten or
+    // deleted backups can still be recovered
+    const backupBucket = new cdk.aws_s3.Bucket(this, 'BackupBucket', {
+      versioned: true,
+      encryption: cdk.aws_s3.BucketEncryption.S3_MANAGED,
+      blockPublicAccess: cdk.aws_s3.BlockPublicAccess.BLOCK_ALL,
+      removalPolicy: cdk.RemovalPolicy.RETAIN,
+    });
+
+    // Malformed validation messages are quarantined under dlq/validation/poison/
+    validateLambda.addEnvironment('BACKUP_BUCKET', backupBucket.bucketName);
+    backupBucket.grantPut(validateLambda, 'dlq/validation/poison/*');
+
+    // Export jobs write their CSV files under exports/<job id>/
+    jobWorkerLambda.addEnvironment('BACKUP_BUCKET', backupBucket.bucketName);
+    backupBucket.grantPut(jobWorkerLambda, 'exports/*');
 
     // Output the API Gateway URL
     new cdk.CfnOutput(this, 'ApiUrl', {
@@ -122,5 +1598,30 @@ export class NewsletterBackendStack extends cdk.Stack {
       value: emailValidationQueue.queueUrl,
       description: 'The URL of the SQS Queue',
     });
+
+    new cdk.CfnOutput(this, 'DlqUrl', {
+      value: validationDeadLetterQueue.queueUrl,
+      description: 'The URL of the validation dead-letter queue',
+    });
+
+    new cdk.CfnOutput(this, 'EmailSendQueueUrl', {
+      value: emailSendQueue.queueUrl,
+      description: 'The URL of the email send queue',
+    });
+
+    new cdk.CfnOutput(this, 'EmailSendDlqUrl', {
+      value: emailSendDeadLetterQueue.queueUrl,
+      description: 'The URL of the email send dead-letter queue',
+    });
+
+    new cdk.CfnOutput(this, 'BackupBucketName', {
+      value: backupBucket.bucketName,
+      description: 'The S3 bucket for subscriber backups',
+    });
+
+    new cdk.CfnOutput(this, 'AttachmentsBucketName', {
+      value: attachmentsBucket.bucketName,
+      description: 'The S3 bucket for email attachments',
+    });
   }
 }
@@ -0,0 +1,115 @@
+use crate::Error;
+use crate::config::env_or;
+
+// Whether addresses with a UTF-8 local part (RFC 6531), e.g. `josé@example.com`,
+// are accepted, from ALLOW_UTF8_LOCAL_PART. Off by default: SES doesn't support
+// SMTPUTF8, so they can be collected but not sent to.
+pub fn utf8_local_parts() -> bool {
+    env_or("ALLOW_UTF8_LOCAL_PART", false)
+}
+
+// The address with its domain lowercased and converted to punycode (IDNA),
+// e.g. `jane@Bücher.example` becomes `jane@xn--bcher-kva.example`. The local
+// part is kept as given. None without a local part or a valid domain.
+pub fn normalize_email(email: &str) -> Option<String> {
+    let (local, domain) = email.trim().rsplit_once('@')?;
+    if local.is_empty() {
+        return None;
+    }
+    let domain = idna::domain_to_ascii(domain).ok()?;
+    (!domain.is_empty()).then(|| format!("{local}@{domain}"))
+}
+
+// What addresses are compared and indexed by: normalized, then lowercased, so
+// a Unicode domain and its punycode form are the same address. Falls back to
+// the trimmed address when it can't be normalized.
+pub fn canonical_email(email: &str) -> String {
+    normalize_email(email)
+        .unwrap_or_else(|| email.trim().to_string())
+        .to_lowercase()
+}
+
+// The address to hand SES: normalized, which SES needs for a Unicode domain,
+// and with an ASCII local part, as SES can't deliver to any other
+pub fn sendable_email(email: &str) -> Result<String, Error> {
+    let normalized = normalize_email(email).ok_or("Invalid email address")?;
+    if !normalized.is_ascii() {
+        return Err("SES can't send to a UTF-8 local part".into());
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::is_valid_email;
+    use std::env;
+    use std::sync::Mutex;
+
+    // Run with ALLOW_UTF8_LOCAL_PART set, one test at a time
+    fn with_utf8_local_parts<T>(allowed: bool, f: impl FnOnce() -> T) -> T {
+        static LOCK: Mutex<()> = Mutex::new(());
+        let _guard = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // SAFETY: every test touching ALLOW_UTF8_LOCAL_PART holds the lock
+        unsafe { env::set_var("ALLOW_UTF8_LOCAL_PART", allowed.to_string()) };
+        f()
+    }
+
+    #[test]
+    fn converts_unicode_domains_to_punycode() {
+        assert_eq!(
+            normalize_email("jane@Bücher.example").as_deref(),
+            Some("jane@xn--bcher-kva.example")
+        );
+        assert_eq!(
+            normalize_email("  Jane@EXAMPLE.com ").as_deref(),
+            Some("Jane@example.com")
+        );
+    }
+
+    #[test]
+    fn refuses_addresses_without_a_local_part_or_domain() {
+        assert_eq!(normalize_email("@example.com"), None);
+        assert_eq!(normalize_email("jane@"), None);
+        assert_eq!(normalize_email("jane"), None);
+    }
+
+    #[test]
+    fn unicode_and_punycode_domains_are_the_same_address() {
+        assert_eq!(
+            canonical_email("Jane@Bücher.example"),
+            canonical_email("jane@xn--bcher-kva.example")
+        );
+        assert_eq!(canonical_email(" not an address "), "not an address");
+    }
+
+    #[test]
+    fn utf8_local_parts_need_to_be_allowed() {
+        with_utf8_local_parts(false, || {
+            assert!(!utf8_local_parts());
+            assert!(!is_valid_email("josé@example.com"));
+            assert!(is_valid_email("jose@bücher.example"));
+        });
+        with_utf8_local_parts(true, || {
+            assert!(utf8_local_parts());
+            assert!(is_valid_email("josé@example.com"));
+        });
+    }
+
+    #[test]
+    fn sendable_email_normalizes_the_domain() {
+        assert_eq!(
+            sendable_email("jane@Bücher.example").unwrap(),
+            "jane@xn--bcher-kva.example"
+        );
+    }
+
+    #[test]
+    fn sendable_email_rejects_invalid_addresses() {
+        assert!(sendable_email("jane").is_err());
+        assert!(sendable_email("@example.com").is_err());
+        assert!(sendable_email("jane@").is_err());
+        // Accepted when allowed, but SES can't deliver to it
+        assert!(sendable_email("josé@example.com").is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::Subscriber;
+use crate::attribution::SourceStats;
+use crate::events::Event;
+use crate::history::LifecycleEvent;
+use crate::jwt::JwtVerifier;
+use crate::rbac::Role;
+use lambda_http::RequestExt;
+use lambda_http::request::RequestContext;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use tracing::info;
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SubscriberListResponse {
+    pub success: bool,
+    pub subscribers: Vec<Subscriber>,
+    pub next_cursor: Option<String>,
+}
+
+// Where a subscriber stands, from the flags on their record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberStatus {
+    // Signed up, waiting on the confirmation link
+    Pending,
+    // Confirmed and receiving emails
+    Active,
+    Unsubscribed,
+    // Bounced, complained or failed verification
+    Suppressed,
+    // Suspicious signup waiting on an admin's approval
+    Quarantined,
+    // Soft-deleted, waiting to be purged
+    Deleted,
+}
+
+impl SubscriberStatus {
+    pub fn of(subscriber: &Subscriber) -> Self {
+        if subscriber.is_deleted() {
+            SubscriberStatus::Deleted
+        } else if !subscriber.active {
+            SubscriberStatus::Unsubscribed
+        } else if subscriber.suppressed {
+            SubscriberStatus::Suppressed
+        } else if subscriber.is_quarantined() {
+            SubscriberStatus::Quarantined
+        } else if !subscriber.validated {
+            SubscriberStatus::Pending
+        } else {
+            SubscriberStatus::Active
+        }
+    }
+}
+
+// Everything known about one subscriber, for support tooling
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SubscriberDetailResponse {
+    pub success: bool,
+    pub status: SubscriberStatus,
+    pub subscriber: Subscriber,
+    // Lifecycle history, oldest first
+    pub history: Vec<LifecycleEvent>,
+    // Latest delivery and engagement events, newest first
+    pub recent_events: Vec<Event>,
+}
+
+// Longest consent note kept on a subscriber added by an operator
+pub const MAX_CONSENT_NOTE_LENGTH: usize = 500;
+
+// A subscriber an operator adds directly, e.g. from a paper signup sheet. They
+// are confirmed right away, so how they consented has to be noted.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AddSubscriberRequest {
+    pub email: String,
+    // Where and how they agreed to get the newsletter
+    pub consent_note: String,
+    // Custom fields, validated like at signup
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub fields: HashMap<String, serde_json::Value>,
+    // "en" or "es", English when left out
+    pub locale: Option<String>,
+    // Stored as the signup source, "manual" when left out
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SourceBreakdownResponse {
+    pub success: bool,
+    pub dimension: String,
+    pub sources: Vec<SourceStats>,
+}
+
+// Compare without short-circuiting so response timing doesn't leak the key
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Whether a request can change something and so belongs in the audit log.
+// Previews and link checks are POSTs that only read.
+pub fn is_mutation(method: &str, route: &str) -> bool {
+    method != "GET" && !route.ends_with("/preview") && !route.ends_with("/check-links")
+}
+
+fn default_role() -> Role {
+    Role::Admin
+}
+
+// A key allowed to call the admin API, with optional usage quotas
+#[derive(Clone, Deserialize)]
+pub struct ApiKey {
+    // Stable name used for quota counters and logs, never the key itself. For
+    // tokens, `jwt:` and the user's email, username or subject.
+    pub id: String,
+    key: String,
+    #[serde(default)]
+    pub requests_per_day: Option<u64>,
+    #[serde(default)]
+    pub sends_per_month: Option<u64>,
+    // Keys can do everything unless given a lower role
+    #[serde(default = "default_role")]
+    pub role: Role,
+}
+
+impl ApiKey {
+    // A token holder, who has no quotas
+    fn for_token(identity: &str, role: Role) -> Self {
+        Self {
+            id: format!("jwt:{identity}"),
+            key: String::new(),
+            requests_per_day: None,
+            sends_per_month: None,
+            role,
+        }
+    }
+}
+
+// ADMIN_API_KEY is unlimited; integrations get their own keys and quotas from
+// API_KEYS, a JSON array of {"id", "key", "requests_per_day", "sends_per_month",
+// "role"}
+fn api_keys() -> Vec<ApiKey> {
+    let mut keys: Vec<ApiKey> = env::var("API_KEYS")
+        .ok()
+        .and_then(|value| serde_json::from_str(&value).ok())
+        .unwrap_or_default();
+
+    if let Ok(key) = env::var("ADMIN_API_KEY") {
+        keys.push(ApiKey {
+            id: "admin".to_string(),
+            key,
+            requests_per_day: None,
+            sends_per_month: None,
+            role: Role::Admin,
+        });
+    }
+
+    keys.retain(|api_key| !api_key.key.is_empty());
+    keys
+}
+
+// Why a request wasn't authenticated
+pub enum AuthError {
+    // No credentials, or ones that don't check out: 401
+    Unauthorized,
+    // A valid token whose groups map to no role: 403
+    NoRole,
+}
+
+// Admin requests must carry one of the configured keys in the `x-api-key`
+// header, have been let through by the JWT authorizer in front of the admin
+// routes (see `bin/authorizer.rs`), or carry a Cognito or OIDC access token in
+// `Authorization: Bearer` when `verifier` is configured. Token holders get the
+// role their groups map to.
+pub async fn authenticate(
+    request: &lambda_http::Request,
+    verifier: Option<&JwtVerifier>,
+) -> Result<ApiKey, AuthError> {
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+
+    if let Some(provided) = header("x-api-key") {
+        return find_api_key(provided).ok_or(AuthError::Unauthorized);
+    }
+    if let Some((principal, groups)) = authorized_principal(request) {
+        let role = Role::for_groups(&groups).ok_or(AuthError::NoRole)?;
+        return Ok(ApiKey::for_token(&principal, role));
+    }
+
+    let token = header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(AuthError::Unauthorized)?;
+    let verifier = verifier.ok_or(AuthError::Unauthorized)?;
+    let claims = match verifier.verify(token.trim()).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            info!("Rejected admin token: {:?}", e);
+            return Err(AuthError::Unauthorized);
+        }
+    };
+    if let Some(scope) = claims.missing_scope(&verifier.settings().required_scopes) {
+        info!(
+            "Rejected admin token of {}: missing scope {}",
+            claims.sub, scope
+        );
+        return Err(AuthError::NoRole);
+    }
+    let role = Role::for_groups(&verifier.groups(&claims)).ok_or(AuthError::NoRole)?;
+    Ok(ApiKey::for_token(claims.identity(), role))
+}
+
+// The user the gateway's authorizer allowed, with their groups. Only API
+// Gateway can set these, clients can't.
+fn authorized_principal(request: &lambda_http::Request) -> Option<(String, Vec<String>)> {
+    let Some(RequestContext::ApiGatewayV1(context)) = request.request_context_ref() else {
+        return None;
+    };
+    let principal = context
+        .authorizer
+        .get("principalId")
+        .and_then(|value| value.as_str())
+        .filter(|principal| !principal.is_empty())?;
+    let groups = context
+        .authorizer
+        .get("groups")
+        .and_then(|value| value.as_str())
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+
+    Some((principal.to_string(), groups))
+}
+
+// Look up the configured key matching a provided secret
+pub fn find_api_key(provided: &str) -> Option<ApiKey> {
+    // Check every key so timing doesn't reveal which one nearly matched
+    api_keys().into_iter().fold(None, |found, api_key| {
+        if constant_time_eq(provided.as_bytes(), api_key.key.as_bytes()) {
+            Some(api_key)
+        } else {
+            found
+        }
+    })
+}
@@ -0,0 +1,138 @@
+use crate::Error;
+use crate::clients::sdk_config;
+use crate::events::Event;
+use crate::history::LifecycleEvent;
+use aws_sdk_firehose::Client as FirehoseClient;
+use aws_sdk_firehose::primitives::Blob;
+use aws_sdk_firehose::types::Record;
+use serde::Serialize;
+use std::env;
+use std::future::Future;
+use std::sync::OnceLock;
+use tokio::sync::OnceCell;
+use tracing::info;
+
+// PutRecordBatch takes at most this many records
+const MAX_BATCH: usize = 500;
+
+// One event as archived, the same flat shape for engagement and lifecycle
+// events so they land in one table. Matches the Glue schema in the CDK stack.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchivedEvent {
+    // "engagement" or "lifecycle"
+    pub kind: &'static str,
+    pub event_type: String,
+    pub subscriber_id: String,
+    pub campaign_id: Option<String>,
+    pub url: Option<String>,
+    pub message_id: Option<String>,
+    pub source: Option<String>,
+    pub detail: Option<String>,
+    // Epoch milliseconds, read by Firehose as a timestamp
+    pub occurred_at: i64,
+}
+
+impl From<&Event> for ArchivedEvent {
+    fn from(event: &Event) -> Self {
+        Self {
+            kind: "engagement",
+            event_type: event.event_type.as_str().to_string(),
+            subscriber_id: event.subscriber_id.clone(),
+            campaign_id: event.campaign_id.clone(),
+            url: event.url.clone(),
+            message_id: event.message_id.clone(),
+            source: None,
+            detail: event.detail.clone(),
+            occurred_at: event.occurred_at.timestamp_millis(),
+        }
+    }
+}
+
+impl From<&LifecycleEvent> for ArchivedEvent {
+    fn from(event: &LifecycleEvent) -> Self {
+        Self {
+            kind: "lifecycle",
+            event_type: event.event_type.as_str().to_string(),
+            subscriber_id: event.subscriber_id.clone(),
+            campaign_id: None,
+            url: None,
+            message_id: None,
+            source: event.source.clone(),
+            detail: event.detail.clone(),
+            occurred_at: event.occurred_at.timestamp_millis(),
+        }
+    }
+}
+
+// Anywhere events can be archived to, so the destination can be swapped or mocked
+pub trait EventSink {
+    fn emit(&self, events: &[ArchivedEvent]) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+// Sends events to the Firehose stream in EVENT_ARCHIVE_STREAM, which writes
+// them to S3 as Parquet partitioned by date for Athena
+pub struct FirehoseSink {
+    stream: String,
+    client: OnceCell<FirehoseClient>,
+}
+
+impl FirehoseSink {
+    // None when archiving is off
+    pub fn from_env() -> Option<Self> {
+        let stream = env::var("EVENT_ARCHIVE_STREAM")
+            .ok()
+            .filter(|stream| !stream.trim().is_empty())?;
+        Some(Self {
+            stream: stream.trim().to_string(),
+            client: OnceCell::new(),
+        })
+    }
+
+    async fn client(&self) -> &FirehoseClient {
+        self.client
+            .get_or_init(|| async { FirehoseClient::new(sdk_config().await) })
+            .await
+    }
+}
+
+impl EventSink for FirehoseSink {
+    async fn emit(&self, events: &[ArchivedEvent]) -> Result<(), Error> {
+        for chunk in events.chunks(MAX_BATCH) {
+            let mut request = self
+                .client()
+                .await
+                .put_record_batch()
+                .delivery_stream_name(&self.stream);
+            for event in chunk {
+                // Newline-delimited, so the raw objects stay readable too
+                let mut data = serde_json::to_vec(event)?;
+                data.push(b'\n');
+                request = request.records(Record::builder().data(Blob::new(data)).build());
+            }
+
+            let output = request.send().await?;
+            let failed = output.failed_put_count().unwrap_or_default();
+            if failed > 0 {
+                return Err(format!("Firehose rejected {failed} of {} events", chunk.len()).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+// The container's sink, shared by every repository so the client is built once
+fn sink() -> Option<&'static FirehoseSink> {
+    static SINK: OnceLock<Option<FirehoseSink>> = OnceLock::new();
+    SINK.get_or_init(FirehoseSink::from_env).as_ref()
+}
+
+// Archive events when EVENT_ARCHIVE_STREAM is set. DynamoDB stays the source
+// of truth, so failures are only logged.
+pub async fn archive_or_log(events: &[ArchivedEvent]) {
+    let Some(sink) = sink() else {
+        return;
+    };
+    if let Err(e) = sink.emit(events).await {
+        info!("Error archiving {} events: {:?}", events.len(), e);
+    }
+}
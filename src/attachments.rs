@@ -0,0 +1,107 @@
+use crate::Error;
+use crate::config::env_or;
+use crate::email::Attachment;
+use aws_config::SdkConfig;
+use aws_sdk_s3::Client as S3Client;
+use std::env;
+
+// Combined size of the files attached to one email. SES accepts messages up to
+// 40 MB, and base64 grows attachments by a third.
+const DEFAULT_MAX_BYTES: u64 = 5 * 1024 * 1024;
+
+// Content type for a file name, for objects uploaded without one
+fn content_type_for(filename: &str) -> &'static str {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "pdf" => "application/pdf",
+        "epub" => "application/epub+zip",
+        "zip" => "application/zip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        _ => "application/octet-stream",
+    }
+}
+
+// Files attached to emails, e.g. a PDF guide promised on the signup form, kept
+// as objects in ATTACHMENTS_BUCKET
+pub struct AttachmentStore {
+    client: S3Client,
+    bucket: String,
+    // Limit for all attachments of one email, from ATTACHMENT_MAX_BYTES
+    max_bytes: u64,
+}
+
+impl AttachmentStore {
+    // None when ATTACHMENTS_BUCKET isn't set
+    pub fn from_env(config: &SdkConfig) -> Option<Self> {
+        let bucket = env::var("ATTACHMENTS_BUCKET")
+            .ok()
+            .filter(|bucket| !bucket.is_empty())?;
+
+        Some(Self {
+            client: S3Client::new(config),
+            bucket,
+            max_bytes: env_or("ATTACHMENT_MAX_BYTES", DEFAULT_MAX_BYTES),
+        })
+    }
+
+    // Download the objects at `keys`, refusing them when together they are over
+    // the size limit. Each is named after the last segment of its key.
+    pub async fn fetch(&self, keys: &[String]) -> Result<Vec<Attachment>, Error> {
+        let mut attachments = Vec::new();
+        let mut total: u64 = 0;
+
+        for key in keys {
+            let result = self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .send()
+                .await?;
+
+            // Checked before downloading, and again after in case the size was unknown
+            let declared = u64::try_from(result.content_length()).unwrap_or(0);
+            if total + declared > self.max_bytes {
+                return Err(self.too_large(key));
+            }
+
+            let filename = key.rsplit('/').next().unwrap_or(key).to_string();
+            let content_type = result
+                .content_type()
+                .filter(|content_type| {
+                    !content_type.is_empty() && *content_type != "binary/octet-stream"
+                })
+                .unwrap_or_else(|| content_type_for(&filename))
+                .to_string();
+            let data = result.body.collect().await?.into_bytes().to_vec();
+
+            total += data.len() as u64;
+            if total > self.max_bytes {
+                return Err(self.too_large(key));
+            }
+            attachments.push(Attachment {
+                filename,
+                content_type,
+                data,
+            });
+        }
+
+        Ok(attachments)
+    }
+
+    fn too_large(&self, key: &str) -> Error {
+        format!(
+            "Attachments are over the {} byte limit at {}",
+            self.max_bytes, key
+        )
+        .into()
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{SubscribeRequest, Subscriber};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+// Attribution values are free-form client input, so cap what gets stored
+const MAX_VALUE_LENGTH: usize = 200;
+
+// Where a signup came from, as reported by the subscribe form
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Attribution {
+    pub source: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub referrer: Option<String>,
+}
+
+fn clean(value: Option<&str>) -> Option<String> {
+    let value = value?.trim();
+    if value.is_empty() {
+        return None;
+    }
+    Some(value.chars().take(MAX_VALUE_LENGTH).collect())
+}
+
+impl Attribution {
+    // Read attribution from the request body, falling back to the Referer header
+    pub fn from_request(request: &SubscribeRequest, referer_header: Option<&str>) -> Self {
+        Self {
+            source: clean(request.source.as_deref()),
+            utm_source: clean(request.utm_source.as_deref()),
+            utm_medium: clean(request.utm_medium.as_deref()),
+            utm_campaign: clean(request.utm_campaign.as_deref()),
+            referrer: clean(request.referrer.as_deref()).or_else(|| clean(referer_header)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    fn entries(&self) -> [(&'static str, &Option<String>); 5] {
+        [
+            ("source", &self.source),
+            ("utm_source", &self.utm_source),
+            ("utm_medium", &self.utm_medium),
+            ("utm_campaign", &self.utm_campaign),
+            ("referrer", &self.referrer),
+        ]
+    }
+
+    // Stored as a map of the values that are set
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        self.entries()
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.clone()?)))
+            .collect()
+    }
+
+    pub fn from_map(mut map: BTreeMap<String, String>) -> Self {
+        Self {
+            source: map.remove("source"),
+            utm_source: map.remove("utm_source"),
+            utm_medium: map.remove("utm_medium"),
+            utm_campaign: map.remove("utm_campaign"),
+            referrer: map.remove("referrer"),
+        }
+    }
+
+    // The value to group by for a breakdown dimension. Referrers are reduced to
+    // their host so individual pages don't fragment the report.
+    pub fn dimension(&self, dimension: &str) -> Option<String> {
+        match dimension {
+            "referrer" => self.referrer.as_deref().map(|referrer| {
+                url::Url::parse(referrer)
+                    .ok()
+                    .and_then(|url| url.host_str().map(|host| host.to_string()))
+                    .unwrap_or_else(|| referrer.to_string())
+            }),
+            _ => self
+                .entries()
+                .into_iter()
+                .find(|(name, _)| *name == dimension)
+                .and_then(|(_, value)| value.clone()),
+        }
+    }
+}
+
+pub const DIMENSIONS: [&str; 5] = [
+    "source",
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "referrer",
+];
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SourceStats {
+    pub value: String,
+    pub subscribers: u64,
+    pub confirmed: u64,
+    pub confirmation_rate: f64,
+}
+
+// Count subscribers and confirmed subscribers per value of a dimension, most
+// confirmed first. Signups without a value are reported as "(none)".
+pub fn breakdown(subscribers: &[Subscriber], dimension: &str) -> Vec<SourceStats> {
+    let mut counts: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+
+    for subscriber in subscribers {
+        let value = subscriber
+            .attribution
+            .dimension(dimension)
+            .unwrap_or_else(|| "(none)".to_string());
+        let entry = counts.entry(value).or_default();
+        entry.0 += 1;
+        if subscriber.validated {
+            entry.1 += 1;
+        }
+    }
+
+    let mut stats: Vec<SourceStats> = counts
+        .into_iter()
+        .map(|(value, (subscribers, confirmed))| SourceStats {
+            value,
+            subscribers,
+            confirmed,
+            confirmation_rate: confirmed as f64 / subscribers as f64,
+        })
+        .collect();
+    stats.sort_by(|a, b| {
+        b.confirmed
+            .cmp(&a.confirmed)
+            .then(b.subscribers.cmp(&a.subscribers))
+    });
+
+    stats
+}
@@ -0,0 +1,256 @@
+use crate::config::{env_or, table_name};
+use crate::signing;
+use crate::{AUDIT_LOG_TABLE_NAME, Error};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::info;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// Keeps audit signatures from being usable as signatures for anything else
+const AUDIT_CONTEXT: &str = "audit";
+
+// How long entries are kept before DynamoDB TTL removes them
+const DEFAULT_RETENTION_DAYS: i64 = 365;
+
+// One admin API request that changed something: who made it, what it was and
+// how it ended. Request bodies aren't kept, since they can hold subscriber data.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditEntry {
+    // Id of the API key that made the request, never the key itself
+    pub api_key_id: String,
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    // HTTP status of the response
+    pub status: u16,
+    pub occurred_at: DateTime<Utc>,
+    // HMAC-SHA256 of the fields above keyed with SIGNING_SECRET, None when the
+    // secret wasn't set
+    pub signature: Option<String>,
+    // Whether the signature matches, so entries edited in the table stand out
+    #[serde(default)]
+    pub verified: bool,
+}
+
+impl AuditEntry {
+    pub fn new(
+        api_key_id: &str,
+        method: &str,
+        path: &str,
+        query: Option<&str>,
+        status: u16,
+    ) -> Self {
+        let mut entry = Self {
+            api_key_id: api_key_id.to_string(),
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.filter(|query| !query.is_empty()).map(str::to_string),
+            status,
+            occurred_at: Utc::now(),
+            signature: None,
+            verified: false,
+        };
+        match signing::sign(&entry.signed_payload()) {
+            Ok(signature) => {
+                entry.signature = Some(signature);
+                entry.verified = true;
+            }
+            Err(e) => info!("Audit entry left unsigned: {:?}", e),
+        }
+        entry
+    }
+
+    fn timestamp(&self) -> String {
+        self.occurred_at
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+    }
+
+    fn signed_payload(&self) -> String {
+        format!(
+            "{AUDIT_CONTEXT}:{}\n{}\n{}\n{}\n{}\n{}",
+            self.api_key_id,
+            self.method,
+            self.path,
+            self.query.as_deref().unwrap_or_default(),
+            self.status,
+            self.timestamp()
+        )
+    }
+
+    pub fn to_dynamodb_item(&self, retention_days: i64) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+
+        // Fixed-width timestamps keep the sort key in chronological order
+        let entry_key = format!("{}#{}", self.timestamp(), Uuid::new_v4());
+
+        item.insert(
+            "day".to_string(),
+            AttributeValue::S(self.occurred_at.format("%Y-%m-%d").to_string()),
+        );
+        item.insert("entry_key".to_string(), AttributeValue::S(entry_key));
+        item.insert(
+            "api_key_id".to_string(),
+            AttributeValue::S(self.api_key_id.clone()),
+        );
+        item.insert("method".to_string(), AttributeValue::S(self.method.clone()));
+        item.insert("path".to_string(), AttributeValue::S(self.path.clone()));
+        if let Some(query) = &self.query {
+            item.insert("query".to_string(), AttributeValue::S(query.clone()));
+        }
+        item.insert(
+            "status".to_string(),
+            AttributeValue::N(self.status.to_string()),
+        );
+        item.insert(
+            "occurred_at".to_string(),
+            AttributeValue::S(self.timestamp()),
+        );
+        if let Some(signature) = &self.signature {
+            item.insert(
+                "signature".to_string(),
+                AttributeValue::S(signature.clone()),
+            );
+        }
+        item.insert(
+            "expires_at".to_string(),
+            AttributeValue::N(
+                (self.occurred_at + Duration::days(retention_days))
+                    .timestamp()
+                    .to_string(),
+            ),
+        );
+
+        item
+    }
+
+    pub fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let string = |name: &str| item.get(name).and_then(|value| value.as_s().ok()).cloned();
+
+        let mut entry = Self {
+            api_key_id: string("api_key_id")?,
+            method: string("method")?,
+            path: string("path")?,
+            query: string("query"),
+            status: item.get("status")?.as_n().ok()?.parse().ok()?,
+            occurred_at: DateTime::parse_from_rfc3339(&string("occurred_at")?)
+                .ok()?
+                .with_timezone(&Utc),
+            signature: string("signature"),
+            verified: false,
+        };
+        entry.verified = entry
+            .signature
+            .as_deref()
+            .is_some_and(|signature| signing::verify(&entry.signed_payload(), signature));
+        Some(entry)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuditLogResponse {
+    pub success: bool,
+    pub days: i64,
+    // Newest first
+    pub entries: Vec<AuditEntry>,
+}
+
+// Append-only log of admin API mutations, one partition per UTC day and
+// removed by DynamoDB TTL after AUDIT_RETENTION_DAYS
+pub struct AuditStore {
+    client: Client,
+    table_name: String,
+    retention_days: i64,
+}
+
+impl AuditStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(AUDIT_LOG_TABLE_NAME),
+            retention_days: env_or("AUDIT_RETENTION_DAYS", DEFAULT_RETENTION_DAYS).max(1),
+        }
+    }
+
+    pub async fn record(&self, entry: &AuditEntry) -> Result<(), Error> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(entry.to_dynamodb_item(self.retention_days)))
+            // Never overwrite an existing entry
+            .condition_expression("attribute_not_exists(entry_key)")
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // The request already went through, so failures are only logged
+    pub async fn record_or_log(&self, entry: AuditEntry) {
+        if let Err(e) = self.record(&entry).await {
+            info!(
+                "Error recording audit entry for {} {} by {}: {:?}",
+                entry.method, entry.path, entry.api_key_id, e
+            );
+        }
+    }
+
+    // Entries from the last `days` days, newest first, optionally only those
+    // made with one API key
+    pub async fn list(
+        &self,
+        days: i64,
+        api_key_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<AuditEntry>, Error> {
+        let today: NaiveDate = Utc::now().date_naive();
+        let mut entries = Vec::new();
+
+        for offset in 0..days.max(1) {
+            let day = (today - Duration::days(offset))
+                .format("%Y-%m-%d")
+                .to_string();
+            let mut start_key = None;
+
+            loop {
+                let mut query = self
+                    .client
+                    .query()
+                    .table_name(&self.table_name)
+                    .key_condition_expression("#day = :day")
+                    .expression_attribute_names("#day", "day")
+                    .expression_attribute_values(":day", AttributeValue::S(day.clone()))
+                    .scan_index_forward(false)
+                    .set_exclusive_start_key(start_key);
+                if let Some(api_key_id) = api_key_id {
+                    query = query
+                        .filter_expression("api_key_id = :api_key_id")
+                        .expression_attribute_values(
+                            ":api_key_id",
+                            AttributeValue::S(api_key_id.to_string()),
+                        );
+                }
+                let result = query.send().await?;
+
+                for item in result.items().unwrap_or_default() {
+                    if let Some(entry) = AuditEntry::from_dynamodb_item(item) {
+                        entries.push(entry);
+                        if entries.len() >= limit {
+                            return Ok(entries);
+                        }
+                    }
+                }
+
+                match result.last_evaluated_key() {
+                    Some(key) => start_key = Some(key.clone()),
+                    None => break,
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+}
@@ -0,0 +1,504 @@
+use crate::Error;
+use crate::batch::Item;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::primitives::ByteStream;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+
+// Sortable timestamp used in backup object keys
+const KEY_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupKind {
+    // Every item in the table
+    Full,
+    // Items updated since the previous backup
+    Incremental,
+}
+
+impl BackupKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackupKind::Full => "full",
+            BackupKind::Incremental => "incremental",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "full" => Some(BackupKind::Full),
+            "incremental" => Some(BackupKind::Incremental),
+            _ => None,
+        }
+    }
+}
+
+// One NDJSON backup object, stored as `<prefix>/<timestamp>-<kind>.ndjson`
+#[derive(Debug, Clone)]
+pub struct BackupObject {
+    pub key: String,
+    pub kind: BackupKind,
+    // When the backup started; everything updated before then is included
+    pub taken_at: DateTime<Utc>,
+}
+
+impl BackupObject {
+    pub fn new(prefix: &str, kind: BackupKind, taken_at: DateTime<Utc>) -> Self {
+        let key = format!(
+            "{}/{}-{}.ndjson",
+            prefix,
+            taken_at.format(KEY_TIMESTAMP_FORMAT),
+            kind.as_str()
+        );
+        Self {
+            key,
+            kind,
+            taken_at,
+        }
+    }
+
+    // None for keys that weren't written by `BackupStore::write`
+    pub fn parse(prefix: &str, key: &str) -> Option<Self> {
+        let name = key
+            .strip_prefix(prefix)?
+            .strip_prefix('/')?
+            .strip_suffix(".ndjson")?;
+        let (timestamp, kind) = name.split_once('-')?;
+        let taken_at = NaiveDateTime::parse_from_str(timestamp, KEY_TIMESTAMP_FORMAT)
+            .ok()?
+            .and_utc();
+
+        Some(Self {
+            key: key.to_string(),
+            kind: BackupKind::parse(kind)?,
+            taken_at,
+        })
+    }
+
+    // Where the ids of every subscriber alive when an incremental backup was
+    // taken are kept, one per line
+    fn live_ids_key(&self) -> String {
+        format!("{}.ids", self.key.trim_end_matches(".ndjson"))
+    }
+}
+
+// What one backup in a restore chain holds
+#[derive(Debug, Clone)]
+pub struct BackupContents {
+    pub backup: BackupObject,
+    pub items: Vec<Item>,
+    // Ids of every subscriber when an incremental backup was taken, None for
+    // full backups and incrementals written before the ids were recorded
+    pub live_ids: Option<HashSet<String>>,
+}
+
+// Rebuild the table from a restore chain, oldest first, by id. Later backups win,
+// so each subscriber ends up in its latest state, and subscribers missing from an
+// incremental backup's live ids were deleted before it and are dropped.
+pub fn merge_backups(chain: Vec<BackupContents>) -> Result<HashMap<String, Item>, Error> {
+    let mut items = HashMap::new();
+    for contents in chain {
+        for item in contents.items {
+            let id = item_id(&item)
+                .ok_or_else(|| format!("Item without an id in {}", contents.backup.key))?
+                .to_string();
+            items.insert(id, item);
+        }
+        if let Some(live_ids) = &contents.live_ids {
+            items.retain(|id, _| live_ids.contains(id));
+        }
+    }
+    Ok(items)
+}
+
+pub fn item_id(item: &Item) -> Option<&str> {
+    item.get("id")
+        .and_then(|value| value.as_s().ok())
+        .map(String::as_str)
+}
+
+// Convert an item to DynamoDB JSON, the format used by DynamoDB's own S3 exports
+pub fn item_to_json(item: &Item) -> Result<Value, Error> {
+    let mut attributes = Map::new();
+    for (name, value) in item {
+        attributes.insert(name.clone(), attribute_to_json(value)?);
+    }
+    Ok(Value::Object(attributes))
+}
+
+fn attribute_to_json(value: &AttributeValue) -> Result<Value, Error> {
+    let (kind, value) = match value {
+        AttributeValue::S(value) => ("S", Value::from(value.as_str())),
+        AttributeValue::N(value) => ("N", Value::from(value.as_str())),
+        AttributeValue::Bool(value) => ("BOOL", Value::from(*value)),
+        AttributeValue::Null(value) => ("NULL", Value::from(*value)),
+        AttributeValue::Ss(values) => ("SS", Value::from(values.clone())),
+        AttributeValue::Ns(values) => ("NS", Value::from(values.clone())),
+        AttributeValue::L(values) => (
+            "L",
+            Value::Array(
+                values
+                    .iter()
+                    .map(attribute_to_json)
+                    .collect::<Result<_, _>>()?,
+            ),
+        ),
+        AttributeValue::M(values) => ("M", item_to_json(values)?),
+        _ => return Err("Unsupported attribute type in backup".into()),
+    };
+
+    let mut typed = Map::new();
+    typed.insert(kind.to_string(), value);
+    Ok(Value::Object(typed))
+}
+
+pub fn item_from_json(value: Value) -> Result<Item, Error> {
+    let Value::Object(attributes) = value else {
+        return Err("Backup item is not an object".into());
+    };
+
+    let mut item = HashMap::new();
+    for (name, value) in attributes {
+        item.insert(name, attribute_from_json(value)?);
+    }
+    Ok(item)
+}
+
+fn attribute_from_json(value: Value) -> Result<AttributeValue, Error> {
+    let malformed = || -> Error { "Malformed attribute in backup".into() };
+    let strings =
+        |value: Value| -> Result<Vec<String>, Error> { Ok(serde_json::from_value(value)?) };
+
+    let Value::Object(typed) = value else {
+        return Err(malformed());
+    };
+    let (kind, value) = typed.into_iter().next().ok_or_else(malformed)?;
+
+    Ok(match (kind.as_str(), value) {
+        ("S", Value::String(value)) => AttributeValue::S(value),
+        ("N", Value::String(value)) => AttributeValue::N(value),
+        ("BOOL", Value::Bool(value)) => AttributeValue::Bool(value),
+        ("NULL", Value::Bool(value)) => AttributeValue::Null(value),
+        ("SS", value) => AttributeValue::Ss(strings(value)?),
+        ("NS", value) => AttributeValue::Ns(strings(value)?),
+        ("L", Value::Array(values)) => AttributeValue::L(
+            values
+                .into_iter()
+                .map(attribute_from_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        ("M", value) => AttributeValue::M(item_from_json(value)?),
+        _ => return Err(malformed()),
+    })
+}
+
+// Whether an item's `updated_at` is at or after `since`; items without one are
+// always included so nothing is silently left out of incremental backups
+pub fn updated_since(item: &Item, since: DateTime<Utc>) -> bool {
+    item.get("updated_at")
+        .and_then(|value| value.as_s().ok())
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .is_none_or(|updated_at| updated_at >= since)
+}
+
+// Backup objects in an S3 bucket, under a key prefix
+pub struct BackupStore {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl BackupStore {
+    pub fn new(client: S3Client, bucket: String, prefix: String) -> Self {
+        Self {
+            client,
+            bucket,
+            prefix: prefix.trim_end_matches('/').to_string(),
+        }
+    }
+
+    // Every backup under the prefix, oldest first
+    pub async fn list(&self) -> Result<Vec<BackupObject>, Error> {
+        let mut backups = Vec::new();
+        let mut continuation_token = None;
+
+        loop {
+            let result = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(format!("{}/", self.prefix))
+                .set_continuation_token(continuation_token)
+                .send()
+                .await?;
+
+            backups.extend(
+                result
+                    .contents()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|object| object.key())
+                    .filter_map(|key| BackupObject::parse(&self.prefix, key)),
+            );
+
+            match result.next_continuation_token() {
+                Some(token) => continuation_token = Some(token.to_string()),
+                None => break,
+            }
+        }
+
+        backups.sort_by_key(|backup| backup.taken_at);
+        Ok(backups)
+    }
+
+    pub async fn write(
+        &self,
+        kind: BackupKind,
+        taken_at: DateTime<Utc>,
+        items: &[Item],
+    ) -> Result<BackupObject, Error> {
+        let backup = BackupObject::new(&self.prefix, kind, taken_at);
+
+        let mut body = Vec::new();
+        for item in items {
+            serde_json::to_writer(&mut body, &item_to_json(item)?)?;
+            body.push(b'\n');
+        }
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&backup.key)
+            .content_type("application/x-ndjson")
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+
+        Ok(backup)
+    }
+
+    // Record which subscribers exist alongside an incremental backup, so a
+    // restore drops the ones deleted since the backup before it
+    pub async fn write_live_ids(
+        &self,
+        backup: &BackupObject,
+        live_ids: &[String],
+    ) -> Result<(), Error> {
+        let mut body = live_ids.join("\n");
+        body.push('\n');
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(backup.live_ids_key())
+            .content_type("text/plain")
+            .body(ByteStream::from(body.into_bytes()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // None for full backups and incrementals taken before live ids were recorded
+    pub async fn read_live_ids(
+        &self,
+        backup: &BackupObject,
+    ) -> Result<Option<HashSet<String>>, Error> {
+        if backup.kind == BackupKind::Full {
+            return Ok(None);
+        }
+
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(backup.live_ids_key())
+            .send()
+            .await;
+        let body = match result {
+            Ok(output) => output.body.collect().await?.into_bytes(),
+            Err(SdkError::ServiceError(err)) if err.err().is_no_such_key() => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Some(
+            String::from_utf8_lossy(&body)
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+        ))
+    }
+
+    pub async fn read(&self, backup: &BackupObject) -> Result<Vec<Item>, Error> {
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&backup.key)
+            .send()
+            .await?;
+        let body = result.body.collect().await?.into_bytes();
+
+        body.split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| item_from_json(serde_json::from_slice(line)?))
+            .collect()
+    }
+
+    // The backups needed to rebuild the table as of `until` (default: the latest
+    // backup): the last full backup and every incremental one taken after it
+    pub async fn restore_chain(
+        &self,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<BackupObject>, Error> {
+        let backups: Vec<BackupObject> = self
+            .list()
+            .await?
+            .into_iter()
+            .filter(|backup| until.is_none_or(|until| backup.taken_at <= until))
+            .collect();
+
+        let start = backups
+            .iter()
+            .rposition(|backup| backup.kind == BackupKind::Full)
+            .ok_or("No full backup found")?;
+
+        Ok(backups[start..].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscriber(id: &str, email: &str) -> Item {
+        HashMap::from([
+            ("id".to_string(), AttributeValue::S(id.to_string())),
+            ("email".to_string(), AttributeValue::S(email.to_string())),
+        ])
+    }
+
+    fn contents(
+        kind: BackupKind,
+        taken_at: &str,
+        items: Vec<Item>,
+        live_ids: Option<&[&str]>,
+    ) -> BackupContents {
+        let taken_at = DateTime::parse_from_rfc3339(taken_at).unwrap().to_utc();
+        BackupContents {
+            backup: BackupObject::new("subscribers", kind, taken_at),
+            items,
+            live_ids: live_ids.map(|ids| ids.iter().map(|id| id.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn later_backups_win() {
+        let merged = merge_backups(vec![
+            contents(
+                BackupKind::Full,
+                "2026-10-01T00:00:00Z",
+                vec![subscriber("1", "old@example.com")],
+                None,
+            ),
+            contents(
+                BackupKind::Incremental,
+                "2026-10-02T00:00:00Z",
+                vec![subscriber("1", "new@example.com")],
+                Some(&["1"]),
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged["1"]["email"].as_s().unwrap(), "new@example.com");
+    }
+
+    #[test]
+    fn drops_subscribers_deleted_after_the_full_backup() {
+        let merged = merge_backups(vec![
+            contents(
+                BackupKind::Full,
+                "2026-10-01T00:00:00Z",
+                vec![
+                    subscriber("1", "kept@example.com"),
+                    subscriber("2", "deleted@example.com"),
+                ],
+                None,
+            ),
+            contents(
+                BackupKind::Incremental,
+                "2026-10-02T00:00:00Z",
+                vec![subscriber("3", "added@example.com")],
+                Some(&["1", "3"]),
+            ),
+        ])
+        .unwrap();
+
+        let mut ids: Vec<&str> = merged.keys().map(String::as_str).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["1", "3"]);
+    }
+
+    #[test]
+    fn keeps_everything_for_incrementals_without_live_ids() {
+        let merged = merge_backups(vec![
+            contents(
+                BackupKind::Full,
+                "2026-10-01T00:00:00Z",
+                vec![
+                    subscriber("1", "a@example.com"),
+                    subscriber("2", "b@example.com"),
+                ],
+                None,
+            ),
+            contents(
+                BackupKind::Incremental,
+                "2026-10-02T00:00:00Z",
+                vec![],
+                None,
+            ),
+        ])
+        .unwrap();
+
+        assert_eq!(merged.len(), 2);
+    }
+
+    #[test]
+    fn refuses_items_without_an_id() {
+        let item = HashMap::from([(
+            "email".to_string(),
+            AttributeValue::S("a@example.com".to_string()),
+        )]);
+        let err = merge_backups(vec![contents(
+            BackupKind::Full,
+            "2026-10-01T00:00:00Z",
+            vec![item],
+            None,
+        )])
+        .unwrap_err();
+
+        assert!(
+            err.to_string()
+                .starts_with("Item without an id in subscribers/")
+        );
+    }
+
+    #[test]
+    fn live_ids_sit_next_to_the_backup() {
+        let taken_at = DateTime::parse_from_rfc3339("2026-10-02T00:00:00Z")
+            .unwrap()
+            .to_utc();
+        let backup = BackupObject::new("subscribers", BackupKind::Incremental, taken_at);
+
+        assert_eq!(
+            backup.live_ids_key(),
+            "subscribers/20261002T000000Z-incremental.ids"
+        );
+        assert!(BackupObject::parse("subscribers", &backup.live_ids_key()).is_none());
+    }
+}
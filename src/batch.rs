@@ -0,0 +1,310 @@
+use crate::Error;
+use crate::config::env_or;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::batch_write_item::BatchWriteItemError;
+use aws_sdk_dynamodb::types::{
+    AttributeValue, BillingMode, DeleteRequest, PutRequest, WriteRequest,
+};
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+use tracing::info;
+
+pub type Item = HashMap<String, AttributeValue>;
+
+// BatchWriteItem accepts at most 25 requests per call
+pub const BATCH_WRITE_SIZE: usize = 25;
+
+// Attempts per chunk before unprocessed items are reported as an error
+const MAX_BATCH_ATTEMPTS: u32 = 8;
+
+// Segments used when SCAN_SEGMENTS isn't set
+const DEFAULT_SCAN_SEGMENTS: i32 = 8;
+
+// Number of segments full-table scans are split into, from SCAN_SEGMENTS
+pub fn scan_segments() -> i32 {
+    env::var("SCAN_SEGMENTS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|segments| (1..=1_000_000).contains(segments))
+        .unwrap_or(DEFAULT_SCAN_SEGMENTS)
+}
+
+// Chunks written at once when BATCH_WRITE_CONCURRENCY isn't set
+const DEFAULT_WRITE_CONCURRENCY: usize = 4;
+
+// Share of a provisioned table's write capacity bulk writes may use, leaving
+// the rest to the subscribe and confirm paths
+const PROVISIONED_WRITE_SHARE: i64 = 2;
+
+// Most chunks written at once, lowered for provisioned tables so a bulk job
+// stays within half their write capacity. On-demand tables get the full
+// BATCH_WRITE_CONCURRENCY.
+async fn write_concurrency(client: &Client, table_name: &str) -> usize {
+    let max = env_or("BATCH_WRITE_CONCURRENCY", DEFAULT_WRITE_CONCURRENCY).max(1);
+
+    let table = match client.describe_table().table_name(table_name).send().await {
+        Ok(output) => output.table,
+        Err(e) => {
+            info!("Error describing table {}: {:?}", table_name, e);
+            return 1;
+        }
+    };
+    let Some(table) = table else {
+        return 1;
+    };
+
+    let on_demand = table
+        .billing_mode_summary()
+        .and_then(|summary| summary.billing_mode())
+        == Some(&BillingMode::PayPerRequest);
+    let write_capacity = table
+        .provisioned_throughput()
+        .and_then(|throughput| throughput.write_capacity_units())
+        .unwrap_or(0);
+    if on_demand || write_capacity <= 0 {
+        return max;
+    }
+
+    // Each chunk writes up to 25 items, at least one write unit each
+    let budget = write_capacity / PROVISIONED_WRITE_SHARE / BATCH_WRITE_SIZE as i64;
+    (budget.max(1) as usize).min(max)
+}
+
+// Whether a failed write was throttled rather than rejected
+fn is_throttled(error: &SdkError<BatchWriteItemError>) -> bool {
+    match error {
+        SdkError::ServiceError(error) => {
+            let error = error.err();
+            error.is_provisioned_throughput_exceeded_exception()
+                || error.is_request_limit_exceeded()
+        }
+        _ => false,
+    }
+}
+
+// Write one chunk, retrying unprocessed items and throttling errors with
+// exponential backoff. Returns whether the table throttled it.
+async fn write_chunk(
+    client: Client,
+    table_name: String,
+    mut pending: Vec<WriteRequest>,
+) -> Result<bool, Error> {
+    let mut throttled = false;
+    let mut attempt = 0;
+
+    while !pending.is_empty() {
+        if attempt == MAX_BATCH_ATTEMPTS {
+            return Err(format!(
+                "{} items left unprocessed in {} after {} attempts",
+                pending.len(),
+                table_name,
+                MAX_BATCH_ATTEMPTS
+            )
+            .into());
+        }
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_millis(50 * 2u64.pow(attempt))).await;
+        }
+        attempt += 1;
+
+        let result = client
+            .batch_write_item()
+            .request_items(&table_name, pending.clone())
+            .send()
+            .await;
+
+        pending = match result {
+            // Unprocessed items mean the table is throttling too
+            Ok(output) => output
+                .unprocessed_items()
+                .and_then(|unprocessed| unprocessed.get(&table_name))
+                .cloned()
+                .unwrap_or_default(),
+            Err(e) if is_throttled(&e) => pending,
+            Err(e) => return Err(e.into()),
+        };
+        throttled |= !pending.is_empty();
+    }
+
+    Ok(throttled)
+}
+
+// Write requests in chunks of 25, several chunks at a time. Concurrency halves
+// whenever the table throttles and grows back by one chunk per clean round, so
+// large imports and restores don't starve interactive writes. Returns the
+// number of requests written.
+pub async fn batch_write(
+    client: &Client,
+    table_name: &str,
+    requests: Vec<WriteRequest>,
+) -> Result<usize, Error> {
+    let total = requests.len();
+    let max_concurrency = write_concurrency(client, table_name).await;
+    let mut concurrency = max_concurrency;
+    let mut chunks = requests.chunks(BATCH_WRITE_SIZE);
+
+    loop {
+        let tasks: Vec<_> = chunks
+            .by_ref()
+            .take(concurrency)
+            .map(|chunk| {
+                tokio::spawn(write_chunk(
+                    client.clone(),
+                    table_name.to_string(),
+                    chunk.to_vec(),
+                ))
+            })
+            .collect();
+        if tasks.is_empty() {
+            break;
+        }
+
+        let mut throttled = false;
+        for task in tasks {
+            throttled |= task.await??;
+        }
+
+        concurrency = if throttled {
+            info!(
+                "{} is throttling batch writes, lowering concurrency from {}",
+                table_name, concurrency
+            );
+            (concurrency / 2).max(1)
+        } else {
+            (concurrency + 1).min(max_concurrency)
+        };
+    }
+
+    Ok(total)
+}
+
+pub async fn batch_put(
+    client: &Client,
+    table_name: &str,
+    items: Vec<Item>,
+) -> Result<usize, Error> {
+    let requests = items
+        .into_iter()
+        .map(|item| {
+            WriteRequest::builder()
+                .put_request(PutRequest::builder().set_item(Some(item)).build())
+                .build()
+        })
+        .collect();
+
+    batch_write(client, table_name, requests).await
+}
+
+pub async fn batch_delete(
+    client: &Client,
+    table_name: &str,
+    keys: Vec<Item>,
+) -> Result<usize, Error> {
+    let requests = keys
+        .into_iter()
+        .map(|key| {
+            WriteRequest::builder()
+                .delete_request(DeleteRequest::builder().set_key(Some(key)).build())
+                .build()
+        })
+        .collect();
+
+    batch_write(client, table_name, requests).await
+}
+
+// Delete every item under one partition key, e.g. all events of a subscriber
+pub async fn delete_partition(
+    client: &Client,
+    table_name: &str,
+    partition_key: (&str, &str),
+    sort_key: &str,
+) -> Result<usize, Error> {
+    let (key_name, key_value) = partition_key;
+    let mut keys = Vec::new();
+    let mut start_key = None;
+
+    loop {
+        let result = client
+            .query()
+            .table_name(table_name)
+            .key_condition_expression("#pk = :pk")
+            .expression_attribute_names("#pk", key_name)
+            .expression_attribute_names("#sk", sort_key)
+            .expression_attribute_values(":pk", AttributeValue::S(key_value.to_string()))
+            .projection_expression("#pk, #sk")
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        keys.extend(result.items().unwrap_or_default().iter().cloned());
+
+        match result.last_evaluated_key() {
+            Some(key) => start_key = Some(key.clone()),
+            None => break,
+        }
+    }
+
+    batch_delete(client, table_name, keys).await
+}
+
+// Read a whole table with a segmented parallel Scan, one task per segment
+pub async fn parallel_scan(
+    client: &Client,
+    table_name: &str,
+    segments: i32,
+) -> Result<Vec<Item>, Error> {
+    let tasks: Vec<_> = (0..segments)
+        .map(|segment| {
+            let client = client.clone();
+            let table_name = table_name.to_string();
+            tokio::spawn(async move { scan_segment(client, table_name, segment, segments).await })
+        })
+        .collect();
+
+    let mut items = Vec::new();
+    for task in tasks {
+        items.extend(task.await??);
+    }
+
+    info!(
+        "Scanned {} items from {} in {} segments",
+        items.len(),
+        table_name,
+        segments
+    );
+    Ok(items)
+}
+
+async fn scan_segment(
+    client: Client,
+    table_name: String,
+    segment: i32,
+    total_segments: i32,
+) -> Result<Vec<Item>, Error> {
+    let mut items = Vec::new();
+    let mut start_key = None;
+
+    loop {
+        let result = client
+            .scan()
+            .table_name(&table_name)
+            .segment(segment)
+            .total_segments(total_segments)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        if let Some(page) = result.items() {
+            items.extend(page.iter().cloned());
+        }
+
+        match result.last_evaluated_key() {
+            Some(key) => start_key = Some(key.clone()),
+            None => break,
+        }
+    }
+
+    Ok(items)
+}
@@ -0,0 +1,2250 @@
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_sesv2::Client as SesClient;
+use aws_sdk_sfn::Client as SfnClient;
+use aws_sdk_sqs::Client as SqsClient;
+use chrono::{DateTime, Utc};
+use lambda_http::http::{HeaderName, HeaderValue};
+use lambda_http::{Body, Error, Request, RequestExt, Response, run, service_fn};
+use newsletter_backend::address::{normalize_email, sendable_email};
+use newsletter_backend::admin::{
+    AddSubscriberRequest, ApiKey, AuthError, MAX_CONSENT_NOTE_LENGTH, SourceBreakdownResponse,
+    SubscriberDetailResponse, SubscriberListResponse, SubscriberStatus, authenticate, is_mutation,
+};
+use newsletter_backend::attribution::{DIMENSIONS, breakdown};
+use newsletter_backend::audit::{AuditEntry, AuditLogResponse, AuditStore};
+use newsletter_backend::campaign::{
+    CampaignCancelData, CampaignSendData, CampaignStatus, Exclusions,
+};
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::config::double_opt_in;
+use newsletter_backend::counters::{CounterStore, SubscriberCountsResponse};
+use newsletter_backend::cursor::Cursor;
+use newsletter_backend::delivery::{
+    PreparedSend, SendGuards, SendRefusal, SendRequest, cancel_schedule, deliver_campaign,
+    prepare_send, schedule_campaign,
+};
+use newsletter_backend::digest::{DigestStore, FrequencyRequest};
+use newsletter_backend::domain_rules::{
+    DomainRule, DomainRuleListResponse, DomainRuleRequest, DomainRuleStore, normalize_domain,
+};
+use newsletter_backend::domains::{DomainMetric, DomainStatsResponse, DomainStatsStore};
+use newsletter_backend::email_queue::Mailer;
+use newsletter_backend::events::EventRepository;
+use newsletter_backend::fields::FieldSchema;
+use newsletter_backend::history::{
+    HistoryRepository, LifecycleEvent, LifecycleEventType, SubscriberHistoryResponse,
+};
+use newsletter_backend::i18n::Locale;
+use newsletter_backend::import::{
+    ImportFormat, ImportPreviewResponse, existing_subscribers, import_emails, plan_import,
+};
+use newsletter_backend::input::{InputLimits, checked, is_valid_email};
+use newsletter_backend::invites::{CreateInviteRequest, InviteListResponse, InviteStore};
+use newsletter_backend::jobs::{
+    Job, JobListResponse, JobPageMessage, JobQueue, JobStore, StartJobRequest,
+};
+use newsletter_backend::jwt::{JwtSettings, JwtVerifier};
+use newsletter_backend::links::{LinkChecker, campaign_links};
+use newsletter_backend::logging::log_request;
+use newsletter_backend::onboarding::confirm_without_link;
+use newsletter_backend::opt_out::{OptOut, OptOutReplayData, OptOutWebhook};
+use newsletter_backend::preview::{PreviewRequest, render_preview, sample_subscriber};
+use newsletter_backend::queue::{SCHEMA_VERSION, VALIDATE_EMAIL_ACTION, ValidationQueue};
+use newsletter_backend::quota::{QuotaKind, QuotaStatus, QuotaStore};
+use newsletter_backend::rbac::Operation;
+use newsletter_backend::referral;
+use newsletter_backend::repository::{CampaignRepository, SubscriberRepository};
+use newsletter_backend::schedule::{RecipientSnapshotStore, ScheduleRequest, ScheduleStore};
+use newsletter_backend::search::SubscriberSearch;
+use newsletter_backend::segment::Segment;
+use newsletter_backend::senders::{
+    CreateSenderRequest, SenderIdentity, SenderListResponse, SenderStatus, SenderStore,
+    SenderVerifier, is_valid_sender_id,
+};
+use newsletter_backend::sync::{
+    SubscriberChange, SubscriberChangesResponse, index_key, settled_until, sync_timestamp,
+};
+use newsletter_backend::templates::{
+    CreateTemplateRequest, PublishTemplateRequest, TemplateListResponse, TemplateResponse,
+    TemplateStore, UpdateTemplateRequest,
+};
+use newsletter_backend::versioning::versioned;
+use newsletter_backend::workflow::{CampaignWorkflow, state_machine_arn};
+use newsletter_backend::{
+    ApiResponse, Subscriber, ValidationMessage, create_json_response, create_response,
+};
+use std::env;
+use tracing::info;
+
+async fn list_subscribers(
+    repository: &SubscriberRepository,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters();
+
+    let limit = params
+        .first("limit")
+        .and_then(|limit| limit.parse::<i32>().ok())
+        .unwrap_or(50)
+        .clamp(1, 500);
+
+    let start_key = match params.first("cursor").map(Cursor::decode) {
+        Some(Ok(cursor)) => Some(cursor.into_key()),
+        Some(Err(e)) => {
+            info!("Rejected pagination cursor: {:?}", e);
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: "Invalid cursor".to_string(),
+                    data: None,
+                },
+            ));
+        }
+        None => None,
+    };
+
+    let segment = match params.first("segment").map(Segment::parse) {
+        Some(Ok(segment)) => Some(segment),
+        Some(Err(e)) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: e.to_string(),
+                    data: None,
+                },
+            ));
+        }
+        None => None,
+    };
+
+    match repository.list_page(limit, start_key).await {
+        Ok((page, next_key)) => {
+            let now = Utc::now();
+            let subscribers = page
+                .into_iter()
+                .filter(|subscriber| {
+                    segment
+                        .as_ref()
+                        .map(|segment| segment.matches(subscriber, now))
+                        .unwrap_or(true)
+                })
+                .map(|mut subscriber| {
+                    // Report the score as of now rather than as of the last event
+                    subscriber.engagement_score = subscriber.engagement_score_at(now);
+                    subscriber
+                })
+                .collect();
+
+            let next_cursor = next_key
+                .map(|key| Cursor::from_key(key).encode())
+                .transpose()?;
+
+            Ok(create_json_response(
+                200,
+                &SubscriberListResponse {
+                    success: true,
+                    subscribers,
+                    next_cursor,
+                },
+            ))
+        }
+        Err(e) => {
+            info!("Error listing subscribers: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to list subscribers".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+// Subscribers changed after a cursor or a time, oldest change first, so
+// external systems can poll for deltas instead of exporting everything
+async fn subscriber_changes(
+    repository: &SubscriberRepository,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters();
+    let limit = params
+        .first("limit")
+        .and_then(|limit| limit.parse::<i32>().ok())
+        .unwrap_or(100)
+        .clamp(1, 1000);
+
+    // A cursor resumes right after the last change returned, `since` at a time
+    let (after, start_key) = match (params.first("cursor"), params.first("since")) {
+        (Some(cursor), _) => {
+            let key = match Cursor::decode(cursor) {
+                Ok(cursor) => cursor.into_key(),
+                Err(e) => {
+                    info!("Rejected sync cursor: {:?}", e);
+                    return Ok(error_response(400, "Invalid cursor"));
+                }
+            };
+            match key.get("sync_key").and_then(|value| value.as_s().ok()) {
+                Some(after) => (after.clone(), Some(key)),
+                None => return Ok(error_response(400, "Invalid cursor")),
+            }
+        }
+        (None, Some(since)) => match DateTime::parse_from_rfc3339(since) {
+            Ok(since) => (sync_timestamp(since.with_timezone(&Utc)), None),
+            Err(_) => {
+                return Ok(error_response(400, "since must be an RFC 3339 timestamp"));
+            }
+        },
+        (None, None) => (sync_timestamp(DateTime::UNIX_EPOCH), None),
+    };
+
+    // Nothing settled after the cursor yet, poll again later with the same one
+    let until = settled_until(Utc::now());
+    if after >= until {
+        return Ok(create_json_response(
+            200,
+            &SubscriberChangesResponse {
+                success: true,
+                changes: Vec::new(),
+                next_cursor: params.first("cursor").map(str::to_string),
+                has_more: false,
+            },
+        ));
+    }
+
+    match repository
+        .changed_between(&after, &until, limit, start_key)
+        .await
+    {
+        Ok((subscribers, next_key)) => {
+            // Built from the last change rather than LastEvaluatedKey, so polls
+            // can carry on from the end of the stream
+            let next_cursor = match subscribers.last() {
+                Some(last) => Some(Cursor::from_key(index_key(last)).encode()?),
+                None => params.first("cursor").map(str::to_string),
+            };
+            let changes = subscribers
+                .into_iter()
+                .map(|subscriber| SubscriberChange {
+                    status: SubscriberStatus::of(&subscriber),
+                    subscriber,
+                })
+                .collect();
+
+            Ok(create_json_response(
+                200,
+                &SubscriberChangesResponse {
+                    success: true,
+                    changes,
+                    next_cursor,
+                    has_more: next_key.is_some(),
+                },
+            ))
+        }
+        Err(e) => {
+            info!("Error listing subscriber changes: {:?}", e);
+            Ok(error_response(500, "Failed to list subscriber changes"))
+        }
+    }
+}
+
+// Find subscribers by exact email, email prefix or domain without scanning the table
+async fn search_subscribers(
+    repository: &SubscriberRepository,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters();
+
+    let search = match SubscriberSearch::from_params(
+        params.first("email"),
+        params.first("prefix"),
+        params.first("domain"),
+    ) {
+        Ok(search) => search,
+        Err(message) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message,
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    let limit = params
+        .first("limit")
+        .and_then(|limit| limit.parse::<i32>().ok())
+        .unwrap_or(50)
+        .clamp(1, 500);
+
+    let start_key = match params.first("cursor").map(Cursor::decode) {
+        Some(Ok(cursor)) => Some(cursor.into_key()),
+        Some(Err(e)) => {
+            info!("Rejected pagination cursor: {:?}", e);
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: "Invalid cursor".to_string(),
+                    data: None,
+                },
+            ));
+        }
+        None => None,
+    };
+
+    match repository.search(&search, limit, start_key).await {
+        Ok((subscribers, next_key)) => {
+            let next_cursor = next_key
+                .map(|key| Cursor::from_key(key).encode())
+                .transpose()?;
+
+            Ok(create_json_response(
+                200,
+                &SubscriberListResponse {
+                    success: true,
+                    subscribers,
+                    next_cursor,
+                },
+            ))
+        }
+        Err(e) => {
+            info!("Error searching subscribers: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to search subscribers".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+// Which signup sources drive confirmed subscriptions, grouped by ?by=<dimension>
+async fn source_stats(
+    repository: &SubscriberRepository,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters();
+    let dimension = params.first("by").unwrap_or("source");
+
+    if !DIMENSIONS.contains(&dimension) {
+        return Ok(create_response(
+            400,
+            ApiResponse {
+                success: false,
+                message: format!(
+                    "Unknown breakdown '{}', expected one of: {}",
+                    dimension,
+                    DIMENSIONS.join(", ")
+                ),
+                data: None,
+            },
+        ));
+    }
+
+    match repository.list_all().await {
+        Ok(subscribers) => Ok(create_json_response(
+            200,
+            &SourceBreakdownResponse {
+                success: true,
+                dimension: dimension.to_string(),
+                sources: breakdown(&subscribers, dimension),
+            },
+        )),
+        Err(e) => {
+            info!("Error computing source stats: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to compute source stats".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+// Subscriptions, deliveries and engagement per email domain or mailbox provider
+// over the last ?days=<n> days, or day by day for a single ?domain=
+async fn domain_stats(
+    domains: &DomainStatsStore,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters();
+    let days = params
+        .first("days")
+        .and_then(|days| days.parse::<i64>().ok())
+        .unwrap_or(30)
+        .clamp(1, 365);
+    let by = params.first("by").unwrap_or("domain");
+
+    let result = match (params.first("domain"), by) {
+        (Some(domain), _) => domains
+            .daily(domain, days)
+            .await
+            .map(|stats| ("day", stats)),
+        (None, "domain") => domains
+            .summary(days, false)
+            .await
+            .map(|stats| ("domain", stats)),
+        (None, "provider") => domains
+            .summary(days, true)
+            .await
+            .map(|stats| ("provider", stats)),
+        (None, _) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: format!("Unknown breakdown '{by}', expected domain or provider"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    match result {
+        Ok((by, stats)) => Ok(create_json_response(
+            200,
+            &DomainStatsResponse {
+                success: true,
+                days,
+                by: by.to_string(),
+                domains: stats,
+            },
+        )),
+        Err(e) => {
+            info!("Error computing domain stats: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to compute domain stats".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+async fn list_domain_rules(rules: &DomainRuleStore) -> Result<Response<Body>, Error> {
+    match rules.list().await {
+        Ok(rules) => Ok(create_json_response(
+            200,
+            &DomainRuleListResponse {
+                success: true,
+                rules,
+            },
+        )),
+        Err(e) => {
+            info!("Error listing domain rules: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to list domain rules".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+// Create or replace the allow or deny rule for a signup domain
+async fn put_domain_rule(
+    rules: &DomainRuleStore,
+    event: &Request,
+    domain: &str,
+) -> Result<Response<Body>, Error> {
+    let Some(domain) = normalize_domain(domain) else {
+        return Ok(create_response(
+            400,
+            ApiResponse {
+                success: false,
+                message: "Invalid domain".to_string(),
+                data: None,
+            },
+        ));
+    };
+
+    let request: DomainRuleRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: format!("Invalid rule: {e}"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    let rule = DomainRule::new(domain, request.kind, request.note);
+    match rules.put(&rule).await {
+        Ok(()) => Ok(create_json_response(200, &rule)),
+        Err(e) => {
+            info!("Error saving domain rule: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to save domain rule".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+async fn delete_domain_rule(
+    rules: &DomainRuleStore,
+    domain: &str,
+) -> Result<Response<Body>, Error> {
+    let domain = normalize_domain(domain).unwrap_or_else(|| domain.to_string());
+    match rules.delete(&domain).await {
+        Ok(true) => Ok(create_response(
+            200,
+            ApiResponse {
+                success: true,
+                message: format!("Removed the rule for {domain}"),
+                data: None,
+            },
+        )),
+        Ok(false) => Ok(create_response(
+            404,
+            ApiResponse {
+                success: false,
+                message: "Domain rule not found".to_string(),
+                data: None,
+            },
+        )),
+        Err(e) => {
+            info!("Error deleting domain rule: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to delete domain rule".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+async fn list_invites(invites: &InviteStore) -> Result<Response<Body>, Error> {
+    match invites.list().await {
+        Ok(invites) => Ok(create_json_response(
+            200,
+            &InviteListResponse {
+                success: true,
+                invites,
+            },
+        )),
+        Err(e) => {
+            info!("Error listing invite codes: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to list invite codes".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+// Mint a single- or multi-use invite code
+async fn create_invite(invites: &InviteStore, event: &Request) -> Result<Response<Body>, Error> {
+    let request: CreateInviteRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: format!("Invalid invite: {e}"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    match invites.create(request).await {
+        Ok(Some(invite)) => Ok(create_json_response(201, &invite)),
+        Ok(None) => Ok(create_response(
+            409,
+            ApiResponse {
+                success: false,
+                message: "Invite code is taken or invalid".to_string(),
+                data: None,
+            },
+        )),
+        Err(e) => {
+            info!("Error creating invite code: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to create invite code".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+async fn revoke_invite(invites: &InviteStore, code: &str) -> Result<Response<Body>, Error> {
+    match invites.revoke(code).await {
+        Ok(Some(invite)) => Ok(create_json_response(200, &invite)),
+        Ok(None) => Ok(create_response(
+            404,
+            ApiResponse {
+                success: false,
+                message: "Invite code not found".to_string(),
+                data: None,
+            },
+        )),
+        Err(e) => {
+            info!("Error revoking invite code: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to revoke invite code".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+// An unsuccessful ApiResponse with the given status
+fn error_response(status: u16, message: &str) -> Response<Body> {
+    create_response(
+        status,
+        ApiResponse {
+            success: false,
+            message: message.to_string(),
+            data: None,
+        },
+    )
+}
+
+// Sender identities, with pending ones checked against SES
+async fn list_senders(
+    senders: &SenderStore,
+    verifier: &SenderVerifier,
+) -> Result<Response<Body>, Error> {
+    let identities = match senders.list().await {
+        Ok(identities) => identities,
+        Err(e) => {
+            info!("Error listing sender identities: {:?}", e);
+            return Ok(error_response(500, "Failed to list sender identities"));
+        }
+    };
+
+    let mut refreshed = Vec::new();
+    for identity in identities {
+        match senders.refresh(verifier, identity).await {
+            Ok(identity) => refreshed.push(identity),
+            Err(e) => {
+                info!("Error checking sender identity: {:?}", e);
+                return Ok(error_response(500, "Failed to check sender identities"));
+            }
+        }
+    }
+
+    Ok(create_json_response(
+        200,
+        &SenderListResponse {
+            success: true,
+            senders: refreshed,
+        },
+    ))
+}
+
+// Add a sender identity and start verifying its address in SES, which emails
+// the address a confirmation link unless it or its domain is already verified
+async fn create_sender(
+    senders: &SenderStore,
+    verifier: &SenderVerifier,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let request: CreateSenderRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: format!("Invalid sender identity: {e}"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    // Both go into headers SES sends, so their domains are stored in punycode
+    // and UTF-8 local parts are refused
+    let from_address = request.from_address.trim().to_string();
+    let reply_to = request
+        .reply_to
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty());
+    let invalid = if !is_valid_sender_id(&request.id) {
+        Some("Sender id must be lowercase letters, digits and dashes")
+    } else if !is_valid_email(&from_address) || sendable_email(&from_address).is_err() {
+        Some("Invalid from address")
+    } else if reply_to
+        .as_deref()
+        .is_some_and(|address| !is_valid_email(address) || sendable_email(address).is_err())
+    {
+        Some("Invalid reply-to address")
+    } else {
+        None
+    };
+    if let Some(message) = invalid {
+        return Ok(create_response(
+            400,
+            ApiResponse {
+                success: false,
+                message: message.to_string(),
+                data: None,
+            },
+        ));
+    }
+    let from_address = normalize_email(&from_address).unwrap_or(from_address);
+    let reply_to = reply_to.map(|address| normalize_email(&address).unwrap_or(address));
+
+    let verified = match verifier.request(&from_address).await {
+        Ok(verified) => verified,
+        Err(e) => {
+            info!("Error requesting SES verification: {:?}", e);
+            return Ok(error_response(
+                500,
+                "Failed to start verifying the from address",
+            ));
+        }
+    };
+    let now = Utc::now();
+    let sender = SenderIdentity {
+        id: request.id,
+        from_address,
+        from_name: request.from_name.filter(|name| !name.trim().is_empty()),
+        reply_to,
+        status: if verified {
+            SenderStatus::Verified
+        } else {
+            SenderStatus::Pending
+        },
+        created_at: now,
+        verified_at: verified.then_some(now),
+    };
+
+    match senders.create(&sender).await {
+        Ok(true) => Ok(create_json_response(201, &sender)),
+        Ok(false) => Ok(create_response(
+            409,
+            ApiResponse {
+                success: false,
+                message: "Sender id is taken".to_string(),
+                data: None,
+            },
+        )),
+        Err(e) => {
+            info!("Error creating sender identity: {:?}", e);
+            Ok(error_response(500, "Failed to create sender identity"))
+        }
+    }
+}
+
+// One sender identity, checked against SES when still pending
+async fn get_sender(
+    senders: &SenderStore,
+    verifier: &SenderVerifier,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let sender = match senders.get(id).await {
+        Ok(Some(sender)) => sender,
+        Ok(None) => {
+            return Ok(create_response(
+                404,
+                ApiResponse {
+                    success: false,
+                    message: "Sender identity not found".to_string(),
+                    data: None,
+                },
+            ));
+        }
+        Err(e) => {
+            info!("Error reading sender identity: {:?}", e);
+            return Ok(error_response(500, "Failed to read sender identity"));
+        }
+    };
+
+    match senders.refresh(verifier, sender).await {
+        Ok(sender) => Ok(create_json_response(200, &sender)),
+        Err(e) => {
+            info!("Error checking sender identity: {:?}", e);
+            Ok(error_response(500, "Failed to check sender identity"))
+        }
+    }
+}
+
+async fn delete_sender(senders: &SenderStore, id: &str) -> Result<Response<Body>, Error> {
+    match senders.delete(id).await {
+        Ok(true) => Ok(create_response(
+            200,
+            ApiResponse {
+                success: true,
+                message: "Sender identity deleted".to_string(),
+                data: None,
+            },
+        )),
+        Ok(false) => Ok(create_response(
+            404,
+            ApiResponse {
+                success: false,
+                message: "Sender identity not found".to_string(),
+                data: None,
+            },
+        )),
+        Err(e) => {
+            info!("Error deleting sender identity: {:?}", e);
+            Ok(error_response(500, "Failed to delete sender identity"))
+        }
+    }
+}
+
+async fn list_templates(templates: &TemplateStore) -> Result<Response<Body>, Error> {
+    match templates.list().await {
+        Ok(templates) => Ok(create_json_response(
+            200,
+            &TemplateListResponse {
+                success: true,
+                templates,
+            },
+        )),
+        Err(e) => {
+            info!("Error listing templates: {:?}", e);
+            Ok(error_response(500, "Failed to list templates"))
+        }
+    }
+}
+
+// Create a template with its first version published
+async fn create_template(
+    templates: &TemplateStore,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let request: CreateTemplateRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: format!("Invalid template: {e}"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    match templates.create(request).await {
+        Ok(template) => Ok(create_json_response(201, &template)),
+        Err(e) => {
+            info!("Error creating template: {:?}", e);
+            Ok(error_response(500, "Failed to create template"))
+        }
+    }
+}
+
+// A template with its version history
+async fn get_template(templates: &TemplateStore, id: &str) -> Result<Response<Body>, Error> {
+    let template = match templates.get(id).await {
+        Ok(Some(template)) => template,
+        Ok(None) => return Ok(error_response(404, "Template not found")),
+        Err(e) => {
+            info!("Error reading template: {:?}", e);
+            return Ok(error_response(500, "Failed to read template"));
+        }
+    };
+
+    match templates.versions(id).await {
+        Ok(versions) => Ok(create_json_response(
+            200,
+            &TemplateResponse {
+                success: true,
+                template,
+                versions,
+            },
+        )),
+        Err(e) => {
+            info!("Error listing template versions: {:?}", e);
+            Ok(error_response(500, "Failed to read template"))
+        }
+    }
+}
+
+// Save an edit as a new version, published unless `publish` is false
+async fn update_template(
+    templates: &TemplateStore,
+    event: &Request,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let request: UpdateTemplateRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: format!("Invalid template: {e}"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    match templates.add_version(id, request).await {
+        Ok(Some((template, version))) => {
+            info!("Saved version {} of template {}", version.version, id);
+            Ok(create_json_response(200, &template))
+        }
+        Ok(None) => Ok(error_response(404, "Template not found")),
+        Err(e) => {
+            info!("Error saving template version: {:?}", e);
+            Ok(error_response(500, "Failed to save template"))
+        }
+    }
+}
+
+// Move the published pointer, e.g. back to the version before a bad edit
+async fn publish_template(
+    templates: &TemplateStore,
+    event: &Request,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let request: PublishTemplateRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: format!("Invalid request: {e}"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    match templates.publish(id, request.version).await {
+        Ok(Some(template)) => Ok(create_json_response(200, &template)),
+        Ok(None) => Ok(create_response(
+            404,
+            ApiResponse {
+                success: false,
+                message: "Template or version not found".to_string(),
+                data: None,
+            },
+        )),
+        Err(e) => {
+            info!("Error publishing template version: {:?}", e);
+            Ok(error_response(500, "Failed to publish template"))
+        }
+    }
+}
+
+// Report what importing the CSV file in the body would do, without writing
+// anything, so a migration can be checked first
+async fn preview_import(
+    repository: &SubscriberRepository,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let format = match event
+        .query_string_parameters()
+        .first("format")
+        .map(str::parse::<ImportFormat>)
+    {
+        Some(Ok(format)) => format,
+        Some(Err(e)) => return Ok(error_response(400, &e)),
+        None => ImportFormat::Generic,
+    };
+
+    let reader = || {
+        csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(event.body().as_ref())
+    };
+
+    // Only the subscribers sharing an address with the file are looked up
+    let emails = match import_emails(&mut reader(), format) {
+        Ok(emails) => emails,
+        Err(e) => return Ok(error_response(400, &format!("Invalid import file: {e}"))),
+    };
+    let existing = match existing_subscribers(repository, &emails).await {
+        Ok(existing) => existing,
+        Err(e) => {
+            info!("Error looking up subscribers for import preview: {:?}", e);
+            return Ok(error_response(500, "Failed to preview import"));
+        }
+    };
+
+    match plan_import(&mut reader(), format, existing) {
+        Ok(plan) => Ok(create_json_response(
+            200,
+            &ImportPreviewResponse {
+                success: true,
+                format: format.as_str().to_string(),
+                report: plan.report,
+            },
+        )),
+        Err(e) => Ok(error_response(400, &format!("Invalid import file: {e}"))),
+    }
+}
+
+// Render a campaign or template for a subscriber, or a sample one, without
+// sending anything
+async fn preview(
+    repository: &SubscriberRepository,
+    campaigns: &CampaignRepository,
+    templates: &TemplateStore,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let request: PreviewRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(error_response(
+                400,
+                &format!("Invalid preview request: {e}"),
+            ));
+        }
+    };
+
+    // A draft made from a template previews with the version it would be sent with
+    let (subject, html_body, id, list) = match (&request.campaign_id, &request.template_id) {
+        (Some(campaign_id), None) => {
+            let Some(campaign) = campaigns.get(campaign_id).await? else {
+                return Ok(error_response(404, "Campaign not found"));
+            };
+            let published = match &campaign.template_id {
+                Some(template_id) if campaign.status == CampaignStatus::Draft => {
+                    templates.published(template_id).await?
+                }
+                _ => None,
+            };
+            let (subject, html_body) = match published {
+                Some(version) => (version.subject, version.html_body),
+                None => (campaign.subject, campaign.html_body),
+            };
+            (subject, html_body, campaign.id, campaign.list)
+        }
+        (None, Some(template_id)) => {
+            let version = match request.version {
+                Some(version) => templates.version(template_id, version).await?,
+                None => templates.published(template_id).await?,
+            };
+            match version {
+                Some(version) => (
+                    version.subject,
+                    version.html_body,
+                    template_id.clone(),
+                    None,
+                ),
+                None => return Ok(error_response(404, "Template or version not found")),
+            }
+        }
+        _ => {
+            return Ok(error_response(
+                400,
+                "Give either a campaign_id or a template_id",
+            ));
+        }
+    };
+
+    let subscriber = match &request.subscriber_id {
+        Some(subscriber_id) => match repository.get(subscriber_id).await? {
+            Some(subscriber) => subscriber,
+            None => return Ok(error_response(404, "Subscriber not found")),
+        },
+        None => sample_subscriber(request.fields),
+    };
+
+    Ok(create_json_response(
+        200,
+        &render_preview(&subscriber, &subject, &html_body, &id, list.as_deref()),
+    ))
+}
+
+// Check the links of a campaign without sending it. A draft made from a
+// template is checked with the version it would be sent with.
+async fn check_campaign_links(
+    campaigns: &CampaignRepository,
+    templates: &TemplateStore,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let Some(campaign) = campaigns.get(id).await? else {
+        return Ok(error_response(404, "Campaign not found"));
+    };
+    let published = match &campaign.template_id {
+        Some(template_id) if campaign.status == CampaignStatus::Draft => {
+            templates.published(template_id).await?
+        }
+        _ => None,
+    };
+    let html_body = published
+        .map(|version| version.html_body)
+        .unwrap_or(campaign.html_body);
+
+    let report = LinkChecker::from_env()?
+        .check(campaign_links(&html_body))
+        .await?;
+    let status = if report.success { 200 } else { 422 };
+    Ok(create_json_response(status, &report))
+}
+
+// Subscriber totals and counts per tag, read from the maintained counters
+// rather than by scanning the subscribers table
+async fn subscriber_counts(counters: &CounterStore) -> Result<Response<Body>, Error> {
+    match counters.counts().await {
+        Ok(counts) => Ok(create_json_response(
+            200,
+            &SubscriberCountsResponse {
+                success: true,
+                counts,
+            },
+        )),
+        Err(e) => {
+            info!("Error reading subscriber counts: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to read subscriber counts".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+// Top referrers and the milestones they've reached
+async fn referral_stats(repository: &SubscriberRepository) -> Result<Response<Body>, Error> {
+    match repository.list_all().await {
+        Ok(subscribers) => Ok(create_json_response(
+            200,
+            &referral::referral_stats(&subscribers),
+        )),
+        Err(e) => {
+            info!("Error computing referral stats: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to compute referral stats".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+// Everything that happened to a subscriber, oldest first. Deleted subscribers
+// keep their history, so only a subscriber with neither is reported missing.
+async fn subscriber_history(
+    repository: &SubscriberRepository,
+    history: &HistoryRepository,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let events = match history.timeline(id).await {
+        Ok(events) => events,
+        Err(e) => {
+            info!("Error loading subscriber history: {:?}", e);
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to load subscriber history".to_string(),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    if events.is_empty() && repository.get(id).await?.is_none() {
+        return Ok(create_response(
+            404,
+            ApiResponse {
+                success: false,
+                message: "Subscriber not found".to_string(),
+                data: None,
+            },
+        ));
+    }
+
+    Ok(create_json_response(
+        200,
+        &SubscriberHistoryResponse {
+            success: true,
+            subscriber_id: id.to_string(),
+            events,
+        },
+    ))
+}
+
+// Latest events returned along with a subscriber's detail
+const RECENT_EVENTS: i32 = 25;
+
+async fn subscriber_detail(
+    history: &HistoryRepository,
+    events: &EventRepository,
+    subscriber: Option<Subscriber>,
+) -> Result<Response<Body>, Error> {
+    let Some(subscriber) = subscriber else {
+        return Ok(error_response(404, "Subscriber not found"));
+    };
+
+    let timeline = history.timeline(&subscriber.id).await?;
+    let recent_events = events
+        .recent_for_subscriber(&subscriber.id, RECENT_EVENTS)
+        .await?;
+
+    Ok(create_json_response(
+        200,
+        &SubscriberDetailResponse {
+            success: true,
+            status: SubscriberStatus::of(&subscriber),
+            subscriber,
+            history: timeline,
+            recent_events,
+        },
+    ))
+}
+
+// One subscriber by id, including soft-deleted ones still waiting to be purged
+async fn get_subscriber(
+    repository: &SubscriberRepository,
+    history: &HistoryRepository,
+    events: &EventRepository,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let subscriber = repository.get_including_deleted(id).await?;
+    subscriber_detail(history, events, subscriber).await
+}
+
+// One subscriber by exact email address
+async fn find_subscriber(
+    repository: &SubscriberRepository,
+    history: &HistoryRepository,
+    events: &EventRepository,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters();
+    let Some(email) = params
+        .first("email")
+        .map(str::trim)
+        .filter(|email| !email.is_empty())
+    else {
+        return Ok(error_response(400, "Missing email"));
+    };
+
+    let subscriber = repository.find_by_email(email).await?;
+    subscriber_detail(history, events, subscriber).await
+}
+
+// Switch a subscriber between immediate campaigns and the weekly digest
+async fn set_subscriber_frequency(
+    repository: &SubscriberRepository,
+    event: &Request,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let request: FrequencyRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: format!("Invalid frequency: {e}"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    match repository.set_frequency(id, request.frequency).await {
+        Ok(Some(subscriber)) => Ok(create_json_response(200, &subscriber)),
+        Ok(None) => Ok(create_response(
+            404,
+            ApiResponse {
+                success: false,
+                message: "Subscriber not found".to_string(),
+                data: None,
+            },
+        )),
+        Err(e) => {
+            info!("Error updating subscriber frequency: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to update frequency".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+// Add a subscriber who consented offline, e.g. on a paper signup sheet. They
+// are confirmed and welcomed right away, with no confirmation email, and the
+// consent note is kept on their record.
+async fn add_subscriber(
+    config: &SdkConfig,
+    repository: &SubscriberRepository,
+    history: &HistoryRepository,
+    domains: &DomainStatsStore,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let request: AddSubscriberRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(400, &format!("Invalid subscriber: {e}"))),
+    };
+
+    let consent_note = request.consent_note.trim().to_string();
+    if consent_note.is_empty() {
+        return Ok(error_response(
+            400,
+            "consent_note is required, saying how they agreed to subscribe",
+        ));
+    }
+    if consent_note.chars().count() > MAX_CONSENT_NOTE_LENGTH {
+        return Ok(error_response(
+            400,
+            &format!("consent_note can be at most {MAX_CONSENT_NOTE_LENGTH} characters"),
+        ));
+    }
+    if !is_valid_email(&request.email) {
+        return Ok(error_response(400, "Invalid email format"));
+    }
+    let email = normalize_email(&request.email).unwrap_or(request.email);
+    let locale = match request.locale.as_deref() {
+        Some(tag) => match Locale::parse(tag) {
+            Some(locale) => locale,
+            None => return Ok(error_response(400, "Unsupported locale")),
+        },
+        None => Locale::default(),
+    };
+    let fields = match FieldSchema::current() {
+        Ok(schema) => match schema.validate(&request.fields) {
+            Ok(fields) => fields,
+            Err(message) => return Ok(error_response(400, &message)),
+        },
+        Err(e) => {
+            info!("Error loading custom field schema: {:?}", e);
+            return Ok(error_response(500, "Failed to add subscriber"));
+        }
+    };
+
+    // Unsubscribed addresses included: they opted out, and only they can opt
+    // back in
+    match repository.find_signup_by_email(&email).await {
+        Ok(Some(_)) => return Ok(error_response(409, "Email is already subscribed")),
+        Ok(None) => {}
+        Err(e) => {
+            info!("Error checking for existing email: {:?}", e);
+            return Ok(error_response(500, "Failed to add subscriber"));
+        }
+    }
+
+    let mut subscriber = Subscriber::new(email);
+    subscriber.validated = true;
+    subscriber.locale = locale;
+    subscriber.fields = fields;
+    subscriber.consent_note = Some(consent_note.clone());
+    subscriber.attribution.source = Some(
+        request
+            .source
+            .map(|source| source.trim().to_string())
+            .filter(|source| !source.is_empty())
+            .unwrap_or_else(|| "manual".to_string()),
+    );
+
+    if let Err(e) = repository.put(&subscriber).await {
+        info!("Error adding subscriber: {:?}", e);
+        return Ok(error_response(500, "Failed to add subscriber"));
+    }
+    history
+        .record_or_log(
+            LifecycleEvent::new(&subscriber.id, LifecycleEventType::Subscribed)
+                .with_source("admin")
+                .with_detail(consent_note),
+        )
+        .await;
+    domains
+        .record_or_log(
+            &subscriber.email,
+            DomainMetric::Subscribed,
+            subscriber.created_at,
+        )
+        .await;
+    confirm_without_link(config, Client::new(config), &subscriber, "admin").await;
+
+    Ok(create_json_response(201, &subscriber))
+}
+
+// Let a quarantined signup through: under double opt-in it gets the
+// confirmation email, otherwise it is confirmed and welcomed
+async fn approve_subscriber(
+    config: &SdkConfig,
+    repository: &SubscriberRepository,
+    history: &HistoryRepository,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let confirm = !double_opt_in();
+    let queue_url = env::var("VALIDATION_QUEUE_URL").ok();
+    if !confirm && queue_url.is_none() {
+        info!("VALIDATION_QUEUE_URL not set in environment");
+        return Ok(error_response(500, "Validation queue is not configured"));
+    }
+
+    let subscriber = match repository.approve(id, confirm).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => return Ok(error_response(404, "Quarantined subscriber not found")),
+        Err(e) => {
+            info!("Error approving subscriber: {:?}", e);
+            return Ok(error_response(500, "Failed to approve subscriber"));
+        }
+    };
+    history
+        .record_or_log(
+            LifecycleEvent::new(&subscriber.id, LifecycleEventType::Approved).with_source("admin"),
+        )
+        .await;
+
+    match queue_url {
+        Some(queue_url) if !confirm => {
+            let message = ValidationMessage {
+                schema_version: SCHEMA_VERSION,
+                action: VALIDATE_EMAIL_ACTION.to_string(),
+                email: subscriber.email.clone(),
+                subscriber_id: subscriber.id.clone(),
+            };
+            if let Err(e) = ValidationQueue::new(SqsClient::new(config), queue_url)
+                .send(&message)
+                .await
+            {
+                info!("Failed to send validation message to queue: {:?}", e);
+                return Ok(error_response(
+                    500,
+                    "Subscriber approved, but the confirmation email couldn't be queued",
+                ));
+            }
+        }
+        _ => confirm_without_link(config, Client::new(config), &subscriber, "admin").await,
+    }
+
+    Ok(create_json_response(200, &subscriber))
+}
+
+// Confirm a subscriber without their link, e.g. when the confirmation email
+// went to spam. The request is in the audit log like every other change.
+async fn confirm_subscriber(
+    config: &SdkConfig,
+    repository: &SubscriberRepository,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    match repository.confirm_manually(id).await {
+        Ok(Some(subscriber)) => {
+            confirm_without_link(config, Client::new(config), &subscriber, "admin_confirm").await;
+            Ok(create_json_response(200, &subscriber))
+        }
+        // Nothing changed: explain why
+        Ok(None) => match repository.get(id).await {
+            Ok(Some(subscriber)) if subscriber.is_quarantined() => Ok(error_response(
+                409,
+                "Subscriber is quarantined, approve them instead",
+            )),
+            // Confirming twice is harmless
+            Ok(Some(subscriber)) => Ok(create_json_response(200, &subscriber)),
+            Ok(None) => Ok(error_response(404, "Subscriber not found")),
+            Err(e) => {
+                info!("Error reading subscriber: {:?}", e);
+                Ok(error_response(500, "Failed to confirm subscriber"))
+            }
+        },
+        Err(e) => {
+            info!("Error confirming subscriber: {:?}", e);
+            Ok(error_response(500, "Failed to confirm subscriber"))
+        }
+    }
+}
+
+// Start a long-running job. The job_worker Lambda goes through the
+// subscribers a page at a time; poll the job for its progress.
+async fn start_job(
+    config: &SdkConfig,
+    jobs: &JobStore,
+    api_key: &ApiKey,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let request: StartJobRequest = match serde_json::from_slice(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(400, &format!("Invalid job: {e}"))),
+    };
+    let params = match request.kind.validate(request.params) {
+        Ok(params) => params,
+        Err(e) => {
+            return Ok(error_response(
+                400,
+                &format!("Invalid {} job: {e}", request.kind.as_str()),
+            ));
+        }
+    };
+    let Ok(queue_url) = env::var("JOB_QUEUE_URL") else {
+        info!("JOB_QUEUE_URL not set in environment");
+        return Ok(error_response(500, "Job queue is not configured"));
+    };
+
+    let job = Job::new(request.kind, params, &api_key.id);
+    if let Err(e) = jobs.create(&job).await {
+        info!("Error creating job: {:?}", e);
+        return Ok(error_response(500, "Failed to start job"));
+    }
+
+    let queue = JobQueue::new(SqsClient::new(config), queue_url);
+    if let Err(e) = queue.send(&JobPageMessage::first(&job.id)).await {
+        info!("Error queueing job {}: {:?}", job.id, e);
+        if let Err(e) = jobs.mark_failed(&job.id, "Couldn't be queued").await {
+            info!("Error marking job {} failed: {:?}", job.id, e);
+        }
+        return Ok(error_response(500, "Failed to start job"));
+    }
+
+    info!(
+        "{} started {} job {}",
+        api_key.id,
+        job.kind.as_str(),
+        job.id
+    );
+    Ok(create_json_response(202, &job))
+}
+
+async fn list_jobs(jobs: &JobStore) -> Result<Response<Body>, Error> {
+    match jobs.list().await {
+        Ok(jobs) => Ok(create_json_response(
+            200,
+            &JobListResponse {
+                success: true,
+                jobs,
+            },
+        )),
+        Err(e) => {
+            info!("Error listing jobs: {:?}", e);
+            Ok(error_response(500, "Failed to list jobs"))
+        }
+    }
+}
+
+async fn get_job(jobs: &JobStore, id: &str) -> Result<Response<Body>, Error> {
+    match jobs.get(id).await {
+        Ok(Some(job)) => Ok(create_json_response(200, &job)),
+        Ok(None) => Ok(error_response(404, "Job not found")),
+        Err(e) => {
+            info!("Error reading job: {:?}", e);
+            Ok(error_response(500, "Failed to read job"))
+        }
+    }
+}
+
+// Stop a job before its remaining pages. What it already did stays done.
+async fn cancel_job(jobs: &JobStore, id: &str) -> Result<Response<Body>, Error> {
+    match jobs.cancel(id).await {
+        Ok(true) => get_job(jobs, id).await,
+        Ok(false) => match jobs.get(id).await {
+            Ok(Some(job)) => Ok(error_response(
+                409,
+                &format!("Job is already {}", job.status.as_str()),
+            )),
+            Ok(None) => Ok(error_response(404, "Job not found")),
+            Err(e) => {
+                info!("Error reading job: {:?}", e);
+                Ok(error_response(500, "Failed to cancel job"))
+            }
+        },
+        Err(e) => {
+            info!("Error cancelling job: {:?}", e);
+            Ok(error_response(500, "Failed to cancel job"))
+        }
+    }
+}
+
+// Add X-RateLimit-* style headers describing a quota window
+fn set_quota_headers(response: &mut Response<Body>, prefix: &str, status: &QuotaStatus) {
+    let headers = response.headers_mut();
+    for (name, value) in [
+        ("Limit", status.limit),
+        ("Remaining", status.remaining()),
+        ("Reset", status.reset_at.timestamp().max(0) as u64),
+    ] {
+        if let Ok(name) = HeaderName::try_from(format!("{prefix}-{name}")) {
+            headers.insert(name, HeaderValue::from(value));
+        }
+    }
+}
+
+fn quota_exceeded(prefix: &str, status: &QuotaStatus, message: &str) -> Response<Body> {
+    let mut response = create_response(
+        429,
+        ApiResponse {
+            success: false,
+            message: message.to_string(),
+            data: None,
+        },
+    );
+    set_quota_headers(&mut response, prefix, status);
+    let retry_after = (status.reset_at - Utc::now()).num_seconds().max(0) as u64;
+    response
+        .headers_mut()
+        .insert("Retry-After", HeaderValue::from(retry_after));
+    response
+}
+
+// The response to a campaign that failed the checks before sending
+// The response for a campaign `prepare_send` refused, see `SendRefusal`
+fn send_refused(refusal: SendRefusal) -> Response<Body> {
+    match refusal {
+        SendRefusal::NotConfigured => error_response(500, &refusal.to_string()),
+        SendRefusal::NotFound => error_response(404, &refusal.to_string()),
+        SendRefusal::NotSendable(_)
+        | SendRefusal::UnverifiedSender(_)
+        | SendRefusal::UnpublishedTemplate(_) => error_response(409, &refusal.to_string()),
+        SendRefusal::BrokenLinks(report) => create_json_response(422, &report),
+        SendRefusal::BlockedContent(report) => create_json_response(422, &report),
+        SendRefusal::QuotaExceeded(status) => {
+            quota_exceeded("X-SendQuota", &status, "Monthly send quota exceeded")
+        }
+    }
+}
+
+// Send a draft campaign, counting its recipients against the key's monthly send
+// quota. With a `date` and `local_time` in the body it is scheduled to go out at
+// that time in each recipient's timezone instead.
+async fn send_campaign(
+    config: &SdkConfig,
+    repository: &SubscriberRepository,
+    quotas: &QuotaStore,
+    api_key: &ApiKey,
+    event: &Request,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let schedule = if event.body().is_empty() {
+        None
+    } else {
+        let parsed = serde_json::from_slice::<ScheduleRequest>(event.body().as_ref())
+            .ok()
+            .and_then(|request| Some((request.date, request.time()?)));
+        match parsed {
+            Some(schedule) => Some(schedule),
+            None => {
+                return Ok(create_response(
+                    400,
+                    ApiResponse {
+                        success: false,
+                        message:
+                            "Invalid schedule, expected a date (YYYY-MM-DD) and local_time (HH:MM)"
+                                .to_string(),
+                        data: None,
+                    },
+                ));
+            }
+        }
+    };
+
+    let sender_email = match env::var("SENDER_EMAIL") {
+        Ok(sender_email) => sender_email,
+        Err(_) => {
+            info!("SENDER_EMAIL not set in environment");
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Sending is not configured".to_string(),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    let campaigns = CampaignRepository::new(Client::new(config));
+    let guards = SendGuards {
+        subscribers: repository,
+        campaigns: &campaigns,
+        templates: &TemplateStore::new(Client::new(config)),
+        senders: &SenderStore::new(Client::new(config)),
+        verifier: &SenderVerifier::new(SesClient::new(config)),
+        quotas,
+    };
+    // A send that stalled mid-way can be sent again, which resumes it from its
+    // checkpoint, but not scheduled
+    let request = SendRequest {
+        campaign_id: id,
+        api_key: Some(api_key),
+        resume: schedule.is_none(),
+        // Unless the caller already knows about broken links
+        check_links: event
+            .query_string_parameters()
+            .first("skip_link_check")
+            .is_none_or(|value| value != "true"),
+    };
+    let prepared = match prepare_send(&guards, &Mailer::new(config, sender_email), request).await? {
+        Ok(prepared) => prepared,
+        Err(refusal) => return Ok(send_refused(refusal)),
+    };
+    let PreparedSend {
+        campaign,
+        mailer,
+        recipients,
+        content_warnings,
+        send_quota,
+    } = prepared;
+
+    if let Some((date, time)) = schedule {
+        let schedules = ScheduleStore::new(Client::new(config));
+        let snapshots = RecipientSnapshotStore::new(Client::new(config));
+        let buckets = schedule_campaign(
+            &campaigns,
+            &schedules,
+            &snapshots,
+            &campaign,
+            &recipients,
+            date,
+            time,
+        )
+        .await?;
+        info!(
+            "Key {} scheduled campaign {} for {} subscribers in {} timezones",
+            api_key.id,
+            campaign.id,
+            recipients.len(),
+            buckets.len()
+        );
+
+        let mut response = create_json_response(
+            202,
+            &ApiResponse {
+                success: true,
+                message: format!(
+                    "Scheduled campaign for {} subscribers in {} timezones",
+                    recipients.len(),
+                    buckets.len()
+                ),
+                data: Some(CampaignSendData {
+                    campaign_id: campaign.id.clone(),
+                    recipients: recipients.len(),
+                    timezones: Some(buckets.len()),
+                    snapshot_count: Some(recipients.len()),
+                    content_warnings,
+                }),
+            },
+        );
+        if let Some(status) = send_quota {
+            set_quota_headers(&mut response, "X-SendQuota", &status);
+        }
+        return Ok(response);
+    }
+
+    // Fresh sends go through the state machine when there is one, which
+    // resolves the recipients again and sends them in batches
+    if let Some(state_machine_arn) =
+        state_machine_arn().filter(|_| campaign.status == CampaignStatus::Draft)
+    {
+        let workflow = CampaignWorkflow::new(SfnClient::new(config), state_machine_arn);
+        let execution = CampaignWorkflow::execution_name(&campaign.id);
+        if !campaigns.start_workflow(&campaign.id, &execution).await? {
+            return Ok(error_response(409, "Campaign is no longer a draft"));
+        }
+        if let Err(e) = workflow.start(&execution, &campaign.id).await {
+            campaigns.abandon_workflow(&campaign.id, &execution).await?;
+            return Err(e);
+        }
+        info!(
+            "Key {} started execution {} sending campaign {} to {} subscribers",
+            api_key.id,
+            execution,
+            campaign.id,
+            recipients.len()
+        );
+
+        let mut response = create_json_response(
+            202,
+            &ApiResponse {
+                success: true,
+                message: format!(
+                    "Started sending campaign to {} subscribers",
+                    recipients.len()
+                ),
+                data: Some(CampaignSendData {
+                    campaign_id: campaign.id.clone(),
+                    recipients: recipients.len(),
+                    timezones: None,
+                    snapshot_count: None,
+                    content_warnings,
+                }),
+            },
+        );
+        if let Some(status) = send_quota {
+            set_quota_headers(&mut response, "X-SendQuota", &status);
+        }
+        return Ok(response);
+    }
+
+    let digests = DigestStore::new(Client::new(config));
+    let counts = deliver_campaign(&campaigns, &digests, &mailer, &campaign, &recipients).await?;
+    let sent_count = counts.sent;
+    info!(
+        "Key {} sent campaign {} to {} subscribers, skipped {}",
+        api_key.id, campaign.id, sent_count, counts.skipped
+    );
+
+    let message = if counts.cancelled() {
+        format!(
+            "Campaign was cancelled after {} emails, {} skipped",
+            sent_count, counts.skipped
+        )
+    } else {
+        format!("Sent campaign to {} subscribers", sent_count)
+    };
+    let mut response = create_json_response(
+        200,
+        &ApiResponse {
+            success: true,
+            message,
+            data: Some(CampaignSendData {
+                campaign_id: campaign.id.clone(),
+                recipients: sent_count as usize,
+                timezones: None,
+                snapshot_count: None,
+                content_warnings,
+            }),
+        },
+    );
+    if let Some(status) = send_quota {
+        set_quota_headers(&mut response, "X-SendQuota", &status);
+    }
+    Ok(response)
+}
+
+// Stop a campaign that is being sent or is scheduled. Senders notice within
+// a batch and skip the rest, and queued emails are dropped by the send queue
+// worker. A scheduled campaign's buckets that haven't gone out are closed
+// right away.
+async fn cancel_campaign(
+    config: &SdkConfig,
+    repository: &SubscriberRepository,
+    campaigns: &CampaignRepository,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let Some(campaign) = campaigns.get(id).await? else {
+        return Ok(error_response(404, "Campaign not found"));
+    };
+    if !campaigns.request_cancel(id).await? {
+        return Ok(error_response(
+            409,
+            &format!(
+                "Campaign is {}, not sending or scheduled",
+                campaign.status.as_str()
+            ),
+        ));
+    }
+
+    if campaign.status == CampaignStatus::Scheduled {
+        let schedules = ScheduleStore::new(Client::new(config));
+        let snapshots = RecipientSnapshotStore::new(Client::new(config));
+        cancel_schedule(repository, campaigns, &schedules, &snapshots, &campaign).await?;
+    }
+
+    let Some(campaign) = campaigns.get(id).await? else {
+        return Ok(error_response(404, "Campaign not found"));
+    };
+    let (status, message) = match campaign.status {
+        CampaignStatus::Cancelled => (200, "Campaign cancelled"),
+        _ => (202, "Campaign is being cancelled"),
+    };
+    info!(
+        "Campaign {} is {} with {} sent and {} skipped",
+        id,
+        campaign.status.as_str(),
+        campaign.sent_count,
+        campaign.skipped_count
+    );
+
+    Ok(create_json_response(
+        status,
+        &ApiResponse {
+            success: true,
+            message: message.to_string(),
+            data: Some(CampaignCancelData {
+                campaign_id: campaign.id,
+                status: campaign.status.as_str().to_string(),
+                sent_count: campaign.sent_count,
+                skipped_count: campaign.skipped_count,
+            }),
+        },
+    ))
+}
+
+// Replace the subscribers a draft campaign leaves out, by tag, segment or
+// address, e.g. customers who already bought what it promotes
+async fn set_campaign_exclusions(
+    campaigns: &CampaignRepository,
+    event: &Request,
+    id: &str,
+) -> Result<Response<Body>, Error> {
+    let exclusions = match serde_json::from_slice::<Exclusions>(event.body().as_ref())
+        .map_err(Error::from)
+        .and_then(Exclusions::normalized)
+    {
+        Ok(exclusions) => exclusions,
+        Err(e) => return Ok(error_response(400, &format!("Invalid exclusions: {e}"))),
+    };
+
+    let Some(campaign) = campaigns.get(id).await? else {
+        return Ok(error_response(404, "Campaign not found"));
+    };
+    if !campaigns.set_exclusions(id, &exclusions).await? {
+        return Ok(error_response(
+            409,
+            &format!("Campaign is {}, not draft", campaign.status.as_str()),
+        ));
+    }
+    info!(
+        "Campaign {} excludes {} tags, {} addresses and segment {:?}",
+        id,
+        exclusions.tags.len(),
+        exclusions.emails.len(),
+        exclusions.segment
+    );
+
+    Ok(create_json_response(
+        200,
+        &ApiResponse {
+            success: true,
+            message: "Campaign exclusions updated".to_string(),
+            data: Some(exclusions),
+        },
+    ))
+}
+
+// Send unsubscribes and suppressions since a time to the opt-out webhook
+// again, e.g. after it was down. Replays keep the id of the original event, so
+// receivers can drop the ones they already have.
+async fn replay_opt_outs(
+    repository: &SubscriberRepository,
+    history: &HistoryRepository,
+    event: &Request,
+) -> Result<Response<Body>, Error> {
+    let Some(webhook) = OptOutWebhook::from_env() else {
+        return Ok(error_response(409, "The opt-out webhook is not configured"));
+    };
+    let params = event.query_string_parameters();
+    let since = match params.first("since").map(DateTime::parse_from_rfc3339) {
+        Some(Ok(since)) => since.with_timezone(&Utc),
+        _ => return Ok(error_response(400, "since must be an RFC 3339 timestamp")),
+    };
+    // Each event is a webhook call, so a page stays within API Gateway's timeout
+    let limit = params
+        .first("limit")
+        .and_then(|limit| limit.parse::<usize>().ok())
+        .unwrap_or(100)
+        .clamp(1, 1000);
+
+    let mut data = OptOutReplayData {
+        sent: 0,
+        failed: 0,
+        skipped: 0,
+        next_since: None,
+    };
+    for (index, lifecycle) in history.opt_outs_since(since).await?.iter().enumerate() {
+        if index == limit {
+            data.next_since = Some(lifecycle.occurred_at);
+            break;
+        }
+        // Purged subscribers leave no address to send
+        let Some(subscriber) = repository
+            .get_including_deleted(&lifecycle.subscriber_id)
+            .await?
+        else {
+            data.skipped += 1;
+            continue;
+        };
+        let Some(mut opt_out) = OptOut::new(lifecycle, &subscriber.email) else {
+            continue;
+        };
+        opt_out.replayed = true;
+        match webhook.send(&opt_out).await {
+            Ok(()) => data.sent += 1,
+            Err(e) => {
+                info!("Error replaying opt-out {}: {:?}", opt_out.id, e);
+                data.failed += 1;
+            }
+        }
+    }
+    info!(
+        "Replayed opt-outs since {}: {} sent, {} failed, {} skipped",
+        since, data.sent, data.failed, data.skipped
+    );
+
+    Ok(create_json_response(
+        200,
+        &ApiResponse {
+            success: data.failed == 0,
+            message: format!("Replayed {} opt-outs", data.sent),
+            data: Some(data),
+        },
+    ))
+}
+
+async fn audit_log(audit: &AuditStore, event: &Request) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters();
+    let days = params
+        .first("days")
+        .and_then(|days| days.parse::<i64>().ok())
+        .unwrap_or(7)
+        .clamp(1, 90);
+    let limit = params
+        .first("limit")
+        .and_then(|limit| limit.parse::<usize>().ok())
+        .unwrap_or(100)
+        .clamp(1, 1000);
+
+    match audit.list(days, params.first("api_key"), limit).await {
+        Ok(entries) => Ok(create_json_response(
+            200,
+            &AuditLogResponse {
+                success: true,
+                days,
+                entries,
+            },
+        )),
+        Err(e) => {
+            info!("Error listing audit entries: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to list audit entries".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+async fn function_handler(
+    tokens: Option<&JwtVerifier>,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    let api_key = match authenticate(&event, tokens).await {
+        Ok(api_key) => api_key,
+        Err(AuthError::Unauthorized) => return Ok(error_response(401, "Unauthorized")),
+        Err(AuthError::NoRole) => {
+            return Ok(error_response(403, "No admin role for this user"));
+        }
+    };
+
+    // Route on the part of the path after /admin. Requests the role doesn't
+    // allow are refused before they count towards the key's quota.
+    let path = event.uri().path().to_string();
+    let route = path
+        .find("/admin")
+        .map(|index| &path[index + "/admin".len()..])
+        .unwrap_or(&path)
+        .trim_end_matches('/');
+    let operation = Operation::for_route(event.method().as_str(), route);
+    if !api_key.role.allows(operation) {
+        info!("Denied {} {} to {}", event.method(), route, api_key.id);
+        return Ok(error_response(
+            403,
+            &format!(
+                "The {} role is not allowed to {}",
+                api_key.role.as_str(),
+                operation.as_str()
+            ),
+        ));
+    }
+
+    // Initialize AWS SDK
+    let config = sdk_config().await;
+    let dynamodb_client = dynamodb().await;
+    let repository = SubscriberRepository::new(dynamodb_client.clone());
+    let quotas = QuotaStore::new(dynamodb_client.clone());
+    let history = HistoryRepository::new(dynamodb_client.clone());
+    let events = EventRepository::new(dynamodb_client.clone());
+    let domains = DomainStatsStore::new(dynamodb_client.clone());
+    let counters = CounterStore::new(dynamodb_client.clone());
+    let domain_rules = DomainRuleStore::new(dynamodb_client.clone());
+    let invites = InviteStore::new(dynamodb_client.clone());
+    let senders = SenderStore::new(dynamodb_client.clone());
+    let templates = TemplateStore::new(dynamodb_client.clone());
+    let audit = AuditStore::new(dynamodb_client.clone());
+    let jobs = JobStore::new(dynamodb_client.clone());
+    let campaigns = CampaignRepository::new(dynamodb_client);
+    let verifier = SenderVerifier::new(SesClient::new(config));
+
+    let request_quota = match api_key.requests_per_day {
+        Some(limit) => match quotas
+            .consume(&api_key.id, QuotaKind::Requests, limit, 1)
+            .await
+        {
+            Ok(status) if !status.allowed => {
+                return Ok(quota_exceeded(
+                    "X-RateLimit",
+                    &status,
+                    "Daily request quota exceeded",
+                ));
+            }
+            Ok(status) => Some(status),
+            Err(e) => {
+                // Don't lock integrations out because the quota table is unavailable
+                info!("Error checking request quota: {:?}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let segments: Vec<&str> = route.split('/').skip(1).collect();
+
+    let mut response = match (event.method().as_str(), segments.as_slice()) {
+        ("GET", ["subscribers"]) => list_subscribers(&repository, &event).await?,
+        ("POST", ["subscribers"]) => {
+            add_subscriber(config, &repository, &history, &domains, &event).await?
+        }
+        ("GET", ["subscribers", "search"]) => search_subscribers(&repository, &event).await?,
+        ("GET", ["subscribers", "changes"]) => subscriber_changes(&repository, &event).await?,
+        ("GET", ["subscribers", "lookup"]) => {
+            find_subscriber(&repository, &history, &events, &event).await?
+        }
+        ("GET", ["subscribers", id]) => get_subscriber(&repository, &history, &events, id).await?,
+        ("GET", ["subscribers", id, "history"]) => {
+            subscriber_history(&repository, &history, id).await?
+        }
+        ("PUT", ["subscribers", id, "frequency"]) => {
+            set_subscriber_frequency(&repository, &event, id).await?
+        }
+        ("POST", ["subscribers", id, "approve"]) => {
+            approve_subscriber(config, &repository, &history, id).await?
+        }
+        ("POST", ["subscribers", id, "confirm"]) => {
+            confirm_subscriber(config, &repository, id).await?
+        }
+        ("GET", ["jobs"]) => list_jobs(&jobs).await?,
+        ("POST", ["jobs"]) => start_job(config, &jobs, &api_key, &event).await?,
+        ("GET", ["jobs", id]) => get_job(&jobs, id).await?,
+        ("POST", ["jobs", id, "cancel"]) => cancel_job(&jobs, id).await?,
+        ("GET", ["audit"]) => audit_log(&audit, &event).await?,
+        ("GET", ["stats", "sources"]) => source_stats(&repository, &event).await?,
+        ("GET", ["stats", "referrals"]) => referral_stats(&repository).await?,
+        ("GET", ["stats", "domains"]) => domain_stats(&domains, &event).await?,
+        ("GET", ["stats", "subscribers"]) => subscriber_counts(&counters).await?,
+        ("GET", ["domain-rules"]) => list_domain_rules(&domain_rules).await?,
+        ("PUT", ["domain-rules", domain]) => put_domain_rule(&domain_rules, &event, domain).await?,
+        ("DELETE", ["domain-rules", domain]) => delete_domain_rule(&domain_rules, domain).await?,
+        ("GET", ["invites"]) => list_invites(&invites).await?,
+        ("POST", ["invites"]) => create_invite(&invites, &event).await?,
+        ("DELETE", ["invites", code]) => revoke_invite(&invites, code).await?,
+        ("GET", ["senders"]) => list_senders(&senders, &verifier).await?,
+        ("POST", ["senders"]) => create_sender(&senders, &verifier, &event).await?,
+        ("GET", ["senders", id]) => get_sender(&senders, &verifier, id).await?,
+        ("DELETE", ["senders", id]) => delete_sender(&senders, id).await?,
+        ("POST", ["preview"]) => preview(&repository, &campaigns, &templates, &event).await?,
+        ("POST", ["import", "preview"]) => preview_import(&repository, &event).await?,
+        ("POST", ["opt-outs", "replay"]) => replay_opt_outs(&repository, &history, &event).await?,
+        ("GET", ["templates"]) => list_templates(&templates).await?,
+        ("POST", ["templates"]) => create_template(&templates, &event).await?,
+        ("GET", ["templates", id]) => get_template(&templates, id).await?,
+        ("PUT", ["templates", id]) => update_template(&templates, &event, id).await?,
+        ("POST", ["templates", id, "publish"]) => publish_template(&templates, &event, id).await?,
+        ("POST", ["campaigns", id, "send"]) => {
+            send_campaign(config, &repository, &quotas, &api_key, &event, id).await?
+        }
+        ("POST", ["campaigns", id, "cancel"]) => {
+            cancel_campaign(config, &repository, &campaigns, id).await?
+        }
+        ("POST", ["campaigns", id, "check-links"]) => {
+            check_campaign_links(&campaigns, &templates, id).await?
+        }
+        ("PUT", ["campaigns", id, "exclusions"]) => {
+            set_campaign_exclusions(&campaigns, &event, id).await?
+        }
+        _ => create_response(
+            404,
+            ApiResponse {
+                success: false,
+                message: "Not found".to_string(),
+                data: None,
+            },
+        ),
+    };
+
+    if is_mutation(event.method().as_str(), route) {
+        audit
+            .record_or_log(AuditEntry::new(
+                &api_key.id,
+                event.method().as_str(),
+                &path,
+                event.uri().query(),
+                response.status().as_u16(),
+            ))
+            .await;
+    }
+
+    if let Some(status) = request_quota {
+        set_quota_headers(&mut response, "X-RateLimit", &status);
+    }
+
+    Ok(response)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    // Bearer tokens are accepted directly when JWT_ISSUER and JWT_AUDIENCE are
+    // set. Built once per container, so the JWKS cache outlives each request.
+    let tokens = match JwtSettings::from_env() {
+        Ok(settings) => Some(JwtVerifier::new(settings)?),
+        Err(_) => None,
+    };
+    let tokens = tokens.as_ref();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            versioned(event, |event| {
+                checked(event, InputLimits::ADMIN, |event| {
+                    function_handler(tokens, event)
+                })
+            })
+        })
+    }))
+    .await
+}
@@ -0,0 +1,93 @@
+use lambda_http::aws_lambda_events::apigw::{
+    ApiGatewayCustomAuthorizerPolicy, ApiGatewayCustomAuthorizerRequest,
+    ApiGatewayCustomAuthorizerResponse, IamPolicyStatement,
+};
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::jwt::{JwtSettings, JwtVerifier};
+use serde_json::json;
+use tracing::info;
+
+// API Gateway caches the policy per token, so it covers every method and path
+// of the stage rather than just the one requested, e.g.
+// arn:aws:execute-api:us-east-1:123456789012:abcdef/prod/*/*
+fn stage_resource(method_arn: &str) -> String {
+    let mut parts = method_arn.splitn(3, '/');
+    match (parts.next(), parts.next()) {
+        (Some(api), Some(stage)) => format!("{api}/{stage}/*/*"),
+        _ => method_arn.to_string(),
+    }
+}
+
+fn policy(
+    principal_id: &str,
+    effect: &str,
+    method_arn: &str,
+    context: serde_json::Value,
+) -> ApiGatewayCustomAuthorizerResponse {
+    ApiGatewayCustomAuthorizerResponse {
+        principal_id: Some(principal_id.to_string()),
+        policy_document: ApiGatewayCustomAuthorizerPolicy {
+            version: Some("2012-10-17".to_string()),
+            statement: vec![IamPolicyStatement {
+                action: vec!["execute-api:Invoke".to_string()],
+                effect: Some(effect.to_string()),
+                resource: vec![stage_resource(method_arn)],
+            }],
+        },
+        context,
+        usage_identifier_key: None,
+    }
+}
+
+// Allow admin requests with a valid bearer token carrying every scope in
+// JWT_REQUIRED_SCOPES. Invalid tokens get 401, missing scopes 403.
+async fn function_handler(
+    verifier: &JwtVerifier,
+    event: LambdaEvent<ApiGatewayCustomAuthorizerRequest>,
+) -> Result<ApiGatewayCustomAuthorizerResponse, Error> {
+    let method_arn = event.payload.method_arn.unwrap_or_default();
+    let token = event
+        .payload
+        .authorization_token
+        .as_deref()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default()
+        .trim();
+
+    let claims = match verifier.verify(token).await {
+        Ok(claims) => claims,
+        Err(e) => {
+            info!("Rejected token: {:?}", e);
+            // The exact message API Gateway turns into a 401
+            return Err("Unauthorized".into());
+        }
+    };
+
+    if let Some(scope) = claims.missing_scope(&verifier.settings().required_scopes) {
+        info!("Denied {}: missing scope {}", claims.sub, scope);
+        return Ok(policy(&claims.sub, "Deny", &method_arn, json!({})));
+    }
+
+    // The admin Lambda audits the identity and maps the groups to a role
+    info!("Allowed {}", claims.identity());
+    Ok(policy(
+        claims.identity(),
+        "Allow",
+        &method_arn,
+        json!({
+            "scope": claims.scopes().join(" "),
+            "groups": verifier.groups(&claims).join(" "),
+        }),
+    ))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    // Built once per container, so the JWKS cache outlives each request
+    let verifier = JwtVerifier::new(JwtSettings::from_env()?)?;
+    run(service_fn(|event| function_handler(&verifier, event))).await
+}
@@ -0,0 +1,80 @@
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_s3::Client as S3Client;
+use chrono::Utc;
+use clap::Parser;
+use newsletter_backend::backup::{BackupKind, BackupStore, item_id, updated_since};
+use newsletter_backend::batch::{parallel_scan, scan_segments};
+use newsletter_backend::config::table_name;
+use newsletter_backend::{Error, TABLE_NAME};
+
+#[derive(Parser)]
+#[command(
+    name = "backup",
+    about = "Export the subscribers table to NDJSON objects in S3"
+)]
+struct Cli {
+    #[arg(long, env = "BACKUP_BUCKET")]
+    bucket: String,
+    /// Key prefix the backups are stored under
+    #[arg(long, default_value = "subscribers")]
+    prefix: String,
+    /// Only export subscribers updated since the previous backup
+    #[arg(long)]
+    incremental: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+
+    // Initialize AWS SDK
+    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+    let config = aws_config::from_env().region(region_provider).load().await;
+    let dynamodb_client = Client::new(&config);
+    let store = BackupStore::new(S3Client::new(&config), cli.bucket, cli.prefix);
+
+    // Taken before the scan, so writes racing it land in the next incremental
+    let taken_at = Utc::now();
+
+    // Raw items, so deleted subscribers and every attribute survive a restore
+    let mut items =
+        parallel_scan(&dynamodb_client, &table_name(TABLE_NAME), scan_segments()).await?;
+
+    // Every subscriber alive now, so a restore can drop the ones deleted since
+    // the previous backup; incrementals only hold what changed
+    let live_ids: Vec<String> = items
+        .iter()
+        .filter_map(|item| item_id(item).map(str::to_string))
+        .collect();
+
+    let kind = if cli.incremental {
+        let previous = store
+            .list()
+            .await?
+            .pop()
+            .ok_or("No previous backup found, run a full backup first")?;
+        items.retain(|item| updated_since(item, previous.taken_at));
+        BackupKind::Incremental
+    } else {
+        BackupKind::Full
+    };
+
+    let backup = store.write(kind, taken_at, &items).await?;
+    if kind == BackupKind::Incremental {
+        store.write_live_ids(&backup, &live_ids).await?;
+    }
+    println!(
+        "Wrote {} backup of {} subscribers to {}",
+        kind.as_str(),
+        items.len(),
+        backup.key
+    );
+
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use aws_sdk_sesv2::Client as SesClient;
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::campaign::CampaignStatus;
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::delivery::{
+    Progress, SendCounts, campaign_mailer, campaign_recipients, finish_send, send_campaign_emails,
+};
+use newsletter_backend::digest::DigestStore;
+use newsletter_backend::email_queue::Mailer;
+use newsletter_backend::repository::{CampaignRepository, SubscriberRepository};
+use newsletter_backend::schedule::RecipientSnapshotStore;
+use newsletter_backend::senders::{SenderStore, SenderVerifier};
+use newsletter_backend::workflow::{
+    BatchResult, FinishInput, RecipientBatch, ResolveOutput, WorkflowInput, WorkflowStep,
+    WorkflowSummary, plan_batches,
+};
+use serde_json::Value;
+use std::env;
+use tracing::info;
+
+struct Steps {
+    subscribers: SubscriberRepository,
+    campaigns: CampaignRepository,
+    digests: DigestStore,
+    snapshots: RecipientSnapshotStore,
+    senders: SenderStore,
+    verifier: SenderVerifier,
+    mailer: Mailer,
+}
+
+impl Steps {
+    // Resolve the recipients of a campaign the admin API started sending,
+    // snapshot them and split them into batches
+    async fn resolve(&self, input: WorkflowInput) -> Result<ResolveOutput, Error> {
+        let campaign = self
+            .campaigns
+            .get(&input.campaign_id)
+            .await?
+            .ok_or_else(|| format!("Campaign {} not found", input.campaign_id))?;
+        // One cancelled since it started goes on, and its batches are skipped
+        if !matches!(
+            campaign.status,
+            CampaignStatus::Sending | CampaignStatus::Cancelling
+        ) {
+            return Err(format!(
+                "Campaign {} is {}, not sending",
+                campaign.id,
+                campaign.status.as_str()
+            )
+            .into());
+        }
+
+        let recipients = campaign_recipients(&self.subscribers, &campaign).await?;
+        self.snapshots
+            .create(&campaign.id, &recipients, &[])
+            .await?;
+        let batches = plan_batches(&campaign.id, &recipients);
+        info!(
+            "Campaign {} goes to {} subscribers in {} batches",
+            campaign.id,
+            recipients.len(),
+            batches.len()
+        );
+
+        Ok(ResolveOutput {
+            campaign_id: campaign.id,
+            recipients: recipients.len() as u32,
+            batches,
+        })
+    }
+
+    // Send a campaign to one batch of its snapshot. Recipients who stopped
+    // being mailable since it was taken are left out and counted as failed.
+    // Everything that can fail comes before the first email, so a failed batch
+    // is retried without repeating any.
+    async fn send_batch(&self, batch: RecipientBatch) -> Result<BatchResult, Error> {
+        let campaign = self
+            .campaigns
+            .get(&batch.campaign_id)
+            .await?
+            .ok_or_else(|| format!("Campaign {} not found", batch.campaign_id))?;
+        let mailer = campaign_mailer(&self.senders, &self.verifier, &self.mailer, &campaign)
+            .await?
+            .ok_or_else(|| {
+                format!(
+                    "Campaign {} sender identity {:?} is missing or not verified",
+                    campaign.id, campaign.sender_id
+                )
+            })?;
+
+        let ids = self
+            .snapshots
+            .subscriber_ids_between(&campaign.id, &batch.first_id, &batch.last_id)
+            .await?;
+        let mut recipients = Vec::with_capacity(ids.len());
+        for id in &ids {
+            let subscriber = self.subscribers.get(id).await?;
+            recipients.extend(subscriber.filter(|subscriber| subscriber.is_mailable()));
+        }
+
+        let counts = send_campaign_emails(
+            &self.campaigns,
+            Progress::Batch,
+            &mailer,
+            &campaign,
+            &recipients,
+        )
+        .await?;
+        info!(
+            "Campaign {} batch {} sent {}, skipped {}",
+            campaign.id, batch.index, counts.sent, counts.skipped
+        );
+
+        Ok(BatchResult {
+            index: batch.index,
+            sent: counts.sent,
+            skipped: counts.skipped,
+            failed: (ids.len() as u32).saturating_sub(counts.sent + counts.skipped),
+        })
+    }
+
+    // Add up the batches and mark the campaign sent, or cancelled
+    async fn finish(&self, input: FinishInput) -> Result<WorkflowSummary, Error> {
+        let counts = SendCounts {
+            sent: input.results.iter().map(|result| result.sent).sum(),
+            skipped: input.results.iter().map(|result| result.skipped).sum(),
+        };
+        let failed = input.results.iter().map(|result| result.failed).sum();
+        let cancelled =
+            finish_send(&self.campaigns, &self.digests, &input.campaign_id, counts).await?;
+        let status = if cancelled {
+            CampaignStatus::Cancelled
+        } else {
+            CampaignStatus::Sent
+        };
+        info!(
+            "Campaign {} {}: {} sent, {} skipped, {} failed",
+            input.campaign_id,
+            status.as_str(),
+            counts.sent,
+            counts.skipped,
+            failed
+        );
+
+        Ok(WorkflowSummary {
+            campaign_id: input.campaign_id,
+            status: status.as_str().to_string(),
+            sent: counts.sent,
+            skipped: counts.skipped,
+            failed,
+        })
+    }
+}
+
+// One state of the campaign send state machine, see `workflow`. Errors fail
+// the state, which the state machine retries.
+async fn function_handler(event: LambdaEvent<WorkflowStep>) -> Result<Value, Error> {
+    let sender_email = env::var("SENDER_EMAIL")?;
+
+    // Initialize AWS SDK
+    let config = sdk_config().await;
+    let dynamodb_client = dynamodb().await;
+    let steps = Steps {
+        subscribers: SubscriberRepository::new(dynamodb_client.clone()),
+        campaigns: CampaignRepository::new(dynamodb_client.clone()),
+        digests: DigestStore::new(dynamodb_client.clone()),
+        snapshots: RecipientSnapshotStore::new(dynamodb_client.clone()),
+        senders: SenderStore::new(dynamodb_client),
+        verifier: SenderVerifier::new(SesClient::new(config)),
+        mailer: Mailer::new(config, sender_email),
+    };
+
+    let output = match event.payload {
+        WorkflowStep::Resolve(input) => serde_json::to_value(steps.resolve(input).await?)?,
+        WorkflowStep::SendBatch { batch } => serde_json::to_value(steps.send_batch(batch).await?)?,
+        WorkflowStep::Finish(input) => serde_json::to_value(steps.finish(input).await?)?,
+    };
+
+    Ok(output)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
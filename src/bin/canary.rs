@@ -0,0 +1,165 @@
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::api_url;
+use newsletter_backend::clients::{dynamodb, http};
+use newsletter_backend::events::EventRepository;
+use newsletter_backend::history::HistoryRepository;
+use newsletter_backend::metrics::emit_metric;
+use newsletter_backend::repository::SubscriberRepository;
+use serde_json::{Value, json};
+use std::env;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// SES mailbox simulator address: accepted and delivered without touching a real
+// inbox or the sender reputation
+const DEFAULT_CANARY_EMAIL: &str = "success+canary@simulator.amazonses.com";
+
+struct Canary {
+    http: reqwest::Client,
+    api_url: String,
+    email: String,
+    token_timeout: Duration,
+    subscribers: SubscriberRepository,
+    events: EventRepository,
+    history: HistoryRepository,
+}
+
+impl Canary {
+    // Fail unless the endpoint answers with the expected status
+    async fn expect(
+        &self,
+        step: &str,
+        request: reqwest::RequestBuilder,
+        expected: u16,
+    ) -> Result<(), Error> {
+        let response = request.send().await?;
+        let status = response.status().as_u16();
+        if status != expected {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("{step} returned {status} instead of {expected}: {body}").into());
+        }
+        Ok(())
+    }
+
+    // Remove the canary subscriber and everything recorded about it
+    async fn clean_up(&self) -> Result<(), Error> {
+        while let Some(subscriber) = self.subscribers.find_by_email(&self.email).await? {
+            self.events.delete_for_subscriber(&subscriber.id).await?;
+            self.history.delete_for_subscriber(&subscriber.id).await?;
+            self.subscribers.delete(&subscriber.id).await?;
+        }
+        Ok(())
+    }
+
+    // subscribe → validation token → confirm → unsubscribe
+    async fn run(&self) -> Result<(), Error> {
+        self.expect(
+            "subscribe",
+            self.http
+                .post(format!("{}/subscribe", self.api_url))
+                .json(&json!({ "email": self.email, "source": "canary" })),
+            201,
+        )
+        .await?;
+
+        // The token is written by the validate worker, so this also covers the
+        // queue wiring and the email index
+        let started = Instant::now();
+        let (id, token) = loop {
+            if let Some(subscriber) = self.subscribers.find_by_email(&self.email).await?
+                && let Some(token) = subscriber.validation_token
+            {
+                break (subscriber.id, token);
+            }
+            if started.elapsed() > self.token_timeout {
+                return Err("validation token was never generated".into());
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        };
+
+        self.expect(
+            "confirm",
+            self.http
+                .get(format!("{}/confirm", self.api_url))
+                .query(&[("id", id.as_str()), ("token", token.as_str())]),
+            200,
+        )
+        .await?;
+
+        self.expect(
+            "unsubscribe",
+            self.http
+                .post(format!("{}/unsubscribe", self.api_url))
+                .json(&json!({ "email": self.email })),
+            200,
+        )
+        .await?;
+
+        let subscriber = self
+            .subscribers
+            .get(&id)
+            .await?
+            .ok_or("subscriber disappeared")?;
+        if !subscriber.validated || subscriber.active {
+            return Err(format!(
+                "unexpected final state: validated={} active={}",
+                subscriber.validated, subscriber.active
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<(), Error> {
+    // Initialize AWS SDK
+    let dynamodb_client = dynamodb().await;
+
+    let canary = Canary {
+        http: http().clone(),
+        api_url: api_url(),
+        email: env::var("CANARY_EMAIL").unwrap_or_else(|_| DEFAULT_CANARY_EMAIL.to_string()),
+        token_timeout: Duration::from_secs(
+            env::var("CANARY_TOKEN_TIMEOUT_SECONDS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(60),
+        ),
+        subscribers: SubscriberRepository::new(dynamodb_client.clone()),
+        events: EventRepository::new(dynamodb_client.clone()),
+        history: HistoryRepository::new(dynamodb_client),
+    };
+
+    // Leftovers from a run that failed halfway would make subscribe return 200
+    canary.clean_up().await?;
+
+    let started = Instant::now();
+    let result = canary.run().await;
+    let elapsed_ms = started.elapsed().as_millis() as f64;
+
+    canary.clean_up().await?;
+
+    match &result {
+        Ok(()) => info!("Canary passed in {} ms", elapsed_ms),
+        Err(e) => info!("Canary failed: {}", e),
+    }
+    emit_metric(
+        "CanarySuccess",
+        if result.is_ok() { 1.0 } else { 0.0 },
+        "Count",
+        &[],
+    );
+    emit_metric("CanaryDuration", elapsed_ms, "Milliseconds", &[]);
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
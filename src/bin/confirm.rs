@@ -1,11 +1,22 @@
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_dynamodb::Client;
-use aws_sdk_dynamodb::types::AttributeValue;
-use chrono::{DateTime, Utc};
-use lambda_http::{Body, Error, Request, Response, run, service_fn};
-use newsletter_backend::{ApiResponse, TABLE_NAME, create_response};
+use chrono::Utc;
+use lambda_http::{Body, Error, Request, RequestExt, Response, run, service_fn};
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::config::{TokenPolicy, env_or};
+use newsletter_backend::domains::{DomainMetric, DomainStatsStore};
+use newsletter_backend::history::{HistoryRepository, LifecycleEvent, LifecycleEventType};
+use newsletter_backend::i18n::{Locale, translate};
+use newsletter_backend::input::{InputLimits, checked};
+use newsletter_backend::logging::log_request;
+use newsletter_backend::notify::{Notification, notify};
+use newsletter_backend::onboarding::{resolve_referral, send_welcome};
+use newsletter_backend::repository::SubscriberRepository;
+use newsletter_backend::versioning::versioned;
+use newsletter_backend::{
+    ApiResponse, ConfirmData, ConfirmStatus, create_json_response, create_response,
+};
 use serde::{Deserialize, Serialize};
 use tracing::info;
+use url::form_urlencoded;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ConfirmRequest {
@@ -13,169 +24,398 @@ struct ConfirmRequest {
     token: String,
 }
 
-async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .init();
+// Result of checking a confirmation link against the stored subscriber
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    NotFound,
+    AlreadyConfirmed,
+    InvalidToken,
+    Expired,
+    Confirmed,
+}
 
-    // Parse query parameters
-    let query_params = event.uri().query().unwrap_or("");
-    let params: Vec<(String, String)> = query_params
-        .split('&')
-        .filter_map(|pair| {
-            let mut parts = pair.split('=');
-            match (parts.next(), parts.next()) {
-                (Some(key), Some(value)) => Some((key.to_string(), value.to_string())),
-                _ => None,
-            }
-        })
-        .collect();
+// Take the first non-empty value of a possibly repeated parameter
+fn first_non_empty<'a>(values: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    values
+        .into_iter()
+        .map(|value| value.trim())
+        .find(|value| !value.is_empty())
+        .map(|value| value.to_string())
+}
+
+// Read id and token from the (already decoded) query string, falling back to a
+// JSON or form-encoded POST body
+fn parse_confirm_request(event: &Request) -> Option<ConfirmRequest> {
+    let params = event.query_string_parameters();
+
+    if let (Some(id), Some(token)) = (
+        first_non_empty(params.all("id").unwrap_or_default()),
+        first_non_empty(params.all("token").unwrap_or_default()),
+    ) {
+        return Some(ConfirmRequest { id, token });
+    }
 
-    // Extract id and token from query parameters
-    let id = params
-        .iter()
-        .find(|(key, _)| key == "id")
-        .map(|(_, value)| value.clone());
+    let body = match event.body() {
+        Body::Text(text) => text.as_bytes(),
+        Body::Binary(bytes) => bytes.as_slice(),
+        _ => return None,
+    };
+
+    let is_json = event
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("application/json"))
+        .unwrap_or_else(|| body.trim_ascii_start().starts_with(b"{"));
+
+    if is_json {
+        return serde_json::from_slice::<ConfirmRequest>(body)
+            .ok()
+            .filter(|request| !request.id.trim().is_empty() && !request.token.trim().is_empty());
+    }
 
-    let token = params
-        .iter()
-        .find(|(key, _)| key == "token")
-        .map(|(_, value)| value.clone());
+    // Form values are percent-decoded by the parser
+    let form: Vec<(String, String)> = form_urlencoded::parse(body).into_owned().collect();
+    let form_values = |key: &'static str| {
+        form.iter()
+            .filter(move |(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    };
+
+    match (
+        first_non_empty(form_values("id")),
+        first_non_empty(form_values("token")),
+    ) {
+        (Some(id), Some(token)) => Some(ConfirmRequest { id, token }),
+        _ => None,
+    }
+}
+
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    let locale = Locale::from_request(&event);
 
     // Check if id and token are provided
-    let (id, token) = match (id, token) {
-        (Some(id), Some(token)) => (id, token),
-        _ => {
+    let (id, token) = match parse_confirm_request(&event) {
+        Some(request) => (request.id, request.token),
+        None => {
             return Ok(create_response(
                 400,
                 ApiResponse {
                     success: false,
-                    message: "Missing id or token".to_string(),
+                    message: translate(locale, "Missing id or token"),
+                    data: None,
                 },
             ));
         }
     };
 
     // Initialize AWS SDK
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let dynamodb_client = Client::new(&config);
-
-    // Get the subscriber from DynamoDB
-    let get_result = dynamodb_client
-        .get_item()
-        .table_name(TABLE_NAME)
-        .key("id", AttributeValue::S(id.clone()))
-        .send()
-        .await;
+    let config = sdk_config().await;
+    let dynamodb_client = dynamodb().await;
 
-    match get_result {
-        Ok(result) => {
-            if let Some(item) = result.item() {
-                // Check if the subscriber has a validation token
-                if let Some(validation_token) = item.get("validation_token") {
-                    if let Ok(stored_token) = validation_token.as_s() {
-                        // Check if the token matches
-                        if stored_token == &token {
-                            // Check if the token is expired
-                            if let Some(expiration) = item.get("token_expiration") {
-                                if let Ok(expiration_str) = expiration.as_s() {
-                                    if let Ok(expiration_time) =
-                                        DateTime::parse_from_rfc3339(expiration_str)
-                                    {
-                                        let now = Utc::now();
-
-                                        if now < expiration_time.with_timezone(&Utc) {
-                                            // Token is valid, mark the subscriber as validated
-                                            let update_result = dynamodb_client
-                                                .update_item()
-                                                .table_name(TABLE_NAME)
-                                                .key("id", AttributeValue::S(id.clone()))
-                                                .update_expression("SET validated = :validated, updated_at = :updated_at REMOVE validation_token, token_expiration")
-                                                .expression_attribute_values(":validated", AttributeValue::Bool(true))
-                                                .expression_attribute_values(":updated_at", AttributeValue::S(Utc::now().to_rfc3339()))
-                                                .send()
-                                                .await;
-
-                                            match update_result {
-                                                Ok(_) => {
-                                                    // Return a success response
-                                                    return Ok(create_response(
-                                                        200,
-                                                        ApiResponse {
-                                                            success: true,
-                                                            message: "Email successfully validated"
-                                                                .to_string(),
-                                                        },
-                                                    ));
-                                                }
-                                                Err(e) => {
-                                                    info!(
-                                                        "Error updating validation status: {:?}",
-                                                        e
-                                                    );
-                                                    return Ok(create_response(
-                                                        500,
-                                                        ApiResponse {
-                                                            success: false,
-                                                            message: "Failed to validate email"
-                                                                .to_string(),
-                                                        },
-                                                    ));
-                                                }
-                                            }
-                                        } else {
-                                            // Token is expired
-                                            return Ok(create_response(
-                                                400,
-                                                ApiResponse {
-                                                    success: false,
-                                                    message: "Validation token has expired"
-                                                        .to_string(),
-                                                },
-                                            ));
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+    // Links are often opened seconds after signup, before a replica or an
+    // eventually consistent read would see the token
+    let repository = SubscriberRepository::new(dynamodb_client.clone()).consistent();
 
-                // If we get here, the token was invalid or not found
-                return Ok(create_response(
-                    400,
-                    ApiResponse {
-                        success: false,
-                        message: "Invalid validation token".to_string(),
-                    },
-                ));
+    // Decided inside the versioned update, so a concurrent unsubscribe or a newer
+    // token is re-checked against the latest copy of the subscriber
+    let mut outcome = Outcome::NotFound;
+    let mut reactivated = false;
+    let update_result = repository
+        .update(&id, |subscriber| {
+            let now = Utc::now();
+            // An unsubscribed subscriber who signed up again was confirmed
+            // before, but is waiting on this link to be active again
+            reactivated = subscriber.reactivation_requested_at.is_some();
+            outcome = if subscriber.validated && !reactivated {
+                // The token is removed on confirmation, so a second click on the
+                // same link lands here and should still get a friendly answer
+                Outcome::AlreadyConfirmed
+            } else if subscriber.validation_token.as_deref() != Some(token.as_str()) {
+                Outcome::InvalidToken
             } else {
-                // Subscriber not found
-                return Ok(create_response(
-                    404,
-                    ApiResponse {
-                        success: false,
-                        message: "Subscriber not found".to_string(),
-                    },
-                ));
+                match subscriber.token_expiration {
+                    Some(expiration) if now < expiration => Outcome::Confirmed,
+                    Some(_) => Outcome::Expired,
+                    None => Outcome::InvalidToken,
+                }
+            };
+
+            if outcome != Outcome::Confirmed {
+                return false;
+            }
+            subscriber.validated = true;
+            if reactivated {
+                subscriber.active = true;
+                subscriber.reactivation_requested_at = None;
+            }
+            subscriber.validation_token = None;
+            subscriber.token_expiration = None;
+            // Confirmed subscribers are kept
+            subscriber.token_ttl = None;
+            true
+        })
+        .await;
+
+    // A confirm racing another, e.g. the same link opened in two regions of a
+    // Global Table, can run out of retries. If the latest copy is confirmed the
+    // click already did its job.
+    let update_result = match update_result {
+        Err(e) => match repository.get_latest(&id).await {
+            Ok(Some(subscriber)) if subscriber.validated => {
+                info!("Confirm of {} raced another, already confirmed", id);
+                outcome = Outcome::AlreadyConfirmed;
+                Ok(None)
+            }
+            _ => Err(e),
+        },
+        result => result,
+    };
+
+    let (status, success, message, confirmed) = match update_result {
+        Ok(Some(subscriber)) => {
+            let mut confirmed =
+                LifecycleEvent::new(&id, LifecycleEventType::Confirmed).with_source("confirm_link");
+            if reactivated {
+                confirmed = confirmed.with_detail("reactivated");
             }
+            HistoryRepository::new(dynamodb_client.clone())
+                .record_or_log(confirmed)
+                .await;
+            DomainStatsStore::new(dynamodb_client)
+                .record_or_log(&subscriber.email, DomainMetric::Confirmed, Utc::now())
+                .await;
+            // A referral was credited when they first confirmed
+            if !reactivated {
+                resolve_referral(&repository, &id, subscriber.referred_by.as_deref()).await;
+            }
+            notify(Notification::Confirmed {
+                email: subscriber.email.clone(),
+            })
+            .await;
+            if env_or("WELCOME_EMAIL", false) {
+                send_welcome(config, &subscriber).await;
+            }
+            (
+                200,
+                true,
+                "Email successfully validated",
+                Some(ConfirmStatus::Confirmed),
+            )
         }
+        Ok(None) => match outcome {
+            Outcome::AlreadyConfirmed => (
+                200,
+                true,
+                "Your email is already confirmed",
+                Some(ConfirmStatus::AlreadyConfirmed),
+            ),
+            Outcome::Expired => (400, false, "Validation token has expired", None),
+            Outcome::NotFound => (404, false, "Subscriber not found", None),
+            Outcome::InvalidToken | Outcome::Confirmed => {
+                (400, false, "Invalid validation token", None)
+            }
+        },
         Err(e) => {
-            info!("Error getting subscriber: {:?}", e);
-            return Ok(create_response(
-                500,
-                ApiResponse {
-                    success: false,
-                    message: "Failed to retrieve subscriber information".to_string(),
-                },
-            ));
+            info!("Error updating validation status: {:?}", e);
+            (500, false, "Failed to validate email", None)
         }
+    };
+
+    let mut message = translate(locale, message);
+    // Tell the subscriber how long the next link will last, from the same policy
+    // the validate and remind Lambdas issue tokens with
+    if outcome == Outcome::Expired {
+        let validity = translate(locale, "Confirmation links are valid for {hours} hours")
+            .replace("{hours}", &TokenPolicy::from_env().ttl_hours.to_string());
+        message = format!("{message}. {validity}");
     }
+
+    Ok(create_json_response(
+        status,
+        &ApiResponse {
+            success,
+            message,
+            data: confirmed.map(|status| ConfirmData {
+                subscriber_id: id.clone(),
+                status,
+            }),
+        },
+    ))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(service_fn(function_handler)).await
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            versioned(event, |event| {
+                checked(event, InputLimits::PUBLIC, function_handler)
+            })
+        })
+    }))
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn request(query: &[(&str, &str)], content_type: Option<&str>, body: &str) -> Request {
+        let mut params: HashMap<String, Vec<String>> = HashMap::new();
+        for (key, value) in query {
+            params
+                .entry(key.to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+
+        let mut builder = lambda_http::http::Request::builder().method("POST");
+        if let Some(content_type) = content_type {
+            builder = builder.header("Content-Type", content_type);
+        }
+        builder
+            .body(Body::from(body))
+            .unwrap()
+            .with_query_string_parameters(params)
+    }
+
+    fn parsed(event: &Request) -> Option<(String, String)> {
+        parse_confirm_request(event).map(|request| (request.id, request.token))
+    }
+
+    fn pair(id: &str, token: &str) -> Option<(String, String)> {
+        Some((id.to_string(), token.to_string()))
+    }
+
+    #[test]
+    fn first_non_empty_skips_blank_values() {
+        assert_eq!(
+            first_non_empty(["", "  ", " abc "]),
+            Some("abc".to_string())
+        );
+        assert_eq!(first_non_empty(["a", "b"]), Some("a".to_string()));
+        assert_eq!(first_non_empty(["", " "]), None);
+        assert_eq!(first_non_empty([]), None);
+    }
+
+    #[test]
+    fn reads_the_query_string() {
+        let event = request(&[("id", "sub-1"), ("token", "abc")], None, "");
+        assert_eq!(parsed(&event), pair("sub-1", "abc"));
+    }
+
+    #[test]
+    fn repeated_query_keys_use_the_first_non_empty_value() {
+        let event = request(
+            &[
+                ("id", ""),
+                ("id", "sub-1"),
+                ("id", "sub-2"),
+                ("token", "abc"),
+            ],
+            None,
+            "",
+        );
+        assert_eq!(parsed(&event), pair("sub-1", "abc"));
+    }
+
+    #[test]
+    fn query_values_are_not_decoded_again() {
+        // API Gateway already decoded them, so a literal `%2B` is kept
+        let event = request(&[("id", "sub-1"), ("token", "a%2Bb")], None, "");
+        assert_eq!(parsed(&event), pair("sub-1", "a%2Bb"));
+    }
+
+    #[test]
+    fn a_complete_query_wins_over_the_body() {
+        let event = request(
+            &[("id", "query-id"), ("token", "query-token")],
+            Some("application/json"),
+            r#"{"id": "body-id", "token": "body-token"}"#,
+        );
+        assert_eq!(parsed(&event), pair("query-id", "query-token"));
+    }
+
+    #[test]
+    fn an_incomplete_query_falls_back_to_the_body() {
+        let event = request(
+            &[("id", "query-id"), ("token", " ")],
+            Some("application/x-www-form-urlencoded"),
+            "id=body-id&token=body-token",
+        );
+        assert_eq!(parsed(&event), pair("body-id", "body-token"));
+    }
+
+    #[test]
+    fn reads_a_json_body() {
+        let event = request(
+            &[],
+            Some("application/json; charset=utf-8"),
+            r#"{"id": "sub-1", "token": "abc"}"#,
+        );
+        assert_eq!(parsed(&event), pair("sub-1", "abc"));
+    }
+
+    #[test]
+    fn sniffs_json_without_a_content_type() {
+        let event = request(&[], None, r#"  {"id": "sub-1", "token": "abc"}"#);
+        assert_eq!(parsed(&event), pair("sub-1", "abc"));
+    }
+
+    #[test]
+    fn refuses_json_with_blank_or_missing_fields() {
+        let blank = request(
+            &[],
+            Some("application/json"),
+            r#"{"id": "sub-1", "token": " "}"#,
+        );
+        assert_eq!(parsed(&blank), None);
+
+        let missing = request(&[], Some("application/json"), r#"{"id": "sub-1"}"#);
+        assert_eq!(parsed(&missing), None);
+    }
+
+    #[test]
+    fn json_is_not_read_as_a_form() {
+        let event = request(&[], Some("application/json"), "id=sub-1&token=abc");
+        assert_eq!(parsed(&event), None);
+    }
+
+    #[test]
+    fn decodes_percent_encoded_form_values() {
+        let event = request(
+            &[],
+            Some("application/x-www-form-urlencoded"),
+            "id=sub%2D1&token=a%2Bb%3D%3D",
+        );
+        assert_eq!(parsed(&event), pair("sub-1", "a+b=="));
+    }
+
+    #[test]
+    fn repeated_and_empty_form_keys_use_the_first_non_empty_value() {
+        let event = request(
+            &[],
+            Some("application/x-www-form-urlencoded"),
+            "id=&id=sub-1&id=sub-2&token=&=orphan&token=abc",
+        );
+        assert_eq!(parsed(&event), pair("sub-1", "abc"));
+    }
+
+    #[test]
+    fn refuses_an_empty_request() {
+        assert_eq!(parsed(&request(&[], None, "")), None);
+        assert_eq!(
+            parsed(&request(
+                &[],
+                Some("application/x-www-form-urlencoded"),
+                "id=sub-1"
+            )),
+            None
+        );
+    }
 }
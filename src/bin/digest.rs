@@ -0,0 +1,138 @@
+use chrono::{Duration, Utc};
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::campaign::{Campaign, CampaignStatus};
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::config::env_or;
+use newsletter_backend::digest::{DigestStore, Frequency, digest_id, render_digest};
+use newsletter_backend::email_queue::{EmailKind, Mailer};
+use newsletter_backend::events::{pixel_tracking_enabled, with_open_tracking};
+use newsletter_backend::repository::{CampaignRepository, SubscriberRepository};
+use newsletter_backend::segment::Segment;
+use newsletter_backend::signing;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use tracing::info;
+
+// Send each weekly subscriber one email with the campaigns sent since their last
+// digest, honouring each campaign's segment
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<(), Error> {
+    // Posts older than this aren't sent in a digest, e.g. after a long pause
+    let max_age_days = env_or("DIGEST_MAX_AGE_DAYS", 14);
+    let sender_email = env::var("SENDER_EMAIL")?;
+    // Posts link to their web version and unsubscribe links, which need signing
+    signing::require_secret()?;
+
+    // Initialize AWS SDK
+    let config = sdk_config().await;
+    let dynamodb_client = dynamodb().await;
+    let subscribers = SubscriberRepository::new(dynamodb_client.clone());
+    let campaigns = CampaignRepository::new(dynamodb_client.clone());
+    let digests = DigestStore::new(dynamodb_client);
+    let mailer = Mailer::new(config, sender_email);
+
+    let now = Utc::now();
+    let oldest = now - Duration::days(max_age_days);
+
+    // Posts of the period, with their campaign and parsed segment
+    let mut posts = Vec::new();
+    for item in digests.added_since(oldest).await? {
+        let Some(campaign) = campaigns.get(&item.campaign_id).await? else {
+            continue;
+        };
+        if campaign.status != CampaignStatus::Sent {
+            continue;
+        }
+        let segment = match campaign.segment.as_deref().map(Segment::parse).transpose() {
+            Ok(segment) => segment,
+            Err(e) => {
+                info!(
+                    "Skipping campaign {} with invalid segment: {:?}",
+                    campaign.id, e
+                );
+                continue;
+            }
+        };
+        posts.push((item.added_at, campaign, segment));
+    }
+
+    info!("Found {} posts for the digest", posts.len());
+    if posts.is_empty() {
+        return Ok(());
+    }
+
+    let digest_id = digest_id(now);
+    let pixel_tracking = pixel_tracking_enabled();
+    let mut sent = 0;
+    let mut skipped: HashMap<&str, u32> = HashMap::new();
+
+    for subscriber in subscribers.list_all().await? {
+        if !subscriber.is_mailable() || subscriber.frequency != Frequency::Weekly {
+            continue;
+        }
+
+        let since = subscriber.digest_sent_at.unwrap_or(subscriber.created_at);
+        let included: Vec<&Campaign> = posts
+            .iter()
+            .filter(|(added_at, _, _)| *added_at > since)
+            .filter(|(_, campaign, _)| subscriber.is_mailable_on(campaign.list.as_deref()))
+            .filter(|(_, _, segment)| {
+                segment
+                    .as_ref()
+                    .map(|segment| segment.matches(&subscriber, now))
+                    .unwrap_or(true)
+            })
+            .map(|(_, campaign, _)| campaign)
+            .collect();
+        if included.is_empty() {
+            *skipped.entry("no_posts").or_default() += 1;
+            continue;
+        }
+
+        let (subject, html_body) = render_digest(&subscriber, &included);
+        let html_body = if pixel_tracking {
+            with_open_tracking(&html_body, &subscriber.id, &digest_id).unwrap_or_else(|e| {
+                info!("Error adding open tracking: {:?}", e);
+                html_body
+            })
+        } else {
+            html_body
+        };
+
+        match mailer
+            .send(
+                EmailKind::Digest,
+                &subscriber,
+                Some(&digest_id),
+                &subject,
+                &html_body,
+            )
+            .await
+        {
+            Ok(_) => {
+                subscribers.set_digest_sent_at(&subscriber.id, now).await?;
+                sent += 1;
+            }
+            Err(e) => {
+                info!(
+                    "Failed to send digest to subscriber {}: {:?}",
+                    subscriber.id, e
+                );
+                *skipped.entry("send_failed").or_default() += 1;
+            }
+        }
+    }
+
+    info!("Sent {} digests, skipped {:?}", sent, skipped);
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
@@ -0,0 +1,29 @@
+use lambda_http::{Body, Error, Request, Response, run, service_fn};
+use newsletter_backend::api_url;
+use newsletter_backend::embed::embed_script;
+use newsletter_backend::input::{InputLimits, checked};
+use newsletter_backend::logging::log_request;
+
+// Serves the embeddable signup form script
+async fn function_handler(_event: Request) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "application/javascript; charset=utf-8")
+        .header("Cache-Control", "public, max-age=300")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(Body::from(embed_script(&api_url())))?)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            checked(event, InputLimits::PUBLIC, function_handler)
+        })
+    }))
+    .await
+}
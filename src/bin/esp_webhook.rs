@@ -0,0 +1,185 @@
+use chrono::Utc;
+use lambda_http::{Body, Error, Request, Response, run, service_fn};
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::esp::{EspEvent, Provider, verify_basic_auth, verify_sendgrid};
+use newsletter_backend::ingest::EventIngest;
+use newsletter_backend::input::{InputLimits, checked};
+use newsletter_backend::logging::log_request;
+use newsletter_backend::{ApiResponse, create_response};
+use std::env;
+use tracing::info;
+
+fn header<'a>(event: &'a Request, name: &str) -> &'a str {
+    event
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+}
+
+// Check the request came from the provider. None when the provider's secret
+// isn't configured.
+fn verify(provider: Provider, event: &Request, payload: &[u8]) -> Option<bool> {
+    let name = match provider {
+        Provider::SendGrid => "SENDGRID_WEBHOOK_PUBLIC_KEY",
+        Provider::Postmark => "POSTMARK_WEBHOOK_CREDENTIALS",
+    };
+    let secret = env::var(name).ok().filter(|secret| !secret.is_empty())?;
+
+    Some(match provider {
+        Provider::SendGrid => verify_sendgrid(
+            payload,
+            header(event, "X-Twilio-Email-Event-Webhook-Signature"),
+            header(event, "X-Twilio-Email-Event-Webhook-Timestamp"),
+            &secret,
+            Utc::now().timestamp(),
+        ),
+        Provider::Postmark => verify_basic_auth(header(event, "Authorization"), &secret),
+    })
+}
+
+// The subscriber an event belongs to: the id the sender attached, or the
+// subscriber with the recipient's address
+async fn subscriber_id(
+    ingest: &EventIngest,
+    esp_event: &EspEvent,
+) -> Result<Option<String>, newsletter_backend::Error> {
+    if let Some(id) = &esp_event.subscriber_id {
+        return Ok(Some(id.clone()));
+    }
+    Ok(ingest
+        .subscribers()
+        .find_by_email(&esp_event.email)
+        .await?
+        .map(|subscriber| subscriber.id))
+}
+
+// Receive bounce, complaint, delivery, open and click events from ESPs other
+// than SES at /webhooks/{provider}, and apply them like SES events
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    let Some(provider) = event
+        .uri()
+        .path()
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .and_then(Provider::parse)
+    else {
+        return Ok(create_response(
+            404,
+            ApiResponse {
+                success: false,
+                message: "Unknown provider".to_string(),
+                data: None,
+            },
+        ));
+    };
+
+    // Signatures cover the raw body, so verify before parsing anything
+    let payload: &[u8] = match event.body() {
+        Body::Text(text) => text.as_bytes(),
+        Body::Binary(bytes) => bytes.as_slice(),
+        _ => &[],
+    };
+    match verify(provider, &event, payload) {
+        Some(true) => {}
+        Some(false) => {
+            return Ok(create_response(
+                401,
+                ApiResponse {
+                    success: false,
+                    message: "Invalid signature".to_string(),
+                    data: None,
+                },
+            ));
+        }
+        None => {
+            info!("No webhook secret configured for {}", provider.as_str());
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Webhook is not configured".to_string(),
+                    data: None,
+                },
+            ));
+        }
+    }
+
+    let esp_events = match provider.parse_events(payload) {
+        Ok(esp_events) => esp_events,
+        Err(e) => {
+            info!("Error parsing {} webhook: {:?}", provider.as_str(), e);
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: "Invalid event payload".to_string(),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    // Initialize AWS SDK
+    let ingest = EventIngest::new(dynamodb().await);
+
+    let mut processed = 0;
+    for esp_event in &esp_events {
+        // Errors return 500 so the provider retries the delivery
+        let result = match subscriber_id(&ingest, esp_event).await {
+            Ok(Some(id)) => ingest
+                .ingest(
+                    &esp_event.to_event(&id),
+                    Some(&esp_event.email),
+                    esp_event.permanent,
+                    provider.as_str(),
+                )
+                .await
+                .map(|_| processed += 1),
+            Ok(None) => {
+                info!(
+                    "Ignoring {} {} event, no subscriber for its recipient",
+                    provider.as_str(),
+                    esp_event.event_type.as_str()
+                );
+                Ok(())
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            info!("Error processing {} event: {:?}", provider.as_str(), e);
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to process event".to_string(),
+                    data: None,
+                },
+            ));
+        }
+    }
+
+    Ok(create_response(
+        200,
+        ApiResponse {
+            success: true,
+            message: format!("Processed {} of {} events", processed, esp_events.len()),
+            data: None,
+        },
+    ))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            checked(event, InputLimits::WEBHOOK, function_handler)
+        })
+    }))
+    .await
+}
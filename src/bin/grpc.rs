@@ -0,0 +1,620 @@
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_sesv2::Client as SesClient;
+use chrono::Utc;
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use newsletter_backend::admin::{ApiKey, find_api_key};
+use newsletter_backend::audit::{AuditEntry, AuditStore};
+use newsletter_backend::campaign::{Campaign, Exclusions};
+use newsletter_backend::cursor::Cursor;
+use newsletter_backend::delivery::{
+    PreparedSend, SendGuards, SendRefusal, SendRequest, deliver_campaign, prepare_send,
+};
+use newsletter_backend::digest::DigestStore;
+use newsletter_backend::domains::{DomainMetric, DomainStatsStore};
+use newsletter_backend::email_queue::Mailer;
+use newsletter_backend::history::{HistoryRepository, LifecycleEvent, LifecycleEventType};
+use newsletter_backend::notify::{Notification, notify};
+use newsletter_backend::opt_out;
+use newsletter_backend::quota::QuotaStore;
+use newsletter_backend::rbac::Operation;
+use newsletter_backend::repository::{CampaignRepository, SubscriberRepository};
+use newsletter_backend::segment::Segment;
+use newsletter_backend::senders::{SenderStore, SenderVerifier};
+use newsletter_backend::templates::TemplateStore;
+use newsletter_backend::{Error, Subscriber};
+use std::env;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tonic::transport::Server;
+use tonic::{Code, Request, Response, Status};
+use tracing::info;
+
+pub mod proto {
+    tonic::include_proto!("newsletter.v1");
+}
+
+use proto::campaign_service_server::{CampaignService, CampaignServiceServer};
+use proto::subscriber_service_server::{SubscriberService, SubscriberServiceServer};
+
+// Calls must carry one of the admin API keys in the `x-api-key` metadata
+#[allow(clippy::result_large_err)]
+fn check_api_key(mut request: Request<()>) -> Result<Request<()>, Status> {
+    let api_key = request
+        .metadata()
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .and_then(find_api_key)
+        .ok_or_else(|| Status::unauthenticated("Missing or unknown API key"))?;
+
+    request.extensions_mut().insert(api_key);
+    Ok(request)
+}
+
+// The calling key's role must allow the operation, see `Role::allows`
+#[allow(clippy::result_large_err)]
+fn authorize<T>(request: &Request<T>, operation: Operation) -> Result<(), Status> {
+    match request.extensions().get::<ApiKey>() {
+        Some(api_key) if api_key.role.allows(operation) => Ok(()),
+        Some(api_key) => Err(Status::permission_denied(format!(
+            "The {} role is not allowed to {}",
+            api_key.role.as_str(),
+            operation.as_str()
+        ))),
+        None => Err(Status::unauthenticated("Missing or unknown API key")),
+    }
+}
+
+// Histogram buckets in seconds, from a cached DynamoDB read to a campaign send
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+// Count and time an RPC, labelled with its method and status code
+async fn observed<T>(
+    method: &'static str,
+    call: impl Future<Output = Result<Response<T>, Status>>,
+) -> Result<Response<T>, Status> {
+    let started = Instant::now();
+    let result = call.await;
+    let code = match &result {
+        Ok(_) => Code::Ok,
+        Err(status) => status.code(),
+    };
+
+    counter!("grpc_requests_total", 1, "method" => method, "code" => format!("{code:?}"));
+    if code != Code::Ok {
+        counter!("grpc_errors_total", 1, "method" => method, "code" => format!("{code:?}"));
+    }
+    histogram!(
+        "grpc_request_duration_seconds",
+        started.elapsed().as_secs_f64(),
+        "method" => method
+    );
+
+    result
+}
+
+// Time a repository call, labelled with the store and operation
+async fn dynamodb<T>(
+    operation: &'static str,
+    call: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let started = Instant::now();
+    let result = call.await;
+    histogram!(
+        "dynamodb_request_duration_seconds",
+        started.elapsed().as_secs_f64(),
+        "operation" => operation
+    );
+    if result.is_err() {
+        counter!("dynamodb_errors_total", 1, "operation" => operation);
+    }
+    result
+}
+
+fn internal(context: &str, err: Error) -> Status {
+    info!("{}: {:?}", context, err);
+    Status::internal(context)
+}
+
+fn send_refused(refusal: SendRefusal) -> Status {
+    match refusal {
+        SendRefusal::NotFound => Status::not_found(refusal.to_string()),
+        SendRefusal::NotConfigured => Status::internal(refusal.to_string()),
+        SendRefusal::QuotaExceeded(status) => Status::resource_exhausted(format!(
+            "Monthly send quota exceeded, {} of {} left until {}",
+            status.remaining(),
+            status.limit,
+            status.reset_at.to_rfc3339()
+        )),
+        refusal => Status::failed_precondition(refusal.to_string()),
+    }
+}
+
+// RPCs that change something are audited like admin API mutations, with the
+// status code mapped to its HTTP equivalent
+fn audit_entry<T>(
+    api_key_id: &str,
+    rpc: &str,
+    id: &str,
+    result: &Result<Response<T>, Status>,
+) -> AuditEntry {
+    let status = match result.as_ref().map_err(Status::code) {
+        Ok(_) => 200,
+        Err(Code::InvalidArgument) => 400,
+        Err(Code::Unauthenticated) => 401,
+        Err(Code::PermissionDenied) => 403,
+        Err(Code::NotFound) => 404,
+        Err(Code::FailedPrecondition) => 409,
+        Err(Code::ResourceExhausted) => 429,
+        Err(_) => 500,
+    };
+    AuditEntry::new(
+        api_key_id,
+        "GRPC",
+        &format!("/newsletter.v1.{rpc}"),
+        Some(&format!("id={id}")),
+        status,
+    )
+}
+
+// Id of the key the interceptor attached, for audit entries
+fn api_key_id<T>(request: &Request<T>) -> String {
+    request
+        .extensions()
+        .get::<ApiKey>()
+        .map(|api_key| api_key.id.clone())
+        .unwrap_or_default()
+}
+
+fn to_proto_subscriber(subscriber: Subscriber) -> proto::Subscriber {
+    proto::Subscriber {
+        engagement_score: subscriber.engagement_score_at(Utc::now()),
+        id: subscriber.id,
+        email: subscriber.email,
+        active: subscriber.active,
+        validated: subscriber.validated,
+        suppressed: subscriber.suppressed,
+        created_at: subscriber.created_at.to_rfc3339(),
+        updated_at: subscriber.updated_at.to_rfc3339(),
+        locale: subscriber.locale.as_str().to_string(),
+        tier: subscriber.tier.as_str().to_string(),
+        fields: subscriber.fields,
+        referral_code: subscriber.referral_code,
+        referral_count: subscriber.referral_count,
+    }
+}
+
+fn to_proto_campaign(campaign: Campaign) -> proto::Campaign {
+    proto::Campaign {
+        id: campaign.id,
+        subject: campaign.subject,
+        html_body: campaign.html_body,
+        segment: campaign.segment,
+        status: campaign.status.as_str().to_string(),
+        sent_count: campaign.sent_count,
+        skipped_count: campaign.skipped_count,
+        created_at: campaign.created_at.to_rfc3339(),
+        updated_at: campaign.updated_at.to_rfc3339(),
+        sent_at: campaign.sent_at.map(|sent_at| sent_at.to_rfc3339()),
+        snapshot_count: campaign.snapshot_count,
+        exclusions: Some(proto::Exclusions {
+            tags: campaign.exclusions.tags,
+            segment: campaign.exclusions.segment,
+            emails: campaign.exclusions.emails,
+        }),
+    }
+}
+
+struct SubscriberApi {
+    subscribers: Arc<SubscriberRepository>,
+    history: HistoryRepository,
+    domains: DomainStatsStore,
+    audit: AuditStore,
+}
+
+#[tonic::async_trait]
+impl SubscriberService for SubscriberApi {
+    async fn get_subscriber(
+        &self,
+        request: Request<proto::GetSubscriberRequest>,
+    ) -> Result<Response<proto::Subscriber>, Status> {
+        observed("GetSubscriber", async move {
+            authorize(&request, Operation::ReadSubscribers)?;
+            let id = request.into_inner().id;
+            match dynamodb("subscribers.get", self.subscribers.get(&id)).await {
+                Ok(Some(subscriber)) => Ok(Response::new(to_proto_subscriber(subscriber))),
+                Ok(None) => Err(Status::not_found("Subscriber not found")),
+                Err(e) => Err(internal("Failed to get subscriber", e)),
+            }
+        })
+        .await
+    }
+
+    async fn find_subscriber_by_email(
+        &self,
+        request: Request<proto::FindSubscriberByEmailRequest>,
+    ) -> Result<Response<proto::Subscriber>, Status> {
+        observed("FindSubscriberByEmail", async move {
+            authorize(&request, Operation::ReadSubscribers)?;
+            let email = request.into_inner().email;
+            match dynamodb(
+                "subscribers.find_by_email",
+                self.subscribers.find_by_email(&email),
+            )
+            .await
+            {
+                Ok(Some(subscriber)) => Ok(Response::new(to_proto_subscriber(subscriber))),
+                Ok(None) => Err(Status::not_found("Subscriber not found")),
+                Err(e) => Err(internal("Failed to find subscriber", e)),
+            }
+        })
+        .await
+    }
+
+    async fn list_subscribers(
+        &self,
+        request: Request<proto::ListSubscribersRequest>,
+    ) -> Result<Response<proto::ListSubscribersResponse>, Status> {
+        observed("ListSubscribers", async move {
+            authorize(&request, Operation::ReadSubscribers)?;
+            let request = request.into_inner();
+            let limit = if request.limit == 0 {
+                50
+            } else {
+                request.limit
+            }
+            .clamp(1, 500);
+
+            let segment = request
+                .segment
+                .as_deref()
+                .map(Segment::parse)
+                .transpose()
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+            let start_key = request
+                .cursor
+                .as_deref()
+                .map(Cursor::decode)
+                .transpose()
+                .map_err(|_| Status::invalid_argument("Invalid cursor"))?
+                .map(Cursor::into_key);
+
+            let (page, next_key) = dynamodb(
+                "subscribers.list_page",
+                self.subscribers.list_page(limit, start_key),
+            )
+            .await
+            .map_err(|e| internal("Failed to list subscribers", e))?;
+
+            let now = Utc::now();
+            let subscribers = page
+                .into_iter()
+                .filter(|subscriber| {
+                    segment
+                        .as_ref()
+                        .map(|segment| segment.matches(subscriber, now))
+                        .unwrap_or(true)
+                })
+                .map(to_proto_subscriber)
+                .collect();
+
+            let next_cursor = next_key
+                .map(|key| Cursor::from_key(key).encode())
+                .transpose()
+                .map_err(|e| internal("Failed to encode cursor", e))?;
+
+            Ok(Response::new(proto::ListSubscribersResponse {
+                subscribers,
+                next_cursor,
+            }))
+        })
+        .await
+    }
+
+    async fn unsubscribe(
+        &self,
+        request: Request<proto::UnsubscribeRequest>,
+    ) -> Result<Response<proto::UnsubscribeResponse>, Status> {
+        observed("Unsubscribe", async move {
+            authorize(&request, Operation::WriteSubscribers)?;
+            let api_key_id = api_key_id(&request);
+            let id = request.into_inner().id;
+
+            let result = async {
+                let subscriber =
+                    dynamodb("subscribers.unsubscribe", self.subscribers.unsubscribe(&id))
+                        .await
+                        .map_err(|e| internal("Failed to unsubscribe", e))?;
+                let event =
+                    LifecycleEvent::new(&id, LifecycleEventType::Unsubscribed).with_source("grpc");
+                opt_out::publish(&event, &subscriber.email).await;
+                self.history.record_or_log(event).await;
+                self.domains
+                    .record_or_log(&subscriber.email, DomainMetric::Unsubscribed, Utc::now())
+                    .await;
+                notify(Notification::Unsubscribed {
+                    email: subscriber.email.clone(),
+                })
+                .await;
+
+                Ok(Response::new(proto::UnsubscribeResponse {}))
+            }
+            .await;
+
+            self.audit
+                .record_or_log(audit_entry(
+                    &api_key_id,
+                    "SubscriberService/Unsubscribe",
+                    &id,
+                    &result,
+                ))
+                .await;
+            result
+        })
+        .await
+    }
+
+    async fn suppress(
+        &self,
+        request: Request<proto::SuppressRequest>,
+    ) -> Result<Response<proto::SuppressResponse>, Status> {
+        observed("Suppress", async move {
+            authorize(&request, Operation::WriteSubscribers)?;
+            let api_key_id = api_key_id(&request);
+            let id = request.into_inner().id;
+
+            let result = async {
+                let subscriber = dynamodb("subscribers.suppress", self.subscribers.suppress(&id))
+                    .await
+                    .map_err(|e| internal("Failed to suppress subscriber", e))?
+                    .ok_or_else(|| Status::not_found("Subscriber not found"))?;
+                let event =
+                    LifecycleEvent::new(&id, LifecycleEventType::Suppressed).with_source("grpc");
+                opt_out::publish(&event, &subscriber.email).await;
+                self.history.record_or_log(event).await;
+
+                Ok(Response::new(proto::SuppressResponse {}))
+            }
+            .await;
+
+            self.audit
+                .record_or_log(audit_entry(
+                    &api_key_id,
+                    "SubscriberService/Suppress",
+                    &id,
+                    &result,
+                ))
+                .await;
+            result
+        })
+        .await
+    }
+}
+
+struct CampaignApi {
+    subscribers: Arc<SubscriberRepository>,
+    campaigns: CampaignRepository,
+    digests: DigestStore,
+    senders: SenderStore,
+    verifier: SenderVerifier,
+    templates: TemplateStore,
+    quotas: QuotaStore,
+    audit: AuditStore,
+    // Sending is disabled when SENDER_EMAIL isn't set
+    mailer: Option<Mailer>,
+}
+
+#[tonic::async_trait]
+impl CampaignService for CampaignApi {
+    async fn get_campaign(
+        &self,
+        request: Request<proto::GetCampaignRequest>,
+    ) -> Result<Response<proto::Campaign>, Status> {
+        observed("GetCampaign", async move {
+            authorize(&request, Operation::ReadCampaigns)?;
+            let id = request.into_inner().id;
+            match dynamodb("campaigns.get", self.campaigns.get(&id)).await {
+                Ok(Some(campaign)) => Ok(Response::new(to_proto_campaign(campaign))),
+                Ok(None) => Err(Status::not_found("Campaign not found")),
+                Err(e) => Err(internal("Failed to get campaign", e)),
+            }
+        })
+        .await
+    }
+
+    async fn list_campaigns(
+        &self,
+        request: Request<proto::ListCampaignsRequest>,
+    ) -> Result<Response<proto::ListCampaignsResponse>, Status> {
+        observed("ListCampaigns", async move {
+            authorize(&request, Operation::ReadCampaigns)?;
+            let campaigns = dynamodb("campaigns.list_all", self.campaigns.list_all())
+                .await
+                .map_err(|e| internal("Failed to list campaigns", e))?;
+
+            Ok(Response::new(proto::ListCampaignsResponse {
+                campaigns: campaigns.into_iter().map(to_proto_campaign).collect(),
+            }))
+        })
+        .await
+    }
+
+    async fn create_campaign(
+        &self,
+        request: Request<proto::CreateCampaignRequest>,
+    ) -> Result<Response<proto::Campaign>, Status> {
+        observed("CreateCampaign", async move {
+            authorize(&request, Operation::WriteCampaigns)?;
+            let request = request.into_inner();
+
+            // Reject invalid segments now rather than at send time
+            if let Some(segment) = &request.segment {
+                Segment::parse(segment).map_err(|e| Status::invalid_argument(e.to_string()))?;
+            }
+
+            let exclusions = request
+                .exclusions
+                .map(|exclusions| Exclusions {
+                    tags: exclusions.tags,
+                    segment: exclusions.segment,
+                    emails: exclusions.emails,
+                })
+                .unwrap_or_default()
+                .normalized()
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+            let mut campaign = Campaign::new(request.subject, request.html_body, request.segment);
+            campaign.exclusions = exclusions;
+            dynamodb("campaigns.put", self.campaigns.put(&campaign))
+                .await
+                .map_err(|e| internal("Failed to create campaign", e))?;
+
+            Ok(Response::new(to_proto_campaign(campaign)))
+        })
+        .await
+    }
+
+    async fn send_campaign(
+        &self,
+        request: Request<proto::SendCampaignRequest>,
+    ) -> Result<Response<proto::SendCampaignResponse>, Status> {
+        observed("SendCampaign", async move {
+            authorize(&request, Operation::SendCampaigns)?;
+            let api_key = request
+                .extensions()
+                .get::<ApiKey>()
+                .cloned()
+                .ok_or_else(|| Status::unauthenticated("Missing or unknown API key"))?;
+            let request = request.into_inner();
+            let id = request.id;
+
+            let result = async {
+                let mailer = self
+                    .mailer
+                    .as_ref()
+                    .ok_or_else(|| Status::failed_precondition("SENDER_EMAIL is not configured"))?;
+
+                // The same checks as the admin API, see `delivery::prepare_send`
+                let guards = SendGuards {
+                    subscribers: &self.subscribers,
+                    campaigns: &self.campaigns,
+                    templates: &self.templates,
+                    senders: &self.senders,
+                    verifier: &self.verifier,
+                    quotas: &self.quotas,
+                };
+                let send = SendRequest {
+                    campaign_id: &id,
+                    api_key: Some(&api_key),
+                    resume: true,
+                    check_links: !request.skip_link_check,
+                };
+                let PreparedSend {
+                    campaign,
+                    mailer,
+                    recipients,
+                    ..
+                } = prepare_send(&guards, mailer, send)
+                    .await
+                    .map_err(|e| internal("Failed to prepare the send", e))?
+                    .map_err(send_refused)?;
+
+                let counts = deliver_campaign(
+                    &self.campaigns,
+                    &self.digests,
+                    &mailer,
+                    &campaign,
+                    &recipients,
+                )
+                .await
+                .map_err(|e| internal("Failed to send campaign", e))?;
+                let sent_count = counts.sent;
+
+                counter!("newsletter_emails_sent_total", u64::from(sent_count), "kind" => "campaign");
+                info!(
+                    "Key {} sent campaign {} to {} subscribers over gRPC",
+                    api_key.id, campaign.id, sent_count
+                );
+
+                Ok(Response::new(proto::SendCampaignResponse { sent_count }))
+            }
+            .await;
+
+            self.audit
+                .record_or_log(audit_entry(&api_key.id, "CampaignService/SendCampaign", &id, &result))
+                .await;
+            result
+        })
+        .await
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let address = env::var("GRPC_ADDR")
+        .unwrap_or_else(|_| "0.0.0.0:50051".to_string())
+        .parse()?;
+
+    // Initialize AWS SDK
+    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+    let config = aws_config::from_env().region(region_provider).load().await;
+    let dynamodb_client = Client::new(&config);
+
+    let subscribers = Arc::new(SubscriberRepository::new(dynamodb_client.clone()));
+    let subscriber_api = SubscriberApi {
+        subscribers: subscribers.clone(),
+        history: HistoryRepository::new(dynamodb_client.clone()),
+        domains: DomainStatsStore::new(dynamodb_client.clone()),
+        audit: AuditStore::new(dynamodb_client.clone()),
+    };
+    let campaign_api = CampaignApi {
+        subscribers,
+        campaigns: CampaignRepository::new(dynamodb_client.clone()),
+        digests: DigestStore::new(dynamodb_client.clone()),
+        senders: SenderStore::new(dynamodb_client.clone()),
+        templates: TemplateStore::new(dynamodb_client.clone()),
+        quotas: QuotaStore::new(dynamodb_client.clone()),
+        audit: AuditStore::new(dynamodb_client),
+        verifier: SenderVerifier::new(SesClient::new(&config)),
+        mailer: env::var("SENDER_EMAIL")
+            .ok()
+            .map(|sender_email| Mailer::new(&config, sender_email)),
+    };
+
+    // Prometheus metrics on METRICS_ADDR, unless it's set empty
+    let metrics_address = env::var("METRICS_ADDR").unwrap_or_else(|_| "0.0.0.0:9090".to_string());
+    if !metrics_address.is_empty() {
+        let metrics_address: SocketAddr = metrics_address.parse()?;
+        PrometheusBuilder::new()
+            .set_buckets(LATENCY_BUCKETS)?
+            .with_http_listener(metrics_address)
+            .install()?;
+        info!("Serving metrics on {}/metrics", metrics_address);
+    }
+
+    info!("Starting gRPC server on {}", address);
+
+    Server::builder()
+        .add_service(SubscriberServiceServer::with_interceptor(
+            subscriber_api,
+            check_api_key,
+        ))
+        .add_service(CampaignServiceServer::with_interceptor(
+            campaign_api,
+            check_api_key,
+        ))
+        .serve(address)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use lambda_http::{Body, Error, Request, Response, run, service_fn};
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::create_json_response;
+use newsletter_backend::health::{BuildInfo, check_health};
+use newsletter_backend::input::{InputLimits, checked};
+use newsletter_backend::logging::log_request;
+
+// GET /health checks the DynamoDB tables and indexes, GET /version only reports
+// the build, without touching AWS
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    if event
+        .uri()
+        .path()
+        .trim_end_matches('/')
+        .ends_with("/version")
+    {
+        return Ok(create_json_response(200, &BuildInfo::current()));
+    }
+
+    // Initialize AWS SDK
+    let dynamodb_client = dynamodb().await;
+
+    let health = check_health(&dynamodb_client).await;
+    let status = if health.is_healthy() { 200 } else { 503 };
+
+    Ok(create_json_response(status, &health))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            checked(event, InputLimits::PUBLIC, function_handler)
+        })
+    }))
+    .await
+}
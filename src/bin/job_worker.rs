@@ -0,0 +1,262 @@
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_sqs::Client as SqsClient;
+use chrono::Utc;
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::Subscriber;
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::export::{ExportParams, ExportStore};
+use newsletter_backend::history::{HistoryRepository, LifecycleEvent, LifecycleEventType};
+use newsletter_backend::jobs::{Job, JobKind, JobPageMessage, JobProgress, JobQueue, JobStore};
+use newsletter_backend::metrics::emit_metric;
+use newsletter_backend::queue::{SqsBatchResponse, SqsEvent, SqsRecord, decode};
+use newsletter_backend::repository::SubscriberRepository;
+use newsletter_backend::segment::Segment;
+use newsletter_backend::tagging::{TagAction, TagParams, apply_tag};
+use std::env;
+use tracing::info;
+
+struct Worker {
+    repository: SubscriberRepository,
+    history: HistoryRepository,
+    jobs: JobStore,
+    queue: JobQueue,
+    // From BACKUP_BUCKET, needed by export jobs
+    exports: Option<ExportStore>,
+}
+
+// What handling a page amounted to: its counts, and where its output went
+struct PageOutcome {
+    progress: JobProgress,
+    result_location: Option<String>,
+}
+
+impl Worker {
+    // Handle one page of a job, queue the next page and save the progress.
+    // Returns false when the record should go back to the queue.
+    async fn process(&self, record: &SqsRecord) -> Result<bool, Error> {
+        let message: JobPageMessage = decode(&record.body)?;
+        let Some(job) = self.jobs.get(&message.job_id).await? else {
+            info!("Job {} not found, dropping its page", message.job_id);
+            return Ok(true);
+        };
+        if job.status.is_finished() || job.progress.pages > message.page {
+            info!(
+                "Skipping page {} of job {}, already handled or the job is {}",
+                message.page,
+                job.id,
+                job.status.as_str()
+            );
+            return Ok(true);
+        }
+        // The page before hasn't been saved yet
+        if job.progress.pages < message.page {
+            return Ok(false);
+        }
+
+        let (subscribers, last_key) = self
+            .repository
+            .list_page(job.kind.page_size(), message.start_key())
+            .await?;
+
+        let outcome = match job.kind {
+            JobKind::Tag => self.tag_page(&job, &subscribers).await,
+            JobKind::Export => self.export_page(&job, message.page, &subscribers).await,
+        };
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            Err(JobError::Permanent(error)) => {
+                info!("Job {} failed: {}", job.id, error);
+                self.jobs.mark_failed(&job.id, &error).await?;
+                return Ok(true);
+            }
+            Err(JobError::Retry(e)) => return Err(e),
+        };
+
+        // The next page goes out before this one is saved, so a failure in
+        // between repeats this page rather than stalling the job. Pages are
+        // safe to repeat and a repeat isn't counted.
+        let next_id = last_key
+            .as_ref()
+            .and_then(|key| key.get("id"))
+            .and_then(|id| id.as_s().ok())
+            .cloned();
+        if let Some(next_id) = &next_id {
+            self.queue.send(&message.next(next_id.clone())).await?;
+        }
+
+        let last = next_id.is_none();
+        let recorded = self
+            .jobs
+            .record_page(
+                &job.id,
+                message.page,
+                outcome.progress,
+                last,
+                outcome.result_location.as_deref(),
+            )
+            .await?;
+        if recorded {
+            emit_metric(
+                "JobItemsProcessed",
+                outcome.progress.processed as f64,
+                "Count",
+                &[("Kind", job.kind.as_str())],
+            );
+            if last {
+                info!("Job {} completed", job.id);
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Add or remove the job's tag on the page's subscribers in its segment
+    async fn tag_page(
+        &self,
+        job: &Job,
+        subscribers: &[Subscriber],
+    ) -> Result<PageOutcome, JobError> {
+        let params: TagParams = job.params().map_err(JobError::permanent)?;
+        let matching = matching(&params.segment, subscribers)?;
+
+        let mut progress = JobProgress {
+            scanned: subscribers.len() as u32,
+            matched: matching.len() as u32,
+            ..JobProgress::default()
+        };
+        for subscriber in matching {
+            // Re-checked on the latest copy, as the scan may be behind
+            let updated = self
+                .repository
+                .update(&subscriber.id, |subscriber| {
+                    apply_tag(subscriber, &params.tag, params.action)
+                })
+                .await
+                .map_err(JobError::Retry)?;
+            if updated.is_none() {
+                continue;
+            }
+            progress.processed += 1;
+
+            let event_type = match params.action {
+                TagAction::Add => LifecycleEventType::TagAdded,
+                TagAction::Remove => LifecycleEventType::TagRemoved,
+            };
+            self.history
+                .record_or_log(
+                    LifecycleEvent::new(&subscriber.id, event_type)
+                        .with_source("bulk_tag")
+                        .with_detail(params.tag.clone()),
+                )
+                .await;
+        }
+
+        Ok(PageOutcome {
+            progress,
+            result_location: None,
+        })
+    }
+
+    // Write the page's subscribers in the job's segment to a CSV file
+    async fn export_page(
+        &self,
+        job: &Job,
+        page: u32,
+        subscribers: &[Subscriber],
+    ) -> Result<PageOutcome, JobError> {
+        let Some(exports) = &self.exports else {
+            return Err(JobError::Permanent(
+                "BACKUP_BUCKET is not configured".to_string(),
+            ));
+        };
+        let params: ExportParams = job.params().map_err(JobError::permanent)?;
+        let matching = match &params.segment {
+            Some(segment) => matching(segment, subscribers)?,
+            None => subscribers.iter().collect(),
+        };
+
+        // Pages without anyone to export leave no file behind
+        if !matching.is_empty() {
+            exports
+                .write_page(&job.id, page, &matching)
+                .await
+                .map_err(JobError::Retry)?;
+        }
+
+        Ok(PageOutcome {
+            progress: JobProgress {
+                scanned: subscribers.len() as u32,
+                matched: matching.len() as u32,
+                processed: matching.len() as u32,
+                ..JobProgress::default()
+            },
+            result_location: Some(exports.location(&job.id)),
+        })
+    }
+}
+
+enum JobError {
+    // Retrying can't help, so the job is marked failed
+    Permanent(String),
+    // The page goes back to the queue
+    Retry(Error),
+}
+
+impl JobError {
+    fn permanent(error: Error) -> Self {
+        JobError::Permanent(error.to_string())
+    }
+}
+
+// The subscribers of a page in a segment
+fn matching<'a>(
+    segment: &str,
+    subscribers: &'a [Subscriber],
+) -> Result<Vec<&'a Subscriber>, JobError> {
+    let segment = Segment::parse(segment).map_err(JobError::permanent)?;
+    let now = Utc::now();
+    Ok(subscribers
+        .iter()
+        .filter(|subscriber| segment.matches(subscriber, now))
+        .collect())
+}
+
+async fn function_handler(event: LambdaEvent<SqsEvent>) -> Result<SqsBatchResponse, Error> {
+    let queue_url = env::var("JOB_QUEUE_URL")?;
+
+    // Initialize AWS SDK
+    let config = sdk_config().await;
+    let dynamodb_client = dynamodb().await;
+    let worker = Worker {
+        repository: SubscriberRepository::new(dynamodb_client.clone()),
+        history: HistoryRepository::new(dynamodb_client.clone()),
+        jobs: JobStore::new(dynamodb_client),
+        queue: JobQueue::new(SqsClient::new(config), queue_url),
+        exports: env::var("BACKUP_BUCKET")
+            .ok()
+            .map(|bucket| ExportStore::new(S3Client::new(config), bucket)),
+    };
+
+    let mut response = SqsBatchResponse::default();
+    for record in event.payload.records {
+        match worker.process(&record).await {
+            Ok(true) => {}
+            Ok(false) => response.fail(record.message_id),
+            Err(e) => {
+                info!("Error processing job page {}: {:?}", record.message_id, e);
+                response.fail(record.message_id);
+            }
+        }
+    }
+
+    Ok(response)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
@@ -0,0 +1,800 @@
+use aws_config::SdkConfig;
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_sesv2::Client as SesClient;
+use aws_sdk_sqs::Client as SqsClient;
+use chrono::{Duration, Utc};
+use clap::{Parser, Subcommand};
+use newsletter_backend::campaign::{Campaign, Exclusions, normalize_list};
+use newsletter_backend::dedup::{find_duplicates, merge_into};
+use newsletter_backend::delivery::{
+    PreparedSend, SendGuards, SendRefusal, SendRequest, deliver_campaign, prepare_send,
+};
+use newsletter_backend::digest::DigestStore;
+use newsletter_backend::email_queue::Mailer;
+use newsletter_backend::export::{EXPORT_COLUMNS, export_record};
+use newsletter_backend::history::{HistoryRepository, LifecycleEvent, LifecycleEventType};
+use newsletter_backend::import::{
+    ImportFormat, ImportReport, ImportStatus, existing_subscribers, import_emails, plan_import,
+};
+use newsletter_backend::notify::{Notification, Notifier};
+use newsletter_backend::openapi::openapi_json;
+use newsletter_backend::opt_out::{self, OptOut, OptOutWebhook};
+use newsletter_backend::pseudonym::{DEFAULT_MIN_DOMAIN_COUNT, Pseudonymizer};
+use newsletter_backend::queue::ValidationQueue;
+use newsletter_backend::quota::QuotaStore;
+use newsletter_backend::redrive::Redriver;
+use newsletter_backend::repository::{CampaignRepository, SubscriberRepository};
+use newsletter_backend::segment::Segment;
+use newsletter_backend::senders::{SenderStore, SenderVerifier};
+use newsletter_backend::templates::TemplateStore;
+use newsletter_backend::{Error, Subscriber};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Parser)]
+#[command(
+    name = "newsletterctl",
+    about = "Day-to-day operations for the newsletter backend"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Inspect and manage subscribers
+    #[command(subcommand)]
+    Subscribers(SubscribersCommand),
+    /// Import subscribers from a CSV file with an `email` column or a provider export
+    Import {
+        file: PathBuf,
+        /// Layout of the file: generic, mailchimp, buttondown or substack
+        #[arg(long, default_value = "generic")]
+        format: ImportFormat,
+        /// Mark imported subscribers as already validated
+        #[arg(long)]
+        validated: bool,
+        /// Report what the import would do without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Match rows against one scan of the whole table instead of looking
+        /// up their addresses, cheaper when the file is about as big as it
+        #[arg(long)]
+        scan: bool,
+    },
+    /// Export all subscribers as CSV
+    Export {
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Replace emails with stable pseudonymous ids and leave out anything
+        /// identifying, for analysts and BI tools
+        #[arg(long)]
+        pseudonymize: bool,
+        /// Secret keying the pseudonymous ids, at least 32 characters
+        #[arg(long, env = "PSEUDONYM_SECRET", hide_env_values = true)]
+        pseudonym_secret: Option<String>,
+        /// Custom field to keep in a pseudonymized export, can be repeated
+        #[arg(long = "field")]
+        fields: Vec<String>,
+        /// Domains with fewer subscribers show as "other" in a pseudonymized export
+        #[arg(long, default_value_t = DEFAULT_MIN_DOMAIN_COUNT)]
+        min_domain_count: usize,
+    },
+    /// Suppress an email address so it never receives mail again
+    Suppress { email: String },
+    /// Create and send campaigns
+    #[command(subcommand)]
+    Campaign(CampaignCommand),
+    /// Work with the validation queue
+    #[command(subcommand)]
+    Queue(QueueCommand),
+    /// Write the OpenAPI document generated from the API types
+    Openapi {
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum QueueCommand {
+    /// Re-enqueue retryable messages from the validation dead-letter queue and
+    /// archive the rest to S3
+    Redrive {
+        #[arg(long, env = "VALIDATION_QUEUE_URL")]
+        queue_url: String,
+        #[arg(long, env = "VALIDATION_DLQ_URL")]
+        dlq_url: String,
+        /// Bucket the unretryable messages and the report are archived to
+        #[arg(long, env = "BACKUP_BUCKET")]
+        bucket: String,
+        /// Stop after this many messages
+        #[arg(long, default_value_t = 1000)]
+        limit: usize,
+        /// Classify the messages without moving, archiving or deleting them
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SubscribersCommand {
+    /// List subscribers
+    List {
+        #[arg(long, default_value_t = 50)]
+        limit: i32,
+    },
+    /// Show the subscriber for an email address
+    Find { email: String },
+    /// Delete the subscriber for an email address, purged after the retention window
+    Delete {
+        email: String,
+        /// Remove the record immediately instead of marking it deleted
+        #[arg(long)]
+        hard: bool,
+    },
+    /// Restore a deleted subscriber that hasn't been purged yet
+    Restore { id: String },
+    /// Permanently delete subscribers who never confirmed their email
+    PurgeUnconfirmed {
+        /// Only purge subscribers created more than this many days ago
+        #[arg(long, default_value_t = 30)]
+        older_than_days: i64,
+        /// Print how many would be purged without deleting them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Rewrite every subscriber so records written before the search indexes are found by them
+    Reindex,
+    /// Merge records sharing an email address into the oldest one and delete the rest
+    Dedup {
+        /// Report the merges without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Encrypt subscribers stored in the clear or under a previous PII_KMS_KEY_ID
+    Reencrypt {
+        /// Count the subscribers to re-encrypt without writing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CampaignCommand {
+    /// Create a draft campaign from an HTML file, or from a saved template
+    Create {
+        #[arg(long, required_unless_present = "template")]
+        subject: Option<String>,
+        #[arg(long, required_unless_present = "template")]
+        html: Option<PathBuf>,
+        /// Use the template's published version at send time instead of --subject and --html
+        #[arg(long, conflicts_with_all = ["subject", "html"])]
+        template: Option<String>,
+        /// Only send to subscribers matching this segment, e.g. "engagement_score >= 2"
+        #[arg(long)]
+        segment: Option<String>,
+        /// Send on this list, e.g. "product-updates", skipping subscribers who left it
+        #[arg(long)]
+        list: Option<String>,
+        /// Leave out subscribers with this tag, can be repeated
+        #[arg(long = "exclude-tag")]
+        exclude_tags: Vec<String>,
+        /// Leave out subscribers matching this segment
+        #[arg(long)]
+        exclude_segment: Option<String>,
+        /// Leave out the addresses in this file, one per line
+        #[arg(long)]
+        exclude_emails: Option<PathBuf>,
+        /// Send from this sender identity instead of SENDER_EMAIL
+        #[arg(long)]
+        sender: Option<String>,
+    },
+    /// List all campaigns
+    List,
+    /// Send a draft campaign to every mailable subscriber
+    Send {
+        id: String,
+        #[arg(long, env = "SENDER_EMAIL")]
+        from: String,
+        /// Send even if some links in the campaign are broken
+        #[arg(long)]
+        skip_link_check: bool,
+    },
+}
+
+fn print_subscriber(subscriber: &Subscriber) {
+    println!(
+        "{}\t{}\tactive={}\tvalidated={}\tsuppressed={}\tengagement={:.2}\tcreated_at={}",
+        subscriber.id,
+        subscriber.email,
+        subscriber.active,
+        subscriber.validated,
+        subscriber.suppressed,
+        subscriber.engagement_score_at(Utc::now()),
+        subscriber.created_at.to_rfc3339()
+    );
+}
+
+async fn import(
+    repository: &SubscriberRepository,
+    history: &HistoryRepository,
+    file: PathBuf,
+    format: ImportFormat,
+    validated: bool,
+    dry_run: bool,
+    scan: bool,
+) -> Result<(), Error> {
+    let existing = if scan {
+        repository.list_including_deleted().await?
+    } else {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(&file)?;
+        existing_subscribers(repository, &import_emails(&mut reader, format)?).await?
+    };
+
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(file)?;
+    let plan = plan_import(&mut reader, format, existing)?;
+
+    if dry_run {
+        print_import_report(&plan.report);
+        return Ok(());
+    }
+
+    let mut subscribers = Vec::new();
+    let mut statuses = Vec::new();
+    for mut row in plan.rows {
+        if validated && row.status == ImportStatus::Unconfirmed {
+            row.status = ImportStatus::Subscribed;
+        }
+        statuses.push(row.status);
+        subscribers.push(row.into_subscriber(format));
+    }
+
+    let imported = repository.put_many(&subscribers).await?;
+
+    // Backdated to the original signup, followed by how the subscription ended
+    let mut events = Vec::new();
+    let mut notifications = Vec::new();
+    let mut opt_outs = Vec::new();
+    for (subscriber, status) in subscribers.iter().zip(statuses) {
+        let mut subscribed = LifecycleEvent::new(&subscriber.id, LifecycleEventType::Subscribed)
+            .with_source("import")
+            .with_detail(format.as_str());
+        subscribed.occurred_at = subscriber.created_at;
+        events.push(subscribed);
+
+        let email = subscriber.email.clone();
+        match status {
+            ImportStatus::Subscribed => notifications.push(Notification::Confirmed { email }),
+            ImportStatus::Unsubscribed => notifications.push(Notification::Unsubscribed { email }),
+            _ => {}
+        }
+
+        let ended = match status {
+            ImportStatus::Unsubscribed => LifecycleEventType::Unsubscribed,
+            ImportStatus::Cleaned => LifecycleEventType::Suppressed,
+            _ => continue,
+        };
+        let ended = LifecycleEvent::new(&subscriber.id, ended).with_source("import");
+        opt_outs.extend(OptOut::new(&ended, &subscriber.email));
+        events.push(ended);
+    }
+    if let Err(e) = history.record_many(&events).await {
+        info!("Error recording import history: {:?}", e);
+    }
+    // Summarized past NOTIFY_BATCH_THRESHOLD so a big import is one message
+    if let Some(notifier) = Notifier::from_env() {
+        notifier.notify_batch("Import", &notifications).await;
+    }
+    // Other tools should stop contacting addresses the old provider had opted out
+    if let Some(webhook) = OptOutWebhook::from_env() {
+        for opt_out in &opt_outs {
+            webhook.send_or_log(opt_out).await;
+        }
+    }
+
+    let report = &plan.report;
+    println!(
+        "Imported {imported} ({} deleted before), skipped {} existing, {} suppressed, {} invalid",
+        report.reactivated, report.duplicates, report.suppressed, report.invalid
+    );
+    Ok(())
+}
+
+fn print_import_report(report: &ImportReport) {
+    println!("New:         {}", report.new);
+    println!("Duplicates:  {}", report.duplicates);
+    println!("Invalid:     {}", report.invalid);
+    println!("Suppressed:  {}", report.suppressed);
+    println!("Reactivated: {}", report.reactivated);
+    for row in &report.samples {
+        println!(
+            "  line {}: {} ({})",
+            row.line,
+            row.email,
+            row.outcome.as_str()
+        );
+    }
+}
+
+async fn export(repository: &SubscriberRepository, output: Option<PathBuf>) -> Result<(), Error> {
+    let writer: Box<dyn io::Write> = match output {
+        Some(path) => Box::new(fs::File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(writer);
+
+    writer.write_record(EXPORT_COLUMNS)?;
+    for subscriber in repository.list_all().await? {
+        writer.write_record(export_record(&subscriber)?)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+// Export without emails, ids or anything else identifying, see `Pseudonymizer`
+async fn export_pseudonymized(
+    repository: &SubscriberRepository,
+    pseudonymizer: &Pseudonymizer,
+    output: Option<PathBuf>,
+) -> Result<(), Error> {
+    let writer: Box<dyn io::Write> = match output {
+        Some(path) => Box::new(fs::File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for row in pseudonymizer.rows(&repository.list_all().await?)? {
+        writer.serialize(row)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+async fn purge_unconfirmed(
+    repository: &SubscriberRepository,
+    older_than_days: i64,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let cutoff = Utc::now() - Duration::days(older_than_days);
+
+    // Suppressed records are kept so the address can't be subscribed again
+    let unconfirmed: Vec<Subscriber> = repository
+        .list_all()
+        .await?
+        .into_iter()
+        .filter(|subscriber| {
+            !subscriber.validated && !subscriber.suppressed && subscriber.created_at < cutoff
+        })
+        .collect();
+
+    if dry_run {
+        println!("Would purge {} unconfirmed subscribers", unconfirmed.len());
+        return Ok(());
+    }
+
+    let purged = repository.delete_many(&unconfirmed).await?;
+    println!("Purged {purged} unconfirmed subscribers");
+    Ok(())
+}
+
+// Search index keys are derived on every write, so a versioned no-op update
+// is enough to add them to older records
+async fn reindex(repository: &SubscriberRepository) -> Result<(), Error> {
+    let subscribers = repository.list_all().await?;
+    let mut reindexed = 0;
+    for subscriber in &subscribers {
+        if repository.update(&subscriber.id, |_| true).await?.is_some() {
+            reindexed += 1;
+        }
+    }
+    println!("Reindexed {reindexed} subscribers");
+    Ok(())
+}
+
+async fn reencrypt(repository: &SubscriberRepository, dry_run: bool) -> Result<(), Error> {
+    let ids = repository.needing_encryption().await?;
+    if dry_run {
+        println!("Would re-encrypt {} subscribers", ids.len());
+        return Ok(());
+    }
+
+    let mut reencrypted = 0;
+    for id in &ids {
+        if repository.reencrypt(id).await? {
+            reencrypted += 1;
+        }
+    }
+    println!("Re-encrypted {reencrypted} subscribers");
+    Ok(())
+}
+
+// Merge duplicate records of one address into the oldest, printing one line
+// per address with the records removed and the attributes the kept one gained
+async fn dedup(
+    repository: &SubscriberRepository,
+    history: &HistoryRepository,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let groups = find_duplicates(repository.list_all().await?);
+    let mut removed = 0;
+
+    for mut group in groups {
+        let duplicate_ids = group.duplicate_ids();
+
+        let changed = if dry_run {
+            merge_into(&mut group.keep, &group.duplicates, Utc::now())
+        } else {
+            // Merged into a fresh read, so writes since the scan aren't lost
+            let mut found = false;
+            let mut changed = Vec::new();
+            repository
+                .update(&group.keep.id, |subscriber| {
+                    found = true;
+                    changed = merge_into(subscriber, &group.duplicates, Utc::now());
+                    !changed.is_empty()
+                })
+                .await?;
+            if !found {
+                info!("Skipping {}, its kept record disappeared", group.email);
+                continue;
+            }
+
+            removed += repository.delete_many(&group.duplicates).await?;
+            history
+                .record_or_log(
+                    LifecycleEvent::new(&group.keep.id, LifecycleEventType::Merged)
+                        .with_source("newsletterctl")
+                        .with_detail(duplicate_ids.join(",")),
+                )
+                .await;
+            changed
+        };
+
+        println!(
+            "{}	kept={}	removed={}	changed={}",
+            group.email,
+            group.keep.id,
+            duplicate_ids.join(","),
+            if changed.is_empty() {
+                "-".to_string()
+            } else {
+                changed.join(",")
+            }
+        );
+    }
+
+    if dry_run {
+        println!("Dry run, nothing was changed");
+    } else {
+        println!("Removed {removed} duplicate subscribers");
+    }
+    Ok(())
+}
+
+async fn send_campaign(
+    config: &SdkConfig,
+    subscribers: &SubscriberRepository,
+    campaigns: &CampaignRepository,
+    digests: &DigestStore,
+    from: String,
+    id: &str,
+    skip_link_check: bool,
+) -> Result<(), Error> {
+    // A stalled send is resumed from its checkpoint. Sends from the CLI don't
+    // count towards any key's quota.
+    let guards = SendGuards {
+        subscribers,
+        campaigns,
+        templates: &TemplateStore::new(Client::new(config)),
+        senders: &SenderStore::new(Client::new(config)),
+        verifier: &SenderVerifier::new(SesClient::new(config)),
+        quotas: &QuotaStore::new(Client::new(config)),
+    };
+    let request = SendRequest {
+        campaign_id: id,
+        api_key: None,
+        resume: true,
+        check_links: !skip_link_check,
+    };
+    let PreparedSend {
+        campaign,
+        mailer,
+        recipients,
+        content_warnings,
+        ..
+    } = match prepare_send(&guards, &Mailer::new(config, from), request).await? {
+        Ok(prepared) => prepared,
+        Err(SendRefusal::BrokenLinks(report)) => {
+            for link in &report.broken {
+                eprintln!("Broken link: {}", link.url);
+            }
+            return Err(format!("Campaign {id}: {}", SendRefusal::BrokenLinks(report)).into());
+        }
+        Err(refusal) => return Err(format!("Campaign {id}: {refusal}").into()),
+    };
+    for warning in &content_warnings {
+        eprintln!("Warning: {}", warning.message);
+    }
+
+    let counts = deliver_campaign(campaigns, digests, &mailer, &campaign, &recipients).await?;
+
+    if counts.cancelled() {
+        println!(
+            "Campaign {id} was cancelled after {} emails, {} skipped",
+            counts.sent, counts.skipped
+        );
+    } else {
+        println!("Sent campaign {id} to {} subscribers", counts.sent);
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Log to stderr so exports written to stdout stay clean
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_writer(io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+
+    // Initialize AWS SDK
+    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+    let config = aws_config::from_env().region(region_provider).load().await;
+    let dynamodb_client = Client::new(&config);
+
+    let subscribers = SubscriberRepository::new(dynamodb_client.clone());
+    let campaigns = CampaignRepository::new(dynamodb_client.clone());
+    let digests = DigestStore::new(dynamodb_client.clone());
+    let senders = SenderStore::new(dynamodb_client.clone());
+    let templates = TemplateStore::new(dynamodb_client.clone());
+    let history = HistoryRepository::new(dynamodb_client);
+
+    match cli.command {
+        Command::Subscribers(SubscribersCommand::List { limit }) => {
+            let (page, next_key) = subscribers.list_page(limit, None).await?;
+            page.iter().for_each(print_subscriber);
+            if next_key.is_some() {
+                println!("(more subscribers available, use `export` for the full list)");
+            }
+        }
+        Command::Subscribers(SubscribersCommand::Find { email }) => {
+            match subscribers.find_by_email(&email).await? {
+                Some(subscriber) => print_subscriber(&subscriber),
+                None => println!("No subscriber found for {email}"),
+            }
+        }
+        Command::Subscribers(SubscribersCommand::Delete { email, hard }) => {
+            match subscribers.find_by_email(&email).await? {
+                Some(subscriber) if hard => {
+                    subscribers.delete(&subscriber.id).await?;
+                    println!("Permanently deleted subscriber {}", subscriber.id);
+                }
+                Some(subscriber) => {
+                    subscribers.soft_delete(&subscriber.id).await?;
+                    history
+                        .record_or_log(
+                            LifecycleEvent::new(&subscriber.id, LifecycleEventType::Deleted)
+                                .with_source("newsletterctl"),
+                        )
+                        .await;
+                    println!("Deleted subscriber {}", subscriber.id);
+                }
+                None => println!("No subscriber found for {email}"),
+            }
+        }
+        Command::Subscribers(SubscribersCommand::Restore { id }) => {
+            match subscribers.restore(&id).await? {
+                Some(subscriber) => {
+                    history
+                        .record_or_log(
+                            LifecycleEvent::new(&subscriber.id, LifecycleEventType::Restored)
+                                .with_source("newsletterctl"),
+                        )
+                        .await;
+                    println!(
+                        "Restored subscriber {} ({})",
+                        subscriber.id, subscriber.email
+                    );
+                }
+                None => println!("No subscriber found with id {id}"),
+            }
+        }
+        Command::Subscribers(SubscribersCommand::PurgeUnconfirmed {
+            older_than_days,
+            dry_run,
+        }) => purge_unconfirmed(&subscribers, older_than_days, dry_run).await?,
+        Command::Subscribers(SubscribersCommand::Reindex) => reindex(&subscribers).await?,
+        Command::Subscribers(SubscribersCommand::Reencrypt { dry_run }) => {
+            reencrypt(&subscribers, dry_run).await?
+        }
+        Command::Subscribers(SubscribersCommand::Dedup { dry_run }) => {
+            dedup(&subscribers, &history, dry_run).await?
+        }
+        Command::Import {
+            file,
+            format,
+            validated,
+            dry_run,
+            scan,
+        } => {
+            import(
+                &subscribers,
+                &history,
+                file,
+                format,
+                validated,
+                dry_run,
+                scan,
+            )
+            .await?
+        }
+        Command::Export {
+            output,
+            pseudonymize,
+            pseudonym_secret,
+            fields,
+            min_domain_count,
+        } => {
+            if pseudonymize {
+                let secret =
+                    pseudonym_secret.ok_or("--pseudonym-secret or PSEUDONYM_SECRET is required")?;
+                let pseudonymizer = Pseudonymizer::new(&secret, fields, min_domain_count)?;
+                export_pseudonymized(&subscribers, &pseudonymizer, output).await?
+            } else {
+                export(&subscribers, output).await?
+            }
+        }
+        Command::Suppress { email } => match subscribers.find_by_email(&email).await? {
+            Some(subscriber) => {
+                subscribers.suppress(&subscriber.id).await?;
+                let event = LifecycleEvent::new(&subscriber.id, LifecycleEventType::Suppressed)
+                    .with_source("newsletterctl");
+                opt_out::publish(&event, &subscriber.email).await;
+                history.record_or_log(event).await;
+                println!("Suppressed {email}");
+            }
+            None => {
+                // Keep a suppressed record so the address can't be subscribed later
+                let mut subscriber = Subscriber::new(email.clone());
+                subscriber.active = false;
+                subscriber.suppressed = true;
+                subscribers.put(&subscriber).await?;
+                let event = LifecycleEvent::new(&subscriber.id, LifecycleEventType::Suppressed)
+                    .with_source("newsletterctl");
+                opt_out::publish(&event, &subscriber.email).await;
+                history.record_or_log(event).await;
+                println!("Suppressed {email} (no existing subscriber)");
+            }
+        },
+        Command::Campaign(CampaignCommand::Create {
+            subject,
+            html,
+            template,
+            segment,
+            list,
+            exclude_tags,
+            exclude_segment,
+            exclude_emails,
+            sender,
+        }) => {
+            // Reject invalid segments now rather than at send time
+            if let Some(segment) = &segment {
+                Segment::parse(segment)?;
+            }
+            let exclusions = Exclusions {
+                tags: exclude_tags,
+                segment: exclude_segment,
+                emails: match exclude_emails {
+                    Some(path) => fs::read_to_string(path)?
+                        .lines()
+                        .map(str::to_string)
+                        .collect(),
+                    None => Vec::new(),
+                },
+            }
+            .normalized()?;
+            if let Some(sender) = &sender {
+                senders
+                    .get(sender)
+                    .await?
+                    .ok_or_else(|| format!("Sender identity {sender} not found"))?;
+            }
+            // Template campaigns get their content when they're sent
+            let (subject, html_body) = match (&template, subject, html) {
+                (Some(template), ..) => {
+                    let version = templates.published(template).await?.ok_or_else(|| {
+                        format!("Template {template} is missing or has no published version")
+                    })?;
+                    (version.subject, version.html_body)
+                }
+                (None, Some(subject), Some(html)) => (subject, fs::read_to_string(html)?),
+                _ => return Err("--subject and --html are required without --template".into()),
+            };
+            let mut campaign = Campaign::new(subject, html_body, segment);
+            campaign.list = list.as_deref().and_then(normalize_list);
+            campaign.exclusions = exclusions;
+            campaign.sender_id = sender;
+            campaign.template_id = template;
+            campaigns.put(&campaign).await?;
+            println!("Created draft campaign {}", campaign.id);
+        }
+        Command::Campaign(CampaignCommand::List) => {
+            for campaign in campaigns.list_all().await? {
+                println!(
+                    "{}\t{}\t{}\tsent_count={}",
+                    campaign.id,
+                    campaign.status.as_str(),
+                    campaign.subject,
+                    campaign.sent_count
+                );
+            }
+        }
+        Command::Campaign(CampaignCommand::Send {
+            id,
+            from,
+            skip_link_check,
+        }) => {
+            send_campaign(
+                &config,
+                &subscribers,
+                &campaigns,
+                &digests,
+                from,
+                &id,
+                skip_link_check,
+            )
+            .await?;
+        }
+        Command::Queue(QueueCommand::Redrive {
+            queue_url,
+            dlq_url,
+            bucket,
+            limit,
+            dry_run,
+        }) => {
+            let redriver = Redriver {
+                sqs: SqsClient::new(&config),
+                s3: S3Client::new(&config),
+                repository: subscribers,
+                queue: ValidationQueue::new(SqsClient::new(&config), queue_url),
+                dlq_url,
+                bucket,
+                prefix: "dlq/validation".to_string(),
+            };
+            let report = redriver.run(limit, dry_run).await?;
+
+            for (class, count) in &report.counts {
+                println!("{}\t{}", class.as_str(), count);
+            }
+            println!(
+                "Requeued {}, archived {}{}",
+                report.requeued,
+                report.archived,
+                report
+                    .archive_key
+                    .map(|key| format!(" to s3://{}/{}", redriver.bucket, key))
+                    .unwrap_or_default()
+            );
+        }
+        Command::Openapi { output } => {
+            let document = openapi_json()?;
+            match output {
+                Some(path) => fs::write(path, document)?,
+                None => println!("{document}"),
+            }
+        }
+    }
+
+    Ok(())
+}
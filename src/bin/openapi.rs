@@ -0,0 +1,27 @@
+use lambda_http::{Body, Error, Request, Response, run, service_fn};
+use newsletter_backend::input::{InputLimits, checked};
+use newsletter_backend::logging::log_request;
+use newsletter_backend::openapi::openapi_json;
+
+// Serves the OpenAPI document generated from the request and response types
+async fn function_handler(_event: Request) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .header("Cache-Control", "public, max-age=300")
+        .body(Body::from(openapi_json()?))?)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            checked(event, InputLimits::PUBLIC, function_handler)
+        })
+    }))
+    .await
+}
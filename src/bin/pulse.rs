@@ -0,0 +1,55 @@
+use chrono::{Duration, Utc};
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::counters::CounterStore;
+use newsletter_backend::domains::DomainStatsStore;
+use newsletter_backend::email::EmailSender;
+use newsletter_backend::pulse::{Pulse, render_pulse};
+use serde_json::Value;
+use std::env;
+use tracing::info;
+
+// Email the newsletter's owner yesterday's confirmations, unsubscribes and
+// bounces from the domain stats, along with the current subscriber count
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<(), Error> {
+    let Some(recipient) = env::var("PULSE_EMAIL")
+        .ok()
+        .filter(|email| !email.is_empty())
+    else {
+        info!("PULSE_EMAIL not set, skipping the daily pulse");
+        return Ok(());
+    };
+    let sender_email = env::var("SENDER_EMAIL")?;
+
+    // Initialize AWS SDK
+    let config = sdk_config().await;
+    let dynamodb_client = dynamodb().await;
+    let counters = CounterStore::new(dynamodb_client.clone());
+    let domain_stats = DomainStatsStore::new(dynamodb_client);
+
+    // Domain stats are kept per UTC day, so the last complete one is reported
+    let day = (Utc::now() - Duration::days(1)).date_naive();
+    let stats = domain_stats.day_totals(day).await?;
+    let pulse = Pulse::new(day, &stats, &counters.counts().await?);
+
+    let (subject, html_body) = render_pulse(&pulse);
+    EmailSender::new(config, sender_email)
+        .send_report(&recipient, &subject, &html_body)
+        .await?;
+
+    info!(
+        "Sent the pulse for {}: {} confirmed, {} unsubscribed, {} bounces, {} total",
+        day, pulse.confirmed, pulse.unsubscribed, pulse.bounces, pulse.total
+    );
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
@@ -0,0 +1,47 @@
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::counters::{CounterStore, tally};
+use newsletter_backend::metrics::emit_metric;
+use newsletter_backend::repository::SubscriberRepository;
+use serde_json::Value;
+use tracing::info;
+
+// Recount subscribers from a full scan once a night and correct the maintained
+// counters, catching drift from TTL purges, restores and counter updates that
+// failed after a batch write
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<(), Error> {
+    // Initialize AWS SDK
+    let dynamodb_client = dynamodb().await;
+    let subscribers = SubscriberRepository::new(dynamodb_client.clone());
+    let counters = CounterStore::new(dynamodb_client);
+
+    let expected = tally(&subscribers.list_all().await?);
+    let corrections = counters.reconcile(&expected).await?;
+
+    for (counter, drift) in &corrections {
+        info!("Corrected counter {} by {}", counter, drift);
+    }
+    emit_metric(
+        "SubscriberCounterDrift",
+        corrections.values().map(|drift| drift.abs()).sum::<i64>() as f64,
+        "Count",
+        &[],
+    );
+
+    info!(
+        "Reconcile run complete: {} counters checked, {} corrected",
+        expected.len(),
+        corrections.len()
+    );
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
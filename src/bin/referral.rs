@@ -0,0 +1,100 @@
+use lambda_http::{Body, Error, Request, RequestExt, Response, run, service_fn};
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::email::referral_key;
+use newsletter_backend::i18n::{Locale, translate};
+use newsletter_backend::input::{InputLimits, checked};
+use newsletter_backend::logging::log_request;
+use newsletter_backend::referral::{ReferralResponse, generate_code};
+use newsletter_backend::repository::SubscriberRepository;
+use newsletter_backend::versioning::versioned;
+use newsletter_backend::{ApiResponse, create_json_response, create_response, signing};
+use tracing::info;
+
+// Referral code, link and milestone progress for a subscriber, authenticated with
+// the signed link from `{{ referral_status_url }}`
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    let locale = Locale::from_request(&event);
+    let params = event.query_string_parameters();
+
+    let id = match (params.first("id"), params.first("token")) {
+        (Some(id), Some(token)) if signing::verify(&referral_key(id), token) => id.to_string(),
+        _ => {
+            return Ok(create_response(
+                401,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Invalid referral link"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    // Initialize AWS SDK
+    let repository = SubscriberRepository::new(dynamodb().await);
+
+    let subscriber = match repository.get(&id).await {
+        Ok(Some(subscriber)) if subscriber.validated => subscriber,
+        Ok(_) => {
+            return Ok(create_response(
+                404,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Confirmed subscriber not found"),
+                    data: None,
+                },
+            ));
+        }
+        Err(e) => {
+            info!("Error loading subscriber: {:?}", e);
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Failed to load referral information"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    // Subscribers confirmed before referrals existed get their code on first visit
+    let referral_code = match subscriber.referral_code {
+        Some(code) => code,
+        None => match repository.assign_referral_code(&id, &generate_code()).await {
+            Ok(code) => code,
+            Err(e) => {
+                info!("Error assigning referral code: {:?}", e);
+                return Ok(create_response(
+                    500,
+                    ApiResponse {
+                        success: false,
+                        message: translate(locale, "Failed to load referral information"),
+                        data: None,
+                    },
+                ));
+            }
+        },
+    };
+
+    Ok(create_json_response(
+        200,
+        &ReferralResponse::new(referral_code, subscriber.referral_count),
+    ))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            versioned(event, |event| {
+                checked(event, InputLimits::PUBLIC, function_handler)
+            })
+        })
+    }))
+    .await
+}
@@ -0,0 +1,92 @@
+use chrono::{Duration, Utc};
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::config::{TokenPolicy, env_or};
+use newsletter_backend::email_queue::{EmailKind, Mailer};
+use newsletter_backend::i18n::EmailTemplate;
+use newsletter_backend::repository::SubscriberRepository;
+use newsletter_backend::template::subscriber_variables;
+use newsletter_backend::validation_url;
+use serde_json::Value;
+use std::env;
+use tracing::info;
+
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<(), Error> {
+    let policy = TokenPolicy::from_env();
+    // Hours after signup, and after the previous reminder, before a reminder is sent
+    let remind_after_hours = env_or("REMINDER_AFTER_HOURS", 24);
+    // Subscribers pending longer than this are left alone
+    let max_age_hours = env_or("REMINDER_MAX_AGE_HOURS", 72);
+    // Upper bound of reminders per run and per second, to stay within SES limits
+    let batch_limit = env_or("REMINDER_BATCH_LIMIT", 50);
+    let send_rate: u64 = env_or("REMINDER_SEND_RATE", 1).max(1);
+
+    let sender_email = env::var("SENDER_EMAIL")?;
+
+    // Initialize AWS SDK
+    let config = sdk_config().await;
+    let repository = SubscriberRepository::new(dynamodb().await);
+    let mailer = Mailer::new(config, sender_email);
+
+    let now = Utc::now();
+    let pending = repository
+        .find_pending_confirmation(
+            now - Duration::hours(max_age_hours),
+            now - Duration::hours(remind_after_hours),
+            now - Duration::hours(remind_after_hours),
+            policy.max_reminders,
+        )
+        .await?;
+
+    info!("Found {} subscribers pending confirmation", pending.len());
+
+    let mut sent = 0;
+    for subscriber in pending.into_iter().take(batch_limit) {
+        // Generate a fresh validation token, valid for another full TTL
+        let token = policy.generate_token();
+
+        if !repository
+            .claim_reminder(&subscriber.id, &token, &policy)
+            .await?
+        {
+            info!("Reminders already sent to subscriber {}", subscriber.id);
+            continue;
+        }
+
+        let mut variables = subscriber_variables(&subscriber);
+        variables.insert(
+            "confirm_url".to_string(),
+            validation_url(&subscriber.id, &token),
+        );
+        variables.insert("valid_hours".to_string(), policy.ttl_hours.to_string());
+        let (subject, html_body) =
+            EmailTemplate::ConfirmationReminder.render(subscriber.locale, &variables);
+
+        match mailer
+            .send(EmailKind::Reminder, &subscriber, None, &subject, &html_body)
+            .await
+        {
+            Ok(_) => sent += 1,
+            Err(e) => info!(
+                "Failed to send reminder to subscriber {}: {:?}",
+                subscriber.id, e
+            ),
+        }
+
+        // Spread sends out to respect the SES sending rate
+        tokio::time::sleep(std::time::Duration::from_millis(1000 / send_rate)).await;
+    }
+
+    info!("Sent {} confirmation reminders", sent);
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
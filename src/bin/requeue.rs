@@ -0,0 +1,81 @@
+use aws_sdk_sqs::Client as SqsClient;
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::ValidationMessage;
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::metrics::emit_metric;
+use newsletter_backend::queue::{
+    REACTIVATE_ACTION, SCHEMA_VERSION, VALIDATE_EMAIL_ACTION, ValidationQueue,
+};
+use newsletter_backend::repository::SubscriberRepository;
+use serde_json::Value;
+use std::env;
+use tracing::info;
+
+// Queue the validation messages subscribe couldn't, e.g. during an SQS outage.
+// Each is flagged on the subscriber and cleared once queued, so a run that
+// still can't reach SQS leaves them for the next one.
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<(), Error> {
+    let queue_url = env::var("VALIDATION_QUEUE_URL")?;
+
+    // Initialize AWS SDK
+    let config = sdk_config().await;
+    let repository = SubscriberRepository::new(dynamodb().await);
+    let queue = ValidationQueue::new(SqsClient::new(config), queue_url);
+
+    let pending = repository.pending_enqueue().await?;
+    let mut queued = 0;
+    let mut failed = 0;
+
+    for subscriber in &pending {
+        // Confirmed, unsubscribed or quarantined since, so no email is owed.
+        // Approval queues quarantined subscribers itself.
+        let reactivation = !subscriber.active && subscriber.reactivation_requested_at.is_some();
+        let owed = (reactivation || (subscriber.active && !subscriber.validated))
+            && !subscriber.is_quarantined();
+        if owed {
+            let action = if reactivation {
+                REACTIVATE_ACTION
+            } else {
+                VALIDATE_EMAIL_ACTION
+            };
+            let message = ValidationMessage {
+                schema_version: SCHEMA_VERSION,
+                action: action.to_string(),
+                email: subscriber.email.clone(),
+                subscriber_id: subscriber.id.clone(),
+            };
+            if let Err(e) = queue.send(&message).await {
+                info!(
+                    "Failed to queue validation message for {}: {:?}",
+                    subscriber.id, e
+                );
+                failed += 1;
+                continue;
+            }
+            queued += 1;
+        }
+
+        if let Err(e) = repository.set_enqueue_pending(&subscriber.id, false).await {
+            info!("Error clearing requeue flag of {}: {:?}", subscriber.id, e);
+        }
+    }
+
+    emit_metric("ValidationMessagesRequeued", queued as f64, "Count", &[]);
+    info!(
+        "Requeue run complete: {} pending, {} queued, {} still failing",
+        pending.len(),
+        queued,
+        failed
+    );
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
@@ -0,0 +1,94 @@
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_s3::Client as S3Client;
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use newsletter_backend::backup::{BackupContents, BackupStore, merge_backups};
+use newsletter_backend::batch::batch_put;
+use newsletter_backend::config::table_name;
+use newsletter_backend::{Error, TABLE_NAME};
+use tracing::info;
+
+#[derive(Parser)]
+#[command(
+    name = "restore",
+    about = "Rebuild an empty subscribers table from the NDJSON backups in S3"
+)]
+struct Cli {
+    #[arg(long, env = "BACKUP_BUCKET")]
+    bucket: String,
+    /// Key prefix the backups are stored under
+    #[arg(long, default_value = "subscribers")]
+    prefix: String,
+    /// Table to restore into, which must be empty. Defaults to the subscribers
+    /// table of the STAGE environment variable's stage
+    #[arg(long, default_value_t = table_name(TABLE_NAME))]
+    table: String,
+    /// Restore the table as of this RFC 3339 time instead of the latest backup
+    #[arg(long)]
+    until: Option<DateTime<Utc>>,
+    /// Print which backups would be restored without writing anything
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+
+    // Initialize AWS SDK
+    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+    let config = aws_config::from_env().region(region_provider).load().await;
+    let dynamodb_client = Client::new(&config);
+    let store = BackupStore::new(S3Client::new(&config), cli.bucket, cli.prefix);
+
+    // Never merge a backup into live data
+    let existing = dynamodb_client
+        .scan()
+        .table_name(&cli.table)
+        .limit(1)
+        .send()
+        .await?;
+    if existing.count() > 0 {
+        return Err(format!("Table {} is not empty, refusing to restore", cli.table).into());
+    }
+
+    let chain = store.restore_chain(cli.until).await?;
+
+    let mut contents = Vec::new();
+    for backup in chain {
+        let items = store.read(&backup).await?;
+        let live_ids = store.read_live_ids(&backup).await?;
+        info!("Read {} subscribers from {}", items.len(), backup.key);
+        contents.push(BackupContents {
+            backup,
+            items,
+            live_ids,
+        });
+    }
+    let backups = contents.len();
+    // Drops subscribers deleted between backups, see `merge_backups`
+    let items = merge_backups(contents)?;
+
+    if cli.dry_run {
+        println!(
+            "Would restore {} subscribers from {} backups into {}",
+            items.len(),
+            backups,
+            cli.table
+        );
+        return Ok(());
+    }
+
+    let restored = batch_put(&dynamodb_client, &cli.table, items.into_values().collect()).await?;
+    println!("Restored {} subscribers into {}", restored, cli.table);
+    // Items are written as they were backed up, around the counters
+    println!("Subscriber counters catch up on the next reconcile run");
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use chrono::{Duration, Utc};
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::Subscriber;
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::events::EventRepository;
+use newsletter_backend::history::HistoryRepository;
+use newsletter_backend::repository::SubscriberRepository;
+use serde_json::Value;
+use std::env;
+use tracing::info;
+
+// Hard-delete subscribers soft-deleted more than DELETED_RETENTION_DAYS ago,
+// together with their events and history
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<(), Error> {
+    let retention_days = env::var("DELETED_RETENTION_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30);
+
+    // Initialize AWS SDK
+    let dynamodb_client = dynamodb().await;
+    let subscribers = SubscriberRepository::new(dynamodb_client.clone());
+    let events = EventRepository::new(dynamodb_client.clone());
+    let history = HistoryRepository::new(dynamodb_client);
+
+    let cutoff = Utc::now() - Duration::days(retention_days);
+    let expired: Vec<Subscriber> = subscribers
+        .list_deleted()
+        .await?
+        .into_iter()
+        .filter(|subscriber| subscriber.deleted_at.is_some_and(|at| at < cutoff))
+        .collect();
+
+    for subscriber in &expired {
+        events.delete_for_subscriber(&subscriber.id).await?;
+        history.delete_for_subscriber(&subscriber.id).await?;
+    }
+    let purged = subscribers.delete_many(&expired).await?;
+
+    info!(
+        "Retention run complete: {} subscribers deleted more than {} days ago purged",
+        purged, retention_days
+    );
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
@@ -0,0 +1,165 @@
+use aws_sdk_sesv2::Client as SesClient;
+use chrono::Utc;
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::campaign::CampaignStatus;
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::delivery::{
+    Progress, campaign_mailer, cancel_schedule, finish_cancellation, scheduled_recipients,
+    send_campaign_emails,
+};
+use newsletter_backend::digest::DigestStore;
+use newsletter_backend::email_queue::Mailer;
+use newsletter_backend::repository::{CampaignRepository, SubscriberRepository};
+use newsletter_backend::schedule::{
+    BucketStatus, RecipientSnapshotStore, ScheduleStore, bucket_for,
+};
+use newsletter_backend::senders::{SenderStore, SenderVerifier};
+use newsletter_backend::{Subscriber, signing};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use tracing::info;
+
+// Send the timezone buckets of scheduled campaigns whose local send time has
+// come, resuming buckets whose send stalled, and mark each campaign sent once
+// all of its buckets went out
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<(), Error> {
+    let sender_email = env::var("SENDER_EMAIL")?;
+    // Fail before claiming any bucket, so they go out once it is set
+    signing::require_secret()?;
+
+    // Initialize AWS SDK
+    let config = sdk_config().await;
+    let dynamodb_client = dynamodb().await;
+    let subscribers = SubscriberRepository::new(dynamodb_client.clone());
+    let campaigns = CampaignRepository::new(dynamodb_client.clone());
+    let digests = DigestStore::new(dynamodb_client.clone());
+    let schedules = ScheduleStore::new(dynamodb_client.clone());
+    let snapshots = RecipientSnapshotStore::new(dynamodb_client.clone());
+    let senders = SenderStore::new(dynamodb_client);
+    let verifier = SenderVerifier::new(SesClient::new(config));
+    let mailer = Mailer::new(config, sender_email);
+
+    // Stalled buckets are claimed again here so only one run resumes them
+    let now = Utc::now();
+    let mut due = BTreeMap::new();
+    for bucket in schedules.due(now).await? {
+        due.entry(bucket.campaign_id.clone())
+            .or_insert_with(Vec::new)
+            .push((bucket, false));
+    }
+    for bucket in schedules.stalled(now).await? {
+        if !schedules.reclaim(&bucket).await? {
+            continue;
+        }
+        info!(
+            "Resuming campaign {} in {} after {} emails",
+            bucket.campaign_id,
+            bucket.timezone,
+            bucket
+                .checkpoint
+                .as_ref()
+                .map(|checkpoint| checkpoint.sent)
+                .unwrap_or(0)
+        );
+        due.entry(bucket.campaign_id.clone())
+            .or_insert_with(Vec::new)
+            .push((bucket, true));
+    }
+
+    info!("Found {} campaigns with buckets due", due.len());
+
+    for (campaign_id, due_buckets) in due {
+        let Some(campaign) = campaigns.get(&campaign_id).await? else {
+            info!("Scheduled campaign {} not found", campaign_id);
+            continue;
+        };
+        // Cancelled while this run was starting; close its buckets instead
+        if campaign.status == CampaignStatus::Cancelling {
+            cancel_schedule(&subscribers, &campaigns, &schedules, &snapshots, &campaign).await?;
+            continue;
+        }
+        if campaign.status != CampaignStatus::Scheduled {
+            info!(
+                "Campaign {} is {}, not scheduled",
+                campaign_id,
+                campaign.status.as_str()
+            );
+            continue;
+        }
+
+        // Buckets stay pending until the sender identity is usable again
+        let Some(mailer) = campaign_mailer(&senders, &verifier, &mailer, &campaign).await? else {
+            info!(
+                "Campaign {} sender identity {:?} is missing or not verified",
+                campaign_id, campaign.sender_id
+            );
+            continue;
+        };
+
+        // Recipients are picked at send time, so unsubscribes since scheduling
+        // are honoured, but only from the snapshot taken when it was scheduled
+        let buckets = schedules.list(&campaign_id).await?;
+        let mut recipients = scheduled_recipients(&subscribers, &snapshots, &campaign).await?;
+
+        for (bucket, reclaimed) in due_buckets {
+            if !reclaimed && !schedules.claim(&bucket).await? {
+                continue;
+            }
+
+            let (bucket_recipients, rest): (Vec<Subscriber>, Vec<Subscriber>) =
+                recipients.into_iter().partition(|subscriber| {
+                    bucket_for(subscriber, &buckets) == Some(bucket.timezone.as_str())
+                });
+            recipients = rest;
+            let counts = send_campaign_emails(
+                &campaigns,
+                Progress::Bucket(&schedules, &bucket),
+                &mailer,
+                &campaign,
+                &bucket_recipients,
+            )
+            .await?;
+            campaigns.add_skipped(&campaign_id, counts.skipped).await?;
+            schedules.complete(&bucket, counts.sent).await?;
+
+            info!(
+                "Sent campaign {} to {} subscribers in {}, skipped {}",
+                campaign_id, counts.sent, bucket.timezone, counts.skipped
+            );
+        }
+
+        // Finish the campaign with the last bucket, or with the bucket that was
+        // going out when it was cancelled
+        if campaigns.status(&campaign_id).await? == Some(CampaignStatus::Cancelling) {
+            if finish_cancellation(&campaigns, &schedules, &campaign_id).await? {
+                info!("Cancelled campaign {}", campaign_id);
+            }
+            continue;
+        }
+        let buckets = schedules.list(&campaign_id).await?;
+        if buckets
+            .iter()
+            .all(|bucket| bucket.status == BucketStatus::Sent)
+        {
+            let sent_count = buckets.iter().map(|bucket| bucket.sent_count).sum();
+            digests.add(&campaign_id).await?;
+            campaigns.mark_sent(&campaign_id, sent_count).await?;
+            info!(
+                "Finished campaign {} with {} emails sent",
+                campaign_id, sent_count
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
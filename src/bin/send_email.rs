@@ -0,0 +1,214 @@
+use aws_sdk_sqs::Client as SqsClient;
+use chrono::Utc;
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::attachments::AttachmentStore;
+use newsletter_backend::campaign::CampaignStatus;
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::config::env_or;
+use newsletter_backend::email::{EmailSender, is_transient};
+use newsletter_backend::email_queue::{DeadLetteredEmail, EmailJob, EmailKind, EmailQueue};
+use newsletter_backend::metrics::emit_count;
+use newsletter_backend::processed::{Claim, ProcessedMessages};
+use newsletter_backend::queue::{SCHEMA_VERSION, SqsBatchResponse, SqsEvent, decode};
+use newsletter_backend::repository::CampaignRepository;
+use std::collections::HashMap;
+use std::env;
+use tracing::info;
+
+// Deliver queued emails through SES. Transient failures are put back on the
+// queue with a growing delay, and jobs that fail permanently or run out of
+// attempts go to the dead-letter queue along with the last error. Emails of a
+// cancelled campaign are dropped without sending.
+async fn function_handler(event: LambdaEvent<SqsEvent>) -> Result<SqsBatchResponse, Error> {
+    let max_attempts: u32 = env_or("EMAIL_MAX_ATTEMPTS", 5).max(1);
+    let sender_email = env::var("SENDER_EMAIL")?;
+    let queue_url = env::var("EMAIL_QUEUE_URL")?;
+    let dlq_url = env::var("EMAIL_DLQ_URL")?;
+
+    // Initialize AWS SDK
+    let config = sdk_config().await;
+    let sqs_client = SqsClient::new(config);
+    let sender = EmailSender::new(config, sender_email);
+    let queue = EmailQueue::new(sqs_client.clone(), queue_url);
+    let attachment_store = AttachmentStore::from_env(config);
+    let dynamodb_client = dynamodb().await;
+    let campaigns = CampaignRepository::new(dynamodb_client.clone());
+    let processed = ProcessedMessages::new(dynamodb_client, "send_email");
+    // Claims last as long as this invocation can
+    let lease_until = event.context.deadline as i64;
+
+    let mut response = SqsBatchResponse::default();
+    // Whether each campaign in the batch was cancelled, looked up once per batch
+    let mut cancelled: HashMap<String, bool> = HashMap::new();
+    // Emails dropped per cancelled campaign
+    let mut drained: HashMap<String, u32> = HashMap::new();
+    // Messages this invocation claimed, settled once the batch is done
+    let mut claimed = Vec::new();
+
+    for record in event.payload.records {
+        // SQS can deliver a job twice, which mustn't send the email twice
+        match processed.claim(&record.message_id, lease_until).await {
+            Ok(Claim::New) => claimed.push(record.message_id.clone()),
+            Ok(Claim::Processed) => {
+                info!("Skipping already processed message {}", record.message_id);
+                emit_count("DuplicateMessagesSkipped", &[("Consumer", "send_email")]);
+                continue;
+            }
+            // Back to the queue, by when the other delivery has settled it
+            Ok(Claim::InProgress) => {
+                response.fail(record.message_id);
+                continue;
+            }
+            Err(e) => {
+                info!("Error claiming message {}: {:?}", record.message_id, e);
+                response.fail(record.message_id);
+                continue;
+            }
+        }
+
+        let job: EmailJob = match decode(&record.body) {
+            Ok(job) => job,
+            // Left to the queue's redrive policy, which moves it to the DLQ as is.
+            // Jobs from a newer producer are read once this worker is upgraded.
+            Err(e) => {
+                info!("Error parsing email job: {:?}", e);
+                response.fail(record.message_id);
+                continue;
+            }
+        };
+        let kind = job.kind.as_str();
+
+        if let (EmailKind::Campaign, Some(campaign_id)) = (job.kind, &job.campaign_id) {
+            if !cancelled.contains_key(campaign_id) {
+                let is_cancelled = match campaigns.status(campaign_id).await {
+                    Ok(status) => matches!(
+                        status,
+                        Some(CampaignStatus::Cancelling | CampaignStatus::Cancelled)
+                    ),
+                    Err(e) => {
+                        info!("Error checking campaign {}: {:?}", campaign_id, e);
+                        false
+                    }
+                };
+                cancelled.insert(campaign_id.clone(), is_cancelled);
+            }
+            if cancelled[campaign_id] {
+                emit_count("EmailsSkipped", &[("Kind", kind)]);
+                *drained.entry(campaign_id.clone()).or_default() += 1;
+                continue;
+            }
+        }
+
+        // Jobs from a sender identity go out from it rather than SENDER_EMAIL
+        let identity_sender;
+        let sender = match &job.from {
+            Some(from) => {
+                identity_sender = sender.with_from(from.clone(), job.reply_to.clone());
+                &identity_sender
+            }
+            None => &sender,
+        };
+
+        // A missing or oversized file fails the job for good, so it ends up in
+        // the dead-letter queue with the reason
+        let attachments = match (&attachment_store, job.attachments.is_empty()) {
+            (_, true) => Ok(Vec::new()),
+            (Some(store), false) => store.fetch(&job.attachments).await,
+            (None, false) => Err("ATTACHMENTS_BUCKET is not set".into()),
+        };
+        let result = match attachments {
+            Ok(attachments) => {
+                sender
+                    .send_to(
+                        &job.subscriber_id,
+                        &job.email,
+                        job.campaign_id.as_deref(),
+                        &job.subject,
+                        &job.html_body,
+                        &attachments,
+                    )
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let error = match result {
+            Ok(_) => {
+                emit_count("EmailsSent", &[("Kind", kind)]);
+                continue;
+            }
+            Err(e) => e,
+        };
+
+        let attempts = job.attempt + 1;
+        if is_transient(&error) && attempts < max_attempts {
+            info!(
+                "Transient error sending {} email to subscriber {} (attempt {}): {:?}",
+                kind, job.subscriber_id, attempts, error
+            );
+            match queue.retry(job).await {
+                Ok(_) => emit_count("EmailsRetried", &[("Kind", kind)]),
+                Err(e) => {
+                    info!("Error requeueing email job: {:?}", e);
+                    response.fail(record.message_id);
+                }
+            }
+            continue;
+        }
+
+        info!(
+            "Giving up on {} email to subscriber {} after {} attempts: {:?}",
+            kind, job.subscriber_id, attempts, error
+        );
+        let dead_letter = DeadLetteredEmail {
+            schema_version: SCHEMA_VERSION,
+            job,
+            error: error.to_string(),
+            attempts,
+            failed_at: Utc::now(),
+        };
+        let result = sqs_client
+            .send_message()
+            .queue_url(&dlq_url)
+            .message_body(serde_json::to_string(&dead_letter)?)
+            .send()
+            .await;
+        match result {
+            Ok(_) => emit_count("EmailsDeadLettered", &[("Kind", kind)]),
+            Err(e) => {
+                info!("Error dead-lettering email job: {:?}", e);
+                response.fail(record.message_id);
+            }
+        }
+    }
+
+    for message_id in &claimed {
+        processed
+            .settle_or_log(message_id, !response.has_failed(message_id))
+            .await;
+    }
+
+    for (campaign_id, count) in drained {
+        info!(
+            "Dropped {} emails of cancelled campaign {}",
+            count, campaign_id
+        );
+        if let Err(e) = campaigns.record_drained(&campaign_id, count).await {
+            info!(
+                "Error recording dropped emails of campaign {}: {:?}",
+                campaign_id, e
+            );
+        }
+    }
+
+    Ok(response)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
@@ -0,0 +1,74 @@
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::ingest::EventIngest;
+use newsletter_backend::ses::SesEvent;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnsEvent {
+    #[serde(rename = "Records")]
+    records: Vec<SnsRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnsRecord {
+    #[serde(rename = "Sns")]
+    sns: SnsMessage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SnsMessage {
+    #[serde(rename = "Message")]
+    message: String,
+}
+
+// Store SES configuration-set events published to SNS in the events table
+async fn function_handler(event: LambdaEvent<SnsEvent>) -> Result<(), Error> {
+    // Initialize AWS SDK
+    let ingest = EventIngest::new(dynamodb().await);
+
+    for record in event.payload.records {
+        let ses_event: SesEvent = match serde_json::from_str(&record.sns.message) {
+            Ok(ses_event) => ses_event,
+            Err(e) => {
+                info!("Error parsing SES event: {:?}", e);
+                continue;
+            }
+        };
+
+        let Some(tracked) = ses_event.to_event() else {
+            info!(
+                "Ignoring SES {} event for message {}",
+                ses_event.event_type, ses_event.mail.message_id
+            );
+            continue;
+        };
+
+        let permanent = ses_event
+            .bounce
+            .as_ref()
+            .is_some_and(|bounce| bounce.is_permanent());
+
+        // Return errors so SNS retries the delivery
+        ingest
+            .ingest(
+                &tracked,
+                ses_event.mail.destination.first().map(String::as_str),
+                permanent,
+                "ses",
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
@@ -0,0 +1,178 @@
+use chrono::Utc;
+use lambda_http::{Body, Error, Request, Response, run, service_fn};
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::input::{InputLimits, checked};
+use newsletter_backend::logging::log_request;
+use newsletter_backend::repository::SubscriberRepository;
+use newsletter_backend::stripe::{StripeEvent, TierUpdate, verify_signature};
+use newsletter_backend::{ApiResponse, Subscriber, create_response};
+use std::env;
+use tracing::info;
+
+// Find the subscriber a tier change applies to, trying the most specific identifier first
+async fn find_subscriber(
+    repository: &SubscriberRepository,
+    update: &TierUpdate,
+) -> Result<Option<Subscriber>, newsletter_backend::Error> {
+    if let Some(id) = &update.subscriber_id
+        && let Some(subscriber) = repository.get(id).await?
+    {
+        return Ok(Some(subscriber));
+    }
+    if let Some(email) = &update.email
+        && let Some(subscriber) = repository.find_by_email(email).await?
+    {
+        return Ok(Some(subscriber));
+    }
+    match &update.customer_id {
+        Some(customer_id) => repository.find_by_stripe_customer(customer_id).await,
+        None => Ok(None),
+    }
+}
+
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    let secret = match env::var("STRIPE_WEBHOOK_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret,
+        _ => {
+            info!("STRIPE_WEBHOOK_SECRET not set in environment");
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Webhook is not configured".to_string(),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    // The signature covers the raw body, so verify before parsing anything
+    let payload: &[u8] = match event.body() {
+        Body::Text(text) => text.as_bytes(),
+        Body::Binary(bytes) => bytes.as_slice(),
+        _ => &[],
+    };
+    let signature = event
+        .headers()
+        .get("Stripe-Signature")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+
+    if !verify_signature(payload, signature, &secret, Utc::now().timestamp()) {
+        return Ok(create_response(
+            400,
+            ApiResponse {
+                success: false,
+                message: "Invalid signature".to_string(),
+                data: None,
+            },
+        ));
+    }
+
+    let stripe_event: StripeEvent = match serde_json::from_slice(payload) {
+        Ok(stripe_event) => stripe_event,
+        Err(_) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: "Invalid event payload".to_string(),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    let Some(update) = stripe_event.tier_update() else {
+        info!(
+            "Ignoring Stripe event {} ({})",
+            stripe_event.id, stripe_event.event_type
+        );
+        return Ok(create_response(
+            200,
+            ApiResponse {
+                success: true,
+                message: "Event ignored".to_string(),
+                data: None,
+            },
+        ));
+    };
+
+    // Initialize AWS SDK
+    let repository = SubscriberRepository::new(dynamodb().await);
+
+    // Errors below return 500 so Stripe retries the delivery
+    let subscriber = match find_subscriber(&repository, &update).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => {
+            // Nothing to retry: the customer isn't a subscriber
+            info!("No subscriber found for Stripe event {}", stripe_event.id);
+            return Ok(create_response(
+                200,
+                ApiResponse {
+                    success: true,
+                    message: "No matching subscriber".to_string(),
+                    data: None,
+                },
+            ));
+        }
+        Err(e) => {
+            info!("Error finding subscriber for Stripe event: {:?}", e);
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to process event".to_string(),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    match repository
+        .set_tier(&subscriber.id, update.tier, update.customer_id.as_deref())
+        .await
+    {
+        Ok(_) => {
+            info!(
+                "Set subscriber {} to tier {} from Stripe event {}",
+                subscriber.id,
+                update.tier.as_str(),
+                stripe_event.id
+            );
+            Ok(create_response(
+                200,
+                ApiResponse {
+                    success: true,
+                    message: "Tier updated".to_string(),
+                    data: None,
+                },
+            ))
+        }
+        Err(e) => {
+            info!("Error updating subscriber tier: {:?}", e);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: "Failed to process event".to_string(),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            checked(event, InputLimits::WEBHOOK, function_handler)
+        })
+    }))
+    .await
+}
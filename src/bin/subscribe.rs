@@ -1,18 +1,50 @@
-use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_dynamodb::Client;
 use aws_sdk_sqs::Client as SqsClient;
-use email_address::*;
+use chrono::{Duration, Utc};
+use lambda_http::http::HeaderValue;
 use lambda_http::{Body, Error, Request, Response, run, service_fn};
-use newsletter_backend::{ApiResponse, SubscribeRequest, Subscriber, TABLE_NAME, create_response};
-use serde_json::json;
+use newsletter_backend::address::normalize_email;
+use newsletter_backend::attribution::Attribution;
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::config::{TokenPolicy, double_opt_in, env_or};
+use newsletter_backend::digest::Frequency;
+use newsletter_backend::domain_rules::{DomainRuleStore, is_allowed};
+use newsletter_backend::domains::{DomainMetric, DomainStatsStore};
+use newsletter_backend::fields::FieldSchema;
+use newsletter_backend::history::{HistoryRepository, LifecycleEvent, LifecycleEventType};
+use newsletter_backend::i18n::{Locale, translate};
+use newsletter_backend::idempotency::{IdempotencyState, IdempotencyStore, fingerprint};
+use newsletter_backend::input::{InputLimits, checked, is_valid_email};
+use newsletter_backend::invites::{InviteStore, invite_only};
+use newsletter_backend::logging::log_request;
+use newsletter_backend::metrics::emit_count;
+use newsletter_backend::onboarding::confirm_without_link;
+use newsletter_backend::quarantine::{QuarantineReason, assess_signup, source_ip};
+use newsletter_backend::queue::{
+    REACTIVATE_ACTION, SCHEMA_VERSION, VALIDATE_EMAIL_ACTION, ValidationQueue,
+};
+use newsletter_backend::quota::{QuotaKind, QuotaStore};
+use newsletter_backend::referral::normalize_code;
+use newsletter_backend::repository::{ExistingSignup, SubscriberRepository};
+use newsletter_backend::schedule::parse_timezone;
+use newsletter_backend::spam::check_submission;
+use newsletter_backend::verification::{EmailVerifier, HttpVerifier, Verdict, verify_on_subscribe};
+use newsletter_backend::versioning::versioned;
+use newsletter_backend::{
+    ApiResponse, SubscribeData, SubscribeRequest, SubscribeStatus, Subscriber, ValidationMessage,
+    create_json_response, create_response,
+};
 use std::env;
 use tracing::info;
+use uuid::Uuid;
 
-async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .init();
+// Confirmation emails an unconfirmed subscriber can ask for again per day by
+// signing up again
+const DEFAULT_RESENDS_PER_DAY: u64 = 3;
+
+async fn subscribe(event: &Request) -> Result<Response<Body>, Error> {
+    // Respond in the browser's language until the request says otherwise
+    let mut locale = Locale::from_request(event);
 
     // Get the SQS queue URL from environment variables
     let queue_url = match env::var("VALIDATION_QUEUE_URL") {
@@ -33,152 +65,704 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                 400,
                 ApiResponse {
                     success: false,
-                    message: "Invalid request body".to_string(),
+                    message: translate(locale, "Invalid request body"),
+                    data: None,
                 },
             ));
         }
     };
 
-    let subscribe_request: SubscribeRequest = match serde_json::from_str(body) {
+    let mut subscribe_request: SubscribeRequest = match serde_json::from_str(body) {
         Ok(req) => req,
         Err(_) => {
             return Ok(create_response(
                 400,
                 ApiResponse {
                     success: false,
-                    message: "Invalid JSON format".to_string(),
+                    message: translate(locale, "Invalid JSON format"),
+                    data: None,
                 },
             ));
         }
     };
 
+    // An explicit locale in the request wins over Accept-Language
+    if let Some(requested) = subscribe_request.locale.as_deref().and_then(Locale::parse) {
+        locale = requested;
+    }
+
+    // Bots get the same answer as everyone else so they can't learn to evade the checks
+    if let Some(reason) = check_submission(&subscribe_request, Utc::now()) {
+        info!("Dropping spam submission: {}", reason.as_str());
+        emit_count("SpamSubmissions", &[("Reason", reason.as_str())]);
+        // An id that belongs to no one, so the answer looks like a real signup
+        return Ok(create_json_response(
+            201,
+            &ApiResponse {
+                success: true,
+                message: translate(
+                    locale,
+                    "Successfully subscribed. Validation email will be sent shortly.",
+                ),
+                data: Some(SubscribeData {
+                    subscriber_id: Some(Uuid::new_v4().to_string()),
+                    status: SubscribeStatus::PendingConfirmation,
+                }),
+            },
+        ));
+    }
+
     // Validate email (basic validation)
-    if !EmailAddress::is_valid(&subscribe_request.email) {
+    if !is_valid_email(&subscribe_request.email) {
         return Ok(create_response(
             400,
             ApiResponse {
                 success: false,
-                message: "Invalid email format".to_string(),
+                message: translate(locale, "Invalid email format"),
+                data: None,
             },
         ));
     }
+    // Stored with its domain in punycode, however it was typed
+    if let Some(email) = normalize_email(&subscribe_request.email) {
+        subscribe_request.email = email;
+    }
+
+    // Invite-only launches need a code; it is redeemed just before storing the
+    // subscriber so duplicate or rejected signups don't use it up
+    let invite_code = if invite_only() {
+        match subscribe_request
+            .invite_code
+            .as_deref()
+            .map(str::trim)
+            .filter(|code| !code.is_empty())
+        {
+            Some(code) => Some(code.to_string()),
+            None => {
+                return Ok(create_response(
+                    403,
+                    ApiResponse {
+                        success: false,
+                        message: translate(locale, "An invite code is required to subscribe"),
+                        data: None,
+                    },
+                ));
+            }
+        }
+    } else {
+        None
+    };
+
+    // Validate custom fields against the configured schema
+    let schema = match FieldSchema::current() {
+        Ok(schema) => schema,
+        Err(err) => {
+            info!("Invalid CUSTOM_FIELDS_SCHEMA: {:?}", err);
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Failed to subscribe"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    let fields = match schema.validate(&subscribe_request.fields) {
+        Ok(fields) => fields,
+        Err(message) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message,
+                    data: None,
+                },
+            ));
+        }
+    };
 
     // Create subscriber
-    let subscriber = Subscriber::new(subscribe_request.email.clone());
+    let mut subscriber = Subscriber::new(subscribe_request.email.clone());
+    subscriber.fields = fields;
+    subscriber.locale = locale;
+    // Unknown timezones are dropped, the subscriber then gets the default one
+    subscriber.timezone = subscribe_request
+        .timezone
+        .as_deref()
+        .and_then(parse_timezone)
+        .map(|timezone| timezone.name().to_string());
+    // Unknown values fall back to immediate, like unsupported locales
+    subscriber.frequency = subscribe_request
+        .frequency
+        .as_deref()
+        .and_then(Frequency::parse)
+        .unwrap_or_default();
+    subscriber.referred_by = subscribe_request
+        .referral_code
+        .as_deref()
+        .and_then(normalize_code);
+    subscriber.attribution = Attribution::from_request(
+        &subscribe_request,
+        event
+            .headers()
+            .get("Referer")
+            .and_then(|value| value.to_str().ok()),
+    );
 
     // Initialize AWS SDK
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let dynamodb_client = Client::new(&config);
+    let config = sdk_config().await;
+    let dynamodb_client = dynamodb().await;
 
     // Initialize SQS client with the same config
-    let sqs_client = SqsClient::new(&config);
-
-    // Check if email already exists (to avoid duplicates)
-    let email_query = match dynamodb_client
-        .query()
-        .table_name(TABLE_NAME)
-        .index_name("email-index")
-        .key_condition_expression("email = :email")
-        .expression_attribute_values(
-            ":email",
-            aws_sdk_dynamodb::types::AttributeValue::S(subscribe_request.email.clone()),
-        )
-        .send()
+    let sqs_client = SqsClient::new(config);
+
+    // Operator-managed allow and deny rules. Failing closed keeps a closed beta
+    // closed while the rules table is unavailable.
+    match DomainRuleStore::new(dynamodb_client.clone()).list().await {
+        Ok(rules) if !is_allowed(&rules, &subscriber.email) => {
+            emit_count("DeniedDomainSignups", &[]);
+            return Ok(create_response(
+                403,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Signups from this email domain are not allowed"),
+                    data: None,
+                },
+            ));
+        }
+        Ok(_) => {}
+        Err(err) => {
+            info!("Error loading domain rules: {:?}", err);
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Failed to subscribe"),
+                    data: None,
+                },
+            ));
+        }
+    }
+
+    // Check if email already exists (to avoid duplicates). Soft-deleted records
+    // don't count, so a deleted subscriber can sign up again.
+    match SubscriberRepository::new(dynamodb_client.clone())
+        .find_signup_by_email(&subscribe_request.email)
         .await
     {
-        Ok(result) => Ok(result),
+        // Unsubscribed, so signing up again brings them back once they confirm
+        Ok(Some(existing)) if !existing.active && !existing.suppressed => {
+            return reactivate(
+                &dynamodb_client,
+                &sqs_client,
+                &queue_url,
+                &existing.id,
+                &subscribe_request.email,
+                locale,
+            )
+            .await;
+        }
+        // Signed up before but never confirmed, so the link is sent again
+        Ok(Some(existing)) if existing.active && !existing.validated && !existing.suppressed => {
+            return resend_confirmation(
+                &dynamodb_client,
+                &sqs_client,
+                &queue_url,
+                &existing,
+                &subscribe_request.email,
+                locale,
+            )
+            .await;
+        }
+        Ok(Some(_)) => {
+            return Ok(create_json_response(
+                200,
+                &ApiResponse {
+                    success: true,
+                    message: translate(locale, "You're already on the list"),
+                    data: Some(SubscribeData {
+                        subscriber_id: None,
+                        status: SubscribeStatus::AlreadySubscribed,
+                    }),
+                },
+            ));
+        }
+        Ok(None) => {}
         Err(err) => {
-            info!("Error querying by email index: {:?}", err);
-            // If the index isn't ready yet, we'll do a scan as a fallback
-            let scan_result = dynamodb_client
-                .scan()
-                .table_name(TABLE_NAME)
-                .filter_expression("email = :email")
-                .expression_attribute_values(
-                    ":email",
-                    aws_sdk_dynamodb::types::AttributeValue::S(subscribe_request.email.clone()),
-                )
-                .send()
-                .await;
-            Err(scan_result)
+            info!("Error checking for existing email: {:?}", err);
+            // Continue with subscription even if query fails
         }
-    };
+    }
+
+    // Suspicious signups are stored but get no email until an admin approves them
+    subscriber.quarantine_reason = assess_signup(
+        &QuotaStore::new(dynamodb_client.clone()),
+        &subscriber.email,
+        source_ip(event).as_deref(),
+    )
+    .await;
 
-    match email_query {
-        Ok(result) => {
-            if let Some(items) = result.items() {
-                if !items.is_empty() {
-                    // Email already exists
-                    return Ok(create_response(
-                        200,
-                        ApiResponse {
-                            success: true,
-                            message: "Email is already subscribed".to_string(),
-                        },
-                    ));
+    // Optionally ask the verification provider before storing anything, always
+    // for quarantined signups. Provider errors let the signup through, the
+    // hygiene job checks it later.
+    if let Some(verifier) =
+        HttpVerifier::from_env().filter(|_| verify_on_subscribe() || subscriber.is_quarantined())
+    {
+        match verifier.verify(&subscriber.email).await {
+            Ok(Verdict::Undeliverable) => {
+                emit_count("UndeliverableSignups", &[]);
+                return Ok(create_response(
+                    400,
+                    ApiResponse {
+                        success: false,
+                        message: translate(locale, "Email address cannot receive mail"),
+                        data: None,
+                    },
+                ));
+            }
+            Ok(verdict) => {
+                // A deliverable address clears a made-up looking local part. Bursts
+                // stay quarantined: subscription bombing uses real addresses.
+                if verdict == Verdict::Deliverable
+                    && subscriber.quarantine_reason == Some(QuarantineReason::DictionaryLocalPart)
+                {
+                    subscriber.quarantine_reason = None;
                 }
+                subscriber.verification = Some(verdict);
+                subscriber.verified_at = Some(Utc::now());
             }
+            Err(e) => info!("Error verifying email address: {:?}", e),
         }
-        Err(err) => {
-            info!("Error checking for existing email: {:?}", err);
-            // Continue with subscription even if query fails
+    }
+
+    let invites = InviteStore::new(dynamodb_client.clone());
+    if let Some(code) = &invite_code {
+        match invites.redeem(code).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return Ok(create_response(
+                    403,
+                    ApiResponse {
+                        success: false,
+                        message: translate(locale, "Invalid or used up invite code"),
+                        data: None,
+                    },
+                ));
+            }
+            Err(err) => {
+                info!("Error redeeming invite code: {:?}", err);
+                return Ok(create_response(
+                    500,
+                    ApiResponse {
+                        success: false,
+                        message: translate(locale, "Failed to subscribe"),
+                        data: None,
+                    },
+                ));
+            }
         }
     }
 
-    // Put item in DynamoDB
-    let put_result = dynamodb_client
-        .put_item()
-        .table_name(TABLE_NAME)
-        .set_item(Some(subscriber.to_dynamodb_item()))
-        .send()
+    // Without double opt-in the subscriber is confirmed right away, unless quarantined
+    let confirmed = !double_opt_in();
+    subscriber.validated = confirmed && !subscriber.is_quarantined();
+
+    // Put item in DynamoDB, encrypted when PII encryption is on
+    let put_result = SubscriberRepository::new(dynamodb_client.clone())
+        .put(&subscriber)
         .await;
 
     match put_result {
         Ok(_) => {
-            // Send validation message to SQS
-            let message = json!({
-                "action": "validate_email",
-                "email": subscribe_request.email,
-                "subscriber_id": subscriber.id
-            })
-            .to_string();
-
-            // Send message to SQS queue
-            match sqs_client
-                .send_message()
-                .queue_url(&queue_url)
-                .message_body(message)
-                .send()
-                .await
-            {
-                Ok(_) => info!("Sent validation message to queue"),
-                Err(e) => info!("Failed to send validation message to queue: {:?}", e),
-            };
+            let mut subscribed =
+                LifecycleEvent::new(&subscriber.id, LifecycleEventType::Subscribed)
+                    .with_source("api");
+            if let Some(code) = &invite_code {
+                subscribed = subscribed.with_detail(format!("invite code {code}"));
+            }
+            let history = HistoryRepository::new(dynamodb_client.clone());
+            history.record_or_log(subscribed).await;
+            DomainStatsStore::new(dynamodb_client.clone())
+                .record_or_log(
+                    &subscriber.email,
+                    DomainMetric::Subscribed,
+                    subscriber.created_at,
+                )
+                .await;
 
-            Ok(create_response(
+            // Quarantined signups get the usual answer so bots can't tell them apart
+            if let Some(reason) = subscriber.quarantine_reason {
+                info!(
+                    "Quarantined subscriber {}: {}",
+                    subscriber.id,
+                    reason.as_str()
+                );
+                emit_count("QuarantinedSignups", &[("Reason", reason.as_str())]);
+                history
+                    .record_or_log(
+                        LifecycleEvent::new(&subscriber.id, LifecycleEventType::Quarantined)
+                            .with_source("api")
+                            .with_detail(reason.as_str()),
+                    )
+                    .await;
+            }
+
+            // Welcomed instead of asked to confirm
+            if confirmed {
+                if subscriber.validated {
+                    confirm_without_link(config, dynamodb_client, &subscriber, "single_opt_in")
+                        .await;
+                }
+
+                return Ok(create_json_response(
+                    201,
+                    &ApiResponse {
+                        success: true,
+                        message: translate(locale, "Successfully subscribed."),
+                        data: Some(SubscribeData {
+                            subscriber_id: Some(subscriber.id.clone()),
+                            status: SubscribeStatus::Subscribed,
+                        }),
+                    },
+                ));
+            }
+
+            // Send validation message to SQS. Quarantined subscribers are
+            // enqueued when an admin approves them.
+            let mut response_message =
+                "Successfully subscribed. Validation email will be sent shortly.";
+            if !subscriber.is_quarantined() {
+                let message = ValidationMessage {
+                    schema_version: SCHEMA_VERSION,
+                    action: VALIDATE_EMAIL_ACTION.to_string(),
+                    email: subscribe_request.email.clone(),
+                    subscriber_id: subscriber.id.clone(),
+                };
+
+                match ValidationQueue::new(sqs_client, queue_url)
+                    .send(&message)
+                    .await
+                {
+                    Ok(_) => info!("Sent validation message to queue"),
+                    Err(e) => {
+                        // Flagged for the requeue job, which queues it once SQS
+                        // is back. If even that fails, the reminder job still
+                        // sends a link later.
+                        info!("Failed to send validation message to queue: {:?}", e);
+                        emit_count("ValidationEnqueueFailures", &[]);
+                        if let Err(e) = SubscriberRepository::new(dynamodb_client.clone())
+                            .set_enqueue_pending(&subscriber.id, true)
+                            .await
+                        {
+                            info!("Error flagging subscriber for requeue: {:?}", e);
+                        }
+                        response_message = "Successfully subscribed. Your confirmation email is delayed and will arrive later.";
+                    }
+                };
+            }
+
+            Ok(create_json_response(
                 201,
-                ApiResponse {
+                &ApiResponse {
                     success: true,
-                    message: "Successfully subscribed. Validation email will be sent shortly."
-                        .to_string(),
+                    message: translate(locale, response_message),
+                    data: Some(SubscribeData {
+                        subscriber_id: Some(subscriber.id.clone()),
+                        status: SubscribeStatus::PendingConfirmation,
+                    }),
                 },
             ))
         }
         Err(err) => {
             info!("Error adding subscriber: {:?}", err);
+            if let Some(code) = &invite_code
+                && let Err(e) = invites.release(code).await
+            {
+                info!("Error releasing invite code: {:?}", e);
+            }
             Ok(create_response(
                 500,
                 ApiResponse {
                     success: false,
-                    message: "Failed to subscribe".to_string(),
+                    message: translate(locale, "Failed to subscribe"),
+                    data: None,
                 },
             ))
         }
     }
 }
 
+// Send an unconfirmed subscriber who signed up again a new confirmation link,
+// up to CONFIRMATION_RESENDS_PER_DAY times a day. Past the limit, and for
+// quarantined subscribers, the answer is the same but nothing is sent.
+async fn resend_confirmation(
+    dynamodb_client: &Client,
+    sqs_client: &SqsClient,
+    queue_url: &str,
+    existing: &ExistingSignup,
+    email: &str,
+    locale: Locale,
+) -> Result<Response<Body>, Error> {
+    let limit = env_or("CONFIRMATION_RESENDS_PER_DAY", DEFAULT_RESENDS_PER_DAY);
+    let allowed = match QuotaStore::new(dynamodb_client.clone())
+        .consume(
+            &format!("subscriber:{}", existing.id),
+            QuotaKind::Resends,
+            limit,
+            1,
+        )
+        .await
+    {
+        Ok(status) => status.allowed,
+        // A lost counter shouldn't hold back a link someone is waiting for
+        Err(e) => {
+            info!("Error counting confirmation resends: {:?}", e);
+            true
+        }
+    };
+
+    // Quarantined subscribers are sent theirs on approval
+    if allowed && !existing.quarantined {
+        let validation = ValidationMessage {
+            schema_version: SCHEMA_VERSION,
+            action: VALIDATE_EMAIL_ACTION.to_string(),
+            email: email.to_string(),
+            subscriber_id: existing.id.clone(),
+        };
+        match ValidationQueue::new(sqs_client.clone(), queue_url.to_string())
+            .send(&validation)
+            .await
+        {
+            Ok(_) => emit_count("ConfirmationResends", &[]),
+            Err(e) => {
+                info!("Failed to send validation message to queue: {:?}", e);
+                emit_count("ValidationEnqueueFailures", &[]);
+                if let Err(e) = SubscriberRepository::new(dynamodb_client.clone())
+                    .set_enqueue_pending(&existing.id, true)
+                    .await
+                {
+                    info!("Error flagging subscriber for requeue: {:?}", e);
+                }
+            }
+        }
+    }
+    let message = if allowed {
+        "You're already signed up. We've sent your confirmation email again."
+    } else {
+        "You're already signed up. Check your inbox for the confirmation email."
+    };
+
+    Ok(create_json_response(
+        200,
+        &ApiResponse {
+            success: true,
+            message: translate(locale, message),
+            data: Some(SubscribeData {
+                subscriber_id: None,
+                status: SubscribeStatus::PendingConfirmation,
+            }),
+        },
+    ))
+}
+
+// Send an unsubscribed subscriber who signed up again a confirmation, which
+// makes them active once followed. Everything else about them is kept, and the
+// record isn't purged if they never confirm.
+async fn reactivate(
+    dynamodb_client: &Client,
+    sqs_client: &SqsClient,
+    queue_url: &str,
+    id: &str,
+    email: &str,
+    locale: Locale,
+) -> Result<Response<Body>, Error> {
+    let repository = SubscriberRepository::new(dynamodb_client.clone());
+    // Repeated signups within a link's lifetime get the same answer, no new email
+    let resend_after = Duration::hours(TokenPolicy::from_env().ttl_hours);
+    let requested = match repository.request_reactivation(id, resend_after).await {
+        Ok(requested) => requested,
+        Err(err) => {
+            info!("Error requesting reactivation of {}: {:?}", id, err);
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Failed to subscribe"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    if requested {
+        HistoryRepository::new(dynamodb_client.clone())
+            .record_or_log(
+                LifecycleEvent::new(id, LifecycleEventType::Subscribed)
+                    .with_source("api")
+                    .with_detail("reactivation requested"),
+            )
+            .await;
+
+        let message = ValidationMessage {
+            schema_version: SCHEMA_VERSION,
+            action: REACTIVATE_ACTION.to_string(),
+            email: email.to_string(),
+            subscriber_id: id.to_string(),
+        };
+        if let Err(e) = ValidationQueue::new(sqs_client.clone(), queue_url.to_string())
+            .send(&message)
+            .await
+        {
+            info!("Failed to send reactivation message to queue: {:?}", e);
+            emit_count("ValidationEnqueueFailures", &[]);
+            if let Err(e) = repository.set_enqueue_pending(id, true).await {
+                info!("Error flagging subscriber for requeue: {:?}", e);
+            }
+        }
+    }
+
+    Ok(create_json_response(
+        201,
+        &ApiResponse {
+            success: true,
+            message: translate(
+                locale,
+                "Welcome back. Confirm your subscription with the email we're sending you.",
+            ),
+            data: Some(SubscribeData {
+                subscriber_id: None,
+                status: SubscribeStatus::ReactivationPending,
+            }),
+        },
+    ))
+}
+
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    let locale = Locale::from_request(&event);
+
+    // Requests without an Idempotency-Key header are always processed
+    let idempotency_key = match event
+        .headers()
+        .get("Idempotency-Key")
+        .map(|value| value.to_str().unwrap_or(""))
+    {
+        Some(key) if !key.is_empty() && key.len() <= 255 => key.to_string(),
+        Some(_) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Invalid Idempotency-Key header"),
+                    data: None,
+                },
+            ));
+        }
+        None => return subscribe(&event).await,
+    };
+
+    let request_body = match event.body() {
+        Body::Text(text) => text.as_str(),
+        _ => "",
+    };
+
+    // Initialize AWS SDK
+    let store = IdempotencyStore::new(dynamodb().await);
+
+    match store
+        .begin(&idempotency_key, &fingerprint(request_body))
+        .await
+    {
+        Ok(IdempotencyState::New) => {}
+        Ok(IdempotencyState::Completed(stored)) => {
+            return Ok(Response::builder()
+                .status(stored.status_code)
+                .header("Content-Type", "application/json")
+                .header("Idempotent-Replayed", "true")
+                .body(Body::from(stored.body))?);
+        }
+        Ok(IdempotencyState::InProgress) => {
+            return Ok(create_response(
+                409,
+                ApiResponse {
+                    success: false,
+                    message: translate(
+                        locale,
+                        "A request with this Idempotency-Key is still being processed",
+                    ),
+                    data: None,
+                },
+            ));
+        }
+        Ok(IdempotencyState::Mismatch) => {
+            return Ok(create_response(
+                422,
+                ApiResponse {
+                    success: false,
+                    message: translate(
+                        locale,
+                        "Idempotency-Key was already used for a different request",
+                    ),
+                    data: None,
+                },
+            ));
+        }
+        Err(err) => {
+            // Don't turn away subscribers because the idempotency table is unavailable
+            info!("Error checking idempotency key: {:?}", err);
+            return subscribe(&event).await;
+        }
+    }
+
+    let response = match subscribe(&event).await {
+        Ok(response) => response,
+        Err(err) => {
+            if let Err(e) = store.release(&idempotency_key).await {
+                info!("Error releasing idempotency key: {:?}", e);
+            }
+            return Err(err);
+        }
+    };
+
+    // Server errors aren't remembered, so retries get another chance
+    let stored = if response.status().is_server_error() {
+        store.release(&idempotency_key).await
+    } else {
+        let body = match response.body() {
+            Body::Text(text) => text.clone(),
+            _ => String::new(),
+        };
+        store
+            .complete(&idempotency_key, response.status().as_u16(), &body)
+            .await
+    };
+
+    if let Err(e) = stored {
+        info!("Error storing idempotency key: {:?}", e);
+    }
+
+    Ok(response)
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(service_fn(function_handler)).await
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event: Request| async {
+        let mut response = log_request(event, |event| {
+            versioned(event, |event| {
+                checked(event, InputLimits::PUBLIC, function_handler)
+            })
+        })
+        .await?;
+        // Lets the embedded form (and other sites) read the result
+        response
+            .headers_mut()
+            .insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
+        Ok::<_, Error>(response)
+    }))
+    .await
 }
@@ -0,0 +1,132 @@
+use chrono::{Duration, Utc};
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::email_queue::{EmailKind, Mailer};
+use newsletter_backend::events::{EventRepository, click_url, pixel_tracking_enabled};
+use newsletter_backend::frontend_url;
+use newsletter_backend::history::{HistoryRepository, LifecycleEvent, LifecycleEventType};
+use newsletter_backend::i18n::EmailTemplate;
+use newsletter_backend::opt_out;
+use newsletter_backend::repository::SubscriberRepository;
+use newsletter_backend::template::subscriber_variables;
+use serde_json::Value;
+use std::env;
+use tracing::info;
+
+// Read a numeric setting from the environment, falling back to a default
+fn env_or(name: &str, default: i64) -> i64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<(), Error> {
+    // Subscribers without opens or clicks for this long get a re-engagement email
+    let inactive_days = env_or("SUNSET_INACTIVE_DAYS", 180);
+    // Time they have to interact with it before being suppressed
+    let grace_days = env_or("SUNSET_GRACE_DAYS", 30);
+
+    let sender_email = env::var("SENDER_EMAIL")?;
+
+    // Initialize AWS SDK
+    let config = sdk_config().await;
+    let dynamodb_client = dynamodb().await;
+    let subscribers = SubscriberRepository::new(dynamodb_client.clone());
+    let events = EventRepository::new(dynamodb_client.clone());
+    let history = HistoryRepository::new(dynamodb_client);
+    let mailer = Mailer::new(config, sender_email);
+
+    let now = Utc::now();
+    let inactive_cutoff = now - Duration::days(inactive_days);
+    let (mut reengaged, mut emailed, mut suppressed) = (0, 0, 0);
+
+    for subscriber in subscribers.list_all().await? {
+        // New subscribers haven't had the chance to engage yet
+        if !subscriber.is_mailable() || subscriber.created_at > inactive_cutoff {
+            continue;
+        }
+
+        let last_engagement = events
+            .latest_engagement_for_subscriber(&subscriber.id)
+            .await?
+            .map(|event| event.occurred_at);
+
+        match subscriber.reengagement_sent_at {
+            // Interacted since we asked, keep them
+            Some(sent_at) if last_engagement.is_some_and(|at| at > sent_at) => {
+                subscribers
+                    .set_reengagement_sent_at(&subscriber.id, None)
+                    .await?;
+                reengaged += 1;
+            }
+            // No interaction within the grace period, stop emailing them
+            Some(sent_at) => {
+                if now - sent_at > Duration::days(grace_days) {
+                    subscribers.suppress(&subscriber.id).await?;
+                    let event = LifecycleEvent::new(&subscriber.id, LifecycleEventType::Suppressed)
+                        .with_source("sunset")
+                        .with_detail(format!("No engagement within {grace_days} days"));
+                    opt_out::publish(&event, &subscriber.email).await;
+                    history.record_or_log(event).await;
+                    info!("Suppressed inactive subscriber {}", subscriber.id);
+                    suppressed += 1;
+                }
+            }
+            // Still engaged, nothing to do
+            None if last_engagement.is_some_and(|at| at > inactive_cutoff) => {}
+            None => {
+                // SES tracks the plain link itself when pixel tracking is off
+                let keep_url = format!("{}/still-subscribed", frontend_url());
+                let keep_url = if pixel_tracking_enabled() {
+                    click_url(&subscriber.id, None, &keep_url)?
+                } else {
+                    keep_url
+                };
+                let mut variables = subscriber_variables(&subscriber);
+                variables.insert("keep_url".to_string(), keep_url);
+                variables.insert("grace_days".to_string(), grace_days.to_string());
+                let (subject, html_body) =
+                    EmailTemplate::Reengagement.render(subscriber.locale, &variables);
+
+                match mailer
+                    .send(
+                        EmailKind::Reengagement,
+                        &subscriber,
+                        None,
+                        &subject,
+                        &html_body,
+                    )
+                    .await
+                {
+                    Ok(_) => {
+                        subscribers
+                            .set_reengagement_sent_at(&subscriber.id, Some(now))
+                            .await?;
+                        emailed += 1;
+                    }
+                    Err(e) => info!(
+                        "Failed to send re-engagement email to subscriber {}: {:?}",
+                        subscriber.id, e
+                    ),
+                }
+            }
+        }
+    }
+
+    info!(
+        "Sunset run complete: {} re-engaged, {} emailed, {} suppressed",
+        reengaged, emailed, suppressed
+    );
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
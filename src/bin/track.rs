@@ -0,0 +1,142 @@
+use lambda_http::{Body, Error, Request, RequestExt, Response, run, service_fn};
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::domains::{DomainMetric, DomainStatsStore};
+use newsletter_backend::events::{Event, EventRepository, EventType, tracking_key};
+use newsletter_backend::input::{InputLimits, checked};
+use newsletter_backend::logging::log_request;
+use newsletter_backend::repository::SubscriberRepository;
+use newsletter_backend::{ApiResponse, create_response, frontend_url, signing};
+use tracing::info;
+use url::Url;
+
+// Transparent 1x1 GIF returned for open tracking
+const PIXEL_GIF: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0xff, 0xff, 0xff,
+    0x00, 0x00, 0x00, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+// Whether a URL is on the frontend's scheme, host and port. Only those are
+// redirected to, so the endpoint can't be used as an open redirect.
+fn same_origin(url: &str, frontend: &str) -> bool {
+    match (Url::parse(url), Url::parse(frontend)) {
+        (Ok(url), Ok(frontend)) => {
+            url.scheme() == frontend.scheme()
+                && url.host() == frontend.host()
+                && url.port_or_known_default() == frontend.port_or_known_default()
+        }
+        _ => false,
+    }
+}
+
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters();
+
+    let (event_type, subscriber_id) = match (
+        params
+            .first("e")
+            .and_then(EventType::parse)
+            .filter(EventType::is_engagement),
+        params.first("s"),
+    ) {
+        (Some(event_type), Some(subscriber_id)) => (event_type, subscriber_id.to_string()),
+        _ => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: "Invalid tracking parameters".to_string(),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    let frontend = frontend_url();
+    let campaign_id = params.first("c");
+
+    // Links and pixels are signed when they're generated, so opens and clicks
+    // can't be forged. Anything else is dropped, and clicks go to the frontend.
+    let signed = params.first("token").is_some_and(|token| {
+        let key = tracking_key(event_type, &subscriber_id, campaign_id, params.first("url"));
+        signing::verify(&key, token)
+    });
+    if !signed {
+        info!("Dropping unsigned {} event", event_type.as_str());
+        return tracking_response(event_type, frontend);
+    }
+    let destination = params
+        .first("url")
+        .filter(|url| same_origin(url, &frontend))
+        .map(|url| url.to_string());
+
+    // Initialize AWS SDK
+    let dynamodb_client = dynamodb().await;
+    let events = EventRepository::new(dynamodb_client.clone());
+    let subscribers = SubscriberRepository::new(dynamodb_client.clone());
+
+    let mut tracked = Event::new(subscriber_id, event_type);
+    tracked.campaign_id = campaign_id.map(|id| id.to_string());
+    tracked.url = destination.clone();
+
+    // Tracking failures must never break the pixel or the redirect
+    if let Err(e) = events.record(&tracked).await {
+        info!("Error recording {} event: {:?}", event_type.as_str(), e);
+    }
+
+    // Fold the event into the subscriber's decayed engagement score
+    match subscribers
+        .record_engagement(
+            &tracked.subscriber_id,
+            event_type.engagement_weight(),
+            tracked.occurred_at,
+        )
+        .await
+    {
+        Ok(Some(subscriber)) => {
+            DomainStatsStore::new(dynamodb_client)
+                .record_or_log(
+                    &subscriber.email,
+                    DomainMetric::from_event(event_type),
+                    tracked.occurred_at,
+                )
+                .await;
+        }
+        Ok(None) => info!("Tracked event for unknown subscriber"),
+        Err(e) => info!("Error updating engagement score: {:?}", e),
+    }
+
+    tracking_response(event_type, destination.unwrap_or(frontend))
+}
+
+// The pixel for opens, a redirect to the destination for clicks
+fn tracking_response(event_type: EventType, destination: String) -> Result<Response<Body>, Error> {
+    let response = match event_type {
+        EventType::Open => Response::builder()
+            .status(200)
+            .header("Content-Type", "image/gif")
+            .header("Cache-Control", "no-store")
+            .body(Body::Binary(PIXEL_GIF.to_vec()))?,
+        // Clicks, the only other engagement event
+        _ => Response::builder()
+            .status(302)
+            .header("Location", destination)
+            .body(Body::Empty)?,
+    };
+
+    Ok(response)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            checked(event, InputLimits::PUBLIC, function_handler)
+        })
+    }))
+    .await
+}
@@ -1,15 +1,176 @@
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_dynamodb::{Client, types::AttributeValue};
+use aws_sdk_dynamodb::Client;
 use chrono::Utc;
-use lambda_http::{Body, Error, Request, Response, run, service_fn};
-use newsletter_backend::{ApiResponse, TABLE_NAME, UnsubscribeRequest, create_response};
+use lambda_http::{Body, Error, Request, RequestExt, Response, run, service_fn};
+use newsletter_backend::campaign::normalize_list;
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::domains::{DomainMetric, DomainStatsStore};
+use newsletter_backend::email::list_unsubscribe_key;
+use newsletter_backend::history::{HistoryRepository, LifecycleEvent, LifecycleEventType};
+use newsletter_backend::i18n::{Locale, translate};
+use newsletter_backend::input::{InputLimits, checked};
+use newsletter_backend::logging::log_request;
+use newsletter_backend::notify::{Notification, notify};
+use newsletter_backend::opt_out;
+use newsletter_backend::repository::SubscriberRepository;
+use newsletter_backend::template::escape_html;
+use newsletter_backend::versioning::versioned;
+use newsletter_backend::{ApiResponse, Subscriber, UnsubscribeRequest, create_response, signing};
 use tracing::info;
+use url::form_urlencoded;
+
+// Whether a link's token was signed for the subscriber, and for the list when
+// it only leaves one
+fn valid_token(id: &str, list: Option<&str>, token: &str) -> bool {
+    match list {
+        Some(list) => signing::verify(&list_unsubscribe_key(id, list), token),
+        None => signing::verify(id, token),
+    }
+}
+
+// Take the subscriber off the list, or opt them out of everything without one
+async fn unsubscribe(
+    dynamodb_client: &Client,
+    id: &str,
+    list: Option<&str>,
+    source: &str,
+) -> Result<Subscriber, newsletter_backend::Error> {
+    let repository = SubscriberRepository::new(dynamodb_client.clone());
+    let history = HistoryRepository::new(dynamodb_client.clone());
+
+    // Leaving a list isn't an opt-out, the subscriber still gets the others
+    if let Some(list) = list {
+        let subscriber = repository.leave_list(id, list).await?;
+        let event = LifecycleEvent::new(id, LifecycleEventType::Unsubscribed)
+            .with_source(source)
+            .with_detail(format!("Left list {list}"));
+        history.record_or_log(event).await;
+        return Ok(subscriber);
+    }
+
+    let subscriber = repository.unsubscribe(id).await?;
+    let event = LifecycleEvent::new(id, LifecycleEventType::Unsubscribed).with_source(source);
+    opt_out::publish(&event, &subscriber.email).await;
+    history.record_or_log(event).await;
+    DomainStatsStore::new(dynamodb_client.clone())
+        .record_or_log(&subscriber.email, DomainMetric::Unsubscribed, Utc::now())
+        .await;
+    notify(Notification::Unsubscribed {
+        email: subscriber.email.clone(),
+    })
+    .await;
+    Ok(subscriber)
+}
+
+// One-click unsubscribe (RFC 8058), posted by mail clients using the List-Unsubscribe header
+async fn one_click_unsubscribe(
+    event: &Request,
+    id: &str,
+    list: Option<&str>,
+    token: &str,
+) -> Result<Response<Body>, Error> {
+    let locale = Locale::from_request(event);
+
+    let body: &[u8] = match event.body() {
+        Body::Text(text) => text.as_bytes(),
+        Body::Binary(bytes) => bytes.as_slice(),
+        _ => &[],
+    };
+
+    let is_one_click = form_urlencoded::parse(body)
+        .any(|(key, value)| key == "List-Unsubscribe" && value == "One-Click");
+
+    if !is_one_click || !valid_token(id, list, token) {
+        return Ok(create_response(
+            400,
+            ApiResponse {
+                success: false,
+                message: translate(locale, "Invalid unsubscribe request"),
+                data: None,
+            },
+        ));
+    }
+
+    // Initialize AWS SDK
+    let dynamodb_client = dynamodb().await;
+
+    match unsubscribe(&dynamodb_client, id, list, "one_click").await {
+        Ok(_) => Ok(create_response(
+            200,
+            ApiResponse {
+                success: true,
+                message: translate(locale, "Successfully unsubscribed"),
+                data: None,
+            },
+        )),
+        Err(err) => {
+            info!("Error processing one-click unsubscribe: {:?}", err);
+            Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Failed to unsubscribe"),
+                    data: None,
+                },
+            ))
+        }
+    }
+}
+
+// Page asking to confirm an unsubscribe link, with a button posting the
+// one-click form back to it
+fn confirmation_page(
+    locale: Locale,
+    id: &str,
+    list: Option<&str>,
+    token: &str,
+) -> Result<Response<Body>, Error> {
+    if !valid_token(id, list, token) {
+        return Ok(create_response(
+            400,
+            ApiResponse {
+                success: false,
+                message: translate(locale, "Invalid unsubscribe request"),
+                data: None,
+            },
+        ));
+    }
+
+    let title = match list {
+        Some(_) => translate(locale, "Leave this list?"),
+        None => translate(locale, "Unsubscribe from this newsletter?"),
+    };
+    let title = escape_html(&title);
+    let button = escape_html(&translate(locale, "Unsubscribe"));
+    let html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{title}</title></head><body style=\"font-family:sans-serif;text-align:center;padding:48px 16px\">\
+         <h1 style=\"font-size:1.4em\">{title}</h1>\
+         <form method=\"post\"><input type=\"hidden\" name=\"List-Unsubscribe\" value=\"One-Click\">\
+         <button type=\"submit\" style=\"padding:10px 16px;font:inherit\">{button}</button></form>\
+         </body></html>"
+    );
+
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(html))?)
+}
 
 async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .init();
+    let locale = Locale::from_request(&event);
+
+    // Signed links from the List-Unsubscribe header carry the subscriber id and
+    // token. The same link in an email body is opened in a browser, which gets
+    // a page posting it, so link scanners following it don't unsubscribe anyone.
+    let params = event.query_string_parameters();
+    if let (Some(id), Some(token)) = (params.first("id"), params.first("token")) {
+        let list = params.first("list");
+        if event.method() == "GET" {
+            return confirmation_page(locale, id, list, token);
+        }
+        return one_click_unsubscribe(&event, id, list, token).await;
+    }
 
     // Parse request body
     let body = match event.body() {
@@ -19,7 +180,8 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                 400,
                 ApiResponse {
                     success: false,
-                    message: "Invalid request body".to_string(),
+                    message: translate(locale, "Invalid request body"),
+                    data: None,
                 },
             ));
         }
@@ -32,120 +194,74 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
                 400,
                 ApiResponse {
                     success: false,
-                    message: "Invalid JSON format".to_string(),
+                    message: translate(locale, "Invalid JSON format"),
+                    data: None,
                 },
             ));
         }
     };
 
-    // Initialize AWS SDK
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let dynamodb_client = Client::new(&config);
-
-    // Find the subscriber by email
-    let query_result = match dynamodb_client
-        .query()
-        .table_name(TABLE_NAME)
-        .index_name("email-index")
-        .key_condition_expression("email = :email")
-        .expression_attribute_values(
-            ":email",
-            AttributeValue::S(unsubscribe_request.email.clone()),
-        )
-        .send()
-        .await
-    {
-        Ok(result) => Ok(result),
-        Err(err) => {
-            info!("Error querying by email index: {:?}", err);
-            // If the index isn't ready yet, we'll do a scan as a fallback
-            let scan_result = dynamodb_client
-                .scan()
-                .table_name(TABLE_NAME)
-                .filter_expression("email = :email")
-                .expression_attribute_values(
-                    ":email",
-                    AttributeValue::S(unsubscribe_request.email.clone()),
-                )
-                .send()
-                .await;
-            Err(scan_result)
+    // A blank list is a mistake, not a request to opt out of everything
+    let list = match unsubscribe_request.list.as_deref().map(normalize_list) {
+        Some(None) => {
+            return Ok(create_response(
+                400,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Invalid list"),
+                    data: None,
+                },
+            ));
         }
+        list => list.flatten(),
     };
 
-    match query_result {
-        Ok(output) => {
-            if let Some(items) = output.items() {
-                if items.is_empty() {
-                    return Ok(create_response(
-                        404,
-                        ApiResponse {
-                            success: false,
-                            message: "Email not found in subscribers".to_string(),
-                        },
-                    ));
-                }
-
-                // Get the first match (should be only one)
-                if let Some(item) = items.first() {
-                    if let Some(id) = item.get("id") {
-                        if let Ok(id_str) = id.as_s() {
-                            // Update the subscriber to inactive
-                            let update_result = dynamodb_client
-                                .update_item()
-                                .table_name(TABLE_NAME)
-                                .key("id", AttributeValue::S(id_str.clone()))
-                                .update_expression("SET active = :active, updated_at = :updated_at")
-                                .expression_attribute_values(":active", AttributeValue::Bool(false))
-                                .expression_attribute_values(
-                                    ":updated_at",
-                                    AttributeValue::S(Utc::now().to_rfc3339()),
-                                )
-                                .send()
-                                .await;
-
-                            match update_result {
-                                Ok(_) => {
-                                    return Ok(create_response(
-                                        200,
-                                        ApiResponse {
-                                            success: true,
-                                            message: "Successfully unsubscribed".to_string(),
-                                        },
-                                    ));
-                                }
-                                Err(err) => {
-                                    info!("Error updating subscriber: {:?}", err);
-                                    return Ok(create_response(
-                                        500,
-                                        ApiResponse {
-                                            success: false,
-                                            message: "Failed to unsubscribe".to_string(),
-                                        },
-                                    ));
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    // Initialize AWS SDK
+    let dynamodb_client = dynamodb().await;
+    let repository = SubscriberRepository::new(dynamodb_client.clone());
 
-            Ok(create_response(
+    let subscriber = match repository.find_by_email(&unsubscribe_request.email).await {
+        Ok(Some(subscriber)) => subscriber,
+        Ok(None) => {
+            return Ok(create_response(
                 404,
                 ApiResponse {
                     success: false,
-                    message: "Subscriber not found".to_string(),
+                    message: translate(locale, "Email not found in subscribers"),
+                    data: None,
                 },
-            ))
+            ));
         }
         Err(err) => {
             info!("Error querying DynamoDB: {:?}", err);
+            return Ok(create_response(
+                500,
+                ApiResponse {
+                    success: false,
+                    message: translate(locale, "Error processing unsubscribe request"),
+                    data: None,
+                },
+            ));
+        }
+    };
+
+    match unsubscribe(&dynamodb_client, &subscriber.id, list.as_deref(), "api").await {
+        Ok(_) => Ok(create_response(
+            200,
+            ApiResponse {
+                success: true,
+                message: translate(locale, "Successfully unsubscribed"),
+                data: None,
+            },
+        )),
+        Err(err) => {
+            info!("Error updating subscriber: {:?}", err);
             Ok(create_response(
                 500,
                 ApiResponse {
                     success: false,
-                    message: "Error processing unsubscribe request".to_string(),
+                    message: translate(locale, "Failed to unsubscribe"),
+                    data: None,
                 },
             ))
         }
@@ -154,5 +270,16 @@ async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    run(service_fn(function_handler)).await
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            versioned(event, |event| {
+                checked(event, InputLimits::PUBLIC, function_handler)
+            })
+        })
+    }))
+    .await
 }
@@ -1,103 +1,262 @@
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_dynamodb::Client;
-use aws_sdk_dynamodb::types::AttributeValue;
-use chrono::{DateTime, Duration, Utc};
+use aws_sdk_s3::Client as S3Client;
+use chrono::Utc;
 use lambda_runtime::{Error, LambdaEvent, run, service_fn};
-use newsletter_backend::{Subscriber, TABLE_NAME};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use newsletter_backend::clients::{dynamodb, sdk_config};
+use newsletter_backend::config::{TokenPolicy, env_or};
+use newsletter_backend::email_queue::{EmailKind, Mailer};
+use newsletter_backend::i18n::EmailTemplate;
+use newsletter_backend::metrics::emit_count;
+use newsletter_backend::processed::{Claim, ProcessedMessages};
+use newsletter_backend::queue::{
+    PayloadError, REACTIVATE_ACTION, SqsBatchResponse, SqsEvent, SqsRecord, decode, is_fifo,
+};
+use newsletter_backend::redrive::{PoisonArchive, PoisonMessage};
+use newsletter_backend::repository::SubscriberRepository;
+use newsletter_backend::template::subscriber_variables;
+use newsletter_backend::{ValidationMessage, validation_url};
+use std::env;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 use tracing::info;
-use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SqsEvent {
-    #[serde(rename = "Records")]
-    records: Vec<SqsRecord>,
-}
+// Records of a standard queue batch processed at once, from VALIDATE_CONCURRENCY
+const DEFAULT_CONCURRENCY: usize = 10;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SqsRecord {
-    #[serde(rename = "messageId")]
-    message_id: String,
-    #[serde(rename = "body")]
-    body: String,
+struct Worker {
+    repository: SubscriberRepository,
+    processed: ProcessedMessages,
+    mailer: Option<Mailer>,
+    policy: TokenPolicy,
+    // From BACKUP_BUCKET. Without it malformed messages are retried until they
+    // reach the dead-letter queue.
+    poison: Option<PoisonArchive>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ValidationMessage {
-    action: String,
-    email: String,
-    subscriber_id: String,
-}
+impl Worker {
+    // Process a message unless an earlier delivery of it already was, so a
+    // duplicate doesn't send a second confirmation email with a new token
+    async fn process_once(&self, record: &SqsRecord, lease_until: i64) -> bool {
+        match self.processed.claim(&record.message_id, lease_until).await {
+            Ok(Claim::New) => {}
+            Ok(Claim::Processed) => {
+                info!("Skipping already processed message {}", record.message_id);
+                emit_count("DuplicateMessagesSkipped", &[("Consumer", "validate")]);
+                return true;
+            }
+            // Back to the queue, by when the other delivery has settled it
+            Ok(Claim::InProgress) => return false,
+            Err(e) => {
+                info!("Error claiming message {}: {:?}", record.message_id, e);
+                return false;
+            }
+        }
 
-async fn function_handler(event: LambdaEvent<SqsEvent>) -> Result<(), Error> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .init();
+        let processed = self.process(record).await;
+        self.processed
+            .settle_or_log(&record.message_id, processed)
+            .await;
+        processed
+    }
+
+    // Store a fresh token and send the confirmation email. Returns false when
+    // the record should go back to the queue.
+    async fn process(&self, record: &SqsRecord) -> bool {
+        let message: ValidationMessage = match decode(&record.body) {
+            Ok(message) => message,
+            Err(PayloadError::Malformed(e)) => return self.quarantine(record, e).await,
+            // Retried until this worker is deployed with the producer's version
+            Err(e @ PayloadError::UnsupportedVersion(_)) => {
+                info!("Can't read message {} yet: {}", record.message_id, e);
+                return false;
+            }
+        };
+
+        info!("Processing validation for email: {}", message.email);
+
+        let token = self.policy.generate_token();
+
+        // Store the token on the subscriber
+        let subscriber = match self
+            .repository
+            .set_validation_token(
+                &message.subscriber_id,
+                &token,
+                &self.policy,
+                message.action != REACTIVATE_ACTION,
+            )
+            .await
+        {
+            Ok(Some(subscriber)) => subscriber,
+            // Possibly a read racing the subscribe write, so retry it too
+            Ok(None) => {
+                info!("Subscriber {} not found", message.subscriber_id);
+                return false;
+            }
+            Err(e) => {
+                info!("Error storing validation token: {:?}", e);
+                return false;
+            }
+        };
+
+        // Approval enqueues the subscriber again
+        if subscriber.is_quarantined() {
+            info!("Skipping quarantined subscriber {}", subscriber.id);
+            return true;
+        }
+
+        // Generate the validation URL that would be included in the email
+        let validation_url = validation_url(&message.subscriber_id, &token);
+
+        info!("Generated validation URL: {}", validation_url);
+
+        let mut variables = subscriber_variables(&subscriber);
+        variables.insert("confirm_url".to_string(), validation_url);
+        variables.insert("valid_hours".to_string(), self.policy.ttl_hours.to_string());
+        let (subject, html_body) =
+            EmailTemplate::Confirmation.render(subscriber.locale, &variables);
+
+        match &self.mailer {
+            Some(mailer) => match mailer
+                .send(
+                    EmailKind::Confirmation,
+                    &subscriber,
+                    None,
+                    &subject,
+                    &html_body,
+                )
+                .await
+            {
+                Ok(_) => {
+                    info!("Sent validation email to: {}", message.email);
+                    true
+                }
+                Err(e) => {
+                    info!("Failed to send validation email: {:?}", e);
+                    false
+                }
+            },
+            // Without a sender address we just log, as in local development
+            None => {
+                info!(
+                    "Simulated email sent to: {} with subject {:?}",
+                    message.email, subject
+                );
+                true
+            }
+        }
+    }
+
+    // Move a message that can never parse out of the queue, with its raw body
+    // and the error. Returns false when it couldn't be stored, so SQS keeps it.
+    async fn quarantine(&self, record: &SqsRecord, error: serde_json::Error) -> bool {
+        info!(
+            "Malformed validation message {}: {}",
+            record.message_id, error
+        );
+        emit_count("PoisonValidationMessages", &[]);
+
+        let Some(poison) = &self.poison else {
+            return false;
+        };
+        let message = PoisonMessage {
+            message_id: record.message_id.clone(),
+            received_at: Utc::now(),
+            error: error.to_string(),
+            body: record.body.clone(),
+        };
+        match poison.store(&message).await {
+            Ok(key) => {
+                info!("Quarantined message {} to {}", record.message_id, key);
+                true
+            }
+            Err(e) => {
+                info!("Error quarantining message {}: {:?}", record.message_id, e);
+                false
+            }
+        }
+    }
+}
 
+async fn function_handler(event: LambdaEvent<SqsEvent>) -> Result<SqsBatchResponse, Error> {
     // Initialize AWS SDK
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let config = aws_config::from_env().region(region_provider).load().await;
-    let dynamodb_client = Client::new(&config);
+    let config = sdk_config().await;
+    let dynamodb_client = dynamodb().await;
+    let worker = Arc::new(Worker {
+        repository: SubscriberRepository::new(dynamodb_client.clone()),
+        processed: ProcessedMessages::new(dynamodb_client, "validate"),
+        mailer: env::var("SENDER_EMAIL")
+            .ok()
+            .map(|sender_email| Mailer::new(config, sender_email)),
+        policy: TokenPolicy::from_env(),
+        poison: env::var("BACKUP_BUCKET").ok().map(|bucket| {
+            PoisonArchive::new(
+                S3Client::new(config),
+                bucket,
+                "dlq/validation/poison".to_string(),
+            )
+        }),
+    });
 
     info!("Processing {} SQS records", event.payload.records.len());
+    // Claims last as long as this invocation can
+    let lease_until = event.context.deadline as i64;
 
-    for record in event.payload.records {
-        // Parse the message from the SQS record
-        let message_result: Result<ValidationMessage, serde_json::Error> =
-            serde_json::from_str(&record.body);
-
-        match message_result {
-            Ok(message) => {
-                info!("Processing validation for email: {}", message.email);
-
-                // Generate a validation token with UUID
-                let token = Uuid::new_v4().to_string();
-
-                // Calculate expiration (24 hours from now)
-                let expiration = Utc::now() + Duration::hours(24);
-
-                // Store the token in DynamoDB
-                let update_result = dynamodb_client
-                    .update_item()
-                    .table_name(TABLE_NAME)
-                    .key("id", aws_sdk_dynamodb::types::AttributeValue::S(message.subscriber_id.clone()))
-                    .update_expression("SET validation_token = :token, token_expiration = :expiration, updated_at = :updated_at")
-                    .expression_attribute_values(":token", AttributeValue::S(token.clone()))
-                    .expression_attribute_values(":expiration", AttributeValue::S(expiration.to_rfc3339()))
-                    .expression_attribute_values(":updated_at", AttributeValue::S(Utc::now().to_rfc3339()))
-                    .send()
-                    .await;
-
-                match update_result {
-                    Ok(_) => {
-                        // Generate the validation URL that would be included in the email
-                        let validation_url = format!(
-                            "https://yourfrontend.com/validate?id={}&token={}",
-                            message.subscriber_id, token
-                        );
-
-                        info!("Generated validation URL: {}", validation_url);
-
-                        // In a real application, you would send an email with the validation URL
-                        // For demo purposes, we'll just log the URL and simulate the email sending
-                        info!(
-                            "Simulated email sent to: {} with validation URL",
-                            message.email
-                        );
-                    }
-                    Err(e) => info!("Error storing validation token: {:?}", e),
-                }
+    let mut response = SqsBatchResponse::default();
+    let (fifo, standard): (Vec<_>, Vec<_>) = event
+        .payload
+        .records
+        .into_iter()
+        .partition(|record| is_fifo(&record.event_source_arn));
+
+    // FIFO queues deliver in order, so once a record fails the rest of the
+    // batch has to be returned too or later messages would overtake it
+    for record in fifo {
+        if !response.batch_item_failures.is_empty()
+            || !worker.process_once(&record, lease_until).await
+        {
+            response.fail(record.message_id);
+        }
+    }
+
+    // Standard queues don't order messages, so a burst after an import is
+    // worked through several records at a time
+    let permits = Arc::new(Semaphore::new(
+        env_or("VALIDATE_CONCURRENCY", DEFAULT_CONCURRENCY).max(1),
+    ));
+    let tasks: Vec<_> = standard
+        .into_iter()
+        .map(|record| {
+            let worker = worker.clone();
+            let permits = permits.clone();
+            let message_id = record.message_id.clone();
+            let task = tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                worker.process_once(&record, lease_until).await
+            });
+            (message_id, task)
+        })
+        .collect();
+
+    for (message_id, task) in tasks {
+        match task.await {
+            Ok(true) => {}
+            Ok(false) => response.fail(message_id),
+            Err(e) => {
+                info!("Validation task failed: {:?}", e);
+                response.fail(message_id);
             }
-            Err(e) => info!("Error parsing SQS message: {:?}", e),
         }
     }
 
-    Ok(())
+    Ok(response)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    // Once per container, a second init on a warm invocation would panic
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
     run(service_fn(function_handler)).await
 }
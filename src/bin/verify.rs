@@ -0,0 +1,106 @@
+use chrono::{Duration, Utc};
+use lambda_runtime::{Error, LambdaEvent, run, service_fn};
+use newsletter_backend::Subscriber;
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::history::{HistoryRepository, LifecycleEvent, LifecycleEventType};
+use newsletter_backend::opt_out;
+use newsletter_backend::repository::SubscriberRepository;
+use newsletter_backend::verification::{EmailVerifier, HttpVerifier, Verdict};
+use serde_json::Value;
+use std::env;
+use tracing::info;
+
+// Read a numeric setting from the environment, falling back to a default
+fn env_or(name: &str, default: i64) -> i64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Verify each candidate, storing the verdict and suppressing undeliverable
+// addresses. Returns how many were checked and how many were suppressed.
+async fn verify_subscribers<V: EmailVerifier>(
+    verifier: &V,
+    subscribers: &SubscriberRepository,
+    history: &HistoryRepository,
+    candidates: &[Subscriber],
+) -> Result<(u32, u32), Error> {
+    let (mut checked, mut suppressed) = (0, 0);
+
+    for subscriber in candidates {
+        let verdict = match verifier.verify(&subscriber.email).await {
+            Ok(verdict) => verdict,
+            Err(e) => {
+                info!("Error verifying subscriber {}: {:?}", subscriber.id, e);
+                continue;
+            }
+        };
+
+        subscribers
+            .set_verification(&subscriber.id, verdict)
+            .await?;
+        checked += 1;
+
+        if verdict == Verdict::Undeliverable {
+            let event = LifecycleEvent::new(&subscriber.id, LifecycleEventType::Suppressed)
+                .with_source("verification")
+                .with_detail(verdict.as_str());
+            opt_out::publish(&event, &subscriber.email).await;
+            history.record_or_log(event).await;
+            info!("Suppressed undeliverable subscriber {}", subscriber.id);
+            suppressed += 1;
+        }
+    }
+
+    Ok((checked, suppressed))
+}
+
+async fn function_handler(_event: LambdaEvent<Value>) -> Result<(), Error> {
+    let Some(verifier) = HttpVerifier::from_env() else {
+        info!("Email verification is not configured, skipping");
+        return Ok(());
+    };
+
+    // Verdicts older than this are checked again
+    let max_age_days = env_or("VERIFICATION_MAX_AGE_DAYS", 90);
+    // Upper bound of provider calls per run, to keep the bill predictable
+    let batch_limit = env_or("VERIFICATION_BATCH_LIMIT", 500) as usize;
+
+    // Initialize AWS SDK
+    let dynamodb_client = dynamodb().await;
+    let subscribers = SubscriberRepository::new(dynamodb_client.clone());
+    let history = HistoryRepository::new(dynamodb_client);
+
+    let cutoff = Utc::now() - Duration::days(max_age_days);
+    let mut candidates: Vec<Subscriber> = subscribers
+        .list_all()
+        .await?
+        .into_iter()
+        .filter(|subscriber| subscriber.active && !subscriber.suppressed)
+        .filter(|subscriber| subscriber.verified_at.is_none_or(|at| at < cutoff))
+        .collect();
+
+    // Never-verified addresses first, then the stalest verdicts
+    candidates.sort_by_key(|subscriber| subscriber.verified_at);
+    candidates.truncate(batch_limit);
+
+    let (checked, suppressed) =
+        verify_subscribers(&verifier, &subscribers, &history, &candidates).await?;
+
+    info!(
+        "Verification run complete: {} checked, {} suppressed",
+        checked, suppressed
+    );
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(function_handler)).await
+}
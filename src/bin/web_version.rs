@@ -0,0 +1,102 @@
+use lambda_http::{Body, Error, Request, RequestExt, Response, run, service_fn};
+use newsletter_backend::campaign::CampaignStatus;
+use newsletter_backend::clients::dynamodb;
+use newsletter_backend::content::render_campaign_html;
+use newsletter_backend::email::web_version_key;
+use newsletter_backend::input::{InputLimits, checked};
+use newsletter_backend::logging::log_request;
+use newsletter_backend::repository::{CampaignRepository, SubscriberRepository};
+use newsletter_backend::template::{escape_html, render_text, subscriber_variables};
+use newsletter_backend::{ApiResponse, create_response, signing};
+
+fn not_found() -> Response<Body> {
+    create_response(
+        404,
+        ApiResponse {
+            success: false,
+            message: "Campaign not found".to_string(),
+            data: None,
+        },
+    )
+}
+
+// Hosted web version of a campaign, rendered for the subscriber the signed
+// `{{ web_version_url }}` link was sent to, as in their email
+async fn function_handler(event: Request) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters();
+    let (Some(campaign_id), Some(subscriber_id), Some(token)) =
+        (params.first("c"), params.first("s"), params.first("token"))
+    else {
+        return Ok(create_response(
+            400,
+            ApiResponse {
+                success: false,
+                message: "Invalid web version link".to_string(),
+                data: None,
+            },
+        ));
+    };
+    if !signing::verify(&web_version_key(subscriber_id, campaign_id), token) {
+        return Ok(create_response(
+            400,
+            ApiResponse {
+                success: false,
+                message: "Invalid web version link".to_string(),
+                data: None,
+            },
+        ));
+    }
+
+    // Initialize AWS SDK
+    let dynamodb_client = dynamodb().await;
+    let campaigns = CampaignRepository::new(dynamodb_client.clone());
+    let subscribers = SubscriberRepository::new(dynamodb_client);
+
+    // Drafts haven't gone out, so there is nothing to show for them
+    let Some(campaign) = campaigns
+        .get(campaign_id)
+        .await?
+        .filter(|campaign| campaign.status != CampaignStatus::Draft)
+    else {
+        return Ok(not_found());
+    };
+    let Some(subscriber) = subscribers.get(subscriber_id).await? else {
+        return Ok(not_found());
+    };
+
+    let subject = render_text(&campaign.subject, &subscriber_variables(&subscriber));
+    let html_body = render_campaign_html(
+        &campaign.html_body,
+        &subscriber,
+        &campaign.id,
+        campaign.list.as_deref(),
+    );
+    // Sanitizing leaves a fragment, even of a body written as a whole document
+    let html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{}</title></head><body>{}</body></html>",
+        escape_html(&subject),
+        html_body
+    );
+
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Cache-Control", "private, no-store")
+        .body(Body::from(html))?)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    run(service_fn(|event| {
+        log_request(event, |event| {
+            checked(event, InputLimits::PUBLIC, function_handler)
+        })
+    }))
+    .await
+}
@@ -0,0 +1,472 @@
+use crate::content::ContentIssue;
+use crate::segment::Segment;
+use crate::{Error, Subscriber, optional_datetime};
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// Data of a campaign sent or scheduled through the admin API
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CampaignSendData {
+    pub campaign_id: String,
+    // Emails sent, or subscribers in the snapshot of a send scheduled at a local time
+    pub recipients: usize,
+    // Timezones a scheduled send goes out in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezones: Option<usize>,
+    // Recipients snapshotted for a scheduled send, the only ones who can get it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_count: Option<usize>,
+    // Spam signals found in the content, which didn't stop it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content_warnings: Vec<ContentIssue>,
+}
+
+// Data of a cancelled campaign, or one being cancelled
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CampaignCancelData {
+    pub campaign_id: String,
+    // "cancelling" while emails are still being skipped, then "cancelled"
+    pub status: String,
+    pub sent_count: u32,
+    pub skipped_count: u32,
+}
+
+// A send without a checkpoint for this long has died, as no Lambda runs longer
+// than 15 minutes
+const STALLED_AFTER_MINUTES: i64 = 20;
+
+// How far a send got. Recipients go out in id order and progress is saved
+// every few emails, so a send cut short by a timeout or crash resumes after the
+// last recipient handled instead of mailing the first part of the list again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SendCheckpoint {
+    // None until the first checkpoint
+    pub last_subscriber_id: Option<String>,
+    // Emails sent up to and including the last recipient
+    pub sent: u32,
+    pub at: DateTime<Utc>,
+}
+
+impl SendCheckpoint {
+    pub fn start() -> Self {
+        Self {
+            last_subscriber_id: None,
+            sent: 0,
+            at: Utc::now(),
+        }
+    }
+
+    // Whether a recipient still has to be handled when resuming from here
+    pub fn is_pending(&self, subscriber_id: &str) -> bool {
+        self.last_subscriber_id
+            .as_deref()
+            .is_none_or(|last| subscriber_id > last)
+    }
+
+    pub fn is_stalled(&self, now: DateTime<Utc>) -> bool {
+        now - self.at > Duration::minutes(STALLED_AFTER_MINUTES)
+    }
+
+    pub fn insert_into(&self, item: &mut HashMap<String, AttributeValue>) {
+        if let Some(last_subscriber_id) = &self.last_subscriber_id {
+            item.insert(
+                "checkpoint_subscriber_id".to_string(),
+                AttributeValue::S(last_subscriber_id.clone()),
+            );
+        }
+        item.insert(
+            "checkpoint_sent".to_string(),
+            AttributeValue::N(self.sent.to_string()),
+        );
+        item.insert(
+            "checkpoint_at".to_string(),
+            AttributeValue::S(self.at.to_rfc3339()),
+        );
+    }
+
+    pub fn from_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        Some(Self {
+            last_subscriber_id: item
+                .get("checkpoint_subscriber_id")
+                .and_then(|value| value.as_s().ok())
+                .cloned(),
+            sent: item
+                .get("checkpoint_sent")
+                .and_then(|value| value.as_n().ok())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0),
+            at: optional_datetime(item, "checkpoint_at")?,
+        })
+    }
+}
+
+// A list name as stored, trimmed and lowercased, None when it's blank. Lists
+// aren't declared anywhere, a campaign sent to a new name starts one.
+pub fn normalize_list(list: &str) -> Option<String> {
+    let list = list.trim().to_lowercase();
+    (!list.is_empty()).then_some(list)
+}
+
+// Subscribers left out of a campaign even though they are in its segment,
+// e.g. customers who already bought what a promo is for
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Exclusions {
+    // Subscribers with any of these tags, from the `tags` custom field set on import
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Segment expression, subscribers matching it are left out
+    #[serde(default)]
+    pub segment: Option<String>,
+    // Addresses left out, compared case-insensitively
+    #[serde(default)]
+    pub emails: Vec<String>,
+}
+
+impl Exclusions {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.segment.is_none() && self.emails.is_empty()
+    }
+
+    // Trims and lowercases tags and addresses, dropping blanks and duplicates,
+    // and rejects an invalid segment now rather than at send time
+    pub fn normalized(self) -> Result<Self, Error> {
+        let segment = self.segment.filter(|segment| !segment.trim().is_empty());
+        if let Some(segment) = &segment {
+            Segment::parse(segment)?;
+        }
+        let normalize = |values: Vec<String>| {
+            let mut values: Vec<String> = values
+                .iter()
+                .map(|value| value.trim().to_lowercase())
+                .filter(|value| !value.is_empty())
+                .collect();
+            values.sort();
+            values.dedup();
+            values
+        };
+
+        Ok(Self {
+            tags: normalize(self.tags),
+            segment,
+            emails: normalize(self.emails),
+        })
+    }
+
+    // Whether a subscriber is excluded by tag or address. The segment is
+    // parsed once per send, so it is matched by the caller.
+    pub fn excludes(&self, subscriber: &Subscriber) -> bool {
+        let email = subscriber.email.to_lowercase();
+        if self.emails.contains(&email) {
+            return true;
+        }
+        subscriber.fields.get("tags").is_some_and(|tags| {
+            tags.split(',')
+                .any(|tag| self.tags.contains(&tag.trim().to_lowercase()))
+        })
+    }
+
+    // String sets can't be empty, so empty lists are left out of the item
+    pub fn insert_into(&self, item: &mut HashMap<String, AttributeValue>) {
+        if !self.tags.is_empty() {
+            item.insert(
+                "exclude_tags".to_string(),
+                AttributeValue::Ss(self.tags.clone()),
+            );
+        }
+        if let Some(segment) = &self.segment {
+            item.insert(
+                "exclude_segment".to_string(),
+                AttributeValue::S(segment.clone()),
+            );
+        }
+        if !self.emails.is_empty() {
+            item.insert(
+                "exclude_emails".to_string(),
+                AttributeValue::Ss(self.emails.clone()),
+            );
+        }
+    }
+
+    pub fn from_item(item: &HashMap<String, AttributeValue>) -> Self {
+        let set = |name: &str| {
+            item.get(name)
+                .and_then(|value| value.as_ss().ok())
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        Self {
+            tags: set("exclude_tags"),
+            segment: item
+                .get("exclude_segment")
+                .and_then(|value| value.as_s().ok())
+                .cloned(),
+            emails: set("exclude_emails"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CampaignStatus {
+    Draft,
+    // Waiting for its timezone buckets to go out, see `schedule`
+    Scheduled,
+    Sending,
+    Sent,
+    // Asked to stop; senders and the send queue worker skip its remaining emails
+    Cancelling,
+    // Stopped, with `sent_count` sent and `skipped_count` never sent
+    Cancelled,
+}
+
+impl CampaignStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CampaignStatus::Draft => "draft",
+            CampaignStatus::Scheduled => "scheduled",
+            CampaignStatus::Sending => "sending",
+            CampaignStatus::Sent => "sent",
+            CampaignStatus::Cancelling => "cancelling",
+            CampaignStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "draft" => Some(CampaignStatus::Draft),
+            "scheduled" => Some(CampaignStatus::Scheduled),
+            "sending" => Some(CampaignStatus::Sending),
+            "sent" => Some(CampaignStatus::Sent),
+            "cancelling" => Some(CampaignStatus::Cancelling),
+            "cancelled" => Some(CampaignStatus::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Campaign {
+    pub id: String,
+    pub subject: String,
+    pub html_body: String,
+    // Segment expression restricting the recipients, see `segment::Segment`
+    pub segment: Option<String>,
+    // List the campaign goes out on, e.g. "product-updates". Subscribers who
+    // left it don't get it, see `Subscriber::is_mailable_on`. None is the
+    // whole newsletter, which only a global opt-out stops.
+    pub list: Option<String>,
+    // Subscribers in the segment who still don't get it
+    pub exclusions: Exclusions,
+    // Sender identity the campaign goes out from, SENDER_EMAIL when not set
+    pub sender_id: Option<String>,
+    // Saved template the content comes from, see `templates`. Its published
+    // version is copied in when the campaign is sent or scheduled.
+    pub template_id: Option<String>,
+    pub template_version: Option<u32>,
+    // Recipients resolved when the campaign was scheduled. Only they can get
+    // it, so late subscribers don't receive an issue planned before they joined.
+    pub snapshot_count: Option<u32>,
+    pub status: CampaignStatus,
+    pub sent_count: u32,
+    // Emails left out because the campaign was cancelled
+    pub skipped_count: u32,
+    // Progress of a send in progress, see `SendCheckpoint`
+    pub checkpoint: Option<SendCheckpoint>,
+    // Step Functions execution sending the campaign, see `workflow`. Such a
+    // send has no checkpoint, as the state machine retries it.
+    pub workflow_execution: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+impl Campaign {
+    pub fn new(subject: String, html_body: String, segment: Option<String>) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            subject,
+            html_body,
+            segment,
+            list: None,
+            exclusions: Exclusions::default(),
+            sender_id: None,
+            template_id: None,
+            template_version: None,
+            snapshot_count: None,
+            status: CampaignStatus::Draft,
+            sent_count: 0,
+            skipped_count: 0,
+            checkpoint: None,
+            workflow_execution: None,
+            created_at: now,
+            updated_at: now,
+            sent_at: None,
+        }
+    }
+
+    // A send that died mid-way and can be resumed from its checkpoint
+    pub fn is_stalled(&self, now: DateTime<Utc>) -> bool {
+        self.status == CampaignStatus::Sending
+            && self
+                .checkpoint
+                .as_ref()
+                .is_some_and(|checkpoint| checkpoint.is_stalled(now))
+    }
+
+    pub fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+
+        item.insert("id".to_string(), AttributeValue::S(self.id.clone()));
+        item.insert(
+            "subject".to_string(),
+            AttributeValue::S(self.subject.clone()),
+        );
+        item.insert(
+            "html_body".to_string(),
+            AttributeValue::S(self.html_body.clone()),
+        );
+        if let Some(segment) = &self.segment {
+            item.insert("segment".to_string(), AttributeValue::S(segment.clone()));
+        }
+        if let Some(list) = &self.list {
+            item.insert("list".to_string(), AttributeValue::S(list.clone()));
+        }
+        self.exclusions.insert_into(&mut item);
+        if let Some(sender_id) = &self.sender_id {
+            item.insert(
+                "sender_id".to_string(),
+                AttributeValue::S(sender_id.clone()),
+            );
+        }
+        if let Some(template_id) = &self.template_id {
+            item.insert(
+                "template_id".to_string(),
+                AttributeValue::S(template_id.clone()),
+            );
+        }
+        if let Some(template_version) = self.template_version {
+            item.insert(
+                "template_version".to_string(),
+                AttributeValue::N(template_version.to_string()),
+            );
+        }
+        if let Some(snapshot_count) = self.snapshot_count {
+            item.insert(
+                "snapshot_count".to_string(),
+                AttributeValue::N(snapshot_count.to_string()),
+            );
+        }
+        item.insert(
+            "status".to_string(),
+            AttributeValue::S(self.status.as_str().to_string()),
+        );
+        item.insert(
+            "sent_count".to_string(),
+            AttributeValue::N(self.sent_count.to_string()),
+        );
+        item.insert(
+            "skipped_count".to_string(),
+            AttributeValue::N(self.skipped_count.to_string()),
+        );
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.insert_into(&mut item);
+        }
+        if let Some(workflow_execution) = &self.workflow_execution {
+            item.insert(
+                "workflow_execution".to_string(),
+                AttributeValue::S(workflow_execution.clone()),
+            );
+        }
+        item.insert(
+            "created_at".to_string(),
+            AttributeValue::S(self.created_at.to_rfc3339()),
+        );
+        item.insert(
+            "updated_at".to_string(),
+            AttributeValue::S(self.updated_at.to_rfc3339()),
+        );
+        if let Some(sent_at) = self.sent_at {
+            item.insert(
+                "sent_at".to_string(),
+                AttributeValue::S(sent_at.to_rfc3339()),
+            );
+        }
+
+        item
+    }
+
+    pub fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let id = item.get("id")?.as_s().ok()?;
+        let subject = item.get("subject")?.as_s().ok()?;
+        let html_body = item.get("html_body")?.as_s().ok()?;
+        let segment = item
+            .get("segment")
+            .and_then(|value| value.as_s().ok())
+            .cloned();
+        let list = item
+            .get("list")
+            .and_then(|value| value.as_s().ok())
+            .cloned();
+        let sender_id = item
+            .get("sender_id")
+            .and_then(|value| value.as_s().ok())
+            .cloned();
+        let template_id = item
+            .get("template_id")
+            .and_then(|value| value.as_s().ok())
+            .cloned();
+        let template_version = item
+            .get("template_version")
+            .and_then(|value| value.as_n().ok())
+            .and_then(|value| value.parse().ok());
+        let snapshot_count = item
+            .get("snapshot_count")
+            .and_then(|value| value.as_n().ok())
+            .and_then(|value| value.parse().ok());
+        let workflow_execution = item
+            .get("workflow_execution")
+            .and_then(|value| value.as_s().ok())
+            .cloned();
+        let status = CampaignStatus::parse(item.get("status")?.as_s().ok()?)?;
+        let sent_count = item.get("sent_count")?.as_n().ok()?.parse().ok()?;
+        let skipped_count = item
+            .get("skipped_count")
+            .and_then(|value| value.as_n().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+        let created_at = DateTime::parse_from_rfc3339(item.get("created_at")?.as_s().ok()?)
+            .ok()?
+            .with_timezone(&Utc);
+        let updated_at = DateTime::parse_from_rfc3339(item.get("updated_at")?.as_s().ok()?)
+            .ok()?
+            .with_timezone(&Utc);
+        let sent_at = optional_datetime(item, "sent_at");
+
+        Some(Self {
+            id: id.clone(),
+            subject: subject.clone(),
+            html_body: html_body.clone(),
+            segment,
+            list,
+            exclusions: Exclusions::from_item(item),
+            sender_id,
+            template_id,
+            template_version,
+            snapshot_count,
+            status,
+            sent_count,
+            skipped_count,
+            checkpoint: SendCheckpoint::from_item(item),
+            workflow_execution,
+            created_at,
+            updated_at,
+            sent_at,
+        })
+    }
+}
@@ -0,0 +1,63 @@
+use aws_config::SdkConfig;
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_dynamodb::Client as DynamoDbClient;
+use std::sync::LazyLock;
+use tokio::sync::OnceCell;
+
+// Clients shared by every invocation a Lambda container serves. The first
+// (cold) invocation resolves the region and credentials and opens connections;
+// warm ones reuse them instead of paying for that on every request.
+
+// The container's AWS config
+pub async fn sdk_config() -> &'static SdkConfig {
+    static CONFIG: OnceCell<SdkConfig> = OnceCell::const_new();
+    CONFIG
+        .get_or_init(|| async {
+            let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+            aws_config::from_env().region(region_provider).load().await
+        })
+        .await
+}
+
+// DynamoDB client, cheap to clone as clones share its connection pool
+pub async fn dynamodb() -> DynamoDbClient {
+    static CLIENT: OnceCell<DynamoDbClient> = OnceCell::const_new();
+    CLIENT
+        .get_or_init(|| async { DynamoDbClient::new(sdk_config().await) })
+        .await
+        .clone()
+}
+
+// HTTP client for webhooks, link checks and verification providers, keeping
+// TLS connections open between invocations. Timeouts are set per request.
+pub fn http() -> &'static reqwest::Client {
+    static HTTP: LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::new);
+    &HTTP
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[tokio::test]
+    async fn later_calls_reuse_the_cached_clients() {
+        // SAFETY: no other test reads the region. Set so loading the config
+        // doesn't wait on the instance metadata service.
+        unsafe { std::env::set_var("AWS_REGION", "eu-west-1") };
+
+        let config = sdk_config().await;
+        assert!(ptr::eq(config, sdk_config().await));
+
+        // Clones share the cached client's config and connection pool
+        let first = dynamodb().await;
+        let second = dynamodb().await;
+        assert!(ptr::eq(first.config(), second.config()));
+        assert_eq!(
+            first.config().region().map(|region| region.as_ref()),
+            Some("eu-west-1")
+        );
+
+        assert!(ptr::eq(http(), http()));
+    }
+}
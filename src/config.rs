@@ -0,0 +1,141 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use std::env;
+use uuid::Uuid;
+
+// Read a numeric setting from the environment, falling back to a default
+pub fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+// Whether new subscribers must confirm their address through an emailed link,
+// from DOUBLE_OPT_IN. With it off they are confirmed on signup and welcomed.
+pub fn double_opt_in() -> bool {
+    env_or("DOUBLE_OPT_IN", true)
+}
+
+// Deployment stage from STAGE, e.g. "dev" or "prod", so several stages can
+// share one account. None when unset.
+pub fn stage() -> Option<String> {
+    env::var("STAGE")
+        .ok()
+        .map(|stage| stage.trim().to_string())
+        .filter(|stage| !stage.is_empty())
+}
+
+// Whether this is a production deployment: no STAGE, or STAGE=prod or production
+pub fn is_production() -> bool {
+    stage().is_none_or(|stage| matches!(stage.to_ascii_lowercase().as_str(), "prod" | "production"))
+}
+
+// Name of a table in the current stage: newsletter_subscribers becomes
+// newsletter_subscribers_dev with STAGE=dev, and stays as is without a stage
+pub fn table_name(base: &str) -> String {
+    match stage() {
+        Some(stage) => format!("{base}_{stage}"),
+        None => base.to_string(),
+    }
+}
+
+// How validation tokens are encoded in confirmation links
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenFormat {
+    // Random UUID v4, the historical format
+    Uuid,
+    Hex,
+    Base64Url,
+}
+
+impl TokenFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "uuid" => Some(TokenFormat::Uuid),
+            "hex" => Some(TokenFormat::Hex),
+            "base64url" | "base64" => Some(TokenFormat::Base64Url),
+            _ => None,
+        }
+    }
+}
+
+// Validation token policy shared by the validate, remind and confirm Lambdas
+#[derive(Debug, Clone)]
+pub struct TokenPolicy {
+    // How long a confirmation link stays valid
+    pub ttl_hours: i64,
+    pub format: TokenFormat,
+    // Random bytes in hex and base64url tokens
+    pub bytes: usize,
+    // Reminder emails, each with a fresh link, sent to a subscriber who hasn't confirmed
+    pub max_reminders: u32,
+    // Unconfirmed records are purged by DynamoDB TTL this long after their
+    // latest link expires, leaving room for the reminders
+    pub pending_retention_hours: i64,
+}
+
+impl Default for TokenPolicy {
+    fn default() -> Self {
+        Self {
+            ttl_hours: 24,
+            format: TokenFormat::Uuid,
+            bytes: 16,
+            max_reminders: 1,
+            pending_retention_hours: 72,
+        }
+    }
+}
+
+impl TokenPolicy {
+    // Read from VALIDATION_TOKEN_TTL_HOURS, VALIDATION_TOKEN_FORMAT (uuid, hex or
+    // base64url), VALIDATION_TOKEN_BYTES (16 to 64), VALIDATION_MAX_REMINDERS and
+    // VALIDATION_PENDING_RETENTION_HOURS
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            ttl_hours: env_or("VALIDATION_TOKEN_TTL_HOURS", default.ttl_hours).max(1),
+            format: env::var("VALIDATION_TOKEN_FORMAT")
+                .ok()
+                .and_then(|value| TokenFormat::parse(&value))
+                .unwrap_or(default.format),
+            // Fewer than 16 bytes would make tokens guessable
+            bytes: env_or("VALIDATION_TOKEN_BYTES", default.bytes).clamp(16, 64),
+            max_reminders: env_or("VALIDATION_MAX_REMINDERS", default.max_reminders),
+            pending_retention_hours: env_or(
+                "VALIDATION_PENDING_RETENTION_HOURS",
+                default.pending_retention_hours,
+            )
+            .max(0),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        Duration::hours(self.ttl_hours)
+    }
+
+    // When a token issued now stops being valid
+    pub fn expiration(&self) -> DateTime<Utc> {
+        Utc::now() + self.ttl()
+    }
+
+    // When DynamoDB may delete a still unconfirmed record holding a token issued now
+    pub fn purge_at(&self) -> DateTime<Utc> {
+        self.expiration() + Duration::hours(self.pending_retention_hours)
+    }
+
+    pub fn generate_token(&self) -> String {
+        if self.format == TokenFormat::Uuid {
+            return Uuid::new_v4().to_string();
+        }
+
+        let mut bytes = vec![0u8; self.bytes];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        match self.format {
+            TokenFormat::Hex => bytes.iter().map(|byte| format!("{byte:02x}")).collect(),
+            _ => URL_SAFE_NO_PAD.encode(bytes),
+        }
+    }
+}
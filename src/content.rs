@@ -0,0 +1,415 @@
+use crate::Subscriber;
+use crate::i18n::{Locale, translate};
+use crate::links::extract_links;
+use crate::template::{campaign_variables, escape_html, has_placeholder, render_html, render_text};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use utoipa::ToSchema;
+
+// Template variable holding the subscriber's unsubscribe link, see
+// `template::subscriber_variables`
+pub const UNSUBSCRIBE_PLACEHOLDER: &str = "unsubscribe_url";
+
+// Template variable holding the link to the campaign's hosted web version, see
+// `template::campaign_variables`
+pub const WEB_VERSION_PLACEHOLDER: &str = "web_version_url";
+
+// More distinct links than this in a campaign is a spam signal, from
+// CONTENT_MAX_LINKS
+const DEFAULT_MAX_LINKS: usize = 20;
+
+// Subjects shorter than this many letters, e.g. "FAQ", aren't taken for shouting
+const MIN_SHOUTING_LETTERS: usize = 8;
+
+// Markup removed from campaigns when they are sent, as mail clients block it
+// and spam filters penalise it. Event handlers are spotted separately.
+const ACTIVE_CONTENT: [&str; 6] = [
+    "<script",
+    "<iframe",
+    "<object",
+    "<embed",
+    "<form",
+    "javascript:",
+];
+
+fn max_links() -> usize {
+    env::var("CONTENT_MAX_LINKS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_LINKS)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentIssueKind {
+    // No `{{ unsubscribe_url }}` in the body, which blocks sending. The default
+    // footer still adds one to emails sent without this check.
+    MissingUnsubscribe,
+    AllCapsSubject,
+    // Runs of `!`, `?` or `$` in the subject
+    SubjectPunctuation,
+    TooManyLinks,
+    // Scripts, frames, forms or event handlers, stripped when sending
+    ActiveContent,
+}
+
+impl ContentIssueKind {
+    // Whether the campaign can't be sent or scheduled with it
+    pub fn is_blocking(&self) -> bool {
+        matches!(self, ContentIssueKind::MissingUnsubscribe)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ContentIssue {
+    pub kind: ContentIssueKind,
+    pub message: String,
+}
+
+impl ContentIssue {
+    fn new(kind: ContentIssueKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ContentReport {
+    // False when an error blocks sending
+    pub success: bool,
+    pub errors: Vec<ContentIssue>,
+    pub warnings: Vec<ContentIssue>,
+}
+
+// Check a campaign's subject and body before it is sent or scheduled: a
+// missing unsubscribe link is an error, common spam signals are warnings
+pub fn check_content(subject: &str, html_body: &str) -> ContentReport {
+    let mut issues = Vec::new();
+
+    if !has_placeholder(html_body, UNSUBSCRIBE_PLACEHOLDER) {
+        issues.push(ContentIssue::new(
+            ContentIssueKind::MissingUnsubscribe,
+            "The body has no {{ unsubscribe_url }} link",
+        ));
+    }
+
+    // Placeholders left out, as their values aren't known yet
+    let subject = render_text(subject, &HashMap::new());
+    let letters: Vec<char> = subject.chars().filter(|c| c.is_alphabetic()).collect();
+    if letters.len() >= MIN_SHOUTING_LETTERS && !letters.iter().any(|c| c.is_lowercase()) {
+        issues.push(ContentIssue::new(
+            ContentIssueKind::AllCapsSubject,
+            "The subject is all capitals",
+        ));
+    }
+    if ["!!", "??", "$$"]
+        .iter()
+        .any(|pattern| subject.contains(pattern))
+    {
+        issues.push(ContentIssue::new(
+            ContentIssueKind::SubjectPunctuation,
+            "The subject repeats !, ? or $",
+        ));
+    }
+
+    let links = extract_links(html_body).len();
+    let max_links = max_links();
+    if links > max_links {
+        issues.push(ContentIssue::new(
+            ContentIssueKind::TooManyLinks,
+            format!("The body has {links} links, more than {max_links}"),
+        ));
+    }
+
+    if has_active_content(html_body) {
+        issues.push(ContentIssue::new(
+            ContentIssueKind::ActiveContent,
+            "Scripts, frames, forms and event handlers are removed when sending",
+        ));
+    }
+
+    let (errors, warnings): (Vec<ContentIssue>, Vec<ContentIssue>) = issues
+        .into_iter()
+        .partition(|issue| issue.kind.is_blocking());
+    ContentReport {
+        success: errors.is_empty(),
+        errors,
+        warnings,
+    }
+}
+
+fn has_active_content(html: &str) -> bool {
+    let html = html.to_lowercase();
+    ACTIVE_CONTENT.iter().any(|pattern| html.contains(pattern)) || has_event_handler(&html)
+}
+
+// An `on...=` attribute such as onclick or onload inside a tag
+fn has_event_handler(html: &str) -> bool {
+    html.split('<').skip(1).any(|tag| {
+        let tag = tag.split('>').next().unwrap_or_default();
+        tag.split_whitespace().skip(1).any(|attribute| {
+            attribute.strip_prefix("on").is_some_and(|rest| {
+                let name_end = rest
+                    .find(|c: char| !c.is_ascii_alphabetic())
+                    .unwrap_or(rest.len());
+                name_end > 0 && rest[name_end..].starts_with('=')
+            })
+        })
+    })
+}
+
+// Remove scripts, frames, forms, event handlers and javascript: links from a
+// rendered email, keeping style sheets and the presentational attributes email
+// layouts rely on
+pub fn sanitize_email_html(html: &str) -> String {
+    ammonia::Builder::default()
+        .add_tags(["style", "center", "font"])
+        .rm_clean_content_tags(["style"])
+        // The title of a body written as a whole document isn't shown
+        .add_clean_content_tags(["title"])
+        .add_generic_attributes([
+            "style",
+            "class",
+            "id",
+            "align",
+            "valign",
+            "width",
+            "height",
+            "bgcolor",
+            "border",
+            "cellpadding",
+            "cellspacing",
+            "role",
+        ])
+        .add_tag_attributes("font", ["color", "face", "size"])
+        .clean(html)
+        .to_string()
+}
+
+// A campaign body with a footer linking to whichever of the unsubscribe link
+// and the web version it leaves out, before `</body>` or at the end
+pub fn with_default_footer(html_body: &str, locale: Locale) -> String {
+    let mut links = Vec::new();
+    if !has_placeholder(html_body, WEB_VERSION_PLACEHOLDER) {
+        links.push((WEB_VERSION_PLACEHOLDER, "View in your browser"));
+    }
+    if !has_placeholder(html_body, UNSUBSCRIBE_PLACEHOLDER) {
+        links.push((UNSUBSCRIBE_PLACEHOLDER, "Unsubscribe"));
+    }
+    if links.is_empty() {
+        return html_body.to_string();
+    }
+
+    let links: Vec<String> = links
+        .into_iter()
+        .map(|(placeholder, text)| {
+            format!(
+                "<a href=\"{{{{ {placeholder} }}}}\" style=\"color:#6b7280\">{}</a>",
+                escape_html(&translate(locale, text))
+            )
+        })
+        .collect();
+    let footer = format!(
+        "<p style=\"margin-top:32px;font-size:12px;color:#6b7280;text-align:center\">{}</p>",
+        links.join(" &middot; ")
+    );
+
+    match html_body.to_ascii_lowercase().rfind("</body>") {
+        Some(index) => format!("{}{}{}", &html_body[..index], footer, &html_body[index..]),
+        None => format!("{html_body}{footer}"),
+    }
+}
+
+// A campaign body as sent to a subscriber, or shown as their web version:
+// with the default footer when needed, rendered and sanitized
+pub fn render_campaign_html(
+    html_body: &str,
+    subscriber: &Subscriber,
+    campaign_id: &str,
+    list: Option<&str>,
+) -> String {
+    let variables = campaign_variables(subscriber, campaign_id, list);
+    let html_body = with_default_footer(html_body, subscriber.locale);
+    sanitize_email_html(&render_html(&html_body, &variables))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "<p>Hello</p><a href=\"{{ unsubscribe_url }}\">Unsubscribe</a>";
+
+    fn kinds(subject: &str, html_body: &str) -> Vec<ContentIssueKind> {
+        let report = check_content(subject, html_body);
+        report
+            .errors
+            .into_iter()
+            .chain(report.warnings)
+            .map(|issue| issue.kind)
+            .collect()
+    }
+
+    #[test]
+    fn clean_content_has_no_issues() {
+        assert!(kinds("Our October news", BODY).is_empty());
+    }
+
+    #[test]
+    fn only_a_missing_unsubscribe_link_blocks_sending() {
+        let report = check_content("BIG OCTOBER SALE!!", "<p>Hello</p>");
+        assert!(!report.success);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].kind, ContentIssueKind::MissingUnsubscribe);
+        assert_eq!(report.warnings.len(), 2);
+
+        let report = check_content("BIG OCTOBER SALE!!", BODY);
+        assert!(report.success);
+        assert!(report.errors.is_empty());
+    }
+
+    #[test]
+    fn flags_a_missing_unsubscribe_link() {
+        assert_eq!(
+            kinds("Our October news", "<p>Hello</p>"),
+            vec![ContentIssueKind::MissingUnsubscribe]
+        );
+        assert!(kinds("News", "<a href=\"{{unsubscribe_url}}\">Leave</a>").is_empty());
+    }
+
+    #[test]
+    fn flags_shouting_subjects() {
+        assert_eq!(
+            kinds("BIG OCTOBER SALE", BODY),
+            vec![ContentIssueKind::AllCapsSubject]
+        );
+        // Too short to be shouting, and placeholders don't count
+        assert!(kinds("FAQ", BODY).is_empty());
+        assert!(kinds("NEWS for {{ first_name }}", BODY).is_empty());
+    }
+
+    #[test]
+    fn flags_repeated_punctuation() {
+        for subject in ["Act now!!", "Really??", "Save $$"] {
+            assert_eq!(
+                kinds(subject, BODY),
+                vec![ContentIssueKind::SubjectPunctuation],
+                "{subject}"
+            );
+        }
+        assert!(kinds("Act now! Really? $5 off", BODY).is_empty());
+    }
+
+    #[test]
+    fn flags_too_many_links() {
+        let links: String = (0..=DEFAULT_MAX_LINKS)
+            .map(|i| format!("<a href=\"https://example.com/{i}\">{i}</a>"))
+            .collect();
+        assert_eq!(
+            kinds("News", &format!("{BODY}{links}")),
+            vec![ContentIssueKind::TooManyLinks]
+        );
+    }
+
+    #[test]
+    fn flags_active_content() {
+        for html in [
+            "<script>alert(1)</script>",
+            "<IFRAME src=\"https://example.com\"></IFRAME>",
+            "<a href=\"javascript:alert(1)\">Hi</a>",
+            "<img src=\"x.png\" onerror=\"alert(1)\">",
+        ] {
+            assert_eq!(
+                kinds("News", &format!("{BODY}{html}")),
+                vec![ContentIssueKind::ActiveContent],
+                "{html}"
+            );
+        }
+    }
+
+    #[test]
+    fn spots_event_handlers_only_as_attributes() {
+        assert!(has_event_handler("<body onload=\"track()\">"));
+        assert!(has_event_handler("<a href=\"#\" onclick='go()'>"));
+        assert!(!has_event_handler("<p>Click on=this</p>"));
+        assert!(!has_event_handler("<input type=\"checkbox\" online>"));
+        assert!(!has_event_handler("<a href=\"#\" on=\"1\">"));
+        assert!(!has_event_handler("<p>Plain text</p>"));
+    }
+
+    #[test]
+    fn footer_goes_before_the_closing_body_tag() {
+        let html = with_default_footer("<html><BODY><p>Hi</p></BODY></html>", Locale::En);
+        let footer = html.find("{{ unsubscribe_url }}").unwrap();
+        assert!(footer < html.find("</BODY>").unwrap());
+        assert!(html.contains("{{ web_version_url }}"));
+        assert!(html.ends_with("</BODY></html>"));
+    }
+
+    #[test]
+    fn footer_goes_at_the_end_without_a_body_tag() {
+        let html = with_default_footer("<p>Hi</p>", Locale::Es);
+        assert!(html.starts_with("<p>Hi</p><p style="));
+        assert!(html.ends_with("</a></p>"));
+        assert!(html.contains(&translate(Locale::Es, "Unsubscribe")));
+    }
+
+    #[test]
+    fn footer_only_adds_the_links_left_out() {
+        let html = with_default_footer(BODY, Locale::En);
+        assert_eq!(html.matches("unsubscribe_url").count(), 1);
+        assert!(html.contains("{{ web_version_url }}"));
+
+        let complete = format!("{BODY}<a href=\"{{{{ web_version_url }}}}\">Web</a>");
+        assert_eq!(with_default_footer(&complete, Locale::En), complete);
+    }
+
+    #[test]
+    fn sanitizing_strips_active_content() {
+        let html = sanitize_email_html(
+            "<p onclick=\"steal()\">Hi</p><script>steal()</script>\
+             <iframe src=\"https://example.com\"></iframe>\
+             <a href=\"javascript:steal()\">Link</a>\
+             <form><input name=\"email\"></form>",
+        );
+        let lower = html.to_lowercase();
+        for pattern in [
+            "onclick",
+            "<script",
+            "steal()",
+            "<iframe",
+            "javascript:",
+            "<form",
+        ] {
+            assert!(!lower.contains(pattern), "{pattern} in {html}");
+        }
+        assert!(html.contains("<p>Hi</p>"));
+    }
+
+    #[test]
+    fn sanitizing_keeps_email_layout() {
+        let html = sanitize_email_html(
+            "<html><head><title>Ignored</title>\
+             <style>.button { color: red; }</style></head><body>\
+             <table width=\"600\" cellpadding=\"0\" bgcolor=\"#ffffff\" role=\"presentation\">\
+             <tr><td align=\"center\" style=\"padding:8px\" class=\"button\">\
+             <font color=\"#333\">Hi</font></td></tr></table></body></html>",
+        );
+        assert!(html.contains("<style>.button { color: red; }</style>"));
+        assert!(!html.contains("Ignored"));
+        for attribute in [
+            "width=\"600\"",
+            "cellpadding=\"0\"",
+            "bgcolor=\"#ffffff\"",
+            "role=\"presentation\"",
+            "align=\"center\"",
+            "style=\"padding:8px\"",
+            "class=\"button\"",
+            "color=\"#333\"",
+        ] {
+            assert!(html.contains(attribute), "{attribute} missing from {html}");
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use crate::config::table_name;
+use crate::{COUNTERS_TABLE_NAME, Error, Subscriber};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::{AttributeValue, TransactWriteItem, Update};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::info;
+use utoipa::ToSchema;
+
+// Prefix of the per-tag counters, e.g. "tag:rust"
+const TAG_PREFIX: &str = "tag:";
+
+// COUNT is a DynamoDB reserved word, so expressions refer to it as #count
+const COUNT_ATTRIBUTE: &str = "count";
+
+// The counters a subscriber adds one to. Soft-deleted subscribers count for
+// nothing, like they're hidden from every listing.
+pub fn counters_for(subscriber: &Subscriber) -> Vec<String> {
+    if subscriber.is_deleted() {
+        return Vec::new();
+    }
+
+    let mut counters = vec!["total".to_string()];
+    if subscriber.active {
+        counters.push("active".to_string());
+    }
+    if subscriber.validated {
+        counters.push("validated".to_string());
+    }
+    if subscriber.is_mailable() {
+        counters.push("mailable".to_string());
+    }
+
+    // Tags come from the `tags` custom field, matched the way campaign
+    // audiences match them
+    let tags: BTreeSet<String> = subscriber
+        .fields
+        .get("tags")
+        .map(|tags| {
+            tags.split(',')
+                .map(|tag| tag.trim().to_lowercase())
+                .filter(|tag| !tag.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    counters.extend(tags.into_iter().map(|tag| format!("{TAG_PREFIX}{tag}")));
+
+    counters
+}
+
+// How each counter changes when a subscriber counted in `before` is counted
+// in `after` instead, either empty for a subscriber that doesn't exist (yet or
+// anymore). Counters that don't change are left out.
+pub fn deltas(before: &[String], after: &[String]) -> BTreeMap<String, i64> {
+    let mut deltas = BTreeMap::new();
+    for counter in before {
+        *deltas.entry(counter.clone()).or_insert(0) -= 1;
+    }
+    for counter in after {
+        *deltas.entry(counter.clone()).or_insert(0) += 1;
+    }
+    deltas.retain(|_, delta| *delta != 0);
+    deltas
+}
+
+// What every counter should be for a full list of subscribers, used to
+// reconcile the maintained counters
+pub fn tally(subscribers: &[Subscriber]) -> BTreeMap<String, i64> {
+    let mut counts = BTreeMap::new();
+    for subscriber in subscribers {
+        for counter in counters_for(subscriber) {
+            *counts.entry(counter).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+// Subscriber counts as the stats endpoints and the pulse report them
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct SubscriberCounts {
+    // Subscribers that aren't soft-deleted
+    pub total: u64,
+    pub active: u64,
+    pub validated: u64,
+    // Active, confirmed, not suppressed and not quarantined
+    pub mailable: u64,
+    // Subscribers per tag, from the `tags` custom field
+    pub tags: BTreeMap<String, u64>,
+}
+
+impl SubscriberCounts {
+    fn from_raw(raw: &BTreeMap<String, i64>) -> Self {
+        // A counter can dip below zero briefly while drifted, until reconciled
+        let count = |name: &str| raw.get(name).copied().unwrap_or(0).max(0) as u64;
+        Self {
+            total: count("total"),
+            active: count("active"),
+            validated: count("validated"),
+            mailable: count("mailable"),
+            tags: raw
+                .iter()
+                .filter_map(|(name, count)| {
+                    let tag = name.strip_prefix(TAG_PREFIX)?;
+                    (*count > 0).then(|| (tag.to_string(), *count as u64))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SubscriberCountsResponse {
+    pub success: bool,
+    pub counts: SubscriberCounts,
+}
+
+// Subscriber counters, one small item per counter, kept up to date by the
+// subscriber repository's writes so counting never scans the subscribers table
+pub struct CounterStore {
+    client: Client,
+    table_name: String,
+}
+
+impl CounterStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(COUNTERS_TABLE_NAME),
+        }
+    }
+
+    // Counter updates to add to a transaction, so they commit or fail together
+    // with the subscriber write they count
+    pub fn transact_items(&self, deltas: &BTreeMap<String, i64>) -> Vec<TransactWriteItem> {
+        deltas
+            .iter()
+            .map(|(counter, delta)| {
+                TransactWriteItem::builder()
+                    .update(
+                        Update::builder()
+                            .table_name(&self.table_name)
+                            .key("counter", AttributeValue::S(counter.clone()))
+                            .update_expression("ADD #count :delta")
+                            .expression_attribute_names("#count", COUNT_ATTRIBUTE)
+                            .expression_attribute_values(
+                                ":delta",
+                                AttributeValue::N(delta.to_string()),
+                            )
+                            .build(),
+                    )
+                    .build()
+            })
+            .collect()
+    }
+
+    // Apply counter changes outside a transaction, for writes that can't take
+    // part in one such as batch writes
+    pub async fn apply(&self, deltas: &BTreeMap<String, i64>) -> Result<(), Error> {
+        for (counter, delta) in deltas {
+            self.client
+                .update_item()
+                .table_name(&self.table_name)
+                .key("counter", AttributeValue::S(counter.clone()))
+                .update_expression("ADD #count :delta")
+                .expression_attribute_names("#count", COUNT_ATTRIBUTE)
+                .expression_attribute_values(":delta", AttributeValue::N(delta.to_string()))
+                .send()
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // The write being counted already happened, so a failure is only logged
+    // and left for the nightly reconciliation to correct
+    pub async fn apply_or_log(&self, deltas: &BTreeMap<String, i64>) {
+        if let Err(e) = self.apply(deltas).await {
+            info!("Error updating subscriber counters: {:?}", e);
+        }
+    }
+
+    // Every counter by name. The table holds a handful of items, so it is
+    // scanned rather than read key by key.
+    pub async fn raw(&self) -> Result<BTreeMap<String, i64>, Error> {
+        let mut counts = BTreeMap::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            for item in result.items().unwrap_or_default() {
+                let Some(counter) = item.get("counter").and_then(|value| value.as_s().ok()) else {
+                    continue;
+                };
+                let count = item
+                    .get(COUNT_ATTRIBUTE)
+                    .and_then(|value| value.as_n().ok())
+                    .and_then(|value| value.parse::<i64>().ok())
+                    .unwrap_or(0);
+                counts.insert(counter.clone(), count);
+            }
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(counts)
+    }
+
+    pub async fn counts(&self) -> Result<SubscriberCounts, Error> {
+        Ok(SubscriberCounts::from_raw(&self.raw().await?))
+    }
+
+    // Bring the counters in line with `expected`, a tally of the subscribers
+    // table, by adding the difference rather than overwriting. Changes counted
+    // between reading the counters and correcting them are kept. Returns the
+    // corrections made.
+    pub async fn reconcile(
+        &self,
+        expected: &BTreeMap<String, i64>,
+    ) -> Result<BTreeMap<String, i64>, Error> {
+        let current = self.raw().await?;
+        let mut corrections = BTreeMap::new();
+        for counter in current.keys().chain(expected.keys()) {
+            let drift = expected.get(counter).copied().unwrap_or(0)
+                - current.get(counter).copied().unwrap_or(0);
+            if drift != 0 {
+                corrections.insert(counter.clone(), drift);
+            }
+        }
+
+        self.apply(&corrections).await?;
+        Ok(corrections)
+    }
+}
@@ -0,0 +1,154 @@
+use crate::Error;
+use crate::repository::ItemKey;
+use crate::signing;
+use aws_sdk_dynamodb::types::AttributeValue;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde_json::{Map, Value};
+
+// Keeps cursor signatures from being usable as signatures for anything else
+const CURSOR_CONTEXT: &str = "cursor";
+
+// Opaque pagination cursor wrapping a DynamoDB LastEvaluatedKey. Encoded as
+// `<base64url payload>.<hmac>`, so clients can pass it back but can't forge keys
+// or build on the table's attribute names.
+#[derive(Debug, Clone)]
+pub struct Cursor {
+    key: ItemKey,
+}
+
+impl Cursor {
+    pub fn from_key(key: ItemKey) -> Self {
+        Self { key }
+    }
+
+    pub fn into_key(self) -> ItemKey {
+        self.key
+    }
+
+    pub fn encode(&self) -> Result<String, Error> {
+        let mut attributes = Map::new();
+        for (name, value) in &self.key {
+            // Table and index keys are only ever strings or numbers
+            let (kind, value) = match value {
+                AttributeValue::S(value) => ("S", value),
+                AttributeValue::N(value) => ("N", value),
+                _ => return Err(format!("Unsupported key attribute `{name}`").into()),
+            };
+            attributes.insert(name.clone(), Value::from(vec![kind, value.as_str()]));
+        }
+
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&attributes)?);
+        let signature = signing::sign(&format!("{CURSOR_CONTEXT}:{payload}"))?;
+        Ok(format!("{payload}.{signature}"))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, Error> {
+        let (payload, signature) = cursor.split_once('.').ok_or("Malformed cursor")?;
+        if !signing::verify(&format!("{CURSOR_CONTEXT}:{payload}"), signature) {
+            return Err("Invalid cursor signature".into());
+        }
+
+        let attributes: Map<String, Value> =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)?;
+
+        let mut key = ItemKey::new();
+        for (name, value) in attributes {
+            let value = match value.as_array().map(Vec::as_slice) {
+                Some([Value::String(kind), Value::String(value)]) if kind == "S" => {
+                    AttributeValue::S(value.clone())
+                }
+                Some([Value::String(kind), Value::String(value)]) if kind == "N" => {
+                    AttributeValue::N(value.clone())
+                }
+                _ => return Err(format!("Malformed cursor attribute `{name}`").into()),
+            };
+            key.insert(name, value);
+        }
+
+        Ok(Self { key })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::with_secret;
+
+    const SECRET: &str = "cursor-test-secret";
+
+    fn key() -> ItemKey {
+        ItemKey::from([
+            ("id".to_string(), AttributeValue::S("sub-1".to_string())),
+            (
+                "created_at".to_string(),
+                AttributeValue::N("1700000000".to_string()),
+            ),
+        ])
+    }
+
+    #[test]
+    fn round_trips_a_key() {
+        let decoded = with_secret(SECRET, || {
+            let cursor = Cursor::from_key(key()).encode().unwrap();
+            Cursor::decode(&cursor).unwrap()
+        });
+        assert_eq!(decoded.into_key(), key());
+    }
+
+    #[test]
+    fn rejects_unsupported_key_attributes() {
+        let key = ItemKey::from([("active".to_string(), AttributeValue::Bool(true))]);
+        assert!(with_secret(SECRET, || Cursor::from_key(key).encode()).is_err());
+    }
+
+    #[test]
+    fn rejects_a_tampered_payload() {
+        with_secret(SECRET, || {
+            let cursor = Cursor::from_key(key()).encode().unwrap();
+            let (_, signature) = cursor.split_once('.').unwrap();
+            let forged = URL_SAFE_NO_PAD.encode(br#"{"id":["S","sub-2"]}"#);
+            assert!(Cursor::decode(&format!("{forged}.{signature}")).is_err());
+        });
+    }
+
+    #[test]
+    fn rejects_a_tampered_signature() {
+        with_secret(SECRET, || {
+            let cursor = Cursor::from_key(key()).encode().unwrap();
+            let mut tampered = cursor.into_bytes();
+            let last = tampered.last_mut().unwrap();
+            *last = if *last == b'0' { b'1' } else { b'0' };
+            assert!(Cursor::decode(&String::from_utf8(tampered).unwrap()).is_err());
+        });
+    }
+
+    #[test]
+    fn rejects_a_cursor_signed_with_another_key() {
+        let cursor = with_secret("another-secret", || {
+            Cursor::from_key(key()).encode().unwrap()
+        });
+        assert!(with_secret(SECRET, || Cursor::decode(&cursor)).is_err());
+    }
+
+    #[test]
+    fn rejects_signatures_made_for_something_else() {
+        with_secret(SECRET, || {
+            let payload = URL_SAFE_NO_PAD.encode(br#"{"id":["S","sub-1"]}"#);
+            let signature = signing::sign(&payload).unwrap();
+            assert!(Cursor::decode(&format!("{payload}.{signature}")).is_err());
+        });
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        with_secret(SECRET, || {
+            assert!(Cursor::decode("").is_err());
+            assert!(Cursor::decode("no-signature").is_err());
+
+            let payload = URL_SAFE_NO_PAD.encode(br#"{"id":["B","c3ViLTE="]}"#);
+            let signature = signing::sign(&format!("{CURSOR_CONTEXT}:{payload}")).unwrap();
+            assert!(Cursor::decode(&format!("{payload}.{signature}")).is_err());
+        });
+    }
+}
@@ -0,0 +1,130 @@
+use crate::Subscriber;
+use crate::search::search_email;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+// Records sharing one canonical email, e.g. from two signups racing past the
+// duplicate check before either was written
+pub struct DuplicateGroup {
+    // Lowercased, trimmed email shared by every record
+    pub email: String,
+    // The oldest record, which the others are merged into
+    pub keep: Subscriber,
+    pub duplicates: Vec<Subscriber>,
+}
+
+impl DuplicateGroup {
+    pub fn duplicate_ids(&self) -> Vec<String> {
+        self.duplicates
+            .iter()
+            .map(|subscriber| subscriber.id.clone())
+            .collect()
+    }
+}
+
+// Group subscribers by canonical email, keeping only emails with more than one
+// record. The oldest record is kept, ties broken by id so reruns agree.
+pub fn find_duplicates(subscribers: Vec<Subscriber>) -> Vec<DuplicateGroup> {
+    let mut by_email: BTreeMap<String, Vec<Subscriber>> = BTreeMap::new();
+    for subscriber in subscribers {
+        by_email
+            .entry(search_email(&subscriber.email))
+            .or_default()
+            .push(subscriber);
+    }
+
+    by_email
+        .into_iter()
+        .filter(|(_, records)| records.len() > 1)
+        .map(|(email, mut records)| {
+            records.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+            let keep = records.remove(0);
+            DuplicateGroup {
+                email,
+                keep,
+                duplicates: records,
+            }
+        })
+        .collect()
+}
+
+fn note(changed: &mut Vec<&'static str>, attribute: &'static str) {
+    if !changed.contains(&attribute) {
+        changed.push(attribute);
+    }
+}
+
+// Fold duplicates into the kept record, returning the attributes that changed.
+// Confirmation, suppression and opt-outs on any copy carry over, so a merge
+// never makes an address mailable that wasn't before. Data missing from the
+// kept record is filled in from the duplicates.
+pub fn merge_into(
+    keep: &mut Subscriber,
+    duplicates: &[Subscriber],
+    now: DateTime<Utc>,
+) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+
+    for duplicate in duplicates {
+        if duplicate.validated && !keep.validated {
+            keep.validated = true;
+            // A pending token's TTL would otherwise delete the confirmed record
+            keep.validation_token = None;
+            keep.token_expiration = None;
+            keep.token_ttl = None;
+            note(&mut changed, "validated");
+        }
+        if duplicate.suppressed && !keep.suppressed {
+            keep.suppressed = true;
+            note(&mut changed, "suppressed");
+        }
+        if !duplicate.active && keep.active {
+            keep.active = false;
+            note(&mut changed, "active");
+        }
+
+        for (name, value) in &duplicate.fields {
+            if !keep.fields.contains_key(name) {
+                keep.fields.insert(name.clone(), value.clone());
+                note(&mut changed, "fields");
+            }
+        }
+
+        if duplicate.engagement_score_at(now) > keep.engagement_score_at(now) {
+            keep.engagement_score = duplicate.engagement_score;
+            keep.last_engaged_at = duplicate.last_engaged_at;
+            note(&mut changed, "engagement_score");
+        }
+
+        if keep.referral_code.is_none() && duplicate.referral_code.is_some() {
+            keep.referral_code = duplicate.referral_code.clone();
+            note(&mut changed, "referral_code");
+        }
+        if keep.referred_by.is_none() && duplicate.referred_by.is_some() {
+            keep.referred_by = duplicate.referred_by.clone();
+            note(&mut changed, "referred_by");
+        }
+        if duplicate.referral_count > 0 {
+            keep.referral_count += duplicate.referral_count;
+            note(&mut changed, "referral_count");
+        }
+
+        // The Stripe customer and the tier it pays for move together
+        if keep.stripe_customer_id.is_none() && duplicate.stripe_customer_id.is_some() {
+            keep.stripe_customer_id = duplicate.stripe_customer_id.clone();
+            keep.tier = duplicate.tier;
+            note(&mut changed, "stripe_customer_id");
+        }
+        if keep.timezone.is_none() && duplicate.timezone.is_some() {
+            keep.timezone = duplicate.timezone.clone();
+            note(&mut changed, "timezone");
+        }
+        if keep.verification.is_none() && duplicate.verification.is_some() {
+            keep.verification = duplicate.verification;
+            keep.verified_at = duplicate.verified_at;
+            note(&mut changed, "verification");
+        }
+    }
+
+    changed
+}
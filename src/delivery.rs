@@ -0,0 +1,630 @@
+use crate::admin::ApiKey;
+use crate::campaign::{Campaign, CampaignStatus, SendCheckpoint};
+use crate::content::{ContentIssue, ContentReport, check_content, render_campaign_html};
+use crate::digest::{DigestStore, Frequency};
+use crate::email_queue::{EmailKind, Mailer};
+use crate::events::{pixel_tracking_enabled, with_open_tracking};
+use crate::links::{LinkChecker, LinkReport, campaign_links};
+use crate::quota::{QuotaKind, QuotaStatus, QuotaStore};
+use crate::repository::{CampaignRepository, SubscriberRepository};
+use crate::schedule::{
+    BucketStatus, RecipientSnapshotStore, ScheduleBucket, ScheduleStore, bucket_for, plan_buckets,
+};
+use crate::segment::Segment;
+use crate::senders::{SenderStore, SenderVerifier};
+use crate::template::{render_text, subscriber_variables};
+use crate::templates::TemplateStore;
+use crate::{Error, Subscriber, signing};
+use chrono::{NaiveDate, NaiveTime, Utc};
+use std::fmt;
+use tracing::info;
+
+// Recipients handled between checkpoints, which is also when the campaign is
+// checked for a cancellation. A send that dies repeats at most this many.
+const CHECKPOINT_INTERVAL: usize = 100;
+
+// Where a send saves its checkpoints
+#[derive(Clone, Copy)]
+pub enum Progress<'a> {
+    // On the campaign, for a send to all recipients at once
+    Campaign,
+    // On a timezone bucket of a scheduled campaign
+    Bucket(&'a ScheduleStore, &'a ScheduleBucket),
+    // Nowhere, for a batch of a Step Functions send, which the state machine
+    // retries as a whole, see `workflow`
+    Batch,
+}
+
+impl Progress<'_> {
+    // The checkpoint to resume from, None for a send starting afresh
+    fn resume_from<'a>(&'a self, campaign: &'a Campaign) -> Option<&'a SendCheckpoint> {
+        match self {
+            Progress::Campaign => campaign.checkpoint.as_ref(),
+            Progress::Bucket(_, bucket) => bucket.checkpoint.as_ref(),
+            Progress::Batch => None,
+        }
+    }
+
+    // Failures are logged, as the send itself is fine
+    async fn save(
+        &self,
+        campaigns: &CampaignRepository,
+        campaign_id: &str,
+        checkpoint: &SendCheckpoint,
+    ) {
+        let result = match self {
+            Progress::Campaign => campaigns.save_checkpoint(campaign_id, checkpoint).await,
+            Progress::Bucket(schedules, bucket) => {
+                schedules.save_checkpoint(bucket, checkpoint).await
+            }
+            Progress::Batch => return,
+        };
+        if let Err(e) = result {
+            info!(
+                "Error saving the checkpoint of campaign {}: {:?}",
+                campaign_id, e
+            );
+        }
+    }
+}
+
+// What came of sending a campaign to a list of recipients
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SendCounts {
+    // Emails sent, or queued when the send queue is in use
+    pub sent: u32,
+    // Emails left out because the campaign was cancelled
+    pub skipped: u32,
+}
+
+impl SendCounts {
+    pub fn cancelled(&self) -> bool {
+        self.skipped > 0
+    }
+}
+
+// Mailable subscribers on the campaign's list and matching its segment, leaving
+// out those it excludes and those who get it in their weekly digest instead
+pub async fn campaign_recipients(
+    subscribers: &SubscriberRepository,
+    campaign: &Campaign,
+) -> Result<Vec<Subscriber>, Error> {
+    let segment = campaign
+        .segment
+        .as_deref()
+        .map(Segment::parse)
+        .transpose()?;
+    let excluded_segment = campaign
+        .exclusions
+        .segment
+        .as_deref()
+        .map(Segment::parse)
+        .transpose()?;
+
+    let now = Utc::now();
+    Ok(subscribers
+        .list_all()
+        .await?
+        .into_iter()
+        .filter(|subscriber| subscriber.is_mailable_on(campaign.list.as_deref()))
+        .filter(|subscriber| subscriber.frequency == Frequency::Immediate)
+        .filter(|subscriber| {
+            segment
+                .as_ref()
+                .map(|segment| segment.matches(subscriber, now))
+                .unwrap_or(true)
+        })
+        .filter(|subscriber| !campaign.exclusions.excludes(subscriber))
+        .filter(|subscriber| {
+            excluded_segment
+                .as_ref()
+                .map(|segment| !segment.matches(subscriber, now))
+                .unwrap_or(true)
+        })
+        .collect())
+}
+
+// Campaign recipients as of now, limited to the snapshot taken when it was
+// scheduled: unsubscribes since then are honoured, new subscribers left out.
+// Campaigns scheduled before snapshots existed go to everyone matching.
+pub async fn scheduled_recipients(
+    subscribers: &SubscriberRepository,
+    snapshots: &RecipientSnapshotStore,
+    campaign: &Campaign,
+) -> Result<Vec<Subscriber>, Error> {
+    let mut recipients = campaign_recipients(subscribers, campaign).await?;
+    if campaign.snapshot_count.is_some() {
+        let snapshot = snapshots.subscriber_ids(&campaign.id).await?;
+        recipients.retain(|subscriber| snapshot.contains(&subscriber.id));
+    }
+    Ok(recipients)
+}
+
+// Pin a draft campaign made from a template to the template's published
+// version, returning it with that content. None when the template doesn't
+// exist or has nothing published.
+pub async fn pin_template(
+    campaigns: &CampaignRepository,
+    templates: &TemplateStore,
+    mut campaign: Campaign,
+) -> Result<Option<Campaign>, Error> {
+    let Some(template_id) = &campaign.template_id else {
+        return Ok(Some(campaign));
+    };
+    // Pinned when it started sending, before it stalled
+    if campaign.status != CampaignStatus::Draft {
+        return Ok(Some(campaign));
+    }
+    let Some(version) = templates.published(template_id).await? else {
+        return Ok(None);
+    };
+
+    campaigns.pin_template(&campaign.id, &version).await?;
+    campaign.subject = version.subject;
+    campaign.html_body = version.html_body;
+    campaign.template_version = Some(version.version);
+    Ok(Some(campaign))
+}
+
+// The mailer for a campaign: the default one, or one sending from the
+// campaign's sender identity. None when that identity doesn't exist or isn't
+// verified, so nothing is sent from an address SES would reject.
+pub async fn campaign_mailer(
+    senders: &SenderStore,
+    verifier: &SenderVerifier,
+    mailer: &Mailer,
+    campaign: &Campaign,
+) -> Result<Option<Mailer>, Error> {
+    let Some(sender_id) = &campaign.sender_id else {
+        return Ok(Some(mailer.clone()));
+    };
+
+    Ok(senders
+        .verified(verifier, sender_id)
+        .await?
+        .map(|sender| mailer.with_sender(&sender)))
+}
+
+// Why a campaign can't be sent or scheduled, found before anything goes out
+#[derive(Debug)]
+pub enum SendRefusal {
+    // SIGNING_SECRET isn't set, so links in the emails couldn't be signed
+    NotConfigured,
+    NotFound,
+    // Not a draft, nor a stalled send where resuming is allowed
+    NotSendable(CampaignStatus),
+    // Its sender identity is missing or not verified
+    UnverifiedSender(String),
+    // Its template is missing or has no published version
+    UnpublishedTemplate(String),
+    BrokenLinks(LinkReport),
+    // No unsubscribe link, see `content::check_content`
+    BlockedContent(ContentReport),
+    // The caller's monthly send quota can't cover the recipients
+    QuotaExceeded(QuotaStatus),
+}
+
+impl fmt::Display for SendRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendRefusal::NotConfigured => write!(f, "Sending is not configured"),
+            SendRefusal::NotFound => write!(f, "Campaign not found"),
+            SendRefusal::NotSendable(status) => write!(
+                f,
+                "Campaign is {}, not draft or a stalled send",
+                status.as_str()
+            ),
+            SendRefusal::UnverifiedSender(sender_id) => {
+                write!(f, "Sender identity {sender_id} is missing or not verified")
+            }
+            SendRefusal::UnpublishedTemplate(template_id) => write!(
+                f,
+                "Template {template_id} is missing or has no published version"
+            ),
+            SendRefusal::BrokenLinks(report) => {
+                write!(f, "Campaign has {} broken links", report.broken.len())
+            }
+            SendRefusal::BlockedContent(report) => {
+                let messages: Vec<&str> = report
+                    .errors
+                    .iter()
+                    .map(|issue| issue.message.as_str())
+                    .collect();
+                write!(f, "Campaign content is blocked: {}", messages.join(", "))
+            }
+            SendRefusal::QuotaExceeded(_) => write!(f, "Monthly send quota exceeded"),
+        }
+    }
+}
+
+// The stores the checks before a send read
+pub struct SendGuards<'a> {
+    pub subscribers: &'a SubscriberRepository,
+    pub campaigns: &'a CampaignRepository,
+    pub templates: &'a TemplateStore,
+    pub senders: &'a SenderStore,
+    pub verifier: &'a SenderVerifier,
+    pub quotas: &'a QuotaStore,
+}
+
+// What to check before sending a campaign
+pub struct SendRequest<'a> {
+    pub campaign_id: &'a str,
+    // The key whose `sends_per_month` the recipients count towards, None for
+    // the CLI
+    pub api_key: Option<&'a ApiKey>,
+    // Whether a stalled send can be resumed; scheduling needs a draft
+    pub resume: bool,
+    pub check_links: bool,
+}
+
+// A campaign that passed the checks, ready for `deliver_campaign` or
+// `schedule_campaign`
+pub struct PreparedSend {
+    pub campaign: Campaign,
+    pub mailer: Mailer,
+    pub recipients: Vec<Subscriber>,
+    // Spam signals that don't stop the send
+    pub content_warnings: Vec<ContentIssue>,
+    // The caller's send quota after counting the recipients
+    pub send_quota: Option<QuotaStatus>,
+}
+
+// Everything that must hold before a campaign is sent or scheduled, whether
+// through the admin API, gRPC or the CLI: SIGNING_SECRET is set, the campaign
+// can be sent, its sender identity is verified, its template is pinned to the
+// published version, its links work, its content has an unsubscribe link and
+// the caller's send quota covers the recipients. The quota is only used once
+// everything else passed.
+pub async fn prepare_send(
+    guards: &SendGuards<'_>,
+    mailer: &Mailer,
+    request: SendRequest<'_>,
+) -> Result<Result<PreparedSend, SendRefusal>, Error> {
+    let id = request.campaign_id;
+    if let Err(e) = signing::require_secret() {
+        info!("Refusing to send campaign {}: {}", id, e);
+        return Ok(Err(SendRefusal::NotConfigured));
+    }
+
+    let campaign = match guards.campaigns.get(id).await? {
+        Some(campaign)
+            if campaign.status == CampaignStatus::Draft
+                || (request.resume && campaign.is_stalled(Utc::now())) =>
+        {
+            campaign
+        }
+        Some(campaign) => return Ok(Err(SendRefusal::NotSendable(campaign.status))),
+        None => return Ok(Err(SendRefusal::NotFound)),
+    };
+
+    let Some(mailer) = campaign_mailer(guards.senders, guards.verifier, mailer, &campaign).await?
+    else {
+        let sender_id = campaign.sender_id.unwrap_or_default();
+        return Ok(Err(SendRefusal::UnverifiedSender(sender_id)));
+    };
+
+    // Pinned so a scheduled send goes out with the version checked here, even
+    // if the template is edited in between
+    let template_id = campaign.template_id.clone().unwrap_or_default();
+    let Some(campaign) = pin_template(guards.campaigns, guards.templates, campaign).await? else {
+        return Ok(Err(SendRefusal::UnpublishedTemplate(template_id)));
+    };
+
+    if request.check_links {
+        let report = LinkChecker::from_env()?
+            .check(campaign_links(&campaign.html_body))
+            .await?;
+        if !report.success {
+            info!(
+                "Campaign {} has {} broken links, not sending",
+                campaign.id,
+                report.broken.len()
+            );
+            return Ok(Err(SendRefusal::BrokenLinks(report)));
+        }
+    }
+
+    // The default footer only covers sends that skip this check
+    let content = check_content(&campaign.subject, &campaign.html_body);
+    if !content.success {
+        info!(
+            "Campaign {} has {} content errors, not sending",
+            campaign.id,
+            content.errors.len()
+        );
+        return Ok(Err(SendRefusal::BlockedContent(content)));
+    }
+
+    let recipients = campaign_recipients(guards.subscribers, &campaign).await?;
+
+    // A resumed send only uses quota for the recipients it hasn't reached
+    let send_quota = match request.api_key {
+        Some(api_key) => match api_key.sends_per_month {
+            Some(limit) => {
+                let pending = match &campaign.checkpoint {
+                    Some(checkpoint) => recipients
+                        .iter()
+                        .filter(|subscriber| checkpoint.is_pending(&subscriber.id))
+                        .count(),
+                    None => recipients.len(),
+                };
+                let status = guards
+                    .quotas
+                    .consume(&api_key.id, QuotaKind::Sends, limit, pending as u64)
+                    .await?;
+                if !status.allowed {
+                    return Ok(Err(SendRefusal::QuotaExceeded(status)));
+                }
+                Some(status)
+            }
+            None => None,
+        },
+        None => None,
+    };
+
+    Ok(Ok(PreparedSend {
+        campaign,
+        mailer,
+        recipients,
+        content_warnings: content.warnings,
+        send_quota,
+    }))
+}
+
+// Send a draft campaign to the given recipients, add it to the upcoming digests
+// and mark it sent, returning how many emails went out, or were queued when the
+// send queue is in use. A campaign cancelled along the way is marked cancelled
+// instead and left out of the digests. A stalled send resumes from its
+// checkpoint.
+pub async fn deliver_campaign(
+    campaigns: &CampaignRepository,
+    digests: &DigestStore,
+    mailer: &Mailer,
+    campaign: &Campaign,
+    recipients: &[Subscriber],
+) -> Result<SendCounts, Error> {
+    signing::require_secret()?;
+    let started = match (&campaign.status, &campaign.checkpoint) {
+        (CampaignStatus::Draft, _) => campaigns.start_sending(&campaign.id).await?,
+        (CampaignStatus::Sending, Some(checkpoint)) if campaign.is_stalled(Utc::now()) => {
+            info!(
+                "Resuming campaign {} after {} emails",
+                campaign.id, checkpoint.sent
+            );
+            campaigns.resume_sending(&campaign.id, checkpoint).await?
+        }
+        _ => false,
+    };
+    if !started {
+        return Err(format!(
+            "Campaign {} is {}, not draft or a stalled send",
+            campaign.id,
+            campaign.status.as_str()
+        )
+        .into());
+    }
+
+    let counts =
+        send_campaign_emails(campaigns, Progress::Campaign, mailer, campaign, recipients).await?;
+    finish_send(campaigns, digests, &campaign.id, counts).await?;
+
+    Ok(counts)
+}
+
+// Mark a campaign that was being sent sent and add it to the upcoming digests,
+// or mark it cancelled when it was cancelled along the way, returning whether
+// it was
+pub async fn finish_send(
+    campaigns: &CampaignRepository,
+    digests: &DigestStore,
+    campaign_id: &str,
+    counts: SendCounts,
+) -> Result<bool, Error> {
+    // Cancelled after the last check, with nothing left to skip
+    let cancelled = counts.cancelled()
+        || campaigns.status(campaign_id).await? == Some(CampaignStatus::Cancelling);
+    if cancelled {
+        campaigns.add_skipped(campaign_id, counts.skipped).await?;
+        campaigns.mark_cancelled(campaign_id, counts.sent).await?;
+    } else {
+        digests.add(campaign_id).await?;
+        campaigns.mark_sent(campaign_id, counts.sent).await?;
+    }
+
+    Ok(cancelled)
+}
+
+// Schedule a draft campaign to go out at a local date and time in each of its
+// recipients' timezones, returning the timezone buckets. The recipients are
+// snapshotted, so subscribers who join later don't get it.
+pub async fn schedule_campaign(
+    campaigns: &CampaignRepository,
+    schedules: &ScheduleStore,
+    snapshots: &RecipientSnapshotStore,
+    campaign: &Campaign,
+    recipients: &[Subscriber],
+    date: NaiveDate,
+    time: NaiveTime,
+) -> Result<Vec<ScheduleBucket>, Error> {
+    if campaign.status != CampaignStatus::Draft {
+        return Err(format!(
+            "Campaign {} is {}, not draft",
+            campaign.id,
+            campaign.status.as_str()
+        )
+        .into());
+    }
+
+    let buckets = plan_buckets(&campaign.id, recipients, date, time);
+    let snapshot_count = snapshots.create(&campaign.id, recipients, &buckets).await?;
+    schedules.create(&buckets).await?;
+    campaigns
+        .mark_scheduled(&campaign.id, snapshot_count as u32)
+        .await?;
+
+    Ok(buckets)
+}
+
+// Whether the campaign was cancelled since sending started. A failed check is
+// logged and sending carries on.
+async fn is_cancelling(campaigns: &CampaignRepository, campaign_id: &str) -> bool {
+    match campaigns.status(campaign_id).await {
+        Ok(status) => matches!(
+            status,
+            Some(CampaignStatus::Cancelling | CampaignStatus::Cancelled)
+        ),
+        Err(e) => {
+            info!(
+                "Error checking campaign {} for a cancellation: {:?}",
+                campaign_id, e
+            );
+            false
+        }
+    }
+}
+
+// Render and send a campaign to each recipient in id order, returning how many
+// were sent. Bodies get the default footer when they need it and are
+// sanitized, see `content::render_campaign_html`, so nothing is sent without
+// SIGNING_SECRET to sign their links. Failures are logged so one bad address
+// doesn't stop the rest. Every CHECKPOINT_INTERVAL recipients
+// progress is saved and the campaign is checked for a cancellation, and the
+// remaining recipients are skipped once it is. A send resuming from a
+// checkpoint starts after its last recipient.
+pub async fn send_campaign_emails(
+    campaigns: &CampaignRepository,
+    progress: Progress<'_>,
+    mailer: &Mailer,
+    campaign: &Campaign,
+    recipients: &[Subscriber],
+) -> Result<SendCounts, Error> {
+    signing::require_secret()?;
+
+    let resume_from = progress.resume_from(campaign);
+    let mut pending: Vec<&Subscriber> = recipients
+        .iter()
+        .filter(|subscriber| {
+            resume_from.is_none_or(|checkpoint| checkpoint.is_pending(&subscriber.id))
+        })
+        .collect();
+    pending.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let pixel_tracking = pixel_tracking_enabled();
+    let mut sent_count = resume_from.map(|checkpoint| checkpoint.sent).unwrap_or(0);
+    for (index, subscriber) in pending.iter().enumerate() {
+        if index > 0 && index % CHECKPOINT_INTERVAL == 0 {
+            let checkpoint = SendCheckpoint {
+                last_subscriber_id: Some(pending[index - 1].id.clone()),
+                sent: sent_count,
+                at: Utc::now(),
+            };
+            progress.save(campaigns, &campaign.id, &checkpoint).await;
+        }
+        if index % CHECKPOINT_INTERVAL == 0 && is_cancelling(campaigns, &campaign.id).await {
+            let skipped = (pending.len() - index) as u32;
+            info!(
+                "Campaign {} cancelled, skipping {} recipients",
+                campaign.id, skipped
+            );
+            return Ok(SendCounts {
+                sent: sent_count,
+                skipped,
+            });
+        }
+
+        let variables = subscriber_variables(subscriber);
+        let subject = render_text(&campaign.subject, &variables);
+        let html_body = render_campaign_html(
+            &campaign.html_body,
+            subscriber,
+            &campaign.id,
+            campaign.list.as_deref(),
+        );
+        let html_body = if pixel_tracking {
+            // Without a signed pixel the email still goes out, untracked
+            with_open_tracking(&html_body, &subscriber.id, &campaign.id).unwrap_or_else(|e| {
+                info!("Error adding open tracking: {:?}", e);
+                html_body
+            })
+        } else {
+            html_body
+        };
+
+        match mailer
+            .send(
+                EmailKind::Campaign,
+                subscriber,
+                Some(&campaign.id),
+                &subject,
+                &html_body,
+            )
+            .await
+        {
+            Ok(_) => sent_count += 1,
+            Err(e) => info!("Failed to send campaign to {}: {:?}", subscriber.email, e),
+        }
+    }
+
+    Ok(SendCounts {
+        sent: sent_count,
+        skipped: 0,
+    })
+}
+
+// Stop a scheduled campaign once it has been asked to cancel: buckets that
+// haven't gone out are closed with nothing sent and their recipients counted
+// as skipped. The campaign is marked cancelled unless a bucket is being sent
+// right now, in which case the `scheduled_send` run sending it finishes it.
+// Returns whether the campaign is now cancelled.
+pub async fn cancel_schedule(
+    subscribers: &SubscriberRepository,
+    campaigns: &CampaignRepository,
+    schedules: &ScheduleStore,
+    snapshots: &RecipientSnapshotStore,
+    campaign: &Campaign,
+) -> Result<bool, Error> {
+    let buckets = schedules.list(&campaign.id).await?;
+    let mut recipients = scheduled_recipients(subscribers, snapshots, campaign).await?;
+
+    for bucket in buckets
+        .iter()
+        .filter(|bucket| bucket.status == BucketStatus::Pending)
+    {
+        // Lost to a scheduled_send run that started it just now
+        if !schedules.claim(bucket).await? {
+            continue;
+        }
+        let (skipped, rest): (Vec<Subscriber>, Vec<Subscriber>) =
+            recipients.into_iter().partition(|subscriber| {
+                bucket_for(subscriber, &buckets) == Some(bucket.timezone.as_str())
+            });
+        recipients = rest;
+        campaigns
+            .add_skipped(&campaign.id, skipped.len() as u32)
+            .await?;
+        schedules.complete(bucket, 0).await?;
+    }
+
+    finish_cancellation(campaigns, schedules, &campaign.id).await
+}
+
+// Mark a cancelled scheduled campaign cancelled once none of its buckets is
+// still pending or being sent, returning whether it was
+pub async fn finish_cancellation(
+    campaigns: &CampaignRepository,
+    schedules: &ScheduleStore,
+    campaign_id: &str,
+) -> Result<bool, Error> {
+    let buckets = schedules.list(campaign_id).await?;
+    if !buckets
+        .iter()
+        .all(|bucket| bucket.status == BucketStatus::Sent)
+    {
+        return Ok(false);
+    }
+
+    let sent_count = buckets.iter().map(|bucket| bucket.sent_count).sum();
+    campaigns.mark_cancelled(campaign_id, sent_count).await?;
+    Ok(true)
+}
@@ -0,0 +1,194 @@
+use crate::campaign::Campaign;
+use crate::config::table_name;
+use crate::content::render_campaign_html;
+use crate::i18n::Locale;
+use crate::template::{escape_html, render_text, subscriber_variables};
+use crate::{DIGEST_ITEMS_TABLE_NAME, Error, Subscriber, optional_datetime};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+// Posts older than this are dropped from the digest table by DynamoDB TTL
+const ITEM_RETENTION_DAYS: i64 = 30;
+
+// How often a subscriber wants to hear from us
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    // Every campaign as it is sent
+    #[default]
+    Immediate,
+    // One combined email a week with the campaigns sent since the last one
+    Weekly,
+}
+
+impl Frequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::Immediate => "immediate",
+            Frequency::Weekly => "weekly",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "immediate" => Some(Frequency::Immediate),
+            "weekly" => Some(Frequency::Weekly),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FrequencyRequest {
+    pub frequency: Frequency,
+}
+
+// A sent campaign waiting to go out in the next digests
+#[derive(Debug, Clone)]
+pub struct DigestItem {
+    pub campaign_id: String,
+    pub added_at: DateTime<Utc>,
+}
+
+impl DigestItem {
+    fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert(
+            "campaign_id".to_string(),
+            AttributeValue::S(self.campaign_id.clone()),
+        );
+        item.insert(
+            "added_at".to_string(),
+            AttributeValue::S(self.added_at.to_rfc3339()),
+        );
+        item.insert(
+            "expires_at".to_string(),
+            AttributeValue::N(
+                (self.added_at + Duration::days(ITEM_RETENTION_DAYS))
+                    .timestamp()
+                    .to_string(),
+            ),
+        );
+        item
+    }
+
+    fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        Some(Self {
+            campaign_id: item.get("campaign_id")?.as_s().ok()?.clone(),
+            added_at: optional_datetime(item, "added_at")?,
+        })
+    }
+}
+
+// Campaigns sent recently, one item per campaign, read by the digest builder
+pub struct DigestStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DigestStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(DIGEST_ITEMS_TABLE_NAME),
+        }
+    }
+
+    pub async fn add(&self, campaign_id: &str) -> Result<(), Error> {
+        let item = DigestItem {
+            campaign_id: campaign_id.to_string(),
+            added_at: Utc::now(),
+        };
+
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item.to_dynamodb_item()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Items added after `since`, oldest first. A few campaigns a week are kept
+    // for a month, so the table is scanned.
+    pub async fn added_since(&self, since: DateTime<Utc>) -> Result<Vec<DigestItem>, Error> {
+        let mut items = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("added_at > :since")
+                .expression_attribute_values(":since", AttributeValue::S(since.to_rfc3339()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            items.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(DigestItem::from_dynamodb_item),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        items.sort_by_key(|item| item.added_at);
+        Ok(items)
+    }
+}
+
+// Id used to tag and track a digest, one per ISO week, e.g. `digest-2026-W42`
+pub fn digest_id(at: DateTime<Utc>) -> String {
+    format!("digest-{}", at.format("%G-W%V"))
+}
+
+fn digest_subject(locale: Locale, count: usize) -> String {
+    match (locale, count) {
+        (Locale::En, 1) => "Your weekly digest: 1 new post".to_string(),
+        (Locale::En, _) => format!("Your weekly digest: {count} new posts"),
+        (Locale::Es, 1) => "Tu resumen semanal: 1 publicación nueva".to_string(),
+        (Locale::Es, _) => format!("Tu resumen semanal: {count} publicaciones nuevas"),
+    }
+}
+
+// One email combining the given campaigns, each rendered for the subscriber
+// under its own subject as a heading, and as it would be sent on its own: with
+// the default footer linking to its web version and unsubscribe link, and
+// sanitized
+pub fn render_digest(subscriber: &Subscriber, campaigns: &[&Campaign]) -> (String, String) {
+    let variables = subscriber_variables(subscriber);
+
+    let sections: Vec<String> = campaigns
+        .iter()
+        .map(|campaign| {
+            format!(
+                "<h2>{}</h2>\n{}",
+                escape_html(&render_text(&campaign.subject, &variables)),
+                render_campaign_html(
+                    &campaign.html_body,
+                    subscriber,
+                    &campaign.id,
+                    campaign.list.as_deref()
+                )
+            )
+        })
+        .collect();
+
+    (
+        digest_subject(subscriber.locale, campaigns.len()),
+        sections.join("\n<hr />\n"),
+    )
+}
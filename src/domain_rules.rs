@@ -0,0 +1,208 @@
+use crate::config::table_name;
+use crate::search::email_domain;
+use crate::{DOMAIN_RULES_TABLE_NAME, Error, optional_datetime};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleKind {
+    // Once any allow rule exists, only allowed domains can sign up
+    Allow,
+    Deny,
+}
+
+impl RuleKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleKind::Allow => "allow",
+            RuleKind::Deny => "deny",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "allow" => Some(RuleKind::Allow),
+            "deny" => Some(RuleKind::Deny),
+            _ => None,
+        }
+    }
+}
+
+// An operator-managed rule for a signup domain, covering its subdomains too
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DomainRule {
+    pub domain: String,
+    pub kind: RuleKind,
+    // Why the rule exists, e.g. "closed beta" or "abuse reports"
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DomainRuleRequest {
+    pub kind: RuleKind,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DomainRuleListResponse {
+    pub success: bool,
+    pub rules: Vec<DomainRule>,
+}
+
+// Lowercased domain without a leading `@`, in punycode like the addresses it's
+// matched against, or None when it isn't a plausible domain
+pub fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = idna::domain_to_ascii(domain.trim().trim_start_matches('@')).ok()?;
+    let valid = domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        });
+    valid.then_some(domain)
+}
+
+impl DomainRule {
+    pub fn new(domain: String, kind: RuleKind, note: Option<String>) -> Self {
+        Self {
+            domain,
+            kind,
+            note,
+            created_at: Utc::now(),
+        }
+    }
+
+    // Whether the rule covers a domain, itself or as a subdomain
+    pub fn covers(&self, domain: &str) -> bool {
+        domain == self.domain
+            || domain
+                .strip_suffix(&self.domain)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    }
+
+    fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert("domain".to_string(), AttributeValue::S(self.domain.clone()));
+        item.insert(
+            "kind".to_string(),
+            AttributeValue::S(self.kind.as_str().to_string()),
+        );
+        if let Some(note) = &self.note {
+            item.insert("note".to_string(), AttributeValue::S(note.clone()));
+        }
+        item.insert(
+            "created_at".to_string(),
+            AttributeValue::S(self.created_at.to_rfc3339()),
+        );
+        item
+    }
+
+    fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        Some(Self {
+            domain: item.get("domain")?.as_s().ok()?.clone(),
+            kind: RuleKind::parse(item.get("kind")?.as_s().ok()?)?,
+            note: item
+                .get("note")
+                .and_then(|value| value.as_s().ok())
+                .cloned(),
+            created_at: optional_datetime(item, "created_at").unwrap_or_else(Utc::now),
+        })
+    }
+}
+
+// Decide whether an email may sign up under the given rules: denied domains are
+// rejected, and with any allow rule in place only allowed domains get through
+pub fn is_allowed(rules: &[DomainRule], email: &str) -> bool {
+    let Some(domain) = email_domain(email) else {
+        return false;
+    };
+
+    let covering = |kind: RuleKind| {
+        rules
+            .iter()
+            .any(|rule| rule.kind == kind && rule.covers(&domain))
+    };
+
+    if covering(RuleKind::Deny) {
+        return false;
+    }
+    !rules.iter().any(|rule| rule.kind == RuleKind::Allow) || covering(RuleKind::Allow)
+}
+
+// Allow and deny rules for signup domains, one item per domain
+pub struct DomainRuleStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DomainRuleStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(DOMAIN_RULES_TABLE_NAME),
+        }
+    }
+
+    // Every rule, ordered by domain. Operators manage a handful of them, so the
+    // table is scanned on each signup.
+    pub async fn list(&self) -> Result<Vec<DomainRule>, Error> {
+        let mut rules = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            rules.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(DomainRule::from_dynamodb_item),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        rules.sort_by(|a, b| a.domain.cmp(&b.domain));
+        Ok(rules)
+    }
+
+    // Create or replace the rule for a domain
+    pub async fn put(&self, rule: &DomainRule) -> Result<(), Error> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(rule.to_dynamodb_item()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Remove the rule for a domain, returning whether there was one
+    pub async fn delete(&self, domain: &str) -> Result<bool, Error> {
+        let result = self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("domain", AttributeValue::S(domain.to_string()))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await?;
+
+        Ok(result.attributes().is_some())
+    }
+}
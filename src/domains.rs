@@ -0,0 +1,357 @@
+use crate::config::table_name;
+use crate::events::EventType;
+use crate::search::email_domain;
+use crate::{DOMAIN_STATS_TABLE_NAME, Error};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use tracing::info;
+use utoipa::ToSchema;
+
+// Daily counters are kept this long, enough to compare a drop against the months before it
+const RETENTION_DAYS: i64 = 400;
+
+// Mailbox providers hosting many domains, so e.g. Outlook deliverability can be
+// looked at as a whole. Anything else is reported as "other", mostly corporate domains.
+const PROVIDERS: &[(&str, &[&str])] = &[
+    ("gmail", &["gmail.com", "googlemail.com"]),
+    (
+        "microsoft",
+        &["outlook.com", "hotmail.com", "live.com", "msn.com"],
+    ),
+    ("yahoo", &["yahoo.com", "ymail.com", "rocketmail.com"]),
+    ("apple", &["icloud.com", "me.com", "mac.com"]),
+    ("proton", &["proton.me", "protonmail.com", "pm.me"]),
+    ("aol", &["aol.com"]),
+];
+
+// The mailbox provider behind a domain, matching country variants like
+// yahoo.co.uk or hotmail.fr
+pub fn provider(domain: &str) -> &'static str {
+    let (name, suffix) = domain.split_once('.').unwrap_or((domain, ""));
+    let country_variant = suffix.rsplit('.').next().is_some_and(|tld| tld.len() == 2)
+        && suffix.split('.').all(|label| label.len() <= 3);
+
+    PROVIDERS
+        .iter()
+        .find(|(_, domains)| {
+            domains.iter().any(|known| {
+                domain == *known
+                    || (country_variant
+                        && known.split_once('.').map(|(known, _)| known) == Some(name))
+            })
+        })
+        .map(|(provider, _)| *provider)
+        .unwrap_or("other")
+}
+
+// Something that happened to a subscriber at a domain, counted per day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainMetric {
+    Subscribed,
+    Confirmed,
+    Unsubscribed,
+    Delivery,
+    Open,
+    Click,
+    Bounce,
+    Reject,
+    Complaint,
+}
+
+impl DomainMetric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomainMetric::Subscribed => "subscribed",
+            DomainMetric::Confirmed => "confirmed",
+            DomainMetric::Unsubscribed => "unsubscribed",
+            DomainMetric::Delivery => "delivered",
+            DomainMetric::Open => "opens",
+            DomainMetric::Click => "clicks",
+            DomainMetric::Bounce => "bounces",
+            DomainMetric::Reject => "rejects",
+            DomainMetric::Complaint => "complaints",
+        }
+    }
+
+    pub fn from_event(event_type: EventType) -> Self {
+        match event_type {
+            EventType::Open => DomainMetric::Open,
+            EventType::Click => DomainMetric::Click,
+            EventType::Delivery => DomainMetric::Delivery,
+            EventType::Reject => DomainMetric::Reject,
+            EventType::Bounce => DomainMetric::Bounce,
+            EventType::Complaint => DomainMetric::Complaint,
+        }
+    }
+
+    const ALL: [DomainMetric; 9] = [
+        DomainMetric::Subscribed,
+        DomainMetric::Confirmed,
+        DomainMetric::Unsubscribed,
+        DomainMetric::Delivery,
+        DomainMetric::Open,
+        DomainMetric::Click,
+        DomainMetric::Bounce,
+        DomainMetric::Reject,
+        DomainMetric::Complaint,
+    ];
+}
+
+// Counters for a domain or provider over a period, or a single day
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct DomainStats {
+    // Domain, or provider when grouped by provider
+    pub value: String,
+    // Set on the per-day rows of a single domain
+    pub day: Option<String>,
+    pub subscribed: u64,
+    pub confirmed: u64,
+    pub unsubscribed: u64,
+    pub delivered: u64,
+    pub opens: u64,
+    pub clicks: u64,
+    pub bounces: u64,
+    pub rejects: u64,
+    pub complaints: u64,
+    // Opens and bounces relative to deliveries, 0 without deliveries
+    pub open_rate: f64,
+    pub bounce_rate: f64,
+}
+
+impl DomainStats {
+    fn counter(&mut self, metric: DomainMetric) -> &mut u64 {
+        match metric {
+            DomainMetric::Subscribed => &mut self.subscribed,
+            DomainMetric::Confirmed => &mut self.confirmed,
+            DomainMetric::Unsubscribed => &mut self.unsubscribed,
+            DomainMetric::Delivery => &mut self.delivered,
+            DomainMetric::Open => &mut self.opens,
+            DomainMetric::Click => &mut self.clicks,
+            DomainMetric::Bounce => &mut self.bounces,
+            DomainMetric::Reject => &mut self.rejects,
+            DomainMetric::Complaint => &mut self.complaints,
+        }
+    }
+
+    fn add_item(&mut self, item: &HashMap<String, AttributeValue>) {
+        for metric in DomainMetric::ALL {
+            let count = item
+                .get(metric.as_str())
+                .and_then(|value| value.as_n().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+                .unwrap_or(0);
+            *self.counter(metric) += count;
+        }
+    }
+
+    fn with_rates(mut self) -> Self {
+        if self.delivered > 0 {
+            self.open_rate = self.opens as f64 / self.delivered as f64;
+            self.bounce_rate = self.bounces as f64 / self.delivered as f64;
+        }
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DomainStatsResponse {
+    pub success: bool,
+    pub days: i64,
+    // "domain" or "provider", or "day" for a single domain
+    pub by: String,
+    pub domains: Vec<DomainStats>,
+}
+
+// Per-domain daily counters, one item per domain and UTC day, updated as things
+// happen and removed by DynamoDB TTL
+pub struct DomainStatsStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DomainStatsStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(DOMAIN_STATS_TABLE_NAME),
+        }
+    }
+
+    // Count one occurrence of `metric` for the domain of `email`
+    pub async fn record(
+        &self,
+        email: &str,
+        metric: DomainMetric,
+        at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let Some(domain) = email_domain(email) else {
+            return Ok(());
+        };
+
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("domain", AttributeValue::S(domain))
+            .key("day", AttributeValue::S(at.format("%Y-%m-%d").to_string()))
+            .update_expression("ADD #metric :one SET expires_at = :expires_at")
+            .expression_attribute_names("#metric", metric.as_str())
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(
+                ":expires_at",
+                AttributeValue::N(
+                    (at + Duration::days(RETENTION_DAYS))
+                        .timestamp()
+                        .to_string(),
+                ),
+            )
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Stats are secondary to the change they count, so failures are only logged
+    pub async fn record_or_log(&self, email: &str, metric: DomainMetric, at: DateTime<Utc>) {
+        if let Err(e) = self.record(email, metric, at).await {
+            info!("Error recording {} domain stat: {:?}", metric.as_str(), e);
+        }
+    }
+
+    // Totals per domain, or per provider, over the last `days` days, most
+    // subscribed first. The table holds one small item per domain and day, so
+    // it is scanned rather than indexed.
+    pub async fn summary(&self, days: i64, by_provider: bool) -> Result<Vec<DomainStats>, Error> {
+        let since = since(days);
+        let mut totals: BTreeMap<String, DomainStats> = BTreeMap::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("#day >= :since")
+                .expression_attribute_names("#day", "day")
+                .expression_attribute_values(":since", AttributeValue::S(since.clone()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            for item in result.items().unwrap_or_default() {
+                let Some(domain) = item.get("domain").and_then(|value| value.as_s().ok()) else {
+                    continue;
+                };
+                let value = if by_provider {
+                    provider(domain).to_string()
+                } else {
+                    domain.clone()
+                };
+                totals
+                    .entry(value.clone())
+                    .or_insert_with(|| DomainStats {
+                        value,
+                        ..DomainStats::default()
+                    })
+                    .add_item(item);
+            }
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        let mut stats: Vec<DomainStats> =
+            totals.into_values().map(DomainStats::with_rates).collect();
+        stats.sort_by(|a, b| {
+            b.subscribed
+                .cmp(&a.subscribed)
+                .then(b.delivered.cmp(&a.delivered))
+        });
+        Ok(stats)
+    }
+
+    // Counters of every domain added up for one UTC day, e.g. for the daily pulse
+    pub async fn day_totals(&self, day: NaiveDate) -> Result<DomainStats, Error> {
+        let day = day.format("%Y-%m-%d").to_string();
+        let mut totals = DomainStats {
+            value: "all".to_string(),
+            day: Some(day.clone()),
+            ..DomainStats::default()
+        };
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("#day = :day")
+                .expression_attribute_names("#day", "day")
+                .expression_attribute_values(":day", AttributeValue::S(day.clone()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            for item in result.items().unwrap_or_default() {
+                totals.add_item(item);
+            }
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(totals.with_rates())
+    }
+
+    // One domain's counters day by day over the last `days` days, oldest first
+    pub async fn daily(&self, domain: &str, days: i64) -> Result<Vec<DomainStats>, Error> {
+        let mut stats = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("#domain = :domain AND #day >= :since")
+                .expression_attribute_names("#domain", "domain")
+                .expression_attribute_names("#day", "day")
+                .expression_attribute_values(":domain", AttributeValue::S(domain.to_lowercase()))
+                .expression_attribute_values(":since", AttributeValue::S(since(days)))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            for item in result.items().unwrap_or_default() {
+                let mut day = DomainStats {
+                    value: domain.to_lowercase(),
+                    day: item.get("day").and_then(|value| value.as_s().ok()).cloned(),
+                    ..DomainStats::default()
+                };
+                day.add_item(item);
+                stats.push(day.with_rates());
+            }
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+// First day included in a window of `days` days ending today
+fn since(days: i64) -> String {
+    let today: NaiveDate = Utc::now().date_naive();
+    (today - Duration::days(days.max(1) - 1))
+        .format("%Y-%m-%d")
+        .to_string()
+}
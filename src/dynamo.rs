@@ -0,0 +1,191 @@
+use crate::Error;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+
+// Table records are (de)serialized with serde_dynamo, so a field added to a
+// record struct is stored without touching any conversion code. The helpers
+// below keep attributes in the formats the tables already hold.
+
+pub fn to_item<T: Serialize>(record: &T) -> Result<HashMap<String, AttributeValue>, Error> {
+    Ok(serde_dynamo::to_item(record)?)
+}
+
+pub fn from_item<T: DeserializeOwned>(item: &HashMap<String, AttributeValue>) -> Result<T, Error> {
+    Ok(serde_dynamo::from_item(item.clone())?)
+}
+
+// Timestamps as RFC 3339 strings, as written by `to_rfc3339`, so they compare
+// correctly with the strings used in condition and filter expressions
+pub mod rfc3339 {
+    use chrono::{DateTime, Utc};
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_rfc3339())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&value)
+            .map(|value| value.with_timezone(&Utc))
+            .map_err(D::Error::custom)
+    }
+}
+
+// Optional RFC 3339 timestamps. Pair with `default` and
+// `skip_serializing_if = "Option::is_none"`; unparseable values read as None.
+pub mod rfc3339_option {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_str(&value.to_rfc3339()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Ok(Option::<String>::deserialize(deserializer)?
+            .and_then(|value| DateTime::parse_from_rfc3339(&value).ok())
+            .map(|value| value.with_timezone(&Utc)))
+    }
+}
+
+// Optional epoch-seconds numbers, the format DynamoDB TTL attributes need.
+// Anything else reads as None.
+pub mod epoch_seconds_option {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_i64(value.timestamp()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Ok(Option::<serde_json::Value>::deserialize(deserializer)?
+            .and_then(|value| value.as_i64())
+            .and_then(|value| DateTime::from_timestamp(value, 0)))
+    }
+}
+
+// An enum stored as a string, falling back to its default for missing or
+// unknown values, e.g. one written by a newer build
+pub fn or_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    Ok(or_none(deserializer)?.unwrap_or_default())
+}
+
+// An optional enum stored as a string, None for unknown values
+pub fn or_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    Ok(
+        Option::<String>::deserialize(deserializer)?.and_then(|value| {
+            T::deserialize(IntoDeserializer::<serde::de::value::Error>::into_deserializer(value))
+                .ok()
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        #[serde(with = "rfc3339")]
+        created_at: DateTime<Utc>,
+        #[serde(
+            default,
+            with = "rfc3339_option",
+            skip_serializing_if = "Option::is_none"
+        )]
+        sent_at: Option<DateTime<Utc>>,
+        #[serde(
+            default,
+            with = "epoch_seconds_option",
+            skip_serializing_if = "Option::is_none"
+        )]
+        expires_at: Option<DateTime<Utc>>,
+    }
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn timestamps_round_trip_in_their_stored_formats() {
+        let record = Record {
+            created_at: at(1_790_000_000),
+            sent_at: Some(at(1_790_000_060)),
+            expires_at: Some(at(1_790_086_400)),
+        };
+        let item = to_item(&record).unwrap();
+
+        assert_eq!(
+            item["created_at"],
+            AttributeValue::S("2026-09-21T14:13:20+00:00".to_string())
+        );
+        assert_eq!(
+            item["expires_at"],
+            AttributeValue::N("1790086400".to_string())
+        );
+        assert_eq!(from_item::<Record>(&item).unwrap(), record);
+    }
+
+    #[test]
+    fn unreadable_optional_timestamps_read_as_none() {
+        let item = HashMap::from([
+            (
+                "created_at".to_string(),
+                AttributeValue::S("2026-09-21T14:13:20+00:00".to_string()),
+            ),
+            (
+                "sent_at".to_string(),
+                AttributeValue::S("yesterday".to_string()),
+            ),
+            (
+                "expires_at".to_string(),
+                AttributeValue::S("1790086400".to_string()),
+            ),
+        ]);
+
+        let record = from_item::<Record>(&item).unwrap();
+        assert_eq!(record.sent_at, None);
+        assert_eq!(record.expires_at, None);
+    }
+
+    #[test]
+    fn missing_required_timestamps_fail() {
+        assert!(from_item::<Record>(&HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,595 @@
+use crate::address::sendable_email;
+use crate::config::{env_or, is_production};
+use crate::{Error, Subscriber, api_url, signing};
+use aws_config::SdkConfig;
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_sesv2::Client;
+use aws_sdk_sesv2::error::SdkError;
+use aws_sdk_sesv2::operation::send_email::SendEmailError;
+use aws_sdk_sesv2::primitives::Blob;
+use aws_sdk_sesv2::types::{
+    Body, Content, Destination, EmailContent, Message, MessageTag, RawMessage,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::Utc;
+use std::env;
+use tracing::info;
+use url::form_urlencoded;
+use uuid::Uuid;
+
+// One-click unsubscribe link for a subscriber (RFC 8058)
+pub fn one_click_unsubscribe_url(subscriber_id: &str) -> Result<String, Error> {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("id", subscriber_id)
+        .append_pair("token", &signing::sign(subscriber_id)?)
+        .finish();
+
+    Ok(format!("{}/unsubscribe/one-click?{}", api_url(), query))
+}
+
+// Signed link leaving a single list, as opposed to the global opt-out of
+// `one_click_unsubscribe_url`
+pub fn list_unsubscribe_url(subscriber_id: &str, list: &str) -> Result<String, Error> {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("id", subscriber_id)
+        .append_pair("list", list)
+        .append_pair(
+            "token",
+            &signing::sign(&list_unsubscribe_key(subscriber_id, list))?,
+        )
+        .finish();
+
+    Ok(format!("{}/unsubscribe/one-click?{}", api_url(), query))
+}
+
+// What a list unsubscribe token signs, so it can't be replayed as a global
+// opt-out or for another list
+pub fn list_unsubscribe_key(subscriber_id: &str, list: &str) -> String {
+    format!("list:{list}:{subscriber_id}")
+}
+
+// Signed link to the hosted web version of a campaign as sent to a subscriber
+pub fn web_version_url(subscriber_id: &str, campaign_id: &str) -> Result<String, Error> {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("c", campaign_id)
+        .append_pair("s", subscriber_id)
+        .append_pair(
+            "token",
+            &signing::sign(&web_version_key(subscriber_id, campaign_id))?,
+        )
+        .finish();
+
+    Ok(format!("{}/web?{}", api_url(), query))
+}
+
+// What a web version token signs, so it doesn't double as an unsubscribe token
+pub fn web_version_key(subscriber_id: &str, campaign_id: &str) -> String {
+    format!("web:{campaign_id}:{subscriber_id}")
+}
+
+// Signed link to a subscriber's referral code and milestone progress
+pub fn referral_status_url(subscriber_id: &str) -> Result<String, Error> {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("id", subscriber_id)
+        .append_pair("token", &signing::sign(&referral_key(subscriber_id))?)
+        .finish();
+
+    Ok(format!("{}/referral?{}", api_url(), query))
+}
+
+// What a referral token signs, so it doesn't double as an unsubscribe token
+pub fn referral_key(subscriber_id: &str) -> String {
+    format!("referral:{subscriber_id}")
+}
+
+// Line width of the generated plain text part
+const TEXT_WIDTH: usize = 78;
+
+// Readable plain text version of an HTML body, with links listed as numbered
+// references, sent as the text/plain alternative of every email
+pub fn html_to_text(html_body: &str) -> String {
+    html2text::from_read(html_body.as_bytes(), TEXT_WIDTH)
+}
+
+// Message tags identifying the recipient and campaign in SES events
+pub const SUBSCRIBER_TAG: &str = "subscriber_id";
+pub const CAMPAIGN_TAG: &str = "campaign_id";
+
+// Header values must never contain line breaks, or they could inject extra headers
+fn sanitize_header(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+// RFC 2047 encoding for non-ASCII header values such as subjects
+fn encode_header(value: &str) -> String {
+    let value = sanitize_header(value);
+    if value.is_ascii() {
+        value
+    } else {
+        format!("=?UTF-8?B?{}?=", STANDARD.encode(value))
+    }
+}
+
+// A From or Reply-To value with an optional display name, e.g.
+// `"Example Weekly" <weekly@example.com>`
+pub fn format_address(name: Option<&str>, address: &str) -> String {
+    let address = sanitize_header(address.trim());
+    match name.map(str::trim).filter(|name| !name.is_empty()) {
+        Some(name) if name.is_ascii() => format!(
+            "\"{}\" <{}>",
+            sanitize_header(name)
+                .replace('\\', "\\\\")
+                .replace('"', "\\\""),
+            address
+        ),
+        Some(name) => format!("{} <{}>", encode_header(name), address),
+        None => address,
+    }
+}
+
+// A file attached to an email
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub filename: String,
+    // e.g. "application/pdf"
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    // MIME part headers, with the file name quoted, or RFC 2047 encoded when
+    // it isn't ASCII
+    fn headers(&self) -> String {
+        let filename = if self.filename.is_ascii() {
+            format!(
+                "\"{}\"",
+                sanitize_header(&self.filename)
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+            )
+        } else {
+            format!("\"{}\"", encode_header(&self.filename))
+        };
+
+        format!(
+            "Content-Type: {}; name={}\r\nContent-Disposition: attachment; filename={}\r\nContent-Transfer-Encoding: base64",
+            sanitize_header(&self.content_type),
+            filename,
+            filename
+        )
+    }
+}
+
+// Base64 body split into 76 character lines, as required by MIME
+fn encode_body(body: impl AsRef<[u8]>) -> String {
+    let encoded = STANDARD.encode(body);
+    encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+// Whether a failed send is worth retrying: throttling, sending limits, timeouts
+// and network or server errors are, rejected messages and account problems aren't
+pub fn is_transient(error: &Error) -> bool {
+    let Some(error) = error.downcast_ref::<SdkError<SendEmailError>>() else {
+        return false;
+    };
+    match error {
+        SdkError::ServiceError(error) => {
+            let error = error.err();
+            error.is_too_many_requests_exception() || error.is_limit_exceeded_exception()
+        }
+        _ => true,
+    }
+}
+
+// DRY_RUN=true renders and logs every email instead of sending it, so staging
+// environments loaded with production data can't reach real subscribers. With
+// DRY_RUN_BUCKET set, each raw message is also kept in S3 for inspection.
+#[derive(Clone)]
+struct DryRun {
+    client: S3Client,
+    bucket: Option<String>,
+}
+
+impl DryRun {
+    fn from_env(config: &SdkConfig) -> Option<Self> {
+        if !env_or("DRY_RUN", false) {
+            return None;
+        }
+
+        Some(Self {
+            client: S3Client::new(config),
+            bucket: env::var("DRY_RUN_BUCKET")
+                .ok()
+                .filter(|bucket| !bucket.is_empty()),
+        })
+    }
+
+    async fn record(
+        &self,
+        subscriber_id: &str,
+        email: &str,
+        campaign_id: Option<&str>,
+        subject: &str,
+        message: String,
+    ) -> Result<(), Error> {
+        info!(
+            "Dry run, not sending \"{}\" to {} ({} bytes)",
+            subject,
+            email,
+            message.len()
+        );
+
+        let Some(bucket) = &self.bucket else {
+            return Ok(());
+        };
+        // Grouped by day and campaign, transactional emails under "transactional"
+        let key = format!(
+            "dry-run/{}/{}/{}-{}.eml",
+            Utc::now().format("%Y-%m-%d"),
+            campaign_id.unwrap_or("transactional"),
+            subscriber_id,
+            Uuid::new_v4()
+        );
+        self.client
+            .put_object()
+            .bucket(bucket)
+            .key(&key)
+            .content_type("message/rfc822")
+            .body(ByteStream::from(message.into_bytes()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+// Outside production, only allowlisted recipients get their own emails. Anyone
+// else's are redirected to a catch-all inbox, so tests on copied data never
+// reach real subscribers.
+#[derive(Clone)]
+struct Sandbox {
+    // Lowercased addresses and domains from RECIPIENT_ALLOWLIST
+    allowlist: Vec<String>,
+    // CATCH_ALL_EMAIL, without which emails to other recipients are dropped
+    catch_all: Option<String>,
+}
+
+impl Sandbox {
+    fn from_env() -> Option<Self> {
+        if is_production() {
+            return None;
+        }
+
+        Some(Self {
+            allowlist: env::var("RECIPIENT_ALLOWLIST")
+                .unwrap_or_default()
+                .split(',')
+                .map(|entry| entry.trim().trim_start_matches('@').to_ascii_lowercase())
+                .filter(|entry| !entry.is_empty())
+                .collect(),
+            catch_all: env::var("CATCH_ALL_EMAIL")
+                .ok()
+                .map(|email| email.trim().to_string())
+                .filter(|email| !email.is_empty()),
+        })
+    }
+
+    // Entries with an @ match the whole address, others its domain
+    fn allows(&self, email: &str) -> bool {
+        let email = email.trim().to_ascii_lowercase();
+        let domain = email.rsplit_once('@').map(|(_, domain)| domain);
+        self.allowlist.iter().any(|entry| {
+            if entry.contains('@') {
+                *entry == email
+            } else {
+                domain == Some(entry.as_str())
+            }
+        })
+    }
+
+    // Where an email for `email` actually goes, None when nowhere
+    fn recipient<'a>(&'a self, email: &'a str) -> Option<&'a str> {
+        if self.allows(email) {
+            Some(email)
+        } else {
+            self.catch_all.as_deref()
+        }
+    }
+}
+
+// Thin wrapper around SES used for every outgoing email
+#[derive(Clone)]
+pub struct EmailSender {
+    client: Client,
+    from_address: String,
+    reply_to: Option<String>,
+    // SES configuration set publishing delivery, open and click events
+    configuration_set: Option<String>,
+    dry_run: Option<DryRun>,
+    sandbox: Option<Sandbox>,
+}
+
+impl EmailSender {
+    pub fn new(config: &SdkConfig, from_address: String) -> Self {
+        Self {
+            client: Client::new(config),
+            from_address,
+            reply_to: None,
+            configuration_set: env::var("SES_CONFIGURATION_SET")
+                .ok()
+                .filter(|name| !name.is_empty()),
+            dry_run: DryRun::from_env(config),
+            sandbox: Sandbox::from_env(),
+        }
+    }
+
+    // The same sender with another From and Reply-To, e.g. a campaign's sender identity
+    pub fn with_from(&self, from_address: String, reply_to: Option<String>) -> Self {
+        Self {
+            from_address,
+            reply_to,
+            ..self.clone()
+        }
+    }
+
+    // Build the raw multipart/alternative MIME message with a plain text and an
+    // HTML part, including the List-Unsubscribe headers Gmail and Yahoo require
+    // from bulk senders. With attachments, it is wrapped in multipart/mixed
+    // along with them.
+    fn build_message(
+        &self,
+        subscriber_id: &str,
+        email: &str,
+        original_recipient: Option<&str>,
+        subject: &str,
+        html_body: &str,
+        attachments: &[Attachment],
+    ) -> Result<String, Error> {
+        let unsubscribe_url = one_click_unsubscribe_url(subscriber_id)?;
+        // "=_" never appears in base64, so the boundary can't clash with the parts
+        let boundary = format!("=_{}", Uuid::new_v4().simple());
+        let mixed_boundary = format!("=_{}", Uuid::new_v4().simple());
+
+        let mut headers = vec![
+            format!("From: {}", sanitize_header(&self.from_address)),
+            format!("To: {}", sanitize_header(email)),
+            format!("Subject: {}", encode_header(subject)),
+            "MIME-Version: 1.0".to_string(),
+            format!("List-Unsubscribe: <{}>", unsubscribe_url),
+            "List-Unsubscribe-Post: List-Unsubscribe=One-Click".to_string(),
+        ];
+        let alternative_type = format!(
+            "Content-Type: multipart/alternative; boundary=\"{}\"",
+            boundary
+        );
+        if attachments.is_empty() {
+            headers.push(alternative_type.clone());
+        } else {
+            headers.push(format!(
+                "Content-Type: multipart/mixed; boundary=\"{}\"",
+                mixed_boundary
+            ));
+        }
+        if let Some(reply_to) = &self.reply_to {
+            headers.insert(1, format!("Reply-To: {}", sanitize_header(reply_to)));
+        }
+        // Redirected by the sandbox, so testers can see who it was meant for
+        if let Some(original_recipient) = original_recipient {
+            headers.push(format!(
+                "X-Original-To: {}",
+                sanitize_header(original_recipient)
+            ));
+        }
+
+        // Plain text first, as clients show the last alternative they support
+        let parts = [
+            ("text/plain", encode_body(html_to_text(html_body))),
+            ("text/html", encode_body(html_body)),
+        ]
+        .map(|(content_type, body)| {
+            format!(
+                "--{}\r\nContent-Type: {}; charset=UTF-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}\r\n",
+                boundary, content_type, body
+            )
+        });
+
+        let alternative = format!("{}--{}--\r\n", parts.concat(), boundary);
+
+        if attachments.is_empty() {
+            return Ok(format!("{}\r\n\r\n{}", headers.join("\r\n"), alternative));
+        }
+
+        let files: String = attachments
+            .iter()
+            .map(|attachment| {
+                format!(
+                    "--{}\r\n{}\r\n\r\n{}\r\n",
+                    mixed_boundary,
+                    attachment.headers(),
+                    encode_body(&attachment.data)
+                )
+            })
+            .collect();
+
+        Ok(format!(
+            "{}\r\n\r\n--{}\r\n{}\r\n\r\n{}{}--{}--\r\n",
+            headers.join("\r\n"),
+            mixed_boundary,
+            alternative_type,
+            alternative,
+            files,
+            mixed_boundary
+        ))
+    }
+
+    pub async fn send(
+        &self,
+        subscriber: &Subscriber,
+        subject: &str,
+        html_body: &str,
+    ) -> Result<(), Error> {
+        self.send_tagged(subscriber, None, subject, html_body).await
+    }
+
+    // Send with message tags, which SES copies onto configuration-set events so
+    // they can be tied back to the subscriber and campaign
+    pub async fn send_tagged(
+        &self,
+        subscriber: &Subscriber,
+        campaign_id: Option<&str>,
+        subject: &str,
+        html_body: &str,
+    ) -> Result<(), Error> {
+        self.send_to(
+            &subscriber.id,
+            &subscriber.email,
+            campaign_id,
+            subject,
+            html_body,
+            &[],
+        )
+        .await
+    }
+
+    // Send to an address without loading the subscriber, e.g. from a queued job
+    pub async fn send_to(
+        &self,
+        subscriber_id: &str,
+        email: &str,
+        campaign_id: Option<&str>,
+        subject: &str,
+        html_body: &str,
+        attachments: &[Attachment],
+    ) -> Result<(), Error> {
+        // Older subscribers may have a Unicode domain stored as typed
+        let email = sendable_email(email)?;
+        let email = email.as_str();
+        let recipient = match &self.sandbox {
+            Some(sandbox) => match sandbox.recipient(email) {
+                Some(recipient) => recipient,
+                None => {
+                    info!(
+                        "Dropping email to {}, not on RECIPIENT_ALLOWLIST and no CATCH_ALL_EMAIL",
+                        email
+                    );
+                    return Ok(());
+                }
+            },
+            None => email,
+        };
+        let original_recipient = (recipient != email).then_some(email);
+
+        let message = self.build_message(
+            subscriber_id,
+            recipient,
+            original_recipient,
+            subject,
+            html_body,
+            attachments,
+        )?;
+        if let Some(dry_run) = &self.dry_run {
+            return dry_run
+                .record(subscriber_id, recipient, campaign_id, subject, message)
+                .await;
+        }
+
+        let mut request = self
+            .client
+            .send_email()
+            .set_configuration_set_name(self.configuration_set.clone())
+            .email_tags(
+                MessageTag::builder()
+                    .name(SUBSCRIBER_TAG)
+                    .value(subscriber_id)
+                    .build(),
+            );
+        if let Some(campaign_id) = campaign_id {
+            request = request.email_tags(
+                MessageTag::builder()
+                    .name(CAMPAIGN_TAG)
+                    .value(campaign_id)
+                    .build(),
+            );
+        }
+
+        request
+            .content(
+                EmailContent::builder()
+                    .raw(RawMessage::builder().data(Blob::new(message)).build())
+                    .build(),
+            )
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Send an operational email, e.g. the daily pulse, to the newsletter's
+    // owner. It isn't newsletter mail, so it has no unsubscribe headers or
+    // tags and skips the dry run and sandbox.
+    pub async fn send_report(&self, to: &str, subject: &str, html_body: &str) -> Result<(), Error> {
+        let content = |data: String| Content::builder().data(data).charset("UTF-8").build();
+
+        self.client
+            .send_email()
+            .from_email_address(&self.from_address)
+            .destination(Destination::builder().to_addresses(to).build())
+            .content(
+                EmailContent::builder()
+                    .simple(
+                        Message::builder()
+                            .subject(content(subject.to_string()))
+                            .body(
+                                Body::builder()
+                                    .text(content(html_to_text(html_body)))
+                                    .html(content(html_body.to_string()))
+                                    .build(),
+                            )
+                            .build(),
+                    )
+                    .build(),
+            )
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn referral_tokens_are_not_unsubscribe_tokens() {
+        signing::with_secret("test-secret", || {
+            let token = signing::sign(&referral_key("subscriber-1")).unwrap();
+
+            assert!(signing::verify(&referral_key("subscriber-1"), &token));
+            assert!(!signing::verify("subscriber-1", &token));
+            assert!(!signing::verify(&referral_key("subscriber-2"), &token));
+        });
+    }
+
+    #[test]
+    fn referral_status_url_is_signed_for_referrals() {
+        signing::with_secret("test-secret", || {
+            let url = referral_status_url("subscriber-1").unwrap();
+            let token = signing::sign(&referral_key("subscriber-1")).unwrap();
+
+            assert!(url.contains("/referral?id=subscriber-1&token="));
+            assert!(
+                url.ends_with(
+                    &form_urlencoded::byte_serialize(token.as_bytes()).collect::<String>()
+                )
+            );
+        });
+    }
+}
@@ -0,0 +1,265 @@
+use crate::attachments::AttachmentStore;
+use crate::email::{EmailSender, format_address};
+use crate::queue::{QueuePayload, SCHEMA_VERSION, SchemaVersion, read_current};
+use crate::senders::SenderIdentity;
+use crate::{Error, Subscriber};
+use aws_config::SdkConfig;
+use aws_sdk_sqs::Client as SqsClient;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+// First retry delay, doubled on every attempt
+const BASE_BACKOFF_SECONDS: i32 = 30;
+// SQS doesn't delay messages for longer than 15 minutes
+const MAX_BACKOFF_SECONDS: i32 = 900;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailKind {
+    Confirmation,
+    Reminder,
+    Reengagement,
+    Campaign,
+    Digest,
+    Welcome,
+}
+
+impl EmailKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailKind::Confirmation => "confirmation",
+            EmailKind::Reminder => "reminder",
+            EmailKind::Reengagement => "reengagement",
+            EmailKind::Campaign => "campaign",
+            EmailKind::Digest => "digest",
+            EmailKind::Welcome => "welcome",
+        }
+    }
+}
+
+// A rendered email waiting on the send queue. Everything needed to send it is
+// in the job, so the worker doesn't read the subscriber again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailJob {
+    // Missing from jobs written before payloads were versioned
+    #[serde(default)]
+    pub schema_version: u32,
+    pub kind: EmailKind,
+    pub subscriber_id: String,
+    pub email: String,
+    pub campaign_id: Option<String>,
+    pub subject: String,
+    pub html_body: String,
+    // From and Reply-To of a sender identity, SENDER_EMAIL when not set
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub reply_to: Option<String>,
+    // Object keys in ATTACHMENTS_BUCKET, fetched by the worker since SQS
+    // messages are limited to 256 KB
+    #[serde(default)]
+    pub attachments: Vec<String>,
+    // Failed sends so far
+    #[serde(default)]
+    pub attempt: u32,
+    pub enqueued_at: DateTime<Utc>,
+}
+
+impl EmailJob {
+    pub fn new(
+        kind: EmailKind,
+        subscriber: &Subscriber,
+        campaign_id: Option<&str>,
+        subject: &str,
+        html_body: &str,
+    ) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            kind,
+            subscriber_id: subscriber.id.clone(),
+            email: subscriber.email.clone(),
+            campaign_id: campaign_id.map(str::to_string),
+            subject: subject.to_string(),
+            html_body: html_body.to_string(),
+            from: None,
+            reply_to: None,
+            attachments: Vec::new(),
+            attempt: 0,
+            enqueued_at: Utc::now(),
+        }
+    }
+}
+
+impl QueuePayload for EmailJob {
+    fn read(_version: SchemaVersion, value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        read_current(value)
+    }
+}
+
+// A job that ran out of attempts or failed permanently, as written to the
+// dead-letter queue
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetteredEmail {
+    #[serde(default)]
+    pub schema_version: u32,
+    pub job: EmailJob,
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+// Exponential backoff with jitter before retry number `attempt`, so a burst of
+// throttled sends doesn't come back all at once
+pub fn backoff_seconds(attempt: u32) -> i32 {
+    let delay = BASE_BACKOFF_SECONDS
+        .saturating_mul(1 << attempt.min(10))
+        .min(MAX_BACKOFF_SECONDS);
+    let jitter = rand::thread_rng().gen_range(0..=delay / 4);
+    (delay - jitter).max(1)
+}
+
+#[derive(Clone)]
+pub struct EmailQueue {
+    client: SqsClient,
+    queue_url: String,
+    // Sender identity stamped on enqueued jobs
+    from: Option<String>,
+    reply_to: Option<String>,
+}
+
+impl EmailQueue {
+    pub fn new(client: SqsClient, queue_url: String) -> Self {
+        Self {
+            client,
+            queue_url,
+            from: None,
+            reply_to: None,
+        }
+    }
+
+    pub async fn enqueue(&self, job: &EmailJob) -> Result<(), Error> {
+        self.client
+            .send_message()
+            .queue_url(&self.queue_url)
+            .message_body(serde_json::to_string(job)?)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Put a failed job back with one more attempt counted, delayed by the backoff
+    pub async fn retry(&self, mut job: EmailJob) -> Result<(), Error> {
+        let delay = backoff_seconds(job.attempt);
+        job.attempt += 1;
+
+        self.client
+            .send_message()
+            .queue_url(&self.queue_url)
+            .message_body(serde_json::to_string(&job)?)
+            .delay_seconds(delay)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+// Sends emails through the send queue when EMAIL_QUEUE_URL is set, or straight
+// through SES otherwise, as in local development and the CLI
+#[derive(Clone)]
+pub enum Mailer {
+    Direct(EmailSender),
+    Queued(EmailQueue),
+}
+
+impl Mailer {
+    pub fn new(config: &SdkConfig, sender_email: String) -> Self {
+        match env::var("EMAIL_QUEUE_URL")
+            .ok()
+            .filter(|url| !url.is_empty())
+        {
+            Some(queue_url) => Mailer::Queued(EmailQueue::new(SqsClient::new(config), queue_url)),
+            None => Mailer::Direct(EmailSender::new(config, sender_email)),
+        }
+    }
+
+    // The same mailer sending from a sender identity instead of SENDER_EMAIL
+    pub fn with_sender(&self, identity: &SenderIdentity) -> Self {
+        let from = identity.from_header();
+        let reply_to = identity
+            .reply_to
+            .as_deref()
+            .map(|address| format_address(None, address));
+        match self {
+            Mailer::Direct(sender) => Mailer::Direct(sender.with_from(from, reply_to)),
+            Mailer::Queued(queue) => Mailer::Queued(EmailQueue {
+                from: Some(from),
+                reply_to,
+                ..queue.clone()
+            }),
+        }
+    }
+
+    // Send, or enqueue, an email. When queued, Ok means the job was accepted and
+    // delivery failures are handled by the worker.
+    pub async fn send(
+        &self,
+        kind: EmailKind,
+        subscriber: &Subscriber,
+        campaign_id: Option<&str>,
+        subject: &str,
+        html_body: &str,
+    ) -> Result<(), Error> {
+        match self {
+            Mailer::Direct(sender) => {
+                sender
+                    .send_tagged(subscriber, campaign_id, subject, html_body)
+                    .await
+            }
+            Mailer::Queued(queue) => {
+                let mut job = EmailJob::new(kind, subscriber, campaign_id, subject, html_body);
+                job.from = queue.from.clone();
+                job.reply_to = queue.reply_to.clone();
+                queue.enqueue(&job).await
+            }
+        }
+    }
+
+    // Send an email with files from the attachment store attached. Queued jobs
+    // carry only the keys, and the worker fetches the files when sending.
+    pub async fn send_with_attachments(
+        &self,
+        kind: EmailKind,
+        subscriber: &Subscriber,
+        subject: &str,
+        html_body: &str,
+        attachments: &AttachmentStore,
+        keys: &[String],
+    ) -> Result<(), Error> {
+        match self {
+            Mailer::Direct(sender) => {
+                let files = attachments.fetch(keys).await?;
+                sender
+                    .send_to(
+                        &subscriber.id,
+                        &subscriber.email,
+                        None,
+                        subject,
+                        html_body,
+                        &files,
+                    )
+                    .await
+            }
+            Mailer::Queued(queue) => {
+                let mut job = EmailJob::new(kind, subscriber, None, subject, html_body);
+                job.from = queue.from.clone();
+                job.reply_to = queue.reply_to.clone();
+                job.attachments = keys.to_vec();
+                queue.enqueue(&job).await
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// Self-contained signup form, embedded with
+// `<script src="<API_URL>/embed.js" data-accent="#0f766e" async></script>`.
+//
+// The script renders the form where its tag is, posts to /subscribe with the
+// honeypot and render-time fields `spam::check_submission` looks at, and shows
+// the API's (translated) message inline. Styling and copy come from data-*
+// attributes on the script tag:
+//
+// - data-accent: button color, default #2563eb
+// - data-radius: corner radius, default 6px
+// - data-button-text / data-placeholder: copy, default "Subscribe" / "you@example.com"
+// - data-source: attribution source, default "embed"
+// - data-locale: language for the response messages, e.g. "es"
+const EMBED_SCRIPT: &str = r##"(function () {
+  var script = document.currentScript;
+  if (!script) return;
+  var options = script.dataset;
+  var endpoint = __API_URL__ + "/subscribe";
+  var accent = options.accent || "#2563eb";
+  var radius = options.radius || "6px";
+
+  var style = document.createElement("style");
+  style.textContent =
+    ".nl-embed{display:flex;flex-wrap:wrap;gap:8px;font:inherit;max-width:480px}" +
+    ".nl-embed input[type=email]{flex:1 1 200px;padding:10px 12px;border:1px solid #d1d5db;border-radius:" + radius + ";font:inherit}" +
+    ".nl-embed button{padding:10px 16px;border:0;border-radius:" + radius + ";background:" + accent + ";color:#fff;font:inherit;cursor:pointer}" +
+    ".nl-embed button[disabled]{opacity:.6;cursor:default}" +
+    ".nl-embed .nl-embed-hp{position:absolute;left:-10000px;width:1px;height:1px;overflow:hidden}" +
+    ".nl-embed .nl-embed-message{flex-basis:100%;margin:0;font-size:.9em}" +
+    ".nl-embed .nl-embed-error{color:#b91c1c}";
+
+  var form = document.createElement("form");
+  form.className = "nl-embed";
+  form.noValidate = true;
+
+  var email = document.createElement("input");
+  email.type = "email";
+  email.name = "email";
+  email.required = true;
+  email.placeholder = options.placeholder || "you@example.com";
+  email.setAttribute("aria-label", email.placeholder);
+
+  // Humans never see or fill this one in
+  var honeypot = document.createElement("input");
+  honeypot.type = "text";
+  honeypot.name = "hp";
+  honeypot.tabIndex = -1;
+  honeypot.autocomplete = "off";
+  honeypot.className = "nl-embed-hp";
+  honeypot.setAttribute("aria-hidden", "true");
+
+  var button = document.createElement("button");
+  button.type = "submit";
+  button.textContent = options.buttonText || "Subscribe";
+
+  var message = document.createElement("p");
+  message.className = "nl-embed-message";
+  message.setAttribute("role", "status");
+
+  form.appendChild(email);
+  form.appendChild(honeypot);
+  form.appendChild(button);
+  form.appendChild(message);
+
+  var renderedAt = Date.now();
+  var params = new URLSearchParams(window.location.search);
+
+  function show(text, isError) {
+    message.textContent = text;
+    message.className = "nl-embed-message" + (isError ? " nl-embed-error" : "");
+  }
+
+  form.addEventListener("submit", function (event) {
+    event.preventDefault();
+    button.disabled = true;
+    show("", false);
+
+    var body = {
+      email: email.value.trim(),
+      hp: honeypot.value,
+      rendered_at: renderedAt,
+      source: options.source || "embed",
+      referrer: document.referrer || undefined,
+      utm_source: params.get("utm_source") || undefined,
+      utm_medium: params.get("utm_medium") || undefined,
+      utm_campaign: params.get("utm_campaign") || undefined,
+      ref: params.get("ref") || undefined,
+      locale: options.locale || undefined
+    };
+
+    fetch(endpoint, {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: JSON.stringify(body)
+    })
+      .then(function (response) {
+        return response.json().then(function (result) {
+          if (!response.ok || !result.success) throw new Error(result.message);
+          show(result.message, false);
+          email.value = "";
+        });
+      })
+      .catch(function (error) {
+        show(error.message || "Something went wrong, please try again.", true);
+      })
+      .then(function () {
+        button.disabled = false;
+      });
+  });
+
+  document.head.appendChild(style);
+  script.parentNode.insertBefore(form, script.nextSibling);
+})();
+"##;
+
+// The embed script, posting to the given API base URL
+pub fn embed_script(api_url: &str) -> String {
+    // A JSON string is a valid JavaScript string literal
+    let api_url = serde_json::Value::from(api_url).to_string();
+    EMBED_SCRIPT.replace("__API_URL__", &api_url)
+}
@@ -0,0 +1,364 @@
+use crate::archive::{ArchivedEvent, archive_or_log};
+use crate::batch::delete_partition;
+use crate::config::table_name;
+use crate::signing;
+use crate::{EVENTS_TABLE_NAME, Error, api_url};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use url::form_urlencoded;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    Open,
+    Click,
+    // Reported by SES through the configuration set
+    Delivery,
+    Reject,
+    Bounce,
+    // Marked as spam by the recipient
+    Complaint,
+}
+
+impl EventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::Open => "open",
+            EventType::Click => "click",
+            EventType::Delivery => "delivery",
+            EventType::Reject => "reject",
+            EventType::Bounce => "bounce",
+            EventType::Complaint => "complaint",
+        }
+    }
+
+    // Opens and clicks are the subscriber's doing, the rest is delivery status
+    pub fn is_engagement(&self) -> bool {
+        matches!(self, EventType::Open | EventType::Click)
+    }
+
+    // Contribution of a single event to the engagement score
+    pub fn engagement_weight(&self) -> f64 {
+        match self {
+            EventType::Open => 1.0,
+            EventType::Click => 3.0,
+            EventType::Delivery | EventType::Reject | EventType::Bounce | EventType::Complaint => {
+                0.0
+            }
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "open" => Some(EventType::Open),
+            "click" => Some(EventType::Click),
+            "delivery" => Some(EventType::Delivery),
+            "reject" => Some(EventType::Reject),
+            "bounce" => Some(EventType::Bounce),
+            "complaint" => Some(EventType::Complaint),
+            _ => None,
+        }
+    }
+}
+
+// Engagement event, stored under the subscriber it belongs to
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Event {
+    pub subscriber_id: String,
+    pub event_type: EventType,
+    pub campaign_id: Option<String>,
+    pub url: Option<String>,
+    // Provider message id, set for events ingested from SES or an ESP webhook
+    pub message_id: Option<String>,
+    // Reject reason, bounce type or complaint
+    pub detail: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl Event {
+    pub fn new(subscriber_id: String, event_type: EventType) -> Self {
+        Self {
+            subscriber_id,
+            event_type,
+            campaign_id: None,
+            url: None,
+            message_id: None,
+            detail: None,
+            occurred_at: Utc::now(),
+        }
+    }
+
+    pub fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+
+        // Fixed-width timestamps keep the sort key in chronological order
+        let event_key = format!(
+            "{}#{}",
+            self.occurred_at
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            Uuid::new_v4()
+        );
+
+        item.insert(
+            "subscriber_id".to_string(),
+            AttributeValue::S(self.subscriber_id.clone()),
+        );
+        item.insert("event_key".to_string(), AttributeValue::S(event_key));
+        item.insert(
+            "event_type".to_string(),
+            AttributeValue::S(self.event_type.as_str().to_string()),
+        );
+        if let Some(campaign_id) = &self.campaign_id {
+            item.insert(
+                "campaign_id".to_string(),
+                AttributeValue::S(campaign_id.clone()),
+            );
+        }
+        if let Some(url) = &self.url {
+            item.insert("url".to_string(), AttributeValue::S(url.clone()));
+        }
+        if let Some(message_id) = &self.message_id {
+            item.insert(
+                "message_id".to_string(),
+                AttributeValue::S(message_id.clone()),
+            );
+        }
+        if let Some(detail) = &self.detail {
+            item.insert("detail".to_string(), AttributeValue::S(detail.clone()));
+        }
+        item.insert(
+            "occurred_at".to_string(),
+            AttributeValue::S(self.occurred_at.to_rfc3339()),
+        );
+
+        item
+    }
+
+    pub fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let subscriber_id = item.get("subscriber_id")?.as_s().ok()?;
+        let event_type = EventType::parse(item.get("event_type")?.as_s().ok()?)?;
+        let campaign_id = item
+            .get("campaign_id")
+            .and_then(|value| value.as_s().ok())
+            .cloned();
+        let url = item.get("url").and_then(|value| value.as_s().ok()).cloned();
+        let message_id = item
+            .get("message_id")
+            .and_then(|value| value.as_s().ok())
+            .cloned();
+        let detail = item
+            .get("detail")
+            .and_then(|value| value.as_s().ok())
+            .cloned();
+        let occurred_at = DateTime::parse_from_rfc3339(item.get("occurred_at")?.as_s().ok()?)
+            .ok()?
+            .with_timezone(&Utc);
+
+        Some(Self {
+            subscriber_id: subscriber_id.clone(),
+            event_type,
+            campaign_id,
+            url,
+            message_id,
+            detail,
+            occurred_at,
+        })
+    }
+}
+
+// Data access for the events table
+pub struct EventRepository {
+    client: Client,
+    table_name: String,
+}
+
+impl EventRepository {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(EVENTS_TABLE_NAME),
+        }
+    }
+
+    pub async fn record(&self, event: &Event) -> Result<(), Error> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(event.to_dynamodb_item()))
+            .send()
+            .await?;
+
+        archive_or_log(&[ArchivedEvent::from(event)]).await;
+        Ok(())
+    }
+
+    // Remove every event of a subscriber, once their record is purged
+    pub async fn delete_for_subscriber(&self, subscriber_id: &str) -> Result<usize, Error> {
+        delete_partition(
+            &self.client,
+            &self.table_name,
+            ("subscriber_id", subscriber_id),
+            "event_key",
+        )
+        .await
+    }
+
+    // A subscriber's latest events of any kind, newest first
+    pub async fn recent_for_subscriber(
+        &self,
+        subscriber_id: &str,
+        limit: i32,
+    ) -> Result<Vec<Event>, Error> {
+        let result = self
+            .client
+            .query()
+            .table_name(&self.table_name)
+            .key_condition_expression("subscriber_id = :subscriber_id")
+            .expression_attribute_values(
+                ":subscriber_id",
+                AttributeValue::S(subscriber_id.to_string()),
+            )
+            .scan_index_forward(false)
+            .limit(limit)
+            .send()
+            .await?;
+
+        Ok(result
+            .items()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(Event::from_dynamodb_item)
+            .collect())
+    }
+
+    // Most recent open or click for a subscriber, if any. Delivery status events
+    // are skipped, so a delivered email doesn't count as engagement.
+    pub async fn latest_engagement_for_subscriber(
+        &self,
+        subscriber_id: &str,
+    ) -> Result<Option<Event>, Error> {
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("subscriber_id = :subscriber_id")
+                .filter_expression("event_type IN (:open, :click)")
+                .expression_attribute_values(
+                    ":subscriber_id",
+                    AttributeValue::S(subscriber_id.to_string()),
+                )
+                .expression_attribute_values(
+                    ":open",
+                    AttributeValue::S(EventType::Open.as_str().to_string()),
+                )
+                .expression_attribute_values(
+                    ":click",
+                    AttributeValue::S(EventType::Click.as_str().to_string()),
+                )
+                .scan_index_forward(false)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            if let Some(event) = result
+                .items()
+                .and_then(|items| items.first())
+                .and_then(Event::from_dynamodb_item)
+            {
+                return Ok(Some(event));
+            }
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+// The open pixel and click redirects can be turned off with PIXEL_TRACKING=false
+// when SES configuration-set tracking is used instead
+pub fn pixel_tracking_enabled() -> bool {
+    !env::var("PIXEL_TRACKING").is_ok_and(|value| value.eq_ignore_ascii_case("false"))
+}
+
+// What a tracking link signs, so recorded opens and clicks can't be forged
+// for other subscribers, campaigns or destinations
+pub fn tracking_key(
+    event_type: EventType,
+    subscriber_id: &str,
+    campaign_id: Option<&str>,
+    destination: Option<&str>,
+) -> String {
+    format!(
+        "track:{}:{}:{}:{}",
+        event_type.as_str(),
+        subscriber_id,
+        campaign_id.unwrap_or_default(),
+        destination.unwrap_or_default()
+    )
+}
+
+// Signed URL of the 1x1 pixel recording an open
+pub fn open_pixel_url(subscriber_id: &str, campaign_id: &str) -> Result<String, Error> {
+    let key = tracking_key(EventType::Open, subscriber_id, Some(campaign_id), None);
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("e", EventType::Open.as_str())
+        .append_pair("s", subscriber_id)
+        .append_pair("c", campaign_id)
+        .append_pair("token", &signing::sign(&key)?)
+        .finish();
+
+    Ok(format!("{}/track?{}", api_url(), query))
+}
+
+// Signed URL recording a click before redirecting to the destination
+pub fn click_url(
+    subscriber_id: &str,
+    campaign_id: Option<&str>,
+    destination: &str,
+) -> Result<String, Error> {
+    let key = tracking_key(
+        EventType::Click,
+        subscriber_id,
+        campaign_id,
+        Some(destination),
+    );
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    query
+        .append_pair("e", EventType::Click.as_str())
+        .append_pair("s", subscriber_id)
+        .append_pair("url", destination);
+    if let Some(campaign_id) = campaign_id {
+        query.append_pair("c", campaign_id);
+    }
+    query.append_pair("token", &signing::sign(&key)?);
+
+    Ok(format!("{}/track?{}", api_url(), query.finish()))
+}
+
+// Append the open tracking pixel to an HTML body
+pub fn with_open_tracking(
+    html_body: &str,
+    subscriber_id: &str,
+    campaign_id: &str,
+) -> Result<String, Error> {
+    let pixel = format!(
+        "<img src=\"{}\" width=\"1\" height=\"1\" alt=\"\" />",
+        open_pixel_url(subscriber_id, campaign_id)?
+    );
+
+    Ok(match html_body.rfind("</body>") {
+        Some(index) => format!("{}{}{}", &html_body[..index], pixel, &html_body[index..]),
+        None => format!("{}{}", html_body, pixel),
+    })
+}
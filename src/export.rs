@@ -0,0 +1,94 @@
+use crate::segment::Segment;
+use crate::{Error, Subscriber};
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::primitives::ByteStream;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Columns of a subscriber export, from `newsletterctl export` or an export job
+pub const EXPORT_COLUMNS: [&str; 8] = [
+    "id",
+    "email",
+    "active",
+    "validated",
+    "suppressed",
+    "created_at",
+    "updated_at",
+    "fields",
+];
+
+// A subscriber as a row of EXPORT_COLUMNS, custom fields as a JSON object
+pub fn export_record(subscriber: &Subscriber) -> Result<[String; 8], Error> {
+    Ok([
+        subscriber.id.clone(),
+        subscriber.email.clone(),
+        subscriber.active.to_string(),
+        subscriber.validated.to_string(),
+        subscriber.suppressed.to_string(),
+        subscriber.created_at.to_rfc3339(),
+        subscriber.updated_at.to_rfc3339(),
+        serde_json::to_string(&subscriber.fields)?,
+    ])
+}
+
+// Parameters of an export job
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ExportParams {
+    // Segment expression limiting the export, everyone when left out
+    #[serde(default)]
+    pub segment: Option<String>,
+}
+
+impl ExportParams {
+    pub fn normalized(self) -> Result<Self, Error> {
+        let segment = self.segment.filter(|segment| !segment.trim().is_empty());
+        if let Some(segment) = &segment {
+            Segment::parse(segment)?;
+        }
+        Ok(Self { segment })
+    }
+}
+
+// Export files in an S3 bucket, one CSV file with a header per page under
+// `exports/<job id>/`
+pub struct ExportStore {
+    client: S3Client,
+    bucket: String,
+}
+
+impl ExportStore {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+
+    // Where the files of a job go, as the job's result location
+    pub fn location(&self, job_id: &str) -> String {
+        format!("s3://{}/exports/{}/", self.bucket, job_id)
+    }
+
+    // Write one page of an export. A repeated page overwrites its own file.
+    pub async fn write_page(
+        &self,
+        job_id: &str,
+        page: u32,
+        subscribers: &[&Subscriber],
+    ) -> Result<(), Error> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(EXPORT_COLUMNS)?;
+        for subscriber in subscribers {
+            writer.write_record(export_record(subscriber)?)?;
+        }
+        let body = writer.into_inner().map_err(|e| e.to_string())?;
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(format!("exports/{job_id}/part-{page:05}.csv"))
+            .content_type("text/csv")
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
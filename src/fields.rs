@@ -0,0 +1,128 @@
+use crate::Error;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    String,
+    Number,
+    Boolean,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FieldDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    #[serde(default)]
+    pub required: bool,
+    pub max_length: Option<usize>,
+}
+
+// Custom subscriber fields accepted on subscribe
+#[derive(Debug, Clone)]
+pub struct FieldSchema {
+    definitions: Vec<FieldDefinition>,
+}
+
+impl Default for FieldSchema {
+    fn default() -> Self {
+        let optional_string = |name: &str| FieldDefinition {
+            name: name.to_string(),
+            field_type: FieldType::String,
+            required: false,
+            max_length: Some(100),
+        };
+
+        Self {
+            definitions: vec![
+                optional_string("first_name"),
+                optional_string("last_name"),
+                optional_string("company"),
+            ],
+        }
+    }
+}
+
+impl FieldSchema {
+    // Read the schema from CUSTOM_FIELDS_SCHEMA, a JSON array of field definitions like
+    // `[{"name": "first_name", "type": "string", "required": true, "max_length": 50}]`
+    pub fn from_env() -> Result<Self, Error> {
+        match env::var("CUSTOM_FIELDS_SCHEMA") {
+            Ok(schema) if !schema.trim().is_empty() => Ok(Self {
+                definitions: serde_json::from_str(&schema)?,
+            }),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    // The container's schema, parsed on first use rather than on every signup
+    pub fn current() -> Result<&'static Self, Error> {
+        static SCHEMA: OnceLock<Result<FieldSchema, String>> = OnceLock::new();
+        match SCHEMA.get_or_init(|| Self::from_env().map_err(|e| e.to_string())) {
+            Ok(schema) => Ok(schema),
+            Err(e) => Err(e.clone().into()),
+        }
+    }
+
+    // Check submitted fields against the schema, returning them normalized to strings
+    pub fn validate(
+        &self,
+        fields: &HashMap<String, Value>,
+    ) -> Result<HashMap<String, String>, String> {
+        if let Some(unknown) = fields
+            .keys()
+            .find(|name| !self.definitions.iter().any(|d| &d.name == *name))
+        {
+            return Err(format!("Unknown field `{}`", unknown));
+        }
+
+        let mut validated = HashMap::new();
+
+        for definition in &self.definitions {
+            let value = match fields.get(&definition.name) {
+                Some(Value::Null) | None => {
+                    if definition.required {
+                        return Err(format!("Field `{}` is required", definition.name));
+                    }
+                    continue;
+                }
+                Some(value) => value,
+            };
+
+            let normalized = match (definition.field_type, value) {
+                (FieldType::String, Value::String(text)) => text.trim().to_string(),
+                (FieldType::Number, Value::Number(number)) => number.to_string(),
+                (FieldType::Boolean, Value::Bool(flag)) => flag.to_string(),
+                (field_type, _) => {
+                    return Err(format!(
+                        "Field `{}` must be a {}",
+                        definition.name,
+                        match field_type {
+                            FieldType::String => "string",
+                            FieldType::Number => "number",
+                            FieldType::Boolean => "boolean",
+                        }
+                    ));
+                }
+            };
+
+            if let Some(max_length) = definition.max_length
+                && normalized.chars().count() > max_length
+            {
+                return Err(format!(
+                    "Field `{}` must be at most {} characters",
+                    definition.name, max_length
+                ));
+            }
+
+            validated.insert(definition.name.clone(), normalized);
+        }
+
+        Ok(validated)
+    }
+}
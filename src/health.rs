@@ -0,0 +1,174 @@
+use crate::config::table_name;
+use crate::{
+    AUDIT_LOG_TABLE_NAME, CAMPAIGN_RECIPIENTS_TABLE_NAME, CAMPAIGN_SCHEDULE_TABLE_NAME,
+    CAMPAIGNS_TABLE_NAME, COUNTERS_TABLE_NAME, DIGEST_ITEMS_TABLE_NAME, DOMAIN_RULES_TABLE_NAME,
+    DOMAIN_STATS_TABLE_NAME, EMAIL_DOMAIN_INDEX, EMAIL_HASH_INDEX, EMAIL_PREFIX_INDEX,
+    ENQUEUE_PENDING_INDEX, EVENTS_TABLE_NAME, HISTORY_TABLE_NAME, IDEMPOTENCY_TABLE_NAME,
+    INVITE_CODES_TABLE_NAME, JOBS_TABLE_NAME, PROCESSED_MESSAGES_TABLE_NAME, QUOTAS_TABLE_NAME,
+    REFERRAL_CODE_INDEX, SENDER_IDENTITIES_TABLE_NAME, STRIPE_CUSTOMER_INDEX, TABLE_NAME,
+    TEMPLATE_VERSIONS_TABLE_NAME, TEMPLATES_TABLE_NAME, UPDATED_AT_INDEX,
+};
+use aws_sdk_dynamodb::Client;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+
+// Every table the backend uses, with the global secondary indexes it queries.
+// Names are without the stage suffix, see config::table_name.
+pub const EXPECTED_TABLES: &[(&str, &[&str])] = &[
+    (
+        TABLE_NAME,
+        &[
+            EMAIL_HASH_INDEX,
+            REFERRAL_CODE_INDEX,
+            STRIPE_CUSTOMER_INDEX,
+            EMAIL_DOMAIN_INDEX,
+            EMAIL_PREFIX_INDEX,
+            UPDATED_AT_INDEX,
+            ENQUEUE_PENDING_INDEX,
+        ],
+    ),
+    (CAMPAIGNS_TABLE_NAME, &[]),
+    (EVENTS_TABLE_NAME, &[]),
+    (HISTORY_TABLE_NAME, &[]),
+    (IDEMPOTENCY_TABLE_NAME, &[]),
+    (QUOTAS_TABLE_NAME, &[]),
+    (DOMAIN_STATS_TABLE_NAME, &[]),
+    (DOMAIN_RULES_TABLE_NAME, &[]),
+    (INVITE_CODES_TABLE_NAME, &[]),
+    (DIGEST_ITEMS_TABLE_NAME, &[]),
+    (CAMPAIGN_SCHEDULE_TABLE_NAME, &[]),
+    (CAMPAIGN_RECIPIENTS_TABLE_NAME, &[]),
+    (SENDER_IDENTITIES_TABLE_NAME, &[]),
+    (TEMPLATES_TABLE_NAME, &[]),
+    (TEMPLATE_VERSIONS_TABLE_NAME, &[]),
+    (AUDIT_LOG_TABLE_NAME, &[]),
+    (COUNTERS_TABLE_NAME, &[]),
+    (PROCESSED_MESSAGES_TABLE_NAME, &[]),
+    (JOBS_TABLE_NAME, &[]),
+];
+
+// What was deployed, for deploy pipelines checking a rollout went out
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BuildInfo {
+    // Crate version from Cargo.toml
+    pub version: String,
+    // Commit the binary was built from, "unknown" outside a git checkout
+    pub git_sha: String,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: env!("GIT_SHA").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct IndexHealth {
+    pub name: String,
+    // DynamoDB IndexStatus, e.g. ACTIVE or CREATING; MISSING when not found
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TableHealth {
+    pub name: String,
+    // DynamoDB TableStatus, e.g. ACTIVE or UPDATING; UNREACHABLE when it couldn't be described
+    pub status: String,
+    pub indexes: Vec<IndexHealth>,
+    pub healthy: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct HealthResponse {
+    // "ok" when every table and index is active, "degraded" otherwise
+    pub status: String,
+    pub build: BuildInfo,
+    pub tables: Vec<TableHealth>,
+}
+
+impl HealthResponse {
+    pub fn is_healthy(&self) -> bool {
+        self.tables.iter().all(|table| table.healthy)
+    }
+}
+
+// Describe a table and check it and the given indexes are ACTIVE
+pub async fn check_table(client: &Client, name: &str, expected_indexes: &[&str]) -> TableHealth {
+    let description = match client.describe_table().table_name(name).send().await {
+        Ok(output) => output.table,
+        Err(e) => {
+            info!("Error describing table {}: {:?}", name, e);
+            None
+        }
+    };
+
+    let Some(description) = description else {
+        return TableHealth {
+            name: name.to_string(),
+            status: "UNREACHABLE".to_string(),
+            indexes: Vec::new(),
+            healthy: false,
+        };
+    };
+
+    let status = description
+        .table_status()
+        .map(|status| status.as_str().to_string())
+        .unwrap_or_default();
+
+    let indexes: Vec<IndexHealth> = expected_indexes
+        .iter()
+        .map(|index_name| {
+            let status = description
+                .global_secondary_indexes()
+                .unwrap_or_default()
+                .iter()
+                .find(|index| index.index_name() == Some(*index_name))
+                .map(|index| {
+                    index
+                        .index_status()
+                        .map(|status| status.as_str().to_string())
+                        .unwrap_or_default()
+                })
+                .unwrap_or_else(|| "MISSING".to_string());
+            IndexHealth {
+                name: index_name.to_string(),
+                status,
+            }
+        })
+        .collect();
+
+    let healthy = status == "ACTIVE" && indexes.iter().all(|index| index.status == "ACTIVE");
+
+    TableHealth {
+        name: name.to_string(),
+        status,
+        indexes,
+        healthy,
+    }
+}
+
+// Check every table the backend depends on
+pub async fn check_health(client: &Client) -> HealthResponse {
+    let mut tables = Vec::new();
+    for (name, indexes) in EXPECTED_TABLES {
+        tables.push(check_table(client, &table_name(name), indexes).await);
+    }
+
+    let mut response = HealthResponse {
+        status: String::new(),
+        build: BuildInfo::current(),
+        tables,
+    };
+    response.status = if response.is_healthy() {
+        "ok"
+    } else {
+        "degraded"
+    }
+    .to_string();
+    response
+}
@@ -0,0 +1,313 @@
+use crate::archive::{ArchivedEvent, archive_or_log};
+use crate::batch::{batch_put, delete_partition};
+use crate::config::table_name;
+use crate::{Error, HISTORY_TABLE_NAME};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::info;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleEventType {
+    Subscribed,
+    Confirmed,
+    Unsubscribed,
+    Suppressed,
+    Bounced,
+    EmailChanged,
+    TagAdded,
+    TagRemoved,
+    Deleted,
+    Restored,
+    // Duplicate records of the same address folded into this one
+    Merged,
+    // Held back as a suspicious signup
+    Quarantined,
+    // Released from quarantine by an admin
+    Approved,
+}
+
+impl LifecycleEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LifecycleEventType::Subscribed => "subscribed",
+            LifecycleEventType::Confirmed => "confirmed",
+            LifecycleEventType::Unsubscribed => "unsubscribed",
+            LifecycleEventType::Suppressed => "suppressed",
+            LifecycleEventType::Bounced => "bounced",
+            LifecycleEventType::EmailChanged => "email_changed",
+            LifecycleEventType::TagAdded => "tag_added",
+            LifecycleEventType::TagRemoved => "tag_removed",
+            LifecycleEventType::Deleted => "deleted",
+            LifecycleEventType::Restored => "restored",
+            LifecycleEventType::Merged => "merged",
+            LifecycleEventType::Quarantined => "quarantined",
+            LifecycleEventType::Approved => "approved",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "subscribed" => Some(LifecycleEventType::Subscribed),
+            "confirmed" => Some(LifecycleEventType::Confirmed),
+            "unsubscribed" => Some(LifecycleEventType::Unsubscribed),
+            "suppressed" => Some(LifecycleEventType::Suppressed),
+            "bounced" => Some(LifecycleEventType::Bounced),
+            "email_changed" => Some(LifecycleEventType::EmailChanged),
+            "tag_added" => Some(LifecycleEventType::TagAdded),
+            "tag_removed" => Some(LifecycleEventType::TagRemoved),
+            "deleted" => Some(LifecycleEventType::Deleted),
+            "restored" => Some(LifecycleEventType::Restored),
+            "merged" => Some(LifecycleEventType::Merged),
+            "quarantined" => Some(LifecycleEventType::Quarantined),
+            "approved" => Some(LifecycleEventType::Approved),
+            _ => None,
+        }
+    }
+}
+
+// Immutable record of something that happened to a subscriber. Items are only
+// ever appended, so the timeline explains how the subscriber got to its state.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LifecycleEvent {
+    pub subscriber_id: String,
+    pub event_type: LifecycleEventType,
+    // What triggered the event, e.g. "one_click", "sunset" or "newsletterctl"
+    pub source: Option<String>,
+    // Free-form context such as a bounce reason, the new email or the tag
+    pub detail: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl LifecycleEvent {
+    pub fn new(subscriber_id: &str, event_type: LifecycleEventType) -> Self {
+        Self {
+            subscriber_id: subscriber_id.to_string(),
+            event_type,
+            source: None,
+            detail: None,
+            occurred_at: Utc::now(),
+        }
+    }
+
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(source.to_string());
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+
+        // Fixed-width timestamps keep the sort key in chronological order
+        let event_key = format!(
+            "{}#{}",
+            self.occurred_at
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            Uuid::new_v4()
+        );
+
+        item.insert(
+            "subscriber_id".to_string(),
+            AttributeValue::S(self.subscriber_id.clone()),
+        );
+        item.insert("event_key".to_string(), AttributeValue::S(event_key));
+        item.insert(
+            "event_type".to_string(),
+            AttributeValue::S(self.event_type.as_str().to_string()),
+        );
+        if let Some(source) = &self.source {
+            item.insert("source".to_string(), AttributeValue::S(source.clone()));
+        }
+        if let Some(detail) = &self.detail {
+            item.insert("detail".to_string(), AttributeValue::S(detail.clone()));
+        }
+        item.insert(
+            "occurred_at".to_string(),
+            AttributeValue::S(self.occurred_at.to_rfc3339()),
+        );
+
+        item
+    }
+
+    pub fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let subscriber_id = item.get("subscriber_id")?.as_s().ok()?;
+        let event_type = LifecycleEventType::parse(item.get("event_type")?.as_s().ok()?)?;
+        let source = item
+            .get("source")
+            .and_then(|value| value.as_s().ok())
+            .cloned();
+        let detail = item
+            .get("detail")
+            .and_then(|value| value.as_s().ok())
+            .cloned();
+        let occurred_at = DateTime::parse_from_rfc3339(item.get("occurred_at")?.as_s().ok()?)
+            .ok()?
+            .with_timezone(&Utc);
+
+        Some(Self {
+            subscriber_id: subscriber_id.clone(),
+            event_type,
+            source,
+            detail,
+            occurred_at,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SubscriberHistoryResponse {
+    pub success: bool,
+    pub subscriber_id: String,
+    pub events: Vec<LifecycleEvent>,
+}
+
+// Data access for the append-only subscriber history table
+pub struct HistoryRepository {
+    client: Client,
+    table_name: String,
+}
+
+impl HistoryRepository {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(HISTORY_TABLE_NAME),
+        }
+    }
+
+    pub async fn record(&self, event: &LifecycleEvent) -> Result<(), Error> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(event.to_dynamodb_item()))
+            // Never overwrite an existing entry
+            .condition_expression("attribute_not_exists(event_key)")
+            .send()
+            .await?;
+
+        archive_or_log(&[ArchivedEvent::from(event)]).await;
+        Ok(())
+    }
+
+    // Append many events at once, e.g. one `subscribed` per imported row
+    pub async fn record_many(&self, events: &[LifecycleEvent]) -> Result<usize, Error> {
+        let items = events
+            .iter()
+            .map(LifecycleEvent::to_dynamodb_item)
+            .collect();
+
+        let written = batch_put(&self.client, &self.table_name, items).await?;
+        let archived: Vec<ArchivedEvent> = events.iter().map(ArchivedEvent::from).collect();
+        archive_or_log(&archived).await;
+        Ok(written)
+    }
+
+    // History is secondary to the change it describes, so failures are only logged
+    pub async fn record_or_log(&self, event: LifecycleEvent) {
+        if let Err(e) = self.record(&event).await {
+            info!(
+                "Error recording {} event for {}: {:?}",
+                event.event_type.as_str(),
+                event.subscriber_id,
+                e
+            );
+        }
+    }
+
+    // Remove a subscriber's history, once their record is purged
+    pub async fn delete_for_subscriber(&self, subscriber_id: &str) -> Result<usize, Error> {
+        delete_partition(
+            &self.client,
+            &self.table_name,
+            ("subscriber_id", subscriber_id),
+            "event_key",
+        )
+        .await
+    }
+
+    // Unsubscribes and suppressions of any subscriber at or after `since`,
+    // oldest first, for replaying them to the opt-out webhook
+    pub async fn opt_outs_since(&self, since: DateTime<Utc>) -> Result<Vec<LifecycleEvent>, Error> {
+        let mut events = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression(
+                    "event_key >= :since AND event_type IN (:unsubscribed, :suppressed)",
+                )
+                .expression_attribute_values(
+                    ":since",
+                    AttributeValue::S(since.to_rfc3339_opts(SecondsFormat::Millis, true)),
+                )
+                .expression_attribute_values(
+                    ":unsubscribed",
+                    AttributeValue::S(LifecycleEventType::Unsubscribed.as_str().to_string()),
+                )
+                .expression_attribute_values(
+                    ":suppressed",
+                    AttributeValue::S(LifecycleEventType::Suppressed.as_str().to_string()),
+                )
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            if let Some(items) = result.items() {
+                events.extend(items.iter().filter_map(LifecycleEvent::from_dynamodb_item));
+            }
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        events.sort_by_key(|event| event.occurred_at);
+        Ok(events)
+    }
+
+    // Every lifecycle event for a subscriber, oldest first
+    pub async fn timeline(&self, subscriber_id: &str) -> Result<Vec<LifecycleEvent>, Error> {
+        let mut events = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("subscriber_id = :subscriber_id")
+                .expression_attribute_values(
+                    ":subscriber_id",
+                    AttributeValue::S(subscriber_id.to_string()),
+                )
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            if let Some(items) = result.items() {
+                events.extend(items.iter().filter_map(LifecycleEvent::from_dynamodb_item));
+            }
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(events)
+    }
+}
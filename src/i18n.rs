@@ -0,0 +1,247 @@
+use crate::template::{render_html, render_text};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+}
+
+impl Locale {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Es => "es",
+        }
+    }
+
+    // Match on the primary language subtag, so "es-AR" resolves to Spanish
+    pub fn parse(tag: &str) -> Option<Self> {
+        let language = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        match language.as_str() {
+            "en" => Some(Locale::En),
+            "es" => Some(Locale::Es),
+            _ => None,
+        }
+    }
+
+    // Pick the supported locale with the highest quality from an Accept-Language header
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        header
+            .split(',')
+            .filter_map(|entry| {
+                let mut parts = entry.split(';');
+                let locale = Locale::parse(parts.next()?)?;
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|quality| quality.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some((locale, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            // max_by keeps the last of equal elements, so reverse to prefer the first listed
+            .rev()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(locale, _)| locale)
+    }
+
+    pub fn from_request(request: &lambda_http::Request) -> Self {
+        request
+            .headers()
+            .get("Accept-Language")
+            .and_then(|value| value.to_str().ok())
+            .and_then(Locale::from_accept_language)
+            .unwrap_or_default()
+    }
+}
+
+// Translate an API message, keyed by its English text. Messages without a
+// translation fall back to English.
+pub fn translate(locale: Locale, message: &str) -> String {
+    let translated = match locale {
+        Locale::En => None,
+        Locale::Es => spanish(message),
+    };
+
+    translated.unwrap_or(message).to_string()
+}
+
+fn spanish(message: &str) -> Option<&'static str> {
+    let translated = match message {
+        "Invalid request body" => "Cuerpo de la solicitud inválido",
+        "Request body too large" => "Cuerpo de la solicitud demasiado grande",
+        "Invalid characters in request" => "Caracteres no válidos en la solicitud",
+        "Welcome back. Confirm your subscription with the email we're sending you." => {
+            "Bienvenido de nuevo. Confirma tu suscripción con el email que te enviamos."
+        }
+        "Invalid JSON format" => "Formato JSON inválido",
+        "Invalid email format" => "Formato de email inválido",
+        "Email address cannot receive mail" => "La dirección de email no puede recibir correo",
+        "An invite code is required to subscribe" => {
+            "Se necesita un código de invitación para suscribirse"
+        }
+        "Invalid or used up invite code" => "Código de invitación inválido o agotado",
+        "Signups from this email domain are not allowed" => {
+            "No se permiten suscripciones desde este dominio de email"
+        }
+        "Successfully subscribed. Validation email will be sent shortly." => {
+            "Suscripción exitosa. En breve recibirás un email de validación."
+        }
+        "Successfully subscribed. Your confirmation email is delayed and will arrive later." => {
+            "Suscripción exitosa. Tu email de confirmación está demorado y llegará más tarde."
+        }
+        "Successfully subscribed." => "Suscripción exitosa.",
+        "You're already on the list" => "Ya estás en la lista",
+        "You're already signed up. We've sent your confirmation email again." => {
+            "Ya estás registrado. Te enviamos de nuevo el email de confirmación."
+        }
+        "You're already signed up. Check your inbox for the confirmation email." => {
+            "Ya estás registrado. Revisa tu bandeja de entrada para el email de confirmación."
+        }
+        "Failed to subscribe" => "No se pudo completar la suscripción",
+        "Missing id or token" => "Falta el id o el token",
+        "Your email is already confirmed" => "Tu email ya está confirmado",
+        "Email successfully validated" => "Email validado correctamente",
+        "Failed to validate email" => "No se pudo validar el email",
+        "Validation token has expired" => "El token de validación expiró",
+        "Confirmation links are valid for {hours} hours" => {
+            "Los enlaces de confirmación son válidos por {hours} horas"
+        }
+        "Invalid validation token" => "Token de validación inválido",
+        "Subscriber not found" => "Suscriptor no encontrado",
+        "Failed to retrieve subscriber information" => {
+            "No se pudo obtener la información del suscriptor"
+        }
+        "Invalid referral link" => "Enlace de referidos inválido",
+        "Confirmed subscriber not found" => "Suscriptor confirmado no encontrado",
+        "Failed to load referral information" => "No se pudo cargar la información de referidos",
+        "Invalid unsubscribe request" => "Solicitud de baja inválida",
+        "Invalid list" => "Lista inválida",
+        "Successfully unsubscribed" => "Baja realizada correctamente",
+        "Unsubscribe from this newsletter?" => "¿Darte de baja de este boletín?",
+        "Leave this list?" => "¿Salir de esta lista?",
+        "Unsubscribe" => "Darme de baja",
+        "View in your browser" => "Ver en el navegador",
+        "Failed to unsubscribe" => "No se pudo dar de baja",
+        "Email not found in subscribers" => "El email no está entre los suscriptores",
+        "Error processing unsubscribe request" => "Error al procesar la solicitud de baja",
+        "Invalid Idempotency-Key header" => "Encabezado Idempotency-Key inválido",
+        "A request with this Idempotency-Key is still being processed" => {
+            "Una solicitud con este Idempotency-Key todavía se está procesando"
+        }
+        "Idempotency-Key was already used for a different request" => {
+            "El Idempotency-Key ya se usó para otra solicitud"
+        }
+        _ => return None,
+    };
+
+    Some(translated)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTemplate {
+    // Variables: confirm_url, valid_hours
+    Confirmation,
+    // Variables: confirm_url, valid_hours
+    ConfirmationReminder,
+    // Variables: keep_url, grace_days
+    Reengagement,
+    // Sent once a subscription is confirmed. Variables: none
+    Welcome,
+    // Welcome with the files promised at signup attached. Variables: none
+    WelcomeWithAttachments,
+}
+
+impl EmailTemplate {
+    fn subject(&self, locale: Locale) -> &'static str {
+        match (self, locale) {
+            (EmailTemplate::Confirmation, Locale::En) => "Confirm your subscription",
+            (EmailTemplate::Confirmation, Locale::Es) => "Confirmá tu suscripción",
+            (EmailTemplate::ConfirmationReminder, Locale::En) => "Please confirm your subscription",
+            (EmailTemplate::ConfirmationReminder, Locale::Es) => {
+                "Por favor confirmá tu suscripción"
+            }
+            (EmailTemplate::Reengagement, Locale::En) => "Still want to hear from us?",
+            (EmailTemplate::Reengagement, Locale::Es) => {
+                "¿Querés seguir recibiendo noticias nuestras?"
+            }
+            (EmailTemplate::Welcome, Locale::En) => "Welcome to our newsletter",
+            (EmailTemplate::Welcome, Locale::Es) => "Bienvenido a nuestro newsletter",
+            (EmailTemplate::WelcomeWithAttachments, Locale::En) => {
+                "Welcome to our newsletter, here is your download"
+            }
+            (EmailTemplate::WelcomeWithAttachments, Locale::Es) => {
+                "Bienvenido a nuestro newsletter, acá está tu descarga"
+            }
+        }
+    }
+
+    fn html_body(&self, locale: Locale) -> &'static str {
+        match (self, locale) {
+            (EmailTemplate::Confirmation, Locale::En) => {
+                "<p>Thanks for subscribing to our newsletter!</p>\
+                 <p><a href=\"{{ confirm_url }}\">Confirm your subscription</a></p>\
+                 <p>This link is valid for {{ valid_hours }} hours.</p>\
+                 <p>If you didn't sign up, you can ignore this email.</p>"
+            }
+            (EmailTemplate::Confirmation, Locale::Es) => {
+                "<p>¡Gracias por suscribirte a nuestro newsletter!</p>\
+                 <p><a href=\"{{ confirm_url }}\">Confirmá tu suscripción</a></p>\
+                 <p>Este enlace es válido por {{ valid_hours }} horas.</p>\
+                 <p>Si no te suscribiste, podés ignorar este email.</p>"
+            }
+            (EmailTemplate::ConfirmationReminder, Locale::En) => {
+                "<p>You signed up for our newsletter but haven't confirmed your email yet.</p>\
+                 <p><a href=\"{{ confirm_url }}\">Confirm your subscription</a></p>\
+                 <p>This link is valid for {{ valid_hours }} hours.</p>\
+                 <p>If you didn't sign up, you can ignore this email and you won't hear from us again.</p>"
+            }
+            (EmailTemplate::ConfirmationReminder, Locale::Es) => {
+                "<p>Te suscribiste a nuestro newsletter pero todavía no confirmaste tu email.</p>\
+                 <p><a href=\"{{ confirm_url }}\">Confirmá tu suscripción</a></p>\
+                 <p>Este enlace es válido por {{ valid_hours }} horas.</p>\
+                 <p>Si no te suscribiste, podés ignorar este email y no volverás a saber de nosotros.</p>"
+            }
+            (EmailTemplate::Reengagement, Locale::En) => {
+                "<p>We noticed you haven't opened our emails in a while.</p>\
+                 <p><a href=\"{{ keep_url }}\">Yes, keep me subscribed</a></p>\
+                 <p>If we don't hear from you in the next {{ grace_days }} days, we'll stop sending you the newsletter.</p>"
+            }
+            (EmailTemplate::Reengagement, Locale::Es) => {
+                "<p>Notamos que hace tiempo no abrís nuestros emails.</p>\
+                 <p><a href=\"{{ keep_url }}\">Sí, quiero seguir suscripto</a></p>\
+                 <p>Si no sabemos de vos en los próximos {{ grace_days }} días, dejaremos de enviarte el newsletter.</p>"
+            }
+            (EmailTemplate::Welcome, Locale::En) => {
+                "<p>Your subscription is confirmed, thanks for joining!</p>\
+                 <p>You'll get our next issue as soon as it's out.</p>"
+            }
+            (EmailTemplate::Welcome, Locale::Es) => {
+                "<p>Tu suscripción está confirmada, ¡gracias por sumarte!</p>\
+                 <p>Vas a recibir nuestro próximo número apenas salga.</p>"
+            }
+            (EmailTemplate::WelcomeWithAttachments, Locale::En) => {
+                "<p>Your subscription is confirmed, thanks for joining!</p>\
+                 <p>The files you signed up for are attached to this email.</p>\
+                 <p>You'll get our next issue as soon as it's out.</p>"
+            }
+            (EmailTemplate::WelcomeWithAttachments, Locale::Es) => {
+                "<p>Tu suscripción está confirmada, ¡gracias por sumarte!</p>\
+                 <p>Los archivos que pediste están adjuntos a este email.</p>\
+                 <p>Vas a recibir nuestro próximo número apenas salga.</p>"
+            }
+        }
+    }
+
+    // Render the subject and HTML body for a locale
+    pub fn render(&self, locale: Locale, variables: &HashMap<String, String>) -> (String, String) {
+        (
+            render_text(self.subject(locale), variables),
+            render_html(self.html_body(locale), variables),
+        )
+    }
+}
@@ -0,0 +1,147 @@
+use crate::config::table_name;
+use crate::{Error, IDEMPOTENCY_TABLE_NAME};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+
+// How long a key is remembered, after which DynamoDB TTL removes it
+pub const IDEMPOTENCY_TTL_HOURS: i64 = 24;
+
+// Response recorded for a completed request
+#[derive(Debug)]
+pub struct StoredResponse {
+    pub status_code: u16,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum IdempotencyState {
+    // First time this key is seen, the caller now owns it
+    New,
+    // Another request with this key is still being processed
+    InProgress,
+    // The key was already used for the same request
+    Completed(StoredResponse),
+    // The key was already used for a different request
+    Mismatch,
+}
+
+// SHA-256 of the request body, used to detect keys reused for different requests
+pub fn fingerprint(body: &str) -> String {
+    Sha256::digest(body.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub struct IdempotencyStore {
+    client: Client,
+    table_name: String,
+}
+
+impl IdempotencyStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(IDEMPOTENCY_TABLE_NAME),
+        }
+    }
+
+    // Claim the key for this request, or report what happened to an earlier one
+    pub async fn begin(&self, key: &str, fingerprint: &str) -> Result<IdempotencyState, Error> {
+        let now = Utc::now();
+        let expires_at = (now + Duration::hours(IDEMPOTENCY_TTL_HOURS)).timestamp();
+
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("key", AttributeValue::S(key.to_string()))
+            .item("fingerprint", AttributeValue::S(fingerprint.to_string()))
+            .item("status", AttributeValue::S("in_progress".to_string()))
+            .item("created_at", AttributeValue::S(now.to_rfc3339()))
+            .item("expires_at", AttributeValue::N(expires_at.to_string()))
+            // Expired keys may linger until TTL deletes them, so they can be reclaimed
+            .condition_expression("attribute_not_exists(#key) OR expires_at < :now")
+            .expression_attribute_names("#key", "key")
+            .expression_attribute_values(":now", AttributeValue::N(now.timestamp().to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => return Ok(IdempotencyState::New),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let existing = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("key", AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        let item = match existing.item() {
+            Some(item) => item,
+            // Removed between the two calls, treat it as still being processed
+            None => return Ok(IdempotencyState::InProgress),
+        };
+
+        let stored_fingerprint = item.get("fingerprint").and_then(|value| value.as_s().ok());
+        if stored_fingerprint.map(String::as_str) != Some(fingerprint) {
+            return Ok(IdempotencyState::Mismatch);
+        }
+
+        let status_code = item
+            .get("status_code")
+            .and_then(|value| value.as_n().ok())
+            .and_then(|value| value.parse().ok());
+        let body = item
+            .get("response_body")
+            .and_then(|value| value.as_s().ok());
+
+        match (status_code, body) {
+            (Some(status_code), Some(body)) => Ok(IdempotencyState::Completed(StoredResponse {
+                status_code,
+                body: body.clone(),
+            })),
+            _ => Ok(IdempotencyState::InProgress),
+        }
+    }
+
+    // Record the response so replays of the key get the same answer
+    pub async fn complete(&self, key: &str, status_code: u16, body: &str) -> Result<(), Error> {
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("key", AttributeValue::S(key.to_string()))
+            .update_expression(
+                "SET #status = :status, status_code = :status_code, response_body = :body",
+            )
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(":status", AttributeValue::S("completed".to_string()))
+            .expression_attribute_values(":status_code", AttributeValue::N(status_code.to_string()))
+            .expression_attribute_values(":body", AttributeValue::S(body.to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Forget the key so the client can retry, used when processing failed unexpectedly
+    pub async fn release(&self, key: &str) -> Result<(), Error> {
+        self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("key", AttributeValue::S(key.to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
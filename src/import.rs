@@ -0,0 +1,442 @@
+use crate::address::normalize_email;
+use crate::input::is_valid_email;
+use crate::repository::{MAX_EMAIL_BATCH, SubscriberRepository};
+use crate::stripe::Tier;
+use crate::{Error, Subscriber};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::str::FromStr;
+use tracing::info;
+use utoipa::ToSchema;
+
+// Rows listed individually in an import report, besides the counts
+const REPORT_SAMPLE_ROWS: usize = 100;
+
+// CSV layout of an import file: a plain `email` column or another provider's export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Generic,
+    // Audience export, one file per status (subscribed, unsubscribed, cleaned)
+    Mailchimp,
+    Buttondown,
+    Substack,
+}
+
+impl ImportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportFormat::Generic => "generic",
+            ImportFormat::Mailchimp => "mailchimp",
+            ImportFormat::Buttondown => "buttondown",
+            ImportFormat::Substack => "substack",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "generic" | "csv" => Some(ImportFormat::Generic),
+            "mailchimp" => Some(ImportFormat::Mailchimp),
+            "buttondown" => Some(ImportFormat::Buttondown),
+            "substack" => Some(ImportFormat::Substack),
+            _ => None,
+        }
+    }
+
+    // Column holding the email address
+    fn email_column(&self) -> &'static str {
+        match self {
+            ImportFormat::Mailchimp => "email address",
+            _ => "email",
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::parse(value).ok_or_else(|| {
+            format!("Unknown import format `{value}`, expected generic, mailchimp, buttondown or substack")
+        })
+    }
+}
+
+// Subscription status in the source system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStatus {
+    Subscribed,
+    // Signed up but never confirmed
+    Unconfirmed,
+    Unsubscribed,
+    // Bounced, marked as spam or removed by the provider
+    Cleaned,
+}
+
+// One subscriber read from an import file
+#[derive(Debug, Clone)]
+pub struct ImportRow {
+    pub email: String,
+    pub status: ImportStatus,
+    // Original signup date, kept as the subscriber's created_at
+    pub subscribed_at: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub fields: HashMap<String, String>,
+    pub tier: Tier,
+}
+
+impl ImportRow {
+    pub fn into_subscriber(self, format: ImportFormat) -> Subscriber {
+        let mut subscriber = Subscriber::new(self.email);
+
+        subscriber.active = matches!(
+            self.status,
+            ImportStatus::Subscribed | ImportStatus::Unconfirmed
+        );
+        // Anyone the provider knew beyond a pending signup had confirmed there
+        subscriber.validated = self.status != ImportStatus::Unconfirmed;
+        subscriber.suppressed = self.status == ImportStatus::Cleaned;
+        subscriber.tier = self.tier;
+        subscriber.fields = self.fields;
+        if !self.tags.is_empty() {
+            subscriber
+                .fields
+                .insert("tags".to_string(), self.tags.join(","));
+        }
+        if let Some(subscribed_at) = self.subscribed_at {
+            subscriber.created_at = subscribed_at;
+        }
+        if format != ImportFormat::Generic {
+            subscriber.attribution.source = Some(format.as_str().to_string());
+        }
+
+        subscriber
+    }
+}
+
+// Case-insensitive lookup of columns by header name
+pub struct ImportColumns {
+    format: ImportFormat,
+    indexes: HashMap<String, usize>,
+}
+
+impl ImportColumns {
+    pub fn new(format: ImportFormat, headers: &StringRecord) -> Result<Self, String> {
+        let indexes: HashMap<String, usize> = headers
+            .iter()
+            .enumerate()
+            .map(|(index, header)| (header.trim().to_ascii_lowercase(), index))
+            .collect();
+
+        if !indexes.contains_key(format.email_column()) {
+            return Err(format!(
+                "CSV file has no `{}` column",
+                format.email_column()
+            ));
+        }
+
+        Ok(Self { format, indexes })
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.indexes.contains_key(name)
+    }
+
+    // Trimmed value of a column, None when the column is missing or empty
+    fn get<'a>(&self, record: &'a StringRecord, name: &str) -> Option<&'a str> {
+        let value = record.get(*self.indexes.get(name)?)?.trim();
+        (!value.is_empty()).then_some(value)
+    }
+
+    fn flag(&self, record: &StringRecord, name: &str) -> bool {
+        self.get(record, name)
+            .is_some_and(|value| value.eq_ignore_ascii_case("true"))
+    }
+
+    fn date(&self, record: &StringRecord, names: &[&str]) -> Option<DateTime<Utc>> {
+        names
+            .iter()
+            .find_map(|name| self.get(record, name).and_then(parse_date))
+    }
+
+    fn names(&self, record: &StringRecord, first: &str, last: &str) -> HashMap<String, String> {
+        let mut fields = HashMap::new();
+        for (field, column) in [("first_name", first), ("last_name", last)] {
+            if let Some(value) = self.get(record, column) {
+                fields.insert(field.to_string(), value.to_string());
+            }
+        }
+        fields
+    }
+
+    pub fn read(&self, record: &StringRecord) -> ImportRow {
+        let email = self
+            .get(record, self.format.email_column())
+            .unwrap_or_default();
+        let email = normalize_email(email).unwrap_or_else(|| email.to_string());
+
+        let mut row = ImportRow {
+            email,
+            status: ImportStatus::Unconfirmed,
+            subscribed_at: None,
+            tags: Vec::new(),
+            fields: HashMap::new(),
+            tier: Tier::Free,
+        };
+
+        match self.format {
+            ImportFormat::Generic => {}
+            ImportFormat::Mailchimp => {
+                // Mailchimp exports each status to its own file, told apart by
+                // the columns only that file has
+                row.status = if self.has("clean_time") {
+                    ImportStatus::Cleaned
+                } else if self.has("unsub_time") {
+                    ImportStatus::Unsubscribed
+                } else {
+                    ImportStatus::Subscribed
+                };
+                row.subscribed_at = self.date(record, &["confirm_time", "optin_time"]);
+                row.tags = split_tags(self.get(record, "tags"));
+                row.fields = self.names(record, "first name", "last name");
+            }
+            ImportFormat::Buttondown => {
+                let subscriber_type = self
+                    .get(record, "subscriber_type")
+                    .or_else(|| self.get(record, "type"))
+                    .unwrap_or("regular")
+                    .to_ascii_lowercase();
+                row.status = match subscriber_type.as_str() {
+                    "unactivated" => ImportStatus::Unconfirmed,
+                    "unsubscribed" => ImportStatus::Unsubscribed,
+                    "removed" | "spammy" | "undeliverable" | "blocked" => ImportStatus::Cleaned,
+                    _ => ImportStatus::Subscribed,
+                };
+                if matches!(subscriber_type.as_str(), "premium" | "gifted" | "churning") {
+                    row.tier = Tier::Premium;
+                }
+                row.subscribed_at = self.date(record, &["creation_date", "created_at"]);
+                row.tags = split_tags(self.get(record, "tags"));
+            }
+            ImportFormat::Substack => {
+                row.status = if self.flag(record, "email_disabled") {
+                    ImportStatus::Unsubscribed
+                } else {
+                    ImportStatus::Subscribed
+                };
+                if self.flag(record, "active_subscription") {
+                    row.tier = Tier::Premium;
+                }
+                row.subscribed_at = self.date(record, &["created_at", "subscription_date"]);
+            }
+        }
+
+        row
+    }
+}
+
+// Tags exported as `"a","b"` (Mailchimp), `['a', 'b']` or `a, b`
+fn split_tags(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .trim_matches(|c| c == '[' || c == ']')
+        .split(',')
+        .map(|tag| tag.trim().trim_matches(|c| c == '"' || c == '\''))
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Providers export dates as RFC 3339, `YYYY-MM-DD HH:MM:SS` in UTC or plain dates
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })
+        .map(|date| date.and_utc())
+}
+
+// What importing a row does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RowOutcome {
+    // Creates a subscriber
+    New,
+    // Skipped, the address is already subscribed or earlier in the file
+    Duplicate,
+    // Skipped, not an email address
+    Invalid,
+    // Skipped, the address is suppressed and must never be mailed again
+    Suppressed,
+    // Creates a subscriber again for an address that was deleted but not purged yet
+    Reactivated,
+}
+
+impl RowOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RowOutcome::New => "new",
+            RowOutcome::Duplicate => "duplicate",
+            RowOutcome::Invalid => "invalid",
+            RowOutcome::Suppressed => "suppressed",
+            RowOutcome::Reactivated => "reactivated",
+        }
+    }
+
+    pub fn is_written(&self) -> bool {
+        matches!(self, RowOutcome::New | RowOutcome::Reactivated)
+    }
+}
+
+// A row worth a closer look before importing
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReportRow {
+    // Line in the CSV file, counting the header as line 1
+    pub line: u64,
+    pub email: String,
+    pub outcome: RowOutcome,
+}
+
+// What an import does, counted per outcome
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ImportReport {
+    pub new: usize,
+    pub duplicates: usize,
+    pub invalid: usize,
+    pub suppressed: usize,
+    pub reactivated: usize,
+    // The first invalid, suppressed and reactivated rows
+    pub samples: Vec<ReportRow>,
+}
+
+impl ImportReport {
+    fn count(&mut self, line: u64, email: &str, outcome: RowOutcome) {
+        match outcome {
+            RowOutcome::New => self.new += 1,
+            RowOutcome::Duplicate => self.duplicates += 1,
+            RowOutcome::Invalid => self.invalid += 1,
+            RowOutcome::Suppressed => self.suppressed += 1,
+            RowOutcome::Reactivated => self.reactivated += 1,
+        }
+        if !matches!(outcome, RowOutcome::New | RowOutcome::Duplicate)
+            && self.samples.len() < REPORT_SAMPLE_ROWS
+        {
+            self.samples.push(ReportRow {
+                line,
+                email: email.to_string(),
+                outcome,
+            });
+        }
+    }
+}
+
+// Rows to write and the report describing them, worked out without writing anything
+pub struct ImportPlan {
+    pub rows: Vec<ImportRow>,
+    pub report: ImportReport,
+}
+
+// The distinct valid addresses in an import file, read ahead of planning so
+// only the subscribers sharing one need to be looked up
+pub fn import_emails<R: Read>(
+    reader: &mut csv::Reader<R>,
+    format: ImportFormat,
+) -> Result<Vec<String>, Error> {
+    let columns = ImportColumns::new(format, reader.headers()?)?;
+
+    let mut seen = HashSet::new();
+    let mut emails = Vec::new();
+    for record in reader.records() {
+        let email = columns.read(&record?).email;
+        if is_valid_email(&email) && seen.insert(email.clone()) {
+            emails.push(email);
+        }
+    }
+    Ok(emails)
+}
+
+// Stored subscribers, soft-deleted ones included, sharing an address with
+// `emails`, looked up MAX_EMAIL_BATCH at a time. Much cheaper than scanning
+// the table unless the file is about as big as it.
+pub async fn existing_subscribers(
+    repository: &SubscriberRepository,
+    emails: &[String],
+) -> Result<Vec<Subscriber>, Error> {
+    let mut existing = Vec::new();
+    for chunk in emails.chunks(MAX_EMAIL_BATCH) {
+        existing.extend(repository.find_many_by_email(chunk).await?);
+    }
+    Ok(existing)
+}
+
+// Read an import file and match its rows against `existing`, the stored
+// subscribers with the file's addresses including soft-deleted ones
+pub fn plan_import<R: Read>(
+    reader: &mut csv::Reader<R>,
+    format: ImportFormat,
+    existing: Vec<Subscriber>,
+) -> Result<ImportPlan, Error> {
+    let columns = ImportColumns::new(format, reader.headers()?)?;
+
+    // A live record wins over deleted ones with the same address
+    let mut known: HashMap<String, Subscriber> = HashMap::new();
+    for subscriber in existing {
+        if subscriber.is_deleted() && known.contains_key(&subscriber.email) {
+            continue;
+        }
+        known.insert(subscriber.email.clone(), subscriber);
+    }
+
+    let mut seen = HashSet::new();
+    let mut plan = ImportPlan {
+        rows: Vec::new(),
+        report: ImportReport::default(),
+    };
+
+    for record in reader.records() {
+        let record = record?;
+        let line = record
+            .position()
+            .map(|position| position.line())
+            .unwrap_or(0);
+        let row = columns.read(&record);
+
+        let outcome = if !is_valid_email(&row.email) {
+            info!("Skipping invalid email: {}", row.email);
+            RowOutcome::Invalid
+        } else if !seen.insert(row.email.clone()) {
+            RowOutcome::Duplicate
+        } else {
+            match known.get(&row.email) {
+                None => RowOutcome::New,
+                Some(subscriber) if subscriber.is_deleted() => RowOutcome::Reactivated,
+                Some(subscriber) if subscriber.suppressed => RowOutcome::Suppressed,
+                Some(_) => RowOutcome::Duplicate,
+            }
+        };
+
+        plan.report.count(line, &row.email, outcome);
+        if outcome.is_written() {
+            plan.rows.push(row);
+        }
+    }
+
+    Ok(plan)
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportPreviewResponse {
+    pub success: bool,
+    pub format: String,
+    pub report: ImportReport,
+}
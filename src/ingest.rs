@@ -0,0 +1,121 @@
+use crate::Error;
+use crate::domains::{DomainMetric, DomainStatsStore};
+use crate::events::{Event, EventRepository, EventType, pixel_tracking_enabled};
+use crate::history::{HistoryRepository, LifecycleEvent, LifecycleEventType};
+use crate::opt_out;
+use crate::repository::SubscriberRepository;
+use aws_sdk_dynamodb::Client;
+use tracing::info;
+
+// Applies delivery and engagement events reported by whichever provider sent
+// the email, SES or an ESP webhook, so suppression, domain stats and engagement
+// scoring work the same for all of them
+pub struct EventIngest {
+    events: EventRepository,
+    subscribers: SubscriberRepository,
+    history: HistoryRepository,
+    domains: DomainStatsStore,
+    // With the pixel also enabled, opens and clicks already feed the engagement
+    // score through /track and are only stored here
+    score_engagement: bool,
+}
+
+impl EventIngest {
+    pub fn new(client: Client) -> Self {
+        Self {
+            events: EventRepository::new(client.clone()),
+            subscribers: SubscriberRepository::new(client.clone()),
+            history: HistoryRepository::new(client.clone()),
+            domains: DomainStatsStore::new(client),
+            score_engagement: !pixel_tracking_enabled(),
+        }
+    }
+
+    pub fn subscribers(&self) -> &SubscriberRepository {
+        &self.subscribers
+    }
+
+    // Store `tracked` and act on it. `recipient` is the address the email went
+    // to, `permanent` marks bounces after which the address is never mailed
+    // again, and `source` names the provider in the subscriber's history.
+    // Errors are returned so the provider retries the delivery.
+    pub async fn ingest(
+        &self,
+        tracked: &Event,
+        recipient: Option<&str>,
+        permanent: bool,
+        source: &str,
+    ) -> Result<(), Error> {
+        self.events.record(tracked).await?;
+
+        // Opens and clicks are counted by whichever of the provider and /track scores them
+        if (self.score_engagement || !tracked.event_type.is_engagement())
+            && let Some(recipient) = recipient
+        {
+            self.domains
+                .record_or_log(
+                    recipient,
+                    DomainMetric::from_event(tracked.event_type),
+                    tracked.occurred_at,
+                )
+                .await;
+        }
+
+        if self.score_engagement && tracked.event_type.is_engagement() {
+            self.subscribers
+                .record_engagement(
+                    &tracked.subscriber_id,
+                    tracked.event_type.engagement_weight(),
+                    tracked.occurred_at,
+                )
+                .await?;
+        }
+
+        match tracked.event_type {
+            EventType::Bounce => {
+                if permanent {
+                    let suppressed = self.subscribers.suppress(&tracked.subscriber_id).await?;
+                    info!("Suppressed bounced subscriber {}", tracked.subscriber_id);
+                    if let Some(subscriber) = suppressed {
+                        let event = LifecycleEvent::new(
+                            &tracked.subscriber_id,
+                            LifecycleEventType::Suppressed,
+                        )
+                        .with_source(source)
+                        .with_detail("bounce");
+                        opt_out::publish(&event, &subscriber.email).await;
+                        self.history.record_or_log(event).await;
+                    }
+                }
+
+                let mut bounced =
+                    LifecycleEvent::new(&tracked.subscriber_id, LifecycleEventType::Bounced)
+                        .with_source(source);
+                if let Some(detail) = &tracked.detail {
+                    bounced = bounced.with_detail(detail.as_str());
+                }
+                self.history.record_or_log(bounced).await;
+            }
+            // Mailing someone who reported spam hurts the sender's reputation everywhere
+            EventType::Complaint => {
+                let suppressed = self.subscribers.suppress(&tracked.subscriber_id).await?;
+                info!(
+                    "Suppressed complaining subscriber {}",
+                    tracked.subscriber_id
+                );
+
+                let event =
+                    LifecycleEvent::new(&tracked.subscriber_id, LifecycleEventType::Suppressed)
+                        .with_source(source)
+                        .with_detail("complaint");
+                if let Some(subscriber) = suppressed {
+                    opt_out::publish(&event, &subscriber.email).await;
+                }
+                self.history.record_or_log(event).await;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use crate::address::{normalize_email, utf8_local_parts};
+use crate::config::env_or;
+use crate::i18n::{Locale, translate};
+use crate::{ApiResponse, Error, create_response};
+use email_address::EmailAddress;
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde_json::Value;
+use std::future::Future;
+use url::form_urlencoded;
+
+// RFC 5321 limits: 64 octets before the @, and 254 for the whole address,
+// being the 256 octet path less its angle brackets
+pub const MAX_EMAIL_LENGTH: usize = 254;
+pub const MAX_LOCAL_PART_LENGTH: usize = 64;
+
+// JSON fields that may span lines, such as template HTML and admin notes.
+// Everything else, like subjects and names that end up in email headers, has
+// to be a single line.
+const MULTILINE_FIELDS: &[&str] = &["html_body", "note"];
+
+// What an HTTP handler accepts, checked before it parses anything
+#[derive(Debug, Clone, Copy)]
+pub struct InputLimits {
+    // MAX_BODY_BYTES overrides it per Lambda
+    pub max_body_bytes: usize,
+    // Whether the strings in the body are checked for control characters.
+    // Webhooks skip it: their payloads are authenticated as sent, and a
+    // provider's event can't be turned away for what's in it.
+    pub check_body: bool,
+}
+
+impl InputLimits {
+    // Signup, confirm, unsubscribe and the other public endpoints
+    pub const PUBLIC: Self = Self {
+        max_body_bytes: 16 * 1024,
+        check_body: true,
+    };
+    // Admin API, whose imports and templates run larger. Lambda's own limit
+    // on a synchronous request is 6 MB.
+    pub const ADMIN: Self = Self {
+        max_body_bytes: 6 * 1024 * 1024,
+        check_body: true,
+    };
+    // Stripe, SendGrid and Postmark webhooks
+    pub const WEBHOOK: Self = Self {
+        max_body_bytes: 256 * 1024,
+        check_body: false,
+    };
+}
+
+// A control character in a value that has to be a single line. Newlines are
+// how header injection works, so they count.
+pub fn is_single_line(value: &str) -> bool {
+    !value.chars().any(char::is_control)
+}
+
+// Multi-line text, allowing line breaks and tabs but no other control characters
+pub fn is_clean_text(value: &str) -> bool {
+    !value
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+}
+
+// A syntactically valid address within the RFC 5321 length limits, measured
+// with its domain in punycode as it goes over the wire. UTF-8 local parts are
+// only valid with ALLOW_UTF8_LOCAL_PART.
+pub fn is_valid_email(email: &str) -> bool {
+    if !is_single_line(email) {
+        return false;
+    }
+    let Some(normalized) = normalize_email(email) else {
+        return false;
+    };
+    let local_part = normalized.rsplit_once('@').map(|(local, _)| local);
+    normalized.len() <= MAX_EMAIL_LENGTH
+        && local_part.is_some_and(|local| {
+            local.len() <= MAX_LOCAL_PART_LENGTH && (local.is_ascii() || utf8_local_parts())
+        })
+        && EmailAddress::is_valid(&normalized)
+}
+
+// Whether every string in a JSON document, and every key, keeps to the rules
+// for its field
+fn is_clean_json(value: &Value, field: Option<&str>) -> bool {
+    match value {
+        Value::String(text) if field.is_some_and(|field| MULTILINE_FIELDS.contains(&field)) => {
+            is_clean_text(text)
+        }
+        Value::String(text) => is_single_line(text),
+        Value::Array(values) => values.iter().all(|value| is_clean_json(value, field)),
+        Value::Object(fields) => fields
+            .iter()
+            .all(|(name, value)| is_single_line(name) && is_clean_json(value, Some(name))),
+        _ => true,
+    }
+}
+
+// Why a request is turned away, or None when it can be handled
+fn check(event: &Request, limits: InputLimits) -> Option<(u16, &'static str)> {
+    let max_body_bytes = env_or("MAX_BODY_BYTES", limits.max_body_bytes);
+    let body: &[u8] = match event.body() {
+        Body::Text(text) => text.as_bytes(),
+        Body::Binary(bytes) => bytes,
+        _ => &[],
+    };
+    if body.len() > max_body_bytes {
+        return Some((413, "Request body too large"));
+    }
+
+    let params = event.query_string_parameters();
+    if params
+        .iter()
+        .any(|(name, value)| !is_single_line(name) || !is_single_line(value))
+    {
+        return Some((400, "Invalid characters in request"));
+    }
+
+    if !limits.check_body || body.is_empty() {
+        return None;
+    }
+
+    let content_type = event
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let clean = if content_type.starts_with("application/x-www-form-urlencoded") {
+        form_urlencoded::parse(body)
+            .all(|(name, value)| is_single_line(&name) && is_single_line(&value))
+    } else {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(value) => is_clean_json(&value, None),
+            // Not JSON, e.g. a CSV import, which spans lines but needs no
+            // other control characters. Malformed JSON and binary bodies are
+            // left to the handler.
+            Err(_) => match std::str::from_utf8(body) {
+                Ok(text) => is_clean_text(text),
+                Err(_) => true,
+            },
+        }
+    };
+
+    (!clean).then_some((400, "Invalid characters in request"))
+}
+
+// Serve `handler` only for requests within `limits`, answering 413 for an
+// oversized body and 400 for control characters where a single line is expected
+pub async fn checked<F, Fut>(
+    event: Request,
+    limits: InputLimits,
+    handler: F,
+) -> Result<Response<Body>, Error>
+where
+    F: FnOnce(Request) -> Fut,
+    Fut: Future<Output = Result<Response<Body>, Error>>,
+{
+    match check(&event, limits) {
+        Some((status, message)) => Ok(create_response(
+            status,
+            ApiResponse {
+                success: false,
+                message: translate(Locale::from_request(&event), message),
+                data: None,
+            },
+        )),
+        None => handler(event).await,
+    }
+}
@@ -0,0 +1,268 @@
+use crate::config::table_name;
+use crate::referral::{generate_code, normalize_code};
+use crate::{Error, INVITE_CODES_TABLE_NAME, optional_datetime};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use utoipa::ToSchema;
+
+// With INVITE_ONLY=true, subscribe requires a valid invite code
+pub fn invite_only() -> bool {
+    env::var("INVITE_ONLY")
+        .map(|value| value == "true")
+        .unwrap_or(false)
+}
+
+// A code that lets up to `max_uses` people subscribe while invite-only mode is on
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct InviteCode {
+    pub code: String,
+    pub max_uses: u32,
+    pub uses: u32,
+    // Revoked codes are kept so their usage stays visible
+    pub revoked: bool,
+    // Who or what the code was minted for, e.g. "launch tweet"
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl InviteCode {
+    pub fn new(code: String, max_uses: u32, note: Option<String>) -> Self {
+        Self {
+            code,
+            max_uses,
+            uses: 0,
+            revoked: false,
+            note,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert("code".to_string(), AttributeValue::S(self.code.clone()));
+        item.insert(
+            "max_uses".to_string(),
+            AttributeValue::N(self.max_uses.to_string()),
+        );
+        item.insert("uses".to_string(), AttributeValue::N(self.uses.to_string()));
+        item.insert("revoked".to_string(), AttributeValue::Bool(self.revoked));
+        if let Some(note) = &self.note {
+            item.insert("note".to_string(), AttributeValue::S(note.clone()));
+        }
+        item.insert(
+            "created_at".to_string(),
+            AttributeValue::S(self.created_at.to_rfc3339()),
+        );
+        item
+    }
+
+    fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let number = |name: &str| {
+            item.get(name)
+                .and_then(|value| value.as_n().ok())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0)
+        };
+
+        Some(Self {
+            code: item.get("code")?.as_s().ok()?.clone(),
+            max_uses: number("max_uses"),
+            uses: number("uses"),
+            revoked: item
+                .get("revoked")
+                .and_then(|value| value.as_bool().ok())
+                .copied()
+                .unwrap_or(false),
+            note: item
+                .get("note")
+                .and_then(|value| value.as_s().ok())
+                .cloned(),
+            created_at: optional_datetime(item, "created_at").unwrap_or_else(Utc::now),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateInviteRequest {
+    // Generated when omitted
+    #[serde(default)]
+    pub code: Option<String>,
+    // 1 for a single-use code
+    #[serde(default = "default_max_uses")]
+    pub max_uses: u32,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+fn default_max_uses() -> u32 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct InviteListResponse {
+    pub success: bool,
+    pub invites: Vec<InviteCode>,
+}
+
+// Invite codes with usage counters, one item per code
+pub struct InviteStore {
+    client: Client,
+    table_name: String,
+}
+
+impl InviteStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(INVITE_CODES_TABLE_NAME),
+        }
+    }
+
+    // Mint a code, refusing to overwrite an existing one. Returns None when the
+    // code is taken or isn't a valid code.
+    pub async fn create(&self, request: CreateInviteRequest) -> Result<Option<InviteCode>, Error> {
+        let code = match request.code.as_deref() {
+            Some(code) => match normalize_code(code) {
+                Some(code) => code,
+                None => return Ok(None),
+            },
+            None => generate_code(),
+        };
+        let invite = InviteCode::new(code, request.max_uses.max(1), request.note);
+
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(invite.to_dynamodb_item()))
+            .condition_expression("attribute_not_exists(code)")
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(Some(invite)),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn list(&self) -> Result<Vec<InviteCode>, Error> {
+        let mut invites = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            invites.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(InviteCode::from_dynamodb_item),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        invites.sort_by_key(|invite| Reverse(invite.created_at));
+        Ok(invites)
+    }
+
+    // Stop a code from being redeemed, returning it or None when it doesn't exist
+    pub async fn revoke(&self, code: &str) -> Result<Option<InviteCode>, Error> {
+        let Some(code) = normalize_code(code) else {
+            return Ok(None);
+        };
+
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("code", AttributeValue::S(code))
+            .update_expression("SET revoked = :revoked")
+            .condition_expression("attribute_exists(code)")
+            .expression_attribute_values(":revoked", AttributeValue::Bool(true))
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(output.attributes().and_then(InviteCode::from_dynamodb_item)),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Count a use of the code if it exists, isn't revoked and has uses left.
+    // The condition makes concurrent signups unable to overuse a code.
+    pub async fn redeem(&self, code: &str) -> Result<bool, Error> {
+        let Some(code) = normalize_code(code) else {
+            return Ok(false);
+        };
+
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("code", AttributeValue::S(code))
+            .update_expression("ADD uses :one")
+            .condition_expression("attribute_exists(code) AND revoked = :false AND uses < max_uses")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":false", AttributeValue::Bool(false))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Give back a use taken by a signup that then failed
+    pub async fn release(&self, code: &str) -> Result<(), Error> {
+        let Some(code) = normalize_code(code) else {
+            return Ok(());
+        };
+
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("code", AttributeValue::S(code))
+            .update_expression("ADD uses :minus_one")
+            .condition_expression("uses > :zero")
+            .expression_attribute_values(":minus_one", AttributeValue::N("-1".to_string()))
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,516 @@
+use crate::config::table_name;
+use crate::export::ExportParams;
+use crate::queue::{QueuePayload, SCHEMA_VERSION, SchemaVersion, read_current};
+use crate::repository::ItemKey;
+use crate::tagging::TagParams;
+use crate::{Error, JOBS_TABLE_NAME, optional_datetime};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::update_item::builders::UpdateItemFluentBuilder;
+use aws_sdk_dynamodb::operation::update_item::{UpdateItemError, UpdateItemOutput};
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_sqs::Client as SqsClient;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// An operation too long for one invocation, worked through by the job_worker
+// Lambda one page of subscribers per queue message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    // Add or remove a tag across a segment, see `TagParams`
+    Tag,
+    // Write subscribers to CSV files in S3, see `ExportParams`
+    Export,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Tag => "tag",
+            JobKind::Export => "export",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "tag" => Some(JobKind::Tag),
+            "export" => Some(JobKind::Export),
+            _ => None,
+        }
+    }
+
+    // Subscribers scanned per queue message. Tagging writes every match, so
+    // its pages are smaller to stay well within the worker's timeout.
+    pub fn page_size(&self) -> i32 {
+        match self {
+            JobKind::Tag => 200,
+            JobKind::Export => 1000,
+        }
+    }
+
+    // Check and normalize the parameters of a new job of this kind
+    pub fn validate(&self, params: Value) -> Result<Value, Error> {
+        Ok(match self {
+            JobKind::Tag => {
+                serde_json::to_value(serde_json::from_value::<TagParams>(params)?.normalized()?)?
+            }
+            JobKind::Export => {
+                serde_json::to_value(serde_json::from_value::<ExportParams>(params)?.normalized()?)?
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    // Waiting for the worker to handle the first page
+    Queued,
+    Running,
+    Completed,
+    // Stopped on an error that retrying can't fix, see `error`
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "queued" => Some(JobStatus::Queued),
+            "running" => Some(JobStatus::Running),
+            "completed" => Some(JobStatus::Completed),
+            "failed" => Some(JobStatus::Failed),
+            "cancelled" => Some(JobStatus::Cancelled),
+            _ => None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Completed | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+// How far a job got, summed over the pages handled so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct JobProgress {
+    pub pages: u32,
+    pub scanned: u32,
+    // Subscribers in the job's segment
+    pub matched: u32,
+    // Subscribers the job acted on: tagged or untagged, or exported
+    pub processed: u32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct StartJobRequest {
+    pub kind: JobKind,
+    // Depends on the kind, see `TagParams` and `ExportParams`
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Job {
+    pub id: String,
+    pub kind: JobKind,
+    #[schema(value_type = Object)]
+    pub params: Value,
+    pub status: JobStatus,
+    pub progress: JobProgress,
+    // Where the output is, e.g. `s3://bucket/exports/<id>/`, for kinds that
+    // produce one
+    pub result_location: Option<String>,
+    // Why a failed job stopped
+    pub error: Option<String>,
+    // API key or token holder who started the job
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl Job {
+    pub fn new(kind: JobKind, params: Value, created_by: &str) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            kind,
+            params,
+            status: JobStatus::Queued,
+            progress: JobProgress::default(),
+            result_location: None,
+            error: None,
+            created_by: created_by.to_string(),
+            created_at: now,
+            updated_at: now,
+            finished_at: None,
+        }
+    }
+
+    // The job's parameters as its kind's type
+    pub fn params<T: serde::de::DeserializeOwned>(&self) -> Result<T, Error> {
+        Ok(serde_json::from_value(self.params.clone())?)
+    }
+
+    fn to_dynamodb_item(&self) -> Result<HashMap<String, AttributeValue>, Error> {
+        let mut item = HashMap::new();
+        item.insert("id".to_string(), AttributeValue::S(self.id.clone()));
+        item.insert(
+            "kind".to_string(),
+            AttributeValue::S(self.kind.as_str().to_string()),
+        );
+        item.insert(
+            "params".to_string(),
+            AttributeValue::S(serde_json::to_string(&self.params)?),
+        );
+        item.insert(
+            "status".to_string(),
+            AttributeValue::S(self.status.as_str().to_string()),
+        );
+        for (name, count) in [
+            ("pages", self.progress.pages),
+            ("scanned", self.progress.scanned),
+            ("matched", self.progress.matched),
+            ("processed", self.progress.processed),
+        ] {
+            item.insert(name.to_string(), AttributeValue::N(count.to_string()));
+        }
+        if let Some(location) = &self.result_location {
+            item.insert(
+                "result_location".to_string(),
+                AttributeValue::S(location.clone()),
+            );
+        }
+        item.insert(
+            "created_by".to_string(),
+            AttributeValue::S(self.created_by.clone()),
+        );
+        item.insert(
+            "created_at".to_string(),
+            AttributeValue::S(self.created_at.to_rfc3339()),
+        );
+        item.insert(
+            "updated_at".to_string(),
+            AttributeValue::S(self.updated_at.to_rfc3339()),
+        );
+        Ok(item)
+    }
+
+    fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let text = |name: &str| item.get(name).and_then(|value| value.as_s().ok());
+        let number = |name: &str| {
+            item.get(name)
+                .and_then(|value| value.as_n().ok())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0)
+        };
+
+        Some(Self {
+            id: text("id")?.clone(),
+            kind: JobKind::parse(text("kind")?)?,
+            params: serde_json::from_str(text("params")?).ok()?,
+            status: JobStatus::parse(text("status")?)?,
+            progress: JobProgress {
+                pages: number("pages"),
+                scanned: number("scanned"),
+                matched: number("matched"),
+                processed: number("processed"),
+            },
+            result_location: text("result_location").cloned(),
+            error: text("error").cloned(),
+            created_by: text("created_by").cloned().unwrap_or_default(),
+            created_at: optional_datetime(item, "created_at").unwrap_or_else(Utc::now),
+            updated_at: optional_datetime(item, "updated_at").unwrap_or_else(Utc::now),
+            finished_at: optional_datetime(item, "finished_at"),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct JobListResponse {
+    pub success: bool,
+    pub jobs: Vec<Job>,
+}
+
+// Jobs and their progress, one item per job
+pub struct JobStore {
+    client: Client,
+    table_name: String,
+}
+
+impl JobStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(JOBS_TABLE_NAME),
+        }
+    }
+
+    pub async fn create(&self, job: &Job) -> Result<(), Error> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(job.to_dynamodb_item()?))
+            .condition_expression("attribute_not_exists(id)")
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<Job>, Error> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        Ok(result.item().and_then(Job::from_dynamodb_item))
+    }
+
+    // Every job, newest first
+    pub async fn list(&self) -> Result<Vec<Job>, Error> {
+        let mut jobs = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            jobs.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(Job::from_dynamodb_item),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        jobs.sort_by_key(|job| Reverse(job.created_at));
+        Ok(jobs)
+    }
+
+    // Add a handled page to the job's progress, completing it after the last
+    // page. Conditional on the job being at that page and not finished, so a
+    // redelivered message isn't counted twice and a cancelled job stays
+    // cancelled. Returns false when the page wasn't recorded.
+    pub async fn record_page(
+        &self,
+        id: &str,
+        page: u32,
+        counts: JobProgress,
+        last: bool,
+        result_location: Option<&str>,
+    ) -> Result<bool, Error> {
+        let status = if last {
+            JobStatus::Completed
+        } else {
+            JobStatus::Running
+        };
+        let mut update = "SET pages = :next, #status = :status, updated_at = :now".to_string();
+        if last {
+            update.push_str(", finished_at = :now");
+        }
+        if result_location.is_some() {
+            update.push_str(", result_location = :location");
+        }
+        update.push_str(" ADD scanned :scanned, matched :matched, processed :processed");
+
+        let mut request = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression(update)
+            .condition_expression("pages = :page AND #status IN (:queued, :running)")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(":page", AttributeValue::N(page.to_string()))
+            .expression_attribute_values(":next", AttributeValue::N((page + 1).to_string()))
+            .expression_attribute_values(":status", AttributeValue::S(status.as_str().to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .expression_attribute_values(
+                ":queued",
+                AttributeValue::S(JobStatus::Queued.as_str().to_string()),
+            )
+            .expression_attribute_values(
+                ":running",
+                AttributeValue::S(JobStatus::Running.as_str().to_string()),
+            )
+            .expression_attribute_values(":scanned", AttributeValue::N(counts.scanned.to_string()))
+            .expression_attribute_values(":matched", AttributeValue::N(counts.matched.to_string()))
+            .expression_attribute_values(
+                ":processed",
+                AttributeValue::N(counts.processed.to_string()),
+            );
+        if let Some(location) = result_location {
+            request = request
+                .expression_attribute_values(":location", AttributeValue::S(location.to_string()));
+        }
+
+        applied(request.send().await)
+    }
+
+    // Stop a job that hasn't finished, with the reason
+    pub async fn mark_failed(&self, id: &str, error: &str) -> Result<bool, Error> {
+        let result = self
+            .finish(id, JobStatus::Failed)
+            .update_expression(
+                "SET #status = :finished, error = :error, updated_at = :now, finished_at = :now",
+            )
+            .expression_attribute_values(":error", AttributeValue::S(error.to_string()))
+            .send()
+            .await;
+
+        applied(result)
+    }
+
+    // Stop a job that hasn't finished. The worker drops its remaining pages;
+    // the one in flight, if any, still completes. Returns false when the job
+    // doesn't exist or already finished.
+    pub async fn cancel(&self, id: &str) -> Result<bool, Error> {
+        let result = self
+            .finish(id, JobStatus::Cancelled)
+            .update_expression("SET #status = :finished, updated_at = :now, finished_at = :now")
+            .send()
+            .await;
+
+        applied(result)
+    }
+
+    // An update moving an unfinished job to `status`, without its expression
+    fn finish(&self, id: &str, status: JobStatus) -> UpdateItemFluentBuilder {
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .condition_expression("#status IN (:queued, :running)")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":finished",
+                AttributeValue::S(status.as_str().to_string()),
+            )
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .expression_attribute_values(
+                ":queued",
+                AttributeValue::S(JobStatus::Queued.as_str().to_string()),
+            )
+            .expression_attribute_values(
+                ":running",
+                AttributeValue::S(JobStatus::Running.as_str().to_string()),
+            )
+    }
+}
+
+// Whether a conditional update was applied. A failed condition means the job
+// moved on in the meantime, e.g. it was cancelled.
+fn applied(result: Result<UpdateItemOutput, SdkError<UpdateItemError>>) -> Result<bool, Error> {
+    match result {
+        Ok(_) => Ok(true),
+        Err(SdkError::ServiceError(err)) if err.err().is_conditional_check_failed_exception() => {
+            Ok(false)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+// A page of a job waiting on the job queue. Pages are scanned in the
+// subscribers table's order, resuming after the last key of the page before.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobPageMessage {
+    #[serde(default)]
+    pub schema_version: u32,
+    pub job_id: String,
+    pub page: u32,
+    // None for the first page
+    pub after_id: Option<String>,
+}
+
+impl JobPageMessage {
+    pub fn first(job_id: &str) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            job_id: job_id.to_string(),
+            page: 0,
+            after_id: None,
+        }
+    }
+
+    // The page after this one, starting after `last_id`
+    pub fn next(&self, last_id: String) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            job_id: self.job_id.clone(),
+            page: self.page + 1,
+            after_id: Some(last_id),
+        }
+    }
+
+    // Where the scan resumes, the subscribers table being keyed by id alone
+    pub fn start_key(&self) -> Option<ItemKey> {
+        self.after_id
+            .as_ref()
+            .map(|id| HashMap::from([("id".to_string(), AttributeValue::S(id.clone()))]))
+    }
+}
+
+impl QueuePayload for JobPageMessage {
+    fn read(_version: SchemaVersion, value: Value) -> Result<Self, serde_json::Error> {
+        read_current(value)
+    }
+}
+
+// Publishes job pages, from JOB_QUEUE_URL
+pub struct JobQueue {
+    client: SqsClient,
+    queue_url: String,
+}
+
+impl JobQueue {
+    pub fn new(client: SqsClient, queue_url: String) -> Self {
+        Self { client, queue_url }
+    }
+
+    pub async fn send(&self, message: &JobPageMessage) -> Result<(), Error> {
+        self.client
+            .send_message()
+            .queue_url(&self.queue_url)
+            .message_body(serde_json::to_string(message)?)
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,440 @@
+use crate::Error;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::info;
+
+// JWKS are refetched after this long, or sooner for a key id they don't have
+pub const DEFAULT_JWKS_CACHE_SECONDS: u64 = 3600;
+
+// A token with an unknown key id refetches the JWKS at most this often, so
+// forged key ids can't make every request call the identity provider
+const MIN_REFRESH_SECONDS: u64 = 60;
+
+const TIMEOUT_SECONDS: u64 = 5;
+
+// Only asymmetric algorithms, so the public keys in the JWKS can never be
+// used as an HMAC secret
+const ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+
+// Who may call the admin API with a token, through the JWT authorizer or
+// directly, from JWT_ISSUER, JWT_AUDIENCE, JWT_JWKS_URL, JWT_REQUIRED_SCOPES and
+// JWT_GROUPS_CLAIM
+#[derive(Debug, Clone)]
+pub struct JwtSettings {
+    pub issuer: String,
+    // Matched against `aud`, or `client_id` for Cognito access tokens, which
+    // have no audience
+    pub audience: String,
+    // Defaults to the issuer's /.well-known/jwks.json
+    pub jwks_url: String,
+    // Every one of them must be granted, space or comma separated
+    pub required_scopes: Vec<String>,
+    // Claim listing the user's groups, `cognito:groups` or `groups` when not set
+    pub groups_claim: Option<String>,
+    pub cache_ttl: Duration,
+}
+
+impl JwtSettings {
+    pub fn from_env() -> Result<Self, Error> {
+        let setting = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        let issuer = setting("JWT_ISSUER").ok_or("JWT_ISSUER is not set")?;
+        let audience = setting("JWT_AUDIENCE").ok_or("JWT_AUDIENCE is not set")?;
+        let jwks_url = env::var("JWT_JWKS_URL")
+            .ok()
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| format!("{}/.well-known/jwks.json", issuer.trim_end_matches('/')));
+
+        Ok(Self {
+            issuer,
+            audience,
+            jwks_url,
+            required_scopes: split_scopes(&env::var("JWT_REQUIRED_SCOPES").unwrap_or_default()),
+            groups_claim: env::var("JWT_GROUPS_CLAIM")
+                .ok()
+                .filter(|claim| !claim.is_empty()),
+            cache_ttl: Duration::from_secs(crate::config::env_or(
+                "JWKS_CACHE_SECONDS",
+                DEFAULT_JWKS_CACHE_SECONDS,
+            )),
+        })
+    }
+}
+
+fn split_scopes(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|scope| !scope.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// The claims admin authentication uses. Issuer and expiry are checked while
+// decoding, the audience right after.
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    // A string or a list of them
+    #[serde(default)]
+    pub aud: Value,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    // Cognito access tokens name the user here
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    // OAuth scopes, space separated, as issued by Auth0, Cognito and most others
+    #[serde(default)]
+    pub scope: Option<String>,
+    // The same as a list, as issued by Okta and Entra ID
+    #[serde(default)]
+    pub scp: Option<Vec<String>>,
+    // Everything else, e.g. the groups claim
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+impl Claims {
+    fn has_audience(&self, audience: &str) -> bool {
+        let in_aud = match &self.aud {
+            Value::String(aud) => aud == audience,
+            Value::Array(auds) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+            _ => false,
+        };
+        in_aud || self.client_id.as_deref() == Some(audience)
+    }
+
+    // Who the token belongs to, as shown in the audit log: the email, else the
+    // username, else the subject
+    pub fn identity(&self) -> &str {
+        self.email
+            .as_deref()
+            .or(self.username.as_deref())
+            .unwrap_or(&self.sub)
+    }
+
+    // The user's groups, from a list or a space separated claim
+    pub fn groups(&self, claim: Option<&str>) -> Vec<String> {
+        let value = match claim {
+            Some(claim) => self.other.get(claim),
+            None => self
+                .other
+                .get("cognito:groups")
+                .or_else(|| self.other.get("groups")),
+        };
+        match value {
+            Some(Value::Array(groups)) => groups
+                .iter()
+                .filter_map(|group| group.as_str())
+                .map(str::to_string)
+                .collect(),
+            Some(Value::String(groups)) => split_scopes(groups),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn scopes(&self) -> Vec<String> {
+        let mut scopes = self.scope.as_deref().map(split_scopes).unwrap_or_default();
+        scopes.extend(self.scp.iter().flatten().cloned());
+        scopes
+    }
+
+    // The first required scope the token wasn't granted
+    pub fn missing_scope<'a>(&self, required: &'a [String]) -> Option<&'a str> {
+        let granted = self.scopes();
+        required
+            .iter()
+            .find(|scope| !granted.contains(scope))
+            .map(String::as_str)
+    }
+}
+
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+// Verifies bearer tokens against the issuer's JWKS, which is kept for the life
+// of the Lambda container
+pub struct JwtVerifier {
+    settings: JwtSettings,
+    client: reqwest::Client,
+    jwks: RwLock<Option<CachedJwks>>,
+}
+
+impl JwtVerifier {
+    pub fn new(settings: JwtSettings) -> Result<Self, Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(TIMEOUT_SECONDS))
+            .build()?;
+
+        Ok(Self {
+            settings,
+            client,
+            jwks: RwLock::new(None),
+        })
+    }
+
+    pub fn settings(&self) -> &JwtSettings {
+        &self.settings
+    }
+
+    pub fn groups(&self, claims: &Claims) -> Vec<String> {
+        claims.groups(self.settings.groups_claim.as_deref())
+    }
+
+    // The token's claims, once its signature, issuer, audience and expiry check out
+    pub async fn verify(&self, token: &str) -> Result<Claims, Error> {
+        let header = decode_header(token)?;
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(format!("Algorithm {:?} is not allowed", header.alg).into());
+        }
+        let kid = header.kid.ok_or("Token has no key id")?;
+        let key = self.key(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.settings.issuer]);
+        validation.validate_aud = false;
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+
+        let claims = decode::<Claims>(token, &key, &validation)?.claims;
+        if !claims.has_audience(&self.settings.audience) {
+            return Err("Token is for another audience".into());
+        }
+        Ok(claims)
+    }
+
+    async fn key(&self, kid: &str) -> Result<DecodingKey, Error> {
+        {
+            let cached = self.jwks.read().await;
+            if let Some(cached) = cached.as_ref() {
+                let fresh = cached.fetched_at.elapsed() < self.settings.cache_ttl;
+                match cached.keys.find(kid) {
+                    Some(jwk) if fresh => return Ok(DecodingKey::from_jwk(jwk)?),
+                    None if cached.fetched_at.elapsed()
+                        < Duration::from_secs(MIN_REFRESH_SECONDS) =>
+                    {
+                        return Err(format!("Unknown key id {kid}").into());
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let keys: JwkSet = self
+            .client
+            .get(&self.settings.jwks_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        info!(
+            "Fetched {} keys from {}",
+            keys.keys.len(),
+            self.settings.jwks_url
+        );
+
+        let key = keys
+            .find(kid)
+            .map(DecodingKey::from_jwk)
+            .transpose()?
+            .ok_or_else(|| format!("Unknown key id {kid}"));
+        *self.jwks.write().await = Some(CachedJwks {
+            keys,
+            fetched_at: Instant::now(),
+        });
+        Ok(key?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use p256::ecdsa::SigningKey;
+    use p256::pkcs8::{EncodePrivateKey, LineEnding};
+    use serde_json::json;
+
+    const ISSUER: &str = "https://auth.example.com";
+    const AUDIENCE: &str = "newsletter-admin";
+    const KID: &str = "test-key";
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32].into()).unwrap()
+    }
+
+    fn jwks(key: &SigningKey) -> JwkSet {
+        let point = key.verifying_key().to_encoded_point(false);
+        serde_json::from_value(json!({
+            "keys": [{
+                "kty": "EC",
+                "crv": "P-256",
+                "kid": KID,
+                "alg": "ES256",
+                "use": "sig",
+                "x": URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+                "y": URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+            }]
+        }))
+        .unwrap()
+    }
+
+    // A verifier with the test key already fetched, so nothing goes out
+    async fn verifier() -> JwtVerifier {
+        let verifier = JwtVerifier::new(JwtSettings {
+            issuer: ISSUER.to_string(),
+            audience: AUDIENCE.to_string(),
+            jwks_url: "http://127.0.0.1:9/jwks.json".to_string(),
+            required_scopes: Vec::new(),
+            groups_claim: None,
+            cache_ttl: Duration::from_secs(DEFAULT_JWKS_CACHE_SECONDS),
+        })
+        .unwrap();
+        *verifier.jwks.write().await = Some(CachedJwks {
+            keys: jwks(&signing_key()),
+            fetched_at: Instant::now(),
+        });
+        verifier
+    }
+
+    fn token(kid: Option<&str>, claims: Value) -> String {
+        let pem = signing_key().to_pkcs8_pem(LineEnding::LF).unwrap();
+        let key = EncodingKey::from_ec_pem(pem.as_bytes()).unwrap();
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = kid.map(str::to_string);
+        encode(&header, &claims, &key).unwrap()
+    }
+
+    fn claims(overrides: Value) -> Value {
+        let mut claims = json!({
+            "sub": "user-1",
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "exp": chrono::Utc::now().timestamp() + 600,
+            "email": "admin@example.com",
+        });
+        for (name, value) in overrides.as_object().unwrap() {
+            match value {
+                Value::Null => claims.as_object_mut().unwrap().remove(name),
+                value => claims
+                    .as_object_mut()
+                    .unwrap()
+                    .insert(name.clone(), value.clone()),
+            };
+        }
+        claims
+    }
+
+    #[tokio::test]
+    async fn accepts_a_valid_token() {
+        let claims = verifier()
+            .await
+            .verify(&token(Some(KID), claims(json!({}))))
+            .await
+            .unwrap();
+        assert_eq!(claims.identity(), "admin@example.com");
+    }
+
+    #[tokio::test]
+    async fn accepts_the_audience_in_a_list_or_as_client_id() {
+        let verifier = verifier().await;
+        let listed = token(Some(KID), claims(json!({ "aud": ["other", AUDIENCE] })));
+        assert!(verifier.verify(&listed).await.is_ok());
+
+        let cognito = token(
+            Some(KID),
+            claims(json!({ "aud": null, "client_id": AUDIENCE })),
+        );
+        assert!(verifier.verify(&cognito).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_another_audience() {
+        let token = token(Some(KID), claims(json!({ "aud": "another-app" })));
+        assert!(verifier().await.verify(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_another_issuer() {
+        let token = token(
+            Some(KID),
+            claims(json!({ "iss": "https://evil.example.com" })),
+        );
+        assert!(verifier().await.verify(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_an_expired_token() {
+        let expired = chrono::Utc::now().timestamp() - 3600;
+        let token = token(Some(KID), claims(json!({ "exp": expired })));
+        assert!(verifier().await.verify(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_token_without_expiry() {
+        let token = token(Some(KID), claims(json!({ "exp": null })));
+        assert!(verifier().await.verify(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_an_unknown_key_id() {
+        // The JWKS was just fetched, so it isn't fetched again
+        let token = token(Some("rotated-away"), claims(json!({})));
+        let error = verifier().await.verify(&token).await.unwrap_err();
+        assert!(error.to_string().contains("Unknown key id"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn rejects_a_token_without_key_id() {
+        let token = token(None, claims(json!({})));
+        assert!(verifier().await.verify(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_symmetric_algorithms() {
+        let header = Header {
+            kid: Some(KID.to_string()),
+            ..Header::new(Algorithm::HS256)
+        };
+        let token = encode(
+            &header,
+            &claims(json!({})),
+            &EncodingKey::from_secret(b"public key bytes"),
+        )
+        .unwrap();
+        assert!(verifier().await.verify(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_token_signed_by_another_key() {
+        let other = SigningKey::from_bytes(&[8u8; 32].into()).unwrap();
+        let pem = other.to_pkcs8_pem(LineEnding::LF).unwrap();
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(KID.to_string());
+        let token = encode(
+            &header,
+            &claims(json!({})),
+            &EncodingKey::from_ec_pem(pem.as_bytes()).unwrap(),
+        )
+        .unwrap();
+        assert!(verifier().await.verify(&token).await.is_err());
+    }
+}
@@ -1,20 +1,194 @@
+use attribution::Attribution;
 use aws_sdk_dynamodb::types::AttributeValue;
 use chrono::{DateTime, Utc};
+use digest::Frequency;
+use i18n::Locale;
+use quarantine::QuarantineReason;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use stripe::Tier;
+use utoipa::ToSchema;
 use uuid::Uuid;
+use verification::Verdict;
+
+pub mod address;
+pub mod admin;
+pub mod archive;
+pub mod attachments;
+pub mod attribution;
+pub mod audit;
+pub mod backup;
+pub mod batch;
+pub mod campaign;
+pub mod clients;
+pub mod config;
+pub mod content;
+pub mod counters;
+pub mod cursor;
+pub mod dedup;
+pub mod delivery;
+pub mod digest;
+pub mod domain_rules;
+pub mod domains;
+pub mod dynamo;
+pub mod email;
+pub mod email_queue;
+pub mod embed;
+pub mod esp;
+pub mod events;
+pub mod export;
+pub mod fields;
+pub mod health;
+pub mod history;
+pub mod i18n;
+pub mod idempotency;
+pub mod import;
+pub mod ingest;
+pub mod input;
+pub mod invites;
+pub mod jobs;
+pub mod jwt;
+pub mod links;
+pub mod logging;
+pub mod metrics;
+pub mod notify;
+pub mod onboarding;
+pub mod openapi;
+pub mod opt_out;
+pub mod pii;
+pub mod preview;
+pub mod processed;
+pub mod pseudonym;
+pub mod pulse;
+pub mod quarantine;
+pub mod queue;
+pub mod quota;
+pub mod rbac;
+pub mod redrive;
+pub mod referral;
+pub mod region;
+pub mod repository;
+pub mod schedule;
+pub mod search;
+pub mod segment;
+pub mod senders;
+pub mod ses;
+pub mod signing;
+pub mod spam;
+pub mod stripe;
+pub mod sync;
+pub mod tagging;
+pub mod template;
+pub mod templates;
+pub mod verification;
+pub mod versioning;
+pub mod workflow;
 
 // Configuration constants
 pub const TABLE_NAME: &str = "newsletter_subscribers";
+pub const EMAIL_HASH_INDEX: &str = "email-hash-index";
+pub const REFERRAL_CODE_INDEX: &str = "referral-code-index";
+pub const STRIPE_CUSTOMER_INDEX: &str = "stripe-customer-index";
+pub const EMAIL_DOMAIN_INDEX: &str = "email-domain-index";
+pub const EMAIL_PREFIX_INDEX: &str = "email-prefix-index";
+pub const UPDATED_AT_INDEX: &str = "updated-at-index";
+pub const ENQUEUE_PENDING_INDEX: &str = "enqueue-pending-index";
+pub const CAMPAIGNS_TABLE_NAME: &str = "newsletter_campaigns";
+pub const EVENTS_TABLE_NAME: &str = "newsletter_events";
+pub const HISTORY_TABLE_NAME: &str = "newsletter_subscriber_history";
+pub const IDEMPOTENCY_TABLE_NAME: &str = "newsletter_idempotency_keys";
+pub const QUOTAS_TABLE_NAME: &str = "newsletter_api_quotas";
+pub const DOMAIN_STATS_TABLE_NAME: &str = "newsletter_domain_stats";
+pub const DOMAIN_RULES_TABLE_NAME: &str = "newsletter_domain_rules";
+pub const INVITE_CODES_TABLE_NAME: &str = "newsletter_invite_codes";
+pub const DIGEST_ITEMS_TABLE_NAME: &str = "newsletter_digest_items";
+pub const CAMPAIGN_SCHEDULE_TABLE_NAME: &str = "newsletter_campaign_schedule";
+pub const CAMPAIGN_RECIPIENTS_TABLE_NAME: &str = "newsletter_campaign_recipients";
+pub const SENDER_IDENTITIES_TABLE_NAME: &str = "newsletter_sender_identities";
+pub const TEMPLATES_TABLE_NAME: &str = "newsletter_templates";
+pub const TEMPLATE_VERSIONS_TABLE_NAME: &str = "newsletter_template_versions";
+pub const AUDIT_LOG_TABLE_NAME: &str = "newsletter_admin_audit";
+pub const COUNTERS_TABLE_NAME: &str = "newsletter_subscriber_counters";
+pub const PROCESSED_MESSAGES_TABLE_NAME: &str = "newsletter_processed_messages";
+pub const JOBS_TABLE_NAME: &str = "newsletter_jobs";
+
+// An interaction counts half as much towards the engagement score after this many days
+pub const ENGAGEMENT_HALF_LIFE_DAYS: f64 = 30.0;
 
-#[derive(Debug, Serialize, Deserialize)]
+// Shared error type, compatible with the lambda_http and lambda_runtime errors
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct Subscriber {
     pub id: String,
     pub email: String,
+    // Cleared by a global opt-out, which stops every list
     pub active: bool,
+    // Lists the subscriber left while staying subscribed to the rest, see
+    // `campaign::Campaign::list`
+    pub unsubscribed_lists: BTreeSet<String>,
     pub validated: bool,
+    pub suppressed: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub reminder_sent_at: Option<DateTime<Utc>>,
+    // Confirmation reminders sent so far, capped by `TokenPolicy::max_reminders`
+    pub reminder_count: u32,
+    pub reengagement_sent_at: Option<DateTime<Utc>>,
+    pub engagement_score: f64,
+    pub last_engaged_at: Option<DateTime<Utc>>,
+    // Custom fields validated against `fields::FieldSchema`
+    pub fields: HashMap<String, String>,
+    // Language for emails sent to this subscriber
+    pub locale: Locale,
+    // Campaigns as they are sent, or bundled into a weekly digest
+    pub frequency: Frequency,
+    // Start of the next digest: the last one sent, or the switch to weekly
+    pub digest_sent_at: Option<DateTime<Utc>>,
+    // IANA timezone, e.g. "Europe/Madrid", used for campaigns sent at a local time
+    pub timezone: Option<String>,
+    // Where the signup came from
+    pub attribution: Attribution,
+    // How consent was given by a subscriber an operator added directly, e.g.
+    // "Signed up on paper at the March meetup"
+    pub consent_note: Option<String>,
+    // Assigned on confirmation and shared as ?ref=<code>
+    pub referral_code: Option<String>,
+    // Code of the subscriber who referred this one, credited on confirmation
+    pub referred_by: Option<String>,
+    pub referral_count: u32,
+    // Paid tier, kept in sync by the Stripe webhook
+    pub tier: Tier,
+    pub stripe_customer_id: Option<String>,
+    // Latest email verification result and when it was checked
+    pub verification: Option<Verdict>,
+    pub verified_at: Option<DateTime<Utc>>,
+    // Set on suspicious signups, which get no email until an admin approves them
+    pub quarantine_reason: Option<QuarantineReason>,
+    // Set when soft-deleted; the record is hard-deleted after the retention window
+    pub deleted_at: Option<DateTime<Utc>>,
+    // When a signup's validation message couldn't be queued, cleared once the
+    // requeue job has queued it
+    pub validation_enqueue_pending: Option<DateTime<Utc>>,
+    // When an unsubscribed subscriber signed up again, until they confirm and
+    // are active again
+    pub reactivation_requested_at: Option<DateTime<Utc>>,
+    // Pending confirmation token, never exposed through the API
+    #[serde(skip)]
+    pub validation_token: Option<String>,
+    #[serde(skip)]
+    pub token_expiration: Option<DateTime<Utc>>,
+    // Written as the epoch-seconds `token_ttl` attribute, the table's TTL, so
+    // DynamoDB removes records that were never confirmed
+    #[serde(skip)]
+    pub token_ttl: Option<DateTime<Utc>>,
+    // Bumped on every write; updates are conditional on the version they read
+    pub version: u64,
+    // Attributes this build doesn't know, e.g. ones added by a newer deploy,
+    // written back untouched so a read-modify-write never drops them
+    #[serde(skip)]
+    pub extra_attributes: HashMap<String, AttributeValue>,
 }
 
 impl Subscriber {
@@ -24,71 +198,557 @@ impl Subscriber {
             id: Uuid::new_v4().to_string(),
             email,
             active: true,
+            unsubscribed_lists: BTreeSet::new(),
             validated: false,
+            suppressed: false,
             created_at: now,
             updated_at: now,
+            reminder_sent_at: None,
+            reminder_count: 0,
+            reengagement_sent_at: None,
+            engagement_score: 0.0,
+            last_engaged_at: None,
+            fields: HashMap::new(),
+            locale: Locale::default(),
+            frequency: Frequency::default(),
+            digest_sent_at: None,
+            timezone: None,
+            attribution: Attribution::default(),
+            consent_note: None,
+            referral_code: None,
+            referred_by: None,
+            referral_count: 0,
+            tier: Tier::default(),
+            stripe_customer_id: None,
+            verification: None,
+            verified_at: None,
+            quarantine_reason: None,
+            deleted_at: None,
+            validation_enqueue_pending: None,
+            reactivation_requested_at: None,
+            validation_token: None,
+            token_expiration: None,
+            token_ttl: None,
+            version: 0,
+            extra_attributes: HashMap::new(),
         }
     }
 
-    pub fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
-        let mut item = HashMap::new();
+    // Whether this subscriber should receive newsletter emails
+    pub fn is_mailable(&self) -> bool {
+        self.active
+            && self.validated
+            && !self.suppressed
+            && !self.is_quarantined()
+            && !self.is_deleted()
+    }
 
-        item.insert("id".to_string(), AttributeValue::S(self.id.clone()));
-        item.insert("email".to_string(), AttributeValue::S(self.email.clone()));
-        item.insert("active".to_string(), AttributeValue::Bool(self.active));
-        item.insert(
-            "validated".to_string(),
-            AttributeValue::Bool(self.validated),
-        );
-        item.insert(
-            "created_at".to_string(),
-            AttributeValue::S(self.created_at.to_rfc3339()),
-        );
-        item.insert(
-            "updated_at".to_string(),
-            AttributeValue::S(self.updated_at.to_rfc3339()),
-        );
+    // Mailable and still on the list, for a campaign sent to one. Global
+    // opt-outs and suppressions apply to every list.
+    pub fn is_mailable_on(&self, list: Option<&str>) -> bool {
+        self.is_mailable() && list.is_none_or(|list| !self.unsubscribed_lists.contains(list))
+    }
+
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantine_reason.is_some()
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    // Engagement score decayed to the given time, so old interactions weigh less
+    pub fn engagement_score_at(&self, now: DateTime<Utc>) -> f64 {
+        match self.last_engaged_at {
+            Some(last_engaged_at) => {
+                let days = (now - last_engaged_at).num_seconds().max(0) as f64 / 86_400.0;
+                self.engagement_score * 0.5_f64.powf(days / ENGAGEMENT_HALF_LIFE_DAYS)
+            }
+            None => 0.0,
+        }
+    }
+
+    pub fn to_dynamodb_item(&self) -> Result<HashMap<String, AttributeValue>, Error> {
+        // DynamoDB numbers can't be NaN or infinite, and would fail the write
+        if !self.engagement_score.is_finite() {
+            return Err(format!("Subscriber {} has an invalid engagement score", self.id).into());
+        }
 
-        item
+        let mut item = self.extra_attributes.clone();
+        item.extend(dynamo::to_item(&SubscriberItem::from(self))?);
+        Ok(item)
     }
 
     pub fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
-        let id = item.get("id")?.as_s().ok()?;
-        let email = item.get("email")?.as_s().ok()?;
-        let active = item.get("active")?.as_bool().ok()?;
-        let validated = item.get("validated")?.as_bool().ok()?;
-        let created_at = DateTime::parse_from_rfc3339(item.get("created_at")?.as_s().ok()?)
-            .ok()?
-            .with_timezone(&Utc);
-        let updated_at = DateTime::parse_from_rfc3339(item.get("updated_at")?.as_s().ok()?)
-            .ok()?
-            .with_timezone(&Utc);
-
-        Some(Self {
-            id: id.clone(),
-            email: email.clone(),
-            active: *active,
-            validated: *validated,
-            created_at,
-            updated_at,
-        })
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
+        let mut subscriber = Self::from(dynamo::from_item::<SubscriberItem>(item).ok()?);
+        subscriber.extra_attributes = item
+            .iter()
+            .filter(|(name, _)| !SUBSCRIBER_ATTRIBUTES.contains(&name.as_str()))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        Some(subscriber)
+    }
+}
+
+// Attributes of `SubscriberItem`. Anything else on a record is carried in
+// `extra_attributes`, so a field added there has to be listed here too, or
+// clearing it would leave the old value behind.
+const SUBSCRIBER_ATTRIBUTES: &[&str] = &[
+    "id",
+    "email",
+    "email_hash",
+    "email_search",
+    "email_domain",
+    "email_prefix",
+    "active",
+    "unsubscribed_lists",
+    "validated",
+    "suppressed",
+    "created_at",
+    "updated_at",
+    "sync_partition",
+    "sync_key",
+    "reminder_sent_at",
+    "reminder_count",
+    "reengagement_sent_at",
+    "engagement_score",
+    "last_engaged_at",
+    "locale",
+    "frequency",
+    "digest_sent_at",
+    "timezone",
+    "attribution",
+    "consent_note",
+    "referral_code",
+    "referred_by",
+    "referral_count",
+    "tier",
+    "stripe_customer_id",
+    "verification",
+    "verified_at",
+    "quarantine_reason",
+    "deleted_at",
+    "validation_enqueue_pending",
+    "enqueue_pending",
+    "reactivation_requested_at",
+    "validation_token",
+    "token_expiration",
+    "token_ttl",
+    "version",
+    "fields",
+    // Set and removed by `pii` around reads and writes
+    "pii_key",
+    "email_encrypted",
+];
+
+// A subscriber as stored in the subscribers table, with the index keys derived
+// from it. Attributes missing from older records take their defaults.
+#[derive(Serialize, Deserialize)]
+struct SubscriberItem {
+    id: String,
+    email: String,
+    // Key of the email hash index that finds subscribers by address
+    #[serde(default)]
+    email_hash: String,
+    // Keys of the admin search indexes, derived from the email on every write
+    #[serde(default)]
+    email_search: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email_domain: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email_prefix: Option<String>,
+    active: bool,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    unsubscribed_lists: BTreeSet<String>,
+    validated: bool,
+    // Records written before suppression existed don't have the attribute
+    #[serde(default)]
+    suppressed: bool,
+    #[serde(with = "dynamo::rfc3339")]
+    created_at: DateTime<Utc>,
+    #[serde(with = "dynamo::rfc3339")]
+    updated_at: DateTime<Utc>,
+    // Keys of the updated-at index that external systems sync changes from
+    #[serde(default)]
+    sync_partition: String,
+    #[serde(default)]
+    sync_key: String,
+    #[serde(
+        default,
+        with = "dynamo::rfc3339_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    reminder_sent_at: Option<DateTime<Utc>>,
+    // Records from before the count was kept had at most one reminder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reminder_count: Option<u32>,
+    #[serde(
+        default,
+        with = "dynamo::rfc3339_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    reengagement_sent_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    engagement_score: f64,
+    #[serde(
+        default,
+        with = "dynamo::rfc3339_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    last_engaged_at: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "dynamo::or_default")]
+    locale: Locale,
+    #[serde(default, deserialize_with = "dynamo::or_default")]
+    frequency: Frequency,
+    #[serde(
+        default,
+        with = "dynamo::rfc3339_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    digest_sent_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    attribution: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    consent_note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    referral_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    referred_by: Option<String>,
+    #[serde(default)]
+    referral_count: u32,
+    #[serde(default, deserialize_with = "dynamo::or_default")]
+    tier: Tier,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stripe_customer_id: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "dynamo::or_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    verification: Option<Verdict>,
+    #[serde(
+        default,
+        with = "dynamo::rfc3339_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    verified_at: Option<DateTime<Utc>>,
+    #[serde(
+        default,
+        deserialize_with = "dynamo::or_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    quarantine_reason: Option<QuarantineReason>,
+    #[serde(
+        default,
+        with = "dynamo::rfc3339_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    deleted_at: Option<DateTime<Utc>>,
+    #[serde(
+        default,
+        with = "dynamo::rfc3339_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    validation_enqueue_pending: Option<DateTime<Utc>>,
+    // Key of the sparse index the requeue job reads, only set while a
+    // validation message is waiting to be queued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    enqueue_pending: Option<String>,
+    #[serde(
+        default,
+        with = "dynamo::rfc3339_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    reactivation_requested_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    validation_token: Option<String>,
+    #[serde(
+        default,
+        with = "dynamo::rfc3339_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    token_expiration: Option<DateTime<Utc>>,
+    // The table's TTL attribute, so it has to be a number
+    #[serde(
+        default,
+        with = "dynamo::epoch_seconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    token_ttl: Option<DateTime<Utc>>,
+    // Records written before versioning start at version 0
+    #[serde(default)]
+    version: u64,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    fields: HashMap<String, String>,
+}
+
+impl From<&Subscriber> for SubscriberItem {
+    fn from(subscriber: &Subscriber) -> Self {
+        Self {
+            id: subscriber.id.clone(),
+            email: subscriber.email.clone(),
+            email_hash: pii::email_hash(&subscriber.email),
+            email_search: search::search_email(&subscriber.email),
+            email_domain: search::email_domain(&subscriber.email),
+            email_prefix: search::prefix_bucket(&subscriber.email),
+            active: subscriber.active,
+            unsubscribed_lists: subscriber.unsubscribed_lists.clone(),
+            validated: subscriber.validated,
+            suppressed: subscriber.suppressed,
+            created_at: subscriber.created_at,
+            updated_at: subscriber.updated_at,
+            sync_partition: sync::SYNC_PARTITION.to_string(),
+            sync_key: sync::sync_key(subscriber.updated_at, &subscriber.id),
+            reminder_sent_at: subscriber.reminder_sent_at,
+            reminder_count: Some(subscriber.reminder_count),
+            reengagement_sent_at: subscriber.reengagement_sent_at,
+            engagement_score: subscriber.engagement_score,
+            last_engaged_at: subscriber.last_engaged_at,
+            locale: subscriber.locale,
+            frequency: subscriber.frequency,
+            digest_sent_at: subscriber.digest_sent_at,
+            timezone: subscriber.timezone.clone(),
+            attribution: subscriber.attribution.to_map(),
+            consent_note: subscriber.consent_note.clone(),
+            referral_code: subscriber.referral_code.clone(),
+            referred_by: subscriber.referred_by.clone(),
+            referral_count: subscriber.referral_count,
+            tier: subscriber.tier,
+            stripe_customer_id: subscriber.stripe_customer_id.clone(),
+            verification: subscriber.verification,
+            verified_at: subscriber.verified_at,
+            quarantine_reason: subscriber.quarantine_reason,
+            deleted_at: subscriber.deleted_at,
+            validation_enqueue_pending: subscriber.validation_enqueue_pending,
+            enqueue_pending: subscriber
+                .validation_enqueue_pending
+                .map(|_| queue::ENQUEUE_PENDING_PARTITION.to_string()),
+            reactivation_requested_at: subscriber.reactivation_requested_at,
+            validation_token: subscriber.validation_token.clone(),
+            token_expiration: subscriber.token_expiration,
+            token_ttl: subscriber.token_ttl,
+            version: subscriber.version,
+            fields: subscriber.fields.clone(),
+        }
+    }
+}
+
+impl From<SubscriberItem> for Subscriber {
+    fn from(item: SubscriberItem) -> Self {
+        Self {
+            reminder_count: item
+                .reminder_count
+                .unwrap_or(u32::from(item.reminder_sent_at.is_some())),
+            id: item.id,
+            email: item.email,
+            active: item.active,
+            unsubscribed_lists: item.unsubscribed_lists,
+            validated: item.validated,
+            suppressed: item.suppressed,
+            created_at: item.created_at,
+            updated_at: item.updated_at,
+            reminder_sent_at: item.reminder_sent_at,
+            reengagement_sent_at: item.reengagement_sent_at,
+            engagement_score: item.engagement_score,
+            last_engaged_at: item.last_engaged_at,
+            fields: item.fields,
+            locale: item.locale,
+            frequency: item.frequency,
+            digest_sent_at: item.digest_sent_at,
+            timezone: item.timezone,
+            attribution: Attribution::from_map(item.attribution),
+            consent_note: item.consent_note,
+            referral_code: item.referral_code,
+            referred_by: item.referred_by,
+            referral_count: item.referral_count,
+            tier: item.tier,
+            stripe_customer_id: item.stripe_customer_id,
+            verification: item.verification,
+            verified_at: item.verified_at,
+            quarantine_reason: item.quarantine_reason,
+            deleted_at: item.deleted_at,
+            validation_enqueue_pending: item.validation_enqueue_pending,
+            reactivation_requested_at: item.reactivation_requested_at,
+            validation_token: item.validation_token,
+            token_expiration: item.token_expiration,
+            token_ttl: item.token_ttl,
+            version: item.version,
+            extra_attributes: HashMap::new(),
+        }
+    }
+}
+
+// Parse an optional RFC 3339 timestamp attribute
+pub(crate) fn optional_datetime(
+    item: &HashMap<String, AttributeValue>,
+    name: &str,
+) -> Option<DateTime<Utc>> {
+    item.get(name)
+        .and_then(|value| value.as_s().ok())
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .map(|value| value.with_timezone(&Utc))
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct SubscribeRequest {
     pub email: String,
+    // Hidden honeypot field, left empty by humans
+    #[serde(default)]
+    pub hp: Option<String>,
+    // When the form was rendered, in milliseconds since the epoch
+    #[serde(default)]
+    pub rendered_at: Option<i64>,
+    // Preferred language, e.g. "es", overriding the Accept-Language header
+    #[serde(default)]
+    pub locale: Option<String>,
+    // Signup attribution, e.g. "footer" or the UTM parameters of the landing page
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub utm_source: Option<String>,
+    #[serde(default)]
+    pub utm_medium: Option<String>,
+    #[serde(default)]
+    pub utm_campaign: Option<String>,
+    // Page the visitor came from, defaulting to the Referer header
+    #[serde(default)]
+    pub referrer: Option<String>,
+    // Referral code from a friend's ?ref= link
+    #[serde(default, alias = "ref")]
+    pub referral_code: Option<String>,
+    // "weekly" to get a digest instead of every campaign as it is sent
+    #[serde(default)]
+    pub frequency: Option<String>,
+    // IANA timezone such as "America/New_York", e.g. from the browser
+    #[serde(default)]
+    pub timezone: Option<String>,
+    // Required while the newsletter is invite-only
+    #[serde(default, alias = "invite")]
+    pub invite_code: Option<String>,
+    // Custom fields such as first_name, checked against the configured schema
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub fields: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Message on the validation queue, sent by subscribe and handled by validate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationMessage {
+    // Missing from messages written before payloads were versioned
+    #[serde(default)]
+    pub schema_version: u32,
+    pub action: String,
+    pub email: String,
+    pub subscriber_id: String,
+}
+
+impl queue::QueuePayload for ValidationMessage {
+    fn read(
+        _version: queue::SchemaVersion,
+        value: serde_json::Value,
+    ) -> Result<Self, serde_json::Error> {
+        queue::read_current(value)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UnsubscribeRequest {
     pub email: String,
+    // Leave only this list, e.g. "product-updates", instead of opting out of
+    // everything
+    #[serde(default)]
+    pub list: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ApiResponse {
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ApiResponse<T = ()> {
     pub success: bool,
     pub message: String,
+    // What the endpoint returns besides the message, left out when there's nothing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub data: Option<T>,
+}
+
+// What a successful subscribe did, so frontends needn't go by the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscribeStatus {
+    // A confirmation email is on its way
+    PendingConfirmation,
+    // Confirmed on signup, with double opt-in off
+    Subscribed,
+    AlreadySubscribed,
+    // An unsubscribed address signed up again and gets a confirmation email,
+    // after which it is mailed again
+    ReactivationPending,
+}
+
+// Data of a successful subscribe
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SubscribeData {
+    // Only for a new subscriber, so an address can't be used to look up
+    // someone else's id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscriber_id: Option<String>,
+    pub status: SubscribeStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfirmStatus {
+    Confirmed,
+    // The link was used before, e.g. clicked twice
+    AlreadyConfirmed,
+}
+
+// Data of a successful confirmation
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ConfirmData {
+    pub subscriber_id: String,
+    pub status: ConfirmStatus,
+}
+
+// Base URL of the frontend, without a trailing slash
+pub fn frontend_url() -> String {
+    env::var("FRONTEND_URL")
+        .unwrap_or_else(|_| "https://yourfrontend.com".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+// Base URL of the API Gateway stage, without a trailing slash
+pub fn api_url() -> String {
+    env::var("API_URL")
+        .unwrap_or_else(|_| "https://api.yourfrontend.com/v1".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+// Link included in validation emails, pointing at the frontend confirmation page
+pub fn validation_url(subscriber_id: &str, token: &str) -> String {
+    format!(
+        "{}/validate?id={}&token={}",
+        frontend_url(),
+        subscriber_id,
+        token
+    )
+}
+
+// Helper function to create a JSON response from any serializable body
+pub fn create_json_response<T: Serialize>(
+    status_code: u16,
+    body: &T,
+) -> lambda_http::Response<lambda_http::Body> {
+    lambda_http::Response::builder()
+        .status(status_code)
+        .header("Content-Type", "application/json")
+        .body(lambda_http::Body::from(
+            serde_json::to_string(body).unwrap(),
+        ))
+        .unwrap()
 }
 
 // Helper function to create an API response
@@ -104,3 +764,206 @@ pub fn create_response(
         ))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_790_000_000 + seconds, 0).unwrap()
+    }
+
+    fn string(value: &str) -> AttributeValue {
+        AttributeValue::S(value.to_string())
+    }
+
+    // Every field set, so a field the conversions forget shows up
+    fn full_subscriber() -> Subscriber {
+        let mut subscriber = Subscriber::new("Ada@Example.com".to_string());
+        subscriber.validated = true;
+        subscriber.suppressed = true;
+        subscriber.unsubscribed_lists = BTreeSet::from(["product".to_string()]);
+        subscriber.created_at = at(0);
+        subscriber.updated_at = at(60);
+        subscriber.reminder_sent_at = Some(at(1));
+        subscriber.reminder_count = 2;
+        subscriber.reengagement_sent_at = Some(at(2));
+        subscriber.engagement_score = 3.5;
+        subscriber.last_engaged_at = Some(at(3));
+        subscriber.fields = HashMap::from([("first_name".to_string(), "Ada".to_string())]);
+        subscriber.locale = Locale::Es;
+        subscriber.frequency = Frequency::Weekly;
+        subscriber.digest_sent_at = Some(at(4));
+        subscriber.timezone = Some("Europe/Madrid".to_string());
+        subscriber.attribution = Attribution::from_map(BTreeMap::from([
+            ("source".to_string(), "blog".to_string()),
+            ("utm_campaign".to_string(), "launch".to_string()),
+        ]));
+        subscriber.consent_note = Some("Signed up on paper".to_string());
+        subscriber.referral_code = Some("7F3A9C2B".to_string());
+        subscriber.referred_by = Some("1A2B3C4D".to_string());
+        subscriber.referral_count = 4;
+        subscriber.tier = Tier::Premium;
+        subscriber.stripe_customer_id = Some("cus_123".to_string());
+        subscriber.verification = Some(Verdict::Risky);
+        subscriber.verified_at = Some(at(5));
+        subscriber.quarantine_reason = Some(QuarantineReason::SignupBurst);
+        subscriber.deleted_at = Some(at(6));
+        subscriber.validation_enqueue_pending = Some(at(7));
+        subscriber.reactivation_requested_at = Some(at(8));
+        subscriber.validation_token = Some("token".to_string());
+        subscriber.token_expiration = Some(at(9));
+        subscriber.token_ttl = Some(at(10));
+        subscriber.version = 7;
+        subscriber
+    }
+
+    #[test]
+    fn subscriber_round_trips() {
+        let subscriber = full_subscriber();
+        let item = subscriber.to_dynamodb_item().unwrap();
+        let read = Subscriber::from_dynamodb_item(&item).unwrap();
+
+        // Everything the API exposes, then the fields it skips
+        assert_eq!(
+            serde_json::to_value(&read).unwrap(),
+            serde_json::to_value(&subscriber).unwrap()
+        );
+        assert_eq!(read.validation_token, subscriber.validation_token);
+        assert_eq!(read.token_expiration, subscriber.token_expiration);
+        assert_eq!(read.token_ttl, subscriber.token_ttl);
+        assert!(read.extra_attributes.is_empty());
+    }
+
+    #[test]
+    fn writes_the_index_keys() {
+        let subscriber = full_subscriber();
+        let item = subscriber.to_dynamodb_item().unwrap();
+
+        assert_eq!(
+            item["email_hash"],
+            string(&pii::email_hash(&subscriber.email))
+        );
+        assert_eq!(item["sync_partition"], string(sync::SYNC_PARTITION));
+        assert_eq!(
+            item["sync_key"],
+            string(&sync::sync_key(subscriber.updated_at, &subscriber.id))
+        );
+        assert_eq!(
+            item["enqueue_pending"],
+            string(queue::ENQUEUE_PENDING_PARTITION)
+        );
+        assert!(
+            item.keys()
+                .all(|name| SUBSCRIBER_ATTRIBUTES.contains(&name.as_str()))
+        );
+    }
+
+    #[test]
+    fn token_ttl_is_an_epoch_seconds_number() {
+        let item = full_subscriber().to_dynamodb_item().unwrap();
+
+        assert_eq!(
+            item["token_ttl"],
+            AttributeValue::N(at(10).timestamp().to_string())
+        );
+        assert_eq!(item["token_expiration"], string(&at(9).to_rfc3339()));
+    }
+
+    #[test]
+    fn unset_options_are_left_out() {
+        let item = Subscriber::new("ada@example.com".to_string())
+            .to_dynamodb_item()
+            .unwrap();
+
+        for name in [
+            "token_ttl",
+            "deleted_at",
+            "verification",
+            "enqueue_pending",
+            "fields",
+        ] {
+            assert!(!item.contains_key(name), "{name} should be left out");
+        }
+    }
+
+    #[test]
+    fn keeps_unknown_attributes() {
+        let mut item = full_subscriber().to_dynamodb_item().unwrap();
+        item.insert("added_later".to_string(), string("kept"));
+
+        let read = Subscriber::from_dynamodb_item(&item).unwrap();
+        assert_eq!(
+            read.extra_attributes,
+            HashMap::from([("added_later".to_string(), string("kept"))])
+        );
+
+        let written = read.to_dynamodb_item().unwrap();
+        assert_eq!(written["added_later"], string("kept"));
+    }
+
+    #[test]
+    fn old_records_take_defaults() {
+        let item = HashMap::from([
+            ("id".to_string(), string("sub-1")),
+            ("email".to_string(), string("ada@example.com")),
+            ("active".to_string(), AttributeValue::Bool(true)),
+            ("validated".to_string(), AttributeValue::Bool(true)),
+            ("created_at".to_string(), string(&at(0).to_rfc3339())),
+            ("updated_at".to_string(), string(&at(60).to_rfc3339())),
+            ("reminder_sent_at".to_string(), string(&at(1).to_rfc3339())),
+        ]);
+
+        let subscriber = Subscriber::from_dynamodb_item(&item).unwrap();
+        assert!(!subscriber.suppressed);
+        assert_eq!(subscriber.version, 0);
+        assert_eq!(subscriber.locale, Locale::En);
+        assert_eq!(subscriber.frequency, Frequency::Immediate);
+        assert_eq!(subscriber.tier, Tier::Free);
+        assert_eq!(subscriber.engagement_score, 0.0);
+        assert_eq!(subscriber.referral_count, 0);
+        // Only one reminder was sent before the count was kept
+        assert_eq!(subscriber.reminder_count, 1);
+        assert!(subscriber.unsubscribed_lists.is_empty());
+        assert!(subscriber.fields.is_empty());
+        assert!(subscriber.token_ttl.is_none());
+        assert!(subscriber.extra_attributes.is_empty());
+    }
+
+    #[test]
+    fn unknown_values_fall_back() {
+        let mut item = full_subscriber().to_dynamodb_item().unwrap();
+        item.insert("tier".to_string(), string("platinum"));
+        item.insert("locale".to_string(), string("fr"));
+        item.insert("verification".to_string(), string("maybe"));
+        item.insert("token_ttl".to_string(), string("not a number"));
+
+        let subscriber = Subscriber::from_dynamodb_item(&item).unwrap();
+        assert_eq!(subscriber.tier, Tier::Free);
+        assert_eq!(subscriber.locale, Locale::En);
+        assert_eq!(subscriber.verification, None);
+        assert_eq!(subscriber.token_ttl, None);
+    }
+
+    #[test]
+    fn refuses_malformed_records() {
+        let mut item = full_subscriber().to_dynamodb_item().unwrap();
+        item.remove("email");
+        assert!(Subscriber::from_dynamodb_item(&item).is_none());
+
+        let mut item = full_subscriber().to_dynamodb_item().unwrap();
+        item.insert("created_at".to_string(), string("yesterday"));
+        assert!(Subscriber::from_dynamodb_item(&item).is_none());
+    }
+
+    #[test]
+    fn refuses_to_write_a_non_finite_engagement_score() {
+        let mut subscriber = full_subscriber();
+        subscriber.engagement_score = f64::NAN;
+        assert!(subscriber.to_dynamodb_item().is_err());
+
+        subscriber.engagement_score = f64::INFINITY;
+        assert!(subscriber.to_dynamodb_item().is_err());
+    }
+}
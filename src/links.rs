@@ -0,0 +1,157 @@
+use crate::Error;
+use crate::config::env_or;
+use crate::preview::sample_subscriber;
+use crate::template::{render_html, subscriber_variables};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use utoipa::ToSchema;
+
+// The http(s) links of an HTML body, each once, sorted. Anchors, mailto: and
+// unrendered placeholders are left out.
+pub fn extract_links(html: &str) -> Vec<String> {
+    let mut links = BTreeSet::new();
+    let mut rest = html;
+
+    while let Some(start) = rest.find("href=") {
+        rest = &rest[start + "href=".len()..];
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        let Some(end) = rest[1..].find(quote) else {
+            break;
+        };
+
+        let link = rest[1..end + 1].trim().replace("&amp;", "&");
+        if (link.starts_with("http://") || link.starts_with("https://")) && !link.contains("{{") {
+            links.insert(link);
+        }
+        rest = &rest[end + 2..];
+    }
+
+    links.into_iter().collect()
+}
+
+// Links of a campaign body as rendered for the sample subscriber
+pub fn campaign_links(html_body: &str) -> Vec<String> {
+    let variables = subscriber_variables(&sample_subscriber(HashMap::new()));
+    extract_links(&render_html(html_body, &variables))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LinkResult {
+    pub url: String,
+    // Final HTTP status after redirects, None when the request failed
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl LinkResult {
+    pub fn is_broken(&self) -> bool {
+        !self
+            .status
+            .map(|status| (200..400).contains(&status))
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LinkReport {
+    pub success: bool,
+    pub checked: usize,
+    pub broken: Vec<LinkResult>,
+}
+
+impl LinkReport {
+    pub fn new(results: Vec<LinkResult>) -> Self {
+        let checked = results.len();
+        let broken: Vec<LinkResult> = results.into_iter().filter(LinkResult::is_broken).collect();
+
+        Self {
+            success: broken.is_empty(),
+            checked,
+            broken,
+        }
+    }
+}
+
+// Checks that links resolve, with HEAD requests falling back to GET for servers
+// that don't support HEAD
+pub struct LinkChecker {
+    client: reqwest::Client,
+    // Requests in flight at once, from LINK_CHECK_CONCURRENCY
+    concurrency: usize,
+}
+
+impl LinkChecker {
+    // Timeout per link from LINK_CHECK_TIMEOUT_SECONDS
+    pub fn from_env() -> Result<Self, Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(env_or(
+                "LINK_CHECK_TIMEOUT_SECONDS",
+                10,
+            )))
+            .build()?;
+
+        Ok(Self {
+            client,
+            concurrency: env_or("LINK_CHECK_CONCURRENCY", 10).max(1),
+        })
+    }
+
+    async fn check_link(client: &reqwest::Client, url: String) -> LinkResult {
+        let mut result = client.head(&url).send().await;
+        let head_unsupported = result.as_ref().is_ok_and(|response| {
+            matches!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+            )
+        });
+        if head_unsupported {
+            result = client.get(&url).send().await;
+        }
+
+        match result {
+            Ok(response) => LinkResult {
+                url,
+                status: Some(response.status().as_u16()),
+                error: None,
+            },
+            Err(e) => LinkResult {
+                url,
+                status: None,
+                error: Some(if e.is_timeout() {
+                    "timed out".to_string()
+                } else {
+                    e.to_string()
+                }),
+            },
+        }
+    }
+
+    // Check every link concurrently, returning results in the order given
+    pub async fn check(&self, urls: Vec<String>) -> Result<LinkReport, Error> {
+        let permits = Arc::new(Semaphore::new(self.concurrency));
+        let tasks: Vec<_> = urls
+            .into_iter()
+            .map(|url| {
+                let client = self.client.clone();
+                let permits = permits.clone();
+                tokio::spawn(async move {
+                    let _permit = permits.acquire_owned().await;
+                    Self::check_link(&client, url).await
+                })
+            })
+            .collect();
+
+        let mut results = Vec::new();
+        for task in tasks {
+            results.push(task.await?);
+        }
+
+        Ok(LinkReport::new(results))
+    }
+}
@@ -0,0 +1,125 @@
+use crate::Error;
+use lambda_http::{Body, Request, Response};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tracing::info;
+
+// Longest request body logged, in characters
+const MAX_LOGGED_BODY: usize = 1024;
+
+fn is_local_part_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '%' | '+' | '-')
+}
+
+fn is_domain_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '-')
+}
+
+// Keep the first character of the local part and the domain, e.g.
+// jane@example.com becomes j***@example.com
+pub fn mask_email(email: &str) -> String {
+    match email.rsplit_once('@') {
+        Some((local, domain)) => {
+            let first: String = local.chars().take(1).collect();
+            format!("{first}***@{domain}")
+        }
+        None => "***".to_string(),
+    }
+}
+
+// Mask every email address in free text, including percent-encoded ones from
+// form bodies and query strings
+pub fn redact(text: &str) -> String {
+    let chars: Vec<char> = text.replace("%40", "@").chars().collect();
+    let mut redacted = String::with_capacity(chars.len());
+    let mut copied = 0;
+
+    for (at, _) in chars.iter().enumerate().filter(|(_, c)| **c == '@') {
+        if at < copied {
+            continue;
+        }
+        let mut start = at;
+        while start > copied && is_local_part_char(chars[start - 1]) {
+            start -= 1;
+        }
+        let mut end = at + 1;
+        while end < chars.len() && is_domain_char(chars[end]) {
+            end += 1;
+        }
+        if start == at || end == at + 1 {
+            continue;
+        }
+
+        redacted.extend(&chars[copied..start]);
+        let email: String = chars[start..end].iter().collect();
+        redacted.push_str(&mask_email(&email));
+        copied = end;
+    }
+
+    redacted.extend(&chars[copied..]);
+    redacted
+}
+
+fn body_for_log(body: &Body) -> String {
+    match body {
+        Body::Empty => String::new(),
+        Body::Text(text) => {
+            let mut text: String = text.chars().take(MAX_LOGGED_BODY).collect();
+            if text.len() < body.len() {
+                text.push_str("...");
+            }
+            redact(&text)
+        }
+        Body::Binary(bytes) => format!("<{} bytes>", bytes.len()),
+    }
+}
+
+// Set once the container has served its first request
+static WARM: AtomicBool = AtomicBool::new(false);
+
+// Run `handler` and log the request's method, path, status, latency and body,
+// with email addresses masked. Query strings aren't logged, since they carry
+// confirmation and unsubscribe tokens. The container's first request is marked
+// cold, so its latency, which includes setting up clients, can be told apart.
+pub async fn log_request<F, Fut>(event: Request, handler: F) -> Result<Response<Body>, Error>
+where
+    F: FnOnce(Request) -> Fut,
+    Fut: Future<Output = Result<Response<Body>, Error>>,
+{
+    let method = event.method().to_string();
+    let path = event.uri().path().to_string();
+    let body = body_for_log(event.body());
+    let started = Instant::now();
+    let start = if WARM.swap(true, Ordering::Relaxed) {
+        "warm"
+    } else {
+        "cold"
+    };
+
+    let result = handler(event).await;
+
+    let latency_ms = started.elapsed().as_millis();
+    match &result {
+        Ok(response) => info!(
+            "{} {} {} {}ms {} {}",
+            method,
+            path,
+            response.status().as_u16(),
+            latency_ms,
+            start,
+            body
+        ),
+        Err(e) => info!(
+            "{} {} error {}ms {} {} {}",
+            method,
+            path,
+            latency_ms,
+            start,
+            body,
+            redact(&e.to_string())
+        ),
+    }
+
+    result
+}
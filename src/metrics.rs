@@ -0,0 +1,33 @@
+use chrono::Utc;
+use serde_json::{Map, Value, json};
+
+pub const METRICS_NAMESPACE: &str = "Newsletter";
+
+// Emit a CloudWatch metric using the Embedded Metric Format. Lambda ships stdout
+// to CloudWatch Logs, which extracts the metric without any API calls.
+pub fn emit_metric(name: &str, value: f64, unit: &str, dimensions: &[(&str, &str)]) {
+    let dimension_names: Vec<&str> = dimensions.iter().map(|(key, _)| *key).collect();
+
+    let mut document = Map::new();
+    document.insert(
+        "_aws".to_string(),
+        json!({
+            "Timestamp": Utc::now().timestamp_millis(),
+            "CloudWatchMetrics": [{
+                "Namespace": METRICS_NAMESPACE,
+                "Dimensions": [dimension_names],
+                "Metrics": [{ "Name": name, "Unit": unit }],
+            }],
+        }),
+    );
+    for (key, value) in dimensions {
+        document.insert(key.to_string(), Value::String(value.to_string()));
+    }
+    document.insert(name.to_string(), json!(value));
+
+    println!("{}", Value::Object(document));
+}
+
+pub fn emit_count(name: &str, dimensions: &[(&str, &str)]) {
+    emit_metric(name, 1.0, "Count", dimensions);
+}
@@ -0,0 +1,200 @@
+use crate::Error;
+use crate::logging::mask_email;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use std::env;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::info;
+
+// Chat messages never hold up the request that triggered them for long
+const TIMEOUT_SECONDS: u64 = 5;
+
+// Batches larger than this are posted as one summary instead of a message each
+pub const DEFAULT_BATCH_THRESHOLD: usize = 5;
+
+// Subscriber news worth telling the team about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    Confirmed { email: String },
+    Unsubscribed { email: String },
+}
+
+impl Notification {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Notification::Confirmed { .. } => "confirmed",
+            Notification::Unsubscribed { .. } => "unsubscribed",
+        }
+    }
+
+    // The message as posted, with the address masked, e.g. "j***@example.com just confirmed!"
+    pub fn text(&self) -> String {
+        match self {
+            Notification::Confirmed { email } => format!("{} just confirmed!", mask_email(email)),
+            Notification::Unsubscribed { email } => format!("{} unsubscribed", mask_email(email)),
+        }
+    }
+}
+
+// One line for a batch, e.g. "Import: 12 confirmed, 3 unsubscribed"
+pub fn summarize(source: &str, notifications: &[Notification]) -> String {
+    let parts: Vec<String> = ["confirmed", "unsubscribed"]
+        .into_iter()
+        .map(|kind| {
+            let count = notifications
+                .iter()
+                .filter(|notification| notification.kind() == kind)
+                .count();
+            (count, kind)
+        })
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, kind)| format!("{count} {kind}"))
+        .collect();
+
+    format!("{source}: {}", parts.join(", "))
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    // Incoming webhook URLs, which are secret themselves
+    Slack(String),
+    Discord(String),
+    // Any other endpoint, verified through the X-Newsletter-Signature header
+    Signed { url: String, secret: String },
+}
+
+// Posts notifications to the chat webhooks configured through
+// NOTIFY_SLACK_WEBHOOK_URL, NOTIFY_DISCORD_WEBHOOK_URL and NOTIFY_WEBHOOK_URL
+// (signed with NOTIFY_WEBHOOK_SECRET)
+pub struct Notifier {
+    client: reqwest::Client,
+    targets: Vec<Target>,
+    batch_threshold: usize,
+}
+
+fn setting(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+// Header value for a signed webhook: `t=<timestamp>,v1=<hex HMAC-SHA256 of
+// "<timestamp>.<body>">`, the scheme Stripe uses, so receivers can reject
+// replays as well as forgeries
+pub fn signature_header(secret: &str, timestamp: i64, body: &str) -> Result<String, Error> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    Ok(format!("t={timestamp},v1={signature}"))
+}
+
+impl Notifier {
+    // None when no webhook is configured
+    pub fn from_env() -> Option<Self> {
+        let mut targets = Vec::new();
+        if let Some(url) = setting("NOTIFY_SLACK_WEBHOOK_URL") {
+            targets.push(Target::Slack(url));
+        }
+        if let Some(url) = setting("NOTIFY_DISCORD_WEBHOOK_URL") {
+            targets.push(Target::Discord(url));
+        }
+        match (
+            setting("NOTIFY_WEBHOOK_URL"),
+            setting("NOTIFY_WEBHOOK_SECRET"),
+        ) {
+            (Some(url), Some(secret)) => targets.push(Target::Signed { url, secret }),
+            (Some(_), None) => info!("NOTIFY_WEBHOOK_SECRET not set, skipping NOTIFY_WEBHOOK_URL"),
+            _ => {}
+        }
+        if targets.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            client: crate::clients::http().clone(),
+            targets,
+            batch_threshold: crate::config::env_or(
+                "NOTIFY_BATCH_THRESHOLD",
+                DEFAULT_BATCH_THRESHOLD,
+            ),
+        })
+    }
+
+    pub async fn notify(&self, notification: &Notification) {
+        self.post(notification.kind(), &notification.text()).await;
+    }
+
+    // Post each notification, or a single summary naming `source` when there
+    // are more than NOTIFY_BATCH_THRESHOLD, e.g. after an import
+    pub async fn notify_batch(&self, source: &str, notifications: &[Notification]) {
+        if notifications.len() > self.batch_threshold {
+            self.post("batch", &summarize(source, notifications)).await;
+            return;
+        }
+        for notification in notifications {
+            self.notify(notification).await;
+        }
+    }
+
+    // Failures are logged, since a chat message is never worth failing a request
+    async fn post(&self, kind: &str, text: &str) {
+        for target in &self.targets {
+            if let Err(e) = self.post_to(target, kind, text).await {
+                info!("Error posting {} notification: {:?}", kind, e);
+            }
+        }
+    }
+
+    async fn post_to(&self, target: &Target, kind: &str, text: &str) -> Result<(), Error> {
+        let request = match target {
+            Target::Slack(url) => self.client.post(url).json(&json!({ "text": text })),
+            Target::Discord(url) => self.client.post(url).json(&json!({ "content": text })),
+            Target::Signed { url, secret } => {
+                let now = Utc::now();
+                let body = json!({
+                    "event": kind,
+                    "text": text,
+                    "sent_at": now.to_rfc3339(),
+                })
+                .to_string();
+                self.client
+                    .post(url)
+                    .header("Content-Type", "application/json")
+                    .header(
+                        "X-Newsletter-Signature",
+                        signature_header(secret, now.timestamp(), &body)?,
+                    )
+                    .body(body)
+            }
+        };
+
+        request
+            .timeout(Duration::from_secs(TIMEOUT_SECONDS))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+// The container's notifier, read from the environment once
+fn notifier() -> Option<&'static Notifier> {
+    static NOTIFIER: OnceLock<Option<Notifier>> = OnceLock::new();
+    NOTIFIER.get_or_init(Notifier::from_env).as_ref()
+}
+
+// Post a single notification if any webhook is configured
+pub async fn notify(notification: Notification) {
+    if let Some(notifier) = notifier() {
+        notifier.notify(&notification).await;
+    }
+}
@@ -0,0 +1,120 @@
+use crate::Subscriber;
+use crate::attachments::AttachmentStore;
+use crate::domains::{DomainMetric, DomainStatsStore};
+use crate::email_queue::{EmailKind, Mailer};
+use crate::history::{HistoryRepository, LifecycleEvent, LifecycleEventType};
+use crate::i18n::EmailTemplate;
+use crate::notify::{Notification, notify};
+use crate::referral::generate_code;
+use crate::repository::SubscriberRepository;
+use crate::template::subscriber_variables;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::Client;
+use chrono::Utc;
+use std::env;
+use tracing::info;
+
+// What happens once a subscriber is confirmed, by their confirmation link or
+// straight away when double opt-in is off
+
+// Give the newly confirmed subscriber a referral code of their own and credit
+// whoever referred them. Failures are logged so they never block confirmation.
+pub async fn resolve_referral(
+    repository: &SubscriberRepository,
+    id: &str,
+    referred_by: Option<&str>,
+) {
+    if let Err(e) = repository.assign_referral_code(id, &generate_code()).await {
+        info!("Error assigning referral code: {:?}", e);
+    }
+
+    let Some(code) = referred_by else {
+        return;
+    };
+
+    match repository.find_by_referral_code(code).await {
+        Ok(Some(referrer)) if referrer.id != id => {
+            if let Err(e) = repository.increment_referral_count(&referrer.id).await {
+                info!("Error crediting referral to {}: {:?}", referrer.id, e);
+            }
+        }
+        Ok(_) => info!("Ignoring unknown or self referral code {}", code),
+        Err(e) => info!("Error resolving referral code: {:?}", e),
+    }
+}
+
+// Everything that follows confirming a subscriber without their link, under
+// single opt-in: history, domain stats, referral and the welcome email.
+// `source` says what confirmed them.
+pub async fn confirm_without_link(
+    config: &SdkConfig,
+    client: Client,
+    subscriber: &Subscriber,
+    source: &str,
+) {
+    HistoryRepository::new(client.clone())
+        .record_or_log(
+            LifecycleEvent::new(&subscriber.id, LifecycleEventType::Confirmed).with_source(source),
+        )
+        .await;
+    DomainStatsStore::new(client.clone())
+        .record_or_log(&subscriber.email, DomainMetric::Confirmed, Utc::now())
+        .await;
+    resolve_referral(
+        &SubscriberRepository::new(client),
+        &subscriber.id,
+        subscriber.referred_by.as_deref(),
+    )
+    .await;
+    notify(Notification::Confirmed {
+        email: subscriber.email.clone(),
+    })
+    .await;
+    send_welcome(config, subscriber).await;
+}
+
+// Send the welcome email, with the files listed in WELCOME_ATTACHMENTS
+// attached. Failures are logged so they never block confirmation.
+pub async fn send_welcome(config: &SdkConfig, subscriber: &Subscriber) {
+    let Ok(sender_email) = env::var("SENDER_EMAIL") else {
+        info!("SENDER_EMAIL not set, skipping welcome email");
+        return;
+    };
+
+    let mailer = Mailer::new(config, sender_email);
+    let keys: Vec<String> = env::var("WELCOME_ATTACHMENTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(str::to_string)
+        .collect();
+    let variables = subscriber_variables(subscriber);
+
+    let result = match AttachmentStore::from_env(config).filter(|_| !keys.is_empty()) {
+        Some(attachments) => {
+            let (subject, html_body) =
+                EmailTemplate::WelcomeWithAttachments.render(subscriber.locale, &variables);
+            mailer
+                .send_with_attachments(
+                    EmailKind::Welcome,
+                    subscriber,
+                    &subject,
+                    &html_body,
+                    &attachments,
+                    &keys,
+                )
+                .await
+        }
+        None => {
+            let (subject, html_body) = EmailTemplate::Welcome.render(subscriber.locale, &variables);
+            mailer
+                .send(EmailKind::Welcome, subscriber, None, &subject, &html_body)
+                .await
+        }
+    };
+
+    if let Err(e) = result {
+        info!("Error sending welcome email to {}: {:?}", subscriber.id, e);
+    }
+}
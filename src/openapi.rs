@@ -0,0 +1,872 @@
+use crate::admin::{
+    AddSubscriberRequest, SourceBreakdownResponse, SubscriberDetailResponse,
+    SubscriberListResponse, SubscriberStatus,
+};
+use crate::attribution::{Attribution, SourceStats};
+use crate::audit::{AuditEntry, AuditLogResponse};
+use crate::campaign::{CampaignCancelData, CampaignSendData, Exclusions};
+use crate::content::{ContentIssue, ContentIssueKind, ContentReport};
+use crate::counters::{SubscriberCounts, SubscriberCountsResponse};
+use crate::digest::{Frequency, FrequencyRequest};
+use crate::domain_rules::{DomainRule, DomainRuleListResponse, DomainRuleRequest, RuleKind};
+use crate::domains::{DomainStats, DomainStatsResponse};
+use crate::events::{Event, EventType};
+use crate::export::ExportParams;
+use crate::health::{BuildInfo, HealthResponse, IndexHealth, TableHealth};
+use crate::history::{LifecycleEvent, LifecycleEventType, SubscriberHistoryResponse};
+use crate::i18n::Locale;
+use crate::import::{ImportPreviewResponse, ImportReport, ReportRow, RowOutcome};
+use crate::invites::{CreateInviteRequest, InviteCode, InviteListResponse};
+use crate::jobs::{Job, JobKind, JobListResponse, JobProgress, JobStatus, StartJobRequest};
+use crate::links::{LinkReport, LinkResult};
+use crate::opt_out::{OptOut, OptOutReplayData};
+use crate::preview::{PreviewRequest, PreviewResponse};
+use crate::quarantine::QuarantineReason;
+use crate::referral::{Milestone, ReferralResponse, ReferralStatsResponse, ReferrerStats};
+use crate::schedule::ScheduleRequest;
+use crate::senders::{CreateSenderRequest, SenderIdentity, SenderListResponse, SenderStatus};
+use crate::stripe::Tier;
+use crate::sync::{SubscriberChange, SubscriberChangesResponse};
+use crate::tagging::{TagAction, TagParams};
+use crate::templates::{
+    CreateTemplateRequest, PublishTemplateRequest, Template, TemplateListResponse,
+    TemplateResponse, TemplateVersion, UpdateTemplateRequest,
+};
+use crate::verification::Verdict;
+use crate::versioning::Envelope;
+use crate::{
+    ApiResponse, ConfirmData, ConfirmStatus, SubscribeData, SubscribeRequest, SubscribeStatus,
+    Subscriber, UnsubscribeRequest, api_url,
+};
+use utoipa::openapi::Server;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+// Path definitions for the Lambda handlers, which live in separate binaries.
+// The functions only carry the annotations and are never called.
+#[allow(dead_code)]
+mod paths {
+    #[utoipa::path(
+        post,
+        path = "/subscribe",
+        request_body = SubscribeRequest,
+        params(
+            ("Idempotency-Key" = Option<String>, Header, description = "Replays the original response for retries within 24 hours"),
+            ("Accept-Language" = Option<String>, Header, description = "Language of the response message and emails"),
+        ),
+        responses(
+            (status = 201, description = "Subscribed, a validation email will be sent, or an unsubscribed address asked to confirm again. `data` is a SubscribeData whose `status` tells which.", body = ApiResponse),
+            (status = 200, description = "Email is already on the list, status `already_subscribed`, or signed up but unconfirmed, status `pending_confirmation`, in which case the confirmation email is sent again. `data` is a SubscribeData.", body = ApiResponse),
+            (status = 400, description = "Invalid request, email or custom fields, or control characters in a field", body = ApiResponse),
+            (status = 403, description = "The email domain is denied or not on the allowlist, or the invite code is missing or invalid", body = ApiResponse),
+            (status = 409, description = "A request with the same Idempotency-Key is in progress", body = ApiResponse),
+            (status = 413, description = "Request body over the size limit", body = ApiResponse),
+            (status = 422, description = "Idempotency-Key reused for a different request", body = ApiResponse),
+        )
+    )]
+    fn subscribe() {}
+
+    #[utoipa::path(
+        get,
+        path = "/confirm",
+        params(
+            ("id" = String, Query, description = "Subscriber id from the validation link"),
+            ("token" = String, Query, description = "Validation token from the validation link"),
+        ),
+        responses(
+            (status = 200, description = "Email confirmed, or already confirmed. `data` is a ConfirmData.", body = ApiResponse),
+            (status = 400, description = "Missing, invalid or expired token", body = ApiResponse),
+            (status = 404, description = "Subscriber not found", body = ApiResponse),
+        )
+    )]
+    fn confirm() {}
+
+    #[utoipa::path(
+        post,
+        path = "/unsubscribe",
+        request_body = UnsubscribeRequest,
+        params(
+            ("id" = Option<String>, Query, description = "Subscriber id, for one-click unsubscribe"),
+            ("token" = Option<String>, Query, description = "Signature of the id, for one-click unsubscribe"),
+        ),
+        responses(
+            (status = 200, description = "Unsubscribed", body = ApiResponse),
+            (status = 400, description = "Invalid request", body = ApiResponse),
+            (status = 404, description = "Email not found", body = ApiResponse),
+        )
+    )]
+    fn unsubscribe() {}
+
+    #[utoipa::path(
+        get,
+        path = "/referral",
+        params(
+            ("id" = String, Query, description = "Subscriber id"),
+            ("token" = String, Query, description = "Signature of `referral:<id>`, from `{{ referral_status_url }}`"),
+        ),
+        responses(
+            (status = 200, description = "Referral code and milestone progress", body = ReferralResponse),
+            (status = 401, description = "Invalid signature", body = ApiResponse),
+            (status = 404, description = "Confirmed subscriber not found", body = ApiResponse),
+        )
+    )]
+    fn referral() {}
+
+    #[utoipa::path(
+        get,
+        path = "/health",
+        responses(
+            (status = 200, description = "Every table and index is active", body = HealthResponse),
+            (status = 503, description = "A table or index is unreachable, missing or not active", body = HealthResponse),
+        )
+    )]
+    fn health() {}
+
+    #[utoipa::path(
+        get,
+        path = "/version",
+        responses(
+            (status = 200, description = "Crate version and git commit of the deployed build", body = BuildInfo),
+        )
+    )]
+    fn version() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/subscribers",
+        params(
+            ("limit" = Option<i32>, Query, description = "Page size, 1 to 500"),
+            ("cursor" = Option<String>, Query, description = "Opaque next_cursor from the previous page"),
+            ("segment" = Option<String>, Query, description = "Segment expression, e.g. `engagement_score >= 2`"),
+        ),
+        responses(
+            (status = 200, description = "A page of subscribers", body = SubscriberListResponse),
+            (status = 400, description = "Invalid segment or cursor", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 429, description = "Daily request quota exceeded", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn list_subscribers() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/subscribers",
+        request_body = AddSubscriberRequest,
+        responses(
+            (status = 201, description = "Subscriber added as confirmed and welcomed", body = Subscriber),
+            (status = 400, description = "Missing consent note, invalid email, locale or fields", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 409, description = "Email is already subscribed", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn add_subscriber() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/jobs",
+        request_body = StartJobRequest,
+        responses(
+            (status = 202, description = "Job queued, poll it for progress", body = Job),
+            (status = 400, description = "Unknown kind or invalid parameters", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 500, description = "Job queue not configured or unavailable", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn start_job() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/jobs",
+        responses(
+            (status = 200, description = "Every job, newest first", body = JobListResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn list_jobs() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/jobs/{id}",
+        params(("id" = String, Path, description = "Job id")),
+        responses(
+            (status = 200, description = "The job, its progress and result location", body = Job),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Job not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn get_job() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/jobs/{id}/cancel",
+        params(("id" = String, Path, description = "Job id")),
+        responses(
+            (status = 200, description = "Job cancelled, its remaining pages are dropped", body = Job),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Job not found", body = ApiResponse),
+            (status = 409, description = "Job already completed, failed or was cancelled", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn cancel_job() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/subscribers/changes",
+        params(
+            ("cursor" = Option<String>, Query, description = "next_cursor from the previous call; wins over since"),
+            ("since" = Option<String>, Query, description = "RFC 3339 time to start from when there's no cursor yet; everything when both are left out"),
+            ("limit" = Option<i32>, Query, description = "Page size, 1 to 1000"),
+        ),
+        responses(
+            (status = 200, description = "Subscribers changed after the cursor or time, oldest change first, including deleted ones", body = SubscriberChangesResponse),
+            (status = 400, description = "Invalid cursor or since", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn subscriber_changes() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/subscribers/search",
+        params(
+            ("email" = Option<String>, Query, description = "Exact email address"),
+            ("prefix" = Option<String>, Query, description = "Start of the email address, at least 2 characters"),
+            ("domain" = Option<String>, Query, description = "Email domain, e.g. `example.com`"),
+            ("limit" = Option<i32>, Query, description = "Page size, 1 to 500"),
+            ("cursor" = Option<String>, Query, description = "Opaque next_cursor from the previous page"),
+        ),
+        responses(
+            (status = 200, description = "A page of matching subscribers", body = SubscriberListResponse),
+            (status = 400, description = "Missing or conflicting search parameters, or an invalid cursor", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn search_subscribers() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/subscribers/lookup",
+        params(("email" = String, Query, description = "Exact email address")),
+        responses(
+            (status = 200, description = "The subscriber with their history and latest events", body = SubscriberDetailResponse),
+            (status = 400, description = "Missing email", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Subscriber not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn find_subscriber() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/subscribers/{id}",
+        params(("id" = String, Path, description = "Subscriber id")),
+        responses(
+            (status = 200, description = "The subscriber with their history and latest events, soft-deleted ones included", body = SubscriberDetailResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Subscriber not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn get_subscriber() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/subscribers/{id}/history",
+        params(("id" = String, Path, description = "Subscriber id")),
+        responses(
+            (status = 200, description = "Lifecycle events for the subscriber, oldest first", body = SubscriberHistoryResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Subscriber not found and no history recorded", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn subscriber_history() {}
+
+    #[utoipa::path(
+        put,
+        path = "/admin/subscribers/{id}/frequency",
+        params(("id" = String, Path, description = "Subscriber id")),
+        request_body = FrequencyRequest,
+        responses(
+            (status = 200, description = "Updated subscriber", body = Subscriber),
+            (status = 400, description = "Invalid frequency", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Subscriber not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn set_subscriber_frequency() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/subscribers/{id}/approve",
+        params(("id" = String, Path, description = "Subscriber id")),
+        responses(
+            (status = 200, description = "Subscriber released from quarantine and sent the confirmation or welcome email", body = Subscriber),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "No quarantined subscriber with this id", body = ApiResponse),
+            (status = 500, description = "Validation queue not configured or unavailable", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn approve_subscriber() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/subscribers/{id}/confirm",
+        params(("id" = String, Path, description = "Subscriber id")),
+        responses(
+            (status = 200, description = "Subscriber confirmed without a token and welcomed, or already confirmed", body = Subscriber),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Subscriber not found", body = ApiResponse),
+            (status = 409, description = "Subscriber is quarantined and has to be approved instead", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn confirm_subscriber() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/stats/sources",
+        params(
+            ("by" = Option<String>, Query, description = "source, utm_source, utm_medium, utm_campaign or referrer"),
+        ),
+        responses(
+            (status = 200, description = "Subscribers and confirmations per signup source", body = SourceBreakdownResponse),
+            (status = 400, description = "Unknown breakdown", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn source_stats() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/stats/referrals",
+        responses(
+            (status = 200, description = "Top referrers and milestones reached", body = ReferralStatsResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn referral_stats() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/stats/domains",
+        params(
+            ("days" = Option<i64>, Query, description = "Days to cover, ending today, 1 to 365"),
+            ("by" = Option<String>, Query, description = "domain or provider"),
+            ("domain" = Option<String>, Query, description = "A single domain, reported day by day"),
+        ),
+        responses(
+            (status = 200, description = "Subscriptions, deliveries and engagement per email domain", body = DomainStatsResponse),
+            (status = 400, description = "Unknown breakdown", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn domain_stats() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/stats/subscribers",
+        responses(
+            (status = 200, description = "Subscriber totals and counts per tag, from maintained counters", body = SubscriberCountsResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn subscriber_counts() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/audit",
+        params(
+            ("days" = Option<i64>, Query, description = "Days to cover, ending today, 1 to 90"),
+            ("api_key" = Option<String>, Query, description = "Only requests made with this API key id"),
+            ("limit" = Option<usize>, Query, description = "Entries to return, 1 to 1000"),
+        ),
+        responses(
+            (status = 200, description = "Admin API mutations, newest first, with whether each entry's signature checks out", body = AuditLogResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn audit_log() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/domain-rules",
+        responses(
+            (status = 200, description = "Signup domain allow and deny rules", body = DomainRuleListResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn list_domain_rules() {}
+
+    #[utoipa::path(
+        put,
+        path = "/admin/domain-rules/{domain}",
+        params(("domain" = String, Path, description = "Domain, also covering its subdomains")),
+        request_body = DomainRuleRequest,
+        responses(
+            (status = 200, description = "Rule created or replaced", body = DomainRule),
+            (status = 400, description = "Invalid domain or rule", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn put_domain_rule() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/admin/domain-rules/{domain}",
+        params(("domain" = String, Path, description = "Domain of the rule")),
+        responses(
+            (status = 200, description = "Rule removed", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "No rule for the domain", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn delete_domain_rule() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/invites",
+        responses(
+            (status = 200, description = "Invite codes with their usage, newest first", body = InviteListResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn list_invites() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/invites",
+        request_body = CreateInviteRequest,
+        responses(
+            (status = 201, description = "Invite code minted", body = InviteCode),
+            (status = 400, description = "Invalid request body", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 409, description = "The code is taken or invalid", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn create_invite() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/admin/invites/{code}",
+        params(("code" = String, Path, description = "Invite code")),
+        responses(
+            (status = 200, description = "Invite code revoked", body = InviteCode),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Invite code not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn revoke_invite() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/senders",
+        responses(
+            (status = 200, description = "Sender identities, with pending ones checked against SES", body = SenderListResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn list_senders() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/senders",
+        request_body = CreateSenderRequest,
+        responses(
+            (status = 201, description = "Sender identity added and SES verification started", body = SenderIdentity),
+            (status = 400, description = "Invalid id or address", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 409, description = "The id is taken", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn create_sender() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/senders/{id}",
+        params(("id" = String, Path, description = "Sender identity id")),
+        responses(
+            (status = 200, description = "Sender identity, checked against SES when pending", body = SenderIdentity),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Sender identity not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn get_sender() {}
+
+    #[utoipa::path(
+        delete,
+        path = "/admin/senders/{id}",
+        params(("id" = String, Path, description = "Sender identity id")),
+        responses(
+            (status = 200, description = "Sender identity deleted", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Sender identity not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn delete_sender() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/templates",
+        responses(
+            (status = 200, description = "Templates by name", body = TemplateListResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn list_templates() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/templates",
+        request_body = CreateTemplateRequest,
+        responses(
+            (status = 201, description = "Template created with version 1 published", body = Template),
+            (status = 400, description = "Invalid request body", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn create_template() {}
+
+    #[utoipa::path(
+        get,
+        path = "/admin/templates/{id}",
+        params(("id" = String, Path, description = "Template id")),
+        responses(
+            (status = 200, description = "Template with its versions, newest first", body = TemplateResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Template not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn get_template() {}
+
+    #[utoipa::path(
+        put,
+        path = "/admin/templates/{id}",
+        params(("id" = String, Path, description = "Template id")),
+        request_body = UpdateTemplateRequest,
+        responses(
+            (status = 200, description = "New version saved, and published unless `publish` is false", body = Template),
+            (status = 400, description = "Invalid request body", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Template not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn update_template() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/templates/{id}/publish",
+        params(("id" = String, Path, description = "Template id")),
+        request_body = PublishTemplateRequest,
+        responses(
+            (status = 200, description = "Published version moved", body = Template),
+            (status = 400, description = "Invalid request body", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Template or version not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn publish_template() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/preview",
+        request_body = PreviewRequest,
+        responses(
+            (status = 200, description = "Rendered subject, sanitized HTML and text part; nothing is sent", body = PreviewResponse),
+            (status = 400, description = "Invalid request, or not exactly one of campaign_id and template_id", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Campaign, template, version or subscriber not found", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn preview() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/import/preview",
+        params(("format" = Option<String>, Query, description = "generic (default), mailchimp, buttondown or substack")),
+        request_body(content = String, content_type = "text/csv", description = "File to import, as for `newsletterctl import`"),
+        responses(
+            (status = 200, description = "What the import would do; nothing is written", body = ImportPreviewResponse),
+            (status = 400, description = "Unknown format or unreadable CSV", body = ApiResponse),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn preview_import() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/campaigns/{id}/check-links",
+        params(("id" = String, Path, description = "Campaign id")),
+        responses(
+            (status = 200, description = "Every link resolved", body = LinkReport),
+            (status = 401, description = "Missing or unknown API key", body = ApiResponse),
+            (status = 404, description = "Campaign not found", body = ApiResponse),
+            (status = 422, description = "Some links are broken", body = LinkReport),
+        ),
+        security(("api_key" = []))
+    )]
+    fn check_campaign_links() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/campaigns/{id}/send",
+        params(
+            ("id" = String, Path, description = "Campaign id"),
+            ("skip_link_check" = Option<bool>, Query, description = "Send even if some links are broken"),
+        ),
+        request_body(content = Option<ScheduleRequest>, description = "Send at a local time in each recipient's timezone instead of right away"),
+        responses(
+            (status = 200, description = "Campaign sent. `data` is a CampaignSendData.", body = ApiResponse),
+            (status = 202, description = "Campaign scheduled, or handed to the send state machine when CAMPAIGN_STATE_MACHINE_ARN is set. `data` is a CampaignSendData.", body = ApiResponse),
+            (status = 400, description = "Invalid schedule", body = ApiResponse),
+            (status = 404, description = "Campaign not found", body = ApiResponse),
+            (status = 409, description = "Campaign is not a draft, its sender identity is missing or not verified, or its template has no published version", body = ApiResponse),
+            (status = 422, description = "Some links are broken (a LinkReport) or the body has no {{ unsubscribe_url }} link (a ContentReport), nothing was sent", body = LinkReport),
+            (status = 429, description = "Request or send quota exceeded", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn send_campaign() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/campaigns/{id}/cancel",
+        params(("id" = String, Path, description = "Campaign id")),
+        responses(
+            (status = 200, description = "Campaign cancelled. `data` is a CampaignCancelData.", body = ApiResponse),
+            (status = 202, description = "Campaign is being cancelled; emails still going out are skipped. `data` is a CampaignCancelData.", body = ApiResponse),
+            (status = 404, description = "Campaign not found", body = ApiResponse),
+            (status = 409, description = "Campaign is not being sent or scheduled", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn cancel_campaign() {}
+
+    #[utoipa::path(
+        put,
+        path = "/admin/campaigns/{id}/exclusions",
+        params(("id" = String, Path, description = "Campaign id")),
+        request_body = Exclusions,
+        responses(
+            (status = 200, description = "Exclusions replaced. `data` is the Exclusions as stored, lowercased and deduplicated.", body = ApiResponse),
+            (status = 400, description = "Invalid body or exclusion segment", body = ApiResponse),
+            (status = 404, description = "Campaign not found", body = ApiResponse),
+            (status = 409, description = "Campaign is not a draft", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn set_campaign_exclusions() {}
+
+    #[utoipa::path(
+        post,
+        path = "/admin/opt-outs/replay",
+        params(
+            ("since" = String, Query, description = "RFC 3339 timestamp, replays opt-outs at or after it"),
+            ("limit" = Option<usize>, Query, description = "1 to 1000, defaults to 100"),
+        ),
+        responses(
+            (status = 200, description = "Opt-outs sent to the webhook again. `data` is an OptOutReplayData.", body = ApiResponse),
+            (status = 400, description = "Missing or invalid since", body = ApiResponse),
+            (status = 409, description = "The opt-out webhook is not configured", body = ApiResponse),
+        ),
+        security(("api_key" = []))
+    )]
+    fn replay_opt_outs() {}
+}
+
+struct ApiKeySecurity;
+
+impl Modify for ApiKeySecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Newsletter Backend API"),
+    paths(
+        paths::subscribe,
+        paths::confirm,
+        paths::unsubscribe,
+        paths::referral,
+        paths::health,
+        paths::version,
+        paths::list_subscribers,
+        paths::add_subscriber,
+        paths::start_job,
+        paths::list_jobs,
+        paths::get_job,
+        paths::cancel_job,
+        paths::subscriber_changes,
+        paths::search_subscribers,
+        paths::find_subscriber,
+        paths::get_subscriber,
+        paths::subscriber_history,
+        paths::set_subscriber_frequency,
+        paths::approve_subscriber,
+        paths::confirm_subscriber,
+        paths::source_stats,
+        paths::referral_stats,
+        paths::domain_stats,
+        paths::subscriber_counts,
+        paths::audit_log,
+        paths::list_domain_rules,
+        paths::put_domain_rule,
+        paths::delete_domain_rule,
+        paths::list_invites,
+        paths::create_invite,
+        paths::revoke_invite,
+        paths::list_senders,
+        paths::create_sender,
+        paths::get_sender,
+        paths::delete_sender,
+        paths::list_templates,
+        paths::create_template,
+        paths::get_template,
+        paths::update_template,
+        paths::publish_template,
+        paths::preview,
+        paths::preview_import,
+        paths::check_campaign_links,
+        paths::send_campaign,
+        paths::cancel_campaign,
+        paths::set_campaign_exclusions,
+        paths::replay_opt_outs,
+    ),
+    components(schemas(
+        ApiResponse,
+        SubscribeData,
+        SubscribeStatus,
+        ConfirmData,
+        ConfirmStatus,
+        CampaignSendData,
+        ContentIssue,
+        ContentIssueKind,
+        ContentReport,
+        CampaignCancelData,
+        Exclusions,
+        OptOut,
+        OptOutReplayData,
+        SubscribeRequest,
+        UnsubscribeRequest,
+        Subscriber,
+        Locale,
+        Frequency,
+        FrequencyRequest,
+        Tier,
+        Verdict,
+        QuarantineReason,
+        Attribution,
+        SubscriberListResponse,
+        AddSubscriberRequest,
+        JobKind,
+        JobStatus,
+        JobProgress,
+        Job,
+        JobListResponse,
+        StartJobRequest,
+        TagAction,
+        TagParams,
+        ExportParams,
+        SubscriberChange,
+        SubscriberChangesResponse,
+        SubscriberHistoryResponse,
+        SubscriberDetailResponse,
+        SubscriberStatus,
+        Event,
+        EventType,
+        LifecycleEvent,
+        LifecycleEventType,
+        SourceBreakdownResponse,
+        SourceStats,
+        ReferralResponse,
+        Milestone,
+        ReferralStatsResponse,
+        ReferrerStats,
+        DomainStatsResponse,
+        SubscriberCounts,
+        SubscriberCountsResponse,
+        AuditEntry,
+        AuditLogResponse,
+        DomainStats,
+        DomainRule,
+        DomainRuleRequest,
+        DomainRuleListResponse,
+        RuleKind,
+        InviteCode,
+        CreateInviteRequest,
+        InviteListResponse,
+        SenderIdentity,
+        SenderStatus,
+        CreateSenderRequest,
+        SenderListResponse,
+        Template,
+        TemplateVersion,
+        CreateTemplateRequest,
+        UpdateTemplateRequest,
+        PublishTemplateRequest,
+        TemplateListResponse,
+        TemplateResponse,
+        PreviewRequest,
+        PreviewResponse,
+        ImportPreviewResponse,
+        ImportReport,
+        ReportRow,
+        RowOutcome,
+        LinkReport,
+        LinkResult,
+        ScheduleRequest,
+        HealthResponse,
+        TableHealth,
+        IndexHealth,
+        BuildInfo,
+        Envelope,
+    )),
+    modifiers(&ApiKeySecurity)
+)]
+pub struct ApiDoc;
+
+// The OpenAPI document as pretty-printed JSON
+pub fn openapi_json() -> Result<String, serde_json::Error> {
+    let mut openapi = ApiDoc::openapi();
+    openapi.servers = Some(vec![Server::new(api_url())]);
+    openapi.to_pretty_json()
+}
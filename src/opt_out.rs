@@ -0,0 +1,144 @@
+use crate::Error;
+use crate::history::{LifecycleEvent, LifecycleEventType};
+use crate::notify::signature_header;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::info;
+use utoipa::ToSchema;
+
+// Webhook calls never hold up the request that triggered them for long
+const TIMEOUT_SECONDS: u64 = 5;
+
+// An address that must not be contacted any more, as sent to the opt-out
+// webhook so CRMs and outreach tools can stop mailing it too
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OptOut {
+    // Same for a live event and its replays, so receivers can drop duplicates
+    pub id: String,
+    // "subscriber.unsubscribed" or "subscriber.suppressed"
+    pub event: String,
+    pub email: String,
+    pub subscriber_id: String,
+    // What triggered it, e.g. "one_click", "sunset" or "ses"
+    pub source: Option<String>,
+    // Why, e.g. "complaint" or a bounce reason
+    pub reason: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+    // Sent again through the replay endpoint
+    pub replayed: bool,
+}
+
+impl OptOut {
+    // None for lifecycle events that aren't an opt-out
+    pub fn new(event: &LifecycleEvent, email: &str) -> Option<Self> {
+        let name = match event.event_type {
+            LifecycleEventType::Unsubscribed => "subscriber.unsubscribed",
+            LifecycleEventType::Suppressed => "subscriber.suppressed",
+            _ => return None,
+        };
+        let occurred_at = event
+            .occurred_at
+            .to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        Some(Self {
+            id: format!(
+                "{}#{}#{}",
+                event.subscriber_id,
+                event.event_type.as_str(),
+                occurred_at
+            ),
+            event: name.to_string(),
+            email: email.to_string(),
+            subscriber_id: event.subscriber_id.clone(),
+            source: event.source.clone(),
+            reason: event.detail.clone(),
+            occurred_at: event.occurred_at,
+            replayed: false,
+        })
+    }
+}
+
+// Outcome of replaying opt-outs through the admin API
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OptOutReplayData {
+    pub sent: usize,
+    pub failed: usize,
+    // Subscribers purged since, whose address is gone
+    pub skipped: usize,
+    // Pass as `since` to continue when the limit was reached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_since: Option<DateTime<Utc>>,
+}
+
+// Posts opt-outs to OPT_OUT_WEBHOOK_URL, signed with OPT_OUT_WEBHOOK_SECRET in
+// the X-Newsletter-Signature header like the signed notification webhook
+pub struct OptOutWebhook {
+    client: reqwest::Client,
+    url: String,
+    secret: String,
+}
+
+impl OptOutWebhook {
+    // None when the webhook isn't configured
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("OPT_OUT_WEBHOOK_URL")
+            .ok()
+            .filter(|url| !url.trim().is_empty())?;
+        let Some(secret) = env::var("OPT_OUT_WEBHOOK_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty())
+        else {
+            info!("OPT_OUT_WEBHOOK_SECRET not set, skipping OPT_OUT_WEBHOOK_URL");
+            return None;
+        };
+
+        Some(Self {
+            client: crate::clients::http().clone(),
+            url: url.trim().to_string(),
+            secret,
+        })
+    }
+
+    pub async fn send(&self, opt_out: &OptOut) -> Result<(), Error> {
+        let body = serde_json::to_string(opt_out)?;
+        self.client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .header(
+                "X-Newsletter-Signature",
+                signature_header(&self.secret, Utc::now().timestamp(), &body)?,
+            )
+            .body(body)
+            .timeout(Duration::from_secs(TIMEOUT_SECONDS))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    // Failures are logged, the replay endpoint re-sends what was missed
+    pub async fn send_or_log(&self, opt_out: &OptOut) {
+        if let Err(e) = self.send(opt_out).await {
+            info!(
+                "Error sending {} for {} to the opt-out webhook: {:?}",
+                opt_out.event, opt_out.subscriber_id, e
+            );
+        }
+    }
+}
+
+// Tell the opt-out webhook, if configured, about an unsubscribe or suppression
+// of `email`. Other lifecycle events are ignored.
+pub async fn publish(event: &LifecycleEvent, email: &str) {
+    let Some(opt_out) = OptOut::new(event, email) else {
+        return;
+    };
+    static WEBHOOK: OnceLock<Option<OptOutWebhook>> = OnceLock::new();
+    if let Some(webhook) = WEBHOOK.get_or_init(OptOutWebhook::from_env) {
+        webhook.send_or_log(&opt_out).await;
+    }
+}
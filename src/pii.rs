@@ -0,0 +1,453 @@
+use crate::Error;
+use crate::address::canonical_email;
+use crate::clients::sdk_config;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_kms::Client as KmsClient;
+use aws_sdk_kms::primitives::Blob;
+use aws_sdk_kms::types::DataKeySpec;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::OnceCell;
+use tracing::info;
+
+// Marks an encrypted attribute value, followed by the base64 nonce and ciphertext
+const ENCRYPTED_PREFIX: &str = "enc1:";
+
+const NONCE_LENGTH: usize = 12;
+
+type Item = HashMap<String, AttributeValue>;
+
+// A data key from KMS, in the clear for encrypting and wrapped by the KMS key
+// for storing next to what it encrypted
+struct DataKey {
+    cipher: Aes256Gcm,
+    wrapped: String,
+    // ARN of the KMS key that wrapped it
+    key_arn: String,
+}
+
+// Field-level envelope encryption of subscriber PII. Each Lambda container
+// asks KMS for one data key and encrypts every subscriber it writes with it,
+// storing the wrapped data key in the record's `pii_key` attribute. Reading
+// unwraps a record's data key through KMS once per container.
+//
+// The email is replaced by `email_encrypted`, and subscribers are found by
+// address through `email_hash`. Custom field values are encrypted in place.
+// The email search indexes would give the address away, so encrypted records
+// are left out of them; the domain index stays.
+pub struct PiiCipher {
+    key_id: String,
+    kms: OnceCell<KmsClient>,
+    current: OnceCell<DataKey>,
+    // Data keys of records already read, by their wrapped form
+    unwrapped: Mutex<HashMap<String, (Aes256Gcm, String)>>,
+}
+
+impl PiiCipher {
+    // From PII_KMS_KEY_ID, a KMS key id, ARN or alias. Needs PII_INDEX_SECRET
+    // too, or the email hash would give the address away. None when
+    // encryption is off.
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let Some(key_id) = env::var("PII_KMS_KEY_ID")
+            .ok()
+            .filter(|key_id| !key_id.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        if index_secret().is_none_or(|secret| secret.len() < 32) {
+            return Err("PII_INDEX_SECRET must be set to at least 32 characters".into());
+        }
+
+        Ok(Some(Self {
+            key_id: key_id.trim().to_string(),
+            kms: OnceCell::new(),
+            current: OnceCell::new(),
+            unwrapped: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    async fn kms(&self) -> &KmsClient {
+        self.kms
+            .get_or_init(|| async { KmsClient::new(sdk_config().await) })
+            .await
+    }
+
+    async fn data_key(&self) -> Result<&DataKey, Error> {
+        self.current
+            .get_or_try_init(|| async {
+                let output = self
+                    .kms()
+                    .await
+                    .generate_data_key()
+                    .key_id(&self.key_id)
+                    .key_spec(DataKeySpec::Aes256)
+                    .send()
+                    .await?;
+                let plaintext = output.plaintext().ok_or("KMS returned no data key")?;
+                let wrapped = output
+                    .ciphertext_blob()
+                    .ok_or("KMS returned no wrapped data key")?;
+                let key_arn = output.key_id().unwrap_or(&self.key_id).to_string();
+                info!("Generated a PII data key under {}", key_arn);
+
+                Ok::<_, Error>(DataKey {
+                    cipher: Aes256Gcm::new_from_slice(plaintext.as_ref())
+                        .map_err(|_| "KMS data key is not 256 bits")?,
+                    wrapped: STANDARD.encode(wrapped.as_ref()),
+                    key_arn,
+                })
+            })
+            .await
+    }
+
+    // The cipher for a record's wrapped data key and the ARN of the KMS key
+    // that wrapped it
+    async fn unwrap(&self, wrapped: &str) -> Result<(Aes256Gcm, String), Error> {
+        if let Some(found) = self.unwrapped.lock().unwrap().get(wrapped) {
+            return Ok(found.clone());
+        }
+
+        // Symmetric KMS ciphertexts name their key, so records written under a
+        // previous PII_KMS_KEY_ID still decrypt
+        let output = self
+            .kms()
+            .await
+            .decrypt()
+            .ciphertext_blob(Blob::new(STANDARD.decode(wrapped)?))
+            .send()
+            .await?;
+        let plaintext = output.plaintext().ok_or("KMS returned no data key")?;
+        let cipher = Aes256Gcm::new_from_slice(plaintext.as_ref())
+            .map_err(|_| "KMS data key is not 256 bits")?;
+        let found = (cipher, output.key_id().unwrap_or_default().to_string());
+
+        self.unwrapped
+            .lock()
+            .unwrap()
+            .insert(wrapped.to_string(), found.clone());
+        Ok(found)
+    }
+
+    // Encrypt a subscriber item about to be written
+    pub async fn seal(&self, item: &mut Item) -> Result<(), Error> {
+        let key = self.data_key().await?;
+
+        if let Some(AttributeValue::S(email)) = item.remove("email") {
+            item.insert(
+                "email_encrypted".to_string(),
+                AttributeValue::S(encrypt(&key.cipher, &email)?),
+            );
+        }
+        item.remove("email_search");
+        item.remove("email_prefix");
+
+        if let Some(AttributeValue::M(fields)) = item.get_mut("fields") {
+            for value in fields.values_mut() {
+                if let AttributeValue::S(text) = value {
+                    *value = AttributeValue::S(encrypt(&key.cipher, text)?);
+                }
+            }
+        }
+
+        item.insert(
+            "pii_key".to_string(),
+            AttributeValue::S(key.wrapped.clone()),
+        );
+        Ok(())
+    }
+
+    // Decrypt a subscriber item as read. Records written without encryption
+    // are left alone.
+    pub async fn open(&self, item: &mut Item) -> Result<(), Error> {
+        let Some(AttributeValue::S(wrapped)) = item.remove("pii_key") else {
+            return Ok(());
+        };
+        let (cipher, _) = self.unwrap(&wrapped).await?;
+
+        if let Some(AttributeValue::S(sealed)) = item.remove("email_encrypted") {
+            item.insert(
+                "email".to_string(),
+                AttributeValue::S(decrypt(&cipher, &sealed)?),
+            );
+        }
+        if let Some(AttributeValue::M(fields)) = item.get_mut("fields") {
+            for value in fields.values_mut() {
+                match value {
+                    AttributeValue::S(text) if text.starts_with(ENCRYPTED_PREFIX) => {
+                        *value = AttributeValue::S(decrypt(&cipher, text)?);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Whether a stored item is in the clear or under another KMS key than
+    // PII_KMS_KEY_ID, and so should be re-encrypted
+    pub async fn is_stale(&self, item: &Item) -> Result<bool, Error> {
+        let Some(AttributeValue::S(wrapped)) = item.get("pii_key") else {
+            return Ok(true);
+        };
+        let (_, key_arn) = self.unwrap(wrapped).await?;
+        Ok(key_arn != self.data_key().await?.key_arn)
+    }
+}
+
+fn encrypt(cipher: &Aes256Gcm, plaintext: &str) -> Result<String, Error> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| "Failed to encrypt attribute")?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(format!("{ENCRYPTED_PREFIX}{}", STANDARD.encode(sealed)))
+}
+
+fn decrypt(cipher: &Aes256Gcm, value: &str) -> Result<String, Error> {
+    let sealed = STANDARD.decode(
+        value
+            .strip_prefix(ENCRYPTED_PREFIX)
+            .ok_or("Attribute is not encrypted")?,
+    )?;
+    if sealed.len() < NONCE_LENGTH {
+        return Err("Encrypted attribute is too short".into());
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Failed to decrypt attribute")?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+// The container's cipher, set up on first use so the data key is shared by
+// every repository
+pub fn cipher() -> Result<Option<&'static PiiCipher>, Error> {
+    static CIPHER: OnceLock<Result<Option<PiiCipher>, String>> = OnceLock::new();
+    match CIPHER.get_or_init(|| PiiCipher::from_env().map_err(|e| e.to_string())) {
+        Ok(cipher) => Ok(cipher.as_ref()),
+        Err(e) => Err(e.clone().into()),
+    }
+}
+
+// Encrypt a subscriber item about to be written, when PII_KMS_KEY_ID is set
+pub async fn seal(item: &mut Item) -> Result<(), Error> {
+    match cipher()? {
+        Some(cipher) => cipher.seal(item).await,
+        None => Ok(()),
+    }
+}
+
+// Decrypt a subscriber item as read. Encrypted records can't be read with
+// encryption turned off.
+pub async fn open(item: &mut Item) -> Result<(), Error> {
+    match cipher()? {
+        Some(cipher) => cipher.open(item).await,
+        None if item.contains_key("pii_key") => {
+            Err("Subscriber is encrypted but PII_KMS_KEY_ID is not set".into())
+        }
+        None => Ok(()),
+    }
+}
+
+fn index_secret() -> Option<&'static str> {
+    static SECRET: OnceLock<Option<String>> = OnceLock::new();
+    SECRET
+        .get_or_init(|| {
+            env::var("PII_INDEX_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty())
+        })
+        .as_deref()
+}
+
+// Key of the email hash index: the SHA-256 of the canonical address in hex.
+// With PII_INDEX_SECRET set it's an HMAC keyed with it instead, so hashes
+// can't be matched by hashing guessed addresses.
+pub fn email_hash(email: &str) -> String {
+    let normalized = canonical_email(email);
+    let hash = match index_secret() {
+        Some(secret) => {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes())
+                .expect("HMAC takes keys of any length");
+            mac.update(normalized.as_bytes());
+            mac.finalize().into_bytes()
+        }
+        None => Sha256::digest(normalized.as_bytes()),
+    };
+    hash.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WRAPPED: &str = "d3JhcHBlZC1rZXk=";
+    const KEY_ARN: &str = "arn:aws:kms:us-east-1:123456789012:key/current";
+
+    fn fixed_cipher(byte: u8) -> Aes256Gcm {
+        Aes256Gcm::new_from_slice(&[byte; 32]).unwrap()
+    }
+
+    // A cipher whose data key is already generated and unwrapped, so KMS is
+    // never called
+    fn test_cipher() -> PiiCipher {
+        let unwrapped =
+            HashMap::from([(WRAPPED.to_string(), (fixed_cipher(1), KEY_ARN.to_string()))]);
+        PiiCipher {
+            key_id: "alias/newsletter-pii".to_string(),
+            kms: OnceCell::new(),
+            current: OnceCell::from(DataKey {
+                cipher: fixed_cipher(1),
+                wrapped: WRAPPED.to_string(),
+                key_arn: KEY_ARN.to_string(),
+            }),
+            unwrapped: Mutex::new(unwrapped),
+        }
+    }
+
+    fn text(item: &Item, name: &str) -> Option<String> {
+        item.get(name).and_then(|value| value.as_s().ok()).cloned()
+    }
+
+    fn subscriber() -> Item {
+        HashMap::from([
+            ("id".to_string(), AttributeValue::S("sub-1".to_string())),
+            (
+                "email".to_string(),
+                AttributeValue::S("alice@example.com".to_string()),
+            ),
+            (
+                "email_search".to_string(),
+                AttributeValue::S("alice@example.com".to_string()),
+            ),
+            (
+                "email_prefix".to_string(),
+                AttributeValue::S("al".to_string()),
+            ),
+            (
+                "domain".to_string(),
+                AttributeValue::S("example.com".to_string()),
+            ),
+            (
+                "fields".to_string(),
+                AttributeValue::M(HashMap::from([(
+                    "name".to_string(),
+                    AttributeValue::S("Alice".to_string()),
+                )])),
+            ),
+        ])
+    }
+
+    #[tokio::test]
+    async fn seal_and_open_round_trip() {
+        let cipher = test_cipher();
+        let mut item = subscriber();
+
+        cipher.seal(&mut item).await.unwrap();
+        assert_eq!(text(&item, "email"), None);
+        assert!(
+            text(&item, "email_encrypted")
+                .unwrap()
+                .starts_with(ENCRYPTED_PREFIX)
+        );
+        assert_eq!(text(&item, "pii_key").as_deref(), Some(WRAPPED));
+        let name = &item["fields"].as_m().unwrap()["name"];
+        assert!(name.as_s().unwrap().starts_with(ENCRYPTED_PREFIX));
+
+        cipher.open(&mut item).await.unwrap();
+        assert_eq!(text(&item, "email").as_deref(), Some("alice@example.com"));
+        assert_eq!(text(&item, "email_encrypted"), None);
+        assert_eq!(text(&item, "pii_key"), None);
+        let name = &item["fields"].as_m().unwrap()["name"];
+        assert_eq!(name.as_s().unwrap(), "Alice");
+    }
+
+    #[tokio::test]
+    async fn seal_drops_the_email_search_indexes() {
+        let mut item = subscriber();
+        test_cipher().seal(&mut item).await.unwrap();
+
+        assert!(!item.contains_key("email_search"));
+        assert!(!item.contains_key("email_prefix"));
+        assert_eq!(text(&item, "domain").as_deref(), Some("example.com"));
+        assert_eq!(text(&item, "id").as_deref(), Some("sub-1"));
+    }
+
+    #[tokio::test]
+    async fn open_leaves_records_in_the_clear_alone() {
+        let mut item = subscriber();
+        test_cipher().open(&mut item).await.unwrap();
+        assert_eq!(item, subscriber());
+    }
+
+    #[tokio::test]
+    async fn is_stale_unless_under_the_current_key() {
+        let cipher = test_cipher();
+        assert!(cipher.is_stale(&subscriber()).await.unwrap());
+
+        let mut item = subscriber();
+        cipher.seal(&mut item).await.unwrap();
+        assert!(!cipher.is_stale(&item).await.unwrap());
+
+        cipher.unwrapped.lock().unwrap().insert(
+            "b2xkLWtleQ==".to_string(),
+            (
+                fixed_cipher(2),
+                "arn:aws:kms:us-east-1:123456789012:key/old".to_string(),
+            ),
+        );
+        item.insert(
+            "pii_key".to_string(),
+            AttributeValue::S("b2xkLWtleQ==".to_string()),
+        );
+        assert!(cipher.is_stale(&item).await.unwrap());
+    }
+
+    #[test]
+    fn encrypt_uses_a_fresh_nonce() {
+        let cipher = fixed_cipher(1);
+        let first = encrypt(&cipher, "alice@example.com").unwrap();
+        let second = encrypt(&cipher, "alice@example.com").unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(decrypt(&cipher, &first).unwrap(), "alice@example.com");
+        assert_eq!(decrypt(&cipher, &second).unwrap(), "alice@example.com");
+    }
+
+    #[test]
+    fn decrypt_rejects_another_key_and_tampering() {
+        let sealed = encrypt(&fixed_cipher(1), "alice@example.com").unwrap();
+        assert!(decrypt(&fixed_cipher(2), &sealed).is_err());
+
+        let mut bytes = STANDARD
+            .decode(sealed.strip_prefix(ENCRYPTED_PREFIX).unwrap())
+            .unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        let tampered = format!("{ENCRYPTED_PREFIX}{}", STANDARD.encode(bytes));
+        assert!(decrypt(&fixed_cipher(1), &tampered).is_err());
+
+        assert!(decrypt(&fixed_cipher(1), "alice@example.com").is_err());
+        assert!(decrypt(&fixed_cipher(1), "enc1:AAAA").is_err());
+    }
+
+    #[test]
+    fn email_hash_is_stable_per_address() {
+        let hash = email_hash("alice@example.com");
+        assert_eq!(hash.len(), 64);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(email_hash("alice@example.com"), hash);
+        assert_eq!(email_hash(" Alice@Example.COM "), hash);
+        assert_ne!(email_hash("bob@example.com"), hash);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::Subscriber;
+use crate::content::render_campaign_html;
+use crate::email::html_to_text;
+use crate::template::{render_text, subscriber_variables};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+// Address of the made-up subscriber previews render for by default
+const SAMPLE_EMAIL: &str = "subscriber@example.com";
+
+// What to preview: a campaign or a template, for a real subscriber or a sample
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PreviewRequest {
+    #[serde(default)]
+    pub campaign_id: Option<String>,
+    #[serde(default)]
+    pub template_id: Option<String>,
+    // Template version, the published one when omitted
+    #[serde(default)]
+    pub version: Option<u32>,
+    // Render for this subscriber instead of the sample one
+    #[serde(default)]
+    pub subscriber_id: Option<String>,
+    // Custom fields of the sample subscriber, e.g. {"first_name": "Ada"}
+    #[serde(default)]
+    pub fields: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PreviewResponse {
+    pub success: bool,
+    pub subject: String,
+    // As sent: with the default footer, and sanitized
+    pub html_body: String,
+    // The plain text part as sent
+    pub text_body: String,
+}
+
+// A subscriber that doesn't exist, for previews without real data
+pub fn sample_subscriber(fields: HashMap<String, String>) -> Subscriber {
+    let mut subscriber = Subscriber::new(SAMPLE_EMAIL.to_string());
+    subscriber.fields = fields;
+    subscriber
+}
+
+// Render a subject and body for a subscriber the way they would be sent as
+// part of a campaign, or a template's id when previewing a template
+pub fn render_preview(
+    subscriber: &Subscriber,
+    subject: &str,
+    html_body: &str,
+    campaign_id: &str,
+    list: Option<&str>,
+) -> PreviewResponse {
+    let html_body = render_campaign_html(html_body, subscriber, campaign_id, list);
+
+    PreviewResponse {
+        success: true,
+        subject: render_text(subject, &subscriber_variables(subscriber)),
+        text_body: html_to_text(&html_body),
+        html_body,
+    }
+}
@@ -0,0 +1,140 @@
+use crate::config::table_name;
+use crate::{Error, PROCESSED_MESSAGES_TABLE_NAME};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{Duration, Utc};
+use tracing::info;
+
+// How long a processed message is remembered, SQS's longest retention period,
+// after which DynamoDB TTL removes it
+pub const PROCESSED_TTL_DAYS: i64 = 14;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Claim {
+    // First delivery, or an earlier one that failed or timed out. The caller
+    // processes the message and then settles it.
+    New,
+    // Already processed by an earlier delivery, so it is skipped
+    Processed,
+    // Another delivery of the message is being processed right now
+    InProgress,
+}
+
+// SQS message ids already processed by a consumer. SQS delivers at least once,
+// so a message can arrive twice; claiming it first means the second delivery
+// doesn't send another email or count the same event again.
+pub struct ProcessedMessages {
+    client: Client,
+    table_name: String,
+    // Consumer name, so several consumers can share the table
+    consumer: &'static str,
+}
+
+impl ProcessedMessages {
+    pub fn new(client: Client, consumer: &'static str) -> Self {
+        Self {
+            client,
+            table_name: table_name(PROCESSED_MESSAGES_TABLE_NAME),
+            consumer,
+        }
+    }
+
+    fn key(&self, message_id: &str) -> AttributeValue {
+        AttributeValue::S(format!("{}#{}", self.consumer, message_id))
+    }
+
+    // Claim a message until `lease_until` (epoch milliseconds), the invocation's
+    // deadline. A claim left behind by an invocation that crashed or timed out
+    // lapses with it, so the redelivery is processed instead of skipped.
+    pub async fn claim(&self, message_id: &str, lease_until: i64) -> Result<Claim, Error> {
+        let now = Utc::now();
+        let expires_at = (now + Duration::days(PROCESSED_TTL_DAYS)).timestamp();
+
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .item("message_id", self.key(message_id))
+            .item("status", AttributeValue::S("in_progress".to_string()))
+            .item("lease_until", AttributeValue::N(lease_until.to_string()))
+            .item("expires_at", AttributeValue::N(expires_at.to_string()))
+            .condition_expression(
+                "attribute_not_exists(message_id) OR (#status = :in_progress AND lease_until < :now)",
+            )
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":in_progress",
+                AttributeValue::S("in_progress".to_string()),
+            )
+            .expression_attribute_values(
+                ":now",
+                AttributeValue::N(now.timestamp_millis().to_string()),
+            )
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => return Ok(Claim::New),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let existing = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("message_id", self.key(message_id))
+            .consistent_read(true)
+            .send()
+            .await?;
+
+        let status = existing
+            .item()
+            .and_then(|item| item.get("status"))
+            .and_then(|value| value.as_s().ok());
+        match status.map(String::as_str) {
+            Some("processed") => Ok(Claim::Processed),
+            // Released between the two calls, or still leased
+            _ => Ok(Claim::InProgress),
+        }
+    }
+
+    // Mark a claimed message processed, or release it so SQS's redelivery is
+    // processed again
+    pub async fn settle(&self, message_id: &str, processed: bool) -> Result<(), Error> {
+        if processed {
+            self.client
+                .update_item()
+                .table_name(&self.table_name)
+                .key("message_id", self.key(message_id))
+                .update_expression("SET #status = :processed REMOVE lease_until")
+                .expression_attribute_names("#status", "status")
+                .expression_attribute_values(
+                    ":processed",
+                    AttributeValue::S("processed".to_string()),
+                )
+                .send()
+                .await?;
+        } else {
+            self.client
+                .delete_item()
+                .table_name(&self.table_name)
+                .key("message_id", self.key(message_id))
+                .send()
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // The message's outcome stands either way. A processed message that can't
+    // be marked is only at risk of a duplicate, and a failed one stays claimed
+    // until its lease lapses.
+    pub async fn settle_or_log(&self, message_id: &str, processed: bool) {
+        if let Err(e) = self.settle(message_id, processed).await {
+            info!("Error settling message {}: {:?}", message_id, e);
+        }
+    }
+}
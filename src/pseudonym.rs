@@ -0,0 +1,114 @@
+use crate::address::canonical_email;
+use crate::admin::SubscriberStatus;
+use crate::search::email_domain;
+use crate::{Error, Subscriber};
+use chrono::NaiveDate;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+
+// Domains with fewer subscribers than this show as "other", since a small
+// company domain can single people out
+pub const DEFAULT_MIN_DOMAIN_COUNT: usize = 10;
+
+const OTHER_DOMAIN: &str = "other";
+
+// One subscriber in an analytics export, without anything that identifies them
+#[derive(Debug, Serialize)]
+pub struct PseudonymousRow {
+    // The same for an address in every export made with the same secret
+    pub pseudonym: String,
+    pub email_domain: String,
+    pub status: SubscriberStatus,
+    // Dates only, times could be matched against other logs
+    pub created_on: NaiveDate,
+    pub last_engaged_on: Option<NaiveDate>,
+    pub engagement_score: f64,
+    pub locale: String,
+    pub frequency: String,
+    pub tier: String,
+    pub source: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub referral_count: u32,
+    // Only the custom fields asked for, as JSON
+    pub fields: String,
+}
+
+// Replaces email addresses with stable pseudonymous ids: the HMAC-SHA256 of
+// the trimmed, lowercased address keyed with a secret kept away from the
+// analysts, so ids can't be matched by hashing guessed addresses
+pub struct Pseudonymizer {
+    secret: String,
+    // Custom fields safe to include, e.g. "plan" but not "name"
+    fields: Vec<String>,
+    min_domain_count: usize,
+}
+
+impl Pseudonymizer {
+    pub fn new(secret: &str, fields: Vec<String>, min_domain_count: usize) -> Result<Self, Error> {
+        if secret.len() < 32 {
+            return Err("The pseudonym secret must be at least 32 characters".into());
+        }
+        Ok(Self {
+            secret: secret.to_string(),
+            fields,
+            min_domain_count,
+        })
+    }
+
+    pub fn pseudonym(&self, email: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(canonical_email(email).as_bytes());
+        // 128 bits is plenty to keep ids apart and keeps them short
+        mac.finalize().into_bytes()[..16]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    // Rows for every subscriber, in the order given
+    pub fn rows(&self, subscribers: &[Subscriber]) -> Result<Vec<PseudonymousRow>, Error> {
+        let mut domain_counts: HashMap<String, usize> = HashMap::new();
+        for subscriber in subscribers {
+            if let Some(domain) = email_domain(&subscriber.email) {
+                *domain_counts.entry(domain).or_default() += 1;
+            }
+        }
+
+        subscribers
+            .iter()
+            .map(|subscriber| {
+                let domain = email_domain(&subscriber.email)
+                    .filter(|domain| domain_counts[domain] >= self.min_domain_count)
+                    .unwrap_or_else(|| OTHER_DOMAIN.to_string());
+                let fields: BTreeMap<&String, &String> = subscriber
+                    .fields
+                    .iter()
+                    .filter(|(name, _)| self.fields.contains(name))
+                    .collect();
+
+                Ok(PseudonymousRow {
+                    pseudonym: self.pseudonym(&subscriber.email),
+                    email_domain: domain,
+                    status: SubscriberStatus::of(subscriber),
+                    created_on: subscriber.created_at.date_naive(),
+                    last_engaged_on: subscriber.last_engaged_at.map(|at| at.date_naive()),
+                    engagement_score: subscriber.engagement_score,
+                    locale: subscriber.locale.as_str().to_string(),
+                    frequency: subscriber.frequency.as_str().to_string(),
+                    tier: subscriber.tier.as_str().to_string(),
+                    source: subscriber.attribution.source.clone(),
+                    utm_source: subscriber.attribution.utm_source.clone(),
+                    utm_medium: subscriber.attribution.utm_medium.clone(),
+                    utm_campaign: subscriber.attribution.utm_campaign.clone(),
+                    referral_count: subscriber.referral_count,
+                    fields: serde_json::to_string(&fields)?,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,69 @@
+use crate::counters::SubscriberCounts;
+use crate::domains::DomainStats;
+use chrono::NaiveDate;
+
+// The numbers in the owner's daily summary email, for one UTC day
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pulse {
+    pub day: NaiveDate,
+    pub confirmed: u64,
+    pub unsubscribed: u64,
+    pub bounces: u64,
+    pub complaints: u64,
+    // Subscribers who can be mailed at the time of the run
+    pub total: u64,
+}
+
+impl Pulse {
+    pub fn new(day: NaiveDate, stats: &DomainStats, counts: &SubscriberCounts) -> Self {
+        Self {
+            day,
+            confirmed: stats.confirmed,
+            unsubscribed: stats.unsubscribed,
+            bounces: stats.bounces,
+            complaints: stats.complaints,
+            total: counts.mailable,
+        }
+    }
+
+    // Confirmations minus unsubscribes, e.g. "+12"
+    pub fn net(&self) -> String {
+        let net = self.confirmed as i64 - self.unsubscribed as i64;
+        if net > 0 {
+            format!("+{net}")
+        } else {
+            net.to_string()
+        }
+    }
+}
+
+// Subject and HTML body of the pulse email
+pub fn render_pulse(pulse: &Pulse) -> (String, String) {
+    let subject = format!(
+        "Newsletter pulse for {}: {} subscribers ({})",
+        pulse.day.format("%Y-%m-%d"),
+        pulse.total,
+        pulse.net()
+    );
+
+    let rows: String = [
+        ("New confirmed subscribers", pulse.confirmed.to_string()),
+        ("Unsubscribes", pulse.unsubscribed.to_string()),
+        ("Bounces", pulse.bounces.to_string()),
+        ("Complaints", pulse.complaints.to_string()),
+        ("Current total", pulse.total.to_string()),
+    ]
+    .iter()
+    .map(|(label, value)| {
+        format!("<tr><td>{label}</td><td align=\"right\"><strong>{value}</strong></td></tr>\n")
+    })
+    .collect();
+
+    let body = format!(
+        "<h2>{}</h2>\n<table cellpadding=\"4\">\n{}</table>\n",
+        pulse.day.format("%A, %B %-d"),
+        rows
+    );
+
+    (subject, body)
+}
@@ -0,0 +1,136 @@
+use crate::config::env_or;
+use crate::quota::{QuotaKind, QuotaStore};
+use lambda_http::request::RequestContext;
+use lambda_http::{Request, RequestExt};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+
+// Signups allowed from one IP address per hour before the rest are quarantined
+pub const DEFAULT_SIGNUP_BURST_LIMIT: u64 = 5;
+
+// Stems that dictionary attacks pair with digits, plus keyboard runs
+const DICTIONARY_WORDS: &[&str] = &[
+    "abc",
+    "admin",
+    "administrator",
+    "asdf",
+    "contact",
+    "demo",
+    "email",
+    "example",
+    "foo",
+    "guest",
+    "hello",
+    "info",
+    "mail",
+    "noreply",
+    "office",
+    "postmaster",
+    "qwerty",
+    "root",
+    "sales",
+    "spam",
+    "support",
+    "test",
+    "tester",
+    "user",
+    "webmaster",
+];
+
+// Why a signup was held back until an admin approves it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QuarantineReason {
+    // Too many signups from the same IP address within the hour
+    SignupBurst,
+    // A local part like those dictionary attacks generate, e.g. admin42 or xkqzvtrp
+    DictionaryLocalPart,
+}
+
+impl QuarantineReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuarantineReason::SignupBurst => "signup_burst",
+            QuarantineReason::DictionaryLocalPart => "dictionary_local_part",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "signup_burst" => Some(QuarantineReason::SignupBurst),
+            "dictionary_local_part" => Some(QuarantineReason::DictionaryLocalPart),
+            _ => None,
+        }
+    }
+}
+
+// Address the request came from, as seen by API Gateway
+pub fn source_ip(event: &Request) -> Option<String> {
+    match event.request_context_ref() {
+        Some(RequestContext::ApiGatewayV1(context)) => context.identity.source_ip.clone(),
+        _ => event
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty()),
+    }
+}
+
+// Whether the local part looks generated: a common word with only digits or
+// separators around it, or a long run of consonants no name has
+pub fn is_dictionary_local_part(email: &str) -> bool {
+    let Some((local, _)) = email.rsplit_once('@') else {
+        return false;
+    };
+    let local = local.split('+').next().unwrap_or(local).to_lowercase();
+
+    let stem = local.trim_matches(|c: char| c.is_ascii_digit() || matches!(c, '.' | '-' | '_'));
+    if DICTIONARY_WORDS.contains(&stem) {
+        return true;
+    }
+
+    let mut consonants = 0;
+    for c in local.chars() {
+        if c.is_ascii_alphabetic() && !matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y') {
+            consonants += 1;
+            if consonants >= 6 {
+                return true;
+            }
+        } else {
+            consonants = 0;
+        }
+    }
+
+    false
+}
+
+// Decide whether a signup should be quarantined. Every signup counts towards
+// its IP's hourly limit from SIGNUP_BURST_LIMIT (0 turns the check off), and
+// QUARANTINE_DICTIONARY_LOCAL_PARTS=false turns off the local part check.
+// Counter errors let the signup through.
+pub async fn assess_signup(
+    quotas: &QuotaStore,
+    email: &str,
+    source_ip: Option<&str>,
+) -> Option<QuarantineReason> {
+    let limit = env_or("SIGNUP_BURST_LIMIT", DEFAULT_SIGNUP_BURST_LIMIT);
+    if let Some(ip) = source_ip.filter(|_| limit > 0) {
+        match quotas
+            .consume(&format!("ip:{ip}"), QuotaKind::Signups, limit, 1)
+            .await
+        {
+            Ok(status) if !status.allowed => return Some(QuarantineReason::SignupBurst),
+            Ok(_) => {}
+            Err(e) => info!("Error counting signups from {}: {:?}", ip, e),
+        }
+    }
+
+    if env_or("QUARANTINE_DICTIONARY_LOCAL_PARTS", true) && is_dictionary_local_part(email) {
+        return Some(QuarantineReason::DictionaryLocalPart);
+    }
+
+    None
+}
@@ -0,0 +1,173 @@
+use crate::{Error, ValidationMessage};
+use aws_sdk_sqs::Client as SqsClient;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+pub const VALIDATE_EMAIL_ACTION: &str = "validate_email";
+// A confirmation for an unsubscribed subscriber who signed up again. Their
+// record isn't purged if they don't confirm, unlike a new signup's.
+pub const REACTIVATE_ACTION: &str = "reactivate";
+
+// Partition key of the enqueue-pending index, the same on every subscriber
+// whose validation message is waiting to be queued
+pub const ENQUEUE_PENDING_PARTITION: &str = "pending";
+
+// Schema version written into every queue payload this build produces
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Versions of the queue payloads. Consumers read every version up to the one
+// they write and leave newer ones on the queue, so producers and consumers can
+// be deployed in either order without breaking messages in flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SchemaVersion {
+    // Written before payloads carried a schema_version
+    Unversioned,
+    V1,
+}
+
+impl SchemaVersion {
+    pub fn parse(version: u64) -> Option<Self> {
+        match version {
+            0 => Some(SchemaVersion::Unversioned),
+            1 => Some(SchemaVersion::V1),
+            _ => None,
+        }
+    }
+}
+
+// A queue payload that can be read from any supported version
+pub trait QueuePayload: Sized {
+    // Read a payload written as `version`, upgraded to the current schema
+    fn read(version: SchemaVersion, value: Value) -> Result<Self, serde_json::Error>;
+}
+
+// Payloads whose fields haven't changed since versioning was introduced read
+// every version the same way, stamped with the current version
+pub fn read_current<T: DeserializeOwned>(mut value: Value) -> Result<T, serde_json::Error> {
+    if let Some(object) = value.as_object_mut() {
+        object.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    }
+    serde_json::from_value(value)
+}
+
+#[derive(Debug)]
+pub enum PayloadError {
+    // Not JSON, or missing fields of its version. Retrying can't help.
+    Malformed(serde_json::Error),
+    // Written by a newer producer, readable once this consumer is upgraded
+    UnsupportedVersion(u64),
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadError::Malformed(e) => write!(f, "malformed payload: {e}"),
+            PayloadError::UnsupportedVersion(version) => {
+                write!(f, "unsupported schema version {version}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+// Parse a queue message body of any supported schema version
+pub fn decode<T: QueuePayload>(body: &str) -> Result<T, PayloadError> {
+    let value: Value = serde_json::from_str(body).map_err(PayloadError::Malformed)?;
+    let number = match value.get("schema_version") {
+        None => 0,
+        Some(version) => version.as_u64().ok_or_else(|| {
+            PayloadError::Malformed(serde_json::Error::custom("schema_version is not a number"))
+        })?,
+    };
+    let version = SchemaVersion::parse(number).ok_or(PayloadError::UnsupportedVersion(number))?;
+    T::read(version, value).map_err(PayloadError::Malformed)
+}
+
+// FIFO queue names (and so their URLs and ARNs) always end in .fifo
+pub fn is_fifo(queue: &str) -> bool {
+    queue.ends_with(".fifo")
+}
+
+// SQS event delivered to the queue worker Lambdas
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SqsEvent {
+    #[serde(rename = "Records")]
+    pub records: Vec<SqsRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SqsRecord {
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+    #[serde(rename = "body")]
+    pub body: String,
+    #[serde(rename = "eventSourceARN", default)]
+    pub event_source_arn: String,
+}
+
+// Partial batch response: only the listed records are retried, and moved to the
+// dead-letter queue once they run out of receives
+#[derive(Debug, Default, Serialize)]
+pub struct SqsBatchResponse {
+    #[serde(rename = "batchItemFailures")]
+    pub batch_item_failures: Vec<BatchItemFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchItemFailure {
+    #[serde(rename = "itemIdentifier")]
+    pub item_identifier: String,
+}
+
+impl SqsBatchResponse {
+    pub fn fail(&mut self, message_id: String) {
+        self.batch_item_failures.push(BatchItemFailure {
+            item_identifier: message_id,
+        });
+    }
+
+    pub fn has_failed(&self, message_id: &str) -> bool {
+        self.batch_item_failures
+            .iter()
+            .any(|failure| failure.item_identifier == message_id)
+    }
+}
+
+// Publishes validation messages to a standard or FIFO queue. FIFO queues are
+// expected to have content-based deduplication on: messages for the same
+// subscriber have identical bodies, so a retried subscribe within the 5 minute
+// deduplication window is dropped instead of sending a second email.
+pub struct ValidationQueue {
+    client: SqsClient,
+    queue_url: String,
+}
+
+impl ValidationQueue {
+    pub fn new(client: SqsClient, queue_url: String) -> Self {
+        Self { client, queue_url }
+    }
+
+    pub fn queue_url(&self) -> &str {
+        &self.queue_url
+    }
+
+    pub async fn send(&self, message: &ValidationMessage) -> Result<(), Error> {
+        let mut request = self
+            .client
+            .send_message()
+            .queue_url(&self.queue_url)
+            .message_body(serde_json::to_string(message)?);
+
+        // One group per subscriber keeps their messages in order without
+        // serializing the whole queue
+        if is_fifo(&self.queue_url) {
+            request = request.message_group_id(&message.subscriber_id);
+        }
+
+        request.send().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use crate::config::table_name;
+use crate::{Error, QUOTAS_TABLE_NAME};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaKind {
+    // API requests, reset every UTC day
+    Requests,
+    // Campaign emails, reset every UTC month
+    Sends,
+    // Signups from one IP address, reset every UTC hour
+    Signups,
+    // Confirmation emails sent again to one subscriber, reset every UTC day
+    Resends,
+}
+
+impl QuotaKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaKind::Requests => "requests",
+            QuotaKind::Sends => "sends",
+            QuotaKind::Signups => "signups",
+            QuotaKind::Resends => "resends",
+        }
+    }
+
+    // Identifier of the current window, e.g. "2024-05-17" or "2024-05"
+    fn period(&self, now: DateTime<Utc>) -> String {
+        match self {
+            QuotaKind::Requests | QuotaKind::Resends => now.format("%Y-%m-%d").to_string(),
+            QuotaKind::Sends => now.format("%Y-%m").to_string(),
+            QuotaKind::Signups => now.format("%Y-%m-%dT%H").to_string(),
+        }
+    }
+
+    // When the current window ends
+    pub fn reset_at(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now.date_naive();
+        let next = match self {
+            QuotaKind::Requests | QuotaKind::Resends => today + Duration::days(1),
+            QuotaKind::Sends => {
+                let (year, month) = if today.month() == 12 {
+                    (today.year() + 1, 1)
+                } else {
+                    (today.year(), today.month() + 1)
+                };
+                NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(today)
+            }
+            QuotaKind::Signups => {
+                return today
+                    .and_hms_opt(now.hour(), 0, 0)
+                    .map(|hour| hour.and_utc() + Duration::hours(1))
+                    .unwrap_or(now);
+            }
+        };
+        next.and_hms_opt(0, 0, 0)
+            .map(|midnight| midnight.and_utc())
+            .unwrap_or(now)
+    }
+}
+
+// Usage of one quota window after a consume attempt
+#[derive(Debug, Clone)]
+pub struct QuotaStatus {
+    pub allowed: bool,
+    pub limit: u64,
+    pub used: u64,
+    pub reset_at: DateTime<Utc>,
+}
+
+impl QuotaStatus {
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used)
+    }
+}
+
+// Per-key usage counters, one item per key, kind and window, removed by DynamoDB TTL
+pub struct QuotaStore {
+    client: Client,
+    table_name: String,
+}
+
+impl QuotaStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(QUOTAS_TABLE_NAME),
+        }
+    }
+
+    // Atomically add `amount` to the key's usage unless that would exceed `limit`.
+    // Nothing is counted when the quota is exceeded.
+    pub async fn consume(
+        &self,
+        key_id: &str,
+        kind: QuotaKind,
+        limit: u64,
+        amount: u64,
+    ) -> Result<QuotaStatus, Error> {
+        let now = Utc::now();
+        let reset_at = kind.reset_at(now);
+        let quota_key = format!("{}#{}#{}", key_id, kind.as_str(), kind.period(now));
+
+        if amount > limit {
+            let used = self.used(&quota_key).await?;
+            return Ok(QuotaStatus {
+                allowed: false,
+                limit,
+                used,
+                reset_at,
+            });
+        }
+
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("quota_key", AttributeValue::S(quota_key.clone()))
+            .update_expression("ADD used :amount SET expires_at = :expires_at")
+            .condition_expression("attribute_not_exists(used) OR used <= :max_used")
+            .expression_attribute_values(":amount", AttributeValue::N(amount.to_string()))
+            .expression_attribute_values(
+                ":max_used",
+                AttributeValue::N((limit - amount).to_string()),
+            )
+            // Keep the counter a day past the window for inspection
+            .expression_attribute_values(
+                ":expires_at",
+                AttributeValue::N((reset_at + Duration::days(1)).timestamp().to_string()),
+            )
+            .return_values(ReturnValue::UpdatedNew)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => {
+                let used = output
+                    .attributes()
+                    .and_then(|attributes| attributes.get("used"))
+                    .and_then(|value| value.as_n().ok())
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(amount);
+                Ok(QuotaStatus {
+                    allowed: true,
+                    limit,
+                    used,
+                    reset_at,
+                })
+            }
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                let used = self.used(&quota_key).await?;
+                Ok(QuotaStatus {
+                    allowed: false,
+                    limit,
+                    used,
+                    reset_at,
+                })
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn used(&self, quota_key: &str) -> Result<u64, Error> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("quota_key", AttributeValue::S(quota_key.to_string()))
+            .send()
+            .await?;
+
+        Ok(result
+            .item()
+            .and_then(|item| item.get("used"))
+            .and_then(|value| value.as_n().ok())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0))
+    }
+}
@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+use std::env;
+
+// What an API key or token holder may do, see `Role::allows`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Admin,
+}
+
+// An operation through the admin API or gRPC, the unit roles are granted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    // Subscribers, their history, import previews and jobs
+    ReadSubscribers,
+    // Approving, changing and unsubscribing or suppressing subscribers, and
+    // starting or cancelling jobs over them
+    WriteSubscribers,
+    // Campaigns and templates, their previews and link checks
+    ReadCampaigns,
+    // Creating and editing campaigns and templates
+    WriteCampaigns,
+    // Sending, scheduling and cancelling campaigns
+    SendCampaigns,
+    // Stats and the audit log
+    ReadReports,
+    // Senders, domain rules and invites
+    ReadSettings,
+    // Changing or deleting senders, domain rules and invites, and replaying
+    // opt-outs
+    WriteSettings,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::ReadSubscribers => "read_subscribers",
+            Operation::WriteSubscribers => "write_subscribers",
+            Operation::ReadCampaigns => "read_campaigns",
+            Operation::WriteCampaigns => "write_campaigns",
+            Operation::SendCampaigns => "send_campaigns",
+            Operation::ReadReports => "read_reports",
+            Operation::ReadSettings => "read_settings",
+            Operation::WriteSettings => "write_settings",
+        }
+    }
+
+    // The operation a request to an admin route performs, `route` being the
+    // path after /admin. Routes not listed here need the admin role.
+    pub fn for_route(method: &str, route: &str) -> Self {
+        let under = |prefix: &str| route.starts_with(prefix);
+
+        if !crate::admin::is_mutation(method, route) {
+            return if under("/subscribers") || under("/import") || under("/jobs") {
+                Operation::ReadSubscribers
+            } else if under("/campaigns") || under("/templates") || under("/preview") {
+                Operation::ReadCampaigns
+            } else if under("/stats") || under("/audit") {
+                Operation::ReadReports
+            } else if under("/senders") || under("/domain-rules") || under("/invites") {
+                Operation::ReadSettings
+            } else {
+                Operation::WriteSettings
+            };
+        }
+
+        if under("/campaigns/") && (route.ends_with("/send") || route.ends_with("/cancel")) {
+            Operation::SendCampaigns
+        } else if under("/campaigns") || under("/templates") {
+            Operation::WriteCampaigns
+        } else if under("/subscribers") || under("/jobs") {
+            Operation::WriteSubscribers
+        } else {
+            Operation::WriteSettings
+        }
+    }
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "viewer" => Some(Role::Viewer),
+            "editor" => Some(Role::Editor),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    // Viewers only read, editors also run campaigns and change subscribers,
+    // admins can do everything
+    pub fn allows(&self, operation: Operation) -> bool {
+        use Operation::*;
+
+        match self {
+            Role::Viewer => matches!(
+                operation,
+                ReadSubscribers | ReadCampaigns | ReadReports | ReadSettings
+            ),
+            Role::Editor => !matches!(operation, WriteSettings),
+            Role::Admin => true,
+        }
+    }
+
+    // The highest role any of a token's groups maps to. JWT_ROLE_GROUPS maps
+    // groups to roles, e.g. "admin=newsletter-admins,editor=marketing";
+    // without it groups named viewer, editor and admin grant those roles.
+    pub fn for_groups(groups: &[String]) -> Option<Self> {
+        let mapping: Vec<(Role, String)> = match env::var("JWT_ROLE_GROUPS") {
+            Ok(value) if !value.trim().is_empty() => value
+                .split(',')
+                .filter_map(|pair| pair.split_once('='))
+                .filter_map(|(role, group)| {
+                    Some((Role::parse(role.trim())?, group.trim().to_string()))
+                })
+                .collect(),
+            _ => [Role::Viewer, Role::Editor, Role::Admin]
+                .into_iter()
+                .map(|role| (role, role.as_str().to_string()))
+                .collect(),
+        };
+
+        mapping
+            .into_iter()
+            .filter(|(_, group)| groups.contains(group))
+            .map(|(role, _)| role)
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Operation::*;
+
+    #[test]
+    fn maps_admin_routes_to_operations() {
+        let routes = [
+            ("GET", "/subscribers", ReadSubscribers),
+            ("POST", "/subscribers", WriteSubscribers),
+            ("GET", "/subscribers/search", ReadSubscribers),
+            ("GET", "/subscribers/changes", ReadSubscribers),
+            ("GET", "/subscribers/lookup", ReadSubscribers),
+            ("GET", "/subscribers/abc", ReadSubscribers),
+            ("GET", "/subscribers/abc/history", ReadSubscribers),
+            ("PUT", "/subscribers/abc/frequency", WriteSubscribers),
+            ("POST", "/subscribers/abc/approve", WriteSubscribers),
+            ("POST", "/subscribers/abc/confirm", WriteSubscribers),
+            ("POST", "/import/preview", ReadSubscribers),
+            ("GET", "/jobs", ReadSubscribers),
+            ("POST", "/jobs", WriteSubscribers),
+            ("GET", "/jobs/abc", ReadSubscribers),
+            ("POST", "/jobs/abc/cancel", WriteSubscribers),
+            ("GET", "/templates", ReadCampaigns),
+            ("POST", "/templates", WriteCampaigns),
+            ("GET", "/templates/abc", ReadCampaigns),
+            ("PUT", "/templates/abc", WriteCampaigns),
+            ("POST", "/templates/abc/publish", WriteCampaigns),
+            ("POST", "/preview", ReadCampaigns),
+            ("POST", "/campaigns/abc/check-links", ReadCampaigns),
+            ("PUT", "/campaigns/abc/exclusions", WriteCampaigns),
+            ("POST", "/campaigns/abc/send", SendCampaigns),
+            ("POST", "/campaigns/abc/cancel", SendCampaigns),
+            ("GET", "/audit", ReadReports),
+            ("GET", "/stats/sources", ReadReports),
+            ("GET", "/stats/referrals", ReadReports),
+            ("GET", "/stats/domains", ReadReports),
+            ("GET", "/stats/subscribers", ReadReports),
+            ("GET", "/senders", ReadSettings),
+            ("POST", "/senders", WriteSettings),
+            ("GET", "/senders/abc", ReadSettings),
+            ("DELETE", "/senders/abc", WriteSettings),
+            ("GET", "/domain-rules", ReadSettings),
+            ("PUT", "/domain-rules/example.com", WriteSettings),
+            ("DELETE", "/domain-rules/example.com", WriteSettings),
+            ("GET", "/invites", ReadSettings),
+            ("POST", "/invites", WriteSettings),
+            ("DELETE", "/invites/abc", WriteSettings),
+            ("POST", "/opt-outs/replay", WriteSettings),
+        ];
+
+        for (method, route, operation) in routes {
+            assert_eq!(
+                Operation::for_route(method, route),
+                operation,
+                "{method} {route}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_routes_need_the_admin_role() {
+        for method in ["GET", "POST", "DELETE"] {
+            let operation = Operation::for_route(method, "/backups");
+            assert!(!Role::Viewer.allows(operation), "{method}");
+            assert!(!Role::Editor.allows(operation), "{method}");
+            assert!(Role::Admin.allows(operation), "{method}");
+        }
+    }
+
+    #[test]
+    fn roles_grant_increasing_operations() {
+        assert!(Role::Viewer.allows(ReadCampaigns));
+        assert!(!Role::Viewer.allows(WriteSubscribers));
+        assert!(Role::Editor.allows(SendCampaigns));
+        assert!(!Role::Editor.allows(WriteSettings));
+        assert!(Role::Admin.allows(WriteSettings));
+    }
+}
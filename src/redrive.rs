@@ -0,0 +1,256 @@
+use crate::queue::{PayloadError, ValidationQueue, decode};
+use crate::repository::SubscriberRepository;
+use crate::{Error, ValidationMessage};
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_sqs::Client as SqsClient;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Messages stay hidden this long after being received, covering a whole run
+const VISIBILITY_TIMEOUT_SECONDS: i32 = 300;
+
+// Why a message ended up in the validation dead-letter queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    // Not a validation message, retrying can't help
+    BadMessage,
+    // Written with a schema version this build can't read, so it's left in the
+    // dead-letter queue for an upgraded newsletterctl
+    NewerVersion,
+    // The subscriber was deleted since signing up
+    MissingSubscriber,
+    // Confirmed in the meantime, e.g. through a reminder email
+    AlreadyConfirmed,
+    // The subscriber is still waiting for the email, so the failure was
+    // transient (throttling, SES or DynamoDB errors)
+    Retryable,
+}
+
+impl FailureClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureClass::BadMessage => "bad_message",
+            FailureClass::NewerVersion => "newer_version",
+            FailureClass::MissingSubscriber => "missing_subscriber",
+            FailureClass::AlreadyConfirmed => "already_confirmed",
+            FailureClass::Retryable => "retryable",
+        }
+    }
+}
+
+// Look at a dead-lettered message body and decide what to do with it
+pub async fn classify(
+    repository: &SubscriberRepository,
+    body: &str,
+) -> Result<FailureClass, Error> {
+    let message: ValidationMessage = match decode(body) {
+        Ok(message) => message,
+        Err(PayloadError::UnsupportedVersion(_)) => return Ok(FailureClass::NewerVersion),
+        Err(PayloadError::Malformed(_)) => return Ok(FailureClass::BadMessage),
+    };
+
+    Ok(match repository.get(&message.subscriber_id).await? {
+        None => FailureClass::MissingSubscriber,
+        Some(subscriber) if subscriber.validated => FailureClass::AlreadyConfirmed,
+        Some(_) => FailureClass::Retryable,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArchivedMessage {
+    pub message_id: String,
+    pub class: FailureClass,
+    pub body: String,
+}
+
+// Outcome of a redrive run, written next to the archived messages
+#[derive(Debug, Serialize)]
+pub struct RedriveReport {
+    pub started_at: DateTime<Utc>,
+    pub dry_run: bool,
+    pub counts: BTreeMap<FailureClass, usize>,
+    pub requeued: usize,
+    pub archived: usize,
+    // S3 key of the archived messages, when there were any
+    pub archive_key: Option<String>,
+}
+
+// Moves retryable messages from the dead-letter queue back to the source queue
+// and archives everything else to S3
+pub struct Redriver {
+    pub sqs: SqsClient,
+    pub s3: S3Client,
+    pub repository: SubscriberRepository,
+    pub queue: ValidationQueue,
+    pub dlq_url: String,
+    pub bucket: String,
+    pub prefix: String,
+}
+
+impl Redriver {
+    // Process up to `limit` messages. With `dry_run` nothing is sent, written or
+    // deleted, and the messages reappear once their visibility timeout expires.
+    pub async fn run(&self, limit: usize, dry_run: bool) -> Result<RedriveReport, Error> {
+        let started_at = Utc::now();
+        let mut counts = BTreeMap::new();
+        let mut requeued = 0;
+        let mut archived = Vec::new();
+        let mut archived_handles = Vec::new();
+        let mut seen = 0;
+
+        while seen < limit {
+            let result = self
+                .sqs
+                .receive_message()
+                .queue_url(&self.dlq_url)
+                .max_number_of_messages((limit - seen).min(10) as i32)
+                .visibility_timeout(VISIBILITY_TIMEOUT_SECONDS)
+                .wait_time_seconds(1)
+                .send()
+                .await?;
+
+            let messages = result.messages().unwrap_or_default();
+            if messages.is_empty() {
+                break;
+            }
+            seen += messages.len();
+
+            for message in messages {
+                let body = message.body().unwrap_or_default();
+                let message_id = message.message_id().unwrap_or_default().to_string();
+                let class = classify(&self.repository, body).await?;
+                *counts.entry(class).or_insert(0) += 1;
+
+                // Reappears in the queue once its visibility timeout expires
+                if dry_run || class == FailureClass::NewerVersion {
+                    continue;
+                }
+
+                if class == FailureClass::Retryable {
+                    // Retryable messages always parse; sending through the queue
+                    // sets the message group on FIFO queues
+                    let validation: ValidationMessage = decode(body)?;
+                    self.queue.send(&validation).await?;
+                    self.delete(message.receipt_handle()).await?;
+                    requeued += 1;
+                } else {
+                    archived.push(ArchivedMessage {
+                        message_id,
+                        class,
+                        body: body.to_string(),
+                    });
+                    archived_handles.push(message.receipt_handle().map(str::to_string));
+                }
+            }
+        }
+
+        let mut report = RedriveReport {
+            started_at,
+            dry_run,
+            counts,
+            requeued,
+            archived: archived.len(),
+            archive_key: None,
+        };
+
+        if !dry_run {
+            let timestamp = started_at.format("%Y%m%dT%H%M%SZ");
+            if !archived.is_empty() {
+                let key = format!("{}/{}-messages.ndjson", self.prefix, timestamp);
+                let mut body = Vec::new();
+                for message in &archived {
+                    serde_json::to_writer(&mut body, message)?;
+                    body.push(b'\n');
+                }
+                self.put(&key, "application/x-ndjson", body).await?;
+                report.archive_key = Some(key);
+
+                // Only drop the messages once they're safely archived
+                for handle in &archived_handles {
+                    self.delete(handle.as_deref()).await?;
+                }
+            }
+
+            let key = format!("{}/{}-report.json", self.prefix, timestamp);
+            self.put(
+                &key,
+                "application/json",
+                serde_json::to_vec_pretty(&report)?,
+            )
+            .await?;
+        }
+
+        Ok(report)
+    }
+
+    async fn delete(&self, receipt_handle: Option<&str>) -> Result<(), Error> {
+        let Some(receipt_handle) = receipt_handle else {
+            return Ok(());
+        };
+        self.sqs
+            .delete_message()
+            .queue_url(&self.dlq_url)
+            .receipt_handle(receipt_handle)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn put(&self, key: &str, content_type: &str, body: Vec<u8>) -> Result<(), Error> {
+        self.s3
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+        Ok(())
+    }
+}
+
+// A validation message the worker couldn't parse, kept with the parse error so
+// whatever enqueued it can be fixed
+#[derive(Debug, Serialize)]
+pub struct PoisonMessage {
+    pub message_id: String,
+    pub received_at: DateTime<Utc>,
+    pub error: String,
+    pub body: String,
+}
+
+// Where the validate worker quarantines malformed messages instead of leaving
+// them to be retried until they reach the dead-letter queue
+pub struct PoisonArchive {
+    s3: S3Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl PoisonArchive {
+    pub fn new(s3: S3Client, bucket: String, prefix: String) -> Self {
+        Self { s3, bucket, prefix }
+    }
+
+    // Write the message as `<prefix>/<date>/<message id>.json`, returning the key
+    pub async fn store(&self, message: &PoisonMessage) -> Result<String, Error> {
+        let key = format!(
+            "{}/{}/{}.json",
+            self.prefix,
+            message.received_at.format("%Y-%m-%d"),
+            message.message_id
+        );
+        self.s3
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type("application/json")
+            .body(ByteStream::from(serde_json::to_vec_pretty(message)?))
+            .send()
+            .await?;
+        Ok(key)
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{Subscriber, frontend_url};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::env;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// Milestones used when REFERRAL_MILESTONES isn't set
+const DEFAULT_MILESTONES: [u32; 4] = [1, 3, 5, 10];
+
+// Short, case-insensitive code that is easy to share, e.g. "7F3A9C2B"
+pub fn generate_code() -> String {
+    Uuid::new_v4().simple().to_string()[..8].to_ascii_uppercase()
+}
+
+// Codes are compared in upper case, so "7f3a9c2b" in a link still resolves
+pub fn normalize_code(code: &str) -> Option<String> {
+    let code = code.trim().to_ascii_uppercase();
+    if code.is_empty() || code.len() > 32 || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(code)
+}
+
+// Link a subscriber shares with friends, pointing at the frontend signup page
+pub fn referral_url(code: &str) -> String {
+    format!("{}/?ref={}", frontend_url(), code)
+}
+
+// Referral counts that unlock a reward, from a comma-separated REFERRAL_MILESTONES
+pub fn milestones() -> Vec<u32> {
+    let mut milestones: Vec<u32> = env::var("REFERRAL_MILESTONES")
+        .ok()
+        .map(|value| {
+            value
+                .split(',')
+                .filter_map(|milestone| milestone.trim().parse().ok())
+                .filter(|milestone| *milestone > 0)
+                .collect()
+        })
+        .filter(|milestones: &Vec<u32>| !milestones.is_empty())
+        .unwrap_or_else(|| DEFAULT_MILESTONES.to_vec());
+    milestones.sort_unstable();
+    milestones.dedup();
+    milestones
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Milestone {
+    pub threshold: u32,
+    pub reached: bool,
+}
+
+pub fn milestone_progress(referral_count: u32, milestones: &[u32]) -> Vec<Milestone> {
+    milestones
+        .iter()
+        .map(|threshold| Milestone {
+            threshold: *threshold,
+            reached: referral_count >= *threshold,
+        })
+        .collect()
+}
+
+// Highest milestone reached, if any
+pub fn milestone_reached(referral_count: u32, milestones: &[u32]) -> Option<u32> {
+    milestones
+        .iter()
+        .rev()
+        .find(|threshold| referral_count >= **threshold)
+        .copied()
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReferralResponse {
+    pub success: bool,
+    pub referral_code: String,
+    pub referral_url: String,
+    pub referral_count: u32,
+    pub milestones: Vec<Milestone>,
+    pub next_milestone: Option<u32>,
+}
+
+impl ReferralResponse {
+    pub fn new(referral_code: String, referral_count: u32) -> Self {
+        let milestones = milestones();
+        let next_milestone = milestones
+            .iter()
+            .find(|threshold| referral_count < **threshold)
+            .copied();
+
+        Self {
+            success: true,
+            referral_url: referral_url(&referral_code),
+            referral_code,
+            referral_count,
+            milestones: milestone_progress(referral_count, &milestones),
+            next_milestone,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReferrerStats {
+    pub id: String,
+    pub email: String,
+    pub referral_code: String,
+    pub referral_count: u32,
+    pub milestone_reached: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ReferralStatsResponse {
+    pub success: bool,
+    pub total_referrals: u64,
+    pub milestones: Vec<u32>,
+    pub referrers: Vec<ReferrerStats>,
+}
+
+// Subscribers with at least one referral, most referrals first
+pub fn referral_stats(subscribers: &[Subscriber]) -> ReferralStatsResponse {
+    let milestones = milestones();
+
+    let mut referrers: Vec<ReferrerStats> = subscribers
+        .iter()
+        .filter(|subscriber| subscriber.referral_count > 0)
+        .filter_map(|subscriber| {
+            Some(ReferrerStats {
+                id: subscriber.id.clone(),
+                email: subscriber.email.clone(),
+                referral_code: subscriber.referral_code.clone()?,
+                referral_count: subscriber.referral_count,
+                milestone_reached: milestone_reached(subscriber.referral_count, &milestones),
+            })
+        })
+        .collect();
+    referrers.sort_by_key(|referrer| Reverse(referrer.referral_count));
+
+    ReferralStatsResponse {
+        success: true,
+        total_referrals: referrers
+            .iter()
+            .map(|referrer| referrer.referral_count as u64)
+            .sum(),
+        milestones,
+        referrers,
+    }
+}
@@ -0,0 +1,89 @@
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::config::Region;
+use std::env;
+
+// Where subscriber reads go in a deployment spanning regions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadPreference {
+    // The read region's replica, eventually consistent. Cheapest and closest,
+    // but a change made in another region can take a second or so to show.
+    Local,
+    // Strongly consistent reads in the write region, so every read sees the
+    // latest write made through it
+    Consistent,
+}
+
+impl ReadPreference {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "local" | "nearest" => Some(ReadPreference::Local),
+            "consistent" | "primary" => Some(ReadPreference::Consistent),
+            _ => None,
+        }
+    }
+}
+
+// Regions the subscriber repository talks to, for deployments running against
+// a DynamoDB Global Table in more than one region. Unset regions are the
+// Lambda's own, which is all a single-region deployment needs.
+#[derive(Debug, Clone)]
+pub struct RegionSettings {
+    // DYNAMODB_WRITE_REGION. Pointing every region's Lambdas at the same one
+    // gives a single writer, so conditional writes see every change.
+    pub write_region: Option<String>,
+    // DYNAMODB_READ_REGION
+    pub read_region: Option<String>,
+    // DYNAMODB_READ_PREFERENCE, local (the default) or consistent
+    pub read_preference: ReadPreference,
+}
+
+impl RegionSettings {
+    pub fn from_env() -> Self {
+        let region = |name: &str| {
+            env::var(name)
+                .ok()
+                .map(|region| region.trim().to_string())
+                .filter(|region| !region.is_empty())
+        };
+        Self {
+            write_region: region("DYNAMODB_WRITE_REGION"),
+            read_region: region("DYNAMODB_READ_REGION"),
+            read_preference: env::var("DYNAMODB_READ_PREFERENCE")
+                .ok()
+                .and_then(|value| ReadPreference::parse(&value))
+                .unwrap_or(ReadPreference::Local),
+        }
+    }
+
+    // The client writes go through
+    pub fn writer(&self, client: &Client) -> Client {
+        in_region(client, self.write_region.as_deref())
+    }
+
+    // The client reads go through: the write region's when reads must be
+    // consistent, as a replica can only be read consistently in its own region
+    pub fn reader(&self, client: &Client) -> Client {
+        match self.read_preference {
+            ReadPreference::Consistent => self.writer(client),
+            ReadPreference::Local => in_region(client, self.read_region.as_deref()),
+        }
+    }
+
+    pub fn consistent_reads(&self) -> bool {
+        self.read_preference == ReadPreference::Consistent
+    }
+}
+
+// The same client, sending to `region` instead when one is given
+fn in_region(client: &Client, region: Option<&str>) -> Client {
+    match region {
+        Some(region) => Client::from_conf(
+            client
+                .config()
+                .to_builder()
+                .region(Region::new(region.to_string()))
+                .build(),
+        ),
+        None => client.clone(),
+    }
+}
@@ -0,0 +1,1588 @@
+use crate::batch::{batch_delete, batch_put, parallel_scan, scan_segments};
+use crate::campaign::{Campaign, CampaignStatus, Exclusions, SendCheckpoint};
+use crate::config::{TokenPolicy, table_name};
+use crate::counters::{CounterStore, counters_for, deltas};
+use crate::digest::Frequency;
+use crate::pii;
+use crate::queue::ENQUEUE_PENDING_PARTITION;
+use crate::region::RegionSettings;
+use crate::search::{SubscriberSearch, prefix_bucket};
+use crate::stripe::Tier;
+use crate::sync::{SYNC_PARTITION, sync_key};
+use crate::templates::TemplateVersion;
+use crate::verification::Verdict;
+use crate::{
+    CAMPAIGNS_TABLE_NAME, EMAIL_DOMAIN_INDEX, EMAIL_HASH_INDEX, EMAIL_PREFIX_INDEX,
+    ENQUEUE_PENDING_INDEX, Error, REFERRAL_CODE_INDEX, STRIPE_CUSTOMER_INDEX, Subscriber,
+    TABLE_NAME, UPDATED_AT_INDEX,
+};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::operation::transact_write_items::{
+    TransactWriteItemsError, TransactWriteItemsOutput,
+};
+use aws_sdk_dynamodb::types::{AttributeValue, Put, ReturnValue, TransactWriteItem};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+use tracing::info;
+
+// Attempts at a versioned update before giving up on a hot item
+const MAX_VERSION_RETRIES: usize = 5;
+
+// Addresses `find_many_by_email` looks up in one call
+pub const MAX_EMAIL_BATCH: usize = 100;
+
+// DynamoDB key used to resume paginated reads
+pub type ItemKey = HashMap<String, AttributeValue>;
+
+// Stores a confirmation token in a single UpdateItem, bumping the version like
+// `update` does, so bursts of validation messages cost one round trip each
+const SET_VALIDATION_TOKEN: &str = "SET validation_token = :token, \
+     token_expiration = :expiration, token_ttl = :ttl, updated_at = :now, \
+     sync_partition = :partition, sync_key = :sync_key ADD version :one";
+
+// The same without the TTL, for a reactivation, whose record predates it
+const SET_REACTIVATION_TOKEN: &str = "SET validation_token = :token, \
+     token_expiration = :expiration, updated_at = :now, \
+     sync_partition = :partition, sync_key = :sync_key ADD version :one";
+
+// What the duplicate check on signup knows of an existing subscriber
+#[derive(Debug, Clone)]
+pub struct ExistingSignup {
+    pub id: String,
+    pub active: bool,
+    pub validated: bool,
+    pub suppressed: bool,
+    pub quarantined: bool,
+}
+
+// Data access for the subscribers table, shared by the Lambdas and the CLI
+pub struct SubscriberRepository {
+    // Writes, and the reads they're conditional on
+    client: Client,
+    reader: Client,
+    consistent_reads: bool,
+    // Subscriber counts, changed together with the writes that change them
+    counters: CounterStore,
+    table_name: String,
+}
+
+impl SubscriberRepository {
+    // Reads and writes go to the regions in DYNAMODB_READ_REGION and
+    // DYNAMODB_WRITE_REGION when set, see `RegionSettings`
+    pub fn new(client: Client) -> Self {
+        let regions = RegionSettings::from_env();
+        Self {
+            client: regions.writer(&client),
+            reader: regions.reader(&client),
+            consistent_reads: regions.consistent_reads(),
+            counters: CounterStore::new(regions.writer(&client)),
+            table_name: table_name(TABLE_NAME),
+        }
+    }
+
+    // Point reads made strongly consistent in the write region, e.g. for a
+    // confirmation link opened seconds after signup. Index queries stay
+    // eventually consistent, as DynamoDB has no consistent reads on them.
+    pub fn consistent(mut self) -> Self {
+        self.reader = self.client.clone();
+        self.consistent_reads = true;
+        self
+    }
+
+    // Soft-deleted subscribers are hidden from every read except the ones that
+    // restore or purge them
+    pub async fn get(&self, id: &str) -> Result<Option<Subscriber>, Error> {
+        Ok(self
+            .get_including_deleted(id)
+            .await?
+            .filter(|subscriber| !subscriber.is_deleted()))
+    }
+
+    pub async fn get_including_deleted(&self, id: &str) -> Result<Option<Subscriber>, Error> {
+        self.get_from(&self.reader, id, self.consistent_reads).await
+    }
+
+    // The latest copy in the write region whatever the read preference, for
+    // checking the outcome of a write that may have raced another
+    pub async fn get_latest(&self, id: &str) -> Result<Option<Subscriber>, Error> {
+        Ok(self
+            .get_from(&self.client, id, true)
+            .await?
+            .filter(|subscriber| !subscriber.is_deleted()))
+    }
+
+    async fn get_from(
+        &self,
+        client: &Client,
+        id: &str,
+        consistent: bool,
+    ) -> Result<Option<Subscriber>, Error> {
+        let result = client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .consistent_read(consistent)
+            .send()
+            .await?;
+
+        match result.item() {
+            Some(item) => decode(item).await,
+            None => Ok(None),
+        }
+    }
+
+    pub async fn find_by_email(&self, email: &str) -> Result<Option<Subscriber>, Error> {
+        let result = self
+            .reader
+            .query()
+            .table_name(&self.table_name)
+            .index_name(EMAIL_HASH_INDEX)
+            .key_condition_expression("email_hash = :hash")
+            .expression_attribute_values(":hash", AttributeValue::S(pii::email_hash(email)))
+            .send()
+            .await?;
+
+        first_live(result.items()).await
+    }
+
+    // Every stored subscriber, soft-deleted ones included, with one of up to
+    // MAX_EMAIL_BATCH addresses, e.g. the rows of an import. DynamoDB can't
+    // batch-read an index, so the lookups run as concurrent queries.
+    pub async fn find_many_by_email(&self, emails: &[String]) -> Result<Vec<Subscriber>, Error> {
+        if emails.len() > MAX_EMAIL_BATCH {
+            return Err(
+                format!("At most {MAX_EMAIL_BATCH} addresses can be looked up at once").into(),
+            );
+        }
+
+        let tasks: Vec<_> = emails
+            .iter()
+            .map(|email| {
+                let client = self.reader.clone();
+                let table_name = self.table_name.clone();
+                let hash = pii::email_hash(email);
+                tokio::spawn(async move {
+                    client
+                        .query()
+                        .table_name(table_name)
+                        .index_name(EMAIL_HASH_INDEX)
+                        .key_condition_expression("email_hash = :hash")
+                        .expression_attribute_values(":hash", AttributeValue::S(hash))
+                        .send()
+                        .await
+                })
+            })
+            .collect();
+
+        let mut subscribers = Vec::new();
+        for task in tasks {
+            let output = task.await??;
+            subscribers.extend(decode_all(output.items().unwrap_or_default()).await?);
+        }
+        Ok(subscribers)
+    }
+
+    // The live subscriber with this address, for the duplicate check on
+    // signup. Only the attributes it needs are fetched, so nothing is
+    // transferred or decrypted beyond them.
+    pub async fn find_signup_by_email(&self, email: &str) -> Result<Option<ExistingSignup>, Error> {
+        let result = self
+            .reader
+            .query()
+            .table_name(&self.table_name)
+            .index_name(EMAIL_HASH_INDEX)
+            .key_condition_expression("email_hash = :hash")
+            .expression_attribute_values(":hash", AttributeValue::S(pii::email_hash(email)))
+            .projection_expression(
+                "id, active, validated, suppressed, quarantine_reason, deleted_at",
+            )
+            .send()
+            .await?;
+
+        let flag = |item: &ItemKey, name: &str| {
+            item.get(name)
+                .and_then(|value| value.as_bool().ok())
+                .copied()
+                .unwrap_or(false)
+        };
+        Ok(result
+            .items()
+            .unwrap_or_default()
+            .iter()
+            .filter(|item| !item.contains_key("deleted_at"))
+            .find_map(|item| {
+                Some(ExistingSignup {
+                    id: item.get("id")?.as_s().ok()?.clone(),
+                    active: flag(item, "active"),
+                    validated: flag(item, "validated"),
+                    suppressed: flag(item, "suppressed"),
+                    quarantined: item
+                        .get("quarantine_reason")
+                        .is_some_and(|value| !matches!(value, AttributeValue::Null(_))),
+                })
+            }))
+    }
+
+    pub async fn find_by_referral_code(&self, code: &str) -> Result<Option<Subscriber>, Error> {
+        let result = self
+            .reader
+            .query()
+            .table_name(&self.table_name)
+            .index_name(REFERRAL_CODE_INDEX)
+            .key_condition_expression("referral_code = :code")
+            .expression_attribute_values(":code", AttributeValue::S(code.to_string()))
+            .send()
+            .await?;
+
+        first_live(result.items()).await
+    }
+
+    pub async fn find_by_stripe_customer(
+        &self,
+        customer_id: &str,
+    ) -> Result<Option<Subscriber>, Error> {
+        let result = self
+            .reader
+            .query()
+            .table_name(&self.table_name)
+            .index_name(STRIPE_CUSTOMER_INDEX)
+            .key_condition_expression("stripe_customer_id = :customer_id")
+            .expression_attribute_values(":customer_id", AttributeValue::S(customer_id.to_string()))
+            .send()
+            .await?;
+
+        first_live(result.items()).await
+    }
+
+    // Signups whose validation message couldn't be queued, read from the
+    // sparse enqueue-pending index, which holds only them
+    pub async fn pending_enqueue(&self) -> Result<Vec<Subscriber>, Error> {
+        let mut subscribers = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .reader
+                .query()
+                .table_name(&self.table_name)
+                .index_name(ENQUEUE_PENDING_INDEX)
+                .key_condition_expression("enqueue_pending = :pending")
+                .expression_attribute_values(
+                    ":pending",
+                    AttributeValue::S(ENQUEUE_PENDING_PARTITION.to_string()),
+                )
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            subscribers.extend(
+                decode_all(result.items().unwrap_or_default())
+                    .await?
+                    .into_iter()
+                    .filter(|subscriber| !subscriber.is_deleted()),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(subscribers)
+    }
+
+    // One page of subscribers matching an admin search, returning the key to
+    // continue from
+    pub async fn search(
+        &self,
+        search: &SubscriberSearch,
+        limit: i32,
+        start_key: Option<ItemKey>,
+    ) -> Result<(Vec<Subscriber>, Option<ItemKey>), Error> {
+        let (index, condition, values) = match search {
+            SubscriberSearch::Email(email) => {
+                return Ok((self.find_by_email(email).await?.into_iter().collect(), None));
+            }
+            SubscriberSearch::Prefix(prefix) => (
+                EMAIL_PREFIX_INDEX,
+                "email_prefix = :bucket AND begins_with(email_search, :prefix)",
+                vec![
+                    (":bucket", prefix_bucket(prefix).unwrap_or_default()),
+                    (":prefix", prefix.clone()),
+                ],
+            ),
+            SubscriberSearch::Domain(domain) => (
+                EMAIL_DOMAIN_INDEX,
+                "email_domain = :domain",
+                vec![(":domain", domain.clone())],
+            ),
+        };
+
+        let mut query = self
+            .reader
+            .query()
+            .table_name(&self.table_name)
+            .index_name(index)
+            .key_condition_expression(condition)
+            .limit(limit)
+            .set_exclusive_start_key(start_key);
+        for (name, value) in values {
+            query = query.expression_attribute_values(name, AttributeValue::S(value));
+        }
+        let result = query.send().await?;
+
+        let subscribers = decode_all(result.items().unwrap_or_default())
+            .await?
+            .into_iter()
+            .filter(|subscriber| !subscriber.is_deleted())
+            .collect();
+
+        Ok((subscribers, result.last_evaluated_key().cloned()))
+    }
+
+    // Read a single page of subscribers, returning the key to continue from
+    pub async fn list_page(
+        &self,
+        limit: i32,
+        start_key: Option<ItemKey>,
+    ) -> Result<(Vec<Subscriber>, Option<ItemKey>), Error> {
+        let result = self
+            .reader
+            .scan()
+            .table_name(&self.table_name)
+            .limit(limit)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        let subscribers = decode_all(result.items().unwrap_or_default())
+            .await?
+            .into_iter()
+            .filter(|subscriber| !subscriber.is_deleted())
+            .collect();
+
+        Ok((subscribers, result.last_evaluated_key().cloned()))
+    }
+
+    // Subscribers whose latest change sorts between `after` and `until` in the
+    // updated-at index, oldest first. Soft-deleted ones are included so syncs
+    // see deletions.
+    pub async fn changed_between(
+        &self,
+        after: &str,
+        until: &str,
+        limit: i32,
+        start_key: Option<ItemKey>,
+    ) -> Result<(Vec<Subscriber>, Option<ItemKey>), Error> {
+        let result = self
+            .reader
+            .query()
+            .table_name(&self.table_name)
+            .index_name(UPDATED_AT_INDEX)
+            .key_condition_expression(
+                "sync_partition = :partition AND sync_key BETWEEN :after AND :until",
+            )
+            .expression_attribute_values(
+                ":partition",
+                AttributeValue::S(SYNC_PARTITION.to_string()),
+            )
+            .expression_attribute_values(":after", AttributeValue::S(after.to_string()))
+            .expression_attribute_values(":until", AttributeValue::S(until.to_string()))
+            .limit(limit)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        let subscribers = decode_all(result.items().unwrap_or_default()).await?;
+
+        Ok((subscribers, result.last_evaluated_key().cloned()))
+    }
+
+    // Every subscriber, read with a segmented parallel scan
+    pub async fn list_all(&self) -> Result<Vec<Subscriber>, Error> {
+        let items = parallel_scan(&self.reader, &self.table_name, scan_segments()).await?;
+
+        Ok(decode_all(&items)
+            .await?
+            .into_iter()
+            .filter(|subscriber| !subscriber.is_deleted())
+            .collect())
+    }
+
+    // Every subscriber including soft-deleted ones, e.g. to match an import against
+    pub async fn list_including_deleted(&self) -> Result<Vec<Subscriber>, Error> {
+        let items = parallel_scan(&self.reader, &self.table_name, scan_segments()).await?;
+
+        decode_all(&items).await
+    }
+
+    // Soft-deleted subscribers, for the retention job
+    pub async fn list_deleted(&self) -> Result<Vec<Subscriber>, Error> {
+        let items = parallel_scan(&self.reader, &self.table_name, scan_segments()).await?;
+
+        Ok(decode_all(&items)
+            .await?
+            .into_iter()
+            .filter(|subscriber| subscriber.is_deleted())
+            .collect())
+    }
+
+    // Ids of subscribers, soft-deleted ones included, stored in the clear or
+    // under another KMS key than PII_KMS_KEY_ID
+    pub async fn needing_encryption(&self) -> Result<Vec<String>, Error> {
+        let cipher = pii::cipher()?.ok_or("PII_KMS_KEY_ID is not set")?;
+        let items = parallel_scan(&self.reader, &self.table_name, scan_segments()).await?;
+
+        let mut ids = Vec::new();
+        for item in &items {
+            let Some(AttributeValue::S(id)) = item.get("id") else {
+                continue;
+            };
+            if cipher.is_stale(item).await? {
+                ids.push(id.clone());
+            }
+        }
+        Ok(ids)
+    }
+
+    // Write a subscriber back unchanged, encrypting it with the current data key
+    pub async fn reencrypt(&self, id: &str) -> Result<bool, Error> {
+        Ok(self.update_matching(id, true, |_| true).await?.is_some())
+    }
+
+    // Active subscribers created within the window who never confirmed nor got a reminder
+    pub async fn find_pending_confirmation(
+        &self,
+        created_after: DateTime<Utc>,
+        created_before: DateTime<Utc>,
+        reminded_before: DateTime<Utc>,
+        max_reminders: u32,
+    ) -> Result<Vec<Subscriber>, Error> {
+        let mut subscribers = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .reader
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression(
+                    "validated = :false AND active = :true \
+                     AND created_at BETWEEN :created_after AND :created_before \
+                     AND (attribute_not_exists(reminder_sent_at) OR reminder_sent_at < :reminded_before) \
+                     AND (attribute_not_exists(reminder_count) OR reminder_count < :max_reminders) \
+                     AND attribute_not_exists(deleted_at) \
+                     AND attribute_not_exists(quarantine_reason) \
+                     AND (attribute_not_exists(suppressed) OR suppressed = :false)",
+                )
+                .expression_attribute_values(":false", AttributeValue::Bool(false))
+                .expression_attribute_values(":true", AttributeValue::Bool(true))
+                .expression_attribute_values(
+                    ":created_after",
+                    AttributeValue::S(created_after.to_rfc3339()),
+                )
+                .expression_attribute_values(
+                    ":created_before",
+                    AttributeValue::S(created_before.to_rfc3339()),
+                )
+                .expression_attribute_values(
+                    ":reminded_before",
+                    AttributeValue::S(reminded_before.to_rfc3339()),
+                )
+                .expression_attribute_values(
+                    ":max_reminders",
+                    AttributeValue::N(max_reminders.to_string()),
+                )
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            subscribers.extend(decode_all(result.items().unwrap_or_default()).await?);
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(subscribers)
+    }
+
+    // Read-modify-write of a subscriber, conditional on the version that was read so
+    // concurrent writers (e.g. confirm racing unsubscribe) can't clobber each other.
+    // `apply` returns false to leave the subscriber unchanged. On a conflict the
+    // subscriber is re-read and `apply` runs again on the fresh copy. Returns the
+    // stored subscriber, or None when it doesn't exist or `apply` declined.
+    pub async fn update<F>(&self, id: &str, apply: F) -> Result<Option<Subscriber>, Error>
+    where
+        F: FnMut(&mut Subscriber) -> bool,
+    {
+        self.update_matching(id, false, apply).await
+    }
+
+    async fn update_matching<F>(
+        &self,
+        id: &str,
+        include_deleted: bool,
+        mut apply: F,
+    ) -> Result<Option<Subscriber>, Error>
+    where
+        F: FnMut(&mut Subscriber) -> bool,
+    {
+        for _ in 0..MAX_VERSION_RETRIES {
+            // Read where the write goes, consistently, so the version checked
+            // is the latest one there rather than a lagging replica's
+            let subscriber = self
+                .get_from(&self.client, id, true)
+                .await?
+                .filter(|subscriber| include_deleted || !subscriber.is_deleted());
+            let Some(mut subscriber) = subscriber else {
+                return Ok(None);
+            };
+            let counted = counters_for(&subscriber);
+            if !apply(&mut subscriber) {
+                return Ok(None);
+            }
+
+            let expected_version = subscriber.version;
+            subscriber.version += 1;
+            subscriber.updated_at = Utc::now();
+
+            let changes = deltas(&counted, &counters_for(&subscriber));
+            let item = encode(&subscriber).await?;
+            // Items written before versioning have no version attribute
+            let condition =
+                "attribute_exists(id) AND (version = :expected OR attribute_not_exists(version))";
+            let expected = AttributeValue::N(expected_version.to_string());
+
+            // Most updates leave the counters alone and stay a single put
+            let conflict = if changes.is_empty() {
+                match self
+                    .client
+                    .put_item()
+                    .table_name(&self.table_name)
+                    .set_item(Some(item))
+                    .condition_expression(condition)
+                    .expression_attribute_values(":expected", expected)
+                    .send()
+                    .await
+                {
+                    Ok(_) => false,
+                    Err(SdkError::ServiceError(err))
+                        if err.err().is_conditional_check_failed_exception() =>
+                    {
+                        true
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            } else {
+                let put = Put::builder()
+                    .table_name(&self.table_name)
+                    .set_item(Some(item))
+                    .condition_expression(condition)
+                    .expression_attribute_values(":expected", expected)
+                    .build();
+                // Cancelled on a version conflict, or when another transaction
+                // touches the same counters at the same time
+                match self.transact_with_counters(put, &changes).await {
+                    Ok(_) => false,
+                    Err(SdkError::ServiceError(err))
+                        if err.err().is_transaction_canceled_exception() =>
+                    {
+                        true
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            };
+
+            if !conflict {
+                return Ok(Some(subscriber));
+            }
+            info!("Version conflict updating subscriber {}, retrying", id);
+        }
+
+        Err(format!("Too many concurrent updates to subscriber {id}").into())
+    }
+
+    // Store a fresh validation token and count the reminder as sent. Returns false
+    // once the policy's reminders were sent, so concurrent runs can't exceed the allowance.
+    pub async fn claim_reminder(
+        &self,
+        id: &str,
+        token: &str,
+        policy: &TokenPolicy,
+    ) -> Result<bool, Error> {
+        let updated = self
+            .update(id, |subscriber| {
+                if subscriber.reminder_count >= policy.max_reminders {
+                    return false;
+                }
+                subscriber.validation_token = Some(token.to_string());
+                subscriber.token_expiration = Some(policy.expiration());
+                subscriber.token_ttl = Some(policy.purge_at());
+                subscriber.reminder_sent_at = Some(Utc::now());
+                subscriber.reminder_count += 1;
+                true
+            })
+            .await?;
+
+        Ok(updated.is_some())
+    }
+
+    // Store a new validation token, replacing any earlier one. With `purge`, the
+    // record is also set to be removed if it's never confirmed, as a new
+    // signup's is; a reactivation's isn't.
+    pub async fn set_validation_token(
+        &self,
+        id: &str,
+        token: &str,
+        policy: &TokenPolicy,
+        purge: bool,
+    ) -> Result<Option<Subscriber>, Error> {
+        let now = Utc::now();
+        let mut request = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .condition_expression("attribute_exists(id) AND attribute_not_exists(deleted_at)");
+        request = if purge {
+            request
+                .update_expression(SET_VALIDATION_TOKEN)
+                .expression_attribute_values(
+                    ":ttl",
+                    AttributeValue::N(policy.purge_at().timestamp().to_string()),
+                )
+        } else {
+            request.update_expression(SET_REACTIVATION_TOKEN)
+        };
+        let result = request
+            .expression_attribute_values(":token", AttributeValue::S(token.to_string()))
+            .expression_attribute_values(
+                ":expiration",
+                AttributeValue::S(policy.expiration().to_rfc3339()),
+            )
+            .expression_attribute_values(":now", AttributeValue::S(now.to_rfc3339()))
+            .expression_attribute_values(
+                ":partition",
+                AttributeValue::S(SYNC_PARTITION.to_string()),
+            )
+            .expression_attribute_values(":sync_key", AttributeValue::S(sync_key(now, id)))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => match output.attributes() {
+                Some(item) => decode(item).await,
+                None => Ok(None),
+            },
+            // Missing or soft-deleted
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Store a new subscriber and count it in the same transaction. Existing
+    // records go through `update`, so they aren't counted twice.
+    pub async fn put(&self, subscriber: &Subscriber) -> Result<(), Error> {
+        let put = Put::builder()
+            .table_name(&self.table_name)
+            .set_item(Some(encode(subscriber).await?))
+            .condition_expression("attribute_not_exists(id)")
+            .build();
+        self.transact_with_counters(put, &deltas(&[], &counters_for(subscriber)))
+            .await?;
+
+        Ok(())
+    }
+
+    // Write new subscribers in batches of 25, e.g. for imports. Batch writes
+    // can't be part of a transaction, so the counters follow once they're in.
+    pub async fn put_many(&self, subscribers: &[Subscriber]) -> Result<usize, Error> {
+        let mut items = Vec::with_capacity(subscribers.len());
+        let mut counted = Vec::new();
+        for subscriber in subscribers {
+            items.push(encode(subscriber).await?);
+            counted.extend(counters_for(subscriber));
+        }
+
+        let written = batch_put(&self.client, &self.table_name, items).await?;
+        self.counters.apply_or_log(&deltas(&[], &counted)).await;
+        Ok(written)
+    }
+
+    // Permanently remove subscribers as they were read, uncounting them once
+    // the batches are written
+    pub async fn delete_many(&self, subscribers: &[Subscriber]) -> Result<usize, Error> {
+        let keys = subscribers
+            .iter()
+            .map(|subscriber| {
+                HashMap::from([("id".to_string(), AttributeValue::S(subscriber.id.clone()))])
+            })
+            .collect();
+        let counted: Vec<String> = subscribers.iter().flat_map(counters_for).collect();
+
+        let deleted = batch_delete(&self.client, &self.table_name, keys).await?;
+        self.counters.apply_or_log(&deltas(&counted, &[])).await;
+        Ok(deleted)
+    }
+
+    // Write a subscriber together with the counter changes it makes
+    async fn transact_with_counters(
+        &self,
+        put: Put,
+        changes: &BTreeMap<String, i64>,
+    ) -> Result<TransactWriteItemsOutput, SdkError<TransactWriteItemsError>> {
+        let mut items = vec![TransactWriteItem::builder().put(put).build()];
+        items.extend(self.counters.transact_items(changes));
+
+        self.client
+            .transact_write_items()
+            .set_transact_items(Some(items))
+            .send()
+            .await
+    }
+
+    // Note that an unsubscribed subscriber signed up again, so confirming makes
+    // them active. Returns false when no confirmation should be sent: they're
+    // active or suppressed by now, or asked within `resend_after` already, which
+    // keeps repeated signups from flooding them.
+    pub async fn request_reactivation(
+        &self,
+        id: &str,
+        resend_after: Duration,
+    ) -> Result<bool, Error> {
+        let now = Utc::now();
+        let updated = self
+            .update(id, |subscriber| {
+                let recent = subscriber
+                    .reactivation_requested_at
+                    .is_some_and(|requested_at| now - requested_at < resend_after);
+                if subscriber.active || subscriber.suppressed || recent {
+                    return false;
+                }
+                subscriber.reactivation_requested_at = Some(now);
+                true
+            })
+            .await?;
+
+        Ok(updated.is_some())
+    }
+
+    // Hide a subscriber from every read and stop all mail, keeping the record
+    // until the retention job hard-deletes it
+    pub async fn soft_delete(&self, id: &str) -> Result<Option<Subscriber>, Error> {
+        self.update(id, |subscriber| {
+            subscriber.active = false;
+            subscriber.deleted_at = Some(Utc::now());
+            true
+        })
+        .await
+    }
+
+    // Undo a soft delete within the retention window. The subscriber stays
+    // inactive until they subscribe again.
+    pub async fn restore(&self, id: &str) -> Result<Option<Subscriber>, Error> {
+        self.update_matching(id, true, |subscriber| {
+            subscriber.deleted_at.take().is_some()
+        })
+        .await
+    }
+
+    // Permanently remove a subscriber, uncounting the record that was removed
+    pub async fn delete(&self, id: &str) -> Result<(), Error> {
+        let output = self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await?;
+
+        let removed = match output.attributes() {
+            Some(item) => decode(item).await?,
+            None => None,
+        };
+        if let Some(subscriber) = removed {
+            self.counters
+                .apply_or_log(&deltas(&counters_for(&subscriber), &[]))
+                .await;
+        }
+
+        Ok(())
+    }
+
+    // Record when the re-engagement email went out, or clear it once they interact again
+    pub async fn set_reengagement_sent_at(
+        &self,
+        id: &str,
+        sent_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Error> {
+        self.update(id, |subscriber| {
+            subscriber.reengagement_sent_at = sent_at;
+            true
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    // Fold an interaction into the decayed engagement score
+    pub async fn record_engagement(
+        &self,
+        id: &str,
+        weight: f64,
+        engaged_at: DateTime<Utc>,
+    ) -> Result<Option<Subscriber>, Error> {
+        self.update(id, |subscriber| {
+            subscriber.engagement_score = subscriber.engagement_score_at(engaged_at) + weight;
+            subscriber.last_engaged_at = Some(engaged_at);
+            true
+        })
+        .await
+    }
+
+    pub async fn unsubscribe(&self, id: &str) -> Result<Subscriber, Error> {
+        Ok(self
+            .update(id, |subscriber| {
+                subscriber.active = false;
+                true
+            })
+            .await?
+            .ok_or_else(|| format!("Subscriber {id} not found"))?)
+    }
+
+    // Take the subscriber off one list, leaving them on every other
+    pub async fn leave_list(&self, id: &str, list: &str) -> Result<Subscriber, Error> {
+        Ok(self
+            .update(id, |subscriber| {
+                subscriber.unsubscribed_lists.insert(list.to_string());
+                true
+            })
+            .await?
+            .ok_or_else(|| format!("Subscriber {id} not found"))?)
+    }
+
+    // Give the subscriber a referral code unless they already have one, returning
+    // the code they end up with
+    pub async fn assign_referral_code(&self, id: &str, code: &str) -> Result<String, Error> {
+        let mut assigned = code.to_string();
+        self.update(id, |subscriber| match &subscriber.referral_code {
+            Some(existing) => {
+                assigned = existing.clone();
+                false
+            }
+            None => {
+                subscriber.referral_code = Some(code.to_string());
+                true
+            }
+        })
+        .await?;
+
+        Ok(assigned)
+    }
+
+    // Count a confirmed signup towards the referrer
+    pub async fn increment_referral_count(&self, id: &str) -> Result<(), Error> {
+        self.update(id, |subscriber| {
+            subscriber.referral_count += 1;
+            true
+        })
+        .await?
+        .ok_or_else(|| format!("Subscriber {id} not found"))?;
+
+        Ok(())
+    }
+
+    // Change the paid tier, linking the Stripe customer when one is known
+    pub async fn set_tier(
+        &self,
+        id: &str,
+        tier: Tier,
+        stripe_customer_id: Option<&str>,
+    ) -> Result<(), Error> {
+        self.update(id, |subscriber| {
+            subscriber.tier = tier;
+            if let Some(customer_id) = stripe_customer_id {
+                subscriber.stripe_customer_id = Some(customer_id.to_string());
+            }
+            true
+        })
+        .await?
+        .ok_or_else(|| format!("Subscriber {id} not found"))?;
+
+        Ok(())
+    }
+
+    // Switch between immediate campaigns and the weekly digest. The next digest
+    // starts at the switch, so campaigns already received aren't repeated.
+    pub async fn set_frequency(
+        &self,
+        id: &str,
+        frequency: Frequency,
+    ) -> Result<Option<Subscriber>, Error> {
+        self.update(id, |subscriber| {
+            if subscriber.frequency != frequency && frequency == Frequency::Weekly {
+                subscriber.digest_sent_at = Some(Utc::now());
+            }
+            subscriber.frequency = frequency;
+            true
+        })
+        .await
+    }
+
+    // Record that a digest covering everything up to `sent_at` went out
+    pub async fn set_digest_sent_at(&self, id: &str, sent_at: DateTime<Utc>) -> Result<(), Error> {
+        self.update(id, |subscriber| {
+            subscriber.digest_sent_at = Some(sent_at);
+            true
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    // Store an email verification result; undeliverable addresses are suppressed
+    pub async fn set_verification(
+        &self,
+        id: &str,
+        verdict: Verdict,
+    ) -> Result<Option<Subscriber>, Error> {
+        self.update(id, |subscriber| {
+            subscriber.verification = Some(verdict);
+            subscriber.verified_at = Some(Utc::now());
+            if verdict == Verdict::Undeliverable {
+                subscriber.active = false;
+                subscriber.suppressed = true;
+            }
+            true
+        })
+        .await
+    }
+
+    // Let a quarantined subscriber through, confirming them straight away when
+    // `confirm` is set. None when the subscriber isn't quarantined.
+    pub async fn approve(&self, id: &str, confirm: bool) -> Result<Option<Subscriber>, Error> {
+        self.update(id, |subscriber| {
+            if subscriber.quarantine_reason.take().is_none() {
+                return false;
+            }
+            if confirm {
+                subscriber.validated = true;
+            }
+            true
+        })
+        .await
+    }
+
+    // Confirm a subscriber without a token, for support cases, the way following
+    // their link would. None when there's nothing to do: the subscriber is
+    // missing, already confirmed, or quarantined and needs approving instead.
+    pub async fn confirm_manually(&self, id: &str) -> Result<Option<Subscriber>, Error> {
+        self.update(id, |subscriber| {
+            let reactivating = subscriber.reactivation_requested_at.is_some();
+            if subscriber.is_quarantined() || (subscriber.validated && !reactivating) {
+                return false;
+            }
+            subscriber.validated = true;
+            if reactivating {
+                subscriber.active = true;
+                subscriber.reactivation_requested_at = None;
+            }
+            subscriber.validation_token = None;
+            subscriber.token_expiration = None;
+            // Confirmed subscribers are kept
+            subscriber.token_ttl = None;
+            true
+        })
+        .await
+    }
+
+    // Flag a signup whose validation message couldn't be queued, or clear the
+    // flag once the requeue job has queued it
+    pub async fn set_enqueue_pending(
+        &self,
+        id: &str,
+        pending: bool,
+    ) -> Result<Option<Subscriber>, Error> {
+        self.update(id, |subscriber| {
+            match (pending, subscriber.validation_enqueue_pending) {
+                (true, None) => subscriber.validation_enqueue_pending = Some(Utc::now()),
+                (false, Some(_)) => subscriber.validation_enqueue_pending = None,
+                _ => return false,
+            }
+            true
+        })
+        .await
+    }
+
+    // Suppressed subscribers are deactivated and never emailed again. Returns
+    // the suppressed subscriber, None when there is no such subscriber.
+    pub async fn suppress(&self, id: &str) -> Result<Option<Subscriber>, Error> {
+        self.update(id, |subscriber| {
+            subscriber.active = false;
+            subscriber.suppressed = true;
+            true
+        })
+        .await
+    }
+}
+
+// A subscriber as written, with its PII encrypted when PII_KMS_KEY_ID is set
+async fn encode(subscriber: &Subscriber) -> Result<HashMap<String, AttributeValue>, Error> {
+    let mut item = subscriber.to_dynamodb_item()?;
+    pii::seal(&mut item).await?;
+    Ok(item)
+}
+
+// A subscriber as read, decrypted if it was encrypted. Malformed items are
+// skipped like before, but a record that can't be decrypted is an error.
+async fn decode(item: &HashMap<String, AttributeValue>) -> Result<Option<Subscriber>, Error> {
+    let mut item = item.clone();
+    pii::open(&mut item).await?;
+    Ok(Subscriber::from_dynamodb_item(&item))
+}
+
+async fn decode_all(items: &[HashMap<String, AttributeValue>]) -> Result<Vec<Subscriber>, Error> {
+    let mut subscribers = Vec::with_capacity(items.len());
+    for item in items {
+        subscribers.extend(decode(item).await?);
+    }
+    Ok(subscribers)
+}
+
+// First subscriber in a query result that isn't soft-deleted
+async fn first_live(
+    items: Option<&[HashMap<String, AttributeValue>]>,
+) -> Result<Option<Subscriber>, Error> {
+    Ok(decode_all(items.unwrap_or_default())
+        .await?
+        .into_iter()
+        .find(|subscriber| !subscriber.is_deleted()))
+}
+
+// Data access for the campaigns table
+pub struct CampaignRepository {
+    client: Client,
+    table_name: String,
+}
+
+impl CampaignRepository {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(CAMPAIGNS_TABLE_NAME),
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<Campaign>, Error> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .send()
+            .await?;
+
+        Ok(result.item().and_then(Campaign::from_dynamodb_item))
+    }
+
+    pub async fn list_all(&self) -> Result<Vec<Campaign>, Error> {
+        let mut campaigns = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            campaigns.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(Campaign::from_dynamodb_item),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(campaigns)
+    }
+
+    pub async fn put(&self, campaign: &Campaign) -> Result<(), Error> {
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(campaign.to_dynamodb_item()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_status(&self, id: &str, status: CampaignStatus) -> Result<(), Error> {
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression("SET #status = :status, updated_at = :updated_at")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(":status", AttributeValue::S(status.as_str().to_string()))
+            .expression_attribute_values(":updated_at", AttributeValue::S(Utc::now().to_rfc3339()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Move a draft campaign to sending with a fresh checkpoint, returning false
+    // when it isn't a draft any more, e.g. because another send started it
+    pub async fn start_sending(&self, id: &str) -> Result<bool, Error> {
+        let checkpoint = SendCheckpoint::start();
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression(
+                "SET #status = :sending, checkpoint_sent = :zero, checkpoint_at = :now, updated_at = :now \
+                 REMOVE checkpoint_subscriber_id",
+            )
+            .condition_expression("#status = :draft")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":sending",
+                AttributeValue::S(CampaignStatus::Sending.as_str().to_string()),
+            )
+            .expression_attribute_values(
+                ":draft",
+                AttributeValue::S(CampaignStatus::Draft.as_str().to_string()),
+            )
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(checkpoint.at.to_rfc3339()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Move a draft campaign to sending by the named Step Functions execution,
+    // returning false when it isn't a draft any more. It gets no checkpoint,
+    // so it is never taken for a stalled send.
+    pub async fn start_workflow(&self, id: &str, execution: &str) -> Result<bool, Error> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression(
+                "SET #status = :sending, workflow_execution = :execution, updated_at = :now \
+                 REMOVE checkpoint_subscriber_id, checkpoint_sent, checkpoint_at",
+            )
+            .condition_expression("#status = :draft")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":sending",
+                AttributeValue::S(CampaignStatus::Sending.as_str().to_string()),
+            )
+            .expression_attribute_values(
+                ":draft",
+                AttributeValue::S(CampaignStatus::Draft.as_str().to_string()),
+            )
+            .expression_attribute_values(":execution", AttributeValue::S(execution.to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Put a campaign back to draft when its execution couldn't be started
+    pub async fn abandon_workflow(&self, id: &str, execution: &str) -> Result<(), Error> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression("SET #status = :draft, updated_at = :now REMOVE workflow_execution")
+            .condition_expression("workflow_execution = :execution")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":draft",
+                AttributeValue::S(CampaignStatus::Draft.as_str().to_string()),
+            )
+            .expression_attribute_values(":execution", AttributeValue::S(execution.to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Take over a stalled send, returning false when its checkpoint moved on
+    // since it was read, i.e. it is still running or was taken over already
+    pub async fn resume_sending(&self, id: &str, seen: &SendCheckpoint) -> Result<bool, Error> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression("SET checkpoint_at = :now, updated_at = :now")
+            .condition_expression("#status = :sending AND checkpoint_at = :seen")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":sending",
+                AttributeValue::S(CampaignStatus::Sending.as_str().to_string()),
+            )
+            .expression_attribute_values(":seen", AttributeValue::S(seen.at.to_rfc3339()))
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn save_checkpoint(
+        &self,
+        id: &str,
+        checkpoint: &SendCheckpoint,
+    ) -> Result<(), Error> {
+        let subscriber_id = match &checkpoint.last_subscriber_id {
+            Some(id) => AttributeValue::S(id.clone()),
+            None => AttributeValue::Null(true),
+        };
+
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression(
+                "SET checkpoint_subscriber_id = :subscriber_id, checkpoint_sent = :sent, checkpoint_at = :at",
+            )
+            .expression_attribute_values(":subscriber_id", subscriber_id)
+            .expression_attribute_values(":sent", AttributeValue::N(checkpoint.sent.to_string()))
+            .expression_attribute_values(":at", AttributeValue::S(checkpoint.at.to_rfc3339()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Mark a campaign scheduled along with the size of its recipient snapshot
+    pub async fn mark_scheduled(&self, id: &str, snapshot_count: u32) -> Result<(), Error> {
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression(
+                "SET #status = :status, snapshot_count = :snapshot_count, updated_at = :updated_at",
+            )
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":status",
+                AttributeValue::S(CampaignStatus::Scheduled.as_str().to_string()),
+            )
+            .expression_attribute_values(
+                ":snapshot_count",
+                AttributeValue::N(snapshot_count.to_string()),
+            )
+            .expression_attribute_values(":updated_at", AttributeValue::S(Utc::now().to_rfc3339()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Copy a template version into a draft campaign, so later edits to the
+    // template don't change what it sends
+    pub async fn pin_template(&self, id: &str, version: &TemplateVersion) -> Result<(), Error> {
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression(
+                "SET subject = :subject, html_body = :html_body, template_version = :version, updated_at = :updated_at",
+            )
+            .condition_expression("#status = :draft")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(":subject", AttributeValue::S(version.subject.clone()))
+            .expression_attribute_values(
+                ":html_body",
+                AttributeValue::S(version.html_body.clone()),
+            )
+            .expression_attribute_values(":version", AttributeValue::N(version.version.to_string()))
+            .expression_attribute_values(
+                ":draft",
+                AttributeValue::S(CampaignStatus::Draft.as_str().to_string()),
+            )
+            .expression_attribute_values(":updated_at", AttributeValue::S(Utc::now().to_rfc3339()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Replace the exclusions of a draft campaign, returning false when it isn't
+    // a draft any more. Empty lists are removed, as string sets can't be empty.
+    pub async fn set_exclusions(&self, id: &str, exclusions: &Exclusions) -> Result<bool, Error> {
+        let mut item = HashMap::new();
+        exclusions.insert_into(&mut item);
+
+        let mut sets = vec!["updated_at = :updated_at".to_string()];
+        let mut removes = Vec::new();
+        let mut request = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .condition_expression("#status = :draft")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":draft",
+                AttributeValue::S(CampaignStatus::Draft.as_str().to_string()),
+            )
+            .expression_attribute_values(":updated_at", AttributeValue::S(Utc::now().to_rfc3339()));
+        for name in ["exclude_tags", "exclude_segment", "exclude_emails"] {
+            match item.remove(name) {
+                Some(value) => {
+                    sets.push(format!("{name} = :{name}"));
+                    request = request.expression_attribute_values(format!(":{name}"), value);
+                }
+                None => removes.push(name),
+            }
+        }
+        let mut update_expression = format!("SET {}", sets.join(", "));
+        if !removes.is_empty() {
+            update_expression.push_str(&format!(" REMOVE {}", removes.join(", ")));
+        }
+
+        match request.update_expression(update_expression).send().await {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // The campaign's status, read consistently so senders see a cancellation
+    // as soon as it is made
+    pub async fn status(&self, id: &str) -> Result<Option<CampaignStatus>, Error> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .consistent_read(true)
+            .projection_expression("#status")
+            .expression_attribute_names("#status", "status")
+            .send()
+            .await?;
+
+        Ok(result
+            .item()
+            .and_then(|item| item.get("status"))
+            .and_then(|value| value.as_s().ok())
+            .and_then(|value| CampaignStatus::parse(value)))
+    }
+
+    // Ask a sending or scheduled campaign to stop, returning false when it is
+    // in any other state
+    pub async fn request_cancel(&self, id: &str) -> Result<bool, Error> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression("SET #status = :cancelling, updated_at = :updated_at")
+            .condition_expression("#status IN (:sending, :scheduled)")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":cancelling",
+                AttributeValue::S(CampaignStatus::Cancelling.as_str().to_string()),
+            )
+            .expression_attribute_values(
+                ":sending",
+                AttributeValue::S(CampaignStatus::Sending.as_str().to_string()),
+            )
+            .expression_attribute_values(
+                ":scheduled",
+                AttributeValue::S(CampaignStatus::Scheduled.as_str().to_string()),
+            )
+            .expression_attribute_values(":updated_at", AttributeValue::S(Utc::now().to_rfc3339()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Count emails a sender left out because the campaign was cancelled
+    pub async fn add_skipped(&self, id: &str, count: u32) -> Result<(), Error> {
+        if count == 0 {
+            return Ok(());
+        }
+
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression("ADD skipped_count :count")
+            .expression_attribute_values(":count", AttributeValue::N(count.to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Count queued emails the send queue worker dropped because the campaign
+    // was cancelled. They were counted in `sent_count` when queued, so once the
+    // campaign is cancelled they come off it; before that, `mark_cancelled`
+    // takes them off through `drained_count`.
+    pub async fn record_drained(&self, id: &str, count: u32) -> Result<(), Error> {
+        if count == 0 {
+            return Ok(());
+        }
+
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression(
+                "SET sent_count = sent_count - :count ADD skipped_count :count, drained_count :count",
+            )
+            .condition_expression("#status = :cancelled AND sent_count >= :count")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(":count", AttributeValue::N(count.to_string()))
+            .expression_attribute_values(
+                ":cancelled",
+                AttributeValue::S(CampaignStatus::Cancelled.as_str().to_string()),
+            )
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                self.client
+                    .update_item()
+                    .table_name(&self.table_name)
+                    .key("id", AttributeValue::S(id.to_string()))
+                    .update_expression("ADD skipped_count :count, drained_count :count")
+                    .expression_attribute_values(":count", AttributeValue::N(count.to_string()))
+                    .send()
+                    .await?;
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Record the outcome of a cancelled send. `handed_off` counts the emails
+    // sent or queued; queued ones the worker already dropped are taken off.
+    pub async fn mark_cancelled(&self, id: &str, handed_off: u32) -> Result<(), Error> {
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression(
+                "SET #status = :status, sent_count = :handed_off - if_not_exists(drained_count, :zero), updated_at = :now",
+            )
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":status",
+                AttributeValue::S(CampaignStatus::Cancelled.as_str().to_string()),
+            )
+            .expression_attribute_values(":handed_off", AttributeValue::N(handed_off.to_string()))
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    // Record the outcome of a finished send
+    pub async fn mark_sent(&self, id: &str, sent_count: u32) -> Result<(), Error> {
+        let now = Utc::now().to_rfc3339();
+
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .update_expression(
+                "SET #status = :status, sent_count = :sent_count, sent_at = :now, updated_at = :now",
+            )
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":status",
+                AttributeValue::S(CampaignStatus::Sent.as_str().to_string()),
+            )
+            .expression_attribute_values(":sent_count", AttributeValue::N(sent_count.to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(now))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,612 @@
+use crate::batch::batch_put;
+use crate::campaign::SendCheckpoint;
+use crate::config::table_name;
+use crate::{
+    CAMPAIGN_RECIPIENTS_TABLE_NAME, CAMPAIGN_SCHEDULE_TABLE_NAME, Error, Subscriber,
+    optional_datetime,
+};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use utoipa::ToSchema;
+
+// Snapshots outlive the campaign's last send by this long, then DynamoDB TTL removes them
+const SNAPSHOT_RETENTION_DAYS: i64 = 7;
+
+// IANA timezone of subscribers who didn't give one, from DEFAULT_TIMEZONE
+pub fn default_timezone() -> Tz {
+    env::var("DEFAULT_TIMEZONE")
+        .ok()
+        .and_then(|value| parse_timezone(&value))
+        .unwrap_or(Tz::UTC)
+}
+
+// An IANA timezone name such as "America/Argentina/Buenos_Aires"
+pub fn parse_timezone(value: &str) -> Option<Tz> {
+    value.trim().parse().ok()
+}
+
+// The instant a local date and time happens in a timezone. Times skipped by a
+// DST change move an hour later, and repeated ones use the first occurrence.
+pub fn local_send_time(date: NaiveDate, time: NaiveTime, timezone: Tz) -> Option<DateTime<Utc>> {
+    let local = date.and_time(time);
+    timezone
+        .from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|at| at.with_timezone(&Utc))
+}
+
+// Body of a campaign send that goes out at a local time instead of right away
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ScheduleRequest {
+    // Local day of the send, e.g. "2026-10-20"
+    #[schema(value_type = String)]
+    pub date: NaiveDate,
+    // Local time of the send, e.g. "09:00"
+    pub local_time: String,
+}
+
+impl ScheduleRequest {
+    pub fn time(&self) -> Option<NaiveTime> {
+        NaiveTime::parse_from_str(self.local_time.trim(), "%H:%M").ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BucketStatus {
+    Pending,
+    Sending,
+    Sent,
+}
+
+impl BucketStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BucketStatus::Pending => "pending",
+            BucketStatus::Sending => "sending",
+            BucketStatus::Sent => "sent",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(BucketStatus::Pending),
+            "sending" => Some(BucketStatus::Sending),
+            "sent" => Some(BucketStatus::Sent),
+            _ => None,
+        }
+    }
+}
+
+// The part of a scheduled campaign going to subscribers in one timezone
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ScheduleBucket {
+    pub campaign_id: String,
+    pub timezone: String,
+    pub send_at: DateTime<Utc>,
+    pub status: BucketStatus,
+    pub sent_count: u32,
+    // Progress while the bucket is being sent
+    pub checkpoint: Option<SendCheckpoint>,
+}
+
+impl ScheduleBucket {
+    fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert(
+            "campaign_id".to_string(),
+            AttributeValue::S(self.campaign_id.clone()),
+        );
+        item.insert(
+            "timezone".to_string(),
+            AttributeValue::S(self.timezone.clone()),
+        );
+        item.insert(
+            "send_at".to_string(),
+            AttributeValue::S(self.send_at.to_rfc3339()),
+        );
+        item.insert(
+            "status".to_string(),
+            AttributeValue::S(self.status.as_str().to_string()),
+        );
+        item.insert(
+            "sent_count".to_string(),
+            AttributeValue::N(self.sent_count.to_string()),
+        );
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.insert_into(&mut item);
+        }
+        item
+    }
+
+    fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        Some(Self {
+            campaign_id: item.get("campaign_id")?.as_s().ok()?.clone(),
+            timezone: item.get("timezone")?.as_s().ok()?.clone(),
+            send_at: optional_datetime(item, "send_at")?,
+            status: BucketStatus::parse(item.get("status")?.as_s().ok()?)?,
+            sent_count: item
+                .get("sent_count")
+                .and_then(|value| value.as_n().ok())
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0),
+            checkpoint: SendCheckpoint::from_item(item),
+        })
+    }
+}
+
+// One bucket per timezone among the recipients, plus the default timezone,
+// each sending at the local date and time there
+pub fn plan_buckets(
+    campaign_id: &str,
+    recipients: &[Subscriber],
+    date: NaiveDate,
+    time: NaiveTime,
+) -> Vec<ScheduleBucket> {
+    let default = default_timezone();
+    let timezones: BTreeMap<&str, Tz> = recipients
+        .iter()
+        .filter_map(|subscriber| subscriber.timezone.as_deref().and_then(parse_timezone))
+        .chain([default])
+        .map(|timezone| (timezone.name(), timezone))
+        .collect();
+
+    timezones
+        .into_values()
+        .filter_map(|timezone| {
+            Some(ScheduleBucket {
+                campaign_id: campaign_id.to_string(),
+                timezone: timezone.name().to_string(),
+                send_at: local_send_time(date, time, timezone)?,
+                status: BucketStatus::Pending,
+                sent_count: 0,
+                checkpoint: None,
+            })
+        })
+        .collect()
+}
+
+// The bucket a subscriber's email goes out with: their own timezone's, or the
+// default one when they have none or it isn't scheduled, e.g. when they
+// subscribed after the campaign was scheduled
+pub fn bucket_for<'a>(subscriber: &Subscriber, buckets: &'a [ScheduleBucket]) -> Option<&'a str> {
+    let own = subscriber
+        .timezone
+        .as_deref()
+        .and_then(parse_timezone)
+        .map(|timezone| timezone.name());
+    let default = default_timezone().name();
+
+    [own, Some(default)]
+        .into_iter()
+        .flatten()
+        .find_map(|timezone| {
+            buckets
+                .iter()
+                .find(|bucket| bucket.timezone == timezone)
+                .map(|bucket| bucket.timezone.as_str())
+        })
+}
+
+// Timezone buckets of scheduled campaigns, keyed by campaign and timezone
+pub struct ScheduleStore {
+    client: Client,
+    table_name: String,
+}
+
+impl ScheduleStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(CAMPAIGN_SCHEDULE_TABLE_NAME),
+        }
+    }
+
+    pub async fn create(&self, buckets: &[ScheduleBucket]) -> Result<(), Error> {
+        let items = buckets
+            .iter()
+            .map(ScheduleBucket::to_dynamodb_item)
+            .collect();
+        batch_put(&self.client, &self.table_name, items).await?;
+
+        Ok(())
+    }
+
+    pub async fn list(&self, campaign_id: &str) -> Result<Vec<ScheduleBucket>, Error> {
+        let mut buckets = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("campaign_id = :campaign_id")
+                .expression_attribute_values(
+                    ":campaign_id",
+                    AttributeValue::S(campaign_id.to_string()),
+                )
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            buckets.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(ScheduleBucket::from_dynamodb_item),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        buckets.sort_by_key(|bucket| bucket.send_at);
+        Ok(buckets)
+    }
+
+    // Pending buckets whose send time has come. Only scheduled campaigns have
+    // buckets, a few dozen each, so the table is scanned.
+    pub async fn due(&self, now: DateTime<Utc>) -> Result<Vec<ScheduleBucket>, Error> {
+        let mut buckets = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("#status = :pending AND send_at <= :now")
+                .expression_attribute_names("#status", "status")
+                .expression_attribute_values(
+                    ":pending",
+                    AttributeValue::S(BucketStatus::Pending.as_str().to_string()),
+                )
+                .expression_attribute_values(":now", AttributeValue::S(now.to_rfc3339()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            buckets.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(ScheduleBucket::from_dynamodb_item),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(buckets)
+    }
+
+    // Buckets whose send died mid-way, with no checkpoint for longer than any
+    // run lasts
+    pub async fn stalled(&self, now: DateTime<Utc>) -> Result<Vec<ScheduleBucket>, Error> {
+        let mut buckets = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .filter_expression("#status = :sending")
+                .expression_attribute_names("#status", "status")
+                .expression_attribute_values(
+                    ":sending",
+                    AttributeValue::S(BucketStatus::Sending.as_str().to_string()),
+                )
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            buckets.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(ScheduleBucket::from_dynamodb_item)
+                    .filter(|bucket| {
+                        bucket
+                            .checkpoint
+                            .as_ref()
+                            .is_some_and(|checkpoint| checkpoint.is_stalled(now))
+                    }),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(buckets)
+    }
+
+    // Move a bucket from pending to sending with a fresh checkpoint, returning
+    // false when another run got to it first
+    pub async fn claim(&self, bucket: &ScheduleBucket) -> Result<bool, Error> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("campaign_id", AttributeValue::S(bucket.campaign_id.clone()))
+            .key("timezone", AttributeValue::S(bucket.timezone.clone()))
+            .update_expression(
+                "SET #status = :sending, checkpoint_sent = :zero, checkpoint_at = :now",
+            )
+            .condition_expression("#status = :pending")
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":sending",
+                AttributeValue::S(BucketStatus::Sending.as_str().to_string()),
+            )
+            .expression_attribute_values(
+                ":pending",
+                AttributeValue::S(BucketStatus::Pending.as_str().to_string()),
+            )
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Take over a stalled bucket, returning false when its checkpoint moved on
+    // since it was read, i.e. another run took it over already
+    pub async fn reclaim(&self, bucket: &ScheduleBucket) -> Result<bool, Error> {
+        let Some(seen) = &bucket.checkpoint else {
+            return Ok(false);
+        };
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("campaign_id", AttributeValue::S(bucket.campaign_id.clone()))
+            .key("timezone", AttributeValue::S(bucket.timezone.clone()))
+            .update_expression("SET checkpoint_at = :now")
+            .condition_expression("#status = :sending AND checkpoint_at = :seen")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":sending",
+                AttributeValue::S(BucketStatus::Sending.as_str().to_string()),
+            )
+            .expression_attribute_values(":seen", AttributeValue::S(seen.at.to_rfc3339()))
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn save_checkpoint(
+        &self,
+        bucket: &ScheduleBucket,
+        checkpoint: &SendCheckpoint,
+    ) -> Result<(), Error> {
+        let subscriber_id = match &checkpoint.last_subscriber_id {
+            Some(id) => AttributeValue::S(id.clone()),
+            None => AttributeValue::Null(true),
+        };
+
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("campaign_id", AttributeValue::S(bucket.campaign_id.clone()))
+            .key("timezone", AttributeValue::S(bucket.timezone.clone()))
+            .update_expression(
+                "SET checkpoint_subscriber_id = :subscriber_id, checkpoint_sent = :sent, checkpoint_at = :at",
+            )
+            .expression_attribute_values(":subscriber_id", subscriber_id)
+            .expression_attribute_values(":sent", AttributeValue::N(checkpoint.sent.to_string()))
+            .expression_attribute_values(":at", AttributeValue::S(checkpoint.at.to_rfc3339()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn complete(&self, bucket: &ScheduleBucket, sent_count: u32) -> Result<(), Error> {
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("campaign_id", AttributeValue::S(bucket.campaign_id.clone()))
+            .key("timezone", AttributeValue::S(bucket.timezone.clone()))
+            .update_expression("SET #status = :sent, sent_count = :sent_count")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":sent",
+                AttributeValue::S(BucketStatus::Sent.as_str().to_string()),
+            )
+            .expression_attribute_values(":sent_count", AttributeValue::N(sent_count.to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+// Subscriber ids a scheduled campaign was resolved to, keyed by campaign and
+// subscriber. Recipients are still checked at send time, so the snapshot only
+// ever narrows who gets the campaign.
+pub struct RecipientSnapshotStore {
+    client: Client,
+    table_name: String,
+}
+
+impl RecipientSnapshotStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(CAMPAIGN_RECIPIENTS_TABLE_NAME),
+        }
+    }
+
+    // Store the recipients, kept until `SNAPSHOT_RETENTION_DAYS` after the
+    // last bucket goes out
+    pub async fn create(
+        &self,
+        campaign_id: &str,
+        recipients: &[Subscriber],
+        buckets: &[ScheduleBucket],
+    ) -> Result<usize, Error> {
+        let last_send = buckets
+            .iter()
+            .map(|bucket| bucket.send_at)
+            .max()
+            .unwrap_or_else(Utc::now);
+        let expires_at = (last_send + Duration::days(SNAPSHOT_RETENTION_DAYS))
+            .timestamp()
+            .to_string();
+
+        let items = recipients
+            .iter()
+            .map(|subscriber| {
+                HashMap::from([
+                    (
+                        "campaign_id".to_string(),
+                        AttributeValue::S(campaign_id.to_string()),
+                    ),
+                    (
+                        "subscriber_id".to_string(),
+                        AttributeValue::S(subscriber.id.clone()),
+                    ),
+                    (
+                        "expires_at".to_string(),
+                        AttributeValue::N(expires_at.clone()),
+                    ),
+                ])
+            })
+            .collect();
+
+        batch_put(&self.client, &self.table_name, items).await
+    }
+
+    pub async fn subscriber_ids(&self, campaign_id: &str) -> Result<HashSet<String>, Error> {
+        let mut ids = HashSet::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression("campaign_id = :campaign_id")
+                .expression_attribute_values(
+                    ":campaign_id",
+                    AttributeValue::S(campaign_id.to_string()),
+                )
+                .projection_expression("subscriber_id")
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            ids.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|item| {
+                        item.get("subscriber_id")
+                            .and_then(|value| value.as_s().ok())
+                            .cloned()
+                    }),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(ids)
+    }
+
+    // Ids of the snapshotted recipients from `first_id` to `last_id`, both
+    // included, in id order
+    pub async fn subscriber_ids_between(
+        &self,
+        campaign_id: &str,
+        first_id: &str,
+        last_id: &str,
+    ) -> Result<Vec<String>, Error> {
+        let mut ids = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .query()
+                .table_name(&self.table_name)
+                .key_condition_expression(
+                    "campaign_id = :campaign_id AND subscriber_id BETWEEN :first AND :last",
+                )
+                .expression_attribute_values(
+                    ":campaign_id",
+                    AttributeValue::S(campaign_id.to_string()),
+                )
+                .expression_attribute_values(":first", AttributeValue::S(first_id.to_string()))
+                .expression_attribute_values(":last", AttributeValue::S(last_id.to_string()))
+                .projection_expression("subscriber_id")
+                .consistent_read(true)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            ids.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|item| {
+                        item.get("subscriber_id")
+                            .and_then(|value| value.as_s().ok())
+                            .cloned()
+                    }),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(ids)
+    }
+}
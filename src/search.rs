@@ -0,0 +1,73 @@
+use crate::address::canonical_email;
+
+// Characters of the lowercased email used as the partition key of the prefix
+// index. Prefix searches need at least this many.
+pub const PREFIX_BUCKET_LENGTH: usize = 2;
+
+// Canonical email, the sort key of both search indexes
+pub fn search_email(email: &str) -> String {
+    canonical_email(email)
+}
+
+// Domain part of an email address, lowercased
+pub fn email_domain(email: &str) -> Option<String> {
+    let email = search_email(email);
+    let (_, domain) = email.rsplit_once('@')?;
+    (!domain.is_empty()).then(|| domain.to_string())
+}
+
+// Partition key of the prefix index
+pub fn prefix_bucket(email: &str) -> Option<String> {
+    let email = search_email(email);
+    let bucket: String = email.chars().take(PREFIX_BUCKET_LENGTH).collect();
+    (bucket.chars().count() == PREFIX_BUCKET_LENGTH).then_some(bucket)
+}
+
+// An admin search over subscriber emails, each answered by a single index query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubscriberSearch {
+    // The exact address, through the email index
+    Email(String),
+    // Addresses starting with the given text, e.g. `ada` or `ada@exa`
+    Prefix(String),
+    // Every address at the domain, e.g. `example.com`
+    Domain(String),
+}
+
+impl SubscriberSearch {
+    // Build a search from `email`, `prefix` or `domain` query parameters,
+    // exactly one of which must be given
+    pub fn from_params(
+        email: Option<&str>,
+        prefix: Option<&str>,
+        domain: Option<&str>,
+    ) -> Result<Self, String> {
+        fn given(value: Option<&str>) -> Option<&str> {
+            value.map(str::trim).filter(|value| !value.is_empty())
+        }
+
+        match (given(email), given(prefix), given(domain)) {
+            (Some(email), None, None) => Ok(SubscriberSearch::Email(email.to_string())),
+            (None, Some(prefix), None) => {
+                let prefix = search_email(prefix);
+                if prefix.chars().count() < PREFIX_BUCKET_LENGTH {
+                    return Err(format!(
+                        "prefix must be at least {PREFIX_BUCKET_LENGTH} characters"
+                    ));
+                }
+                Ok(SubscriberSearch::Prefix(prefix))
+            }
+            (None, None, Some(domain)) => {
+                // Unicode domains are indexed in punycode
+                let domain = idna::domain_to_ascii(domain.trim().trim_start_matches('@'))
+                    .unwrap_or_default();
+                if domain.is_empty() || domain.contains('@') {
+                    return Err("Invalid domain".to_string());
+                }
+                Ok(SubscriberSearch::Domain(domain))
+            }
+            (None, None, None) => Err("One of email, prefix or domain is required".to_string()),
+            _ => Err("Only one of email, prefix or domain can be given".to_string()),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use crate::quarantine::QuarantineReason;
+use crate::stripe::Tier;
+use crate::{Error, Subscriber};
+use chrono::{DateTime, NaiveDate, Utc};
+
+// Subscriber segment, written as conditions joined by AND, e.g.
+// `engagement_score >= 2 AND email_domain != example.com`
+#[derive(Debug, Clone)]
+pub struct Segment {
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    EngagementScore,
+    EmailDomain,
+    CreatedAt,
+    LastEngagedAt,
+    Tier,
+    QuarantineReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(f64),
+    Text(String),
+    Date(DateTime<Utc>),
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    field: Field,
+    operator: Operator,
+    value: Value,
+}
+
+impl Field {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "engagement_score" => Some(Field::EngagementScore),
+            "email_domain" => Some(Field::EmailDomain),
+            "created_at" => Some(Field::CreatedAt),
+            "last_engaged_at" => Some(Field::LastEngagedAt),
+            "tier" => Some(Field::Tier),
+            "quarantine_reason" => Some(Field::QuarantineReason),
+            _ => None,
+        }
+    }
+
+    // Value of this field for a subscriber, None when the subscriber doesn't have one
+    fn extract(&self, subscriber: &Subscriber, now: DateTime<Utc>) -> Option<Value> {
+        match self {
+            Field::EngagementScore => Some(Value::Number(subscriber.engagement_score_at(now))),
+            Field::EmailDomain => subscriber
+                .email
+                .rsplit_once('@')
+                .map(|(_, domain)| Value::Text(domain.to_lowercase())),
+            Field::CreatedAt => Some(Value::Date(subscriber.created_at)),
+            Field::LastEngagedAt => subscriber.last_engaged_at.map(Value::Date),
+            Field::Tier => Some(Value::Text(subscriber.tier.as_str().to_string())),
+            Field::QuarantineReason => subscriber
+                .quarantine_reason
+                .map(|reason| Value::Text(reason.as_str().to_string())),
+        }
+    }
+}
+
+impl Operator {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "=" => Some(Operator::Eq),
+            "!=" => Some(Operator::Ne),
+            ">" => Some(Operator::Gt),
+            ">=" => Some(Operator::Ge),
+            "<" => Some(Operator::Lt),
+            "<=" => Some(Operator::Le),
+            _ => None,
+        }
+    }
+
+    fn compare<T: PartialOrd>(&self, left: T, right: T) -> bool {
+        match self {
+            Operator::Eq => left == right,
+            Operator::Ne => left != right,
+            Operator::Gt => left > right,
+            Operator::Ge => left >= right,
+            Operator::Lt => left < right,
+            Operator::Le => left <= right,
+        }
+    }
+}
+
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc())
+}
+
+impl Condition {
+    fn parse(field: &str, operator: &str, value: &str) -> Result<Self, Error> {
+        let field =
+            Field::parse(field).ok_or_else(|| format!("Unknown segment field `{field}`"))?;
+        let operator = Operator::parse(operator)
+            .ok_or_else(|| format!("Unknown segment operator `{operator}`"))?;
+
+        let value = match field {
+            Field::EngagementScore => Value::Number(
+                value
+                    .parse()
+                    .map_err(|_| format!("`{value}` is not a number"))?,
+            ),
+            Field::EmailDomain => {
+                if !matches!(operator, Operator::Eq | Operator::Ne) {
+                    return Err("email_domain only supports = and !=".into());
+                }
+                Value::Text(value.to_lowercase())
+            }
+            Field::Tier => {
+                if !matches!(operator, Operator::Eq | Operator::Ne) {
+                    return Err("tier only supports = and !=".into());
+                }
+                let tier = Tier::parse(&value.to_lowercase())
+                    .ok_or_else(|| format!("Unknown tier `{value}`, expected free or premium"))?;
+                Value::Text(tier.as_str().to_string())
+            }
+            Field::QuarantineReason => {
+                if !matches!(operator, Operator::Eq | Operator::Ne) {
+                    return Err("quarantine_reason only supports = and !=".into());
+                }
+                let reason = QuarantineReason::parse(&value.to_lowercase()).ok_or_else(|| {
+                    format!(
+                        "Unknown quarantine reason `{value}`, expected signup_burst or dictionary_local_part"
+                    )
+                })?;
+                Value::Text(reason.as_str().to_string())
+            }
+            Field::CreatedAt | Field::LastEngagedAt => {
+                Value::Date(parse_date(value).ok_or_else(|| format!("`{value}` is not a date"))?)
+            }
+        };
+
+        Ok(Self {
+            field,
+            operator,
+            value,
+        })
+    }
+
+    fn matches(&self, subscriber: &Subscriber, now: DateTime<Utc>) -> bool {
+        match (self.field.extract(subscriber, now), &self.value) {
+            (Some(Value::Number(left)), Value::Number(right)) => {
+                self.operator.compare(left, *right)
+            }
+            (Some(Value::Text(left)), Value::Text(right)) => {
+                self.operator.compare(left.as_str(), right.as_str())
+            }
+            (Some(Value::Date(left)), Value::Date(right)) => self.operator.compare(left, *right),
+            // Missing values only satisfy "not equal"
+            _ => self.operator == Operator::Ne,
+        }
+    }
+}
+
+impl Segment {
+    pub fn parse(expression: &str) -> Result<Self, Error> {
+        let tokens: Vec<&str> = expression.split_whitespace().collect();
+        let mut conditions = Vec::new();
+
+        for (index, chunk) in tokens
+            .split(|token| token.eq_ignore_ascii_case("and"))
+            .enumerate()
+        {
+            match chunk {
+                [field, operator, value] => {
+                    conditions.push(Condition::parse(field, operator, value)?)
+                }
+                _ => {
+                    return Err(format!(
+                        "Condition {} must look like `field operator value`",
+                        index + 1
+                    )
+                    .into());
+                }
+            }
+        }
+
+        Ok(Self { conditions })
+    }
+
+    pub fn matches(&self, subscriber: &Subscriber, now: DateTime<Utc>) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(subscriber, now))
+    }
+}
@@ -0,0 +1,336 @@
+use crate::config::table_name;
+use crate::email::format_address;
+use crate::search::email_domain;
+use crate::{Error, SENDER_IDENTITIES_TABLE_NAME, optional_datetime};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_sdk_sesv2::Client as SesClient;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SenderStatus {
+    // Waiting for the address, or its domain, to be verified in SES
+    Pending,
+    Verified,
+}
+
+impl SenderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SenderStatus::Pending => "pending",
+            SenderStatus::Verified => "verified",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(SenderStatus::Pending),
+            "verified" => Some(SenderStatus::Verified),
+            _ => None,
+        }
+    }
+}
+
+// A From address campaigns can be sent from instead of SENDER_EMAIL, e.g. one
+// per newsletter sharing the deployment
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SenderIdentity {
+    // Short name campaigns refer to, e.g. "weekly"
+    pub id: String,
+    pub from_address: String,
+    // Display name shown by mail clients, e.g. "Example Weekly"
+    pub from_name: Option<String>,
+    pub reply_to: Option<String>,
+    pub status: SenderStatus,
+    pub created_at: DateTime<Utc>,
+    pub verified_at: Option<DateTime<Utc>>,
+}
+
+impl SenderIdentity {
+    // The From header value, with the display name when there is one
+    pub fn from_header(&self) -> String {
+        format_address(self.from_name.as_deref(), &self.from_address)
+    }
+
+    fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert("id".to_string(), AttributeValue::S(self.id.clone()));
+        item.insert(
+            "from_address".to_string(),
+            AttributeValue::S(self.from_address.clone()),
+        );
+        if let Some(from_name) = &self.from_name {
+            item.insert(
+                "from_name".to_string(),
+                AttributeValue::S(from_name.clone()),
+            );
+        }
+        if let Some(reply_to) = &self.reply_to {
+            item.insert("reply_to".to_string(), AttributeValue::S(reply_to.clone()));
+        }
+        item.insert(
+            "status".to_string(),
+            AttributeValue::S(self.status.as_str().to_string()),
+        );
+        item.insert(
+            "created_at".to_string(),
+            AttributeValue::S(self.created_at.to_rfc3339()),
+        );
+        if let Some(verified_at) = self.verified_at {
+            item.insert(
+                "verified_at".to_string(),
+                AttributeValue::S(verified_at.to_rfc3339()),
+            );
+        }
+        item
+    }
+
+    fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let optional = |name: &str| item.get(name).and_then(|value| value.as_s().ok()).cloned();
+
+        Some(Self {
+            id: item.get("id")?.as_s().ok()?.clone(),
+            from_address: item.get("from_address")?.as_s().ok()?.clone(),
+            from_name: optional("from_name"),
+            reply_to: optional("reply_to"),
+            status: item
+                .get("status")
+                .and_then(|value| value.as_s().ok())
+                .and_then(|value| SenderStatus::parse(value))
+                .unwrap_or(SenderStatus::Pending),
+            created_at: optional_datetime(item, "created_at").unwrap_or_else(Utc::now),
+            verified_at: optional_datetime(item, "verified_at"),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateSenderRequest {
+    // Lowercase letters, digits and dashes, e.g. "weekly"
+    pub id: String,
+    pub from_address: String,
+    #[serde(default)]
+    pub from_name: Option<String>,
+    #[serde(default)]
+    pub reply_to: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SenderListResponse {
+    pub success: bool,
+    pub senders: Vec<SenderIdentity>,
+}
+
+// Sender ids end up in campaign records and admin URLs, so keep them simple
+pub fn is_valid_sender_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+// Address verification through SES. Sending from an address works once either
+// the address itself or its whole domain is verified.
+pub struct SenderVerifier {
+    client: SesClient,
+}
+
+impl SenderVerifier {
+    pub fn new(client: SesClient) -> Self {
+        Self { client }
+    }
+
+    pub async fn is_verified(&self, address: &str) -> Result<bool, Error> {
+        let identities = [Some(address.to_string()), email_domain(address)];
+
+        for identity in identities.into_iter().flatten() {
+            let result = self
+                .client
+                .get_email_identity()
+                .email_identity(&identity)
+                .send()
+                .await;
+
+            match result {
+                Ok(output) if output.verified_for_sending_status() => return Ok(true),
+                Ok(_) => {}
+                Err(SdkError::ServiceError(err)) if err.err().is_not_found_exception() => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(false)
+    }
+
+    // Start verifying an address, which makes SES email it a confirmation link.
+    // Returns whether it can already be sent from.
+    pub async fn request(&self, address: &str) -> Result<bool, Error> {
+        if self.is_verified(address).await? {
+            return Ok(true);
+        }
+
+        let result = self
+            .client
+            .create_email_identity()
+            .email_identity(address)
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(false),
+            // Verification was already started, e.g. by an earlier identity
+            Err(SdkError::ServiceError(err)) if err.err().is_already_exists_exception() => {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+// Sender identities, one item per id
+pub struct SenderStore {
+    client: Client,
+    table_name: String,
+}
+
+impl SenderStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(SENDER_IDENTITIES_TABLE_NAME),
+        }
+    }
+
+    // Store a new identity, returning false when the id is taken
+    pub async fn create(&self, sender: &SenderIdentity) -> Result<bool, Error> {
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(sender.to_dynamodb_item()))
+            .condition_expression("attribute_not_exists(id)")
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<SenderIdentity>, Error> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .send()
+            .await?;
+
+        Ok(result.item().and_then(SenderIdentity::from_dynamodb_item))
+    }
+
+    pub async fn list(&self) -> Result<Vec<SenderIdentity>, Error> {
+        let mut senders = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            senders.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(SenderIdentity::from_dynamodb_item),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        senders.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(senders)
+    }
+
+    // Remove an identity, returning false when it doesn't exist. The SES
+    // identity is left alone, since other senders may share its domain.
+    pub async fn delete(&self, id: &str) -> Result<bool, Error> {
+        let result = self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await?;
+
+        Ok(result.attributes().is_some())
+    }
+
+    // Check a pending identity with SES, recording when it got verified
+    pub async fn refresh(
+        &self,
+        verifier: &SenderVerifier,
+        mut sender: SenderIdentity,
+    ) -> Result<SenderIdentity, Error> {
+        if sender.status == SenderStatus::Verified
+            || !verifier.is_verified(&sender.from_address).await?
+        {
+            return Ok(sender);
+        }
+
+        let now = Utc::now();
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(sender.id.clone()))
+            .update_expression("SET #status = :verified, verified_at = :now")
+            .condition_expression("attribute_exists(id)")
+            .expression_attribute_names("#status", "status")
+            .expression_attribute_values(
+                ":verified",
+                AttributeValue::S(SenderStatus::Verified.as_str().to_string()),
+            )
+            .expression_attribute_values(":now", AttributeValue::S(now.to_rfc3339()))
+            .send()
+            .await?;
+
+        sender.status = SenderStatus::Verified;
+        sender.verified_at = Some(now);
+        Ok(sender)
+    }
+
+    // The identity to send from, or None when it doesn't exist or isn't
+    // verified yet, since SES would reject every send from it
+    pub async fn verified(
+        &self,
+        verifier: &SenderVerifier,
+        id: &str,
+    ) -> Result<Option<SenderIdentity>, Error> {
+        let Some(sender) = self.get(id).await? else {
+            return Ok(None);
+        };
+        let sender = self.refresh(verifier, sender).await?;
+        Ok((sender.status == SenderStatus::Verified).then_some(sender))
+    }
+}
@@ -0,0 +1,104 @@
+use crate::email::{CAMPAIGN_TAG, SUBSCRIBER_TAG};
+use crate::events::{Event, EventType};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// Event published by an SES configuration set, as delivered in the SNS message
+// https://docs.aws.amazon.com/ses/latest/dg/event-publishing-retrieving-sns-contents.html
+#[derive(Debug, Deserialize)]
+pub struct SesEvent {
+    #[serde(rename = "eventType")]
+    pub event_type: String,
+    pub mail: SesMail,
+    pub delivery: Option<SesTimestamped>,
+    pub open: Option<SesTimestamped>,
+    pub click: Option<SesClick>,
+    pub reject: Option<SesReject>,
+    pub bounce: Option<SesBounce>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SesMail {
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+    pub timestamp: DateTime<Utc>,
+    // Recipient addresses
+    #[serde(default)]
+    pub destination: Vec<String>,
+    // Message tags, each with a list of values
+    #[serde(default)]
+    pub tags: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SesTimestamped {
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SesClick {
+    pub timestamp: DateTime<Utc>,
+    pub link: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SesReject {
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SesBounce {
+    #[serde(rename = "bounceType")]
+    pub bounce_type: String,
+    #[serde(rename = "bounceSubType", default)]
+    pub bounce_sub_type: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl SesMail {
+    fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .get(name)
+            .and_then(|values| values.first())
+            .map(String::as_str)
+    }
+}
+
+impl SesBounce {
+    // Permanent bounces mean the address will never accept mail
+    pub fn is_permanent(&self) -> bool {
+        self.bounce_type == "Permanent"
+    }
+}
+
+impl SesEvent {
+    // The event to store, None for event types we don't track or mail that wasn't
+    // sent to a subscriber (no subscriber_id tag)
+    pub fn to_event(&self) -> Option<Event> {
+        let subscriber_id = self.mail.tag(SUBSCRIBER_TAG)?;
+
+        let (event_type, occurred_at) = match self.event_type.as_str() {
+            "Delivery" => (EventType::Delivery, self.delivery.as_ref()?.timestamp),
+            "Open" => (EventType::Open, self.open.as_ref()?.timestamp),
+            "Click" => (EventType::Click, self.click.as_ref()?.timestamp),
+            // Rejected mail is never attempted, so SES has no later timestamp
+            "Reject" => (EventType::Reject, self.mail.timestamp),
+            "Bounce" => (EventType::Bounce, self.bounce.as_ref()?.timestamp),
+            _ => return None,
+        };
+
+        let mut event = Event::new(subscriber_id.to_string(), event_type);
+        event.campaign_id = self.mail.tag(CAMPAIGN_TAG).map(str::to_string);
+        event.message_id = Some(self.mail.message_id.clone());
+        event.url = self.click.as_ref().map(|click| click.link.clone());
+        event.detail = match (&self.reject, &self.bounce) {
+            (Some(reject), _) => Some(reject.reason.clone()),
+            (_, Some(bounce)) => Some(format!("{}/{}", bounce.bounce_type, bounce.bounce_sub_type)),
+            _ => None,
+        };
+        event.occurred_at = occurred_at;
+
+        Some(event)
+    }
+}
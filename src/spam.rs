@@ -0,0 +1,47 @@
+use crate::SubscribeRequest;
+use chrono::{DateTime, Utc};
+use std::env;
+
+// Humans need at least this long to fill in the form
+pub const DEFAULT_MIN_SUBMIT_SECONDS: i64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpamReason {
+    // The hidden honeypot field was filled in
+    Honeypot,
+    // The form was submitted faster than a person could type
+    TooFast,
+}
+
+impl SpamReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpamReason::Honeypot => "honeypot",
+            SpamReason::TooFast => "too_fast",
+        }
+    }
+}
+
+fn min_submit_seconds() -> i64 {
+    env::var("MIN_SUBMIT_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MIN_SUBMIT_SECONDS)
+}
+
+// Honeypot and time-trap heuristics, both optional for the frontend to use
+pub fn check_submission(request: &SubscribeRequest, now: DateTime<Utc>) -> Option<SpamReason> {
+    if request.hp.as_deref().is_some_and(|hp| !hp.is_empty()) {
+        return Some(SpamReason::Honeypot);
+    }
+
+    // A negative elapsed time means the client clock runs ahead, give it the benefit of the doubt
+    if let Some(rendered_at) = request.rendered_at {
+        let elapsed_ms = now.timestamp_millis() - rendered_at;
+        if (0..min_submit_seconds() * 1000).contains(&elapsed_ms) {
+            return Some(SpamReason::TooFast);
+        }
+    }
+
+    None
+}
@@ -0,0 +1,241 @@
+use crate::signing::decode_hex;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use utoipa::ToSchema;
+
+// Signatures older than this are rejected to prevent replays
+const SIGNATURE_TOLERANCE_SECONDS: i64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    #[default]
+    Free,
+    Premium,
+}
+
+impl Tier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tier::Free => "free",
+            Tier::Premium => "premium",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "free" => Some(Tier::Free),
+            "premium" => Some(Tier::Premium),
+            _ => None,
+        }
+    }
+}
+
+// Verify a `Stripe-Signature` header (`t=<timestamp>,v1=<signature>,...`) against
+// the raw request body, as described in Stripe's webhook documentation
+pub fn verify_signature(payload: &[u8], header: &str, secret: &str, now: i64) -> bool {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+            Some(("v1", value)) => signatures.extend(decode_hex(value)),
+            _ => {}
+        }
+    }
+
+    let timestamp = match timestamp {
+        Some(timestamp) if (now - timestamp).abs() <= SIGNATURE_TOLERANCE_SECONDS => timestamp,
+        _ => return false,
+    };
+
+    signatures.iter().any(|signature| {
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+            return false;
+        };
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(payload);
+        mac.verify_slice(signature).is_ok()
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StripeEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub data: StripeEventData,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StripeEventData {
+    pub object: Value,
+}
+
+// Who a tier change applies to and the resulting tier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TierUpdate {
+    // Set through `client_reference_id` when the checkout link carries the subscriber id
+    pub subscriber_id: Option<String>,
+    pub email: Option<String>,
+    pub customer_id: Option<String>,
+    pub tier: Tier,
+}
+
+fn string_at(object: &Value, pointer: &str) -> Option<String> {
+    object
+        .pointer(pointer)
+        .and_then(Value::as_str)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string())
+}
+
+impl StripeEvent {
+    // Map checkout and subscription events to a tier change, ignoring everything else
+    pub fn tier_update(&self) -> Option<TierUpdate> {
+        let object = &self.data.object;
+        let customer_id = string_at(object, "/customer");
+
+        let tier = match self.event_type.as_str() {
+            "checkout.session.completed" => {
+                if string_at(object, "/mode").as_deref() != Some("subscription") {
+                    return None;
+                }
+                return Some(TierUpdate {
+                    subscriber_id: string_at(object, "/client_reference_id"),
+                    email: string_at(object, "/customer_details/email")
+                        .or_else(|| string_at(object, "/customer_email")),
+                    customer_id,
+                    tier: Tier::Premium,
+                });
+            }
+            "customer.subscription.created" | "customer.subscription.updated" => {
+                match string_at(object, "/status").as_deref() {
+                    Some("active" | "trialing") => Tier::Premium,
+                    // Keep access while Stripe retries a failed payment
+                    Some("past_due") => return None,
+                    _ => Tier::Free,
+                }
+            }
+            "customer.subscription.deleted" => Tier::Free,
+            _ => return None,
+        };
+
+        // Subscription events only identify the customer
+        customer_id.as_ref()?;
+
+        Some(TierUpdate {
+            subscriber_id: None,
+            email: None,
+            customer_id,
+            tier,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "whsec_test";
+    const PAYLOAD: &[u8] = br#"{"id":"evt_1","type":"checkout.session.completed"}"#;
+    const NOW: i64 = 1_700_000_000;
+
+    fn header(payload: &[u8], timestamp: i64) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(format!("{timestamp}.").as_bytes());
+        mac.update(payload);
+        let signature: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        format!("t={timestamp},v1={signature}")
+    }
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        assert!(verify_signature(
+            PAYLOAD,
+            &header(PAYLOAD, NOW),
+            SECRET,
+            NOW
+        ));
+        // Within the tolerance either way
+        assert!(verify_signature(
+            PAYLOAD,
+            &header(PAYLOAD, NOW - 300),
+            SECRET,
+            NOW
+        ));
+        assert!(verify_signature(
+            PAYLOAD,
+            &header(PAYLOAD, NOW + 300),
+            SECRET,
+            NOW
+        ));
+    }
+
+    #[test]
+    fn accepts_any_matching_v1_signature() {
+        let header = format!("{},v1=00ff", header(PAYLOAD, NOW));
+        assert!(verify_signature(PAYLOAD, &header, SECRET, NOW));
+    }
+
+    #[test]
+    fn rejects_a_tampered_payload() {
+        let header = header(PAYLOAD, NOW);
+        let tampered = br#"{"id":"evt_2","type":"checkout.session.completed"}"#;
+        assert!(!verify_signature(tampered, &header, SECRET, NOW));
+    }
+
+    #[test]
+    fn rejects_the_wrong_secret() {
+        assert!(!verify_signature(
+            PAYLOAD,
+            &header(PAYLOAD, NOW),
+            "whsec_other",
+            NOW
+        ));
+    }
+
+    #[test]
+    fn rejects_a_stale_timestamp() {
+        assert!(!verify_signature(
+            PAYLOAD,
+            &header(PAYLOAD, NOW - 301),
+            SECRET,
+            NOW
+        ));
+        assert!(!verify_signature(
+            PAYLOAD,
+            &header(PAYLOAD, NOW + 301),
+            SECRET,
+            NOW
+        ));
+    }
+
+    #[test]
+    fn rejects_a_malformed_header() {
+        let valid = header(PAYLOAD, NOW);
+        let signature = valid.split_once(",v1=").unwrap().1;
+        for header in [
+            String::new(),
+            "garbage".to_string(),
+            format!("v1={signature}"),
+            format!("t=not-a-number,v1={signature}"),
+            format!("t={NOW}"),
+            format!("t={NOW},v1=zz"),
+            format!("t={NOW},v0={signature}"),
+        ] {
+            assert!(
+                !verify_signature(PAYLOAD, &header, SECRET, NOW),
+                "accepted {header:?}"
+            );
+        }
+    }
+}
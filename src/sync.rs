@@ -0,0 +1,64 @@
+use crate::Subscriber;
+use crate::admin::SubscriberStatus;
+use crate::repository::ItemKey;
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// Partition key of the updated-at index. Every subscriber shares it, so all
+// changes come back as one ordered stream.
+pub const SYNC_PARTITION: &str = "subscribers";
+
+// Writes stamp updated_at just before they land, so the latest changes are
+// held back until the next poll in case an earlier stamp is still in flight
+pub const SETTLE_SECONDS: i64 = 10;
+
+// Fixed-width UTC timestamp, so index keys sort in time order
+pub fn sync_timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string()
+}
+
+// Sort key of the updated-at index. The id orders changes made in the same
+// microsecond.
+pub fn sync_key(updated_at: DateTime<Utc>, id: &str) -> String {
+    format!("{}#{id}", sync_timestamp(updated_at))
+}
+
+// Newest change a poll made now may return
+pub fn settled_until(now: DateTime<Utc>) -> String {
+    sync_timestamp(now - Duration::seconds(SETTLE_SECONDS))
+}
+
+// Key of the subscriber's entry in the updated-at index, for resuming after it
+pub fn index_key(subscriber: &Subscriber) -> ItemKey {
+    ItemKey::from([
+        ("id".to_string(), AttributeValue::S(subscriber.id.clone())),
+        (
+            "sync_partition".to_string(),
+            AttributeValue::S(SYNC_PARTITION.to_string()),
+        ),
+        (
+            "sync_key".to_string(),
+            AttributeValue::S(sync_key(subscriber.updated_at, &subscriber.id)),
+        ),
+    ])
+}
+
+// A subscriber as of their latest change
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SubscriberChange {
+    pub status: SubscriberStatus,
+    pub subscriber: Subscriber,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SubscriberChangesResponse {
+    pub success: bool,
+    // Oldest change first
+    pub changes: Vec<SubscriberChange>,
+    // Pass back to get the changes after these, now or on a later poll
+    pub next_cursor: Option<String>,
+    // Whether more changes were already waiting
+    pub has_more: bool,
+}
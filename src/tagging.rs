@@ -0,0 +1,86 @@
+use crate::segment::Segment;
+use crate::{Error, Subscriber};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TagAction {
+    Add,
+    Remove,
+}
+
+impl TagAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TagAction::Add => "add",
+            TagAction::Remove => "remove",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "add" => Some(TagAction::Add),
+            "remove" => Some(TagAction::Remove),
+            _ => None,
+        }
+    }
+}
+
+// Tags, as stored in the `tags` custom field, are compared lowercased and
+// can't contain the comma separating them
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    (!tag.is_empty() && !tag.contains(',')).then_some(tag)
+}
+
+// Add or remove a tag in the subscriber's `tags` field, keeping the others as
+// they are. Returns whether the subscriber changed.
+pub fn apply_tag(subscriber: &mut Subscriber, tag: &str, action: TagAction) -> bool {
+    let mut tags: Vec<String> = subscriber
+        .fields
+        .get("tags")
+        .map(|tags| {
+            tags.split(',')
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    let tagged = tags.iter().any(|existing| existing.to_lowercase() == tag);
+
+    match action {
+        TagAction::Add if !tagged => tags.push(tag.to_string()),
+        TagAction::Remove if tagged => tags.retain(|existing| existing.to_lowercase() != tag),
+        _ => return false,
+    }
+
+    if tags.is_empty() {
+        subscriber.fields.remove("tags");
+    } else {
+        subscriber.fields.insert("tags".to_string(), tags.join(","));
+    }
+    true
+}
+
+// Parameters of a tag job
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TagParams {
+    pub tag: String,
+    pub action: TagAction,
+    // Segment expression picking the subscribers, e.g.
+    // `email_domain = example.com AND created_at >= 2026-01-01`
+    pub segment: String,
+}
+
+impl TagParams {
+    // The tag normalized, after checking it and the segment
+    pub fn normalized(self) -> Result<Self, Error> {
+        let tag = normalize_tag(&self.tag).ok_or("tag must be non-empty and without commas")?;
+        if self.segment.trim().is_empty() {
+            return Err("segment is required, e.g. `created_at >= 2026-01-01`".into());
+        }
+        Segment::parse(&self.segment)?;
+        Ok(Self { tag, ..self })
+    }
+}
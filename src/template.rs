@@ -0,0 +1,115 @@
+use crate::Subscriber;
+use crate::email::{
+    list_unsubscribe_url, one_click_unsubscribe_url, referral_status_url, web_version_url,
+};
+use crate::referral::referral_url;
+use std::collections::HashMap;
+
+pub fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+// Replace `{{ name }}` placeholders, leaving unknown ones empty
+fn render(template: &str, variables: &HashMap<String, String>, escape: bool) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+
+        rendered.push_str(&rest[..start]);
+        let name = rest[start + 2..start + end].trim();
+        if let Some(value) = variables.get(name) {
+            if escape {
+                rendered.push_str(&escape_html(value));
+            } else {
+                rendered.push_str(value);
+            }
+        }
+        rest = &rest[start + end + 2..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+// Whether a template uses the `{{ name }}` placeholder
+pub fn has_placeholder(template: &str, name: &str) -> bool {
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        if rest[start + 2..start + end].trim() == name {
+            return true;
+        }
+        rest = &rest[start + end + 2..];
+    }
+
+    false
+}
+
+// Render an HTML template, escaping the substituted values
+pub fn render_html(template: &str, variables: &HashMap<String, String>) -> String {
+    render(template, variables, true)
+}
+
+// Render a plain text template such as a subject line
+pub fn render_text(template: &str, variables: &HashMap<String, String>) -> String {
+    render(template, variables, false)
+}
+
+// Variables available to email templates: the subscriber's email and custom
+// fields, and their unsubscribe and referral status links when SIGNING_SECRET
+// is set
+pub fn subscriber_variables(subscriber: &Subscriber) -> HashMap<String, String> {
+    let mut variables = subscriber.fields.clone();
+    variables.insert("email".to_string(), subscriber.email.clone());
+    if let Ok(url) = one_click_unsubscribe_url(&subscriber.id) {
+        variables.insert("unsubscribe_url".to_string(), url);
+    }
+    if let Some(code) = &subscriber.referral_code {
+        variables.insert("referral_code".to_string(), code.clone());
+        variables.insert("referral_url".to_string(), referral_url(code));
+        if let Ok(url) = referral_status_url(&subscriber.id) {
+            variables.insert("referral_status_url".to_string(), url);
+        }
+    }
+    variables
+}
+
+// Variables of a campaign email: the subscriber's, and the link to the web
+// version of the campaign when SIGNING_SECRET is set. For a campaign sent to a
+// list, `unsubscribe_url` only leaves that list and `unsubscribe_all_url` opts
+// out of everything.
+pub fn campaign_variables(
+    subscriber: &Subscriber,
+    campaign_id: &str,
+    list: Option<&str>,
+) -> HashMap<String, String> {
+    let mut variables = subscriber_variables(subscriber);
+    if let Ok(url) = web_version_url(&subscriber.id, campaign_id) {
+        variables.insert("web_version_url".to_string(), url);
+    }
+    if let Some(list) = list
+        && let Ok(url) = list_unsubscribe_url(&subscriber.id, list)
+        && let Some(all) = variables.insert("unsubscribe_url".to_string(), url)
+    {
+        variables.insert("unsubscribe_all_url".to_string(), all);
+    }
+    variables
+}
@@ -0,0 +1,427 @@
+use crate::config::table_name;
+use crate::{Error, TEMPLATE_VERSIONS_TABLE_NAME, TEMPLATES_TABLE_NAME, optional_datetime};
+use aws_sdk_dynamodb::Client;
+use aws_sdk_dynamodb::error::SdkError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+// A reusable campaign template. Its content lives in immutable versions, and
+// campaigns using it are sent with whichever version is published.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Template {
+    pub id: String,
+    pub name: String,
+    // Highest version number handed out so far
+    pub latest_version: u32,
+    // Version new sends use, moved back to roll a bad edit back
+    pub published_version: Option<u32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Template {
+    fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert("id".to_string(), AttributeValue::S(self.id.clone()));
+        item.insert("name".to_string(), AttributeValue::S(self.name.clone()));
+        item.insert(
+            "latest_version".to_string(),
+            AttributeValue::N(self.latest_version.to_string()),
+        );
+        if let Some(version) = self.published_version {
+            item.insert(
+                "published_version".to_string(),
+                AttributeValue::N(version.to_string()),
+            );
+        }
+        item.insert(
+            "created_at".to_string(),
+            AttributeValue::S(self.created_at.to_rfc3339()),
+        );
+        item.insert(
+            "updated_at".to_string(),
+            AttributeValue::S(self.updated_at.to_rfc3339()),
+        );
+        item
+    }
+
+    fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let number = |name: &str| {
+            item.get(name)
+                .and_then(|value| value.as_n().ok())
+                .and_then(|value| value.parse().ok())
+        };
+
+        Some(Self {
+            id: item.get("id")?.as_s().ok()?.clone(),
+            name: item.get("name")?.as_s().ok()?.clone(),
+            latest_version: number("latest_version").unwrap_or(0),
+            published_version: number("published_version"),
+            created_at: optional_datetime(item, "created_at").unwrap_or_else(Utc::now),
+            updated_at: optional_datetime(item, "updated_at").unwrap_or_else(Utc::now),
+        })
+    }
+}
+
+// One saved edit of a template, never changed once written
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TemplateVersion {
+    pub template_id: String,
+    pub version: u32,
+    pub subject: String,
+    pub html_body: String,
+    // What changed, e.g. "fix footer link"
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TemplateVersion {
+    fn to_dynamodb_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert(
+            "template_id".to_string(),
+            AttributeValue::S(self.template_id.clone()),
+        );
+        item.insert(
+            "version".to_string(),
+            AttributeValue::N(self.version.to_string()),
+        );
+        item.insert(
+            "subject".to_string(),
+            AttributeValue::S(self.subject.clone()),
+        );
+        item.insert(
+            "html_body".to_string(),
+            AttributeValue::S(self.html_body.clone()),
+        );
+        if let Some(note) = &self.note {
+            item.insert("note".to_string(), AttributeValue::S(note.clone()));
+        }
+        item.insert(
+            "created_at".to_string(),
+            AttributeValue::S(self.created_at.to_rfc3339()),
+        );
+        item
+    }
+
+    fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        Some(Self {
+            template_id: item.get("template_id")?.as_s().ok()?.clone(),
+            version: item.get("version")?.as_n().ok()?.parse().ok()?,
+            subject: item.get("subject")?.as_s().ok()?.clone(),
+            html_body: item.get("html_body")?.as_s().ok()?.clone(),
+            note: item
+                .get("note")
+                .and_then(|value| value.as_s().ok())
+                .cloned(),
+            created_at: optional_datetime(item, "created_at").unwrap_or_else(Utc::now),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateTemplateRequest {
+    pub name: String,
+    pub subject: String,
+    pub html_body: String,
+}
+
+// A new version of a template
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateTemplateRequest {
+    pub subject: String,
+    pub html_body: String,
+    #[serde(default)]
+    pub note: Option<String>,
+    // Make it the published version right away
+    #[serde(default = "default_publish")]
+    pub publish: bool,
+}
+
+fn default_publish() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PublishTemplateRequest {
+    pub version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TemplateListResponse {
+    pub success: bool,
+    pub templates: Vec<Template>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TemplateResponse {
+    pub success: bool,
+    pub template: Template,
+    // Newest first
+    pub versions: Vec<TemplateVersion>,
+}
+
+// Templates with their version history: one item per template in one table,
+// and one item per version in another, keyed by template and version number
+pub struct TemplateStore {
+    client: Client,
+    table_name: String,
+    versions_table_name: String,
+}
+
+impl TemplateStore {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table_name: table_name(TEMPLATES_TABLE_NAME),
+            versions_table_name: table_name(TEMPLATE_VERSIONS_TABLE_NAME),
+        }
+    }
+
+    // Create a template with its content as version 1, published
+    pub async fn create(&self, request: CreateTemplateRequest) -> Result<Template, Error> {
+        let now = Utc::now();
+        let template = Template {
+            id: Uuid::new_v4().to_string(),
+            name: request.name,
+            latest_version: 1,
+            published_version: Some(1),
+            created_at: now,
+            updated_at: now,
+        };
+        let version = TemplateVersion {
+            template_id: template.id.clone(),
+            version: 1,
+            subject: request.subject,
+            html_body: request.html_body,
+            note: None,
+            created_at: now,
+        };
+
+        // The version goes first, so the template never points at a missing one
+        self.client
+            .put_item()
+            .table_name(&self.versions_table_name)
+            .set_item(Some(version.to_dynamodb_item()))
+            .send()
+            .await?;
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(template.to_dynamodb_item()))
+            .send()
+            .await?;
+
+        Ok(template)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<Template>, Error> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(id.to_string()))
+            .send()
+            .await?;
+
+        Ok(result.item().and_then(Template::from_dynamodb_item))
+    }
+
+    pub async fn list(&self) -> Result<Vec<Template>, Error> {
+        let mut templates = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            templates.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(Template::from_dynamodb_item),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(templates)
+    }
+
+    pub async fn version(
+        &self,
+        template_id: &str,
+        version: u32,
+    ) -> Result<Option<TemplateVersion>, Error> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.versions_table_name)
+            .key("template_id", AttributeValue::S(template_id.to_string()))
+            .key("version", AttributeValue::N(version.to_string()))
+            .send()
+            .await?;
+
+        Ok(result.item().and_then(TemplateVersion::from_dynamodb_item))
+    }
+
+    // Every version of a template, newest first
+    pub async fn versions(&self, template_id: &str) -> Result<Vec<TemplateVersion>, Error> {
+        let mut versions = Vec::new();
+        let mut start_key = None;
+
+        loop {
+            let result = self
+                .client
+                .query()
+                .table_name(&self.versions_table_name)
+                .key_condition_expression("template_id = :template_id")
+                .expression_attribute_values(
+                    ":template_id",
+                    AttributeValue::S(template_id.to_string()),
+                )
+                .scan_index_forward(false)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await?;
+
+            versions.extend(
+                result
+                    .items()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(TemplateVersion::from_dynamodb_item),
+            );
+
+            match result.last_evaluated_key() {
+                Some(key) => start_key = Some(key.clone()),
+                None => break,
+            }
+        }
+
+        Ok(versions)
+    }
+
+    // The version campaigns using the template are sent with
+    pub async fn published(&self, template_id: &str) -> Result<Option<TemplateVersion>, Error> {
+        let Some(version) = self
+            .get(template_id)
+            .await?
+            .and_then(|template| template.published_version)
+        else {
+            return Ok(None);
+        };
+
+        self.version(template_id, version).await
+    }
+
+    // Save a new version, publishing it when asked. Returns None when the
+    // template doesn't exist.
+    pub async fn add_version(
+        &self,
+        template_id: &str,
+        request: UpdateTemplateRequest,
+    ) -> Result<Option<(Template, TemplateVersion)>, Error> {
+        // Take the next version number, so concurrent edits get one each
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(template_id.to_string()))
+            .update_expression("ADD latest_version :one")
+            .condition_expression("attribute_exists(id)")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await;
+
+        let template = match result {
+            Ok(output) => output.attributes().and_then(Template::from_dynamodb_item),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                None
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let Some(template) = template else {
+            return Ok(None);
+        };
+
+        let version = TemplateVersion {
+            template_id: template_id.to_string(),
+            version: template.latest_version,
+            subject: request.subject,
+            html_body: request.html_body,
+            note: request.note,
+            created_at: Utc::now(),
+        };
+        self.client
+            .put_item()
+            .table_name(&self.versions_table_name)
+            .set_item(Some(version.to_dynamodb_item()))
+            .condition_expression("attribute_not_exists(version)")
+            .send()
+            .await?;
+
+        let template = if request.publish {
+            match self.publish(template_id, version.version).await? {
+                Some(template) => template,
+                None => return Ok(None),
+            }
+        } else {
+            template
+        };
+
+        Ok(Some((template, version)))
+    }
+
+    // Point the template at one of its versions, e.g. an older one to roll an
+    // edit back. Returns None when the template or version doesn't exist.
+    pub async fn publish(
+        &self,
+        template_id: &str,
+        version: u32,
+    ) -> Result<Option<Template>, Error> {
+        if self.version(template_id, version).await?.is_none() {
+            return Ok(None);
+        }
+
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("id", AttributeValue::S(template_id.to_string()))
+            .update_expression("SET published_version = :version, updated_at = :now")
+            .condition_expression("attribute_exists(id)")
+            .expression_attribute_values(":version", AttributeValue::N(version.to_string()))
+            .expression_attribute_values(":now", AttributeValue::S(Utc::now().to_rfc3339()))
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(output.attributes().and_then(Template::from_dynamodb_item)),
+            Err(SdkError::ServiceError(err))
+                if err.err().is_conditional_check_failed_exception() =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::future::Future;
+use utoipa::ToSchema;
+
+// Outcome of checking an address with an email verification provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Deliverable,
+    // Accept-all domains, role addresses and the like
+    Risky,
+    Undeliverable,
+    // The provider couldn't tell, e.g. the mail server timed out
+    Unknown,
+}
+
+impl Verdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Deliverable => "deliverable",
+            Verdict::Risky => "risky",
+            Verdict::Undeliverable => "undeliverable",
+            Verdict::Unknown => "unknown",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "deliverable" => Some(Verdict::Deliverable),
+            "risky" => Some(Verdict::Risky),
+            "undeliverable" => Some(Verdict::Undeliverable),
+            "unknown" => Some(Verdict::Unknown),
+            _ => None,
+        }
+    }
+}
+
+// Anything that can check an address, so providers can be swapped or mocked
+pub trait EmailVerifier {
+    fn verify(&self, email: &str) -> impl Future<Output = Result<Verdict, Error>> + Send;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    ZeroBounce,
+    Kickbox,
+}
+
+impl Provider {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "zerobounce" => Some(Provider::ZeroBounce),
+            "kickbox" => Some(Provider::Kickbox),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ZeroBounceResponse {
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct KickboxResponse {
+    result: String,
+}
+
+// Whether new signups are checked before being stored, from
+// EMAIL_VERIFICATION_ON_SUBSCRIBE; the hygiene job runs either way
+pub fn verify_on_subscribe() -> bool {
+    env::var("EMAIL_VERIFICATION_ON_SUBSCRIBE").is_ok_and(|value| value == "true")
+}
+
+// Verifier calling a hosted provider's HTTP API
+pub struct HttpVerifier {
+    client: reqwest::Client,
+    provider: Provider,
+    api_key: String,
+}
+
+impl HttpVerifier {
+    pub fn new(provider: Provider, api_key: String) -> Self {
+        Self {
+            client: crate::clients::http().clone(),
+            provider,
+            api_key,
+        }
+    }
+
+    // Configured through EMAIL_VERIFICATION_PROVIDER (zerobounce or kickbox) and
+    // EMAIL_VERIFICATION_API_KEY; None when verification is turned off
+    pub fn from_env() -> Option<Self> {
+        let provider = Provider::parse(&env::var("EMAIL_VERIFICATION_PROVIDER").ok()?)?;
+        let api_key = env::var("EMAIL_VERIFICATION_API_KEY")
+            .ok()
+            .filter(|key| !key.is_empty())?;
+        Some(Self::new(provider, api_key))
+    }
+}
+
+impl EmailVerifier for HttpVerifier {
+    async fn verify(&self, email: &str) -> Result<Verdict, Error> {
+        match self.provider {
+            Provider::ZeroBounce => {
+                let response: ZeroBounceResponse = self
+                    .client
+                    .get("https://api.zerobounce.net/v2/validate")
+                    .query(&[("api_key", self.api_key.as_str()), ("email", email)])
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+
+                Ok(match response.status.as_str() {
+                    "valid" => Verdict::Deliverable,
+                    "catch-all" => Verdict::Risky,
+                    "invalid" | "spamtrap" | "abuse" | "do_not_mail" => Verdict::Undeliverable,
+                    _ => Verdict::Unknown,
+                })
+            }
+            Provider::Kickbox => {
+                let response: KickboxResponse = self
+                    .client
+                    .get("https://api.kickbox.com/v2/verify")
+                    .query(&[("email", email), ("apikey", self.api_key.as_str())])
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+
+                Ok(Verdict::parse(&response.result).unwrap_or(Verdict::Unknown))
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::Error;
+use lambda_http::http::Uri;
+use lambda_http::request::RequestContext;
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
+use utoipa::ToSchema;
+
+// Paths under this prefix get the v1 envelope; the same paths without it keep
+// their original response shape for existing clients
+pub const V1_PREFIX: &str = "/v1";
+
+// Every JSON response under /v1
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Envelope {
+    pub success: bool,
+    pub message: Option<String>,
+    // The rest of the legacy body, e.g. the subscribers of a list
+    #[schema(value_type = Object)]
+    pub data: Option<Value>,
+    // Stable, machine readable reason for a failure, e.g. "not_found"
+    pub error_code: Option<String>,
+    // Quote this when reporting a problem
+    pub request_id: Option<String>,
+}
+
+// Error code for a failed request's status
+pub fn error_code(status: u16) -> &'static str {
+    match status {
+        400 => "bad_request",
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        409 => "conflict",
+        410 => "gone",
+        412 => "precondition_failed",
+        413 => "payload_too_large",
+        422 => "unprocessable",
+        429 => "rate_limited",
+        _ if (400..500).contains(&status) => "client_error",
+        503 => "unavailable",
+        _ => "internal_error",
+    }
+}
+
+// API Gateway's request id, or Lambda's when invoked some other way
+pub fn request_id(event: &Request) -> Option<String> {
+    match event.request_context_ref() {
+        Some(RequestContext::ApiGatewayV1(context)) if context.request_id.is_some() => {
+            context.request_id.clone()
+        }
+        _ => event
+            .lambda_context_ref()
+            .map(|context| context.request_id.clone()),
+    }
+}
+
+// The request with the /v1 prefix removed from its path, and whether it had
+// one. The prefix may follow a stage name, as in /prod/v1/subscribe.
+pub fn strip_version(event: Request) -> (Request, bool) {
+    let path = event.uri().path();
+    let Some(index) = path.find(V1_PREFIX).filter(|index| {
+        let rest = &path[index + V1_PREFIX.len()..];
+        path[..*index].matches('/').count() <= 1 && (rest.is_empty() || rest.starts_with('/'))
+    }) else {
+        return (event, false);
+    };
+
+    let mut path_and_query = format!("{}{}", &path[..index], &path[index + V1_PREFIX.len()..]);
+    if !path_and_query.starts_with('/') {
+        path_and_query.insert(0, '/');
+    }
+    if let Some(query) = event.uri().query() {
+        path_and_query = format!("{path_and_query}?{query}");
+    }
+
+    let (mut parts, body) = event.into_parts();
+    let mut uri = parts.uri.clone().into_parts();
+    uri.path_and_query = path_and_query.parse().ok();
+    match Uri::from_parts(uri) {
+        Ok(uri) => {
+            parts.uri = uri;
+            (Request::from_parts(parts, body), true)
+        }
+        Err(_) => (Request::from_parts(parts, body), false),
+    }
+}
+
+// Wrap a legacy JSON body in the envelope. Other responses, e.g. HTML pages
+// and redirects, are returned as they are.
+pub fn envelope(response: Response<Body>, request_id: Option<String>) -> Response<Body> {
+    let is_json = response
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    if !is_json {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let legacy: Value = match &body {
+        Body::Text(text) => serde_json::from_str(text).unwrap_or(Value::Null),
+        Body::Binary(bytes) => serde_json::from_slice(bytes).unwrap_or(Value::Null),
+        Body::Empty => Value::Null,
+    };
+    let status = parts.status.as_u16();
+
+    let mut envelope = Envelope {
+        success: status < 400,
+        message: None,
+        data: None,
+        error_code: None,
+        request_id,
+    };
+    match legacy {
+        Value::Object(mut object) => {
+            if let Some(success) = object.remove("success").and_then(|value| value.as_bool()) {
+                envelope.success = success;
+            }
+            envelope.message = match object.remove("message") {
+                Some(Value::String(message)) => Some(message),
+                _ => None,
+            };
+            if !object.is_empty() {
+                envelope.data = Some(Value::Object(object));
+            }
+        }
+        Value::Null => {}
+        other => envelope.data = Some(other),
+    }
+    if !envelope.success {
+        envelope.error_code = Some(error_code(status).to_string());
+    }
+
+    let body = match serde_json::to_string(&envelope) {
+        Ok(body) => Body::from(body),
+        Err(_) => body,
+    };
+    Response::from_parts(parts, body)
+}
+
+// Serve `handler` under both its legacy paths and the /v1 ones, which answer
+// with the envelope
+pub async fn versioned<F, Fut>(event: Request, handler: F) -> Result<Response<Body>, Error>
+where
+    F: FnOnce(Request) -> Fut,
+    Fut: Future<Output = Result<Response<Body>, Error>>,
+{
+    let (event, is_v1) = strip_version(event);
+    if !is_v1 {
+        return handler(event).await;
+    }
+
+    let request_id = request_id(&event);
+    Ok(envelope(handler(event).await?, request_id))
+}
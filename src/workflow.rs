@@ -0,0 +1,140 @@
+use crate::{Error, Subscriber};
+use aws_sdk_sfn::Client as SfnClient;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::env;
+
+// Recipients per batch, keeping each Map iteration well inside the Lambda
+// timeout and the state payloads far below their 256 KB limit
+pub const BATCH_SIZE: usize = 500;
+
+// Immediate campaign sends go through the Step Functions state machine with
+// this ARN when it is set, instead of running in the admin Lambda: it
+// resolves the recipients, sends them in batches from a Map state and adds up
+// the counts, each a call to the `campaign_workflow` Lambda
+pub fn state_machine_arn() -> Option<String> {
+    env::var("CAMPAIGN_STATE_MACHINE_ARN")
+        .ok()
+        .filter(|arn| !arn.is_empty())
+}
+
+// Input of the state machine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowInput {
+    pub campaign_id: String,
+}
+
+// A run of recipients by id, from the snapshot taken when the send started.
+// Only the ids bounding it travel through the state machine.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecipientBatch {
+    pub campaign_id: String,
+    pub index: u32,
+    pub first_id: String,
+    pub last_id: String,
+    pub size: u32,
+}
+
+// Output of the resolve step, the input of the Map state sending the batches
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveOutput {
+    pub campaign_id: String,
+    pub recipients: u32,
+    pub batches: Vec<RecipientBatch>,
+}
+
+// Output of a send step
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub index: u32,
+    pub sent: u32,
+    // Left out because the campaign was cancelled
+    pub skipped: u32,
+    // Unsubscribed, bounced or failed to send since the recipients were resolved
+    pub failed: u32,
+}
+
+// Input of the finish step: the resolve output with the Map state's results
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinishInput {
+    pub campaign_id: String,
+    pub results: Vec<BatchResult>,
+}
+
+// Output of the finish step, and of the state machine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowSummary {
+    pub campaign_id: String,
+    // "sent" or "cancelled"
+    pub status: String,
+    pub sent: u32,
+    pub skipped: u32,
+    pub failed: u32,
+}
+
+// What the state machine asks the `campaign_workflow` Lambda to do, by its
+// `step` field
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum WorkflowStep {
+    Resolve(WorkflowInput),
+    SendBatch { batch: RecipientBatch },
+    Finish(FinishInput),
+}
+
+// Split the recipients into batches of BATCH_SIZE in id order
+pub fn plan_batches(campaign_id: &str, recipients: &[Subscriber]) -> Vec<RecipientBatch> {
+    let mut ids: Vec<&str> = recipients
+        .iter()
+        .map(|subscriber| subscriber.id.as_str())
+        .collect();
+    ids.sort_unstable();
+
+    ids.chunks(BATCH_SIZE)
+        .enumerate()
+        .map(|(index, chunk)| RecipientBatch {
+            campaign_id: campaign_id.to_string(),
+            index: index as u32,
+            first_id: chunk[0].to_string(),
+            last_id: chunk[chunk.len() - 1].to_string(),
+            size: chunk.len() as u32,
+        })
+        .collect()
+}
+
+pub struct CampaignWorkflow {
+    client: SfnClient,
+    state_machine_arn: String,
+}
+
+impl CampaignWorkflow {
+    pub fn new(client: SfnClient, state_machine_arn: String) -> Self {
+        Self {
+            client,
+            state_machine_arn,
+        }
+    }
+
+    // Execution names are unique per state machine, so a campaign sent again
+    // after its execution couldn't start gets a new one
+    pub fn execution_name(campaign_id: &str) -> String {
+        format!("{}-{}", campaign_id, Utc::now().timestamp())
+    }
+
+    // Start sending a campaign, returning the execution's ARN
+    pub async fn start(&self, execution_name: &str, campaign_id: &str) -> Result<String, Error> {
+        let input = WorkflowInput {
+            campaign_id: campaign_id.to_string(),
+        };
+        let result = self
+            .client
+            .start_execution()
+            .state_machine_arn(&self.state_machine_arn)
+            .name(execution_name)
+            .input(serde_json::to_string(&input)?)
+            .send()
+            .await?;
+
+        Ok(result.execution_arn().unwrap_or_default().to_string())
+    }
+}